@@ -46,8 +46,8 @@ const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultRespons
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 4;
 
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
@@ -509,7 +509,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_management_capability,
     )
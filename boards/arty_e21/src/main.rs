@@ -31,8 +31,8 @@ const NUM_PROCS: usize = 4;
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
 // Actual memory for holding the active process structures.
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None, None, None, None];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 // Reference to the chip for panic dumps.
 static mut CHIP: Option<&'static arty_e21_chip::chip::ArtyExx<ArtyExxDefaultPeripherals>> = None;
@@ -235,7 +235,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
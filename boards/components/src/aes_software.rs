@@ -0,0 +1,47 @@
+//! Component for the software AES-128 engine.
+//!
+//! This provides one Component, `Aes128SoftwareComponent`, which creates an
+//! `Aes128Software` that can be used in place of a hardware AES block, for
+//! example under a `virtual_aes_ccm::MuxAES128CCM` or by the AES syscall
+//! driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let aes = components::aes_software::Aes128SoftwareComponent::new(dynamic_deferred_caller)
+//!     .finalize(());
+//! ```
+
+use capsules::aes_software::Aes128Software;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct Aes128SoftwareComponent {
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl Aes128SoftwareComponent {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> Aes128SoftwareComponent {
+        Aes128SoftwareComponent { deferred_caller }
+    }
+}
+
+impl Component for Aes128SoftwareComponent {
+    type StaticInput = ();
+    type Output = &'static Aes128Software<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let aes = static_init!(
+            Aes128Software<'static>,
+            Aes128Software::new(self.deferred_caller)
+        );
+        aes.initialize_callback_handle(
+            self.deferred_caller
+                .register(aes)
+                .expect("no deferred call slot available for aes"),
+        );
+
+        aes
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod aes_software;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
@@ -33,12 +34,14 @@ pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
+pub mod p256;
 pub mod panic_button;
 pub mod process_console;
 pub mod rng;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sha256;
 pub mod sht3x;
 pub mod si7021;
 pub mod sound_pressure;
@@ -0,0 +1,46 @@
+//! Component for the software P-256 engine.
+//!
+//! This provides one Component, `P256SoftwareComponent`, which creates a
+//! `P256Software` that verifies and signs with ECDSA and agrees on keys with
+//! ECDH, for example for the signature syscall driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let p256 = components::p256::P256SoftwareComponent::new(dynamic_deferred_caller)
+//!     .finalize(());
+//! ```
+
+use capsules::p256::P256Software;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct P256SoftwareComponent {
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl P256SoftwareComponent {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> P256SoftwareComponent {
+        P256SoftwareComponent { deferred_caller }
+    }
+}
+
+impl Component for P256SoftwareComponent {
+    type StaticInput = ();
+    type Output = &'static P256Software<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let p256 = static_init!(
+            P256Software<'static>,
+            P256Software::new(self.deferred_caller)
+        );
+        p256.initialize_callback_handle(
+            self.deferred_caller
+                .register(p256)
+                .expect("no deferred call slot available for p256"),
+        );
+
+        p256
+    }
+}
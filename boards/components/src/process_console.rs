@@ -7,7 +7,17 @@
 //! Usage
 //! -----
 //! ```rust
-//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
+//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux)
+//!     .finalize(components::process_console_component_helper!());
+//! ```
+//!
+//! The default queue holds the output of the `process` command. Boards that
+//! are short of memory can give smaller sizes of the write buffer and of the
+//! queue to the helper, at the cost of a truncated `process` output:
+//!
+//! ```rust
+//! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux)
+//!     .finalize(components::process_console_component_helper!(32, 512));
 //! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
@@ -20,6 +30,22 @@ use kernel::component::Component;
 use kernel::hil;
 use kernel::static_init;
 
+#[macro_export]
+macro_rules! process_console_component_helper {
+    () => {{
+        use capsules::process_console;
+        $crate::process_console_component_helper!(
+            process_console::DEFAULT_WRITE_BUF_LEN,
+            process_console::DEFAULT_QUEUE_BUF_LEN
+        )
+    };};
+    ($write_len:expr, $queue_len:expr $(,)?) => {{
+        static mut WRITE_BUF: [u8; $write_len] = [0; $write_len];
+        static mut QUEUE_BUF: [u8; $queue_len] = [0; $queue_len];
+        (&mut WRITE_BUF, &mut QUEUE_BUF)
+    };};
+}
+
 pub struct ProcessConsoleComponent {
     board_kernel: &'static kernel::Kernel,
     uart_mux: &'static MuxUart<'static>,
@@ -41,10 +67,10 @@ pub struct Capability;
 unsafe impl capabilities::ProcessManagementCapability for Capability {}
 
 impl Component for ProcessConsoleComponent {
-    type StaticInput = ();
+    type StaticInput = (&'static mut [u8], &'static mut [u8]);
     type Output = &'static process_console::ProcessConsole<'static, Capability>;
 
-    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         // Create virtual device for console.
         let console_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
         console_uart.setup();
@@ -53,7 +79,8 @@ impl Component for ProcessConsoleComponent {
             process_console::ProcessConsole<'static, Capability>,
             process_console::ProcessConsole::new(
                 console_uart,
-                &mut process_console::WRITE_BUF,
+                static_buffer.0,
+                static_buffer.1,
                 &mut process_console::READ_BUF,
                 &mut process_console::COMMAND_BUF,
                 self.board_kernel,
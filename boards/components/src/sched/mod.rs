@@ -1,4 +1,5 @@
 pub mod cooperative;
 pub mod mlfq;
 pub mod priority;
+pub mod real_time;
 pub mod round_robin;
@@ -0,0 +1,112 @@
+//! Component for the real-time schedulers.
+//!
+//! This provides one Component, RealTimeComponent, with the aliases
+//! EDFComponent and RateMonotonicComponent for the two priority policies.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let scheduler = components::sched::real_time::EDFComponent::new(mux_alarm, &PROCESSES)
+//!     .finalize(components::edf_component_helper!(
+//!         sam4l::ast::Ast,
+//!         NUM_PROCS
+//!     ));
+//! ```
+
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::component::Component;
+use kernel::hil::time;
+use kernel::procs::ProcessSlot;
+use kernel::static_init_half;
+use kernel::{EarliestDeadlineFirst, PriorityPolicy, RateMonotonic};
+use kernel::{RealTimeProcessNode, RealTimeSched};
+
+#[macro_export]
+macro_rules! real_time_component_helper {
+    ($A:ty, $P:ty, $N:expr $(,)?) => {{
+        use core::mem::MaybeUninit;
+        use kernel::static_init;
+        use kernel::{RealTimeProcessNode, RealTimeSched};
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<RealTimeSched<'static, VirtualMuxAlarm<'static, $A>, $P>> =
+            MaybeUninit::uninit();
+        const UNINIT: MaybeUninit<RealTimeProcessNode<'static>> = MaybeUninit::uninit();
+        static mut BUF3: [MaybeUninit<RealTimeProcessNode<'static>>; $N] = [UNINIT; $N];
+        (&mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+#[macro_export]
+macro_rules! edf_component_helper {
+    ($A:ty, $N:expr $(,)?) => {{
+        $crate::real_time_component_helper!($A, kernel::EarliestDeadlineFirst, $N)
+    };};
+}
+
+#[macro_export]
+macro_rules! rate_monotonic_component_helper {
+    ($A:ty, $N:expr $(,)?) => {{
+        $crate::real_time_component_helper!($A, kernel::RateMonotonic, $N)
+    };};
+}
+
+pub type EDFComponent<A> = RealTimeComponent<A, EarliestDeadlineFirst>;
+pub type RateMonotonicComponent<A> = RealTimeComponent<A, RateMonotonic>;
+
+pub struct RealTimeComponent<A: 'static + time::Alarm<'static>, P: 'static + PriorityPolicy> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    processes: &'static [ProcessSlot],
+    _policy: PhantomData<P>,
+}
+
+impl<A: 'static + time::Alarm<'static>, P: 'static + PriorityPolicy> RealTimeComponent<A, P> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        processes: &'static [ProcessSlot],
+    ) -> RealTimeComponent<A, P> {
+        RealTimeComponent {
+            alarm_mux,
+            processes,
+            _policy: PhantomData,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>, P: 'static + PriorityPolicy> Component
+    for RealTimeComponent<A, P>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<RealTimeSched<'static, VirtualMuxAlarm<'static, A>, P>>,
+        &'static mut [MaybeUninit<RealTimeProcessNode<'static>>],
+    );
+    type Output = &'static mut RealTimeSched<'static, VirtualMuxAlarm<'static, A>, P>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let (alarm_buf, sched_buf, proc_nodes) = static_buffer;
+        let scheduler_alarm = static_init_half!(
+            alarm_buf,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let scheduler = static_init_half!(
+            sched_buf,
+            RealTimeSched<'static, VirtualMuxAlarm<'static, A>, P>,
+            RealTimeSched::new(scheduler_alarm)
+        );
+        // Keep the nodes in the order of the processes array, so that
+        // background processes take turns in that order.
+        for (i, node) in proc_nodes.iter_mut().enumerate() {
+            let init_node = static_init_half!(
+                node,
+                RealTimeProcessNode<'static>,
+                RealTimeProcessNode::new(&self.processes[i])
+            );
+            scheduler.processes.push_tail(init_node);
+        }
+        scheduler
+    }
+}
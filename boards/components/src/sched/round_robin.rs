@@ -14,7 +14,7 @@
 
 use core::mem::MaybeUninit;
 use kernel::component::Component;
-use kernel::procs::ProcessType;
+use kernel::procs::ProcessSlot;
 use kernel::{static_init, static_init_half};
 use kernel::{RoundRobinProcessNode, RoundRobinSched};
 
@@ -31,11 +31,11 @@ macro_rules! rr_component_helper {
 }
 
 pub struct RoundRobinComponent {
-    processes: &'static [Option<&'static dyn ProcessType>],
+    processes: &'static [ProcessSlot],
 }
 
 impl RoundRobinComponent {
-    pub fn new(processes: &'static [Option<&'static dyn ProcessType>]) -> RoundRobinComponent {
+    pub fn new(processes: &'static [ProcessSlot]) -> RoundRobinComponent {
         RoundRobinComponent { processes }
     }
 }
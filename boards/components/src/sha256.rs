@@ -0,0 +1,50 @@
+//! Component for the software SHA-256 and HMAC-SHA256 engine.
+//!
+//! This provides one Component, `Sha256SoftwareComponent`, which creates a
+//! `Sha256Software` that can be used in place of a hardware digest block,
+//! for example with the components in `components::hmac`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sha256 = components::sha256::Sha256SoftwareComponent::new(dynamic_deferred_caller)
+//!     .finalize(());
+//!
+//! let mux_hmac = components::hmac::HmacMuxComponent::new(sha256).finalize(
+//!     components::hmac_mux_component_helper!(Sha256Software<'static>, [u8; 32]),
+//! );
+//! ```
+
+use capsules::sha256::Sha256Software;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::static_init;
+
+pub struct Sha256SoftwareComponent {
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl Sha256SoftwareComponent {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> Sha256SoftwareComponent {
+        Sha256SoftwareComponent { deferred_caller }
+    }
+}
+
+impl Component for Sha256SoftwareComponent {
+    type StaticInput = ();
+    type Output = &'static Sha256Software<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let sha256 = static_init!(
+            Sha256Software<'static>,
+            Sha256Software::new(self.deferred_caller)
+        );
+        sha256.initialize_callback_handle(
+            self.deferred_caller
+                .register(sha256)
+                .expect("no deferred call slot available for sha256"),
+        );
+
+        sha256
+    }
+}
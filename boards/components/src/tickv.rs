@@ -1,7 +1,8 @@
 //! Component for TicKV KV System Driver.
 //!
-//! This provides one component, TicKVComponent, which provides
-//! a system call inteface to non-volatile storage.
+//! This provides two components: TicKVComponent, which provides a
+//! `hil::kv_system` interface to non-volatile storage, and KVStoreComponent,
+//! which provides a system call interface to it.
 //!
 //! Usage
 //! -----
@@ -30,9 +31,20 @@
 //!        lowrisc::flash_ctrl::FlashCtrl
 //!    ));
 //!    hil::flash::HasClient::set_client(&peripherals.flash_ctrl, mux_flash);
+//!
+//!    let identities = static_init!(
+//!        capsules::app_identity::AppIdentities,
+//!        capsules::app_identity::AppIdentities::with_package_name_fallback(&[])
+//!    );
+//!    let kv_store = components::tickv::KVStoreComponent::new(board_kernel, kvstore, identities, 1024)
+//!        .finalize(components::kv_store_component_helper!(
+//!            lowrisc::flash_ctrl::FlashCtrl
+//!        ));
 //! ```
 
-use capsules::tickv::TicKVStore;
+use capsules::app_identity::AppIdentities;
+use capsules::kv_driver::{KVStoreDriver, UNHASHED_KEY_LEN};
+use capsules::tickv::{TicKVKeyType, TicKVStore};
 use capsules::virtual_flash::FlashUser;
 use capsules::virtual_flash::MuxFlash;
 use core::mem::MaybeUninit;
@@ -41,7 +53,8 @@ use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil;
 use kernel::hil::flash::HasClient;
-use kernel::static_init_half;
+use kernel::hil::kv_system::KVSystem;
+use kernel::{static_init, static_init_half};
 
 // Setup static space for the objects.
 #[macro_export]
@@ -79,7 +92,8 @@ impl<F: 'static + hil::flash::Flash> Component for FlashMuxComponent<F> {
 #[macro_export]
 macro_rules! tickv_component_helper {
     ($F:ty) => {{
-        use capsules::tickv::TicKVStore;
+        use capsules::kv_driver::{KVStoreDriver, UNHASHED_KEY_LEN};
+        use capsules::tickv::{TicKVKeyType, TicKVStore};
         use capsules::virtual_flash::FlashUser;
         use core::mem::MaybeUninit;
         use kernel::hil;
@@ -148,3 +162,74 @@ impl<F: 'static + hil::flash::Flash> Component for TicKVComponent<F> {
         driver
     }
 }
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! kv_store_component_helper {
+    ($F:ty) => {{
+        use capsules::kv_driver::KVStoreDriver;
+        use capsules::tickv::{TicKVKeyType, TicKVStore};
+        use capsules::virtual_flash::FlashUser;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<
+            KVStoreDriver<'static, TicKVStore<'static, FlashUser<'static, $F>>, TicKVKeyType>,
+        > = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct KVStoreComponent<F: 'static + hil::flash::Flash> {
+    board_kernel: &'static kernel::Kernel,
+    tickv: &'static TicKVStore<'static, FlashUser<'static, F>>,
+    identities: &'static AppIdentities,
+    quota: usize,
+}
+
+impl<F: 'static + hil::flash::Flash> KVStoreComponent<F> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        tickv: &'static TicKVStore<'static, FlashUser<'static, F>>,
+        identities: &'static AppIdentities,
+        quota: usize,
+    ) -> Self {
+        Self {
+            board_kernel,
+            tickv,
+            identities,
+            quota,
+        }
+    }
+}
+
+impl<F: 'static + hil::flash::Flash> Component for KVStoreComponent<F> {
+    type StaticInput = &'static mut MaybeUninit<
+        KVStoreDriver<'static, TicKVStore<'static, FlashUser<'static, F>>, TicKVKeyType>,
+    >;
+    type Output =
+        &'static KVStoreDriver<'static, TicKVStore<'static, FlashUser<'static, F>>, TicKVKeyType>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let key_buffer = static_init!(TicKVKeyType, [0; 8]);
+        let unhashed_key_buffer = static_init!([u8; UNHASHED_KEY_LEN], [0; UNHASHED_KEY_LEN]);
+        // Values can be up to this long, less the header
+        let data_buffer = static_init!([u8; 256], [0; 256]);
+
+        let driver = static_init_half!(
+            static_buffer,
+            KVStoreDriver<'static, TicKVStore<'static, FlashUser<'static, F>>, TicKVKeyType>,
+            KVStoreDriver::new(
+                self.tickv,
+                key_buffer,
+                unhashed_key_buffer,
+                data_buffer,
+                self.board_kernel.create_grant(&grant_cap),
+                self.identities,
+                self.quota,
+            )
+        );
+        self.tickv.set_client(driver);
+        driver
+    }
+}
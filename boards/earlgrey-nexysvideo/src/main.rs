@@ -18,6 +18,7 @@ use kernel::hil::i2c::I2CMaster;
 use kernel::hil::led::LedHigh;
 use kernel::hil::time::Alarm;
 use kernel::mpu::KernelMPU;
+use kernel::syscall_filter::SyscallFilter;
 use kernel::Platform;
 use kernel::{create_capability, debug, static_init};
 use kernel::{mpu, Chip};
@@ -38,7 +39,7 @@ const NUM_PROCS: usize = 4;
 //
 // Actual memory for holding the active process structures. Need an empty list
 // at least.
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; 4] = [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; 4] = [kernel::procs::ProcessSlot::EMPTY; 4];
 
 static mut CHIP: Option<
     &'static earlgrey::chip::EarlGrey<
@@ -78,6 +79,15 @@ struct EarlGreyNexysVideo {
         capsules::virtual_uart::UartDevice<'static>,
     >,
     i2c_master: &'static capsules::i2c_master::I2CMasterDriver<'static, lowrisc::i2c::I2c<'static>>,
+    kv_store: &'static capsules::kv_driver::KVStoreDriver<
+        'static,
+        capsules::tickv::TicKVStore<
+            'static,
+            capsules::virtual_flash::FlashUser<'static, lowrisc::flash_ctrl::FlashCtrl<'static>>,
+        >,
+        capsules::tickv::TicKVKeyType,
+    >,
+    syscall_filter: kernel::syscall_filter::TbfHeaderFilterDefaultAllow,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -94,9 +104,18 @@ impl Platform for EarlGreyNexysVideo {
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
             capsules::i2c_master::DRIVER_NUM => f(Some(self.i2c_master)),
+            capsules::kv_driver::DRIVER_NUM => f(Some(self.kv_store)),
             _ => f(None),
         }
     }
+
+    fn filter_syscall(
+        &self,
+        process: &dyn kernel::procs::ProcessType,
+        syscall: &kernel::syscall::Syscall,
+    ) -> Result<(), kernel::ErrorCode> {
+        self.syscall_filter.filter_syscall(process, syscall)
+    }
 }
 
 /// Main function.
@@ -287,7 +306,7 @@ pub unsafe fn main() {
     );
 
     // TicKV
-    let _tickv = components::tickv::TicKVComponent::new(
+    let tickv = components::tickv::TicKVComponent::new(
         &mux_flash,                                  // Flash controller
         0x20040000 / lowrisc::flash_ctrl::PAGE_SIZE, // Region offset (size / page_size)
         0x40000,                                     // Region size
@@ -299,6 +318,18 @@ pub unsafe fn main() {
     ));
     hil::flash::HasClient::set_client(&peripherals.flash_ctrl, mux_flash);
 
+    // Apps are not checked for credentials, so values are stored under the
+    // package name of each app.
+    let kv_identities = static_init!(
+        capsules::app_identity::AppIdentities,
+        capsules::app_identity::AppIdentities::with_package_name_fallback(&[])
+    );
+    let kv_store =
+        components::tickv::KVStoreComponent::new(board_kernel, tickv, kv_identities, 1024)
+            .finalize(components::kv_store_component_helper!(
+                lowrisc::flash_ctrl::FlashCtrl
+            ));
+
     /// These symbols are defined in the linker script.
     extern "C" {
         /// Beginning of the ROM region containing app images.
@@ -337,6 +368,8 @@ pub unsafe fn main() {
         hmac,
         lldb: lldb,
         i2c_master,
+        kv_store,
+        syscall_filter: kernel::syscall_filter::TbfHeaderFilterDefaultAllow {},
     };
 
     // This is PMP support for kernel regions
@@ -397,7 +430,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
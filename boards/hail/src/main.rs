@@ -38,8 +38,8 @@ mod test_take_map_cell;
 const NUM_PROCS: usize = 20;
 
 // Actual memory for holding the active process structures.
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 static mut CHIP: Option<&'static sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> = None;
 
@@ -241,7 +241,7 @@ pub unsafe fn main() {
     let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
     let process_console =
         components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
-            .finalize(());
+            .finalize(components::process_console_component_helper!());
     components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
 
     // Initialize USART3 for UART for the nRF serialization link.
@@ -473,7 +473,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         fault_response,
         &process_management_capability,
     )
@@ -31,8 +31,8 @@ pub const NUM_PROCS: usize = 4;
 //
 // Actual memory for holding the active process structures. Need an empty list
 // at least.
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 // Reference to the chip for panic dumps.
 static mut CHIP: Option<
@@ -244,7 +244,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
@@ -0,0 +1,433 @@
+//! Board file for running the Tock kernel as a program on the host.
+//!
+//! The board uses the simulated `host` chip: the console and the process
+//! console are connected to stdin and stdout, and nonvolatile storage is
+//! backed by a file. Processes are loaded from a file of concatenated TBFs,
+//! but do not execute any code of their own. See the README for usage.
+
+use std::path::PathBuf;
+
+use capsules::aes_software::Aes128Software;
+use capsules::date_time_software::DateTimeSoftware;
+use capsules::p256::P256Software;
+use capsules::sha256::Sha256Software;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::VirtualMuxAlarm;
+use capsules::virtual_hmac::VirtualMuxHmac;
+use host::chip::{Host, HostDefaultPeripherals};
+use host::flash::{Flash, PAGE_SIZE};
+use host::time::{Alarm, Clock};
+use host::uart::Uart;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::date_time::{DateTime, DateTimeValues};
+use kernel::hil::public_key_crypto::SignatureVerify;
+use kernel::hil::symmetric_encryption::{AES128, AES128CCM, AES128_BLOCK_SIZE};
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+
+pub const NUM_PROCS: usize = 4;
+
+// Actual memory for holding the active process structures.
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
+
+// How should the kernel respond when a process faults.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+
+/// Number of pages of the flash file. The first half is accessible to
+/// userspace through the nonvolatile storage driver, the second half holds
+/// the filesystem.
+const FLASH_PAGES: usize = 64;
+
+/// Capability of the app watchdog to fault processes, which must be named to
+/// be held by the capsule.
+struct ProcessMgmtCap;
+unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+
+/// Memory for the processes. Stored as words as process memory must be word
+/// aligned.
+static mut APP_MEMORY: [u32; 0x4000] = [0; 0x4000];
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct HostPlatform {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<'static, VirtualMuxAlarm<'static, Alarm<'static>>>,
+    nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    filesystem: &'static capsules::filesystem_driver::FileSystemDriver<'static>,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static>,
+    app_watchdog: &'static capsules::app_watchdog::AppWatchdog<
+        'static,
+        VirtualMuxAlarm<'static, Alarm<'static>>,
+        ProcessMgmtCap,
+    >,
+    hmac: &'static capsules::hmac::HmacDriver<
+        'static,
+        VirtualMuxHmac<'static, Sha256Software<'static>, [u8; 32]>,
+        [u8; 32],
+    >,
+    aes: &'static capsules::aes::AesDriver<
+        'static,
+        Aes128Software<'static>,
+        VirtualAES128CCM<'static, Aes128Software<'static>>,
+    >,
+    signature: &'static capsules::signature_driver::SignatureDriver<
+        'static,
+        P256Software<'static>,
+        { capsules::p256::HASH_LEN },
+        { capsules::p256::SIGNATURE_LEN },
+    >,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for HostPlatform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::filesystem_driver::DRIVER_NUM => f(Some(self.filesystem)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
+            capsules::app_watchdog::DRIVER_NUM => f(Some(self.app_watchdog)),
+            capsules::hmac::DRIVER_NUM => f(Some(self.hmac)),
+            capsules::aes::DRIVER_NUM => f(Some(self.aes)),
+            capsules::signature_driver::DRIVER_NUM => f(Some(self.signature)),
+            _ => f(None),
+        }
+    }
+}
+
+/// Command line options of the board.
+struct Options {
+    /// File backing the flash.
+    flash: PathBuf,
+    /// File with the TBFs of the processes to load.
+    apps: Option<PathBuf>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        flash: PathBuf::from("host-flash.bin"),
+        apps: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--flash" => options.flash = PathBuf::from(value),
+            "--apps" => options.apps = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        eprintln!("usage: host [--flash FILE] [--apps FILE]");
+        std::process::exit(2);
+    });
+    unsafe { run(options) }
+}
+
+unsafe fn run(options: Options) {
+    let clock = static_init!(Clock, Clock::new());
+    let flash = Flash::new(&options.flash, FLASH_PAGES).unwrap_or_else(|err| {
+        panic!(
+            "cannot open flash file {}: {}",
+            options.flash.display(),
+            err
+        )
+    });
+    let peripherals = static_init!(
+        HostDefaultPeripherals,
+        HostDefaultPeripherals::new(clock, Uart::new(), Some(flash))
+    );
+    peripherals.uart.attach_stdin();
+
+    // initialize capabilities
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+    let memory_allocation_capability = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 7], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux = components::console::UartMuxComponent::new(
+        &peripherals.uart,
+        115200,
+        dynamic_deferred_caller,
+    )
+    .finalize(());
+
+    // Setup the console and the process console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(components::process_console_component_helper!(64, 2048));
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+
+    // Create a shared virtualization mux layer on top of the simulated alarm.
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(&peripherals.alarm)
+        .finalize(components::alarm_mux_component_helper!(Alarm));
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(Alarm));
+
+    let flash = peripherals.flash.as_ref().unwrap();
+    let nonvolatile_storage = components::nonvolatile_storage::NonvolatileStorageComponent::new(
+        board_kernel,
+        flash,
+        0,                           // Start address for userspace accessible region
+        FLASH_PAGES / 2 * PAGE_SIZE, // Length of userspace accessible region
+        FLASH_PAGES / 2 * PAGE_SIZE, // Start address of kernel region
+        FLASH_PAGES / 2 * PAGE_SIZE, // Length of kernel region
+    )
+    .finalize(components::nv_storage_component_helper!(Flash));
+
+    // The filesystem uses the kernel region of the flash, through the
+    // nonvolatile storage driver. Its table has room for 8 files.
+    const FS_TABLE_LEN: usize =
+        capsules::filesystem::HEADER_LEN + 8 * capsules::filesystem::ENTRY_LEN;
+    let fs = static_init!(
+        capsules::filesystem::FileSystem<'static>,
+        capsules::filesystem::FileSystem::new(
+            nonvolatile_storage,
+            FLASH_PAGES / 2 * PAGE_SIZE, // Start of the kernel region
+            FLASH_PAGES / 2 * PAGE_SIZE, // Length of the kernel region
+            PAGE_SIZE,
+            static_init!([u8; FS_TABLE_LEN], [0; FS_TABLE_LEN]),
+            static_init!([u8; PAGE_SIZE], [0; PAGE_SIZE]),
+        )
+    );
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, fs);
+    // Apps are not checked for credentials, so directories are named after
+    // the package name of each app.
+    let fs_identities = static_init!(
+        capsules::app_identity::AppIdentities,
+        capsules::app_identity::AppIdentities::with_package_name_fallback(&[])
+    );
+    let filesystem = static_init!(
+        capsules::filesystem_driver::FileSystemDriver<'static>,
+        capsules::filesystem_driver::FileSystemDriver::new(
+            fs,
+            static_init!([u8; 256], [0; 256]),
+            board_kernel.create_grant(&memory_allocation_capability),
+            fs_identities,
+        )
+    );
+    fs.set_client(filesystem);
+    if let Err(e) = fs.mount() {
+        debug!("cannot mount filesystem: {:?}", e);
+    }
+
+    // There is no RTC, so the date and time are counted on the alarm,
+    // starting from the time of the host.
+    let date_time_alarm = static_init!(
+        VirtualMuxAlarm<'static, Alarm<'static>>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let rtc = static_init!(
+        DateTimeSoftware<'static, VirtualMuxAlarm<'static, Alarm<'static>>>,
+        DateTimeSoftware::new(date_time_alarm, dynamic_deferred_caller)
+    );
+    hil::time::Alarm::set_alarm_client(date_time_alarm, rtc);
+    rtc.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(rtc)
+            .expect("no deferred call slot available for software date time"),
+    );
+    rtc.start();
+    let host_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    if let Ok(now) = DateTimeValues::from_seconds_since_epoch(host_time) {
+        let _ = rtc.set_date_time(now);
+    }
+    let date_time = static_init!(
+        capsules::date_time::DateTimeDriver<'static>,
+        capsules::date_time::DateTimeDriver::new(
+            rtc,
+            board_kernel.create_grant(&memory_allocation_capability),
+        )
+    );
+    rtc.set_client(date_time);
+
+    // The host cannot reset, so a process that is not restarted after
+    // missing its deadline stays faulted.
+    let app_watchdog_alarm = static_init!(
+        VirtualMuxAlarm<'static, Alarm<'static>>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let app_watchdog = static_init!(
+        capsules::app_watchdog::AppWatchdog<
+            'static,
+            VirtualMuxAlarm<'static, Alarm<'static>>,
+            ProcessMgmtCap,
+        >,
+        capsules::app_watchdog::AppWatchdog::new(
+            app_watchdog_alarm,
+            board_kernel,
+            ProcessMgmtCap,
+            board_kernel.create_grant(&memory_allocation_capability),
+            None,
+        )
+    );
+    hil::time::Alarm::set_alarm_client(app_watchdog_alarm, app_watchdog);
+
+    // There is no hash accelerator, so HMAC is computed in software.
+    let sha256 =
+        components::sha256::Sha256SoftwareComponent::new(dynamic_deferred_caller).finalize(());
+    let hmac_data_buffer = static_init!([u8; 64], [0; 64]);
+    let hmac_dest_buffer = static_init!([u8; 32], [0; 32]);
+    let mux_hmac = components::hmac::HmacMuxComponent::new(sha256).finalize(
+        components::hmac_mux_component_helper!(Sha256Software<'static>, [u8; 32]),
+    );
+    let hmac = components::hmac::HmacComponent::new(
+        board_kernel,
+        mux_hmac,
+        hmac_data_buffer,
+        hmac_dest_buffer,
+    )
+    .finalize(components::hmac_component_helper!(
+        Sha256Software<'static>,
+        [u8; 32]
+    ));
+
+    // There is no AES accelerator either. The block modes of the AES driver
+    // use an engine of their own, as they hold it for a whole operation,
+    // while CCM* goes through the CCM mux.
+    let aes_blocks =
+        components::aes_software::Aes128SoftwareComponent::new(dynamic_deferred_caller)
+            .finalize(());
+    let aes_ccm = components::aes_software::Aes128SoftwareComponent::new(dynamic_deferred_caller)
+        .finalize(());
+    let ccm_mux = static_init!(
+        MuxAES128CCM<'static, Aes128Software<'static>>,
+        MuxAES128CCM::new(aes_ccm, dynamic_deferred_caller)
+    );
+    ccm_mux.initialize_callback_handle(
+        dynamic_deferred_caller
+            .register(ccm_mux)
+            .expect("no deferred call slot available for ccm mux"),
+    );
+    aes_ccm.set_client(ccm_mux);
+    const CCM_CRYPT_SIZE: usize = 10 * AES128_BLOCK_SIZE;
+    let ccm_client = static_init!(
+        VirtualAES128CCM<'static, Aes128Software<'static>>,
+        VirtualAES128CCM::new(
+            ccm_mux,
+            static_init!([u8; CCM_CRYPT_SIZE], [0; CCM_CRYPT_SIZE])
+        )
+    );
+    ccm_client.setup();
+    let aes = static_init!(
+        capsules::aes::AesDriver<
+            'static,
+            Aes128Software<'static>,
+            VirtualAES128CCM<'static, Aes128Software<'static>>,
+        >,
+        capsules::aes::AesDriver::new(
+            aes_blocks,
+            ccm_client,
+            static_init!([u8; 128], [0; 128]),
+            static_init!([u8; 128], [0; 128]),
+            board_kernel.create_grant(&memory_allocation_capability),
+        )
+    );
+    aes_blocks.set_client(aes);
+    ccm_client.set_client(aes);
+
+    let p256 = components::p256::P256SoftwareComponent::new(dynamic_deferred_caller).finalize(());
+    let signature = static_init!(
+        capsules::signature_driver::SignatureDriver<
+            'static,
+            P256Software<'static>,
+            { capsules::p256::HASH_LEN },
+            { capsules::p256::SIGNATURE_LEN },
+        >,
+        capsules::signature_driver::SignatureDriver::new(
+            p256,
+            static_init!(
+                [u8; capsules::p256::HASH_LEN],
+                [0; capsules::p256::HASH_LEN]
+            ),
+            static_init!(
+                [u8; capsules::p256::SIGNATURE_LEN],
+                [0; capsules::p256::SIGNATURE_LEN]
+            ),
+            board_kernel.create_grant(&memory_allocation_capability),
+        )
+    );
+    p256.set_verify_client(signature);
+
+    let chip = static_init!(Host, Host::new(peripherals));
+
+    let platform = HostPlatform {
+        console,
+        alarm,
+        nonvolatile_storage,
+        filesystem,
+        date_time,
+        app_watchdog,
+        hmac,
+        aes,
+        signature,
+    };
+
+    let apps: &'static [u8] = match options.apps {
+        Some(path) => Box::leak(
+            std::fs::read(&path)
+                .unwrap_or_else(|err| panic!("cannot read apps {}: {}", path.display(), err))
+                .into_boxed_slice(),
+        ),
+        None => &[],
+    };
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        apps,
+        core::slice::from_raw_parts_mut(
+            APP_MEMORY.as_mut_ptr() as *mut u8,
+            APP_MEMORY.len() * core::mem::size_of::<u32>(),
+        ),
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    )
+    .unwrap_or_else(|err| {
+        debug!("Error loading processes!");
+        debug!("{:?}", err);
+    });
+
+    let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
+        .finalize(components::rr_component_helper!(NUM_PROCS));
+
+    debug!("Host simulation initialisation complete.");
+    debug!("Entering main loop.");
+    process_console.start();
+
+    board_kernel.kernel_loop(
+        &platform,
+        chip,
+        None::<&kernel::ipc::IPC<NUM_PROCS>>,
+        scheduler,
+        &main_loop_cap,
+    );
+}
@@ -29,6 +29,7 @@ use kernel::hil::symmetric_encryption::AES128;
 //use kernel::hil::time::Alarm;
 use kernel::hil::led::LedHigh;
 use kernel::hil::Controller;
+use kernel::syscall_filter::SyscallFilter;
 #[allow(unused_imports)]
 use kernel::{create_capability, debug, debug_gpio, static_init};
 use sam4l::chip::Sam4lDefaultPeripherals;
@@ -93,8 +94,8 @@ const PAN_ID: u16 = 0xABCD;
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
 
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 static mut CHIP: Option<&'static sam4l::chip::Sam4l<Sam4lDefaultPeripherals>> = None;
 
@@ -137,6 +138,7 @@ struct Imix {
     >,
     nrf51822: &'static capsules::nrf51822_serialization::Nrf51822Serialization<'static>,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    syscall_filter: kernel::syscall_filter::TbfHeaderFilterDefaultAllow,
 }
 
 // The RF233 radio stack requires our buffers for its SPI operations:
@@ -180,6 +182,14 @@ impl kernel::Platform for Imix {
             _ => f(None),
         }
     }
+
+    fn filter_syscall(
+        &self,
+        process: &dyn kernel::procs::ProcessType,
+        syscall: &kernel::syscall::Syscall,
+    ) -> Result<(), kernel::ErrorCode> {
+        self.syscall_filter.filter_syscall(process, syscall)
+    }
 }
 
 unsafe fn set_pin_primary_functions(peripherals: &Sam4lDefaultPeripherals) {
@@ -315,7 +325,8 @@ pub unsafe fn main() {
     let uart_mux =
         UartMuxComponent::new(&peripherals.usart3, 115200, dynamic_deferred_caller).finalize(());
 
-    let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux)
+        .finalize(components::process_console_component_helper!(64, 2048));
     let console = ConsoleComponent::new(board_kernel, uart_mux).finalize(());
     DebugWriterComponent::new(uart_mux).finalize(());
 
@@ -549,6 +560,7 @@ pub unsafe fn main() {
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
+        syscall_filter: kernel::syscall_filter::TbfHeaderFilterDefaultAllow {},
     };
 
     // Need to initialize the UART for the nRF51 serialization.
@@ -632,7 +644,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_mgmt_cap,
     )
@@ -63,8 +63,8 @@ const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultRespons
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 4;
 
-static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
-    [None; NUM_PROCS];
+static mut PROCESSES: [kernel::procs::ProcessSlot; NUM_PROCS] =
+    [kernel::procs::ProcessSlot::EMPTY; NUM_PROCS];
 
 static mut CHIP: Option<&'static nrf52833::chip::NRF52<Nrf52833DefaultPeripherals>> = None;
 
@@ -470,7 +470,7 @@ pub unsafe fn main() {
     //--------------------------------------------------------------------------
     let process_console =
         components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
-            .finalize(());
+            .finalize(components::process_console_component_helper!());
     process_console.start();
 
     //--------------------------------------------------------------------------
@@ -538,7 +538,6 @@ pub unsafe fn main() {
             &mut _sappmem as *mut u8,
             &_eappmem as *const u8 as usize - &_sappmem as *const u8 as usize,
         ),
-        &mut PROCESSES,
         FAULT_RESPONSE,
         &process_management_capability,
     )
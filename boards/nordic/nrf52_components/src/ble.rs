@@ -78,3 +78,104 @@ impl Component for BLEComponent {
         ble_radio
     }
 }
+
+/// Component for BLE connections, with the link layer and the GATT server.
+///
+/// The radio can only be used by one of `BLEComponent` and
+/// `BLEConnectionComponent`.
+///
+/// ```rust
+/// let ble_connection = BLEConnectionComponent::new(
+///     board_kernel,
+///     &nrf52::ble_radio::RADIO,
+///     mux_alarm,
+///     [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6],
+/// )
+/// .finalize(());
+/// ```
+pub struct BLEConnectionComponent {
+    board_kernel: &'static kernel::Kernel,
+    radio: &'static nrf52::ble_radio::Radio<'static>,
+    mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
+    address: [u8; capsules::ble::link_layer::ADDRESS_LEN],
+}
+
+impl BLEConnectionComponent {
+    /// `address` is the static device address, least significant byte
+    /// first. Its two most significant bits must be set.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        radio: &'static nrf52::ble_radio::Radio,
+        mux_alarm: &'static capsules::virtual_alarm::MuxAlarm<'static, nrf52::rtc::Rtc>,
+        address: [u8; capsules::ble::link_layer::ADDRESS_LEN],
+    ) -> BLEConnectionComponent {
+        BLEConnectionComponent {
+            board_kernel,
+            radio,
+            mux_alarm,
+            address,
+        }
+    }
+}
+
+type LinkLayer = capsules::ble::link_layer::LinkLayer<
+    'static,
+    nrf52::ble_radio::Radio<'static>,
+    VirtualMuxAlarm<'static, Rtc<'static>>,
+>;
+
+impl Component for BLEConnectionComponent {
+    type StaticInput = ();
+    type Output = &'static capsules::ble::BleConnectionDriver<
+        'static,
+        nrf52::ble_radio::Radio<'static>,
+        VirtualMuxAlarm<'static, Rtc<'static>>,
+    >;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, Rtc>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        let buffer = static_init!(
+            [u8; capsules::ble::link_layer::BUFFER_LEN],
+            [0; capsules::ble::link_layer::BUFFER_LEN]
+        );
+        let link_layer = static_init!(
+            LinkLayer,
+            capsules::ble::link_layer::LinkLayer::new(self.radio, virtual_alarm, buffer)
+        );
+        link_layer.set_address(self.address);
+        kernel::hil::ble_advertising::BleAdvertisementDriver::set_receive_client(
+            self.radio, link_layer,
+        );
+        kernel::hil::ble_advertising::BleAdvertisementDriver::set_transmit_client(
+            self.radio, link_layer,
+        );
+        virtual_alarm.set_alarm_client(link_layer);
+
+        let gatt_server = static_init!(
+            capsules::ble::gatt_server::GattServer<'static, LinkLayer>,
+            capsules::ble::gatt_server::GattServer::new(link_layer)
+        );
+        capsules::ble::link_layer::Connection::set_connection_client(link_layer, gatt_server);
+
+        let ble_connection = static_init!(
+            capsules::ble::BleConnectionDriver<
+                'static,
+                nrf52::ble_radio::Radio,
+                VirtualMuxAlarm<'static, Rtc>,
+            >,
+            capsules::ble::BleConnectionDriver::new(
+                link_layer,
+                gatt_server,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        gatt_server.set_client(ble_connection);
+
+        ble_connection
+    }
+}
@@ -3,7 +3,7 @@
 pub mod ble;
 pub mod startup;
 
-pub use self::ble::BLEComponent;
+pub use self::ble::{BLEComponent, BLEConnectionComponent};
 pub use self::startup::{
     NrfClockComponent, NrfStartupComponent, UartChannel, UartChannelComponent, UartPins,
 };
@@ -0,0 +1,153 @@
+//! Mock alarm.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, AlarmClient, Freq1KHz, Frequency, Ticks, Ticks32};
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmCall<T> {
+    SetAlarm { reference: T, dt: T },
+    Disarm,
+}
+
+/// An alarm whose clock only moves when the test advances it.
+///
+/// The alarm fires, i.e. disarms and calls its client, once the clock is
+/// past `reference + dt`. Like hardware, it only fires when time passes:
+/// setting an alarm that expired already fires it on the next `advance()`.
+pub struct MockAlarm<'a, T: Ticks = Ticks32, F: Frequency = Freq1KHz> {
+    now: Cell<T>,
+    reference: Cell<T>,
+    dt: Cell<T>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+    calls: Calls<AlarmCall<T>>,
+    _frequency: PhantomData<F>,
+}
+
+impl<'a, T: Ticks, F: Frequency> MockAlarm<'a, T, F> {
+    pub fn new() -> MockAlarm<'a, T, F> {
+        MockAlarm {
+            now: Cell::new(T::from(0)),
+            reference: Cell::new(T::from(0)),
+            dt: Cell::new(T::from(0)),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+            calls: Calls::new(),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// Set the clock without firing the alarm.
+    pub fn set_now(&self, now: T) {
+        self.now.set(now);
+    }
+
+    /// Move the clock forward and fire the alarm if it expired.
+    pub fn advance(&self, ticks: u32) {
+        self.now.set(self.now.get().wrapping_add(T::from(ticks)));
+        self.fire_if_expired();
+    }
+
+    /// Move the clock to the expiration of the alarm, if it is armed, and
+    /// fire it. Returns whether the alarm fired.
+    pub fn advance_to_alarm(&self) -> bool {
+        if !self.armed.get() {
+            return false;
+        }
+        let expiration = self.reference.get().wrapping_add(self.dt.get());
+        if self
+            .now
+            .get()
+            .within_range(self.reference.get(), expiration)
+        {
+            self.now.set(expiration);
+        }
+        self.fire_if_expired()
+    }
+
+    /// Fire the alarm if it is armed and expired. Returns whether the alarm
+    /// fired.
+    pub fn fire_if_expired(&self) -> bool {
+        let expiration = self.reference.get().wrapping_add(self.dt.get());
+        if self.armed.get()
+            && !self
+                .now
+                .get()
+                .within_range(self.reference.get(), expiration)
+        {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Ticks until the alarm expires, if it is armed.
+    pub fn remaining(&self) -> Option<T> {
+        if !self.armed.get() {
+            return None;
+        }
+        let expiration = self.reference.get().wrapping_add(self.dt.get());
+        let now = self.now.get();
+        if now.within_range(self.reference.get(), expiration) {
+            Some(expiration.wrapping_sub(now))
+        } else {
+            Some(T::from(0))
+        }
+    }
+
+    pub fn calls(&self) -> Vec<AlarmCall<T>> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<AlarmCall<T>> {
+        self.calls.take()
+    }
+}
+
+impl<T: Ticks, F: Frequency> time::Time for MockAlarm<'_, T, F> {
+    type Frequency = F;
+    type Ticks = T;
+
+    fn now(&self) -> T {
+        self.now.get()
+    }
+}
+
+impl<'a, T: Ticks, F: Frequency> time::Alarm<'a> for MockAlarm<'a, T, F> {
+    fn set_alarm_client(&'a self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: T, dt: T) {
+        self.calls.record(AlarmCall::SetAlarm { reference, dt });
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> T {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.calls.record(AlarmCall::Disarm);
+        self.armed.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> T {
+        T::from(1)
+    }
+}
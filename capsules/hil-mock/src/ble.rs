@@ -0,0 +1,150 @@
+//! Mock BLE radio.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::ble_advertising::{self, RadioChannel};
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BleCall {
+    Transmit {
+        channel: RadioChannel,
+        packet: Vec<u8>,
+    },
+    Receive {
+        channel: RadioChannel,
+    },
+    CancelReceive,
+    SetTxPower(u8),
+    SetAccessAddress {
+        access_address: u32,
+        crc_init: u32,
+    },
+}
+
+/// A BLE radio.
+///
+/// Transmissions stay pending until `complete_transmit()`. Receptions stay
+/// pending until a packet arrives with `receive()`, or until they are
+/// cancelled.
+pub struct MockBleRadio<'a> {
+    tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
+    rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    receiving: OptionalCell<RadioChannel>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
+    calls: Calls<BleCall>,
+}
+
+impl<'a> MockBleRadio<'a> {
+    pub fn new() -> MockBleRadio<'a> {
+        MockBleRadio {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            receiving: OptionalCell::empty(),
+            access_address: Cell::new(ble_advertising::ADVERTISING_ACCESS_ADDRESS),
+            crc_init: Cell::new(ble_advertising::ADVERTISING_CRC_INIT),
+            calls: Calls::new(),
+        }
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    /// The channel of the pending reception.
+    pub fn receiving(&self) -> Option<RadioChannel> {
+        self.receiving.map(|channel| *channel)
+    }
+
+    /// The access address and CRC initialization value in use.
+    pub fn access_address(&self) -> (u32, u32) {
+        (self.access_address.get(), self.crc_init.get())
+    }
+
+    /// Finish the pending transmission. Returns whether there was one.
+    pub fn complete_transmit(&self) -> bool {
+        self.tx_buffer.take().map_or(false, |buffer| {
+            self.tx_client
+                .map(move |client| client.transmit_event(buffer, ReturnCode::SUCCESS));
+            true
+        })
+    }
+
+    /// Receive `packet`, made of the PDU header and payload, if a reception
+    /// is pending. Returns whether there was one.
+    pub fn receive(&self, packet: &[u8]) -> bool {
+        self.receive_with_result(packet, ReturnCode::SUCCESS)
+    }
+
+    /// Receive `packet` with `result`, e.g. `FAIL` for a CRC error.
+    pub fn receive_with_result(&self, packet: &[u8], result: ReturnCode) -> bool {
+        self.receiving.take().map_or(false, |_| {
+            let buffer = Box::leak(packet.to_vec().into_boxed_slice());
+            self.rx_client
+                .map(move |client| client.receive_event(buffer, packet.len() as u8, result));
+            true
+        })
+    }
+
+    pub fn calls(&self) -> Vec<BleCall> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<BleCall> {
+        self.calls.take()
+    }
+}
+
+impl<'a> ble_advertising::BleAdvertisementDriver<'a> for MockBleRadio<'a> {
+    fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel) {
+        self.calls.record(BleCall::Transmit {
+            channel,
+            packet: buf[..len].to_vec(),
+        });
+        self.tx_buffer.replace(buf);
+    }
+
+    fn receive_advertisement(&self, channel: RadioChannel) {
+        self.calls.record(BleCall::Receive { channel });
+        self.receiving.set(channel);
+    }
+
+    fn cancel_receive(&self) -> ReturnCode {
+        self.calls.record(BleCall::CancelReceive);
+        self.receiving.clear();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_transmit_client(&self, client: &'a dyn ble_advertising::TxClient) {
+        self.tx_client.set(client);
+    }
+}
+
+impl ble_advertising::BleConfig for MockBleRadio<'_> {
+    fn set_tx_power(&self, power: u8) -> ReturnCode {
+        self.calls.record(BleCall::SetTxPower(power));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl ble_advertising::BleConnectionConfig for MockBleRadio<'_> {
+    fn set_access_address(&self, access_address: u32, crc_init: u32) -> ReturnCode {
+        self.calls.record(BleCall::SetAccessAddress {
+            access_address,
+            crc_init,
+        });
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init);
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,36 @@
+//! Recording of the calls made to a mock.
+
+use std::cell::RefCell;
+
+/// The calls made to a mock, in order.
+pub struct Calls<T> {
+    calls: RefCell<Vec<T>>,
+}
+
+impl<T: Clone> Calls<T> {
+    pub fn new() -> Calls<T> {
+        Calls {
+            calls: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, call: T) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    /// All calls recorded so far.
+    pub fn get(&self) -> Vec<T> {
+        self.calls.borrow().clone()
+    }
+
+    /// All calls recorded so far, which are then forgotten.
+    pub fn take(&self) -> Vec<T> {
+        self.calls.borrow_mut().split_off(0)
+    }
+}
+
+impl<T: Clone> Default for Calls<T> {
+    fn default() -> Calls<T> {
+        Calls::new()
+    }
+}
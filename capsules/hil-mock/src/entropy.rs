@@ -0,0 +1,109 @@
+//! Mock entropy source.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::entropy::{self, Continue};
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntropyCall {
+    Get,
+    Cancel,
+}
+
+/// A source of 32 bit entropy.
+///
+/// A request stays pending until `complete()`, which hands the entropy
+/// pushed with `push_entropy()` to the client. The request stays pending if
+/// the client asks for more.
+pub struct MockEntropy32<'a> {
+    client: OptionalCell<&'a dyn entropy::Client32>,
+    requested: Cell<bool>,
+    entropy: RefCell<VecDeque<u32>>,
+    /// Return codes for the next requests, `SUCCESS` once empty.
+    results: RefCell<VecDeque<ReturnCode>>,
+    calls: Calls<EntropyCall>,
+}
+
+impl<'a> MockEntropy32<'a> {
+    pub fn new() -> MockEntropy32<'a> {
+        MockEntropy32 {
+            client: OptionalCell::empty(),
+            requested: Cell::new(false),
+            entropy: RefCell::new(VecDeque::new()),
+            results: RefCell::new(VecDeque::new()),
+            calls: Calls::new(),
+        }
+    }
+
+    /// Add entropy to hand out.
+    pub fn push_entropy(&self, entropy: &[u32]) {
+        self.entropy.borrow_mut().extend(entropy);
+    }
+
+    /// Make the next request fail right away with `result`.
+    pub fn push_result(&self, result: ReturnCode) {
+        self.results.borrow_mut().push_back(result);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.get()
+    }
+
+    /// Hand the entropy to the client, if it requested some. Returns whether
+    /// the client was called.
+    pub fn complete(&self) -> bool {
+        if !self.requested.replace(false) {
+            return false;
+        }
+        let mut entropy = self.entropy.borrow_mut();
+        let mut iter = std::iter::from_fn(|| entropy.pop_front());
+        let more = self.client.map_or(false, |client| {
+            client.entropy_available(&mut iter, ReturnCode::SUCCESS) == Continue::More
+        });
+        if more {
+            self.requested.set(true);
+        }
+        true
+    }
+
+    /// The number of values not handed out yet.
+    pub fn remaining(&self) -> usize {
+        self.entropy.borrow().len()
+    }
+
+    pub fn calls(&self) -> Vec<EntropyCall> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<EntropyCall> {
+        self.calls.take()
+    }
+}
+
+impl<'a> entropy::Entropy32<'a> for MockEntropy32<'a> {
+    fn get(&self) -> ReturnCode {
+        self.calls.record(EntropyCall::Get);
+        match self.results.borrow_mut().pop_front() {
+            Some(rcode) if rcode != ReturnCode::SUCCESS => rcode,
+            _ => {
+                self.requested.set(true);
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.calls.record(EntropyCall::Cancel);
+        self.requested.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'a self, client: &'a dyn entropy::Client32) {
+        self.client.set(client);
+    }
+}
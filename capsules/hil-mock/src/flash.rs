@@ -0,0 +1,212 @@
+//! Mock flash.
+
+use core::cell::{Cell, RefCell};
+use core::ops::{Index, IndexMut};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+pub const PAGE_SIZE: usize = 64;
+
+pub struct MockPage(pub [u8; PAGE_SIZE]);
+
+impl Default for MockPage {
+    fn default() -> Self {
+        Self { 0: [0; PAGE_SIZE] }
+    }
+}
+
+impl Index<usize> for MockPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for MockPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for MockPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashCall {
+    Read(usize),
+    Write(usize),
+    Erase(usize),
+}
+
+/// Flash backed by memory, erased to `0xFF`.
+///
+/// Operations stay pending until `complete()`. A read or write happens when
+/// it completes, unless an error was scripted for it with `push_error()`.
+pub struct MockFlash<'a> {
+    pages: RefCell<Vec<[u8; PAGE_SIZE]>>,
+    client: OptionalCell<&'a dyn hil::flash::Client<MockFlash<'a>>>,
+    buffer: TakeCell<'static, MockPage>,
+    pending: Cell<Option<FlashCall>>,
+    /// Return codes for the next operations, `SUCCESS` once empty.
+    results: RefCell<VecDeque<ReturnCode>>,
+    /// Errors reported by the next completed operations,
+    /// `CommandComplete` once empty.
+    errors: RefCell<VecDeque<hil::flash::Error>>,
+    calls: Calls<FlashCall>,
+}
+
+impl<'a> MockFlash<'a> {
+    pub fn new(num_pages: usize) -> MockFlash<'a> {
+        MockFlash {
+            pages: RefCell::new(vec![[0xFF; PAGE_SIZE]; num_pages]),
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(None),
+            results: RefCell::new(VecDeque::new()),
+            errors: RefCell::new(VecDeque::new()),
+            calls: Calls::new(),
+        }
+    }
+
+    /// The contents of a page.
+    pub fn page(&self, page_number: usize) -> [u8; PAGE_SIZE] {
+        self.pages.borrow()[page_number]
+    }
+
+    /// Write to a page directly, without an operation.
+    pub fn set_page(&self, page_number: usize, data: &[u8]) {
+        self.pages.borrow_mut()[page_number][..data.len()].copy_from_slice(data);
+    }
+
+    /// Make the next operation fail right away with `result`.
+    pub fn push_result(&self, result: ReturnCode) {
+        self.results.borrow_mut().push_back(result);
+    }
+
+    /// Make the next operation report `error` when it completes.
+    pub fn push_error(&self, error: hil::flash::Error) {
+        self.errors.borrow_mut().push_back(error);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.pending.get().is_some()
+    }
+
+    /// Finish the pending operation. Returns whether there was one.
+    pub fn complete(&self) -> bool {
+        let operation = match self.pending.take() {
+            Some(operation) => operation,
+            None => return false,
+        };
+        let error = self
+            .errors
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or(hil::flash::Error::CommandComplete);
+        let succeeded = error == hil::flash::Error::CommandComplete;
+        match operation {
+            FlashCall::Read(page_number) => {
+                self.buffer.take().map(|buffer| {
+                    if succeeded {
+                        buffer.0 = self.pages.borrow()[page_number];
+                    }
+                    self.client
+                        .map(move |client| client.read_complete(buffer, error));
+                });
+            }
+            FlashCall::Write(page_number) => {
+                self.buffer.take().map(|buffer| {
+                    if succeeded {
+                        self.pages.borrow_mut()[page_number] = buffer.0;
+                    }
+                    self.client
+                        .map(move |client| client.write_complete(buffer, error));
+                });
+            }
+            FlashCall::Erase(page_number) => {
+                if succeeded {
+                    self.pages.borrow_mut()[page_number] = [0xFF; PAGE_SIZE];
+                }
+                self.client.map(|client| client.erase_complete(error));
+            }
+        }
+        true
+    }
+
+    pub fn calls(&self) -> Vec<FlashCall> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<FlashCall> {
+        self.calls.take()
+    }
+
+    /// Check whether an operation can start, and if so start it.
+    fn start(&self, operation: FlashCall, page_number: usize) -> ReturnCode {
+        self.calls.record(operation);
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        if page_number >= self.pages.borrow().len() {
+            return ReturnCode::EINVAL;
+        }
+        match self.results.borrow_mut().pop_front() {
+            Some(rcode) if rcode != ReturnCode::SUCCESS => rcode,
+            _ => {
+                self.pending.set(Some(operation));
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+}
+
+impl<'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C> for MockFlash<'a> {
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl hil::flash::Flash for MockFlash<'_> {
+    type Page = MockPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(FlashCall::Read(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            rcode => Err((rcode, buf)),
+        }
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        match self.start(FlashCall::Write(page_number), page_number) {
+            ReturnCode::SUCCESS => {
+                self.buffer.replace(buf);
+                Ok(())
+            }
+            rcode => Err((rcode, buf)),
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(FlashCall::Erase(page_number), page_number)
+    }
+}
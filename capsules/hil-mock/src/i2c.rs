@@ -0,0 +1,249 @@
+//! Mock I2C master and I2C device.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2c::{self, Error};
+
+use crate::calls::Calls;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum I2CCall {
+    Enable,
+    Disable,
+    Write {
+        addr: u8,
+        data: Vec<u8>,
+    },
+    Read {
+        addr: u8,
+        len: u8,
+    },
+    WriteRead {
+        addr: u8,
+        data: Vec<u8>,
+        read_len: u8,
+    },
+}
+
+/// Transfers shared by the mock master and the mock device.
+struct Transfers {
+    buffer: TakeCell<'static, [u8]>,
+    read_len: Cell<u8>,
+    /// Responses for the next transfers: the bytes read, or the error to
+    /// report.
+    responses: RefCell<VecDeque<Result<Vec<u8>, Error>>>,
+    calls: Calls<I2CCall>,
+}
+
+impl Transfers {
+    fn new() -> Transfers {
+        Transfers {
+            buffer: TakeCell::empty(),
+            read_len: Cell::new(0),
+            responses: RefCell::new(VecDeque::new()),
+            calls: Calls::new(),
+        }
+    }
+
+    fn start(&self, call: I2CCall, buffer: &'static mut [u8], read_len: u8) {
+        assert!(
+            self.buffer.is_none(),
+            "I2C transfer started while another is in progress: {:?}",
+            call
+        );
+        self.calls.record(call);
+        self.read_len.set(read_len);
+        self.buffer.replace(buffer);
+    }
+
+    /// Finish the pending transfer with the next response, and pass the
+    /// buffer and the result to `complete`.
+    fn complete<F: FnOnce(&'static mut [u8], Error)>(&self, complete: F) -> bool {
+        self.buffer.take().map_or(false, |buffer| {
+            let response = self
+                .responses
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Ok(Vec::new()));
+            let error = match response {
+                Ok(data) => {
+                    let len = self.read_len.get() as usize;
+                    for (byte, read) in buffer.iter_mut().take(len).zip(data) {
+                        *byte = read;
+                    }
+                    Error::CommandComplete
+                }
+                Err(error) => error,
+            };
+            complete(buffer, error);
+            true
+        })
+    }
+}
+
+/// An I2C master.
+///
+/// Transfers stay pending until `complete()`, which reports the next
+/// response pushed with `push_response()`. Without a response, a transfer
+/// succeeds and reads nothing. Starting a transfer while another is pending
+/// panics.
+pub struct MockI2CMaster {
+    client: OptionalCell<&'static dyn i2c::I2CHwMasterClient>,
+    transfers: Transfers,
+}
+
+impl MockI2CMaster {
+    pub fn new() -> MockI2CMaster {
+        MockI2CMaster {
+            client: OptionalCell::empty(),
+            transfers: Transfers::new(),
+        }
+    }
+
+    /// Set the bytes read by, or the error reported by, the next transfer.
+    pub fn push_response(&self, response: Result<Vec<u8>, Error>) {
+        self.transfers.responses.borrow_mut().push_back(response);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.transfers.buffer.is_some()
+    }
+
+    /// Finish the pending transfer. Returns whether there was one.
+    pub fn complete(&self) -> bool {
+        self.transfers.complete(|buffer, error| {
+            self.client
+                .map(move |client| client.command_complete(buffer, error));
+        })
+    }
+
+    pub fn calls(&self) -> Vec<I2CCall> {
+        self.transfers.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<I2CCall> {
+        self.transfers.calls.take()
+    }
+}
+
+impl i2c::I2CMaster for MockI2CMaster {
+    fn set_master_client(&self, master_client: &'static dyn i2c::I2CHwMasterClient) {
+        self.client.set(master_client);
+    }
+
+    fn enable(&self) {
+        self.transfers.calls.record(I2CCall::Enable);
+    }
+
+    fn disable(&self) {
+        self.transfers.calls.record(I2CCall::Disable);
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let call = I2CCall::WriteRead {
+            addr,
+            data: data[..write_len as usize].to_vec(),
+            read_len,
+        };
+        self.transfers.start(call, data, read_len);
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        let call = I2CCall::Write {
+            addr,
+            data: data[..len as usize].to_vec(),
+        };
+        self.transfers.start(call, data, 0);
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.transfers
+            .start(I2CCall::Read { addr, len }, buffer, len);
+    }
+}
+
+/// An I2C device, for testing capsules that talk to a single device.
+///
+/// Behaves like `MockI2CMaster`, and records the address of the device in
+/// its calls.
+pub struct MockI2CDevice<'a> {
+    addr: u8,
+    client: OptionalCell<&'a dyn i2c::I2CClient>,
+    transfers: Transfers,
+}
+
+impl<'a> MockI2CDevice<'a> {
+    pub fn new(addr: u8) -> MockI2CDevice<'a> {
+        MockI2CDevice {
+            addr,
+            client: OptionalCell::empty(),
+            transfers: Transfers::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn i2c::I2CClient) {
+        self.client.set(client);
+    }
+
+    /// Set the bytes read by, or the error reported by, the next transfer.
+    pub fn push_response(&self, response: Result<Vec<u8>, Error>) {
+        self.transfers.responses.borrow_mut().push_back(response);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.transfers.buffer.is_some()
+    }
+
+    /// Finish the pending transfer. Returns whether there was one.
+    pub fn complete(&self) -> bool {
+        self.transfers.complete(|buffer, error| {
+            self.client
+                .map(move |client| client.command_complete(buffer, error));
+        })
+    }
+
+    pub fn calls(&self) -> Vec<I2CCall> {
+        self.transfers.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<I2CCall> {
+        self.transfers.calls.take()
+    }
+}
+
+impl i2c::I2CDevice for MockI2CDevice<'_> {
+    fn enable(&self) {
+        self.transfers.calls.record(I2CCall::Enable);
+    }
+
+    fn disable(&self) {
+        self.transfers.calls.record(I2CCall::Disable);
+    }
+
+    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let call = I2CCall::WriteRead {
+            addr: self.addr,
+            data: data[..write_len as usize].to_vec(),
+            read_len,
+        };
+        self.transfers.start(call, data, read_len);
+    }
+
+    fn write(&self, data: &'static mut [u8], len: u8) {
+        let call = I2CCall::Write {
+            addr: self.addr,
+            data: data[..len as usize].to_vec(),
+        };
+        self.transfers.start(call, data, 0);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], len: u8) {
+        let call = I2CCall::Read {
+            addr: self.addr,
+            len,
+        };
+        self.transfers.start(call, buffer, len);
+    }
+}
@@ -0,0 +1,52 @@
+//! Mock implementations of HILs for testing capsules with `cargo test`.
+//!
+//! Each mock stands in for the hardware below a capsule. Operations that
+//! complete asynchronously on hardware stay pending until the test completes
+//! them, so a test controls exactly when callbacks happen:
+//!
+//! - Calls made to a mock are recorded, and can be checked with `calls()` or
+//!   `take_calls()`.
+//! - Responses are scripted: data to return and errors to report are pushed
+//!   to the mock before the operation completes.
+//! - `complete()` (or a variant for the operation) finishes the pending
+//!   operation and issues the callback.
+//!
+//! The mocks are:
+//!
+//! - `alarm::MockAlarm`: an `Alarm` with a clock that only moves when the
+//!   test advances it.
+//! - `ble::MockBleRadio`: a BLE radio, implementing `BleAdvertisementDriver`,
+//!   `BleConfig` and `BleConnectionConfig`.
+//! - `uart::MockUart`: a `Uart`.
+//! - `i2c::MockI2CMaster` and `i2c::MockI2CDevice`: an `I2CMaster` and an
+//!   `I2CDevice`.
+//! - `spi::MockSpiMaster` and `spi::MockSpiMasterDevice`: a `SpiMaster` and
+//!   a `SpiMasterDevice`.
+//! - `radio::MockRadio`: an IEEE 802.15.4 `Radio`.
+//! - `flash::MockFlash`: a `Flash` backed by memory.
+//! - `entropy::MockEntropy32`: an `Entropy32` source.
+//!
+//! The tests of this crate use the mocks to test the virtualizers in
+//! `capsules`.
+
+pub mod alarm;
+pub mod ble;
+pub mod entropy;
+pub mod flash;
+pub mod i2c;
+pub mod radio;
+pub mod spi;
+pub mod uart;
+
+mod calls;
+
+pub use crate::calls::Calls;
+
+#[cfg(test)]
+mod tests;
+
+/// Leak a value, so that it can be used where capsules need `'static`
+/// references, e.g. for buffers.
+pub fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
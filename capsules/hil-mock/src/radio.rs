@@ -0,0 +1,213 @@
+//! Mock IEEE 802.15.4 radio.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RadioCall {
+    /// A transmission of `frame`, the PSDU without its FCS.
+    Transmit {
+        frame: Vec<u8>,
+    },
+    ConfigCommit,
+}
+
+/// An 802.15.4 radio.
+///
+/// Transmissions stay pending until `complete_transmit()`. Frames arrive
+/// with `receive()`, in the receive buffer of the radio, if it has one.
+pub struct MockRadio {
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    busy: Cell<bool>,
+    address: Cell<u16>,
+    address_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    calls: Calls<RadioCall>,
+}
+
+impl MockRadio {
+    pub fn new() -> MockRadio {
+        MockRadio {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            busy: Cell::new(false),
+            address: Cell::new(0),
+            address_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            calls: Calls::new(),
+        }
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    pub fn has_receive_buffer(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    /// Make `busy()` report `busy`, like a radio that is receiving a frame.
+    pub fn set_busy(&self, busy: bool) {
+        self.busy.set(busy);
+    }
+
+    /// Finish the pending transmission with `acked` and `result`. Returns
+    /// whether there was one.
+    pub fn complete_transmit(&self, acked: bool, result: ReturnCode) -> bool {
+        self.tx_buffer.take().map_or(false, |buffer| {
+            self.tx_client
+                .map(move |client| client.send_done(buffer, acked, result));
+            true
+        })
+    }
+
+    /// Receive `frame`, the PSDU without its FCS, if the radio has a receive
+    /// buffer. Returns whether it had one.
+    pub fn receive(&self, frame: &[u8]) -> bool {
+        self.rx_buffer.take().map_or(false, |buffer| {
+            buffer[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame.len()].copy_from_slice(frame);
+            self.rx_client
+                .map(move |client| client.receive(buffer, frame.len(), true, ReturnCode::SUCCESS));
+            true
+        })
+    }
+
+    pub fn calls(&self) -> Vec<RadioCall> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<RadioCall> {
+        self.calls.take()
+    }
+}
+
+impl radio::Radio for MockRadio {}
+
+impl radio::RadioConfig for MockRadio {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn start(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        true
+    }
+
+    fn busy(&self) -> bool {
+        self.busy.get()
+    }
+
+    fn set_power_client(&self, _client: &'static dyn radio::PowerClient) {}
+
+    fn config_commit(&self) {
+        self.calls.record(RadioCall::ConfigCommit);
+        self.config_client
+            .map(|client| client.config_done(ReturnCode::SUCCESS));
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.address.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.address_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        0
+    }
+
+    fn get_channel(&self) -> u8 {
+        26
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.address.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.address_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, _power: i8) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, _chan: u8) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+impl radio::RadioData for MockRadio {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(
+        &self,
+        client: &'static dyn radio::RxClient,
+        receive_buffer: &'static mut [u8],
+    ) {
+        self.rx_client.set(client);
+        self.rx_buffer.replace(receive_buffer);
+    }
+
+    fn set_receive_buffer(&self, receive_buffer: &'static mut [u8]) {
+        self.rx_buffer.replace(receive_buffer);
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(spi_buf));
+        }
+        self.calls.record(RadioCall::Transmit {
+            frame: spi_buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len].to_vec(),
+        });
+        self.tx_buffer.replace(spi_buf);
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -0,0 +1,334 @@
+//! Mock SPI master and SPI master device.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity};
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpiCall {
+    Init,
+    SpecifyChipSelect(u8),
+    Configure(ClockPolarity, ClockPhase, u32),
+    SetRate(u32),
+    SetPolarity(ClockPolarity),
+    SetPhase(ClockPhase),
+    HoldLow,
+    ReleaseLow,
+    ReadWriteBytes { write: Vec<u8>, read: bool },
+    WriteByte(u8),
+    ReadByte,
+    ReadWriteByte(u8),
+}
+
+/// Transfers shared by the mock master and the mock device.
+struct Transfers {
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    /// Bytes read by the next transfers, zeros once empty.
+    responses: RefCell<VecDeque<Vec<u8>>>,
+    calls: Calls<SpiCall>,
+}
+
+impl Transfers {
+    fn new() -> Transfers {
+        Transfers {
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(0),
+            responses: RefCell::new(VecDeque::new()),
+            calls: Calls::new(),
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn start(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        let len = core::cmp::min(len, write_buffer.len());
+        self.calls.record(SpiCall::ReadWriteBytes {
+            write: write_buffer[..len].to_vec(),
+            read: read_buffer.is_some(),
+        });
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        self.read_buffer.put(read_buffer);
+        ReturnCode::SUCCESS
+    }
+
+    fn complete<F>(&self, complete: F) -> bool
+    where
+        F: FnOnce(&'static mut [u8], Option<&'static mut [u8]>, usize),
+    {
+        self.write_buffer.take().map_or(false, |write_buffer| {
+            let response = self.responses.borrow_mut().pop_front().unwrap_or_default();
+            let len = self.len.get();
+            let read_buffer = self.read_buffer.take().map(|read_buffer| {
+                for (i, byte) in read_buffer.iter_mut().take(len).enumerate() {
+                    *byte = response.get(i).copied().unwrap_or(0);
+                }
+                read_buffer
+            });
+            complete(write_buffer, read_buffer, len);
+            true
+        })
+    }
+
+    fn next_byte(&self) -> u8 {
+        let mut responses = self.responses.borrow_mut();
+        let byte = responses.front_mut().and_then(|response| {
+            if response.is_empty() {
+                None
+            } else {
+                Some(response.remove(0))
+            }
+        });
+        if responses
+            .front()
+            .map_or(false, |response| response.is_empty())
+        {
+            responses.pop_front();
+        }
+        byte.unwrap_or(0)
+    }
+}
+
+/// A SPI master with `u8` chip selects.
+///
+/// Transfers stay pending until `complete()`, which fills the read buffer
+/// with the next response pushed with `push_response()`. Single byte reads
+/// take their bytes from the responses as well.
+pub struct MockSpiMaster {
+    client: OptionalCell<&'static dyn spi::SpiMasterClient>,
+    chip_select: Cell<Option<u8>>,
+    transfers: Transfers,
+}
+
+impl MockSpiMaster {
+    pub fn new() -> MockSpiMaster {
+        MockSpiMaster {
+            client: OptionalCell::empty(),
+            chip_select: Cell::new(None),
+            transfers: Transfers::new(),
+        }
+    }
+
+    /// Set the bytes read by the next transfer.
+    pub fn push_response(&self, response: Vec<u8>) {
+        self.transfers.responses.borrow_mut().push_back(response);
+    }
+
+    /// The chip select of the last transfer.
+    pub fn chip_select(&self) -> Option<u8> {
+        self.chip_select.get()
+    }
+
+    /// Finish the pending transfer. Returns whether there was one.
+    pub fn complete(&self) -> bool {
+        self.transfers.complete(|write_buffer, read_buffer, len| {
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        })
+    }
+
+    pub fn calls(&self) -> Vec<SpiCall> {
+        self.transfers.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<SpiCall> {
+        self.transfers.calls.take()
+    }
+}
+
+impl spi::SpiMaster for MockSpiMaster {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static dyn spi::SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    fn init(&self) {
+        self.transfers.calls.record(SpiCall::Init);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.transfers.is_busy()
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        self.transfers.start(write_buffer, read_buffer, len)
+    }
+
+    fn write_byte(&self, val: u8) {
+        self.transfers.calls.record(SpiCall::WriteByte(val));
+    }
+
+    fn read_byte(&self) -> u8 {
+        self.transfers.calls.record(SpiCall::ReadByte);
+        self.transfers.next_byte()
+    }
+
+    fn read_write_byte(&self, val: u8) -> u8 {
+        self.transfers.calls.record(SpiCall::ReadWriteByte(val));
+        self.transfers.next_byte()
+    }
+
+    fn specify_chip_select(&self, cs: u8) {
+        self.transfers.calls.record(SpiCall::SpecifyChipSelect(cs));
+        self.chip_select.set(Some(cs));
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.transfers.calls.record(SpiCall::SetRate(rate));
+        self.transfers.rate.set(rate);
+        rate
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.transfers.rate.get()
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.transfers.calls.record(SpiCall::SetPolarity(polarity));
+        self.transfers.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.transfers.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.transfers.calls.record(SpiCall::SetPhase(phase));
+        self.transfers.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.transfers.phase.get()
+    }
+
+    fn hold_low(&self) {
+        self.transfers.calls.record(SpiCall::HoldLow);
+    }
+
+    fn release_low(&self) {
+        self.transfers.calls.record(SpiCall::ReleaseLow);
+    }
+}
+
+/// A SPI master device, for testing capsules that talk to a single device.
+///
+/// Behaves like `MockSpiMaster`.
+pub struct MockSpiMasterDevice<'a> {
+    client: OptionalCell<&'a dyn spi::SpiMasterClient>,
+    transfers: Transfers,
+}
+
+impl<'a> MockSpiMasterDevice<'a> {
+    pub fn new() -> MockSpiMasterDevice<'a> {
+        MockSpiMasterDevice {
+            client: OptionalCell::empty(),
+            transfers: Transfers::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn spi::SpiMasterClient) {
+        self.client.set(client);
+    }
+
+    /// Set the bytes read by the next transfer.
+    pub fn push_response(&self, response: Vec<u8>) {
+        self.transfers.responses.borrow_mut().push_back(response);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.transfers.is_busy()
+    }
+
+    /// Finish the pending transfer. Returns whether there was one.
+    pub fn complete(&self) -> bool {
+        self.transfers.complete(|write_buffer, read_buffer, len| {
+            self.client
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        })
+    }
+
+    pub fn calls(&self) -> Vec<SpiCall> {
+        self.transfers.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<SpiCall> {
+        self.transfers.calls.take()
+    }
+}
+
+impl spi::SpiMasterDevice for MockSpiMasterDevice<'_> {
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) {
+        self.transfers
+            .calls
+            .record(SpiCall::Configure(cpol, cpal, rate));
+        self.transfers.polarity.set(cpol);
+        self.transfers.phase.set(cpal);
+        self.transfers.rate.set(rate);
+    }
+
+    fn read_write_bytes(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> ReturnCode {
+        self.transfers.start(write_buffer, read_buffer, len)
+    }
+
+    fn set_polarity(&self, cpol: ClockPolarity) {
+        self.transfers.calls.record(SpiCall::SetPolarity(cpol));
+        self.transfers.polarity.set(cpol);
+    }
+
+    fn set_phase(&self, cpal: ClockPhase) {
+        self.transfers.calls.record(SpiCall::SetPhase(cpal));
+        self.transfers.phase.set(cpal);
+    }
+
+    fn set_rate(&self, rate: u32) {
+        self.transfers.calls.record(SpiCall::SetRate(rate));
+        self.transfers.rate.set(rate);
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.transfers.polarity.get()
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.transfers.phase.get()
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.transfers.rate.get()
+    }
+}
@@ -0,0 +1,238 @@
+use core::cell::RefCell;
+
+use capsules::aes_modes::Aes128Modes;
+use capsules::aes_software::Aes128Software;
+use capsules::test::aes::{CTXT_CBC, CTXT_CTR, CTXT_ECB, IV_CBC, IV_CTR, KEY, PTXT};
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE,
+};
+use kernel::ReturnCode;
+
+use crate::leak;
+use crate::tests::{buffer, deferred_caller, run_deferred_calls};
+
+/// Records the callbacks of an AES client.
+#[derive(Default)]
+struct Client {
+    done: RefCell<Vec<(Option<Vec<u8>>, Vec<u8>)>>,
+}
+
+impl<'a> symmetric_encryption::Client<'a> for Client {
+    fn crypt_done(&'a self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        self.done
+            .borrow_mut()
+            .push((source.map(|source| source.to_vec()), dest.to_vec()));
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Mode {
+    Ecb,
+    Ctr,
+    Cbc,
+}
+
+trait Aes: AES128<'static> + AES128ECB + AES128Ctr + AES128CBC {}
+impl<A: AES128<'static> + AES128ECB + AES128Ctr + AES128CBC> Aes for A {}
+
+fn software() -> (
+    &'static Aes128Software<'static>,
+    &'static Client,
+    &'static DynamicDeferredCall,
+) {
+    let deferred_caller = deferred_caller(1);
+    let aes = leak(Aes128Software::new(deferred_caller));
+    aes.initialize_callback_handle(deferred_caller.register(aes).unwrap());
+    let client = leak(Client::default());
+    aes.set_client(client);
+    (aes, client, deferred_caller)
+}
+
+/// The CTR and CBC wrapper, on top of the software engine used in ECB mode.
+fn modes() -> (
+    &'static Aes128Modes<'static, Aes128Software<'static>>,
+    &'static Client,
+    &'static DynamicDeferredCall,
+) {
+    let (ecb, _, deferred_caller) = software();
+    let aes = leak(Aes128Modes::new(ecb, leak([0; AES128_BLOCK_SIZE])));
+    ecb.set_client(aes);
+    let client = leak(Client::default());
+    aes.set_client(client);
+    (aes, client, deferred_caller)
+}
+
+fn set_mode(aes: &dyn Aes, mode: Mode, encrypting: bool) {
+    assert_eq!(aes.set_key(&KEY), ReturnCode::SUCCESS);
+    match mode {
+        Mode::Ecb => aes.set_mode_aes128ecb(encrypting),
+        Mode::Ctr => {
+            assert_eq!(aes.set_iv(&IV_CTR), ReturnCode::SUCCESS);
+            aes.set_mode_aes128ctr(encrypting);
+        }
+        Mode::Cbc => {
+            assert_eq!(aes.set_iv(&IV_CBC), ReturnCode::SUCCESS);
+            aes.set_mode_aes128cbc(encrypting);
+        }
+    }
+    aes.start_message();
+}
+
+/// Encrypt or decrypt `input` with `mode`, from a source buffer or in
+/// place, in a single request.
+fn crypt(
+    aes: &'static dyn Aes,
+    client: &Client,
+    deferred_caller: &DynamicDeferredCall,
+    mode: Mode,
+    encrypting: bool,
+    input: &[u8],
+    in_place: bool,
+) -> Vec<u8> {
+    set_mode(aes, mode, encrypting);
+    // The data is preceded by a block that must not be touched.
+    let offset = AES128_BLOCK_SIZE;
+    let mut dest = vec![0xA5; offset + input.len()];
+    let source = if in_place {
+        dest[offset..].copy_from_slice(input);
+        None
+    } else {
+        Some(buffer(input))
+    };
+    assert!(aes
+        .crypt(source, buffer(&dest), offset, offset + input.len())
+        .is_none());
+    run_deferred_calls(deferred_caller);
+
+    let (source, dest) = client.done.borrow_mut().pop().unwrap();
+    assert_eq!(source.is_none(), in_place);
+    assert_eq!(dest[..offset], [0xA5; AES128_BLOCK_SIZE]);
+    dest[offset..].to_vec()
+}
+
+fn expected(mode: Mode) -> &'static [u8] {
+    match mode {
+        Mode::Ecb => &CTXT_ECB,
+        Mode::Ctr => &CTXT_CTR,
+        Mode::Cbc => &CTXT_CBC,
+    }
+}
+
+fn check_test_vectors(
+    aes: &'static dyn Aes,
+    client: &Client,
+    deferred_caller: &DynamicDeferredCall,
+) {
+    for mode in [Mode::Ecb, Mode::Ctr, Mode::Cbc].iter() {
+        for in_place in [false, true].iter() {
+            assert_eq!(
+                crypt(aes, client, deferred_caller, *mode, true, &PTXT, *in_place),
+                expected(*mode),
+                "{:?} encryption",
+                mode
+            );
+            assert_eq!(
+                crypt(
+                    aes,
+                    client,
+                    deferred_caller,
+                    *mode,
+                    false,
+                    expected(*mode),
+                    *in_place
+                ),
+                PTXT,
+                "{:?} decryption",
+                mode
+            );
+        }
+    }
+}
+
+#[test]
+fn software_test_vectors() {
+    let (aes, client, deferred_caller) = software();
+    check_test_vectors(aes, client, deferred_caller);
+}
+
+#[test]
+fn modes_test_vectors() {
+    let (aes, client, deferred_caller) = modes();
+    check_test_vectors(aes, client, deferred_caller);
+}
+
+/// Encrypt the test plaintext one block per request, within one message.
+fn crypt_blocks(
+    aes: &'static dyn Aes,
+    client: &Client,
+    deferred_caller: &DynamicDeferredCall,
+    mode: Mode,
+) -> Vec<u8> {
+    set_mode(aes, mode, true);
+    let mut output = Vec::new();
+    for block in PTXT.chunks(AES128_BLOCK_SIZE) {
+        assert!(aes
+            .crypt(None, buffer(block), 0, AES128_BLOCK_SIZE)
+            .is_none());
+        run_deferred_calls(deferred_caller);
+        output.extend(client.done.borrow_mut().pop().unwrap().1);
+    }
+    output
+}
+
+#[test]
+fn message_continues_across_requests() {
+    let (aes, client, deferred_caller) = software();
+    assert_eq!(
+        crypt_blocks(aes, client, deferred_caller, Mode::Ctr),
+        CTXT_CTR
+    );
+    assert_eq!(
+        crypt_blocks(aes, client, deferred_caller, Mode::Cbc),
+        CTXT_CBC
+    );
+
+    let (aes, client, deferred_caller) = modes();
+    assert_eq!(
+        crypt_blocks(aes, client, deferred_caller, Mode::Ctr),
+        CTXT_CTR
+    );
+    assert_eq!(
+        crypt_blocks(aes, client, deferred_caller, Mode::Cbc),
+        CTXT_CBC
+    );
+}
+
+#[test]
+fn invalid_requests_are_rejected() {
+    let (software, _, _) = software();
+    let (modes, _, _) = modes();
+    for aes in [software as &dyn Aes, modes].iter() {
+        assert_eq!(aes.set_key(&KEY[..8]), ReturnCode::EINVAL);
+        assert_eq!(aes.set_iv(&IV_CBC[..15]), ReturnCode::EINVAL);
+
+        let (rcode, _, _) = aes.crypt(None, buffer(&[0; 32]), 0, 15).unwrap();
+        assert_eq!(rcode, ReturnCode::EINVAL);
+        let (rcode, _, _) = aes.crypt(None, buffer(&[0; 32]), 16, 48).unwrap();
+        assert_eq!(rcode, ReturnCode::EINVAL);
+        let (rcode, source, _) = aes
+            .crypt(Some(buffer(&[0; 32])), buffer(&[0; 32]), 0, 16)
+            .unwrap();
+        assert_eq!(rcode, ReturnCode::EINVAL);
+        assert_eq!(source.unwrap().len(), 32);
+    }
+}
+
+#[test]
+fn crypt_while_busy_is_rejected() {
+    let (aes, client, deferred_caller) = modes();
+    set_mode(aes, Mode::Ecb, true);
+    assert!(aes.crypt(None, buffer(&PTXT), 0, PTXT.len()).is_none());
+    let (rcode, _, dest) = aes.crypt(None, buffer(&[1; 16]), 0, 16).unwrap();
+    assert_eq!(rcode, ReturnCode::EBUSY);
+    assert_eq!(dest, [1; 16]);
+
+    run_deferred_calls(deferred_caller);
+    assert_eq!(client.done.borrow()[0].1, CTXT_ECB);
+}
@@ -0,0 +1,629 @@
+use core::cell::{Cell, RefCell};
+
+use capsules::ble::gatt_server::{
+    GattServer, GattServerClient, PROPERTY_NOTIFY, PROPERTY_READ, PROPERTY_WRITE,
+    PROPERTY_WRITE_WITHOUT_RESPONSE,
+};
+use capsules::ble::link_layer::{
+    Connection, ConnectionClient, LinkLayer, BUFFER_LEN, CONNECTION_TIMEOUT, FAILED_TO_ESTABLISH,
+    LOCAL_HOST_TERMINATED, REMOTE_USER_TERMINATED,
+};
+use kernel::hil::ble_advertising::{self, BleAdvertisementDriver, RadioChannel};
+use kernel::hil::time::{Alarm, Ticks, Ticks32, Time};
+use kernel::ErrorCode;
+
+use crate::alarm::MockAlarm;
+use crate::ble::{BleCall, MockBleRadio};
+use crate::leak;
+
+type TestLinkLayer = LinkLayer<'static, MockBleRadio<'static>, MockAlarm<'static>>;
+
+const ADDRESS: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6];
+const ACCESS_ADDRESS: u32 = 0x5065_a3f1;
+const CRC_INIT: u32 = 0x17_a0c3;
+const ALL_CHANNELS: [u8; 5] = [0xff, 0xff, 0xff, 0xff, 0x1f];
+// 50 ms, i.e. 50 ticks of the mock alarm.
+const INTERVAL: u16 = 40;
+// 1 s.
+const TIMEOUT: u16 = 100;
+
+const LLID_EMPTY: u8 = 0b01;
+const LLID_START: u8 = 0b10;
+const LLID_CONTROL: u8 = 0b11;
+
+/// Records the callbacks of the link layer and of the GATT server.
+#[derive(Default)]
+struct Events {
+    connected: Cell<usize>,
+    disconnected: RefCell<Vec<u8>>,
+    received: RefCell<Vec<Vec<u8>>>,
+    send_done: Cell<usize>,
+    written: RefCell<Vec<usize>>,
+}
+
+impl ConnectionClient for Events {
+    fn connected(&self) {
+        self.connected.set(self.connected.get() + 1);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.disconnected.borrow_mut().push(reason);
+    }
+
+    fn received(&self, frame: &[u8]) {
+        self.received.borrow_mut().push(frame.to_vec());
+    }
+
+    fn send_done(&self) {
+        self.send_done.set(self.send_done.get() + 1);
+    }
+}
+
+impl GattServerClient for Events {
+    fn connected(&self) {
+        self.connected.set(self.connected.get() + 1);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.disconnected.borrow_mut().push(reason);
+    }
+
+    fn value_written(&self, index: usize) {
+        self.written.borrow_mut().push(index);
+    }
+}
+
+struct Fixture {
+    radio: &'static MockBleRadio<'static>,
+    alarm: &'static MockAlarm<'static>,
+    link_layer: &'static TestLinkLayer,
+    events: &'static Events,
+}
+
+fn link_layer() -> Fixture {
+    let radio = leak(MockBleRadio::new());
+    let alarm = leak(MockAlarm::new());
+    let link_layer = leak(LinkLayer::new(radio, alarm, leak([0; BUFFER_LEN])));
+    radio.set_receive_client(link_layer);
+    radio.set_transmit_client(link_layer);
+    alarm.set_alarm_client(link_layer);
+    link_layer.set_address(ADDRESS);
+    let events = leak(Events::default());
+    link_layer.set_connection_client(events);
+    Fixture {
+        radio,
+        alarm,
+        link_layer,
+        events,
+    }
+}
+
+fn gatt_server(fixture: &Fixture) -> &'static GattServer<'static, TestLinkLayer> {
+    let server = leak(GattServer::new(fixture.link_layer));
+    fixture.link_layer.set_connection_client(server);
+    server.set_client(fixture.events);
+    server.set_device_name(b"tock").unwrap();
+    server.set_service(0xfff0).unwrap();
+    assert_eq!(
+        server.add_characteristic(0xfff1, PROPERTY_READ | PROPERTY_NOTIFY),
+        Ok(0)
+    );
+    assert_eq!(
+        server.add_characteristic(0xfff2, PROPERTY_WRITE | PROPERTY_WRITE_WITHOUT_RESPONSE),
+        Ok(1)
+    );
+    server
+}
+
+fn transmitted(radio: &MockBleRadio) -> Vec<(RadioChannel, Vec<u8>)> {
+    radio
+        .take_calls()
+        .into_iter()
+        .filter_map(|call| match call {
+            BleCall::Transmit { channel, packet } => Some((channel, packet)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn connect_ind(channel_map: [u8; 5], hop: u8) -> Vec<u8> {
+    let mut packet = vec![0x85, 34];
+    packet.extend(&[0xaa; 6]);
+    packet.extend(&ADDRESS);
+    packet.extend(&ACCESS_ADDRESS.to_le_bytes());
+    packet.extend(&CRC_INIT.to_le_bytes()[..3]);
+    // Window size and offset.
+    packet.extend(&[2, 0, 0]);
+    packet.extend(&INTERVAL.to_le_bytes());
+    // Latency.
+    packet.extend(&[0, 0]);
+    packet.extend(&TIMEOUT.to_le_bytes());
+    packet.extend(&channel_map);
+    packet.push(hop);
+    packet
+}
+
+/// A central, connected to the link layer.
+struct Central {
+    radio: &'static MockBleRadio<'static>,
+    alarm: &'static MockAlarm<'static>,
+    sequence: bool,
+    next_expected_sequence: bool,
+}
+
+struct Response {
+    channel: RadioChannel,
+    llid: u8,
+    payload: Vec<u8>,
+}
+
+impl Central {
+    /// Run a connection event: wait for the link layer to listen, send a PDU
+    /// and return the response.
+    fn exchange(&mut self, llid: u8, payload: &[u8]) -> Response {
+        assert!(self.alarm.advance_to_alarm());
+        let channel = self.radio.receiving().expect("not listening");
+        let header = llid | (self.next_expected_sequence as u8) << 2 | (self.sequence as u8) << 3;
+        let mut packet = vec![header, payload.len() as u8];
+        packet.extend(payload);
+        self.radio.take_calls();
+        assert!(self.radio.receive(&packet));
+
+        let (response_channel, response) = transmitted(self.radio).pop().expect("no response");
+        assert_eq!(response_channel, channel);
+        assert_eq!(response.len(), 2 + response[1] as usize);
+        assert!(self.radio.complete_transmit());
+
+        // Our packet was acknowledged.
+        if (response[0] & 1 << 2 != 0) != self.sequence {
+            self.sequence = !self.sequence;
+        }
+        // The response is a new packet.
+        if (response[0] & 1 << 3 != 0) == self.next_expected_sequence {
+            self.next_expected_sequence = !self.next_expected_sequence;
+        }
+        Response {
+            channel,
+            llid: response[0] & 0b11,
+            payload: response[2..].to_vec(),
+        }
+    }
+
+    fn empty(&mut self) -> Response {
+        self.exchange(LLID_EMPTY, &[])
+    }
+
+    /// Acknowledge the termination of the connection, which gets no
+    /// response.
+    fn acknowledge_termination(&mut self) {
+        assert!(self.alarm.advance_to_alarm());
+        let header =
+            LLID_EMPTY | (self.next_expected_sequence as u8) << 2 | (self.sequence as u8) << 3;
+        self.radio.take_calls();
+        assert!(self.radio.receive(&[header, 0]));
+        assert!(transmitted(self.radio).is_empty());
+    }
+
+    /// Send an ATT request and return the ATT response.
+    fn att(&mut self, request: &[u8]) -> Vec<u8> {
+        let mut frame = (request.len() as u16).to_le_bytes().to_vec();
+        frame.extend(&[0x04, 0x00]);
+        frame.extend(request);
+        let response = self.exchange(LLID_START, &frame);
+        assert_eq!(response.llid, LLID_START);
+        att_payload(&response.payload)
+    }
+}
+
+fn att_payload(frame: &[u8]) -> Vec<u8> {
+    assert_eq!(frame[..4], [frame.len() as u8 - 4, 0, 0x04, 0x00]);
+    frame[4..].to_vec()
+}
+
+/// Advertise and let a central connect after the first advertisement.
+fn connect(fixture: &Fixture, channel_map: [u8; 5], hop: u8) -> Central {
+    let connected = fixture.events.connected.get();
+    fixture.link_layer.start_advertising(100).unwrap();
+    assert!(fixture.alarm.advance_to_alarm());
+    assert!(fixture.radio.complete_transmit());
+    assert!(fixture.radio.receive(&connect_ind(channel_map, hop)));
+    assert_eq!(fixture.events.connected.get(), connected + 1);
+    assert!(fixture.link_layer.is_connected());
+    Central {
+        radio: fixture.radio,
+        alarm: fixture.alarm,
+        sequence: false,
+        next_expected_sequence: false,
+    }
+}
+
+#[test]
+fn advertises_connectable_on_each_channel() {
+    let fixture = link_layer();
+    let (radio, alarm) = (fixture.radio, fixture.alarm);
+    fixture
+        .link_layer
+        .set_advertising_data(&[0x02, 0x01, 0x06])
+        .unwrap();
+    fixture.link_layer.start_advertising(100).unwrap();
+
+    let mut advertisement = vec![0x40, 9];
+    advertisement.extend(&ADDRESS);
+    advertisement.extend(&[0x02, 0x01, 0x06]);
+    for channel in [
+        RadioChannel::AdvertisingChannel37,
+        RadioChannel::AdvertisingChannel38,
+        RadioChannel::AdvertisingChannel39,
+    ]
+    .iter()
+    {
+        assert!(alarm.advance_to_alarm());
+        assert!(radio.complete_transmit());
+        let calls = radio.take_calls();
+        assert!(calls.contains(&BleCall::Transmit {
+            channel: *channel,
+            packet: advertisement.clone(),
+        }));
+        assert_eq!(radio.receiving(), Some(*channel));
+
+        // Advertisements of other devices do not stop the listening.
+        assert!(radio.receive(&[0x40, 6, 1, 1, 1, 1, 1, 1]));
+        assert_eq!(
+            radio.take_calls(),
+            vec![BleCall::Receive { channel: *channel }]
+        );
+    }
+
+    assert!(alarm.advance_to_alarm());
+    assert_eq!(radio.take_calls(), vec![BleCall::CancelReceive]);
+    // The next advertising event is after the interval and a random delay.
+    let remaining = alarm.remaining().unwrap().into_u32();
+    assert!((100..110).contains(&remaining));
+
+    fixture.link_layer.stop_advertising().unwrap();
+    assert!(!alarm.is_armed());
+}
+
+#[test]
+fn connection_events_hop_channels() {
+    let fixture = link_layer();
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    assert!(fixture.radio.calls().contains(&BleCall::SetAccessAddress {
+        access_address: ACCESS_ADDRESS,
+        crc_init: CRC_INIT,
+    }));
+
+    let mut channels = vec![];
+    for _ in 0..6 {
+        let response = central.empty();
+        assert_eq!(response.llid, LLID_EMPTY);
+        assert!(response.payload.is_empty());
+        channels.push(response.channel.get_channel_index());
+        // The next event is one interval after this one.
+        assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(50)));
+    }
+    assert_eq!(channels, vec![7, 14, 21, 28, 35, 5]);
+}
+
+#[test]
+fn channel_map_remaps_unused_channels() {
+    let fixture = link_layer();
+    // Only channels 0 to 9 are used.
+    let mut central = connect(&fixture, [0xff, 0x03, 0, 0, 0], 7);
+    let channels: Vec<u32> = (0..6)
+        .map(|_| central.empty().channel.get_channel_index())
+        .collect();
+    assert_eq!(channels, vec![7, 4, 1, 8, 5, 5]);
+
+    // Use all channels from the event after the next one.
+    let mut channel_map_ind = vec![0x01];
+    channel_map_ind.extend(&ALL_CHANNELS);
+    channel_map_ind.extend(&7u16.to_le_bytes());
+    assert_eq!(
+        central
+            .exchange(LLID_CONTROL, &channel_map_ind)
+            .channel
+            .get_channel_index(),
+        2
+    );
+    let channels: Vec<u32> = (0..2)
+        .map(|_| central.empty().channel.get_channel_index())
+        .collect();
+    assert_eq!(channels, vec![19, 26]);
+}
+
+#[test]
+fn frames_are_acknowledged_or_retransmitted() {
+    let fixture = link_layer();
+    let link_layer = fixture.link_layer;
+    assert_eq!(link_layer.send(&[1, 2, 3]), Err(ErrorCode::OFF));
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+
+    assert_eq!(link_layer.send(&[0; 28]), Err(ErrorCode::SIZE));
+    assert_eq!(link_layer.send(&[1, 2, 3]), Ok(()));
+    assert_eq!(link_layer.send(&[4, 5]), Err(ErrorCode::BUSY));
+    let response = central.empty();
+    assert_eq!(
+        (response.llid, response.payload),
+        (LLID_START, vec![1, 2, 3])
+    );
+    assert_eq!(fixture.events.send_done.get(), 0);
+
+    // The central acknowledges the frame in its next packet.
+    let response = central.exchange(LLID_START, &[9, 9]);
+    assert_eq!(response.llid, LLID_EMPTY);
+    assert_eq!(fixture.events.send_done.get(), 1);
+    assert_eq!(*fixture.events.received.borrow(), vec![vec![9, 9]]);
+
+    // The central misses the next frame, which is sent again.
+    assert_eq!(link_layer.send(&[4, 5]), Ok(()));
+    assert_eq!(central.empty().payload, vec![4, 5]);
+    central.next_expected_sequence = !central.next_expected_sequence;
+    assert_eq!(central.empty().payload, vec![4, 5]);
+    assert_eq!(fixture.events.send_done.get(), 1);
+    assert!(central.empty().payload.is_empty());
+    assert_eq!(fixture.events.send_done.get(), 2);
+
+    // The central retransmits a packet that was not acknowledged, which is
+    // not received twice.
+    central.exchange(LLID_START, &[7]);
+    central.sequence = !central.sequence;
+    central.next_expected_sequence = !central.next_expected_sequence;
+    central.exchange(LLID_START, &[7]);
+    assert_eq!(fixture.events.received.borrow().len(), 2);
+}
+
+#[test]
+fn supervision_timeout_ends_connection() {
+    let fixture = link_layer();
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    central.empty();
+    let last_packet = fixture.alarm.now().into_u32();
+
+    // The central stops sending packets.
+    while fixture.events.disconnected.borrow().is_empty() {
+        assert!(fixture.alarm.advance_to_alarm());
+    }
+    assert_eq!(
+        *fixture.events.disconnected.borrow(),
+        vec![CONNECTION_TIMEOUT]
+    );
+    assert!(fixture.alarm.now().into_u32() - last_packet > 1000);
+    assert!(!fixture.link_layer.is_connected());
+    assert!(!fixture.alarm.is_armed());
+    assert_eq!(
+        fixture.radio.access_address(),
+        (
+            ble_advertising::ADVERTISING_ACCESS_ADDRESS,
+            ble_advertising::ADVERTISING_CRC_INIT
+        )
+    );
+}
+
+#[test]
+fn connection_fails_without_first_packet() {
+    let fixture = link_layer();
+    connect(&fixture, ALL_CHANNELS, 7);
+    let mut windows = 0;
+    while fixture.events.disconnected.borrow().is_empty() {
+        assert!(fixture.alarm.advance_to_alarm());
+        if fixture.radio.receiving().is_some() {
+            windows += 1;
+        }
+    }
+    assert_eq!(windows, 6);
+    assert_eq!(
+        *fixture.events.disconnected.borrow(),
+        vec![FAILED_TO_ESTABLISH]
+    );
+}
+
+#[test]
+fn control_procedures_are_answered() {
+    let fixture = link_layer();
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    let mut control = |request: &[u8]| {
+        let response = central.exchange(LLID_CONTROL, request);
+        assert_eq!(response.llid, LLID_CONTROL);
+        response.payload
+    };
+
+    // LL_VERSION_IND
+    assert_eq!(
+        control(&[0x0c, 0x09, 0x59, 0x00, 0x01, 0x00]),
+        vec![0x0c, 0x08, 0xff, 0xff, 0x00, 0x00]
+    );
+    // LL_FEATURE_REQ
+    assert_eq!(
+        control(&[0x08, 0xff, 0, 0, 0, 0, 0, 0, 0]),
+        vec![0x09, 0x10, 0, 0, 0, 0, 0, 0, 0]
+    );
+    // LL_PING_REQ
+    assert_eq!(control(&[0x12]), vec![0x13]);
+    // LL_ENC_REQ is not supported.
+    assert_eq!(control(&[0x03; 23]), vec![0x07, 0x03]);
+
+    // The central terminates the connection.
+    let response = central.exchange(LLID_CONTROL, &[0x02, REMOTE_USER_TERMINATED]);
+    assert_eq!(response.llid, LLID_EMPTY);
+    assert_eq!(
+        *fixture.events.disconnected.borrow(),
+        vec![REMOTE_USER_TERMINATED]
+    );
+    assert!(!fixture.alarm.is_armed());
+}
+
+#[test]
+fn disconnect_terminates_connection() {
+    let fixture = link_layer();
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    assert_eq!(fixture.link_layer.disconnect(), Ok(()));
+    let response = central.empty();
+    assert_eq!(
+        (response.llid, response.payload),
+        (LLID_CONTROL, vec![0x02, REMOTE_USER_TERMINATED])
+    );
+    assert!(fixture.events.disconnected.borrow().is_empty());
+
+    central.acknowledge_termination();
+    assert_eq!(
+        *fixture.events.disconnected.borrow(),
+        vec![LOCAL_HOST_TERMINATED]
+    );
+}
+
+#[test]
+fn connection_update_applies_at_instant() {
+    let fixture = link_layer();
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    central.empty();
+
+    // At event 4, move to an interval of 100 ms, with a transmit window 2.5
+    // ms after the previous interval.
+    let mut update = vec![0x00, 1];
+    update.extend(&2u16.to_le_bytes());
+    update.extend(&80u16.to_le_bytes());
+    update.extend(&0u16.to_le_bytes());
+    update.extend(&TIMEOUT.to_le_bytes());
+    update.extend(&4u16.to_le_bytes());
+    central.exchange(LLID_CONTROL, &update);
+    assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(50)));
+    central.empty();
+    assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(50)));
+    central.empty();
+    assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(52)));
+    central.empty();
+    assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(100)));
+    central.empty();
+    assert_eq!(fixture.alarm.remaining(), Some(Ticks32::from(100)));
+
+    // An update with an instant that passed ends the connection.
+    update[10..12].copy_from_slice(&1u16.to_le_bytes());
+    central.exchange(LLID_CONTROL, &update);
+    assert_eq!(*fixture.events.disconnected.borrow(), vec![0x28]);
+}
+
+#[test]
+fn gatt_server_is_discovered() {
+    let fixture = link_layer();
+    gatt_server(&fixture);
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+
+    // Exchange MTU
+    assert_eq!(central.att(&[0x02, 185, 0]), vec![0x03, 23, 0]);
+    // Read By Group Type, for primary services
+    assert_eq!(
+        central.att(&[0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28]),
+        vec![0x11, 6, 1, 0, 3, 0, 0x00, 0x18, 4, 0, 10, 0, 0xf0, 0xff]
+    );
+    assert_eq!(
+        central.att(&[0x10, 0x0b, 0x00, 0xff, 0xff, 0x00, 0x28]),
+        vec![0x01, 0x10, 0x0b, 0x00, 0x0a]
+    );
+    // Find By Type Value, for the service UUID
+    assert_eq!(
+        central.att(&[0x06, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28, 0xf0, 0xff]),
+        vec![0x07, 4, 0, 10, 0]
+    );
+    // Read By Type, for characteristic declarations
+    assert_eq!(
+        central.att(&[0x08, 0x05, 0x00, 0x0a, 0x00, 0x03, 0x28]),
+        vec![0x09, 7, 5, 0, 0x12, 6, 0, 0xf1, 0xff, 8, 0, 0x0c, 9, 0, 0xf2, 0xff]
+    );
+    // Find Information, for descriptors. Only the first characteristic
+    // supports notifications.
+    assert_eq!(
+        central.att(&[0x04, 0x07, 0x00, 0x07, 0x00]),
+        vec![0x05, 1, 7, 0, 0x02, 0x29]
+    );
+    assert_eq!(
+        central.att(&[0x04, 0x0a, 0x00, 0x0a, 0x00]),
+        vec![0x01, 0x04, 0x0a, 0x00, 0x0a]
+    );
+    // Read, for the device name
+    assert_eq!(central.att(&[0x0a, 0x03, 0x00]), b"\x0btock".to_vec());
+    // Invalid handle ranges and unsupported requests
+    assert_eq!(
+        central.att(&[0x08, 0x05, 0x00, 0x04, 0x00, 0x03, 0x28]),
+        vec![0x01, 0x08, 0x05, 0x00, 0x01]
+    );
+    assert_eq!(
+        central.att(&[0x16, 0x06, 0x00, 0x00, 0x00, 1]),
+        vec![0x01, 0x16, 0x00, 0x00, 0x06]
+    );
+}
+
+#[test]
+fn gatt_values_are_read_and_written() {
+    let fixture = link_layer();
+    let server = gatt_server(&fixture);
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    server.set_value(0, b"42").unwrap();
+
+    // Read and Read Blob
+    assert_eq!(central.att(&[0x0a, 0x06, 0x00]), b"\x0b42".to_vec());
+    assert_eq!(central.att(&[0x0c, 0x06, 0x00, 1, 0]), b"\x0d2".to_vec());
+    assert_eq!(
+        central.att(&[0x0c, 0x06, 0x00, 3, 0]),
+        vec![0x01, 0x0c, 0x06, 0x00, 0x07]
+    );
+    // The second characteristic cannot be read.
+    assert_eq!(
+        central.att(&[0x0a, 0x09, 0x00]),
+        vec![0x01, 0x0a, 0x09, 0x00, 0x02]
+    );
+
+    // Write Request
+    assert_eq!(central.att(&[0x12, 0x09, 0x00, 1, 2, 3]), vec![0x13]);
+    assert_eq!(*fixture.events.written.borrow(), vec![1]);
+    let mut value = [0; 20];
+    assert_eq!(server.value(1, &mut value), Ok(3));
+    assert_eq!(value[..3], [1, 2, 3]);
+    // The first characteristic cannot be written.
+    assert_eq!(
+        central.att(&[0x12, 0x06, 0x00, 1]),
+        vec![0x01, 0x12, 0x06, 0x00, 0x03]
+    );
+
+    // Write Command, without response
+    let response = central.exchange(LLID_START, &[4, 0, 0x04, 0x00, 0x52, 0x09, 0x00, 7]);
+    assert_eq!(response.llid, LLID_EMPTY);
+    assert_eq!(*fixture.events.written.borrow(), vec![1, 1]);
+    assert_eq!(server.value(1, &mut value), Ok(1));
+}
+
+#[test]
+fn gatt_notifications_are_enabled_by_central() {
+    let fixture = link_layer();
+    let server = gatt_server(&fixture);
+    assert_eq!(server.notify(0), Err(ErrorCode::OFF));
+    let mut central = connect(&fixture, ALL_CHANNELS, 7);
+    server.set_value(0, b"7").unwrap();
+    assert_eq!(server.notify(0), Err(ErrorCode::RESERVE));
+    assert_eq!(server.notify(1), Err(ErrorCode::RESERVE));
+
+    // Write the client characteristic configuration.
+    assert_eq!(central.att(&[0x12, 0x07, 0x00, 0x01, 0x00]), vec![0x13]);
+    assert_eq!(central.att(&[0x0a, 0x07, 0x00]), vec![0x0b, 0x01, 0x00]);
+    central.empty();
+    assert_eq!(server.notify(0), Ok(()));
+    assert_eq!(server.notify(0), Err(ErrorCode::BUSY));
+
+    // A request made while the notification is sent is answered after it.
+    let response = central.exchange(LLID_START, &[3, 0, 0x04, 0x00, 0x0a, 0x06, 0x00]);
+    assert_eq!(att_payload(&response.payload), b"\x1b\x06\x007".to_vec());
+    let response = central.empty();
+    assert_eq!(att_payload(&response.payload), b"\x0b7".to_vec());
+
+    // Notifications are disabled again by a new connection.
+    fixture.link_layer.disconnect().unwrap();
+    central.empty();
+    central.acknowledge_termination();
+    assert_eq!(server.notify(0), Err(ErrorCode::OFF));
+    assert_eq!(
+        *fixture.events.disconnected.borrow(),
+        vec![LOCAL_HOST_TERMINATED]
+    );
+    connect(&fixture, ALL_CHANNELS, 7);
+    assert_eq!(server.notify(0), Err(ErrorCode::RESERVE));
+}
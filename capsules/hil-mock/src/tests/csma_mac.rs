@@ -0,0 +1,352 @@
+use core::cell::RefCell;
+
+use capsules::ieee802154::csma::{CsmaMac, FrameStats, FrameStatsClient};
+use capsules::ieee802154::mac::Mac;
+use capsules::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress};
+use capsules::rng::Entropy32ToRandom;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::rng::Rng;
+use kernel::hil::time::{Alarm, Freq1MHz, Ticks, Ticks32};
+use kernel::ReturnCode;
+
+use super::buffer;
+use crate::alarm::{AlarmCall, MockAlarm};
+use crate::entropy::MockEntropy32;
+use crate::leak;
+use crate::radio::{MockRadio, RadioCall};
+
+type TestAlarm = MockAlarm<'static, Ticks32, Freq1MHz>;
+type TestMac = CsmaMac<'static, MockRadio, TestAlarm>;
+
+const ADDRESS: u16 = 0x0001;
+const PEER: u16 = 0x0002;
+const BROADCAST: u16 = 0xffff;
+const PAYLOAD: &[u8] = b"frame";
+// Unit backoff period, in ticks of the 1 MHz alarm.
+const UNIT_BACKOFF: u32 = 320;
+const ACK_WAIT: u32 = 2000;
+
+/// Records the callbacks of the MAC.
+struct Client {
+    mac: OptionalCell<&'static TestMac>,
+    sent: RefCell<Vec<(bool, ReturnCode)>>,
+    received: RefCell<Vec<Vec<u8>>>,
+    stats: RefCell<Vec<FrameStats>>,
+}
+
+impl radio::TxClient for Client {
+    fn send_done(&self, _buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        self.sent.borrow_mut().push((acked, result));
+    }
+}
+
+impl radio::RxClient for Client {
+    fn receive(&self, buf: &'static mut [u8], frame_len: usize, _: bool, _: ReturnCode) {
+        self.received
+            .borrow_mut()
+            .push(buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len].to_vec());
+        self.mac.map(move |mac| mac.set_receive_buffer(buf));
+    }
+}
+
+impl FrameStatsClient for Client {
+    fn frame_stats(&self, stats: &FrameStats) {
+        self.stats.borrow_mut().push(*stats);
+    }
+}
+
+struct Fixture {
+    radio: &'static MockRadio,
+    alarm: &'static TestAlarm,
+    entropy: &'static MockEntropy32<'static>,
+    mac: &'static TestMac,
+    client: &'static Client,
+}
+
+fn mac() -> Fixture {
+    let radio = leak(MockRadio::new());
+    let alarm = leak(TestAlarm::new());
+    let entropy = leak(MockEntropy32::new());
+    let rng = leak(Entropy32ToRandom::new(entropy));
+    let mac = leak(CsmaMac::new(radio, alarm, rng));
+    rng.set_client(mac);
+    alarm.set_alarm_client(mac);
+    radio.set_transmit_client(mac);
+    radio.set_receive_client(mac, buffer(&[0; radio::MAX_BUF_SIZE]));
+    radio.set_address(ADDRESS);
+    assert_eq!(
+        mac.initialize(buffer(&[0; radio::MAX_BUF_SIZE])),
+        ReturnCode::SUCCESS
+    );
+
+    let client = leak(Client {
+        mac: OptionalCell::new(mac),
+        sent: RefCell::new(Vec::new()),
+        received: RefCell::new(Vec::new()),
+        stats: RefCell::new(Vec::new()),
+    });
+    mac.set_transmit_client(client);
+    mac.set_receive_client(client);
+    mac.set_stats_client(client);
+    Fixture {
+        radio,
+        alarm,
+        entropy,
+        mac,
+        client,
+    }
+}
+
+/// A data frame, without its FCS.
+fn data_frame(seq: u8, src: u16, dst: u16) -> Vec<u8> {
+    let header = Header {
+        frame_type: FrameType::Data,
+        frame_pending: false,
+        ack_requested: true,
+        version: FrameVersion::V2006,
+        seq: Some(seq),
+        dst_pan: Some(0xabcd),
+        dst_addr: Some(MacAddress::Short(dst)),
+        src_pan: Some(0xabcd),
+        src_addr: Some(MacAddress::Short(src)),
+        security: None,
+        header_ies: Default::default(),
+        header_ies_len: 0,
+        payload_ies: Default::default(),
+        payload_ies_len: 0,
+    };
+    let mut frame = vec![0; radio::MAX_MTU];
+    let (len, _) = header.encode(&mut frame, true).done().unwrap();
+    frame.truncate(len);
+    frame.extend(PAYLOAD);
+    frame
+}
+
+fn ack_frame(seq: u8) -> Vec<u8> {
+    vec![0x02, 0x10, seq]
+}
+
+impl Fixture {
+    /// Transmit `frame` through the MAC.
+    fn transmit(&self, frame: &[u8]) -> ReturnCode {
+        let mut buf = vec![0; radio::MAX_BUF_SIZE];
+        buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame.len()].copy_from_slice(frame);
+        let (result, _) = self.mac.transmit(buffer(&buf), frame.len());
+        result
+    }
+
+    /// Draw `random` for the pending backoff.
+    fn randomness(&self, random: u32) {
+        self.entropy.push_entropy(&[random]);
+        assert!(self.entropy.complete());
+    }
+
+    /// Draw a backoff of 0 periods, which transmits right away.
+    fn no_backoff(&self) {
+        self.randomness(0);
+    }
+
+    fn transmitted(&self) -> Vec<Vec<u8>> {
+        self.radio
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RadioCall::Transmit { frame } => Some(frame),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn alarm_dt(&self) -> Option<u32> {
+        self.alarm
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                AlarmCall::SetAlarm { dt, .. } => Some(dt.into_u32()),
+                AlarmCall::Disarm => None,
+            })
+            .last()
+    }
+
+    fn sent(&self) -> Vec<(bool, ReturnCode)> {
+        self.client.sent.borrow().clone()
+    }
+
+    fn stats(&self) -> FrameStats {
+        *self.client.stats.borrow().last().unwrap()
+    }
+}
+
+#[test]
+fn frame_is_sent_after_a_random_backoff() {
+    let f = mac();
+    let frame = data_frame(1, ADDRESS, PEER);
+    assert_eq!(f.transmit(&frame), ReturnCode::SUCCESS);
+
+    // The backoff exponent starts at 3, so 0x25 is 5 periods.
+    f.randomness(0x25);
+    assert_eq!(f.alarm_dt(), Some(5 * UNIT_BACKOFF));
+    assert!(!f.radio.is_transmitting());
+
+    assert!(f.alarm.advance_to_alarm());
+    assert_eq!(f.transmitted(), vec![frame]);
+}
+
+#[test]
+fn unicast_frame_completes_with_its_ack() {
+    let f = mac();
+    assert_eq!(
+        f.transmit(&data_frame(1, ADDRESS, PEER)),
+        ReturnCode::SUCCESS
+    );
+    f.no_backoff();
+    assert!(f.radio.complete_transmit(false, ReturnCode::SUCCESS));
+    assert_eq!(f.alarm_dt(), Some(ACK_WAIT));
+    assert!(f.sent().is_empty());
+
+    assert!(f.radio.receive(&ack_frame(1)));
+    assert_eq!(f.sent(), vec![(true, ReturnCode::SUCCESS)]);
+    assert!(!f.alarm.is_armed());
+    // The ACK stays in the MAC layer.
+    assert!(f.client.received.borrow().is_empty());
+    assert!(f.radio.has_receive_buffer());
+    assert_eq!(
+        f.stats(),
+        FrameStats {
+            seq: Some(1),
+            result: ReturnCode::SUCCESS,
+            acked: true,
+            transmissions: 1,
+            backoffs: 1,
+            busy_channels: 0,
+        }
+    );
+}
+
+#[test]
+fn ack_for_another_frame_is_ignored() {
+    let f = mac();
+    f.transmit(&data_frame(1, ADDRESS, PEER));
+    f.no_backoff();
+    f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+
+    assert!(f.radio.receive(&ack_frame(2)));
+    assert!(f.sent().is_empty());
+    assert!(f.alarm.is_armed());
+}
+
+#[test]
+fn unacknowledged_frame_is_retried_then_fails() {
+    let f = mac();
+    let frame = data_frame(1, ADDRESS, PEER);
+    f.transmit(&frame);
+    for _ in 0..4 {
+        f.no_backoff();
+        assert_eq!(f.transmitted(), vec![frame.clone()]);
+        f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+        assert!(f.alarm.advance_to_alarm());
+    }
+
+    assert_eq!(f.sent(), vec![(false, ReturnCode::ENOACK)]);
+    assert!(!f.entropy.is_requested());
+    let stats = f.stats();
+    assert_eq!(stats.transmissions, 4);
+    assert_eq!(stats.backoffs, 4);
+}
+
+#[test]
+fn frame_acknowledged_by_the_radio_completes() {
+    let f = mac();
+    f.transmit(&data_frame(1, ADDRESS, PEER));
+    f.no_backoff();
+    f.radio.complete_transmit(true, ReturnCode::SUCCESS);
+    assert_eq!(f.sent(), vec![(true, ReturnCode::SUCCESS)]);
+    assert!(!f.alarm.is_armed());
+}
+
+#[test]
+fn broadcast_frame_does_not_wait_for_an_ack() {
+    let f = mac();
+    f.transmit(&data_frame(1, ADDRESS, BROADCAST));
+    f.no_backoff();
+    f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+    assert_eq!(f.sent(), vec![(false, ReturnCode::SUCCESS)]);
+    assert!(!f.alarm.is_armed());
+}
+
+#[test]
+fn busy_channel_increases_the_backoff_then_fails() {
+    let f = mac();
+    f.radio.set_busy(true);
+    f.transmit(&data_frame(1, ADDRESS, PEER));
+
+    // The backoff exponent grows from 3 to at most 5.
+    for &periods in &[7, 15, 31, 31, 31] {
+        f.randomness(u32::MAX);
+        assert_eq!(f.alarm_dt(), Some(periods * UNIT_BACKOFF));
+        assert!(f.alarm.advance_to_alarm());
+    }
+
+    assert_eq!(f.sent(), vec![(false, ReturnCode::EBUSY)]);
+    assert!(f.transmitted().is_empty());
+    let stats = f.stats();
+    assert_eq!(stats.busy_channels, 5);
+    assert_eq!(stats.backoffs, 5);
+    assert_eq!(stats.transmissions, 0);
+}
+
+#[test]
+fn frame_is_sent_once_the_channel_is_free() {
+    let f = mac();
+    f.radio.set_busy(true);
+    f.transmit(&data_frame(1, ADDRESS, BROADCAST));
+    f.no_backoff();
+    assert!(f.transmitted().is_empty());
+
+    f.radio.set_busy(false);
+    f.no_backoff();
+    assert_eq!(f.transmitted().len(), 1);
+    f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+    assert_eq!(f.stats().busy_channels, 1);
+    assert_eq!(f.stats().backoffs, 2);
+}
+
+#[test]
+fn transmit_fails_while_a_frame_is_pending() {
+    let f = mac();
+    assert_eq!(
+        f.transmit(&data_frame(1, ADDRESS, PEER)),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(f.transmit(&data_frame(2, ADDRESS, PEER)), ReturnCode::EBUSY);
+}
+
+#[test]
+fn received_frame_is_acknowledged_and_repeats_are_dropped() {
+    let f = mac();
+    let frame = data_frame(7, PEER, ADDRESS);
+    assert!(f.radio.receive(&frame));
+    assert_eq!(*f.client.received.borrow(), vec![frame.clone()]);
+    assert_eq!(f.transmitted(), vec![ack_frame(7)]);
+    f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+
+    // The peer did not get the ACK and sends the frame again.
+    assert!(f.radio.receive(&frame));
+    assert_eq!(f.client.received.borrow().len(), 1);
+    assert_eq!(f.transmitted(), vec![ack_frame(7)]);
+    f.radio.complete_transmit(false, ReturnCode::SUCCESS);
+    assert!(f.radio.has_receive_buffer());
+
+    // Completing the ACKs does not complete a transmission.
+    assert!(f.sent().is_empty());
+}
+
+#[test]
+fn frame_for_another_device_is_dropped() {
+    let f = mac();
+    assert!(f.radio.receive(&data_frame(7, PEER, 0x0003)));
+    assert!(f.client.received.borrow().is_empty());
+    assert!(f.transmitted().is_empty());
+    assert!(f.radio.has_receive_buffer());
+}
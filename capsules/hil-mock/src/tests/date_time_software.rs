@@ -0,0 +1,281 @@
+use core::cell::RefCell;
+
+use capsules::date_time_software::DateTimeSoftware;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::hil::time::{Alarm, Ticks, Ticks32, Time};
+use kernel::ErrorCode;
+
+use crate::alarm::MockAlarm;
+use crate::leak;
+use crate::tests::{deferred_caller, run_deferred_calls};
+
+/// Records the callbacks of a date and time client.
+#[derive(Default)]
+struct Client {
+    get_done: RefCell<Vec<Result<DateTimeValues, ErrorCode>>>,
+    set_done: RefCell<Vec<Result<(), ErrorCode>>>,
+    alarms: RefCell<usize>,
+}
+
+impl DateTimeClient for Client {
+    fn get_date_time_done(&self, date_time: Result<DateTimeValues, ErrorCode>) {
+        self.get_done.borrow_mut().push(date_time);
+    }
+
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>) {
+        self.set_done.borrow_mut().push(result);
+    }
+
+    fn alarm_fired(&self) {
+        *self.alarms.borrow_mut() += 1;
+    }
+}
+
+type Clock = DateTimeSoftware<'static, MockAlarm<'static>>;
+
+/// A software clock on a 1kHz mock alarm, started at tick `now`.
+fn clock(
+    now: u32,
+) -> (
+    &'static Clock,
+    &'static MockAlarm<'static>,
+    &'static Client,
+    &'static DynamicDeferredCall,
+) {
+    let deferred_caller = deferred_caller(1);
+    let alarm = leak(MockAlarm::new());
+    alarm.set_now(Ticks32::from(now));
+    let clock = leak(DateTimeSoftware::new(alarm, deferred_caller));
+    alarm.set_alarm_client(clock);
+    clock.initialize_callback_handle(deferred_caller.register(clock).unwrap());
+    let client = leak(Client::default());
+    clock.set_client(client);
+    clock.start();
+    (clock, alarm, client, deferred_caller)
+}
+
+fn date_time(
+    year: u16,
+    month: Month,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    seconds: u8,
+) -> DateTimeValues {
+    DateTimeValues {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        seconds,
+    }
+}
+
+fn read(clock: &Clock, client: &Client, deferred_caller: &DynamicDeferredCall) -> DateTimeValues {
+    assert_eq!(clock.get_date_time(), Ok(()));
+    run_deferred_calls(deferred_caller);
+    client.get_done.borrow_mut().pop().unwrap().unwrap()
+}
+
+fn set(clock: &Clock, client: &Client, deferred_caller: &DynamicDeferredCall, to: DateTimeValues) {
+    assert_eq!(clock.set_date_time(to), Ok(()));
+    run_deferred_calls(deferred_caller);
+    assert_eq!(client.set_done.borrow_mut().pop(), Some(Ok(())));
+}
+
+#[test]
+fn converts_to_and_from_seconds() {
+    let cases = [
+        (
+            0,
+            date_time(1970, Month::January, 1, 0, 0, 0),
+            DayOfWeek::Thursday,
+        ),
+        (
+            951_782_400,
+            date_time(2000, Month::February, 29, 0, 0, 0),
+            DayOfWeek::Tuesday,
+        ),
+        (
+            1_616_155_199,
+            date_time(2021, Month::March, 19, 11, 59, 59),
+            DayOfWeek::Friday,
+        ),
+        (
+            4_107_542_399,
+            date_time(2100, Month::February, 28, 23, 59, 59),
+            DayOfWeek::Sunday,
+        ),
+    ];
+    for (seconds, date_time, day_of_week) in cases.iter() {
+        assert_eq!(date_time.seconds_since_epoch(), *seconds);
+        assert_eq!(
+            DateTimeValues::from_seconds_since_epoch(*seconds),
+            Ok(*date_time)
+        );
+        assert_eq!(date_time.day_of_week(), *day_of_week);
+    }
+
+    // Every day for 500 years round-trips and follows the previous one
+    let mut previous = DateTimeValues::from_seconds_since_epoch(0).unwrap();
+    for day in 1..500 * 366 {
+        let date_time = DateTimeValues::from_seconds_since_epoch(day * 86400).unwrap();
+        assert!(date_time.is_valid());
+        assert_eq!(date_time.seconds_since_epoch(), day * 86400);
+        assert!(
+            date_time.day == previous.day + 1
+                || (date_time.day == 1 && (date_time.month != previous.month))
+        );
+        previous = date_time;
+    }
+}
+
+#[test]
+fn rejects_invalid_dates() {
+    assert!(!date_time(2021, Month::February, 29, 0, 0, 0).is_valid());
+    assert!(date_time(2024, Month::February, 29, 0, 0, 0).is_valid());
+    assert!(!date_time(2100, Month::February, 29, 0, 0, 0).is_valid());
+    assert!(!date_time(2021, Month::April, 31, 0, 0, 0).is_valid());
+    assert!(!date_time(2021, Month::April, 0, 0, 0, 0).is_valid());
+    assert!(!date_time(2021, Month::April, 1, 24, 0, 0).is_valid());
+    assert!(!date_time(1969, Month::December, 31, 23, 59, 59).is_valid());
+
+    let (clock, ..) = clock(0);
+    assert_eq!(
+        clock.set_date_time(date_time(2021, Month::June, 31, 0, 0, 0)),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        clock.set_alarm(date_time(2021, Month::June, 1, 0, 60, 0)),
+        Err(ErrorCode::INVAL)
+    );
+}
+
+#[test]
+fn counts_from_start_and_from_set() {
+    let (clock, alarm, client, deferred_caller) = clock(12345);
+
+    alarm.advance(61_999);
+    assert_eq!(
+        read(clock, client, deferred_caller),
+        date_time(1970, Month::January, 1, 0, 1, 1)
+    );
+
+    set(
+        clock,
+        client,
+        deferred_caller,
+        date_time(2021, Month::December, 31, 23, 59, 58),
+    );
+    alarm.advance(1_999);
+    assert_eq!(
+        read(clock, client, deferred_caller),
+        date_time(2021, Month::December, 31, 23, 59, 59)
+    );
+    alarm.advance(1);
+    assert_eq!(
+        read(clock, client, deferred_caller),
+        date_time(2022, Month::January, 1, 0, 0, 0)
+    );
+}
+
+#[test]
+fn keeps_time_across_counter_wraparounds() {
+    let (clock, alarm, client, deferred_caller) = clock(u32::MAX - 500);
+
+    // Without any operation, the clock only sees the counter when it
+    // re-anchors itself
+    let mut elapsed: u64 = 0;
+    for _ in 0..5 {
+        let before = alarm.now().into_u32();
+        assert!(alarm.advance_to_alarm());
+        elapsed += alarm.now().into_u32().wrapping_sub(before) as u64;
+    }
+    assert!(elapsed > 2 * (u32::MAX as u64));
+
+    alarm.advance(250);
+    elapsed += 250;
+    assert_eq!(
+        read(clock, client, deferred_caller).seconds_since_epoch(),
+        elapsed / 1000
+    );
+}
+
+#[test]
+fn fires_alarm_at_its_time() {
+    let (clock, alarm, client, deferred_caller) = clock(0);
+    set(
+        clock,
+        client,
+        deferred_caller,
+        date_time(2021, Month::March, 19, 11, 59, 50),
+    );
+
+    assert_eq!(
+        clock.set_alarm(date_time(2021, Month::March, 19, 12, 0, 0)),
+        Ok(())
+    );
+    alarm.advance(9_999);
+    assert_eq!(*client.alarms.borrow(), 0);
+    alarm.advance(1);
+    assert_eq!(*client.alarms.borrow(), 1);
+
+    // The alarm only fires once
+    alarm.advance(100_000);
+    assert!(alarm.advance_to_alarm());
+    assert_eq!(*client.alarms.borrow(), 1);
+}
+
+#[test]
+fn disabled_and_skipped_alarms_do_not_fire() {
+    let (clock, alarm, client, deferred_caller) = clock(0);
+    set(
+        clock,
+        client,
+        deferred_caller,
+        date_time(2021, Month::March, 19, 12, 0, 0),
+    );
+
+    // In the past
+    assert_eq!(
+        clock.set_alarm(date_time(2021, Month::March, 19, 11, 0, 0)),
+        Ok(())
+    );
+    alarm.advance(10_000);
+    assert_eq!(*client.alarms.borrow(), 0);
+
+    // Disabled
+    assert_eq!(
+        clock.set_alarm(date_time(2021, Month::March, 19, 12, 1, 0)),
+        Ok(())
+    );
+    assert_eq!(clock.disable_alarm(), Ok(()));
+    alarm.advance(60_000);
+    assert_eq!(*client.alarms.borrow(), 0);
+
+    // Skipped by setting the clock past it
+    assert_eq!(
+        clock.set_alarm(date_time(2021, Month::March, 19, 13, 0, 0)),
+        Ok(())
+    );
+    set(
+        clock,
+        client,
+        deferred_caller,
+        date_time(2021, Month::March, 19, 14, 0, 0),
+    );
+    alarm.advance(3_600_000);
+    assert_eq!(*client.alarms.borrow(), 0);
+}
+
+#[test]
+fn get_is_busy_until_done() {
+    let (clock, _, client, deferred_caller) = clock(0);
+    assert_eq!(clock.get_date_time(), Ok(()));
+    assert_eq!(clock.get_date_time(), Err(ErrorCode::BUSY));
+    deferred_caller.call();
+    assert_eq!(client.get_done.borrow().len(), 1);
+    assert_eq!(clock.get_date_time(), Ok(()));
+}
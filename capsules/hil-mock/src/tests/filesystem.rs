@@ -0,0 +1,357 @@
+use core::cell::RefCell;
+
+use capsules::filesystem::{
+    FileId, FileSystem, FileSystemClient, ENTRY_LEN, HEADER_LEN, MAX_FILE_BLOCKS,
+};
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use kernel::hil::flash::HasClient;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::ErrorCode;
+
+use super::buffer;
+use crate::flash::{FlashCall, MockFlash, MockPage, PAGE_SIZE};
+use crate::leak;
+
+/// The number of pages of the flash. The two copies of the table take 12
+/// pages, leaving 20 blocks.
+const PAGES: usize = 32;
+const FILES: usize = 4;
+const TABLE_LEN: usize = HEADER_LEN + FILES * ENTRY_LEN;
+
+#[derive(Debug, PartialEq)]
+enum Completed {
+    Mount(Result<(), ErrorCode>),
+    Update(Result<(), ErrorCode>),
+    Read(Vec<u8>, Result<(), ErrorCode>),
+    Write(usize, Result<(), ErrorCode>),
+}
+
+/// Records the operations completed for a filesystem client.
+#[derive(Default)]
+struct Client {
+    completed: RefCell<Vec<Completed>>,
+}
+
+impl Client {
+    fn take(&self) -> Vec<Completed> {
+        self.completed.replace(Vec::new())
+    }
+}
+
+impl FileSystemClient for Client {
+    fn mount_done(&self, result: Result<(), ErrorCode>) {
+        self.completed.borrow_mut().push(Completed::Mount(result));
+    }
+
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        self.completed.borrow_mut().push(Completed::Update(result));
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>) {
+        self.completed
+            .borrow_mut()
+            .push(Completed::Read(buffer[..length].to_vec(), result));
+    }
+
+    fn write_done(&self, _buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>) {
+        self.completed
+            .borrow_mut()
+            .push(Completed::Write(length, result));
+    }
+}
+
+struct Fs {
+    flash: &'static MockFlash<'static>,
+    fs: &'static FileSystem<'static>,
+    client: &'static Client,
+}
+
+impl Fs {
+    /// A filesystem on `flash`, not mounted yet.
+    fn new(flash: &'static MockFlash<'static>) -> Fs {
+        let storage = leak(NonvolatileToPages::new(flash, leak(MockPage::default())));
+        flash.set_client(storage);
+        let fs = leak(FileSystem::new(
+            storage,
+            0,
+            PAGES * PAGE_SIZE,
+            PAGE_SIZE,
+            buffer(&[0; TABLE_LEN]),
+            buffer(&[0; PAGE_SIZE]),
+        ));
+        storage.set_client(fs);
+        let client = leak(Client::default());
+        fs.set_client(client);
+        Fs { flash, fs, client }
+    }
+
+    /// A mounted filesystem on `flash`.
+    fn mount(flash: &'static MockFlash<'static>) -> Fs {
+        let fs = Fs::new(flash);
+        assert_eq!(fs.fs.mount(), Ok(()));
+        fs.run();
+        assert_eq!(fs.client.take(), [Completed::Mount(Ok(()))]);
+        fs
+    }
+
+    /// Completes flash operations until there are none left.
+    fn run(&self) {
+        while self.flash.complete() {}
+    }
+
+    fn create(&self, dir: &[u8], name: &[u8]) -> FileId {
+        assert_eq!(self.fs.create(dir, name), Ok(()));
+        self.run();
+        assert_eq!(self.client.take(), [Completed::Update(Ok(()))]);
+        self.fs.find(dir, name).unwrap().unwrap()
+    }
+
+    fn write(&self, file: FileId, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.fs
+            .write(file, offset, buffer(data), data.len())
+            .map_err(|(e, _)| e)?;
+        self.run();
+        assert_eq!(self.client.take(), [Completed::Write(data.len(), Ok(()))]);
+        Ok(())
+    }
+
+    fn read(&self, file: FileId, offset: usize, length: usize) -> Vec<u8> {
+        assert!(self
+            .fs
+            .read(file, offset, buffer(&[0; 512]), length)
+            .is_ok());
+        self.run();
+        match self.client.take().as_slice() {
+            [Completed::Read(data, Ok(()))] => data.clone(),
+            completed => panic!("unexpected completions {:?}", completed),
+        }
+    }
+}
+
+/// A copy of the contents of `flash`, as it would be after losing power.
+fn image(flash: &MockFlash) -> &'static MockFlash<'static> {
+    let copy = leak(MockFlash::new(PAGES));
+    for page in 0..PAGES {
+        copy.set_page(page, &flash.page(page));
+    }
+    copy
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed))
+        .collect()
+}
+
+#[test]
+fn mount_formats_blank_storage() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+
+    assert_eq!(fs.fs.num_files(), FILES);
+    assert_eq!(fs.fs.num_blocks(), 20);
+    assert_eq!(&fs.flash.page(0)[..4], b"TFS1");
+    assert_eq!(fs.fs.find(b"app", b"file"), Ok(None));
+    assert_eq!(fs.fs.list(b"app", 0, &mut [0; 32]), Err(ErrorCode::INVAL));
+}
+
+#[test]
+fn operations_need_a_mounted_filesystem() {
+    let fs = Fs::new(leak(MockFlash::new(PAGES)));
+
+    assert_eq!(fs.fs.create(b"app", b"file"), Err(ErrorCode::OFF));
+    assert_eq!(fs.fs.find(b"app", b"file"), Err(ErrorCode::OFF));
+
+    assert_eq!(fs.fs.mount(), Ok(()));
+    assert_eq!(fs.fs.mount(), Err(ErrorCode::BUSY));
+}
+
+#[test]
+fn written_data_is_read_back() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    let file = fs.create(b"app", b"data");
+    let data = pattern(150, 1);
+
+    assert_eq!(fs.write(file, 0, &data), Ok(()));
+    assert_eq!(fs.fs.size(file), Ok(150));
+    assert_eq!(fs.read(file, 0, 512), data);
+    assert_eq!(fs.read(file, 60, 10), &data[60..70]);
+}
+
+#[test]
+fn partial_writes_keep_the_rest_of_the_file() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    let file = fs.create(b"app", b"data");
+    let mut data = pattern(200, 1);
+
+    assert_eq!(fs.write(file, 0, &data), Ok(()));
+    // Overwrite across a block boundary, then append past the end
+    assert_eq!(fs.write(file, 50, &[0xAA; 30]), Ok(()));
+    assert_eq!(fs.write(file, 200, &[0xBB; 20]), Ok(()));
+    data[50..80].copy_from_slice(&[0xAA; 30]);
+    data.extend_from_slice(&[0xBB; 20]);
+
+    assert_eq!(fs.fs.size(file), Ok(220));
+    assert_eq!(fs.read(file, 0, 512), data);
+}
+
+#[test]
+fn writes_are_checked() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    let file = fs.create(b"app", b"data");
+
+    // Writes may not leave a gap after the end of the file
+    assert_eq!(fs.write(file, 1, &[1]), Err(ErrorCode::INVAL));
+    assert_eq!(
+        fs.write(file, 0, &vec![0; MAX_FILE_BLOCKS * PAGE_SIZE + 1]),
+        Err(ErrorCode::SIZE)
+    );
+    assert_eq!(fs.write(file + 1, 0, &[1]), Err(ErrorCode::INVAL));
+    assert_eq!(
+        fs.fs.read(file, 0, buffer(&[0; 8]), 8).map_err(|(e, _)| e),
+        Err(ErrorCode::SIZE)
+    );
+}
+
+#[test]
+fn files_are_kept_after_remounting() {
+    let flash = leak(MockFlash::new(PAGES));
+    let fs = Fs::mount(flash);
+    let first = fs.create(b"app", b"first");
+    let second = fs.create(b"app", b"second");
+    assert_eq!(fs.write(first, 0, &pattern(100, 1)), Ok(()));
+    assert_eq!(fs.write(second, 0, &pattern(30, 2)), Ok(()));
+    assert_eq!(fs.write(first, 100, &pattern(10, 3)), Ok(()));
+
+    let fs = Fs::mount(flash);
+    let first = fs.fs.find(b"app", b"first").unwrap().unwrap();
+    let second = fs.fs.find(b"app", b"second").unwrap().unwrap();
+    assert_eq!(fs.read(first, 0, 100), pattern(100, 1));
+    assert_eq!(fs.read(first, 100, 100), pattern(10, 3));
+    assert_eq!(fs.read(second, 0, 100), pattern(30, 2));
+}
+
+#[test]
+fn power_loss_keeps_the_old_or_the_new_contents() {
+    let old = pattern(100, 1);
+    let mut new = old.clone();
+    new.resize(120, 0);
+    new[40..120].copy_from_slice(&[0xCC; 80]);
+
+    // Lose power after each flash operation of an overwrite
+    for steps in 0.. {
+        let flash = leak(MockFlash::new(PAGES));
+        let fs = Fs::mount(flash);
+        let file = fs.create(b"app", b"data");
+        assert_eq!(fs.write(file, 0, &old), Ok(()));
+
+        assert!(fs.fs.write(file, 40, buffer(&[0xCC; 80]), 80).is_ok());
+        for _ in 0..steps {
+            assert!(flash.complete());
+        }
+        let finished = !flash.is_busy();
+        if finished {
+            assert_eq!(fs.client.take(), [Completed::Write(80, Ok(()))]);
+        }
+
+        let remounted = Fs::mount(image(flash));
+        let file = remounted.fs.find(b"app", b"data").unwrap().unwrap();
+        let contents = remounted.read(file, 0, 512);
+        assert!(contents == old || contents == new, "after {} steps", steps);
+        if finished {
+            assert_eq!(contents, new, "after {} steps", steps);
+            assert!(steps > 1);
+            break;
+        }
+    }
+}
+
+#[test]
+fn torn_table_falls_back_to_the_other_copy() {
+    let flash = leak(MockFlash::new(PAGES));
+    let fs = Fs::mount(flash);
+    fs.create(b"app", b"old");
+
+    // Complete the first page of the table, then lose power
+    assert_eq!(fs.fs.create(b"app", b"new"), Ok(()));
+    assert_eq!(flash.take_calls().last(), Some(&FlashCall::Write(0)));
+    assert!(flash.complete());
+    assert_eq!(flash.take_calls(), [FlashCall::Write(1)]);
+
+    let remounted = Fs::mount(image(flash));
+    assert!(remounted.fs.find(b"app", b"old").unwrap().is_some());
+    assert_eq!(remounted.fs.find(b"app", b"new"), Ok(None));
+}
+
+#[test]
+fn files_are_listed_by_directory() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    fs.create(b"one", b"a");
+    fs.create(b"two", b"b");
+    fs.create(b"one", b"c");
+
+    let mut name = [0; 32];
+    assert_eq!(fs.fs.list(b"one", 0, &mut name), Ok(1));
+    assert_eq!(&name[..1], b"a");
+    assert_eq!(fs.fs.list(b"one", 1, &mut name), Ok(1));
+    assert_eq!(&name[..1], b"c");
+    assert_eq!(fs.fs.list(b"one", 2, &mut name), Err(ErrorCode::INVAL));
+    assert_eq!(fs.fs.list(b"two", 0, &mut name), Ok(1));
+    assert_eq!(&name[..1], b"b");
+    assert_eq!(fs.fs.find(b"two", b"a"), Ok(None));
+}
+
+#[test]
+fn creating_files_is_checked() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    fs.create(b"app", b"a");
+
+    assert_eq!(fs.fs.create(b"app", b"a"), Err(ErrorCode::ALREADY));
+    assert_eq!(fs.fs.create(b"app", b""), Err(ErrorCode::INVAL));
+    assert_eq!(fs.fs.create(b"app", &[b'x'; 33]), Err(ErrorCode::INVAL));
+
+    fs.create(b"app", b"b");
+    fs.create(b"app", b"c");
+    fs.create(b"app", b"d");
+    assert_eq!(fs.fs.create(b"app", b"e"), Err(ErrorCode::NOMEM));
+}
+
+#[test]
+fn deleting_files_frees_their_blocks() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    let first = fs.create(b"app", b"first");
+    let second = fs.create(b"app", b"second");
+    let third = fs.create(b"app", b"third");
+
+    assert_eq!(fs.write(first, 0, &pattern(8 * PAGE_SIZE, 1)), Ok(()));
+    assert_eq!(fs.write(second, 0, &pattern(8 * PAGE_SIZE, 2)), Ok(()));
+    // Only 4 blocks are left
+    assert_eq!(
+        fs.write(third, 0, &pattern(5 * PAGE_SIZE, 3)),
+        Err(ErrorCode::NOMEM)
+    );
+
+    assert_eq!(fs.fs.delete(first), Ok(()));
+    fs.run();
+    assert_eq!(fs.client.take(), [Completed::Update(Ok(()))]);
+    assert_eq!(fs.fs.find(b"app", b"first"), Ok(None));
+    assert_eq!(fs.write(third, 0, &pattern(5 * PAGE_SIZE, 3)), Ok(()));
+    assert_eq!(
+        fs.read(third, 0, 512)[..5 * PAGE_SIZE],
+        pattern(5 * PAGE_SIZE, 3)[..]
+    );
+    assert_eq!(fs.read(second, 0, 512), pattern(8 * PAGE_SIZE, 2));
+}
+
+#[test]
+fn truncating_empties_files() {
+    let fs = Fs::mount(leak(MockFlash::new(PAGES)));
+    let file = fs.create(b"app", b"data");
+    assert_eq!(fs.write(file, 0, &pattern(100, 1)), Ok(()));
+
+    assert_eq!(fs.fs.truncate(file), Ok(()));
+    fs.run();
+    assert_eq!(fs.client.take(), [Completed::Update(Ok(()))]);
+    assert_eq!(fs.fs.size(file), Ok(0));
+    assert_eq!(fs.write(file, 0, &[1, 2, 3]), Ok(()));
+    assert_eq!(fs.read(file, 0, 512), [1, 2, 3]);
+}
@@ -0,0 +1,679 @@
+use core::cell::{Cell, RefCell};
+
+use capsules::net::icmpv6::icmpv6_recv::{ICMP6RecvClient, ICMP6RecvStruct};
+use capsules::net::icmpv6::icmpv6_responder::ICMP6Responder;
+use capsules::net::icmpv6::neighbor_cache::{Neighbor, NeighborCache, NEIGHBOR_CACHE_SIZE};
+use capsules::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use capsules::net::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::cells::OptionalCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::ReturnCode;
+
+use crate::leak;
+use crate::tests::buffer;
+
+const NODE_EXT_ADDR: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+const PEER_EXT_ADDR: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+const ALL_NODES: IPAddr = IPAddr([
+    0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+]);
+const ALL_ROUTERS: IPAddr = IPAddr([
+    0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+]);
+const PREFIX: IPAddr = IPAddr([
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+]);
+const BROADCAST: MacAddress = MacAddress::Short(0xffff);
+
+const ECHO_REQUEST: u8 = 128;
+const ECHO_REPLY: u8 = 129;
+const ROUTER_SOLICITATION: u8 = 133;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+fn address(ext_addr: [u8; 8]) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Long(ext_addr))
+}
+
+fn solicited_node_address(addr: IPAddr) -> IPAddr {
+    let mut snma = IPAddr([
+        0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00,
+        0x00,
+    ]);
+    snma.0[13..].copy_from_slice(&addr.0[13..]);
+    snma
+}
+
+/// A link-layer address option of type `option_type` with an extended
+/// address.
+fn link_layer_option(option_type: u8, ext_addr: [u8; 8]) -> Vec<u8> {
+    let mut option = vec![option_type, 2];
+    option.extend_from_slice(&ext_addr);
+    option.extend_from_slice(&[0; 6]);
+    option
+}
+
+/// The ICMPv6 checksum of `message`, computed from RFC 4443 rather than with
+/// the helpers of the capsules.
+fn checksum(src: IPAddr, dst: IPAddr, message: &[u8]) -> u16 {
+    let mut pseudo_header = Vec::new();
+    pseudo_header.extend_from_slice(&src.0);
+    pseudo_header.extend_from_slice(&dst.0);
+    pseudo_header.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    pseudo_header.extend_from_slice(&[0, 0, 0, ip6_nh::ICMP]);
+    pseudo_header.extend_from_slice(message);
+    if pseudo_header.len() % 2 == 1 {
+        pseudo_header.push(0);
+    }
+    let mut sum: u32 = pseudo_header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// An IPv6 packet carrying the ICMPv6 `message`, with its checksum filled
+/// in.
+fn packet(src: IPAddr, dst: IPAddr, hop_limit: u8, message: &[u8]) -> Vec<u8> {
+    let mut message = message.to_vec();
+    let cksum = checksum(src, dst, &message);
+    message[2..4].copy_from_slice(&cksum.to_be_bytes());
+
+    let mut packet = vec![0x60, 0, 0, 0];
+    packet.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[ip6_nh::ICMP, hop_limit]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    packet.extend_from_slice(&message);
+    packet
+}
+
+fn echo_request(id: u16, seqno: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = vec![ECHO_REQUEST, 0, 0, 0];
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&seqno.to_be_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+fn neighbor_solicitation(target: IPAddr, options: &[u8]) -> Vec<u8> {
+    let mut message = vec![NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    message.extend_from_slice(&target.0);
+    message.extend_from_slice(options);
+    message
+}
+
+fn neighbor_advertisement(flags: u32, target: IPAddr, options: &[u8]) -> Vec<u8> {
+    let mut message = vec![NEIGHBOR_ADVERTISEMENT, 0, 0, 0];
+    message.extend_from_slice(&flags.to_be_bytes());
+    message.extend_from_slice(&target.0);
+    message.extend_from_slice(options);
+    message
+}
+
+fn router_advertisement(router_lifetime: u16, options: &[u8]) -> Vec<u8> {
+    let mut message = vec![ROUTER_ADVERTISEMENT, 0, 0, 0, 64, 0];
+    message.extend_from_slice(&router_lifetime.to_be_bytes());
+    message.extend_from_slice(&[0; 8]);
+    message.extend_from_slice(options);
+    message
+}
+
+/// A Prefix Information option for `PREFIX`/64 with the autonomous flag,
+/// valid for `valid_lifetime` seconds.
+fn prefix_option(valid_lifetime: u32) -> Vec<u8> {
+    let mut option = vec![3, 4, 64, 0xc0];
+    option.extend_from_slice(&valid_lifetime.to_be_bytes());
+    option.extend_from_slice(&valid_lifetime.to_be_bytes());
+    option.extend_from_slice(&[0; 4]);
+    option.extend_from_slice(&PREFIX.0);
+    option
+}
+
+struct CreateCapability;
+unsafe impl NetworkCapabilityCreationCapability for CreateCapability {}
+
+#[derive(Debug, PartialEq)]
+struct Sent {
+    dst: IPAddr,
+    gateway: MacAddress,
+    /// The ICMPv6 message, with a zero checksum since the IPv6 layer fills
+    /// it in.
+    message: Vec<u8>,
+}
+
+/// An IPv6 sender that records the ICMPv6 messages sent. A message is
+/// pending until `complete()`.
+struct IpSender {
+    client: OptionalCell<&'static dyn IP6SendClient>,
+    gateway: Cell<MacAddress>,
+    pending: Cell<bool>,
+    sent: RefCell<Vec<Sent>>,
+}
+
+impl IpSender {
+    fn complete(&self) {
+        assert!(self.pending.replace(false));
+        self.client
+            .map(|client| client.send_done(ReturnCode::SUCCESS));
+    }
+
+    fn take_sent(&self) -> Vec<Sent> {
+        self.sent.replace(Vec::new())
+    }
+}
+
+impl IP6Sender<'static> for IpSender {
+    fn set_client(&self, client: &'static dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.gateway.set(gateway);
+    }
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        assert!(!self.pending.replace(true));
+        let header = match transport_header {
+            TransportHeader::ICMP(header) => header,
+            _ => panic!("not an ICMPv6 message"),
+        };
+        let mut message = vec![0; 8];
+        assert!(header.encode(&mut message, 0).done().is_some());
+        message.extend_from_slice(&payload[..]);
+        assert_eq!(header.get_len() as usize, message.len());
+        self.sent.borrow_mut().push(Sent {
+            dst,
+            gateway: self.gateway.get(),
+            message,
+        });
+        ReturnCode::SUCCESS
+    }
+}
+
+/// Records the type and body of the ICMPv6 messages received.
+#[derive(Default)]
+struct RecvClient {
+    received: RefCell<Vec<(ICMP6Type, Vec<u8>)>>,
+}
+
+impl ICMP6RecvClient for RecvClient {
+    fn receive(&self, _ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        self.received
+            .borrow_mut()
+            .push((icmp_header.get_type(), payload.to_vec()));
+    }
+}
+
+struct Fixture {
+    ip_recv: &'static IP6RecvStruct<'static>,
+    icmp_recv: &'static ICMP6RecvStruct<'static>,
+    responder: &'static ICMP6Responder<'static>,
+    sender: &'static IpSender,
+}
+
+impl Fixture {
+    fn deliver(&self, packet: &[u8]) {
+        self.ip_recv
+            .receive(packet, packet.len(), ReturnCode::SUCCESS);
+    }
+}
+
+fn responder() -> Fixture {
+    let sender = leak(IpSender {
+        client: OptionalCell::empty(),
+        gateway: Cell::new(BROADCAST),
+        pending: Cell::new(false),
+        sent: RefCell::new(Vec::new()),
+    });
+    let net_cap = leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &CreateCapability,
+    ));
+    let local_addrs = Box::leak(vec![address(NODE_EXT_ADDR)].into_boxed_slice());
+    let responder = leak(ICMP6Responder::new(
+        sender,
+        local_addrs,
+        MacAddress::Long(NODE_EXT_ADDR),
+        buffer(&[0; 64]),
+        net_cap,
+    ));
+    sender.set_client(responder);
+
+    let ip_recv = leak(IP6RecvStruct::new());
+    let icmp_recv = leak(ICMP6RecvStruct::new());
+    ip_recv.set_protocol_client(ip6_nh::ICMP, icmp_recv);
+    assert_eq!(icmp_recv.add_client(responder), ReturnCode::SUCCESS);
+    Fixture {
+        ip_recv,
+        icmp_recv,
+        responder,
+        sender,
+    }
+}
+
+#[test]
+fn echo_request_is_answered() {
+    let fixture = responder();
+    let request = echo_request(0x1234, 7, b"ping data");
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        64,
+        &request,
+    ));
+
+    let mut reply = request.clone();
+    reply[0] = ECHO_REPLY;
+    assert_eq!(
+        fixture.sender.take_sent(),
+        vec![Sent {
+            dst: address(PEER_EXT_ADDR),
+            gateway: MacAddress::Long(PEER_EXT_ADDR),
+            message: reply,
+        }]
+    );
+}
+
+#[test]
+fn echo_request_to_multicast_address_is_answered() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        ALL_NODES,
+        64,
+        &echo_request(1, 1, &[]),
+    ));
+
+    let sent = fixture.sender.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dst, address(PEER_EXT_ADDR));
+    assert_eq!(sent[0].message[0], ECHO_REPLY);
+}
+
+#[test]
+fn checksum_is_verified() {
+    let mut packet = packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        64,
+        &echo_request(1, 1, b"data"),
+    );
+    let (offset, header) = IP6Header::decode(&packet).done().unwrap();
+    assert_eq!(
+        header.check_transport_checksum(&packet[offset..]),
+        ReturnCode::SUCCESS
+    );
+
+    *packet.last_mut().unwrap() ^= 0x01;
+    assert_eq!(
+        header.check_transport_checksum(&packet[offset..]),
+        ReturnCode::FAIL
+    );
+}
+
+#[test]
+fn checksum_is_computed_for_sent_messages() {
+    let mut header = ICMP6Header::new(ICMP6Type::Type129);
+    header.set_options(ICMP6HeaderOptions::Type129 { id: 1, seqno: 2 });
+    header.set_len(12);
+    let mut packet = IP6Packet::new(IPPayload::new(
+        TransportHeader::ICMP(header),
+        buffer(&[0; 16]),
+    ));
+    packet.header.src_addr = address(NODE_EXT_ADDR);
+    packet.header.dst_addr = address(PEER_EXT_ADDR);
+    packet.set_payload(
+        TransportHeader::ICMP(header),
+        &LeasableBuffer::new(buffer(b"data")),
+    );
+    packet.set_transport_checksum();
+
+    let mut encoded = [0; 64];
+    let (len, _) = packet.encode(&mut encoded).done().unwrap();
+    let message = &encoded[40..len];
+    assert_eq!(message[..2], [ECHO_REPLY, 0]);
+    assert_eq!(message[4..], [0, 1, 0, 2, b'd', b'a', b't', b'a']);
+    assert_eq!(
+        checksum(address(NODE_EXT_ADDR), address(PEER_EXT_ADDR), message),
+        0
+    );
+}
+
+#[test]
+fn echo_request_to_other_node_is_ignored() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        address([0x0a, 0, 0, 0, 0, 0, 0, 1]),
+        64,
+        &echo_request(1, 1, b"data"),
+    ));
+
+    assert_eq!(fixture.sender.take_sent(), vec![]);
+}
+
+#[test]
+fn echo_request_while_sending_is_dropped() {
+    let fixture = responder();
+    let request = packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        64,
+        &echo_request(1, 1, b"data"),
+    );
+    fixture.deliver(&request);
+    fixture.deliver(&request);
+    assert_eq!(fixture.sender.take_sent().len(), 1);
+
+    fixture.sender.complete();
+    fixture.deliver(&request);
+    assert_eq!(fixture.sender.take_sent().len(), 1);
+}
+
+#[test]
+fn neighbor_solicitation_is_answered() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        solicited_node_address(address(NODE_EXT_ADDR)),
+        255,
+        &neighbor_solicitation(address(NODE_EXT_ADDR), &link_layer_option(1, PEER_EXT_ADDR)),
+    ));
+
+    assert_eq!(
+        fixture.sender.take_sent(),
+        vec![Sent {
+            dst: address(PEER_EXT_ADDR),
+            gateway: MacAddress::Long(PEER_EXT_ADDR),
+            message: neighbor_advertisement(
+                0x6000_0000,
+                address(NODE_EXT_ADDR),
+                &link_layer_option(2, NODE_EXT_ADDR),
+            ),
+        }]
+    );
+    assert_eq!(
+        fixture
+            .responder
+            .neighbors()
+            .lookup(&address(PEER_EXT_ADDR)),
+        Some(Neighbor {
+            ip_addr: address(PEER_EXT_ADDR),
+            mac_addr: MacAddress::Long(PEER_EXT_ADDR),
+            is_router: false,
+        })
+    );
+}
+
+#[test]
+fn duplicate_address_detection_is_answered_to_all_nodes() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        IPAddr::new(),
+        solicited_node_address(address(NODE_EXT_ADDR)),
+        255,
+        &neighbor_solicitation(address(NODE_EXT_ADDR), &[]),
+    ));
+
+    assert_eq!(
+        fixture.sender.take_sent(),
+        vec![Sent {
+            dst: ALL_NODES,
+            gateway: BROADCAST,
+            message: neighbor_advertisement(
+                0x2000_0000,
+                address(NODE_EXT_ADDR),
+                &link_layer_option(2, NODE_EXT_ADDR),
+            ),
+        }]
+    );
+}
+
+#[test]
+fn neighbor_solicitation_from_off_link_is_ignored() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        solicited_node_address(address(NODE_EXT_ADDR)),
+        64,
+        &neighbor_solicitation(address(NODE_EXT_ADDR), &link_layer_option(1, PEER_EXT_ADDR)),
+    ));
+
+    assert_eq!(fixture.sender.take_sent(), vec![]);
+    assert_eq!(fixture.responder.neighbors().iter().count(), 0);
+}
+
+#[test]
+fn neighbor_solicitation_with_malformed_option_is_ignored() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        solicited_node_address(address(NODE_EXT_ADDR)),
+        255,
+        &neighbor_solicitation(address(NODE_EXT_ADDR), &[1, 0, 0, 0, 0, 0, 0, 0]),
+    ));
+
+    assert_eq!(fixture.sender.take_sent(), vec![]);
+}
+
+#[test]
+fn neighbor_advertisement_updates_cache() {
+    let fixture = responder();
+    let short_option = [2, 1, 0x12, 0x34, 0, 0, 0, 0];
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        255,
+        &neighbor_advertisement(0xe000_0000, address(PEER_EXT_ADDR), &short_option),
+    ));
+
+    assert_eq!(
+        fixture
+            .responder
+            .neighbors()
+            .lookup(&address(PEER_EXT_ADDR)),
+        Some(Neighbor {
+            ip_addr: address(PEER_EXT_ADDR),
+            mac_addr: MacAddress::Short(0x1234),
+            is_router: true,
+        })
+    );
+
+    // Replies to the neighbor go to its cached address
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        64,
+        &echo_request(1, 1, &[]),
+    ));
+    assert_eq!(
+        fixture.sender.take_sent()[0].gateway,
+        MacAddress::Short(0x1234)
+    );
+}
+
+#[test]
+fn solicited_neighbor_advertisement_to_multicast_address_is_ignored() {
+    let fixture = responder();
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        ALL_NODES,
+        255,
+        &neighbor_advertisement(
+            0x6000_0000,
+            address(PEER_EXT_ADDR),
+            &link_layer_option(2, PEER_EXT_ADDR),
+        ),
+    ));
+
+    assert_eq!(fixture.responder.neighbors().iter().count(), 0);
+}
+
+#[test]
+fn router_advertisement_sets_router_and_prefix() {
+    let fixture = responder();
+    let mut options = link_layer_option(1, PEER_EXT_ADDR);
+    options.extend_from_slice(&prefix_option(86400));
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        ALL_NODES,
+        255,
+        &router_advertisement(1800, &options),
+    ));
+
+    assert_eq!(
+        fixture.responder.default_router(),
+        Some(address(PEER_EXT_ADDR))
+    );
+    assert_eq!(fixture.responder.prefix(), Some((PREFIX, 64)));
+    assert_eq!(
+        fixture
+            .responder
+            .neighbors()
+            .lookup(&address(PEER_EXT_ADDR)),
+        Some(Neighbor {
+            ip_addr: address(PEER_EXT_ADDR),
+            mac_addr: MacAddress::Long(PEER_EXT_ADDR),
+            is_router: true,
+        })
+    );
+
+    // A router lifetime of zero, and a valid lifetime of zero, withdraw them
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        ALL_NODES,
+        255,
+        &router_advertisement(0, &prefix_option(0)),
+    ));
+    assert_eq!(fixture.responder.default_router(), None);
+    assert_eq!(fixture.responder.prefix(), None);
+}
+
+#[test]
+fn router_advertisement_from_global_address_is_ignored() {
+    let fixture = responder();
+    let mut src = PREFIX;
+    src.0[15] = 1;
+    fixture.deliver(&packet(
+        src,
+        ALL_NODES,
+        255,
+        &router_advertisement(1800, &prefix_option(86400)),
+    ));
+
+    assert_eq!(fixture.responder.default_router(), None);
+    assert_eq!(fixture.responder.prefix(), None);
+}
+
+#[test]
+fn router_solicitation_is_sent_to_all_routers() {
+    let fixture = responder();
+    assert_eq!(
+        fixture.responder.send_router_solicitation(),
+        ReturnCode::SUCCESS
+    );
+
+    let mut message = vec![ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    message.extend_from_slice(&link_layer_option(1, NODE_EXT_ADDR));
+    assert_eq!(
+        fixture.sender.take_sent(),
+        vec![Sent {
+            dst: ALL_ROUTERS,
+            gateway: BROADCAST,
+            message,
+        }]
+    );
+    assert_eq!(
+        fixture.responder.send_router_solicitation(),
+        ReturnCode::EBUSY
+    );
+}
+
+#[test]
+fn neighbor_solicitation_is_sent_to_solicited_node_address() {
+    let fixture = responder();
+    assert_eq!(
+        fixture
+            .responder
+            .send_neighbor_solicitation(address(PEER_EXT_ADDR)),
+        ReturnCode::SUCCESS
+    );
+
+    assert_eq!(
+        fixture.sender.take_sent(),
+        vec![Sent {
+            dst: solicited_node_address(address(PEER_EXT_ADDR)),
+            gateway: BROADCAST,
+            message: neighbor_solicitation(
+                address(PEER_EXT_ADDR),
+                &link_layer_option(1, NODE_EXT_ADDR),
+            ),
+        }]
+    );
+}
+
+#[test]
+fn all_clients_receive_messages() {
+    let fixture = responder();
+    let client = leak(RecvClient::default());
+    assert_eq!(fixture.icmp_recv.add_client(client), ReturnCode::SUCCESS);
+    fixture.deliver(&packet(
+        address(PEER_EXT_ADDR),
+        address(NODE_EXT_ADDR),
+        64,
+        &echo_request(1, 2, b"data"),
+    ));
+
+    assert_eq!(
+        client.received.replace(Vec::new()),
+        vec![(ICMP6Type::Type128, b"data".to_vec())]
+    );
+    assert_eq!(fixture.sender.take_sent().len(), 1);
+}
+
+#[test]
+fn neighbor_cache_replaces_least_recently_used() {
+    let cache = NeighborCache::new();
+    let neighbor = |i: u8| Neighbor {
+        ip_addr: address([0x0a, 0, 0, 0, 0, 0, 0, i]),
+        mac_addr: MacAddress::Short(i as u16),
+        is_router: false,
+    };
+    for i in 0..NEIGHBOR_CACHE_SIZE as u8 {
+        cache.insert(neighbor(i));
+    }
+    assert!(cache.lookup(&neighbor(0).ip_addr).is_some());
+
+    cache.insert(neighbor(100));
+    assert!(cache.lookup(&neighbor(0).ip_addr).is_some());
+    assert!(cache.lookup(&neighbor(1).ip_addr).is_none());
+    assert!(cache.lookup(&neighbor(100).ip_addr).is_some());
+    assert_eq!(cache.iter().count(), NEIGHBOR_CACHE_SIZE);
+
+    cache.remove(&neighbor(100).ip_addr);
+    assert!(cache.lookup(&neighbor(100).ip_addr).is_none());
+}
@@ -0,0 +1,50 @@
+use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+
+use crate::leak;
+
+mod aes;
+mod ble_connection;
+mod csma_mac;
+mod date_time_software;
+mod filesystem;
+mod icmpv6;
+mod p256;
+mod sha256;
+mod sixlowpan_mesh;
+mod thread_mle;
+mod virtual_alarm;
+mod virtual_flash;
+mod virtual_i2c;
+mod virtual_rng;
+mod virtual_spi;
+mod virtual_uart;
+
+/// A deferred call instance with room for `clients` clients. It is not
+/// registered globally, so tests running in parallel do not share it.
+fn deferred_caller(clients: usize) -> &'static DynamicDeferredCall {
+    let client_states: Vec<DynamicDeferredCallClientState> =
+        (0..clients).map(|_| Default::default()).collect();
+    leak(DynamicDeferredCall::new(Box::leak(
+        client_states.into_boxed_slice(),
+    )))
+}
+
+/// A buffer for a capsule.
+fn buffer(contents: &[u8]) -> &'static mut [u8] {
+    Box::leak(contents.to_vec().into_boxed_slice())
+}
+
+/// Run the deferred calls of `deferred_caller` until none are pending.
+fn run_deferred_calls(deferred_caller: &DynamicDeferredCall) {
+    while deferred_caller.has_pending() {
+        deferred_caller.call();
+    }
+}
+
+/// Decode a string of hexadecimal digits.
+fn hex(string: &str) -> Vec<u8> {
+    (0..string.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&string[i..i + 2], 16).unwrap())
+        .collect()
+}
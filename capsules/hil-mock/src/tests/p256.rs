@@ -0,0 +1,343 @@
+use core::cell::RefCell;
+use core::convert::TryFrom;
+
+use capsules::p256::{
+    verify_signature, P256CredentialsChecker, P256Software, HASH_LEN, PUBLIC_KEY_LEN, SECRET_LEN,
+    SIGNATURE_LEN,
+};
+use capsules::sha256::Sha256;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::public_key_crypto::{
+    ClientKeyAgreement, ClientSign, ClientVerify, KeyAgreement, PrivateKey, PublicKey,
+    SignatureSign, SignatureVerify,
+};
+use kernel::procs::{AppCredentialsChecker, CheckResult, TbfHeaderV2Credentials};
+use kernel::ErrorCode;
+
+use crate::leak;
+use crate::tests::{deferred_caller, hex, run_deferred_calls};
+
+/// Records the callbacks of a public key crypto client.
+#[derive(Default)]
+struct Client {
+    verified: RefCell<Vec<Result<bool, ErrorCode>>>,
+    signed: RefCell<Vec<(Result<(), ErrorCode>, [u8; SIGNATURE_LEN])>>,
+    agreed: RefCell<Vec<(Result<(), ErrorCode>, [u8; SECRET_LEN])>>,
+}
+
+impl ClientVerify<HASH_LEN, SIGNATURE_LEN> for Client {
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        _hash: &'static mut [u8; HASH_LEN],
+        _signature: &'static mut [u8; SIGNATURE_LEN],
+    ) {
+        self.verified.borrow_mut().push(result);
+    }
+}
+
+impl ClientSign<HASH_LEN, SIGNATURE_LEN> for Client {
+    fn signing_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        _hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) {
+        self.signed.borrow_mut().push((result, *signature));
+    }
+}
+
+impl ClientKeyAgreement<PUBLIC_KEY_LEN, SECRET_LEN> for Client {
+    fn agreement_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        _peer_key: &'static mut [u8; PUBLIC_KEY_LEN],
+        secret: &'static mut [u8; SECRET_LEN],
+    ) {
+        self.agreed.borrow_mut().push((result, *secret));
+    }
+}
+
+// The P-256 key and SHA-256 signatures of RFC 6979, appendix A.2.5.
+const PRIVATE_KEY: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+const PUBLIC_X: &str = "60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6";
+const PUBLIC_Y: &str = "7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+const SIGNATURES: [(&[u8], &str, &str); 2] = [
+    (
+        b"sample",
+        "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716",
+        "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+    ),
+    (
+        b"test",
+        "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367",
+        "019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083",
+    ),
+];
+
+fn p256() -> (
+    &'static P256Software<'static>,
+    &'static Client,
+    &'static DynamicDeferredCall,
+) {
+    let deferred_caller = deferred_caller(1);
+    let p256 = leak(P256Software::new(deferred_caller));
+    p256.initialize_callback_handle(deferred_caller.register(p256).unwrap());
+    let client = leak(Client::default());
+    p256.set_verify_client(client);
+    p256.set_sign_client(client);
+    p256.set_key_agreement_client(client);
+    (p256, client, deferred_caller)
+}
+
+fn public_key() -> Vec<u8> {
+    let mut key = vec![0x04];
+    key.extend(hex(PUBLIC_X));
+    key.extend(hex(PUBLIC_Y));
+    key
+}
+
+fn hash(message: &[u8]) -> &'static mut [u8; HASH_LEN] {
+    let mut sha256 = Sha256::new();
+    sha256.update(message);
+    leak(sha256.finish())
+}
+
+fn signature(r: &str, s: &str) -> &'static mut [u8; SIGNATURE_LEN] {
+    let mut signature = [0; SIGNATURE_LEN];
+    signature[..32].copy_from_slice(&hex(r));
+    signature[32..].copy_from_slice(&hex(s));
+    leak(signature)
+}
+
+fn verify(
+    p256: &P256Software,
+    client: &Client,
+    deferred_caller: &DynamicDeferredCall,
+    hash: &'static mut [u8; HASH_LEN],
+    signature: &'static mut [u8; SIGNATURE_LEN],
+) -> Result<bool, ErrorCode> {
+    assert!(p256.verify(hash, signature).is_ok());
+    run_deferred_calls(deferred_caller);
+    client.verified.borrow_mut().pop().unwrap()
+}
+
+#[test]
+fn private_key_sets_public_key() {
+    let (p256, _, _) = p256();
+    assert_eq!(p256.set_private_key(&hex(PRIVATE_KEY)), Ok(()));
+    let mut key = [0; PUBLIC_KEY_LEN];
+    assert_eq!(p256.public_key(&mut key), Ok(()));
+    assert_eq!(key.to_vec(), public_key());
+}
+
+#[test]
+fn signatures_match_rfc6979() {
+    let (p256, client, deferred_caller) = p256();
+    p256.set_private_key(&hex(PRIVATE_KEY)).unwrap();
+    for (message, r, s) in SIGNATURES.iter() {
+        assert!(p256.sign(hash(message), leak([0; SIGNATURE_LEN])).is_ok());
+        run_deferred_calls(deferred_caller);
+        let (result, signed) = client.signed.borrow_mut().pop().unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(signed, *signature(r, s));
+    }
+}
+
+#[test]
+fn signatures_are_verified() {
+    let (p256, client, deferred_caller) = p256();
+    assert_eq!(p256.set_public_key(&public_key()), Ok(()));
+    for (message, r, s) in SIGNATURES.iter() {
+        assert_eq!(
+            verify(
+                p256,
+                client,
+                deferred_caller,
+                hash(message),
+                signature(r, s)
+            ),
+            Ok(true)
+        );
+
+        let (_, r_other, s_other) = SIGNATURES
+            .iter()
+            .find(|(other, _, _)| other != message)
+            .unwrap();
+        assert_eq!(
+            verify(
+                p256,
+                client,
+                deferred_caller,
+                hash(message),
+                signature(r_other, s_other)
+            ),
+            Ok(false)
+        );
+        let tampered = signature(r, s);
+        tampered[63] ^= 1;
+        assert_eq!(
+            verify(p256, client, deferred_caller, hash(message), tampered),
+            Ok(false)
+        );
+        // r and s must be between 1 and n - 1.
+        assert_eq!(
+            verify(
+                p256,
+                client,
+                deferred_caller,
+                hash(message),
+                leak([0; SIGNATURE_LEN])
+            ),
+            Ok(false)
+        );
+    }
+}
+
+#[test]
+fn signatures_are_verified_synchronously() {
+    for (message, r, s) in SIGNATURES.iter() {
+        assert_eq!(
+            verify_signature(&public_key(), hash(message), signature(r, s)),
+            Ok(true)
+        );
+        let tampered = signature(r, s);
+        tampered[0] ^= 1;
+        assert_eq!(
+            verify_signature(&public_key(), hash(message), tampered),
+            Ok(false)
+        );
+    }
+
+    let mut off_curve = public_key();
+    off_curve[64] ^= 1;
+    let (message, r, s) = SIGNATURES[0];
+    assert_eq!(
+        verify_signature(&off_curve, hash(message), signature(r, s)),
+        Err(ErrorCode::INVAL)
+    );
+}
+
+/// ECDSA P-256 credentials with the signature `r || s`.
+fn ecdsa_credentials(r: &str, s: &str) -> TbfHeaderV2Credentials {
+    let mut bytes = 3u32.to_le_bytes().to_vec();
+    bytes.extend_from_slice(signature(r, s));
+    TbfHeaderV2Credentials::try_from(&leak(bytes)[..]).unwrap()
+}
+
+#[test]
+fn credentials_signed_by_a_trusted_key_are_accepted() {
+    let mut key = [0; PUBLIC_KEY_LEN];
+    key.copy_from_slice(&public_key());
+    let checker = P256CredentialsChecker::new(leak([key]), true);
+    assert!(checker.require_credentials());
+
+    // The message is split across integrity regions.
+    let (_, r, s) = SIGNATURES[0];
+    let credentials = ecdsa_credentials(r, s);
+    assert_eq!(
+        checker.check_credentials(&credentials, &[b"sam", b"ple"]),
+        CheckResult::Accept
+    );
+    assert_eq!(
+        checker.check_credentials(&credentials, &[b"test"]),
+        CheckResult::Pass
+    );
+
+    let untrusted = P256CredentialsChecker::new(&[], false);
+    assert_eq!(
+        untrusted.check_credentials(&credentials, &[b"sample"]),
+        CheckResult::Pass
+    );
+    assert!(!untrusted.require_credentials());
+
+    let mut sha256 = 1u32.to_le_bytes().to_vec();
+    sha256.extend_from_slice(hash(b"sample"));
+    let sha256 = TbfHeaderV2Credentials::try_from(&leak(sha256)[..]).unwrap();
+    assert_eq!(
+        checker.check_credentials(&sha256, &[b"sample"]),
+        CheckResult::Pass
+    );
+}
+
+#[test]
+fn key_agreement_is_symmetric() {
+    let (alice, alice_client, alice_deferred_caller) = p256();
+    let (bob, bob_client, bob_deferred_caller) = p256();
+    alice.set_private_key(&hex(PRIVATE_KEY)).unwrap();
+    let mut bob_private_key = [0; 32];
+    bob_private_key[31] = 7;
+    bob.set_private_key(&bob_private_key).unwrap();
+
+    let mut alice_key = [0; PUBLIC_KEY_LEN];
+    alice.public_key(&mut alice_key).unwrap();
+    let mut bob_key = [0; PUBLIC_KEY_LEN];
+    bob.public_key(&mut bob_key).unwrap();
+
+    assert!(alice.agree(leak(bob_key), leak([0; SECRET_LEN])).is_ok());
+    run_deferred_calls(alice_deferred_caller);
+    assert!(bob.agree(leak(alice_key), leak([0; SECRET_LEN])).is_ok());
+    run_deferred_calls(bob_deferred_caller);
+
+    let (alice_result, alice_secret) = alice_client.agreed.borrow_mut().pop().unwrap();
+    let (bob_result, bob_secret) = bob_client.agreed.borrow_mut().pop().unwrap();
+    assert_eq!(alice_result, Ok(()));
+    assert_eq!(bob_result, Ok(()));
+    assert_eq!(alice_secret, bob_secret);
+    assert_ne!(alice_secret, [0; SECRET_LEN]);
+}
+
+#[test]
+fn invalid_keys_are_rejected() {
+    let (p256, client, deferred_caller) = p256();
+    let mut key = public_key();
+    key[64] ^= 1;
+    assert_eq!(p256.set_public_key(&key), Err(ErrorCode::INVAL));
+    assert_eq!(
+        p256.set_public_key(&public_key()[1..]),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(p256.set_private_key(&[0; 32]), Err(ErrorCode::INVAL));
+    assert_eq!(p256.set_private_key(&[0xff; 32]), Err(ErrorCode::INVAL));
+
+    // Without keys, operations cannot start.
+    let (error, _, _) = p256
+        .verify(hash(b"sample"), leak([0; SIGNATURE_LEN]))
+        .unwrap_err();
+    assert_eq!(error, ErrorCode::RESERVE);
+    let (error, _, _) = p256
+        .sign(hash(b"sample"), leak([0; SIGNATURE_LEN]))
+        .unwrap_err();
+    assert_eq!(error, ErrorCode::RESERVE);
+
+    // A peer key off the curve fails the agreement.
+    p256.set_private_key(&hex(PRIVATE_KEY)).unwrap();
+    let mut peer_key = [0; PUBLIC_KEY_LEN];
+    peer_key.copy_from_slice(&key);
+    assert!(p256.agree(leak(peer_key), leak([0; SECRET_LEN])).is_ok());
+    run_deferred_calls(deferred_caller);
+    assert_eq!(client.agreed.borrow()[0].0, Err(ErrorCode::INVAL));
+}
+
+#[test]
+fn operations_are_busy_until_done() {
+    let (p256, client, deferred_caller) = p256();
+    p256.set_private_key(&hex(PRIVATE_KEY)).unwrap();
+    assert!(p256.sign(hash(b"sample"), leak([0; SIGNATURE_LEN])).is_ok());
+
+    let (error, _, _) = p256
+        .verify(hash(b"sample"), leak([0; SIGNATURE_LEN]))
+        .unwrap_err();
+    assert_eq!(error, ErrorCode::BUSY);
+    assert_eq!(p256.set_public_key(&public_key()), Err(ErrorCode::BUSY));
+    assert_eq!(
+        p256.set_private_key(&hex(PRIVATE_KEY)),
+        Err(ErrorCode::BUSY)
+    );
+
+    run_deferred_calls(deferred_caller);
+    assert_eq!(client.signed.borrow().len(), 1);
+    assert!(p256
+        .verify(hash(b"sample"), leak([0; SIGNATURE_LEN]))
+        .is_ok());
+}
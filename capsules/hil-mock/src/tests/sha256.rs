@@ -0,0 +1,258 @@
+use core::cell::RefCell;
+
+use capsules::sha256::{HmacSha256, Sha256Software};
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest::{self, Digest, HMACSha256};
+use kernel::ErrorCode;
+
+use crate::leak;
+use crate::tests::{buffer, deferred_caller, hex, run_deferred_calls};
+
+/// Records the callbacks of a digest client.
+#[derive(Default)]
+struct Client {
+    added: RefCell<Vec<(Result<(), ErrorCode>, Vec<u8>)>>,
+    hashed: RefCell<Vec<(Result<(), ErrorCode>, [u8; 32])>>,
+}
+
+impl<'a> digest::Client<'a, [u8; 32]> for Client {
+    fn add_data_done(&'a self, result: Result<(), ErrorCode>, data: &'static mut [u8]) {
+        self.added.borrow_mut().push((result, data.to_vec()));
+    }
+
+    fn hash_done(&'a self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        self.hashed.borrow_mut().push((result, *digest));
+    }
+}
+
+fn sha256() -> (
+    &'static Sha256Software<'static>,
+    &'static Client,
+    &'static DynamicDeferredCall,
+) {
+    let deferred_caller = deferred_caller(1);
+    let sha256 = leak(Sha256Software::new(deferred_caller));
+    sha256.initialize_callback_handle(deferred_caller.register(sha256).unwrap());
+    let client = leak(Client::default());
+    sha256.set_client(client);
+    (sha256, client, deferred_caller)
+}
+
+/// Hash `message`, added in chunks of at most `chunk_len` bytes.
+fn digest(
+    sha256: &'static Sha256Software<'static>,
+    client: &Client,
+    deferred_caller: &DynamicDeferredCall,
+    message: &[u8],
+    chunk_len: usize,
+) -> [u8; 32] {
+    for chunk in message.chunks(chunk_len) {
+        assert_eq!(
+            sha256.add_data(LeasableBuffer::new(buffer(chunk))),
+            Ok(chunk.len())
+        );
+        run_deferred_calls(deferred_caller);
+        assert_eq!(
+            client.added.borrow_mut().pop(),
+            Some((Ok(()), chunk.to_vec()))
+        );
+    }
+    assert!(sha256.run(leak([0; 32])).is_ok());
+    run_deferred_calls(deferred_caller);
+    let (result, digest) = client.hashed.borrow_mut().pop().unwrap();
+    assert_eq!(result, Ok(()));
+    digest
+}
+
+/// Pad an HMAC key of at most 32 bytes with zeros, which gives the same
+/// HMAC as the key itself.
+fn key(key: &[u8]) -> [u8; 32] {
+    let mut padded = [0; 32];
+    padded[..key.len()].copy_from_slice(key);
+    padded
+}
+
+// Messages and digests from the FIPS 180-2 examples and NIST CAVP.
+#[test]
+fn sha256_test_vectors() {
+    let (sha256, client, deferred_caller) = sha256();
+    let vectors: [(&[u8], &str); 3] = [
+        (
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+    ];
+    for (message, expected) in vectors.iter() {
+        assert_eq!(
+            digest(sha256, client, deferred_caller, message, 64),
+            hex(expected)[..]
+        );
+    }
+}
+
+#[test]
+fn long_message_is_hashed_over_several_calls() {
+    let (sha256, client, deferred_caller) = sha256();
+    let message = vec![b'a'; 1_000_000];
+    assert_eq!(
+        digest(sha256, client, deferred_caller, &message, 100_000),
+        hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")[..]
+    );
+
+    // Adding data only compresses a few blocks per deferred call.
+    assert_eq!(
+        sha256.add_data(LeasableBuffer::new(buffer(&[0; 1024]))),
+        Ok(1024)
+    );
+    deferred_caller.call();
+    assert!(client.added.borrow().is_empty());
+    assert!(deferred_caller.has_pending());
+}
+
+#[test]
+fn chunking_does_not_change_the_digest() {
+    let (sha256, client, deferred_caller) = sha256();
+    let message: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let expected = digest(sha256, client, deferred_caller, &message, 300);
+    for chunk_len in [1, 7, 55, 56, 63, 64, 65, 128].iter() {
+        assert_eq!(
+            digest(sha256, client, deferred_caller, &message, *chunk_len),
+            expected
+        );
+    }
+}
+
+#[test]
+fn leased_data_is_hashed_and_returned_whole() {
+    let (sha256, client, deferred_caller) = sha256();
+    let mut data = LeasableBuffer::new(buffer(b"xxabcxx"));
+    data.slice(2..5);
+    assert_eq!(sha256.add_data(data), Ok(3));
+    run_deferred_calls(deferred_caller);
+    assert_eq!(*client.added.borrow(), [(Ok(()), b"xxabcxx".to_vec())]);
+
+    sha256.run(leak([0; 32])).unwrap();
+    run_deferred_calls(deferred_caller);
+    assert_eq!(
+        client.hashed.borrow()[0].1,
+        hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")[..]
+    );
+}
+
+// Keys, messages and MACs from RFC 4231 and the NIST HMAC examples.
+#[test]
+fn hmac_sha256_test_vectors() {
+    let (sha256, client, deferred_caller) = sha256();
+    let nist_key: Vec<u8> = (0..32).collect();
+    let vectors: [(&[u8], &[u8], &str); 4] = [
+        (
+            &[0x0b; 20],
+            b"Hi There",
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+        ),
+        (
+            b"Jefe",
+            b"what do ya want for nothing?",
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        ),
+        (
+            &[0xaa; 20],
+            &[0xdd; 50],
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+        ),
+        (
+            &nist_key,
+            b"Sample message for keylen<blocklen",
+            "a28cf43130ee696a98f14a37678b56bcfcbdd9e5cf69717fecf5480f0ebdf790",
+        ),
+    ];
+    for (hmac_key, message, expected) in vectors.iter() {
+        assert_eq!(sha256.set_mode_hmacsha256(&key(hmac_key)), Ok(()));
+        assert_eq!(
+            digest(sha256, client, deferred_caller, message, 16),
+            hex(expected)[..]
+        );
+    }
+}
+
+// RFC 4231 test cases 2, 6 and 7: the last two have keys longer than a
+// block, which are hashed first.
+#[test]
+fn synchronous_hmac_sha256_test_vectors() {
+    let vectors: [(&[u8], &[&[u8]], &str); 3] = [
+        (
+            b"Jefe",
+            &[b"what do ya ", b"want for nothing?"],
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        ),
+        (
+            &[0xaa; 131],
+            &[b"Test Using Larger Than Block-Size Key - Hash Key First"],
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+        ),
+        (
+            &[0xaa; 131],
+            &[
+                b"This is a test using a larger than block-size key and a larger t",
+                b"han block-size data. The key needs to be hashed before being use",
+                b"d by the HMAC algorithm.",
+            ],
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+        ),
+    ];
+    for (key, parts, expected) in vectors.iter() {
+        assert_eq!(HmacSha256::mac(key, parts), hex(expected)[..]);
+    }
+}
+
+#[test]
+fn hmac_key_is_kept_until_cleared() {
+    let (sha256, client, deferred_caller) = sha256();
+    sha256.set_mode_hmacsha256(&key(b"Jefe")).unwrap();
+    let message = b"what do ya want for nothing?";
+    let mac = hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    assert_eq!(
+        digest(sha256, client, deferred_caller, message, 64),
+        mac[..]
+    );
+    assert_eq!(
+        digest(sha256, client, deferred_caller, message, 64),
+        mac[..]
+    );
+
+    sha256.clear_data();
+    assert_eq!(
+        digest(sha256, client, deferred_caller, b"abc", 64),
+        hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")[..]
+    );
+}
+
+#[test]
+fn operations_are_busy_until_done() {
+    let (sha256, client, deferred_caller) = sha256();
+    assert_eq!(sha256.add_data(LeasableBuffer::new(buffer(b"a"))), Ok(1));
+
+    let (error, data) = sha256
+        .add_data(LeasableBuffer::new(buffer(b"b")))
+        .unwrap_err();
+    assert_eq!(error, ErrorCode::BUSY);
+    assert_eq!(data, b"b");
+    assert_eq!(
+        sha256.run(leak([0; 32])).map_err(|(error, _)| error),
+        Err(ErrorCode::BUSY)
+    );
+    assert_eq!(sha256.set_mode_hmacsha256(&[0; 32]), Err(ErrorCode::BUSY));
+
+    run_deferred_calls(deferred_caller);
+    assert_eq!(*client.added.borrow(), [(Ok(()), b"a".to_vec())]);
+    assert!(sha256.run(leak([0; 32])).is_ok());
+}
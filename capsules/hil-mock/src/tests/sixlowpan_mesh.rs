@@ -0,0 +1,665 @@
+use core::cell::RefCell;
+
+use capsules::aes_software::Aes128Software;
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::net::ieee802154::{Header, MacAddress};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvClient, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_routing::{Route, RoutingTable};
+use capsules::net::ipv6::ipv6_send::{IP6SendClient, IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange,
+};
+use capsules::net::sixlowpan::sixlowpan_compression::{self, Context, MeshHeader};
+use capsules::net::sixlowpan::sixlowpan_mesh::{
+    MeshForwarder, MeshRoute, MeshRoutingTable, MESH_HOPS_LEFT, MESH_ROUTES,
+};
+use capsules::net::sixlowpan::sixlowpan_state::{RxState, Sixlowpan, SixlowpanState, TxState};
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::symmetric_encryption::{AES128CCM, AES128_BLOCK_SIZE};
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+
+use crate::alarm::MockAlarm;
+use crate::leak;
+use crate::radio::{MockRadio, RadioCall};
+use crate::tests::{buffer, deferred_caller};
+
+type Ccm = VirtualAES128CCM<'static, Aes128Software<'static>>;
+type TestMac = AwakeMac<'static, MockRadio>;
+type TestFramer = Framer<'static, TestMac, Ccm>;
+type TestSixlowpan = Sixlowpan<'static, MockAlarm<'static>, Context>;
+type TestIpSend = IP6SendStruct<'static, MockAlarm<'static>>;
+
+const PAN: u16 = 0xabcd;
+/// The short addresses of the nodes: the node under test is `NODE`, frames
+/// arrive from `PREVIOUS`, and `NEXT` is on the way to `FAR`.
+const NODE: u16 = 0x0002;
+const PREVIOUS: u16 = 0x0001;
+const NEXT: u16 = 0x0004;
+const FAR: u16 = 0x0003;
+const DATA: &[u8] = b"sensor reading";
+
+const PREFIX: [u8; 16] = [
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// The address with `PREFIX` and the interface identifier of `short_addr`.
+fn address(short_addr: u16) -> IPAddr {
+    let mut addr = IPAddr::generate_from_mac(MacAddress::Short(short_addr));
+    addr.set_prefix(&PREFIX, 64);
+    addr
+}
+
+fn context() -> Context {
+    Context {
+        prefix: PREFIX,
+        prefix_len: 64,
+        id: 0,
+        compress: false,
+    }
+}
+
+struct CreateCapability;
+unsafe impl NetworkCapabilityCreationCapability for CreateCapability {}
+
+/// Records the packets and send completions that reach the IPv6 layer.
+#[derive(Default)]
+struct IpClient {
+    received: RefCell<Vec<(IP6Header, Vec<u8>)>>,
+    sent: RefCell<Vec<ReturnCode>>,
+}
+
+impl IP6RecvClient for IpClient {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        self.received.borrow_mut().push((header, payload.to_vec()));
+    }
+}
+
+impl IP6SendClient for IpClient {
+    fn send_done(&self, result: ReturnCode) {
+        self.sent.borrow_mut().push(result);
+    }
+}
+
+/// A node with the whole stack from the radio up to the IPv6 layer, which
+/// forwards packets for other nodes both with mesh-under and route-over
+/// routing.
+struct Node {
+    radio: &'static MockRadio,
+    alarm: &'static MockAlarm<'static>,
+    ip_send: &'static TestIpSend,
+    routes: &'static RoutingTable,
+    mesh_routes: &'static MeshRoutingTable,
+    client: &'static IpClient,
+    net_cap: &'static NetworkCapability,
+}
+
+fn node() -> Node {
+    let deferred_caller = deferred_caller(0);
+    let aes = leak(Aes128Software::new(deferred_caller));
+    let aes_mux = leak(MuxAES128CCM::new(aes, deferred_caller));
+    let ccm: &'static Ccm = leak(VirtualAES128CCM::new(
+        aes_mux,
+        buffer(&[0; 7 * AES128_BLOCK_SIZE]),
+    ));
+    ccm.setup();
+
+    let radio = leak(MockRadio::new());
+    radio.set_address(NODE);
+    radio.set_pan(PAN);
+    let mac: &'static TestMac = leak(AwakeMac::new(radio));
+    radio.set_transmit_client(mac);
+    radio.set_receive_client(mac, buffer(&[0; radio::MAX_BUF_SIZE]));
+    let framer: &'static TestFramer = leak(Framer::new(mac, ccm));
+    ccm.set_client(framer);
+    mac.set_transmit_client(framer);
+    mac.set_receive_client(framer);
+    mac.set_config_client(framer);
+
+    let mux_mac = leak(MuxMac::new(framer));
+    framer.set_transmit_client(mux_mac);
+    framer.set_receive_client(mux_mac);
+    let ip_mac = leak(MacUser::new(mux_mac));
+    mux_mac.add_user(ip_mac);
+    let forward_mac = leak(MacUser::new(mux_mac));
+    mux_mac.add_user(forward_mac);
+
+    let alarm = leak(MockAlarm::new());
+    let sixlowpan: &'static TestSixlowpan = leak(Sixlowpan::new(context(), alarm));
+    sixlowpan.add_rx_state(leak(RxState::new(buffer(&[0; 1280]))));
+
+    let mesh_routes = leak(MeshRoutingTable::new());
+    let forwarder = leak(MeshForwarder::new(
+        forward_mac,
+        mesh_routes,
+        MacAddress::Short(NODE),
+        buffer(&[0; radio::MAX_BUF_SIZE]),
+    ));
+    forward_mac.set_transmit_client(forwarder);
+    ip_mac.set_receive_client(forwarder);
+    forwarder.set_receive_client(sixlowpan);
+
+    let ip_packet = leak(IP6Packet::new(IPPayload::new(
+        TransportHeader::UDP(UDPHeader::new()),
+        buffer(&[0; 200]),
+    )));
+    let ip_send: &'static TestIpSend = leak(IP6SendStruct::new(
+        ip_packet,
+        alarm,
+        buffer(&[0; radio::MAX_BUF_SIZE]),
+        TxState::new(sixlowpan),
+        ip_mac,
+        MacAddress::Short(0xffff),
+        MacAddress::Short(NODE),
+        leak(IpVisibilityCapability::new(&CreateCapability)),
+    ));
+    ip_send.set_addr(address(NODE));
+    ip_mac.set_transmit_client(ip_send);
+    alarm.set_alarm_client(ip_send);
+    let routes = leak(RoutingTable::new());
+    ip_send.set_routing_table(routes);
+    ip_send.set_mesh_routing_table(mesh_routes);
+
+    let ip_recv = leak(IP6RecvStruct::new());
+    ip_recv.set_local_addrs(Box::leak(vec![address(NODE)].into_boxed_slice()));
+    ip_recv.set_forwarder(ip_send);
+    sixlowpan.set_rx_client(ip_recv);
+    let client = leak(IpClient::default());
+    ip_recv.set_client(client);
+    ip_send.set_client(client);
+
+    Node {
+        radio,
+        alarm,
+        ip_send,
+        routes,
+        mesh_routes,
+        client,
+        net_cap: leak(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &CreateCapability,
+        )),
+    }
+}
+
+/// An unsecured data frame from `src` to `dst` with `payload`.
+fn frame(src: u16, dst: u16, payload: &[u8]) -> Vec<u8> {
+    let header = Header {
+        frame_type: capsules::net::ieee802154::FrameType::Data,
+        frame_pending: false,
+        ack_requested: true,
+        version: capsules::net::ieee802154::FrameVersion::V2006,
+        seq: Some(0),
+        dst_pan: Some(PAN),
+        dst_addr: Some(MacAddress::Short(dst)),
+        src_pan: Some(PAN),
+        src_addr: Some(MacAddress::Short(src)),
+        security: None,
+        header_ies: Default::default(),
+        header_ies_len: 0,
+        payload_ies: Default::default(),
+        payload_ies_len: 0,
+    };
+    let mut frame = vec![0; radio::MAX_MTU];
+    let (len, _) = header.encode(&mut frame, true).done().unwrap();
+    frame.truncate(len);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// A mesh header followed by `payload`.
+fn mesh_payload(hops_left: u8, originator: u16, final_dst: u16, payload: &[u8]) -> Vec<u8> {
+    let mesh_header = MeshHeader {
+        hops_left,
+        originator: MacAddress::Short(originator),
+        final_dst: MacAddress::Short(final_dst),
+    };
+    let mut buf = vec![0; mesh_header.get_hdr_size()];
+    assert_eq!(mesh_header.encode(&mut buf), buf.len());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// A UDP datagram with `DATA` from the node `src` to the node `dst`, as
+/// 6LoWPAN packet in a frame from `src_mac` to `dst_mac`.
+fn udp_packet(src: u16, dst: u16, hop_limit: u8, src_mac: u16, dst_mac: u16) -> Vec<u8> {
+    let mut udp_header = UDPHeader::new();
+    udp_header.set_src_port(0x1234);
+    udp_header.set_dst_port(0x5678);
+    udp_header.set_len((8 + DATA.len()) as u16);
+    let mut packet = IP6Packet::new(IPPayload::new(
+        TransportHeader::UDP(UDPHeader::new()),
+        buffer(&[0; 200]),
+    ));
+    packet.header.src_addr = address(src);
+    packet.header.dst_addr = address(dst);
+    packet.header.set_hop_limit(hop_limit);
+    packet.set_payload(
+        TransportHeader::UDP(udp_header),
+        &LeasableBuffer::new(buffer(DATA)),
+    );
+    packet.set_transport_checksum();
+
+    let mut uncompressed = vec![0; packet.get_total_len() as usize];
+    packet.encode(&mut uncompressed).done().unwrap();
+    let mut compressed = vec![0; radio::MAX_MTU];
+    let (consumed, written) = sixlowpan_compression::compress(
+        &context(),
+        &packet,
+        MacAddress::Short(src_mac),
+        MacAddress::Short(dst_mac),
+        &mut compressed,
+    )
+    .unwrap();
+    compressed.truncate(written);
+    compressed.extend_from_slice(&uncompressed[consumed..]);
+    compressed
+}
+
+/// The header and payload of a transmitted frame.
+fn parse(frame: &[u8]) -> (Header, &[u8]) {
+    let (data_offset, (header, _)) = Header::decode(frame, false).done().unwrap();
+    (header, &frame[data_offset..])
+}
+
+/// Decompresses the 6LoWPAN packet in `payload`, sent from `src` to `dst`.
+fn decompress(payload: &[u8], src: MacAddress, dst: MacAddress) -> (IP6Header, Vec<u8>) {
+    let mut packet = vec![0; 1280];
+    let (consumed, written) =
+        sixlowpan_compression::decompress(&context(), payload, src, dst, &mut packet, 0, false)
+            .unwrap();
+    let remaining = payload.len() - consumed;
+    packet[written..written + remaining].copy_from_slice(&payload[consumed..]);
+    packet.truncate(written + remaining);
+    let (offset, header) = IP6Header::decode(&packet).done().unwrap();
+    (header, packet[offset..].to_vec())
+}
+
+impl Node {
+    fn transmitted(&self) -> Vec<Vec<u8>> {
+        self.radio
+            .take_calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                RadioCall::Transmit { frame } => Some(frame),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Completes the pending transmission, and the fragment delay of the
+    /// IPv6 layer after it.
+    fn complete_transmit(&self) {
+        assert!(self.radio.complete_transmit(true, ReturnCode::SUCCESS));
+        self.alarm.advance_to_alarm();
+    }
+
+    fn send_udp(&self, dst: IPAddr) -> ReturnCode {
+        self.send_udp_data(dst, DATA)
+    }
+
+    fn send_udp_data(&self, dst: IPAddr, data: &[u8]) -> ReturnCode {
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_src_port(0x1234);
+        udp_header.set_dst_port(0x5678);
+        udp_header.set_len((8 + data.len()) as u16);
+        self.ip_send.send_to(
+            dst,
+            TransportHeader::UDP(udp_header),
+            &LeasableBuffer::new(buffer(data)),
+            self.net_cap,
+        )
+    }
+}
+
+#[test]
+fn mesh_header_round_trips() {
+    let short = MeshHeader {
+        hops_left: 5,
+        originator: MacAddress::Short(0x1234),
+        final_dst: MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]),
+    };
+    let mut buf = [0; 18];
+    assert_eq!(short.encode(&mut buf), 11);
+    assert_eq!(buf[..11], [0xa5, 0x12, 0x34, 1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(MeshHeader::decode(&buf[..11]), Ok((short, 11)));
+
+    let deep = MeshHeader {
+        hops_left: 20,
+        originator: MacAddress::Long([8, 7, 6, 5, 4, 3, 2, 1]),
+        final_dst: MacAddress::Short(0x5678),
+    };
+    assert_eq!(deep.get_hdr_size(), 12);
+    assert_eq!(deep.encode(&mut buf), 12);
+    assert_eq!(buf[..2], [0x9f, 20]);
+    assert_eq!(MeshHeader::decode(&buf[..12]), Ok((deep, 12)));
+}
+
+#[test]
+fn truncated_mesh_header_is_rejected() {
+    assert_eq!(MeshHeader::decode(&[0xb5, 0x00, 0x01, 0x00]), Err(()));
+    assert_eq!(MeshHeader::decode(&[0xbf]), Err(()));
+    // An IPHC dispatch is not a mesh header
+    assert_eq!(MeshHeader::decode(&[0x60, 0x00, 0x00, 0x00]), Err(()));
+}
+
+#[test]
+fn mesh_routing_table_replaces_and_removes_routes() {
+    let routes = MeshRoutingTable::new();
+    let route = |final_dst: u16, next_hop: u16| MeshRoute {
+        final_dst: MacAddress::Short(final_dst),
+        next_hop: MacAddress::Short(next_hop),
+    };
+    assert_eq!(routes.add_route(route(FAR, NEXT)), ReturnCode::SUCCESS);
+    assert_eq!(routes.add_route(route(FAR, PREVIOUS)), ReturnCode::SUCCESS);
+    assert_eq!(
+        routes.next_hop(&MacAddress::Short(FAR)),
+        Some(MacAddress::Short(PREVIOUS))
+    );
+
+    for i in 1..MESH_ROUTES as u16 {
+        assert_eq!(
+            routes.add_route(route(0x100 + i, NEXT)),
+            ReturnCode::SUCCESS
+        );
+    }
+    assert_eq!(routes.add_route(route(0x200, NEXT)), ReturnCode::ENOMEM);
+
+    assert_eq!(
+        routes.remove_route(&MacAddress::Short(FAR)),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(routes.next_hop(&MacAddress::Short(FAR)), None);
+    assert_eq!(
+        routes.remove_route(&MacAddress::Short(FAR)),
+        ReturnCode::EINVAL
+    );
+}
+
+#[test]
+fn routing_table_uses_the_longest_matching_prefix() {
+    let routes = RoutingTable::new();
+    assert_eq!(
+        routes.add_route(Route {
+            prefix: IPAddr::new(),
+            prefix_len: 0,
+            next_hop: MacAddress::Short(PREVIOUS),
+        }),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        routes.add_route(Route {
+            prefix: IPAddr(PREFIX),
+            prefix_len: 64,
+            next_hop: MacAddress::Short(NEXT),
+        }),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        routes.add_route(Route {
+            prefix: IPAddr(PREFIX),
+            prefix_len: 129,
+            next_hop: MacAddress::Short(NEXT),
+        }),
+        ReturnCode::EINVAL
+    );
+
+    assert_eq!(routes.lookup(&address(FAR)), Some(MacAddress::Short(NEXT)));
+    let mut other = address(FAR);
+    other.0[7] = 1;
+    assert_eq!(routes.lookup(&other), Some(MacAddress::Short(PREVIOUS)));
+
+    assert_eq!(routes.remove_route(&IPAddr::new(), 0), ReturnCode::SUCCESS);
+    assert_eq!(routes.lookup(&other), None);
+}
+
+#[test]
+fn packet_follows_its_route() {
+    let node = node();
+    node.routes.add_route(Route {
+        prefix: IPAddr(PREFIX),
+        prefix_len: 64,
+        next_hop: MacAddress::Short(NEXT),
+    });
+    assert_eq!(node.send_udp(address(FAR)), ReturnCode::SUCCESS);
+
+    let frames = node.transmitted();
+    assert_eq!(frames.len(), 1);
+    let (header, payload) = parse(&frames[0]);
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(NEXT)));
+    let (ip_header, _) = decompress(payload, MacAddress::Short(NODE), MacAddress::Short(NEXT));
+    assert_eq!(ip_header.get_dst_addr(), address(FAR));
+
+    node.complete_transmit();
+    assert_eq!(*node.client.sent.borrow(), vec![ReturnCode::SUCCESS]);
+}
+
+#[test]
+fn packet_without_route_goes_to_the_gateway() {
+    let node = node();
+    node.ip_send.set_gateway(MacAddress::Short(PREVIOUS));
+    assert_eq!(node.send_udp(address(FAR)), ReturnCode::SUCCESS);
+
+    let frames = node.transmitted();
+    let (header, _) = parse(&frames[0]);
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(PREVIOUS)));
+}
+
+#[test]
+fn packet_is_sent_over_several_hops_with_a_mesh_header() {
+    let node = node();
+    node.ip_send.set_gateway(MacAddress::Short(FAR));
+    node.mesh_routes.add_route(MeshRoute {
+        final_dst: MacAddress::Short(FAR),
+        next_hop: MacAddress::Short(NEXT),
+    });
+    assert_eq!(node.send_udp(address(FAR)), ReturnCode::SUCCESS);
+
+    let frames = node.transmitted();
+    let (header, payload) = parse(&frames[0]);
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(NEXT)));
+    let (mesh_header, consumed) = MeshHeader::decode(payload).unwrap();
+    assert_eq!(
+        mesh_header,
+        MeshHeader {
+            hops_left: MESH_HOPS_LEFT,
+            originator: MacAddress::Short(NODE),
+            final_dst: MacAddress::Short(FAR),
+        }
+    );
+    // The packet is compressed relative to the originator and final
+    // destination
+    let (ip_header, udp) = decompress(
+        &payload[consumed..],
+        MacAddress::Short(NODE),
+        MacAddress::Short(FAR),
+    );
+    assert_eq!(ip_header.get_src_addr(), address(NODE));
+    assert_eq!(ip_header.get_dst_addr(), address(FAR));
+    assert_eq!(udp[8..], *DATA);
+}
+
+#[test]
+fn mesh_frame_for_another_node_is_forwarded() {
+    let node = node();
+    node.mesh_routes.add_route(MeshRoute {
+        final_dst: MacAddress::Short(FAR),
+        next_hop: MacAddress::Short(NEXT),
+    });
+    let lowpan = b"\x41compressed packet";
+    assert!(node.radio.receive(&frame(
+        PREVIOUS,
+        NODE,
+        &mesh_payload(3, PREVIOUS, FAR, lowpan)
+    )));
+
+    let frames = node.transmitted();
+    assert_eq!(frames.len(), 1);
+    let (header, payload) = parse(&frames[0]);
+    assert_eq!(header.src_addr, Some(MacAddress::Short(NODE)));
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(NEXT)));
+    assert_eq!(payload, &mesh_payload(2, PREVIOUS, FAR, lowpan)[..]);
+    assert!(node.client.received.borrow().is_empty());
+}
+
+#[test]
+fn mesh_frame_without_route_goes_to_its_final_destination() {
+    let node = node();
+    assert!(node.radio.receive(&frame(
+        PREVIOUS,
+        NODE,
+        &mesh_payload(3, PREVIOUS, FAR, b"\x41")
+    )));
+
+    let frames = node.transmitted();
+    let (header, _) = parse(&frames[0]);
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(FAR)));
+}
+
+#[test]
+fn mesh_frame_without_hops_left_is_dropped() {
+    let node = node();
+    assert!(node.radio.receive(&frame(
+        PREVIOUS,
+        NODE,
+        &mesh_payload(1, PREVIOUS, FAR, b"\x41")
+    )));
+
+    assert!(node.transmitted().is_empty());
+}
+
+#[test]
+fn mesh_frame_is_dropped_while_forwarding_another() {
+    let node = node();
+    let mesh_frame = frame(PREVIOUS, NODE, &mesh_payload(3, PREVIOUS, FAR, b"\x41"));
+    assert!(node.radio.receive(&mesh_frame));
+    assert!(node.radio.receive(&mesh_frame));
+    assert_eq!(node.transmitted().len(), 1);
+
+    node.complete_transmit();
+    assert!(node.radio.receive(&mesh_frame));
+    assert_eq!(node.transmitted().len(), 1);
+}
+
+#[test]
+fn mesh_frame_for_this_node_is_received() {
+    let node = node();
+    // Originated by `PREVIOUS`, and compressed relative to the addresses in
+    // the mesh header
+    let packet = udp_packet(PREVIOUS, NODE, 64, PREVIOUS, NODE);
+    assert!(node.radio.receive(&frame(
+        NEXT,
+        NODE,
+        &mesh_payload(1, PREVIOUS, NODE, &packet)
+    )));
+
+    let received = node.client.received.borrow();
+    assert_eq!(received.len(), 1);
+    // The elided addresses are derived from the mesh header
+    assert_eq!(received[0].0.get_src_addr(), address(PREVIOUS));
+    assert_eq!(received[0].0.get_dst_addr(), address(NODE));
+    assert_eq!(received[0].1[8..], *DATA);
+    assert!(node.transmitted().is_empty());
+}
+
+#[test]
+fn packet_for_another_node_is_routed_on() {
+    let node = node();
+    node.routes.add_route(Route {
+        prefix: IPAddr(PREFIX),
+        prefix_len: 64,
+        next_hop: MacAddress::Short(NEXT),
+    });
+    let lowpan = udp_packet(PREVIOUS, FAR, 64, PREVIOUS, NODE);
+    assert!(node.radio.receive(&frame(PREVIOUS, NODE, &lowpan)));
+
+    let frames = node.transmitted();
+    assert_eq!(frames.len(), 1);
+    let (header, payload) = parse(&frames[0]);
+    assert_eq!(header.dst_addr, Some(MacAddress::Short(NEXT)));
+    let (ip_header, udp) = decompress(payload, MacAddress::Short(NODE), MacAddress::Short(NEXT));
+    assert_eq!(ip_header.get_src_addr(), address(PREVIOUS));
+    assert_eq!(ip_header.get_dst_addr(), address(FAR));
+    assert_eq!(ip_header.get_hop_limit(), 63);
+    assert_eq!(udp[..4], [0x12, 0x34, 0x56, 0x78]);
+    assert_eq!(udp[8..], *DATA);
+    assert!(node.client.received.borrow().is_empty());
+
+    // The completion of a forwarded packet is not reported to the client
+    node.complete_transmit();
+    assert!(node.client.sent.borrow().is_empty());
+}
+
+#[test]
+fn packet_with_last_hop_is_not_routed_on() {
+    let node = node();
+    let lowpan = udp_packet(PREVIOUS, FAR, 1, PREVIOUS, NODE);
+    assert!(node.radio.receive(&frame(PREVIOUS, NODE, &lowpan)));
+
+    assert!(node.transmitted().is_empty());
+    assert!(node.client.received.borrow().is_empty());
+}
+
+#[test]
+fn packet_for_this_node_is_received() {
+    let node = node();
+    let lowpan = udp_packet(PREVIOUS, NODE, 64, PREVIOUS, NODE);
+    assert!(node.radio.receive(&frame(PREVIOUS, NODE, &lowpan)));
+
+    let received = node.client.received.borrow();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].1[8..], *DATA);
+    assert!(node.transmitted().is_empty());
+}
+
+#[test]
+fn packet_for_another_node_is_dropped_during_a_local_send() {
+    let node = node();
+    node.routes.add_route(Route {
+        prefix: IPAddr(PREFIX),
+        prefix_len: 64,
+        next_hop: MacAddress::Short(NEXT),
+    });
+    // Too long for one frame, so it is sent in several fragments
+    assert_eq!(
+        node.send_udp_data(address(FAR), &[0x5a; 150]),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(node.transmitted().len(), 1);
+
+    // Between the fragments of the local packet
+    assert!(node.radio.complete_transmit(true, ReturnCode::SUCCESS));
+    let lowpan = udp_packet(PREVIOUS, FAR, 64, PREVIOUS, NODE);
+    assert!(node.radio.receive(&frame(PREVIOUS, NODE, &lowpan)));
+    assert!(node.transmitted().is_empty());
+    assert_eq!(node.send_udp(address(FAR)), ReturnCode::EBUSY);
+
+    let mut fragments = 1;
+    loop {
+        node.alarm.advance_to_alarm();
+        let frames = node.transmitted();
+        if frames.is_empty() {
+            break;
+        }
+        assert_eq!(frames.len(), 1);
+        fragments += 1;
+        assert!(node.radio.complete_transmit(true, ReturnCode::SUCCESS));
+    }
+    assert!(fragments > 1);
+    assert_eq!(*node.client.sent.borrow(), vec![ReturnCode::SUCCESS]);
+
+    // Packets are forwarded again once the local send is done
+    assert!(node.radio.receive(&frame(PREVIOUS, NODE, &lowpan)));
+    assert_eq!(node.transmitted().len(), 1);
+}
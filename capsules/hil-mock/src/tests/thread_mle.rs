@@ -0,0 +1,575 @@
+use core::cell::{Cell, RefCell};
+
+use capsules::aes_software::Aes128Software;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::thread::mle::{
+    derive_keys, MleClient, MleSed, State, CCM_BUF_LEN, CRYPT_BUF_LEN, MLE_PORT, SEND_BUF_LEN,
+};
+use capsules::net::udp::udp_port_table::UdpPortBindingTx;
+use capsules::net::udp::udp_recv::UDPRecvClient;
+use capsules::net::udp::udp_send::{UDPSendClient, UDPSender};
+use capsules::net::udp::UDPHeader;
+use capsules::rng::Entropy32ToRandom;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use kernel::capabilities::{NetworkCapabilityCreationCapability, UdpDriverCapability};
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::Rng;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128, AES128CCM};
+use kernel::hil::time::Alarm;
+use kernel::{ErrorCode, ReturnCode};
+
+use crate::alarm::MockAlarm;
+use crate::entropy::MockEntropy32;
+use crate::leak;
+use crate::tests::{buffer, deferred_caller, run_deferred_calls};
+
+type Mux = MuxAES128CCM<'static, Aes128Software<'static>>;
+type Ccm = VirtualAES128CCM<'static, Aes128Software<'static>>;
+type TestMle = MleSed<'static, MockAlarm<'static>, Ccm>;
+
+const MASTER_KEY: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+const CHILD_EXT_ADDR: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+const PARENT_EXT_ADDR: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+const OTHER_EXT_ADDR: [u8; 8] = [0x0a, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x88];
+const ALL_ROUTERS: IPAddr = IPAddr([
+    0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+]);
+/// Random numbers for the challenge of the child.
+const RANDOM: [u32; 2] = [0x0102_0304, 0x0506_0708];
+const CHALLENGE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+const PARENT_CHALLENGE: [u8; 8] = [0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8];
+const PARENT_RLOC16: u16 = 0x2400;
+const CHILD_RLOC16: u16 = 0x2401;
+
+const PARENT_REQUEST: u8 = 9;
+const PARENT_RESPONSE: u8 = 10;
+const CHILD_ID_REQUEST: u8 = 11;
+const CHILD_ID_RESPONSE: u8 = 12;
+
+fn address(ext_addr: [u8; 8]) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Long(ext_addr))
+}
+
+struct CreateCapability;
+unsafe impl NetworkCapabilityCreationCapability for CreateCapability {}
+
+/// A UDP sender that records the datagrams sent, and keeps the buffer of the
+/// last one until `send_done()`.
+#[derive(Default)]
+struct UdpSender {
+    sent: RefCell<Vec<(IPAddr, u16, Vec<u8>)>>,
+    dgram: Cell<Option<LeasableBuffer<'static, u8>>>,
+}
+
+impl<'a> UDPSender<'a> for UdpSender {
+    fn set_client(&self, _client: &'a dyn UDPSendClient) {}
+
+    fn send_to(
+        &'a self,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        self.sent
+            .borrow_mut()
+            .push((dest, dst_port, buf[..].to_vec()));
+        self.dgram.set(Some(buf));
+        Ok(())
+    }
+
+    fn driver_send_to(
+        &'a self,
+        _dest: IPAddr,
+        _dst_port: u16,
+        _src_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _driver_send_cap: &dyn UdpDriverCapability,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn send(
+        &'a self,
+        _dest: IPAddr,
+        _udp_header: UDPHeader,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn get_binding(&self) -> Option<UdpPortBindingTx> {
+        None
+    }
+
+    fn is_bound(&self) -> bool {
+        true
+    }
+
+    fn set_binding(&self, _binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+        None
+    }
+}
+
+/// Records the results of attaches.
+#[derive(Default)]
+struct Client {
+    results: RefCell<Vec<Result<u16, ErrorCode>>>,
+}
+
+impl MleClient for Client {
+    fn attach_done(&self, result: Result<u16, ErrorCode>) {
+        self.results.borrow_mut().push(result);
+    }
+}
+
+/// The other end of the MLE exchanges, which secures and checks messages
+/// with its own CCM client.
+struct Peer {
+    ccm: &'static Ccm,
+    done: RefCell<Option<(Vec<u8>, bool)>>,
+}
+
+impl CCMClient for Peer {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        assert_eq!(res, ReturnCode::SUCCESS);
+        *self.done.borrow_mut() = Some((buf.to_vec(), tag_is_valid));
+    }
+}
+
+struct Fixture {
+    mle: &'static TestMle,
+    udp: &'static UdpSender,
+    alarm: &'static MockAlarm<'static>,
+    entropy: &'static MockEntropy32<'static>,
+    client: &'static Client,
+    peer: &'static Peer,
+    deferred_caller: &'static DynamicDeferredCall,
+    mle_key: [u8; 16],
+}
+
+fn ccm(mux: &'static Mux) -> &'static Ccm {
+    let ccm = leak(VirtualAES128CCM::new(mux, buffer(&[0; CCM_BUF_LEN])));
+    ccm.setup();
+    ccm
+}
+
+fn mle() -> Fixture {
+    let deferred_caller = deferred_caller(2);
+    let aes = leak(Aes128Software::new(deferred_caller));
+    aes.initialize_callback_handle(deferred_caller.register(aes).unwrap());
+    let mux: &'static Mux = leak(MuxAES128CCM::new(aes, deferred_caller));
+    mux.initialize_callback_handle(deferred_caller.register(mux).unwrap());
+    aes.set_client(mux);
+
+    let udp = leak(UdpSender::default());
+    let alarm = leak(MockAlarm::new());
+    let entropy = leak(MockEntropy32::new());
+    let rng = leak(Entropy32ToRandom::new(entropy));
+    let net_cap = leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &CreateCapability,
+    ));
+
+    let mle_ccm = ccm(mux);
+    let mle = leak(MleSed::new(
+        udp,
+        mle_ccm,
+        rng,
+        alarm,
+        net_cap,
+        CHILD_EXT_ADDR,
+        address(CHILD_EXT_ADDR),
+        buffer(&[0; CRYPT_BUF_LEN]),
+        buffer(&[0; SEND_BUF_LEN]),
+    ));
+    mle_ccm.set_client(mle);
+    rng.set_client(mle);
+    alarm.set_alarm_client(mle);
+    let client = leak(Client::default());
+    mle.set_client(client);
+
+    let peer = leak(Peer {
+        ccm: ccm(mux),
+        done: RefCell::new(None),
+    });
+    peer.ccm.set_client(peer);
+
+    Fixture {
+        mle,
+        udp,
+        alarm,
+        entropy,
+        client,
+        peer,
+        deferred_caller,
+        mle_key: derive_keys(&MASTER_KEY, 0).0,
+    }
+}
+
+/// A TLV with a one-byte type and length.
+fn tlv(tlv_type: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tlv_type, value.len() as u8];
+    tlv.extend_from_slice(value);
+    tlv
+}
+
+fn command(command_type: u8, tlvs: &[Vec<u8>]) -> Vec<u8> {
+    let mut command = vec![command_type];
+    for tlv in tlvs {
+        command.extend_from_slice(tlv);
+    }
+    command
+}
+
+fn parent_response(challenge: &[u8; 8], link_margin: u8) -> Vec<u8> {
+    command(
+        PARENT_RESPONSE,
+        &[
+            tlv(0, &PARENT_RLOC16.to_be_bytes()),
+            tlv(11, &[0x00, 0x00, 0x12, 0x34, 64, 1, 1, 0x00]),
+            tlv(5, &[0, 0, 0, 0]),
+            tlv(4, challenge),
+            tlv(3, &PARENT_CHALLENGE),
+            tlv(16, &[link_margin]),
+            tlv(15, &[0x40, 1, 0, 0, 1, 5, 2]),
+            tlv(18, &[0, 2]),
+        ],
+    )
+}
+
+fn child_id_response() -> Vec<u8> {
+    command(
+        CHILD_ID_RESPONSE,
+        &[
+            tlv(0, &PARENT_RLOC16.to_be_bytes()),
+            tlv(11, &[0x00, 0x00, 0x12, 0x34, 64, 1, 1, 0x00]),
+            tlv(10, &CHILD_RLOC16.to_be_bytes()),
+            // An unknown TLV, skipped.
+            tlv(22, &[0, 0, 0, 0, 0, 1, 0, 0]),
+            tlv(12, &[]),
+        ],
+    )
+}
+
+impl Fixture {
+    fn run_deferred_calls(&self) {
+        run_deferred_calls(self.deferred_caller);
+    }
+
+    /// Start attaching, up to the Parent Request.
+    fn attach(&self) {
+        assert_eq!(self.mle.attach(&MASTER_KEY, 0), Ok(()));
+        assert_eq!(self.mle.state(), State::Starting);
+        self.entropy.push_entropy(&RANDOM);
+        assert!(self.entropy.complete());
+        self.run_deferred_calls();
+        assert_eq!(self.mle.state(), State::ParentRequest(0));
+    }
+
+    /// Secure a message with `key`, and return the UDP payload.
+    fn secure(
+        &self,
+        key: &[u8; 16],
+        source: [u8; 8],
+        destination: IPAddr,
+        frame_counter: u32,
+        command: &[u8],
+    ) -> Vec<u8> {
+        let mut aux_header = vec![0x15];
+        aux_header.extend_from_slice(&frame_counter.to_le_bytes());
+        aux_header.extend_from_slice(&[0, 0, 0, 0, 1]);
+
+        let mut buf = address(source).0.to_vec();
+        buf.extend_from_slice(&destination.0);
+        buf.extend_from_slice(&aux_header);
+        buf.extend_from_slice(command);
+        buf.extend_from_slice(&[0; 4]);
+        let (buf, _) = self.crypt(key, source, frame_counter, buf, command.len(), true);
+
+        let mut payload = vec![0];
+        payload.extend_from_slice(&buf[32..]);
+        payload
+    }
+
+    /// Check a message from the child, and return its command.
+    fn check(&self, destination: IPAddr, payload: &[u8]) -> Vec<u8> {
+        assert_eq!(payload[0], 0);
+        assert_eq!(payload[1], 0x15);
+        assert_eq!(payload[6..11], [0, 0, 0, 0, 1]);
+        let frame_counter = u32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]]);
+
+        let mut buf = address(CHILD_EXT_ADDR).0.to_vec();
+        buf.extend_from_slice(&destination.0);
+        buf.extend_from_slice(&payload[1..]);
+        let len = payload.len() - 15;
+        let key = self.mle_key;
+        let (buf, tag_is_valid) = self.crypt(&key, CHILD_EXT_ADDR, frame_counter, buf, len, false);
+        assert!(tag_is_valid);
+        buf[42..42 + len].to_vec()
+    }
+
+    fn crypt(
+        &self,
+        key: &[u8; 16],
+        sender: [u8; 8],
+        frame_counter: u32,
+        buf: Vec<u8>,
+        len: usize,
+        encrypting: bool,
+    ) -> (Vec<u8>, bool) {
+        let mut nonce = sender.to_vec();
+        nonce.extend_from_slice(&frame_counter.to_be_bytes());
+        nonce.push(5);
+        let ccm = self.peer.ccm;
+        assert_eq!(ccm.set_key(key), ReturnCode::SUCCESS);
+        assert_eq!(ccm.set_nonce(&nonce), ReturnCode::SUCCESS);
+        let (res, _) = ccm.crypt(buffer(&buf), 0, 42, len, 4, true, encrypting);
+        assert_eq!(res, ReturnCode::SUCCESS);
+        self.run_deferred_calls();
+        self.peer.done.borrow_mut().take().unwrap()
+    }
+
+    /// The datagrams the child sent, which are all sent once this returns.
+    fn sent(&self) -> Vec<(IPAddr, u16, Vec<u8>)> {
+        if let Some(dgram) = self.udp.dgram.take() {
+            self.mle.send_done(ReturnCode::SUCCESS, dgram);
+        }
+        self.udp.sent.borrow_mut().drain(..).collect()
+    }
+
+    /// The command of the only message the child sent, to `destination`.
+    fn sent_command(&self, destination: IPAddr) -> Vec<u8> {
+        let sent = self.sent();
+        assert_eq!(sent.len(), 1);
+        let (dest, port, payload) = &sent[0];
+        assert_eq!(*dest, destination);
+        assert_eq!(*port, MLE_PORT);
+        self.check(destination, payload)
+    }
+
+    /// Deliver a message from `source`, secured with `key`.
+    fn receive_with_key(
+        &self,
+        key: &[u8; 16],
+        source: [u8; 8],
+        frame_counter: u32,
+        command: &[u8],
+    ) {
+        let payload = self.secure(key, source, address(CHILD_EXT_ADDR), frame_counter, command);
+        self.mle.receive(
+            address(source),
+            address(CHILD_EXT_ADDR),
+            MLE_PORT,
+            MLE_PORT,
+            &payload,
+        );
+        self.run_deferred_calls();
+    }
+
+    fn receive(&self, source: [u8; 8], frame_counter: u32, command: &[u8]) {
+        let key = self.mle_key;
+        self.receive_with_key(&key, source, frame_counter, command);
+    }
+
+    /// Attach up to the Child ID Request, and return its command.
+    fn child_id_request(&self) -> Vec<u8> {
+        self.attach();
+        self.sent();
+        self.receive(PARENT_EXT_ADDR, 100, &parent_response(&CHALLENGE, 20));
+        assert!(self.alarm.advance_to_alarm());
+        self.run_deferred_calls();
+        assert_eq!(self.mle.state(), State::ChildIdRequest(0));
+        self.sent_command(address(PARENT_EXT_ADDR))
+    }
+}
+
+#[test]
+fn keys_are_derived_from_the_master_key() {
+    let (mle_key, mac_key) = derive_keys(&MASTER_KEY, 0);
+    assert_eq!(
+        mle_key,
+        [
+            0x54, 0x45, 0xf4, 0x15, 0x8f, 0xd7, 0x59, 0x12, 0x17, 0x58, 0x09, 0xf8, 0xb5, 0x7a,
+            0x66, 0xa4
+        ]
+    );
+    assert_eq!(
+        mac_key,
+        [
+            0xde, 0x89, 0xc5, 0x3a, 0xf3, 0x82, 0xb4, 0x21, 0xe0, 0xfd, 0xe5, 0xa9, 0xba, 0xe3,
+            0xbe, 0xf0
+        ]
+    );
+}
+
+#[test]
+fn attach_sends_a_secured_parent_request() {
+    let f = mle();
+    f.attach();
+
+    let command = f.sent_command(ALL_ROUTERS);
+    assert_eq!(
+        command,
+        [
+            vec![PARENT_REQUEST],
+            // Mode: sleepy end device.
+            tlv(1, &[0x04]),
+            tlv(3, &CHALLENGE),
+            // Scan mask: routers.
+            tlv(14, &[0x80]),
+            tlv(18, &[0, 2]),
+        ]
+        .concat()
+    );
+    assert_eq!(f.mle.attach(&MASTER_KEY, 0), Err(ErrorCode::BUSY));
+}
+
+#[test]
+fn attach_completes_with_the_child_id_response() {
+    let f = mle();
+    let request = f.child_id_request();
+    assert_eq!(
+        request,
+        [
+            vec![CHILD_ID_REQUEST],
+            tlv(4, &PARENT_CHALLENGE),
+            tlv(5, &[0, 0, 0, 0]),
+            // The Parent Request used frame counter 0.
+            tlv(8, &[0, 0, 0, 1]),
+            tlv(1, &[0x04]),
+            tlv(2, &240u32.to_be_bytes()),
+            tlv(18, &[0, 2]),
+            tlv(13, &[10, 12]),
+        ]
+        .concat()
+    );
+
+    f.receive(PARENT_EXT_ADDR, 101, &child_id_response());
+    assert_eq!(f.mle.state(), State::Attached(CHILD_RLOC16));
+    assert_eq!(*f.client.results.borrow(), [Ok(CHILD_RLOC16)]);
+    assert_eq!(f.mle.mac_key(), Some(derive_keys(&MASTER_KEY, 0).1));
+    assert_eq!(f.mle.attach(&MASTER_KEY, 0), Err(ErrorCode::ALREADY));
+    // The timeout of the Child ID Request was cancelled.
+    assert!(!f.alarm.advance_to_alarm());
+
+    assert_eq!(f.mle.detach(), Ok(()));
+    assert_eq!(f.mle.state(), State::Detached);
+    assert_eq!(f.mle.mac_key(), None);
+}
+
+#[test]
+fn parent_with_the_best_link_margin_is_selected() {
+    let f = mle();
+    f.attach();
+    f.sent();
+
+    f.receive(OTHER_EXT_ADDR, 7, &parent_response(&CHALLENGE, 10));
+    f.receive(PARENT_EXT_ADDR, 100, &parent_response(&CHALLENGE, 20));
+    f.receive(OTHER_EXT_ADDR, 8, &parent_response(&CHALLENGE, 15));
+    assert!(f.alarm.advance_to_alarm());
+    f.run_deferred_calls();
+
+    f.sent_command(address(PARENT_EXT_ADDR));
+}
+
+#[test]
+fn invalid_parent_responses_are_ignored() {
+    let f = mle();
+    f.attach();
+    f.sent();
+
+    // Wrong response to the challenge.
+    f.receive(PARENT_EXT_ADDR, 100, &parent_response(&[0; 8], 20));
+    // Wrong key.
+    let (wrong_key, _) = derive_keys(&[0x55; 16], 0);
+    f.receive_with_key(
+        &wrong_key,
+        PARENT_EXT_ADDR,
+        101,
+        &parent_response(&CHALLENGE, 20),
+    );
+    // Tampered with.
+    let mut payload = f.secure(
+        &f.mle_key.clone(),
+        PARENT_EXT_ADDR,
+        address(CHILD_EXT_ADDR),
+        102,
+        &parent_response(&CHALLENGE, 20),
+    );
+    payload[20] ^= 1;
+    f.mle.receive(
+        address(PARENT_EXT_ADDR),
+        address(CHILD_EXT_ADDR),
+        MLE_PORT,
+        MLE_PORT,
+        &payload,
+    );
+    f.run_deferred_calls();
+
+    // Without a valid response, the Parent Request is sent again, to
+    // routers and router-eligible end devices.
+    assert!(f.alarm.advance_to_alarm());
+    f.run_deferred_calls();
+    assert_eq!(f.mle.state(), State::ParentRequest(1));
+    let command = f.sent_command(ALL_ROUTERS);
+    assert_eq!(
+        command[4..],
+        [tlv(3, &CHALLENGE), tlv(14, &[0xc0]), tlv(18, &[0, 2])].concat()
+    );
+}
+
+#[test]
+fn attach_fails_without_parent() {
+    let f = mle();
+    f.attach();
+    assert_eq!(f.sent().len(), 1);
+
+    assert!(f.alarm.advance_to_alarm());
+    f.run_deferred_calls();
+    assert_eq!(f.sent().len(), 1);
+    assert!(f.alarm.advance_to_alarm());
+
+    assert_eq!(f.mle.state(), State::Detached);
+    assert_eq!(*f.client.results.borrow(), [Err(ErrorCode::FAIL)]);
+    assert!(f.sent().is_empty());
+}
+
+#[test]
+fn child_id_request_is_retried() {
+    let f = mle();
+    f.child_id_request();
+
+    for index in 1..3 {
+        assert!(f.alarm.advance_to_alarm());
+        f.run_deferred_calls();
+        assert_eq!(f.mle.state(), State::ChildIdRequest(index));
+        f.sent_command(address(PARENT_EXT_ADDR));
+    }
+    // A replayed message of the parent is ignored.
+    f.receive(PARENT_EXT_ADDR, 100, &child_id_response());
+    assert_eq!(f.mle.state(), State::ChildIdRequest(2));
+
+    assert!(f.alarm.advance_to_alarm());
+    assert_eq!(f.mle.state(), State::Detached);
+    assert_eq!(*f.client.results.borrow(), [Err(ErrorCode::FAIL)]);
+}
+
+#[test]
+fn child_id_response_from_another_device_is_ignored() {
+    let f = mle();
+    f.child_id_request();
+
+    f.receive(OTHER_EXT_ADDR, 200, &child_id_response());
+    assert_eq!(f.mle.state(), State::ChildIdRequest(0));
+    assert!(f.client.results.borrow().is_empty());
+}
@@ -0,0 +1,149 @@
+use core::cell::Cell;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::hil::time::{Alarm, AlarmClient, Ticks32, Time};
+
+use crate::alarm::{AlarmCall, MockAlarm};
+use crate::leak;
+
+/// Counts how often it fires, and re-arms its alarm `repeat` times.
+struct Counter {
+    alarm: Cell<Option<&'static VirtualMuxAlarm<'static, MockAlarm<'static>>>>,
+    fired: Cell<usize>,
+    repeat: Cell<usize>,
+    period: u32,
+}
+
+impl AlarmClient for Counter {
+    fn alarm(&self) {
+        self.fired.set(self.fired.get() + 1);
+        if self.repeat.get() > 0 {
+            self.repeat.set(self.repeat.get() - 1);
+            let alarm = self.alarm.get().unwrap();
+            alarm.set_alarm(alarm.now(), Ticks32::from(self.period));
+        }
+    }
+}
+
+fn mux() -> (
+    &'static MockAlarm<'static>,
+    &'static MuxAlarm<'static, MockAlarm<'static>>,
+) {
+    let alarm = leak(MockAlarm::new());
+    let mux = leak(MuxAlarm::new(alarm));
+    alarm.set_alarm_client(mux);
+    (alarm, mux)
+}
+
+fn virtual_alarm(
+    mux: &'static MuxAlarm<'static, MockAlarm<'static>>,
+    repeat: usize,
+    period: u32,
+) -> (
+    &'static VirtualMuxAlarm<'static, MockAlarm<'static>>,
+    &'static Counter,
+) {
+    let alarm = leak(VirtualMuxAlarm::new(mux));
+    let counter = leak(Counter {
+        alarm: Cell::new(Some(alarm)),
+        fired: Cell::new(0),
+        repeat: Cell::new(repeat),
+        period,
+    });
+    alarm.set_alarm_client(counter);
+    (alarm, counter)
+}
+
+fn set_alarm(reference: u32, dt: u32) -> AlarmCall<Ticks32> {
+    AlarmCall::SetAlarm {
+        reference: Ticks32::from(reference),
+        dt: Ticks32::from(dt),
+    }
+}
+
+#[test]
+fn alarms_fire_in_order() {
+    let (alarm, mux) = mux();
+    let (first, first_counter) = virtual_alarm(mux, 0, 0);
+    let (second, second_counter) = virtual_alarm(mux, 0, 0);
+
+    second.set_alarm(Ticks32::from(0), Ticks32::from(20));
+    first.set_alarm(Ticks32::from(0), Ticks32::from(10));
+    // The earlier alarm replaces the later one in the underlying alarm.
+    assert_eq!(alarm.take_calls(), [set_alarm(0, 20), set_alarm(0, 10)]);
+
+    alarm.advance(10);
+    assert_eq!(first_counter.fired.get(), 1);
+    assert_eq!(second_counter.fired.get(), 0);
+    assert_eq!(alarm.take_calls(), [set_alarm(0, 20)]);
+
+    alarm.advance(10);
+    assert_eq!(first_counter.fired.get(), 1);
+    assert_eq!(second_counter.fired.get(), 1);
+    assert_eq!(alarm.take_calls(), [AlarmCall::Disarm]);
+    assert!(!first.is_armed() && !second.is_armed());
+}
+
+#[test]
+fn later_alarm_keeps_earlier_one() {
+    let (alarm, mux) = mux();
+    let (first, first_counter) = virtual_alarm(mux, 0, 0);
+    let (second, second_counter) = virtual_alarm(mux, 0, 0);
+
+    first.set_alarm(Ticks32::from(0), Ticks32::from(10));
+    second.set_alarm(Ticks32::from(0), Ticks32::from(20));
+    assert_eq!(alarm.take_calls(), [set_alarm(0, 10)]);
+
+    alarm.advance(25);
+    assert_eq!(first_counter.fired.get(), 1);
+    assert_eq!(second_counter.fired.get(), 1);
+}
+
+#[test]
+fn alarms_fire_across_wrap_around() {
+    let (alarm, mux) = mux();
+    let (first, counter) = virtual_alarm(mux, 0, 0);
+    alarm.set_now(Ticks32::from(u32::MAX - 5));
+
+    first.set_alarm(first.now(), Ticks32::from(10));
+    alarm.advance(5);
+    assert_eq!(counter.fired.get(), 0);
+    alarm.advance(5);
+    assert_eq!(counter.fired.get(), 1);
+    assert_eq!(alarm.now(), Ticks32::from(4));
+}
+
+#[test]
+fn alarms_rearmed_in_callback() {
+    let (alarm, mux) = mux();
+    let (periodic, periodic_counter) = virtual_alarm(mux, 2, 10);
+    let (other, other_counter) = virtual_alarm(mux, 0, 0);
+
+    periodic.set_alarm(Ticks32::from(0), Ticks32::from(10));
+    other.set_alarm(Ticks32::from(0), Ticks32::from(25));
+    while alarm.advance_to_alarm() {}
+
+    assert_eq!(periodic_counter.fired.get(), 3);
+    assert_eq!(other_counter.fired.get(), 1);
+    assert_eq!(alarm.now(), Ticks32::from(30));
+    assert_eq!(alarm.remaining(), None);
+}
+
+#[test]
+fn disarming_last_alarm_disarms_underlying_alarm() {
+    let (alarm, mux) = mux();
+    let (first, first_counter) = virtual_alarm(mux, 0, 0);
+    let (second, _) = virtual_alarm(mux, 0, 0);
+
+    first.set_alarm(Ticks32::from(0), Ticks32::from(10));
+    second.set_alarm(Ticks32::from(0), Ticks32::from(20));
+    alarm.take_calls();
+
+    second.disarm();
+    assert_eq!(alarm.take_calls(), []);
+    first.disarm();
+    assert_eq!(alarm.take_calls(), [AlarmCall::Disarm]);
+
+    alarm.advance(30);
+    assert_eq!(first_counter.fired.get(), 0);
+}
@@ -0,0 +1,109 @@
+use core::cell::RefCell;
+
+use capsules::virtual_flash::{FlashUser, MuxFlash};
+use kernel::hil::flash::{self, Flash, HasClient};
+
+use crate::flash::{FlashCall, MockFlash, MockPage, PAGE_SIZE};
+use crate::leak;
+
+type User = FlashUser<'static, MockFlash<'static>>;
+
+#[derive(Debug, PartialEq)]
+enum Completed {
+    Read(Vec<u8>, flash::Error),
+    Write(flash::Error),
+    Erase(flash::Error),
+}
+
+/// Records the operations completed for a flash client.
+#[derive(Default)]
+struct Client {
+    completed: RefCell<Vec<Completed>>,
+}
+
+impl flash::Client<User> for Client {
+    fn read_complete(&self, read_buffer: &'static mut MockPage, error: flash::Error) {
+        self.completed
+            .borrow_mut()
+            .push(Completed::Read(read_buffer.0[..4].to_vec(), error));
+    }
+
+    fn write_complete(&self, _write_buffer: &'static mut MockPage, error: flash::Error) {
+        self.completed.borrow_mut().push(Completed::Write(error));
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        self.completed.borrow_mut().push(Completed::Erase(error));
+    }
+}
+
+fn mux() -> (
+    &'static MockFlash<'static>,
+    &'static MuxFlash<'static, MockFlash<'static>>,
+) {
+    let flash = leak(MockFlash::new(4));
+    let mux = leak(MuxFlash::new(flash));
+    flash.set_client(mux);
+    (flash, mux)
+}
+
+fn user(mux: &'static MuxFlash<'static, MockFlash<'static>>) -> (&'static User, &'static Client) {
+    let user = leak(FlashUser::new(mux));
+    let client = leak(Client::default());
+    user.set_client(client);
+    (user, client)
+}
+
+fn page(contents: &[u8]) -> &'static mut MockPage {
+    let page = leak(MockPage::default());
+    page.0[..contents.len()].copy_from_slice(contents);
+    page
+}
+
+#[test]
+fn operations_are_serialized() {
+    let (flash, mux) = mux();
+    let (writer, writer_client) = user(mux);
+    let (reader, reader_client) = user(mux);
+
+    assert!(writer.write_page(1, page(&[1, 2, 3, 4])).is_ok());
+    assert!(reader.read_page(1, page(&[])).is_ok());
+    assert_eq!(flash.take_calls(), [FlashCall::Write(1)]);
+
+    assert!(flash.complete());
+    assert_eq!(
+        *writer_client.completed.borrow(),
+        [Completed::Write(flash::Error::CommandComplete)]
+    );
+    assert_eq!(flash.take_calls(), [FlashCall::Read(1)]);
+
+    assert!(flash.complete());
+    assert_eq!(
+        *reader_client.completed.borrow(),
+        [Completed::Read(
+            vec![1, 2, 3, 4],
+            flash::Error::CommandComplete
+        )]
+    );
+    assert!(!flash.complete());
+}
+
+#[test]
+fn errors_are_passed_to_the_user() {
+    let (flash, mux) = mux();
+    let (user, client) = user(mux);
+    flash.set_page(2, &[5; PAGE_SIZE]);
+
+    flash.push_error(flash::Error::FlashError);
+    user.erase_page(2);
+    assert!(flash.complete());
+    assert_eq!(
+        *client.completed.borrow(),
+        [Completed::Erase(flash::Error::FlashError)]
+    );
+    assert_eq!(flash.page(2), [5; PAGE_SIZE]);
+
+    user.erase_page(2);
+    assert!(flash.complete());
+    assert_eq!(flash.page(2), [0xFF; PAGE_SIZE]);
+}
@@ -0,0 +1,123 @@
+use core::cell::RefCell;
+
+use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use kernel::hil::i2c::{self, Error, I2CMaster};
+
+use crate::i2c::{I2CCall, MockI2CDevice, MockI2CMaster};
+use crate::leak;
+use crate::tests::{buffer, deferred_caller};
+
+/// Records the transfers completed for an I2C client.
+#[derive(Default)]
+struct Client {
+    completed: RefCell<Vec<(Vec<u8>, Error)>>,
+}
+
+impl i2c::I2CClient for Client {
+    fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        self.completed.borrow_mut().push((buffer.to_vec(), error));
+    }
+}
+
+fn mux() -> (&'static MockI2CMaster, &'static MuxI2C<'static>) {
+    let i2c = leak(MockI2CMaster::new());
+    let mux = leak(MuxI2C::new(i2c, None, deferred_caller(1)));
+    i2c.set_master_client(mux);
+    (i2c, mux)
+}
+
+fn device(
+    mux: &'static MuxI2C<'static>,
+    addr: u8,
+) -> (&'static I2CDevice<'static>, &'static Client) {
+    let device = leak(I2CDevice::new(mux, addr));
+    let client = leak(Client::default());
+    device.set_client(client);
+    (device, client)
+}
+
+#[test]
+fn transfers_are_serialized() {
+    let (i2c, mux) = mux();
+    let (sensor, sensor_client) = device(mux, 0x40);
+    let (eeprom, eeprom_client) = device(mux, 0x50);
+
+    i2c::I2CDevice::write_read(sensor, buffer(&[0xE3, 0, 0]), 1, 2);
+    i2c::I2CDevice::write(eeprom, buffer(&[0x00, 0x10, 0xAA]), 3);
+    assert_eq!(
+        i2c.take_calls(),
+        [I2CCall::WriteRead {
+            addr: 0x40,
+            data: vec![0xE3],
+            read_len: 2
+        }]
+    );
+
+    i2c.push_response(Ok(vec![0x12, 0x34]));
+    assert!(i2c.complete());
+    assert_eq!(
+        *sensor_client.completed.borrow(),
+        [(vec![0x12, 0x34, 0], Error::CommandComplete)]
+    );
+    assert_eq!(
+        i2c.take_calls(),
+        [I2CCall::Write {
+            addr: 0x50,
+            data: vec![0x00, 0x10, 0xAA]
+        }]
+    );
+
+    assert!(i2c.complete());
+    assert_eq!(
+        *eeprom_client.completed.borrow(),
+        [(vec![0x00, 0x10, 0xAA], Error::CommandComplete)]
+    );
+    assert!(!i2c.complete());
+}
+
+#[test]
+fn errors_are_passed_to_the_device() {
+    let (i2c, mux) = mux();
+    let (device, client) = device(mux, 0x40);
+
+    i2c::I2CDevice::read(device, buffer(&[0; 2]), 2);
+    i2c.push_response(Err(Error::AddressNak));
+    assert!(i2c.complete());
+    assert_eq!(
+        *client.completed.borrow(),
+        [(vec![0, 0], Error::AddressNak)]
+    );
+}
+
+#[test]
+fn bus_is_enabled_while_any_device_is() {
+    let (i2c, mux) = mux();
+    let (first, _) = device(mux, 0x40);
+    let (second, _) = device(mux, 0x50);
+
+    i2c::I2CDevice::enable(first);
+    i2c::I2CDevice::enable(second);
+    i2c::I2CDevice::enable(second);
+    assert_eq!(i2c.take_calls(), [I2CCall::Enable]);
+
+    i2c::I2CDevice::disable(first);
+    assert_eq!(i2c.take_calls(), []);
+    i2c::I2CDevice::disable(second);
+    assert_eq!(i2c.take_calls(), [I2CCall::Disable]);
+}
+
+#[test]
+fn mock_device_records_address() {
+    let device = leak(MockI2CDevice::new(0x1D));
+    let client = leak(Client::default());
+    device.set_client(client);
+
+    i2c::I2CDevice::read(device, buffer(&[0; 1]), 1);
+    device.push_response(Ok(vec![0x2A]));
+    assert!(device.complete());
+    assert_eq!(device.take_calls(), [I2CCall::Read { addr: 0x1D, len: 1 }]);
+    assert_eq!(
+        *client.completed.borrow(),
+        [(vec![0x2A], Error::CommandComplete)]
+    );
+}
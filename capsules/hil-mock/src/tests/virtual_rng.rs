@@ -0,0 +1,136 @@
+use core::cell::{Cell, RefCell};
+
+use capsules::rng::Entropy32ToRandom;
+use capsules::virtual_rng::{MuxRngMaster, VirtualRngMasterDevice};
+use kernel::hil::rng::{self, Continue, Rng};
+use kernel::ReturnCode;
+
+use crate::entropy::{EntropyCall, MockEntropy32};
+use crate::leak;
+
+/// Takes `wanted` random numbers.
+struct Client {
+    wanted: Cell<usize>,
+    received: RefCell<Vec<u32>>,
+}
+
+impl rng::Client for Client {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> Continue {
+        while self.wanted.get() > 0 {
+            match randomness.next() {
+                Some(value) => {
+                    self.received.borrow_mut().push(value);
+                    self.wanted.set(self.wanted.get() - 1);
+                }
+                None => return Continue::More,
+            }
+        }
+        Continue::Done
+    }
+}
+
+fn mux() -> (
+    &'static MockEntropy32<'static>,
+    &'static MuxRngMaster<'static>,
+) {
+    let entropy = leak(MockEntropy32::new());
+    let rng = leak(Entropy32ToRandom::new(entropy));
+    (entropy, leak(MuxRngMaster::new(rng)))
+}
+
+fn device(
+    mux: &'static MuxRngMaster<'static>,
+    wanted: usize,
+) -> (&'static VirtualRngMasterDevice<'static>, &'static Client) {
+    let device = leak(VirtualRngMasterDevice::new(mux));
+    let client = leak(Client {
+        wanted: Cell::new(wanted),
+        received: RefCell::new(Vec::new()),
+    });
+    device.set_client(client);
+    (device, client)
+}
+
+#[test]
+fn requests_are_served_in_turn() {
+    let (entropy, mux) = mux();
+    let (first, first_client) = device(mux, 2);
+    let (second, second_client) = device(mux, 1);
+
+    assert_eq!(first.get(), ReturnCode::SUCCESS);
+    assert_eq!(second.get(), ReturnCode::SUCCESS);
+    assert_eq!(entropy.take_calls(), [EntropyCall::Get]);
+
+    // Not enough entropy for the first request yet.
+    entropy.push_entropy(&[1]);
+    assert!(entropy.complete());
+    assert!(entropy.is_requested());
+    assert!(second_client.received.borrow().is_empty());
+
+    entropy.push_entropy(&[2, 3]);
+    assert!(entropy.complete());
+    assert_eq!(*first_client.received.borrow(), [1, 2]);
+    // The second request starts once the first one is done.
+    assert_eq!(entropy.take_calls(), [EntropyCall::Get]);
+
+    assert!(entropy.complete());
+    assert_eq!(*second_client.received.borrow(), [3]);
+    assert!(!entropy.is_requested());
+}
+
+#[test]
+fn failed_request_is_reported() {
+    let (entropy, mux) = mux();
+    let (device, _) = device(mux, 1);
+
+    entropy.push_result(ReturnCode::EOFF);
+    assert_eq!(device.get(), ReturnCode::EOFF);
+    assert!(!entropy.is_requested());
+    assert_eq!(device.get(), ReturnCode::SUCCESS);
+}
+
+#[test]
+fn cancel_stops_inflight_request() {
+    let (entropy, mux) = mux();
+    let (device, client) = device(mux, 1);
+
+    device.get();
+    assert_eq!(device.cancel(), ReturnCode::SUCCESS);
+    assert_eq!(
+        entropy.take_calls(),
+        [EntropyCall::Get, EntropyCall::Cancel]
+    );
+    entropy.push_entropy(&[1]);
+    assert!(!entropy.complete());
+    assert!(client.received.borrow().is_empty());
+    assert_eq!(entropy.remaining(), 1);
+}
+
+/// A device that asks for more randomness must stay in flight: the mux used
+/// to drop it after the first batch, so the rest of the randomness was
+/// discarded and the request never completed.
+#[test]
+fn request_wanting_more_stays_inflight() {
+    let (entropy, mux) = mux();
+    let (first, first_client) = device(mux, 3);
+    let (second, second_client) = device(mux, 1);
+
+    assert_eq!(first.get(), ReturnCode::SUCCESS);
+    assert_eq!(second.get(), ReturnCode::SUCCESS);
+    for value in 1..=3 {
+        entropy.push_entropy(&[value]);
+        assert!(entropy.complete());
+        // The second request can not start while the first one is in flight.
+        assert!(second_client.received.borrow().is_empty());
+    }
+    assert_eq!(*first_client.received.borrow(), [1, 2, 3]);
+    assert_eq!(entropy.take_calls(), [EntropyCall::Get, EntropyCall::Get]);
+
+    entropy.push_entropy(&[4]);
+    assert!(entropy.complete());
+    assert_eq!(*second_client.received.borrow(), [4]);
+}
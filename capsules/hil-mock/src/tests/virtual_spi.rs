@@ -0,0 +1,136 @@
+use core::cell::RefCell;
+
+use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMaster, SpiMasterDevice};
+use kernel::ReturnCode;
+
+use crate::leak;
+use crate::spi::{MockSpiMaster, MockSpiMasterDevice, SpiCall};
+use crate::tests::buffer;
+
+/// Records the transfers completed for a SPI client.
+#[derive(Default)]
+struct Client {
+    completed: RefCell<Vec<(Vec<u8>, Option<Vec<u8>>, usize)>>,
+}
+
+impl spi::SpiMasterClient for Client {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) {
+        self.completed.borrow_mut().push((
+            write_buffer.to_vec(),
+            read_buffer.map(|read_buffer| read_buffer.to_vec()),
+            len,
+        ));
+    }
+}
+
+type Device = VirtualSpiMasterDevice<'static, MockSpiMaster>;
+
+fn mux() -> (
+    &'static MockSpiMaster,
+    &'static MuxSpiMaster<'static, MockSpiMaster>,
+) {
+    let spi = leak(MockSpiMaster::new());
+    let mux = leak(MuxSpiMaster::new(spi));
+    spi.set_client(mux);
+    (spi, mux)
+}
+
+fn device(
+    mux: &'static MuxSpiMaster<'static, MockSpiMaster>,
+    chip_select: u8,
+) -> (&'static Device, &'static Client) {
+    let device = leak(VirtualSpiMasterDevice::new(mux, chip_select));
+    let client = leak(Client::default());
+    device.set_client(client);
+    (device, client)
+}
+
+#[test]
+fn transfers_select_their_chip() {
+    let (spi, mux) = mux();
+    let (radio, radio_client) = device(mux, 1);
+    let (flash, flash_client) = device(mux, 2);
+
+    assert_eq!(
+        radio.read_write_bytes(buffer(&[0x9F, 0, 0]), Some(buffer(&[0; 3])), 3),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        flash.read_write_bytes(buffer(&[0x06]), None, 1),
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        spi.take_calls(),
+        [
+            SpiCall::SpecifyChipSelect(1),
+            SpiCall::ReadWriteBytes {
+                write: vec![0x9F, 0, 0],
+                read: true
+            }
+        ]
+    );
+
+    spi.push_response(vec![0xFF, 0xC2, 0x28]);
+    assert!(spi.complete());
+    // The next transfer starts before the client of the first one is called.
+    assert_eq!(spi.chip_select(), Some(2));
+    assert_eq!(
+        *radio_client.completed.borrow(),
+        [(vec![0x9F, 0, 0], Some(vec![0xFF, 0xC2, 0x28]), 3)]
+    );
+
+    assert!(spi.complete());
+    assert_eq!(*flash_client.completed.borrow(), [(vec![0x06], None, 1)]);
+    assert!(!spi.complete());
+}
+
+#[test]
+fn configure_applies_to_bus() {
+    let (spi, mux) = mux();
+    let (device, _) = device(mux, 3);
+
+    device.configure(
+        ClockPolarity::IdleHigh,
+        ClockPhase::SampleTrailing,
+        1_000_000,
+    );
+    assert_eq!(
+        spi.take_calls(),
+        [
+            SpiCall::SpecifyChipSelect(3),
+            SpiCall::SetPolarity(ClockPolarity::IdleHigh),
+            SpiCall::SetPhase(ClockPhase::SampleTrailing),
+            SpiCall::SetRate(1_000_000),
+        ]
+    );
+    assert_eq!(device.get_polarity(), ClockPolarity::IdleHigh);
+    assert_eq!(device.get_phase(), ClockPhase::SampleTrailing);
+    assert_eq!(device.get_rate(), 1_000_000);
+}
+
+#[test]
+fn mock_device_completes_transfers() {
+    let device = leak(MockSpiMasterDevice::new());
+    let client = leak(Client::default());
+    device.set_client(client);
+
+    device.configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading, 8_000_000);
+    device.read_write_bytes(buffer(&[1, 2]), Some(buffer(&[0; 2])), 2);
+    assert_eq!(
+        device.read_write_bytes(buffer(&[3]), None, 1),
+        ReturnCode::EBUSY
+    );
+    device.push_response(vec![7]);
+    assert!(device.complete());
+    assert_eq!(
+        *client.completed.borrow(),
+        [(vec![1, 2], Some(vec![7, 0]), 2)]
+    );
+    assert_eq!(device.get_rate(), 8_000_000);
+}
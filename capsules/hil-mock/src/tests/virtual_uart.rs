@@ -0,0 +1,214 @@
+use core::cell::RefCell;
+
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::uart::{self, Receive, Transmit};
+use kernel::ReturnCode;
+
+use crate::leak;
+use crate::tests::{buffer, deferred_caller};
+use crate::uart::{MockUart, UartCall};
+
+/// Records the callbacks of a UART client.
+#[derive(Default)]
+struct Client {
+    transmitted: RefCell<Vec<(Vec<u8>, ReturnCode)>>,
+    received: RefCell<Vec<(Vec<u8>, ReturnCode, uart::Error)>>,
+}
+
+impl uart::TransmitClient for Client {
+    fn transmitted_buffer(&self, tx_buffer: &'static mut [u8], tx_len: usize, rval: ReturnCode) {
+        self.transmitted
+            .borrow_mut()
+            .push((tx_buffer[..tx_len].to_vec(), rval));
+    }
+}
+
+impl uart::ReceiveClient for Client {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: ReturnCode,
+        error: uart::Error,
+    ) {
+        self.received
+            .borrow_mut()
+            .push((rx_buffer[..rx_len].to_vec(), rval, error));
+    }
+}
+
+fn mux() -> (
+    &'static MockUart<'static>,
+    &'static MuxUart<'static>,
+    &'static DynamicDeferredCall,
+) {
+    let uart = leak(MockUart::new());
+    let deferred_caller = deferred_caller(1);
+    let mux = leak(MuxUart::new(
+        uart,
+        buffer(&[0; 64]),
+        115200,
+        deferred_caller,
+    ));
+    mux.initialize_callback_handle(deferred_caller.register(mux).unwrap());
+    uart.set_transmit_client(mux);
+    uart.set_receive_client(mux);
+    mux.initialize();
+    (uart, mux, deferred_caller)
+}
+
+fn device(mux: &'static MuxUart<'static>) -> (&'static UartDevice<'static>, &'static Client) {
+    let device = leak(UartDevice::new(mux, true));
+    device.setup();
+    let client = leak(Client::default());
+    device.set_transmit_client(client);
+    device.set_receive_client(client);
+    (device, client)
+}
+
+#[test]
+fn transmissions_are_serialized() {
+    let (uart, mux, deferred_caller) = mux();
+    let (first, first_client) = device(mux);
+    let (second, second_client) = device(mux);
+    assert_eq!(
+        uart.take_calls(),
+        [UartCall::Configure { baud_rate: 115200 }]
+    );
+
+    assert_eq!(
+        first.transmit_buffer(buffer(b"first"), 5).0,
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        second.transmit_buffer(buffer(b"second"), 6).0,
+        ReturnCode::SUCCESS
+    );
+    // Transmissions start from a deferred call.
+    assert!(!uart.is_transmitting());
+    deferred_caller.call();
+    assert_eq!(uart.calls().len(), 1);
+
+    assert!(uart.complete_transmit());
+    assert!(uart.complete_transmit());
+    assert!(!uart.complete_transmit());
+    assert_eq!(uart.calls().len(), 2);
+
+    let output = uart.take_output();
+    assert!(output == b"firstsecond" || output == b"secondfirst");
+    assert_eq!(
+        *first_client.transmitted.borrow(),
+        [(b"first".to_vec(), ReturnCode::SUCCESS)]
+    );
+    assert_eq!(
+        *second_client.transmitted.borrow(),
+        [(b"second".to_vec(), ReturnCode::SUCCESS)]
+    );
+}
+
+#[test]
+fn transmit_while_transmitting_is_busy() {
+    let (uart, mux, deferred_caller) = mux();
+    let (device, client) = device(mux);
+
+    assert_eq!(
+        device.transmit_buffer(buffer(b"a"), 1).0,
+        ReturnCode::SUCCESS
+    );
+    let (rcode, returned) = device.transmit_buffer(buffer(b"b"), 1);
+    assert_eq!(rcode, ReturnCode::EBUSY);
+    assert_eq!(returned.unwrap(), b"b");
+
+    deferred_caller.call();
+    uart.complete_transmit();
+    assert_eq!(
+        *client.transmitted.borrow(),
+        [(b"a".to_vec(), ReturnCode::SUCCESS)]
+    );
+    assert_eq!(
+        device.transmit_buffer(buffer(b"c"), 1).0,
+        ReturnCode::SUCCESS
+    );
+}
+
+#[test]
+fn receptions_are_shared() {
+    let (uart, mux, _) = mux();
+    let (short, short_client) = device(mux);
+    let (long, long_client) = device(mux);
+    uart.take_calls();
+
+    assert_eq!(
+        short.receive_buffer(buffer(&[0; 2]), 2).0,
+        ReturnCode::SUCCESS
+    );
+    // A shorter reception is ongoing, so the mux keeps it and adds the
+    // longer one to it.
+    assert_eq!(
+        long.receive_buffer(buffer(&[0; 4]), 4).0,
+        ReturnCode::SUCCESS
+    );
+    assert_eq!(
+        uart.take_calls(),
+        [UartCall::ReceiveBuffer { len: 2 }, UartCall::ReceiveAbort]
+    );
+
+    // The aborted reception restarts for the shortest outstanding length.
+    assert!(uart.complete_receive());
+    assert_eq!(uart.take_calls(), [UartCall::ReceiveBuffer { len: 2 }]);
+
+    uart.push_input(b"abcd");
+    assert!(uart.complete_receive());
+    assert_eq!(
+        *short_client.received.borrow(),
+        [(b"ab".to_vec(), ReturnCode::SUCCESS, uart::Error::None)]
+    );
+    assert!(long_client.received.borrow().is_empty());
+
+    assert!(uart.complete_receive());
+    assert_eq!(
+        *long_client.received.borrow(),
+        [(b"abcd".to_vec(), ReturnCode::SUCCESS, uart::Error::None)]
+    );
+    assert!(!uart.is_receiving());
+}
+
+#[test]
+fn abort_cancels_reception_of_one_device() {
+    let (uart, mux, _) = mux();
+    let (aborting, aborting_client) = device(mux);
+    let (other, other_client) = device(mux);
+
+    aborting.receive_buffer(buffer(&[0; 4]), 4);
+    other.receive_buffer(buffer(&[0; 4]), 4);
+    uart.push_input(b"xy");
+    assert_eq!(aborting.receive_abort(), ReturnCode::EBUSY);
+    assert!(uart.complete_receive());
+    assert_eq!(
+        *aborting_client.received.borrow(),
+        [(b"xy".to_vec(), ReturnCode::ECANCEL, uart::Error::Aborted)]
+    );
+
+    // The other device keeps receiving.
+    uart.push_input(b"zw");
+    assert!(uart.complete_receive());
+    assert_eq!(
+        *other_client.received.borrow(),
+        [(b"xyzw".to_vec(), ReturnCode::SUCCESS, uart::Error::None)]
+    );
+}
+
+#[test]
+fn failed_transmission_is_reported() {
+    let (uart, mux, deferred_caller) = mux();
+    let (device, client) = device(mux);
+
+    uart.push_transmit_result(ReturnCode::FAIL);
+    assert_eq!(
+        device.transmit_buffer(buffer(b"a"), 1).0,
+        ReturnCode::SUCCESS
+    );
+    deferred_caller.call();
+    assert_eq!(*client.transmitted.borrow(), [(vec![], ReturnCode::FAIL)]);
+}
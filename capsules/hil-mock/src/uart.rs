@@ -0,0 +1,255 @@
+//! Mock UART.
+
+use core::cell::{Cell, RefCell};
+use core::cmp;
+use std::collections::VecDeque;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+use crate::calls::Calls;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UartCall {
+    Configure { baud_rate: u32 },
+    TransmitBuffer(Vec<u8>),
+    TransmitWord(u32),
+    TransmitAbort,
+    ReceiveBuffer { len: usize },
+    ReceiveWord,
+    ReceiveAbort,
+}
+
+/// A UART.
+///
+/// Transmissions stay pending until `complete_transmit()`. Receptions
+/// complete with `complete_receive()` once enough input, scripted with
+/// `push_input()`, is available.
+pub struct MockUart<'a> {
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_word: Cell<Option<u32>>,
+    tx_aborted: Cell<bool>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_aborted: Cell<bool>,
+    /// Return codes for the next transmissions, `SUCCESS` once empty.
+    tx_results: RefCell<VecDeque<ReturnCode>>,
+    /// Return codes for the next receptions, `SUCCESS` once empty.
+    rx_results: RefCell<VecDeque<ReturnCode>>,
+    input: RefCell<VecDeque<u8>>,
+    output: RefCell<Vec<u8>>,
+    calls: Calls<UartCall>,
+}
+
+impl<'a> MockUart<'a> {
+    pub fn new() -> MockUart<'a> {
+        MockUart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word: Cell::new(None),
+            tx_aborted: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_aborted: Cell::new(false),
+            tx_results: RefCell::new(VecDeque::new()),
+            rx_results: RefCell::new(VecDeque::new()),
+            input: RefCell::new(VecDeque::new()),
+            output: RefCell::new(Vec::new()),
+            calls: Calls::new(),
+        }
+    }
+
+    /// Make the next transmission fail with `result`.
+    pub fn push_transmit_result(&self, result: ReturnCode) {
+        self.tx_results.borrow_mut().push_back(result);
+    }
+
+    /// Make the next reception fail with `result`.
+    pub fn push_receive_result(&self, result: ReturnCode) {
+        self.rx_results.borrow_mut().push_back(result);
+    }
+
+    /// Make bytes available to receive, as if they arrived on the wire.
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes);
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.tx_buffer.is_some() || self.tx_word.get().is_some()
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.rx_buffer.is_some()
+    }
+
+    /// Finish the pending transmission. Returns whether there was one.
+    pub fn complete_transmit(&self) -> bool {
+        if let Some(word) = self.tx_word.take() {
+            self.output.borrow_mut().push(word as u8);
+            self.tx_client
+                .map(|client| client.transmitted_word(ReturnCode::SUCCESS));
+            return true;
+        }
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let (len, rcode) = if self.tx_aborted.replace(false) {
+                (0, ReturnCode::ECANCEL)
+            } else {
+                let len = self.tx_len.get();
+                self.output.borrow_mut().extend_from_slice(&buffer[..len]);
+                (len, ReturnCode::SUCCESS)
+            };
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, rcode));
+            true
+        })
+    }
+
+    /// Finish the pending reception if enough input is available, or if it
+    /// was aborted. Returns whether there was a reception to finish.
+    pub fn complete_receive(&self) -> bool {
+        let aborted = self.rx_aborted.get();
+        if self.rx_buffer.is_none() || (!aborted && self.input.borrow().len() < self.rx_len.get()) {
+            return false;
+        }
+        self.rx_aborted.set(false);
+        self.rx_buffer.take().map_or(false, |buffer| {
+            let len = cmp::min(self.rx_len.get(), self.input.borrow().len());
+            for (byte, input) in buffer.iter_mut().zip(self.input.borrow_mut().drain(..len)) {
+                *byte = input;
+            }
+            let (rcode, error) = if aborted {
+                (ReturnCode::ECANCEL, uart::Error::Aborted)
+            } else {
+                (ReturnCode::SUCCESS, uart::Error::None)
+            };
+            self.rx_client
+                .map(move |client| client.received_buffer(buffer, len, rcode, error));
+            true
+        })
+    }
+
+    /// Take the bytes transmitted so far.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.output.borrow_mut().split_off(0)
+    }
+
+    pub fn calls(&self) -> Vec<UartCall> {
+        self.calls.get()
+    }
+
+    pub fn take_calls(&self) -> Vec<UartCall> {
+        self.calls.take()
+    }
+}
+
+impl uart::Configure for MockUart<'_> {
+    fn configure(&self, params: uart::Parameters) -> ReturnCode {
+        self.calls.record(UartCall::Configure {
+            baud_rate: params.baud_rate,
+        });
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Transmit<'a> for MockUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let len = cmp::min(tx_len, tx_buffer.len());
+        self.calls
+            .record(UartCall::TransmitBuffer(tx_buffer[..len].to_vec()));
+        if self.is_transmitting() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+        match self.tx_results.borrow_mut().pop_front() {
+            Some(rcode) if rcode != ReturnCode::SUCCESS => (rcode, Some(tx_buffer)),
+            _ => {
+                self.tx_len.set(len);
+                self.tx_buffer.replace(tx_buffer);
+                (ReturnCode::SUCCESS, None)
+            }
+        }
+    }
+
+    fn transmit_word(&self, word: u32) -> ReturnCode {
+        self.calls.record(UartCall::TransmitWord(word));
+        if self.is_transmitting() {
+            return ReturnCode::EBUSY;
+        }
+        match self.tx_results.borrow_mut().pop_front() {
+            Some(rcode) if rcode != ReturnCode::SUCCESS => rcode,
+            _ => {
+                self.tx_word.set(Some(word));
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        self.calls.record(UartCall::TransmitAbort);
+        if self.tx_buffer.is_some() {
+            self.tx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for MockUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.calls.record(UartCall::ReceiveBuffer { len: rx_len });
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+        if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        match self.rx_results.borrow_mut().pop_front() {
+            Some(rcode) if rcode != ReturnCode::SUCCESS => (rcode, Some(rx_buffer)),
+            _ => {
+                self.rx_len.set(rx_len);
+                self.rx_buffer.replace(rx_buffer);
+                (ReturnCode::SUCCESS, None)
+            }
+        }
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        self.calls.record(UartCall::ReceiveWord);
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        self.calls.record(UartCall::ReceiveAbort);
+        if self.rx_buffer.is_some() {
+            self.rx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::UartData<'a> for MockUart<'a> {}
+impl<'a> uart::Uart<'a> for MockUart<'a> {}
@@ -0,0 +1,562 @@
+//! AES-128 encryption and decryption for applications.
+//!
+//! This capsule exposes an AES-128 engine to applications with the ECB, CTR,
+//! CBC and CCM* modes. Applications share the key, the IV or nonce, and the
+//! input with read-only allow buffers, and receive the output in a
+//! read-write allow buffer.
+//!
+//! The engine is shared between processes: one operation runs at a time, and
+//! operations of other processes are queued. Every operation loads the key
+//! and IV of the process that requested it, so a process can never encrypt
+//! or decrypt with the key of another process, and the key is overwritten in
+//! the engine once the operation completes.
+//!
+//! The block modes use an `AES128` engine that must be dedicated to this
+//! driver, such as `capsules::aes_software::Aes128Software`. CCM* uses an
+//! `AES128CCM` engine, which can be a `virtual_aes_ccm::VirtualAES128CCM`
+//! shared with the radio stack.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let aes_driver = static_init!(
+//!     capsules::aes::AesDriver<
+//!         'static,
+//!         Aes128Software<'static>,
+//!         VirtualAES128CCM<'static, Aes128Software<'static>>,
+//!     >,
+//!     capsules::aes::AesDriver::new(
+//!         aes,
+//!         ccm_client,
+//!         static_init!([u8; 128], [0; 128]),
+//!         static_init!([u8; 128], [0; 128]),
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! aes.set_client(aes_driver);
+//! ccm_client.set_client(aes_driver);
+//! ```
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Aes as usize;
+
+use core::cell::Cell;
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+    CCM_NONCE_LENGTH,
+};
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, ReturnCode, Upcall,
+};
+
+/// The largest MIC CCM* can produce.
+const MAX_MIC_LEN: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    Ecb,
+    Ctr,
+    Cbc,
+    Ccm,
+}
+
+impl Algorithm {
+    fn from_usize(algorithm: usize) -> Option<Algorithm> {
+        match algorithm {
+            0 => Some(Algorithm::Ecb),
+            1 => Some(Algorithm::Ctr),
+            2 => Some(Algorithm::Cbc),
+            3 => Some(Algorithm::Ccm),
+            _ => None,
+        }
+    }
+}
+
+pub struct AesDriver<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB, C: AES128CCM<'a>> {
+    aes: &'a A,
+    ccm: &'a C,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+
+    /// The length of the output of the operation in progress.
+    length: Cell<usize>,
+    /// Whether the CCM* operation in progress decrypts, and so checks a MIC.
+    ccm_decrypting: Cell<bool>,
+    buffer: TakeCell<'a, [u8]>,
+    ccm_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB, C: AES128CCM<'a>> AesDriver<'a, A, C> {
+    /// Creates the driver.
+    ///
+    /// The input of block mode operations is limited to the length of
+    /// `buffer`. The input of CCM* operations, and the MIC that encryption
+    /// appends, are limited to the length of `ccm_buffer`.
+    pub fn new(
+        aes: &'a A,
+        ccm: &'a C,
+        buffer: &'a mut [u8],
+        ccm_buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> AesDriver<'a, A, C> {
+        AesDriver {
+            aes,
+            ccm,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            length: Cell::new(0),
+            ccm_decrypting: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            ccm_buffer: TakeCell::new(ccm_buffer),
+        }
+    }
+
+    /// Starts the operation configured by `appid`.
+    fn start(&self, appid: AppId) -> Result<(), ReturnCode> {
+        let ret = self
+            .apps
+            .enter(appid, |app, _| {
+                let algorithm = app.algorithm.ok_or(ReturnCode::EINVAL)?;
+                let mut key = [0; AES128_KEY_SIZE];
+                let key_len = app.key.map_or(0, |slice| {
+                    if slice.len() == AES128_KEY_SIZE {
+                        key.copy_from_slice(slice);
+                    }
+                    slice.len()
+                });
+                if key_len != AES128_KEY_SIZE {
+                    return Err(ReturnCode::EINVAL);
+                }
+                let mut iv = [0; AES128_BLOCK_SIZE];
+                let iv_len = app.iv.map_or(0, |slice| {
+                    if slice.len() <= AES128_BLOCK_SIZE {
+                        iv[..slice.len()].copy_from_slice(slice);
+                    }
+                    slice.len()
+                });
+                let iv = if iv_len <= AES128_BLOCK_SIZE {
+                    &iv[..iv_len]
+                } else {
+                    &[]
+                };
+                let output_len = app.output.len();
+
+                app.input
+                    .map_or(None, |input| {
+                        Some(if algorithm == Algorithm::Ccm {
+                            self.start_ccm(app, &key, iv, input, output_len)
+                        } else {
+                            self.start_block_mode(app, algorithm, &key, iv, input, output_len)
+                        })
+                    })
+                    .unwrap_or(Err(ReturnCode::EINVAL))
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if ret.is_ok() {
+            self.appid.set(appid);
+        }
+        ret
+    }
+
+    fn start_block_mode(
+        &self,
+        app: &App,
+        algorithm: Algorithm,
+        key: &[u8],
+        iv: &[u8],
+        input: &[u8],
+        output_len: usize,
+    ) -> Result<(), ReturnCode> {
+        let len = input.len();
+        if len == 0 || len % AES128_BLOCK_SIZE != 0 {
+            return Err(ReturnCode::EINVAL);
+        }
+        if algorithm != Algorithm::Ecb && iv.len() != AES128_BLOCK_SIZE {
+            return Err(ReturnCode::EINVAL);
+        }
+        if output_len < len {
+            return Err(ReturnCode::ESIZE);
+        }
+        let buffer = self.buffer.take().ok_or(ReturnCode::EBUSY)?;
+        if buffer.len() < len {
+            self.buffer.replace(buffer);
+            return Err(ReturnCode::ESIZE);
+        }
+        buffer[..len].copy_from_slice(input);
+
+        self.aes.enable();
+        self.aes.set_key(key);
+        match algorithm {
+            Algorithm::Ecb => self.aes.set_mode_aes128ecb(app.encrypting),
+            Algorithm::Ctr => self.aes.set_mode_aes128ctr(app.encrypting),
+            _ => self.aes.set_mode_aes128cbc(app.encrypting),
+        }
+        if algorithm != Algorithm::Ecb {
+            self.aes.set_iv(iv);
+        }
+        self.aes.start_message();
+
+        self.length.set(len);
+        match self.aes.crypt(None, buffer, 0, len) {
+            None => Ok(()),
+            Some((rcode, _, buffer)) => {
+                self.clear_block_mode(buffer);
+                Err(rcode)
+            }
+        }
+    }
+
+    fn start_ccm(
+        &self,
+        app: &App,
+        key: &[u8],
+        nonce: &[u8],
+        input: &[u8],
+        output_len: usize,
+    ) -> Result<(), ReturnCode> {
+        if nonce.len() != CCM_NONCE_LENGTH {
+            return Err(ReturnCode::EINVAL);
+        }
+        // When decrypting, the input ends with the MIC to check.
+        let data_len = if app.encrypting {
+            input.len()
+        } else {
+            input
+                .len()
+                .checked_sub(app.mic_len)
+                .ok_or(ReturnCode::EINVAL)?
+        };
+        let m_len = data_len.checked_sub(app.a_len).ok_or(ReturnCode::EINVAL)?;
+        let len = data_len + app.mic_len;
+        if output_len < len {
+            return Err(ReturnCode::ESIZE);
+        }
+        let buffer = self.ccm_buffer.take().ok_or(ReturnCode::EBUSY)?;
+        if buffer.len() < len {
+            self.ccm_buffer.replace(buffer);
+            return Err(ReturnCode::ESIZE);
+        }
+        buffer[..input.len()].copy_from_slice(input);
+
+        self.ccm.set_key(key);
+        self.ccm.set_nonce(nonce);
+        self.length.set(len);
+        self.ccm_decrypting.set(!app.encrypting);
+        match self.ccm.crypt(
+            buffer,
+            0,
+            app.a_len,
+            m_len,
+            app.mic_len,
+            true,
+            app.encrypting,
+        ) {
+            (ReturnCode::SUCCESS, _) => Ok(()),
+            (rcode, buffer) => {
+                buffer.map(|buffer| self.clear_ccm(buffer));
+                Err(rcode)
+            }
+        }
+    }
+
+    /// Wipes the data and the key of the last block mode operation.
+    fn clear_block_mode(&self, buffer: &'a mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        self.buffer.replace(buffer);
+        self.aes.set_key(&[0; AES128_KEY_SIZE]);
+        self.aes.disable();
+    }
+
+    /// Wipes the data and the key of the last CCM* operation.
+    fn clear_ccm(&self, buffer: &'static mut [u8]) {
+        for byte in buffer.iter_mut() {
+            *byte = 0;
+        }
+        self.ccm_buffer.replace(buffer);
+        self.ccm.set_key(&[0; AES128_KEY_SIZE]);
+    }
+
+    /// Copies the output to the application that requested the operation
+    /// and calls its upcall.
+    fn complete(&self, result: ReturnCode, output: &[u8], tag_is_valid: bool) {
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let len = if result == ReturnCode::SUCCESS {
+                    app.output.mut_map_or(0, |dest| {
+                        let len = core::cmp::min(output.len(), dest.len());
+                        dest.as_mut()[..len].copy_from_slice(&output[..len]);
+                        len
+                    })
+                } else {
+                    0
+                };
+                app.callback
+                    .schedule(usize::from(result), len, tag_is_valid as usize);
+            });
+        });
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            // If an app is already running let it complete
+            if self.appid.is_some() {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                if app.pending {
+                    app.pending = false;
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            });
+            if let Some(appid) = pending {
+                if let Err(e) = self.start(appid) {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback.schedule(usize::from(e), 0, 0);
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB, C: AES128CCM<'a>>
+    symmetric_encryption::Client<'a> for AesDriver<'a, A, C>
+{
+    fn crypt_done(&'a self, _source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        let len = self.length.get();
+        self.complete(ReturnCode::SUCCESS, &dest[..len], false);
+        self.clear_block_mode(dest);
+        self.check_queue();
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB, C: AES128CCM<'a>>
+    symmetric_encryption::CCMClient for AesDriver<'a, A, C>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        if res == ReturnCode::SUCCESS && self.ccm_decrypting.get() && !tag_is_valid {
+            // The decrypted data can not be trusted, so it is wiped instead
+            // of being passed to the application.
+            self.clear_ccm(buf);
+            self.complete(ReturnCode::FAIL, &[], false);
+        } else {
+            let len = self.length.get();
+            self.complete(res, &buf[..len], tag_is_valid);
+            self.clear_ccm(buf);
+        }
+        self.check_queue();
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB, C: AES128CCM<'a>> Driver
+    for AesDriver<'a, A, C>
+{
+    /// Specify the buffer the output is written to.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer for the output. It must be at least as long as
+    ///        the input, plus the MIC for CCM* encryption.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.output);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Specify the key, the IV and the input.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer containing the 16 byte key.
+    /// - `1`: Allow a buffer containing the 16 byte IV or initial counter for
+    ///        CTR and CBC, or the 13 byte nonce for CCM*.
+    /// - `2`: Allow a buffer containing the input. For the block modes, its
+    ///        length must be a multiple of 16 bytes. For CCM*, it contains
+    ///        the additional authenticated data followed by the message, and
+    ///        when decrypting, followed by the MIC.
+    ///
+    /// The buffers should not be changed until the operation has completed.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => mem::swap(&mut slice, &mut app.key),
+                    1 => mem::swap(&mut slice, &mut app.iv),
+                    2 => mem::swap(&mut slice, &mut app.input),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::FAIL));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to AES events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: u32, length: u32, tag_is_valid: u32)`,
+    ///        where `length` is the length of the output and `tag_is_valid`
+    ///        tells whether the MIC checked by CCM* decryption is valid. If
+    ///        the MIC is not valid the operation fails with `FAIL`, and no
+    ///        output is written.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Configure and run AES operations.
+    ///
+    /// Operations are queued if another operation is in progress. Each
+    /// process can queue one operation.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the algorithm to `data1`: `0` for ECB, `1` for CTR, `2` for
+    ///        CBC and `3` for CCM*. Encrypts if `data2` is `1`, decrypts if
+    ///        it is `0`.
+    /// - `2`: Set the CCM* parameters: the length of the additional
+    ///        authenticated data at the start of the input to `data1`, and
+    ///        the length of the MIC to `data2`, an even number up to 16 other
+    ///        than 2. A MIC length of 0 only encrypts.
+    /// - `3`: Run the operation.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match Algorithm::from_usize(data1) {
+                Some(algorithm) if data2 <= 1 => self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.algorithm = Some(algorithm);
+                        app.encrypting = data2 == 1;
+                        CommandReturn::success()
+                    })
+                    .unwrap_or_else(|err| err.into()),
+                _ => CommandReturn::failure(ErrorCode::INVAL),
+            },
+
+            2 => {
+                if data2 > MAX_MIC_LEN || data2 % 2 != 0 || data2 == 2 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.a_len = data1;
+                        app.mic_len = data2;
+                        CommandReturn::success()
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            3 => {
+                if self.appid.is_none() {
+                    match self.start(appid) {
+                        Ok(()) => CommandReturn::success(),
+                        Err(e) => CommandReturn::from(e),
+                    }
+                } else {
+                    // Some app is using the engine, we must wait.
+                    self.apps
+                        .enter(appid, |app, _| {
+                            if app.pending {
+                                // No more room in the queue
+                                CommandReturn::failure(ErrorCode::NOMEM)
+                            } else {
+                                app.pending = true;
+                                CommandReturn::success()
+                            }
+                        })
+                        .unwrap_or_else(|err| err.into())
+                }
+            }
+
+            // default
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    pending: bool,
+    algorithm: Option<Algorithm>,
+    encrypting: bool,
+    /// Length of the CCM* additional authenticated data.
+    a_len: usize,
+    /// Length of the CCM* MIC.
+    mic_len: usize,
+    key: ReadOnlyAppSlice,
+    iv: ReadOnlyAppSlice,
+    input: ReadOnlyAppSlice,
+    output: ReadWriteAppSlice,
+}
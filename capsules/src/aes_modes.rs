@@ -0,0 +1,294 @@
+//! CTR and CBC modes on top of an ECB-only AES-128 block.
+//!
+//! Some AES peripherals can only encrypt or decrypt single blocks. `Aes128Modes`
+//! wraps such a block and implements the CTR and CBC modes on top of it, one
+//! block at a time, so that users of `AES128Ctr` and `AES128CBC` such as
+//! `virtual_aes_ccm` can use that hardware. ECB requests are passed through,
+//! block by block.
+//!
+//! The wrapper needs a buffer of one block to hand to the underlying
+//! engine. Unlike the `AES128` HIL allows, empty requests are rejected with
+//! `EINVAL`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE};
+//! # use capsules::aes_modes::Aes128Modes;
+//!
+//! let aes_block = static_init!([u8; AES128_BLOCK_SIZE], [0; AES128_BLOCK_SIZE]);
+//! let aes = static_init!(
+//!     Aes128Modes<'static, Ecb<'static>>,
+//!     Aes128Modes::new(&ecb, aes_block)
+//! );
+//! ecb.set_client(aes);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE,
+};
+use kernel::ReturnCode;
+
+type Block = [u8; AES128_BLOCK_SIZE];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Ecb,
+    Ctr,
+    Cbc,
+}
+
+pub struct Aes128Modes<'a, E: AES128<'a> + AES128ECB> {
+    ecb: &'a E,
+    client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+
+    /// IV or initial counter, as set by `set_iv()`.
+    iv: Cell<Block>,
+    /// IV or counter for the next block of the current message.
+    chain: Cell<Block>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+
+    /// Block handed to the underlying engine.
+    block: TakeCell<'a, [u8]>,
+    /// Input of the block in flight, needed to finish CTR and CBC.
+    input: Cell<Block>,
+
+    source: TakeCell<'a, [u8]>,
+    dest: TakeCell<'a, [u8]>,
+    start_index: Cell<usize>,
+    stop_index: Cell<usize>,
+    /// Index in `dest` of the block in flight.
+    current_index: Cell<usize>,
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> Aes128Modes<'a, E> {
+    pub fn new(ecb: &'a E, block: &'a mut [u8; AES128_BLOCK_SIZE]) -> Aes128Modes<'a, E> {
+        Aes128Modes {
+            ecb: ecb,
+            client: OptionalCell::empty(),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ecb),
+            encrypting: Cell::new(true),
+            block: TakeCell::new(block),
+            input: Cell::new([0; AES128_BLOCK_SIZE]),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            start_index: Cell::new(0),
+            stop_index: Cell::new(0),
+            current_index: Cell::new(0),
+        }
+    }
+
+    /// Hand the block at `current_index` to the underlying engine.
+    fn start_block(&'a self) -> ReturnCode {
+        let index = self.current_index.get();
+        let offset = index - self.start_index.get();
+        let mut input = [0; AES128_BLOCK_SIZE];
+        if self.source.is_some() {
+            self.source.map(|source| {
+                input.copy_from_slice(&source[offset..offset + AES128_BLOCK_SIZE]);
+            });
+        } else {
+            self.dest.map(|dest| {
+                input.copy_from_slice(&dest[index..index + AES128_BLOCK_SIZE]);
+            });
+        }
+        self.input.set(input);
+
+        let chain = self.chain.get();
+        let block = match self.block.take() {
+            Some(block) => block,
+            None => return ReturnCode::EBUSY,
+        };
+        let ecb_encrypting = match self.mode.get() {
+            Mode::Ecb => {
+                block.copy_from_slice(&input);
+                self.encrypting.get()
+            }
+            Mode::Ctr => {
+                block.copy_from_slice(&chain);
+                true
+            }
+            Mode::Cbc if self.encrypting.get() => {
+                for i in 0..AES128_BLOCK_SIZE {
+                    block[i] = input[i] ^ chain[i];
+                }
+                true
+            }
+            Mode::Cbc => {
+                block.copy_from_slice(&input);
+                false
+            }
+        };
+
+        self.ecb.set_mode_aes128ecb(ecb_encrypting);
+        self.ecb.start_message();
+        match self.ecb.crypt(None, block, 0, AES128_BLOCK_SIZE) {
+            None => ReturnCode::SUCCESS,
+            Some((rcode, _, block)) => {
+                self.block.replace(block);
+                rcode
+            }
+        }
+    }
+
+    /// Write the result of the block in flight to `dest`, and update the
+    /// chaining value.
+    fn finish_block(&self, block: &[u8]) {
+        let index = self.current_index.get();
+        let input = self.input.get();
+        let mut chain = self.chain.get();
+        let mut output = [0; AES128_BLOCK_SIZE];
+        match self.mode.get() {
+            Mode::Ecb => output.copy_from_slice(block),
+            Mode::Ctr => {
+                for i in 0..AES128_BLOCK_SIZE {
+                    output[i] = input[i] ^ block[i];
+                }
+                for byte in chain.iter_mut().rev() {
+                    *byte = byte.wrapping_add(1);
+                    if *byte != 0 {
+                        break;
+                    }
+                }
+            }
+            Mode::Cbc if self.encrypting.get() => {
+                output.copy_from_slice(block);
+                chain = output;
+            }
+            Mode::Cbc => {
+                for i in 0..AES128_BLOCK_SIZE {
+                    output[i] = block[i] ^ chain[i];
+                }
+                chain = input;
+            }
+        }
+        self.chain.set(chain);
+        self.dest.map(|dest| {
+            dest[index..index + AES128_BLOCK_SIZE].copy_from_slice(&output);
+        });
+        self.current_index.set(index + AES128_BLOCK_SIZE);
+    }
+
+    fn done(&self) {
+        let source = self.source.take();
+        self.dest.take().map(|dest| {
+            self.client
+                .map(move |client| client.crypt_done(source, dest));
+        });
+    }
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> symmetric_encryption::Client<'a> for Aes128Modes<'a, E> {
+    fn crypt_done(&'a self, _source: Option<&'a mut [u8]>, block: &'a mut [u8]) {
+        self.finish_block(block);
+        self.block.replace(block);
+
+        if self.current_index.get() < self.stop_index.get() {
+            if self.start_block() != ReturnCode::SUCCESS {
+                // The engine refused to continue: report the partial result.
+                self.done();
+            }
+        } else {
+            self.done();
+        }
+    }
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> AES128<'a> for Aes128Modes<'a, E> {
+    fn enable(&self) {
+        self.ecb.enable();
+    }
+
+    fn disable(&self) {
+        self.ecb.disable();
+    }
+
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        self.ecb.set_key(key)
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+        if iv.len() != AES128_BLOCK_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut block = [0; AES128_BLOCK_SIZE];
+        block.copy_from_slice(iv);
+        self.iv.set(block);
+        self.chain.set(block);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_message(&self) {
+        if self.dest.is_none() {
+            self.chain.set(self.iv.get());
+        }
+    }
+
+    fn crypt(
+        &'a self,
+        source: Option<&'a mut [u8]>,
+        dest: &'a mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+        if self.dest.is_some() {
+            return Some((ReturnCode::EBUSY, source, dest));
+        }
+        if start_index >= stop_index
+            || stop_index > dest.len()
+            || (stop_index - start_index) % AES128_BLOCK_SIZE != 0
+            || source
+                .as_ref()
+                .map_or(false, |source| source.len() != stop_index - start_index)
+        {
+            return Some((ReturnCode::EINVAL, source, dest));
+        }
+
+        self.start_index.set(start_index);
+        self.stop_index.set(stop_index);
+        self.current_index.set(start_index);
+        self.source.put(source);
+        self.dest.replace(dest);
+
+        let chain = self.chain.get();
+        let rcode = self.start_block();
+        if rcode == ReturnCode::SUCCESS {
+            None
+        } else {
+            self.chain.set(chain);
+            Some((rcode, self.source.take(), self.dest.take().unwrap()))
+        }
+    }
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> AES128ECB for Aes128Modes<'a, E> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.mode.set(Mode::Ecb);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> AES128Ctr for Aes128Modes<'a, E> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) {
+        self.mode.set(Mode::Ctr);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl<'a, E: AES128<'a> + AES128ECB> AES128CBC for Aes128Modes<'a, E> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) {
+        self.mode.set(Mode::Cbc);
+        self.encrypting.set(encrypting);
+    }
+}
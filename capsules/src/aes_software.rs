@@ -0,0 +1,390 @@
+//! Software implementation of AES-128.
+//!
+//! `Aes128Software` implements the `AES128` HIL with the ECB, CTR and CBC
+//! modes without any hardware support, so that AES users such as
+//! `virtual_aes_ccm` work on chips without an AES peripheral. Operations
+//! complete in a deferred call.
+//!
+//! This implementation uses lookup tables and is not hardened against
+//! timing side channels.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::aes_software::Aes128Software;
+//!
+//! let aes = static_init!(
+//!     Aes128Software<'static>,
+//!     Aes128Software::new(dynamic_deferred_caller)
+//! );
+//! aes.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(aes)
+//!         .expect("no deferred call slot available for software aes"),
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::ReturnCode;
+
+const ROUNDS: usize = 10;
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+#[rustfmt::skip]
+const INV_SBOX: [u8; 256] = [
+    0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+    0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+    0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+    0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+    0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+    0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+    0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+    0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+    0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+    0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+    0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+    0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+    0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+    0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+    0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+    0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+];
+
+const ROUND_CONSTANTS: [u8; ROUNDS] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+type Block = [u8; AES128_BLOCK_SIZE];
+type RoundKeys = [Block; ROUNDS + 1];
+
+fn expand_key(key: &[u8]) -> RoundKeys {
+    let mut round_keys = [[0; AES128_BLOCK_SIZE]; ROUNDS + 1];
+    round_keys[0].copy_from_slice(key);
+    for round in 1..=ROUNDS {
+        let previous = round_keys[round - 1];
+        let mut word = [previous[13], previous[14], previous[15], previous[12]];
+        for byte in word.iter_mut() {
+            *byte = SBOX[*byte as usize];
+        }
+        word[0] ^= ROUND_CONSTANTS[round - 1];
+        for i in 0..AES128_BLOCK_SIZE {
+            word[i % 4] ^= previous[i];
+            round_keys[round][i] = word[i % 4];
+        }
+    }
+    round_keys
+}
+
+/// Multiply by x in GF(2^8).
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = xtime(a);
+        b >>= 1;
+    }
+    product
+}
+
+fn add_round_key(state: &mut Block, round_key: &Block) {
+    for (byte, key) in state.iter_mut().zip(round_key.iter()) {
+        *byte ^= key;
+    }
+}
+
+/// Shift row `r` of the column-major state left by `r * step` columns.
+fn shift_rows(state: &mut Block, step: usize) {
+    let original = *state;
+    for row in 1..4 {
+        for column in 0..4 {
+            state[4 * column + row] = original[4 * ((column + row * step) % 4) + row];
+        }
+    }
+}
+
+fn encrypt_block(round_keys: &RoundKeys, state: &mut Block) {
+    add_round_key(state, &round_keys[0]);
+    for round in 1..=ROUNDS {
+        for byte in state.iter_mut() {
+            *byte = SBOX[*byte as usize];
+        }
+        shift_rows(state, 1);
+        if round != ROUNDS {
+            for column in state.chunks_mut(4) {
+                let all = column[0] ^ column[1] ^ column[2] ^ column[3];
+                let first = column[0];
+                for i in 0..4 {
+                    let next = if i == 3 { first } else { column[i + 1] };
+                    column[i] ^= all ^ xtime(column[i] ^ next);
+                }
+            }
+        }
+        add_round_key(state, &round_keys[round]);
+    }
+}
+
+fn decrypt_block(round_keys: &RoundKeys, state: &mut Block) {
+    add_round_key(state, &round_keys[ROUNDS]);
+    for round in (0..ROUNDS).rev() {
+        shift_rows(state, 3);
+        for byte in state.iter_mut() {
+            *byte = INV_SBOX[*byte as usize];
+        }
+        add_round_key(state, &round_keys[round]);
+        if round != 0 {
+            for column in state.chunks_mut(4) {
+                let c = [column[0], column[1], column[2], column[3]];
+                for i in 0..4 {
+                    column[i] = multiply(c[i], 0x0e)
+                        ^ multiply(c[(i + 1) % 4], 0x0b)
+                        ^ multiply(c[(i + 2) % 4], 0x0d)
+                        ^ multiply(c[(i + 3) % 4], 0x09);
+                }
+            }
+        }
+    }
+}
+
+/// Increment a big-endian counter block.
+fn increment(counter: &mut Block) {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Ecb,
+    Ctr,
+    Cbc,
+}
+
+pub struct Aes128Software<'a> {
+    client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+
+    round_keys: Cell<RoundKeys>,
+    /// IV or initial counter, as set by `set_iv()`.
+    iv: Cell<Block>,
+    /// IV or counter for the next block of the current message.
+    chain: Cell<Block>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+
+    source: TakeCell<'a, [u8]>,
+    dest: TakeCell<'a, [u8]>,
+    start_index: Cell<usize>,
+    stop_index: Cell<usize>,
+}
+
+impl<'a> Aes128Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> Aes128Software<'a> {
+        Aes128Software {
+            client: OptionalCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+            round_keys: Cell::new([[0; AES128_BLOCK_SIZE]; ROUNDS + 1]),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ecb),
+            encrypting: Cell::new(true),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            start_index: Cell::new(0),
+            stop_index: Cell::new(0),
+        }
+    }
+
+    /// Must be called with the handle returned when registering this engine
+    /// with the deferred caller, otherwise no operation ever completes.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Encrypt or decrypt `input` into `output` with the current mode,
+    /// updating the chaining value.
+    fn crypt_block(&self, input: &Block, output: &mut Block) {
+        let round_keys = self.round_keys.get();
+        let mut chain = self.chain.get();
+        match (self.mode.get(), self.encrypting.get()) {
+            (Mode::Ecb, true) => {
+                *output = *input;
+                encrypt_block(&round_keys, output);
+            }
+            (Mode::Ecb, false) => {
+                *output = *input;
+                decrypt_block(&round_keys, output);
+            }
+            (Mode::Ctr, _) => {
+                let mut keystream = chain;
+                encrypt_block(&round_keys, &mut keystream);
+                for i in 0..AES128_BLOCK_SIZE {
+                    output[i] = input[i] ^ keystream[i];
+                }
+                increment(&mut chain);
+            }
+            (Mode::Cbc, true) => {
+                for i in 0..AES128_BLOCK_SIZE {
+                    output[i] = input[i] ^ chain[i];
+                }
+                encrypt_block(&round_keys, output);
+                chain = *output;
+            }
+            (Mode::Cbc, false) => {
+                *output = *input;
+                decrypt_block(&round_keys, output);
+                for i in 0..AES128_BLOCK_SIZE {
+                    output[i] ^= chain[i];
+                }
+                chain = *input;
+            }
+        }
+        self.chain.set(chain);
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for Aes128Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if let Some(dest) = self.dest.take() {
+            let source = self.source.take();
+            let start_index = self.start_index.get();
+            let stop_index = self.stop_index.get();
+
+            for index in (start_index..stop_index).step_by(AES128_BLOCK_SIZE) {
+                let mut input = [0; AES128_BLOCK_SIZE];
+                match source {
+                    Some(ref source) => input.copy_from_slice(
+                        &source[index - start_index..index - start_index + AES128_BLOCK_SIZE],
+                    ),
+                    None => input.copy_from_slice(&dest[index..index + AES128_BLOCK_SIZE]),
+                }
+                let mut output = [0; AES128_BLOCK_SIZE];
+                self.crypt_block(&input, &mut output);
+                dest[index..index + AES128_BLOCK_SIZE].copy_from_slice(&output);
+            }
+
+            self.client
+                .map(move |client| client.crypt_done(source, dest));
+        }
+    }
+}
+
+impl<'a> AES128<'a> for Aes128Software<'a> {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        self.round_keys.set(expand_key(key));
+        ReturnCode::SUCCESS
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+        if iv.len() != AES128_BLOCK_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut block = [0; AES128_BLOCK_SIZE];
+        block.copy_from_slice(iv);
+        self.iv.set(block);
+        self.chain.set(block);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_message(&self) {
+        if self.dest.is_none() {
+            self.chain.set(self.iv.get());
+        }
+    }
+
+    fn crypt(
+        &'a self,
+        source: Option<&'a mut [u8]>,
+        dest: &'a mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+        if self.dest.is_some() {
+            return Some((ReturnCode::EBUSY, source, dest));
+        }
+        if start_index > stop_index
+            || stop_index > dest.len()
+            || (stop_index - start_index) % AES128_BLOCK_SIZE != 0
+            || source
+                .as_ref()
+                .map_or(false, |source| source.len() != stop_index - start_index)
+        {
+            return Some((ReturnCode::EINVAL, source, dest));
+        }
+
+        self.start_index.set(start_index);
+        self.stop_index.set(stop_index);
+        self.source.put(source);
+        self.dest.replace(dest);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        None
+    }
+}
+
+impl AES128ECB for Aes128Software<'_> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.mode.set(Mode::Ecb);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl AES128Ctr for Aes128Software<'_> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) {
+        self.mode.set(Mode::Ctr);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl AES128CBC for Aes128Software<'_> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) {
+        self.mode.set(Mode::Cbc);
+        self.encrypting.set(encrypting);
+    }
+}
@@ -0,0 +1,143 @@
+//! Persistent identities of applications.
+//!
+//! Capsules that keep data for applications across restarts, reboots and
+//! updates, such as `kv_driver`, `log_driver` and `filesystem_driver`, need a
+//! name for each application that stays the same and, ideally, that another
+//! application cannot claim. The package name in the TBF header stays the
+//! same, but any application can choose any package name.
+//!
+//! Instead, the board lists the applications it stores data for, and the name
+//! the data of each is stored under. An entry recognizes an application by:
+//!
+//! - Its package name (`AppKey::PackageName`). This stays the same when the
+//!   application is updated, so its data is kept. But the name is only as
+//!   trustworthy as the applications the board runs: use it on boards whose
+//!   `AppCredentialsChecker` requires credentials from trusted signers, or
+//!   that only run trusted applications.
+//! - A credential that the kernel checked with its `AppCredentialsChecker`
+//!   when the application was loaded (`AppKey::Credential`). No other
+//!   application can claim it, but a hash or signature only matches the exact
+//!   build it was made for, so each build needs its own entry. Entries for
+//!   several builds can share a name so that they share data.
+//!
+//! Applications that match no entry have no identity. Boards whose
+//! applications have no credentials, such as boards that use the
+//! `NullCredentialsChecker`, can instead create the table with
+//! `AppIdentities::with_package_name_fallback()`, which stores the data of
+//! applications that match no entry under their package name. As any
+//! application can choose any package name, this does not keep applications
+//! from accessing each other's data.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! use capsules::app_identity::{AppIdentities, AppIdentity, AppKey};
+//! use kernel::procs::TbfHeaderV2CredentialsType;
+//!
+//! let identities = static_init!(
+//!     AppIdentities,
+//!     AppIdentities::new(&[
+//!         AppIdentity {
+//!             app: AppKey::PackageName("org.tockos.sensors"),
+//!             name: "sensors",
+//!         },
+//!         AppIdentity {
+//!             app: AppKey::Credential {
+//!                 format: TbfHeaderV2CredentialsType::Sha256,
+//!                 data: &[0x9f, 0x86, 0xd0, /* ... */],
+//!             },
+//!             name: "blink",
+//!         },
+//!     ])
+//! );
+//! ```
+
+use kernel::procs::TbfHeaderV2CredentialsType;
+use kernel::{AppId, ErrorCode};
+
+/// How an entry of the table recognizes an application.
+pub enum AppKey {
+    /// Every build of the application with this package name.
+    PackageName(&'static str),
+    /// The build of the application with this accepted credential.
+    Credential {
+        /// The format of the credential.
+        format: TbfHeaderV2CredentialsType,
+        /// The credential data, e.g. the hash or signature, of the build.
+        data: &'static [u8],
+    },
+}
+
+impl AppKey {
+    fn matches(&self, appid: AppId) -> bool {
+        match self {
+            AppKey::PackageName(package_name) => appid.get_process_name() == Some(*package_name),
+            AppKey::Credential { format, data } => {
+                appid.get_credentials().map_or(false, |credentials| {
+                    credentials.format() == *format && credentials.data() == *data
+                })
+            }
+        }
+    }
+}
+
+/// An application the board stores data for.
+pub struct AppIdentity {
+    /// How the application is recognized.
+    pub app: AppKey,
+    /// The name the data of the application is stored under.
+    pub name: &'static str,
+}
+
+/// The table of applications the board stores data for.
+pub struct AppIdentities {
+    identities: &'static [AppIdentity],
+    package_name_fallback: bool,
+}
+
+impl AppIdentities {
+    pub const fn new(identities: &'static [AppIdentity]) -> AppIdentities {
+        AppIdentities {
+            identities,
+            package_name_fallback: false,
+        }
+    }
+
+    /// Like `new()`, but applications that match no entry are stored under
+    /// their package name. See the module documentation for when this is
+    /// appropriate.
+    pub const fn with_package_name_fallback(identities: &'static [AppIdentity]) -> AppIdentities {
+        AppIdentities {
+            identities,
+            package_name_fallback: true,
+        }
+    }
+
+    /// Returns the name the data of `appid` is stored under.
+    ///
+    /// Returns `NOSUPPORT` if the application matches no entry and there is
+    /// no fallback, and `SIZE` if the name is empty or longer than `max_len`
+    /// bytes.
+    pub fn name(&self, appid: AppId, max_len: usize) -> Result<&'static str, ErrorCode> {
+        let name = self
+            .identities
+            .iter()
+            .find(|identity| identity.app.matches(appid))
+            .map(|identity| identity.name)
+            .or_else(|| {
+                if self.package_name_fallback {
+                    appid.get_process_name()
+                } else {
+                    None
+                }
+            })
+            .ok_or(ErrorCode::NOSUPPORT)?;
+
+        if name.is_empty() || name.len() > max_len {
+            return Err(ErrorCode::SIZE);
+        }
+        Ok(name)
+    }
+}
@@ -0,0 +1,262 @@
+//! Watchdog for applications.
+//!
+//! The watchdog of the kernel (`kernel::watchdog::WatchDog`) is tickled by
+//! the kernel loop, so it only catches a hung kernel. This capsule lets
+//! applications register a deadline of their own: an application starts its
+//! watchdog with a timeout, and must then send a heartbeat before the timeout
+//! passes, every time. An application that misses its deadline is faulted,
+//! which applies its `FaultResponse`: with `FaultResponse::Restart`, its
+//! `ProcessRestartPolicy` decides whether it is restarted.
+//!
+//! If the process is not restarted, i.e. it is left faulted because its
+//! restart policy gave up on it or its fault response is to stop it, the
+//! watchdog escalates to a system reset by calling the reset function of the
+//! board, if it has one.
+//!
+//! Missed deadlines are counted per process, across restarts, and shown by
+//! the `list` and `kernel` commands of the process console.
+//!
+//! A restarted process starts without a watchdog, and must start it again.
+//! Processes stopped from the process console do not miss their deadline:
+//! their deadline is pushed back until they run again.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! struct ProcessMgmtCap;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessMgmtCap {}
+//!
+//! let watchdog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let app_watchdog = static_init!(
+//!     capsules::app_watchdog::AppWatchdog<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!         ProcessMgmtCap,
+//!     >,
+//!     capsules::app_watchdog::AppWatchdog::new(
+//!         watchdog_alarm,
+//!         board_kernel,
+//!         ProcessMgmtCap,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!         Some(|| unsafe { cortexm4::scb::reset() }),
+//!     )
+//! );
+//! watchdog_alarm.set_alarm_client(app_watchdog);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//! - `1`: Start the watchdog with a timeout of `data1` milliseconds, or
+//!        change its timeout. This also counts as a heartbeat.
+//! - `2`: Heartbeat: the next one is due `timeout` milliseconds from now.
+//! - `3`: Stop the watchdog.
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::AppWatchdog as usize;
+
+use core::cell::Cell;
+use core::convert::TryFrom;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::procs::State;
+use kernel::{AppId, CommandReturn, Driver, ErrorCode, Grant, Kernel};
+
+/// The deadline of a process, in ticks of the alarm.
+#[derive(Clone, Copy)]
+struct Deadline {
+    /// When the last heartbeat was sent.
+    heartbeat: u32,
+    timeout: u32,
+}
+
+impl Deadline {
+    /// The ticks left from `now` until the deadline, or 0 if it passed.
+    fn remaining<T: Ticks>(&self, now: T) -> u32 {
+        let elapsed = now.wrapping_sub(T::from(self.heartbeat)).into_u32();
+        self.timeout.saturating_sub(elapsed)
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    deadline: Option<Deadline>,
+}
+
+pub struct AppWatchdog<'a, A: Alarm<'a>, C: ProcessManagementCapability> {
+    alarm: &'a A,
+    kernel: &'static Kernel,
+    capability: C,
+    apps: Grant<App>,
+    /// Resets the system when a process that missed its deadline is not
+    /// restarted.
+    reset: Option<fn()>,
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> AppWatchdog<'a, A, C> {
+    pub fn new(
+        alarm: &'a A,
+        kernel: &'static Kernel,
+        capability: C,
+        grant: Grant<App>,
+        reset: Option<fn()>,
+    ) -> AppWatchdog<'a, A, C> {
+        AppWatchdog {
+            alarm,
+            kernel,
+            capability,
+            apps: grant,
+            reset,
+        }
+    }
+
+    /// Programs the alarm for the earliest deadline of all processes.
+    fn schedule(&self) {
+        let now = self.alarm.now();
+        let earliest: Cell<Option<u32>> = Cell::new(None);
+        self.apps.each(|app| {
+            if let Some(deadline) = app.deadline {
+                let remaining = deadline.remaining(now);
+                if earliest.get().map_or(true, |earliest| remaining < earliest) {
+                    earliest.set(Some(remaining));
+                }
+            }
+        });
+        match earliest.get() {
+            Some(remaining) => self.alarm.set_alarm(now, A::Ticks::from(remaining)),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Handles a process that missed its deadline: faults it, and resets the
+    /// system if it is not restarted.
+    fn missed(&self, appid: AppId) {
+        let stopped = Cell::new(false);
+        let escalate = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if process.appid() == appid {
+                    match process.get_state() {
+                        State::StoppedRunning | State::StoppedYielded => stopped.set(true),
+                        _ => {
+                            process.debug_watchdog_expired();
+                            process.set_fault_state();
+                            escalate.set(process.get_state() == State::Faulted);
+                        }
+                    }
+                }
+            });
+
+        if stopped.get() {
+            // The process cannot send heartbeats while it is stopped
+            let now = self.alarm.now().into_u32();
+            let _ = self.apps.enter(appid, |app, _| {
+                app.deadline
+                    .as_mut()
+                    .map(|deadline| deadline.heartbeat = now);
+            });
+        } else {
+            let _ = self.apps.enter(appid, |app, _| app.deadline = None);
+            if escalate.get() {
+                self.reset.map(|reset| reset());
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> time::AlarmClient for AppWatchdog<'a, A, C> {
+    fn alarm(&self) {
+        loop {
+            let now = self.alarm.now();
+            // Faulting a process frees its grant, so do it outside of `each`
+            let expired: Cell<Option<AppId>> = Cell::new(None);
+            self.apps.each(|app| {
+                if expired.get().is_none() {
+                    if let Some(deadline) = app.deadline {
+                        if deadline.remaining(now) == 0 {
+                            expired.set(Some(app.appid()));
+                        }
+                    }
+                }
+            });
+            match expired.get() {
+                Some(appid) => self.missed(appid),
+                None => break,
+            }
+        }
+        self.schedule();
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: ProcessManagementCapability> Driver for AppWatchdog<'a, A, C> {
+    /// Start, feed and stop the watchdog of the process.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start the watchdog with a timeout of `data1` milliseconds.
+    ///        Returns INVAL if the timeout is 0 or too long for the alarm.
+    /// - `2`: Send a heartbeat. Returns OFF if the watchdog is not started.
+    /// - `3`: Stop the watchdog.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> CommandReturn {
+        let now = self.alarm.now().into_u32();
+        let res = match command_num {
+            0 => return CommandReturn::success(),
+            1 => {
+                // Deadlines must stay within half a wrap of the alarm to be
+                // told apart from heartbeats.
+                let timeout = match u32::try_from(data1) {
+                    Ok(ms) if ms > 0 => A::ticks_from_ms(ms).into_u32(),
+                    _ => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                if timeout > A::Ticks::max_value().into_u32() / 2 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps.enter(appid, |app, _| {
+                    app.deadline = Some(Deadline {
+                        heartbeat: now,
+                        timeout,
+                    });
+                    Ok(())
+                })
+            }
+            2 => self
+                .apps
+                .enter(appid, |app, _| match app.deadline.as_mut() {
+                    Some(deadline) => {
+                        deadline.heartbeat = now;
+                        Ok(())
+                    }
+                    None => Err(ErrorCode::OFF),
+                }),
+            3 => self.apps.enter(appid, |app, _| {
+                app.deadline = None;
+                Ok(())
+            }),
+
+            // default
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(Ok(())) => {
+                self.schedule();
+                CommandReturn::success()
+            }
+            Ok(Err(e)) => CommandReturn::failure(e),
+            Err(err) => err.into(),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Bluetooth Low Energy connections for applications.
+//!
+//! This capsule lets a process act as a BLE peripheral: it advertises with
+//! connectable advertisements, accepts a connection from a central, and
+//! exposes a GATT service with up to `MAX_CHARACTERISTICS` characteristics
+//! to the central.
+//!
+//! The radio is used by one process at a time: the first process to set up a
+//! service or to advertise owns the driver until it exits.
+//!
+//! ### Allow system calls
+//!
+//! * ReadOnly 0: Advertising data, made of AD structures.
+//! * ReadOnly 1: Device name, the value of the GAP device name
+//!   characteristic.
+//! * ReadOnly 2: Value to set with command 3.
+//! * ReadWrite 0: Buffer for the value read with command 4.
+//!
+//! ### Subscribe system call
+//!
+//! * 0: Connection events. The callback signature is
+//!   `fn(event: u32, data: u32)`, where `event` is 0 when a central
+//!   connects, 1 when the connection ends, with the HCI error code of the
+//!   reason as `data`, and 2 when the central writes the value of the
+//!   characteristic with index `data`.
+//!
+//! ### Command system call
+//!
+//! * 0: Driver check.
+//! * 1: Set the 16-bit UUID of the service, and remove its characteristics.
+//! * 2: Add a characteristic with the 16-bit UUID `data1` and the GATT
+//!      properties `data2`. Returns the index of the characteristic.
+//! * 3: Set the value of characteristic `data1` from read-only buffer 2.
+//! * 4: Copy the value of characteristic `data1` to the read-write buffer,
+//!      and return its length.
+//! * 5: Start advertising every `data1` milliseconds, with the advertising
+//!      data and the device name buffers.
+//! * 6: Stop advertising, or disconnect from the central.
+//! * 7: Notify the central of the value of characteristic `data1`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ble_connection = static_init!(
+//!     capsules::ble::BleConnectionDriver<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ble::BleConnectionDriver::new(
+//!         link_layer,
+//!         gatt_server,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! gatt_server.set_client(ble_connection);
+//! ```
+
+use core::cmp;
+use core::mem;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::ble_advertising::{BleAdvertisementDriver, BleConfig, BleConnectionConfig};
+use kernel::hil::time::Alarm;
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, Upcall,
+};
+
+use super::gatt_server::{GattServer, GattServerClient, MAX_VALUE_LEN};
+use super::link_layer::{Connection, LinkLayer, MAX_ADVERTISING_DATA_LEN};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleConnection as usize;
+
+const EVENT_CONNECTED: usize = 0;
+const EVENT_DISCONNECTED: usize = 1;
+const EVENT_WRITTEN: usize = 2;
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    advertising_data: ReadOnlyAppSlice,
+    device_name: ReadOnlyAppSlice,
+    value: ReadOnlyAppSlice,
+    read_buffer: ReadWriteAppSlice,
+}
+
+pub struct BleConnectionDriver<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    link_layer: &'a LinkLayer<'a, B, A>,
+    gatt_server: &'a GattServer<'a, LinkLayer<'a, B, A>>,
+    apps: Grant<App>,
+    owner: OptionalCell<AppId>,
+}
+
+impl<'a, B, A> BleConnectionDriver<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    pub fn new(
+        link_layer: &'a LinkLayer<'a, B, A>,
+        gatt_server: &'a GattServer<'a, LinkLayer<'a, B, A>>,
+        grant: Grant<App>,
+    ) -> BleConnectionDriver<'a, B, A> {
+        BleConnectionDriver {
+            link_layer,
+            gatt_server,
+            apps: grant,
+            owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Make `appid` the owner of the driver, unless another process that is
+    /// still alive owns it.
+    fn claim(&self, appid: AppId) -> Result<(), ErrorCode> {
+        let owned_by_other = self.owner.map_or(false, |owner| {
+            *owner != appid && self.apps.enter(*owner, |_, _| ()).is_ok()
+        });
+        if owned_by_other {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.owner.set(appid);
+            Ok(())
+        }
+    }
+
+    fn start_advertising(&self, appid: AppId, interval_ms: u32) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                let advertising_data = app.advertising_data.map_or(Ok(()), |data| {
+                    let len = cmp::min(data.len(), MAX_ADVERTISING_DATA_LEN);
+                    self.link_layer.set_advertising_data(&data[..len])
+                });
+                let device_name = app.device_name.map_or(Ok(()), |name| {
+                    let len = cmp::min(name.len(), MAX_VALUE_LEN);
+                    self.gatt_server.set_device_name(&name[..len])
+                });
+                advertising_data.and(device_name)
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.link_layer.start_advertising(interval_ms)
+    }
+
+    fn upcall(&self, event: usize, data: usize) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |app, _| {
+                app.callback.schedule(event, data, 0);
+            });
+        });
+    }
+}
+
+impl<'a, B, A> GattServerClient for BleConnectionDriver<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    fn connected(&self) {
+        self.upcall(EVENT_CONNECTED, 0);
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.upcall(EVENT_DISCONNECTED, reason as usize);
+    }
+
+    fn value_written(&self, index: usize) {
+        self.upcall(EVENT_WRITTEN, index);
+    }
+}
+
+impl<'a, B, A> Driver for BleConnectionDriver<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    /// Share the advertising data, the device name and values.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Advertising data.
+    /// - `1`: Device name.
+    /// - `2`: Value to set with command 3.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => mem::swap(&mut slice, &mut app.advertising_data),
+                    1 => mem::swap(&mut slice, &mut app.device_name),
+                    2 => mem::swap(&mut slice, &mut app.value),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::FAIL));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Share the buffer for the values read with command 4.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer for values.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.read_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to connection events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Connections, disconnections and writes of the central.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Set up the service, advertise and exchange values.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the service UUID.
+    /// - `2`: Add a characteristic with UUID `data1` and properties `data2`.
+    /// - `3`: Set the value of characteristic `data1`.
+    /// - `4`: Read the value of characteristic `data1`.
+    /// - `5`: Start advertising every `data1` milliseconds.
+    /// - `6`: Stop advertising, or disconnect.
+    /// - `7`: Notify the value of characteristic `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if let Err(e) = self.claim(appid) {
+            return CommandReturn::failure(e);
+        }
+
+        let res = match command_num {
+            1 => self.gatt_server.set_service(data1 as u16),
+
+            2 => {
+                return match self
+                    .gatt_server
+                    .add_characteristic(data1 as u16, data2 as u8)
+                {
+                    Ok(index) => CommandReturn::success_u32(index as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            3 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.value.map_or(Err(ErrorCode::RESERVE), |value| {
+                        self.gatt_server.set_value(data1, value)
+                    })
+                })
+                .unwrap_or_else(|err| Err(err.into())),
+
+            4 => {
+                let mut value = [0; MAX_VALUE_LEN];
+                return match self.gatt_server.value(data1, &mut value) {
+                    Ok(len) => self
+                        .apps
+                        .enter(appid, |app, _| {
+                            app.read_buffer.mut_map_or(
+                                CommandReturn::failure(ErrorCode::RESERVE),
+                                |buffer| {
+                                    let len = cmp::min(len, buffer.len());
+                                    buffer[..len].copy_from_slice(&value[..len]);
+                                    CommandReturn::success_u32(len as u32)
+                                },
+                            )
+                        })
+                        .unwrap_or_else(|err| err.into()),
+                    Err(e) => CommandReturn::failure(e),
+                };
+            }
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+            // The advertising interval is at least 20 ms.
+            5 => self.start_advertising(appid, cmp::max(20, data1 as u32)),
+
+            6 => {
+                if self.link_layer.is_connected() {
+                    self.link_layer.disconnect()
+                } else {
+                    self.link_layer.stop_advertising()
+                }
+            }
+
+            7 => self.gatt_server.notify(data1),
+
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
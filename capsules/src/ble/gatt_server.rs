@@ -0,0 +1,666 @@
+//! A minimal GATT server over a link layer connection.
+//!
+//! The attribute database holds the GAP service with the device name, and
+//! one primary service with up to `MAX_CHARACTERISTICS` characteristics,
+//! identified by 16-bit UUIDs. Characteristic values are stored in the
+//! server, and are up to `MAX_VALUE_LEN` bytes long. Characteristics that
+//! support notifications have a client characteristic configuration
+//! descriptor, with which the central enables them.
+//!
+//! The handles of the attributes are fixed:
+//!
+//! ```text
+//! 1          GAP service declaration
+//! 2          Device name characteristic declaration
+//! 3          Device name value
+//! 4          Service declaration
+//! 5 + 3 * i  Declaration of characteristic i
+//! 6 + 3 * i  Value of characteristic i
+//! 7 + 3 * i  Client characteristic configuration of characteristic i
+//! ```
+//!
+//! The server supports the ATT requests to discover services,
+//! characteristics and descriptors, to read and write attributes, and sends
+//! notifications. The ATT MTU is the default of 23 bytes.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::ErrorCode;
+
+use super::link_layer::{Connection, ConnectionClient, MAX_FRAME_LEN};
+
+pub const ATT_MTU: usize = 23;
+pub const MAX_CHARACTERISTICS: usize = 4;
+/// The longest characteristic value, which fits in a notification.
+pub const MAX_VALUE_LEN: usize = ATT_MTU - 3;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part G], section 3.3.1.1
+pub const PROPERTY_READ: u8 = 0x02;
+pub const PROPERTY_WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+pub const PROPERTY_WRITE: u8 = 0x08;
+pub const PROPERTY_NOTIFY: u8 = 0x10;
+
+const PRIMARY_SERVICE_UUID: u16 = 0x2800;
+const SECONDARY_SERVICE_UUID: u16 = 0x2801;
+const CHARACTERISTIC_UUID: u16 = 0x2803;
+const CLIENT_CONFIGURATION_UUID: u16 = 0x2902;
+const GAP_SERVICE_UUID: u16 = 0x1800;
+const DEVICE_NAME_UUID: u16 = 0x2a00;
+
+const GAP_SERVICE_HANDLE: u16 = 1;
+const DEVICE_NAME_DECLARATION_HANDLE: u16 = 2;
+const DEVICE_NAME_HANDLE: u16 = 3;
+const SERVICE_HANDLE: u16 = 4;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part A], section 3.1
+const L2CAP_HEADER_LEN: usize = 4;
+const ATT_CID: u16 = 0x0004;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.8
+const ERROR_RSP: u8 = 0x01;
+const EXCHANGE_MTU_REQ: u8 = 0x02;
+const EXCHANGE_MTU_RSP: u8 = 0x03;
+const FIND_INFORMATION_REQ: u8 = 0x04;
+const FIND_INFORMATION_RSP: u8 = 0x05;
+const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+const READ_BY_TYPE_REQ: u8 = 0x08;
+const READ_BY_TYPE_RSP: u8 = 0x09;
+const READ_REQ: u8 = 0x0a;
+const READ_RSP: u8 = 0x0b;
+const READ_BLOB_REQ: u8 = 0x0c;
+const READ_BLOB_RSP: u8 = 0x0d;
+const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+const WRITE_REQ: u8 = 0x12;
+const WRITE_RSP: u8 = 0x13;
+const HANDLE_VALUE_NTF: u8 = 0x1b;
+const WRITE_CMD: u8 = 0x52;
+const COMMAND_FLAG: u8 = 0x40;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.1.1
+const INVALID_HANDLE: u8 = 0x01;
+const READ_NOT_PERMITTED: u8 = 0x02;
+const WRITE_NOT_PERMITTED: u8 = 0x03;
+const INVALID_PDU: u8 = 0x04;
+const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+const INVALID_OFFSET: u8 = 0x07;
+const ATTRIBUTE_NOT_FOUND: u8 = 0x0a;
+const INVALID_ATTRIBUTE_VALUE_LENGTH: u8 = 0x0d;
+const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+
+/// The handle of the value of characteristic `index`.
+pub const fn value_handle(index: usize) -> u16 {
+    6 + 3 * index as u16
+}
+
+/// Implement this trait and use `set_client()` to be notified of
+/// connections and of values written by the central.
+pub trait GattServerClient {
+    fn connected(&self);
+
+    /// The connection ended, with the HCI error code `reason`.
+    fn disconnected(&self, reason: u8);
+
+    /// The central wrote the value of characteristic `index`.
+    fn value_written(&self, index: usize);
+}
+
+#[derive(Clone, Copy)]
+enum Attribute {
+    Service(u16),
+    CharacteristicDeclaration {
+        properties: u8,
+        value_handle: u16,
+        uuid: u16,
+    },
+    DeviceName,
+    Value(usize),
+    ClientConfiguration(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Characteristic {
+    uuid: u16,
+    properties: u8,
+    value: [u8; MAX_VALUE_LEN],
+    len: usize,
+    notifications: bool,
+}
+
+impl Characteristic {
+    const fn empty() -> Characteristic {
+        Characteristic {
+            uuid: 0,
+            properties: 0,
+            value: [0; MAX_VALUE_LEN],
+            len: 0,
+            notifications: false,
+        }
+    }
+}
+
+pub struct GattServer<'a, C: Connection<'a>> {
+    connection: &'a C,
+    client: OptionalCell<&'a dyn GattServerClient>,
+    device_name: Cell<[u8; MAX_VALUE_LEN]>,
+    device_name_len: Cell<usize>,
+    service: OptionalCell<u16>,
+    characteristics: MapCell<[Characteristic; MAX_CHARACTERISTICS]>,
+    characteristics_len: Cell<usize>,
+    /// A response that could not be sent yet, because the connection was
+    /// sending a notification.
+    pending: Cell<[u8; MAX_FRAME_LEN]>,
+    pending_len: OptionalCell<usize>,
+}
+
+impl<'a, C: Connection<'a>> GattServer<'a, C> {
+    pub fn new(connection: &'a C) -> GattServer<'a, C> {
+        GattServer {
+            connection,
+            client: OptionalCell::empty(),
+            device_name: Cell::new([0; MAX_VALUE_LEN]),
+            device_name_len: Cell::new(0),
+            service: OptionalCell::empty(),
+            characteristics: MapCell::new([Characteristic::empty(); MAX_CHARACTERISTICS]),
+            characteristics_len: Cell::new(0),
+            pending: Cell::new([0; MAX_FRAME_LEN]),
+            pending_len: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn GattServerClient) {
+        self.client.set(client);
+    }
+
+    pub fn set_device_name(&self, name: &[u8]) -> Result<(), ErrorCode> {
+        if name.len() > MAX_VALUE_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut device_name = [0; MAX_VALUE_LEN];
+        device_name[..name.len()].copy_from_slice(name);
+        self.device_name.set(device_name);
+        self.device_name_len.set(name.len());
+        Ok(())
+    }
+
+    /// Set the UUID of the service, and remove its characteristics. Returns
+    /// `BUSY` while connected.
+    pub fn set_service(&self, uuid: u16) -> Result<(), ErrorCode> {
+        if self.connection.is_connected() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.service.set(uuid);
+        self.characteristics_len.set(0);
+        Ok(())
+    }
+
+    /// Add a characteristic with `properties`, a combination of the
+    /// `PROPERTY_*` flags, to the service. Returns the index of the
+    /// characteristic.
+    pub fn add_characteristic(&self, uuid: u16, properties: u8) -> Result<usize, ErrorCode> {
+        if self.connection.is_connected() {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.service.is_none() {
+            return Err(ErrorCode::RESERVE);
+        }
+        let index = self.characteristics_len.get();
+        if index == MAX_CHARACTERISTICS {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.characteristics.map(|characteristics| {
+            characteristics[index] = Characteristic {
+                uuid,
+                properties,
+                ..Characteristic::empty()
+            }
+        });
+        self.characteristics_len.set(index + 1);
+        Ok(index)
+    }
+
+    pub fn set_value(&self, index: usize, value: &[u8]) -> Result<(), ErrorCode> {
+        if index >= self.characteristics_len.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.characteristics.map(|characteristics| {
+            characteristics[index].value[..value.len()].copy_from_slice(value);
+            characteristics[index].len = value.len();
+        });
+        Ok(())
+    }
+
+    /// Copy the value of characteristic `index` into `value`, and return its
+    /// length.
+    pub fn value(&self, index: usize, value: &mut [u8]) -> Result<usize, ErrorCode> {
+        let characteristic = self.characteristic(index).ok_or(ErrorCode::INVAL)?;
+        if value.len() < characteristic.len {
+            return Err(ErrorCode::SIZE);
+        }
+        value[..characteristic.len].copy_from_slice(&characteristic.value[..characteristic.len]);
+        Ok(characteristic.len)
+    }
+
+    /// Notify the central of the value of characteristic `index`. Returns
+    /// `RESERVE` if the central did not enable notifications, and `BUSY` if
+    /// the previous notification or response is still being sent.
+    pub fn notify(&self, index: usize) -> Result<(), ErrorCode> {
+        let characteristic = self.characteristic(index).ok_or(ErrorCode::INVAL)?;
+        if !self.connection.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        if !characteristic.notifications {
+            return Err(ErrorCode::RESERVE);
+        }
+        if self.pending_len.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut pdu = [0; ATT_MTU];
+        pdu[0] = HANDLE_VALUE_NTF;
+        pdu[1..3].copy_from_slice(&value_handle(index).to_le_bytes());
+        pdu[3..3 + characteristic.len].copy_from_slice(&characteristic.value[..characteristic.len]);
+        let (frame, frame_len) = self.frame(&pdu[..3 + characteristic.len]);
+        self.connection.send(&frame[..frame_len])
+    }
+
+    fn characteristic(&self, index: usize) -> Option<Characteristic> {
+        if index < self.characteristics_len.get() {
+            self.characteristics
+                .map(|characteristics| characteristics[index])
+        } else {
+            None
+        }
+    }
+
+    fn last_handle(&self) -> u16 {
+        if self.service.is_some() {
+            SERVICE_HANDLE + 3 * self.characteristics_len.get() as u16
+        } else {
+            DEVICE_NAME_HANDLE
+        }
+    }
+
+    fn attribute(&self, handle: u16) -> Option<Attribute> {
+        match handle {
+            GAP_SERVICE_HANDLE => Some(Attribute::Service(GAP_SERVICE_UUID)),
+            DEVICE_NAME_DECLARATION_HANDLE => Some(Attribute::CharacteristicDeclaration {
+                properties: PROPERTY_READ,
+                value_handle: DEVICE_NAME_HANDLE,
+                uuid: DEVICE_NAME_UUID,
+            }),
+            DEVICE_NAME_HANDLE => Some(Attribute::DeviceName),
+            SERVICE_HANDLE => self.service.map(|uuid| Attribute::Service(*uuid)),
+            _ if handle > SERVICE_HANDLE && handle <= self.last_handle() => {
+                let index = (handle - SERVICE_HANDLE - 1) as usize / 3;
+                let characteristic = self.characteristic(index)?;
+                match (handle - SERVICE_HANDLE - 1) % 3 {
+                    0 => Some(Attribute::CharacteristicDeclaration {
+                        properties: characteristic.properties,
+                        value_handle: value_handle(index),
+                        uuid: characteristic.uuid,
+                    }),
+                    1 => Some(Attribute::Value(index)),
+                    _ if characteristic.properties & PROPERTY_NOTIFY != 0 => {
+                        Some(Attribute::ClientConfiguration(index))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn attribute_type(&self, attribute: Attribute) -> u16 {
+        match attribute {
+            Attribute::Service(_) => PRIMARY_SERVICE_UUID,
+            Attribute::CharacteristicDeclaration { .. } => CHARACTERISTIC_UUID,
+            Attribute::DeviceName => DEVICE_NAME_UUID,
+            Attribute::Value(index) => self.characteristic(index).map_or(0, |c| c.uuid),
+            Attribute::ClientConfiguration(_) => CLIENT_CONFIGURATION_UUID,
+        }
+    }
+
+    /// The last handle of the service declared at `handle`.
+    fn group_end(&self, handle: u16) -> u16 {
+        if handle == GAP_SERVICE_HANDLE {
+            DEVICE_NAME_HANDLE
+        } else {
+            self.last_handle()
+        }
+    }
+
+    /// Copy the value of `attribute` into `value`, and return its length, or
+    /// the ATT error code.
+    fn read(&self, attribute: Attribute, value: &mut [u8; MAX_VALUE_LEN]) -> Result<usize, u8> {
+        match attribute {
+            Attribute::Service(uuid) => {
+                value[..2].copy_from_slice(&uuid.to_le_bytes());
+                Ok(2)
+            }
+            Attribute::CharacteristicDeclaration {
+                properties,
+                value_handle,
+                uuid,
+            } => {
+                value[0] = properties;
+                value[1..3].copy_from_slice(&value_handle.to_le_bytes());
+                value[3..5].copy_from_slice(&uuid.to_le_bytes());
+                Ok(5)
+            }
+            Attribute::DeviceName => {
+                value.copy_from_slice(&self.device_name.get());
+                Ok(self.device_name_len.get())
+            }
+            Attribute::Value(index) => {
+                let characteristic = self.characteristic(index).ok_or(INVALID_HANDLE)?;
+                if characteristic.properties & PROPERTY_READ == 0 {
+                    return Err(READ_NOT_PERMITTED);
+                }
+                value.copy_from_slice(&characteristic.value);
+                Ok(characteristic.len)
+            }
+            Attribute::ClientConfiguration(index) => {
+                let characteristic = self.characteristic(index).ok_or(INVALID_HANDLE)?;
+                value[..2].copy_from_slice(&(characteristic.notifications as u16).to_le_bytes());
+                Ok(2)
+            }
+        }
+    }
+
+    /// Write `value` to the attribute at `handle`. Returns the index of the
+    /// characteristic if its value was written.
+    fn write(&self, handle: u16, value: &[u8]) -> Result<Option<usize>, u8> {
+        match self.attribute(handle).ok_or(INVALID_HANDLE)? {
+            Attribute::Value(index) => {
+                let properties = self.characteristic(index).map_or(0, |c| c.properties);
+                if properties & (PROPERTY_WRITE | PROPERTY_WRITE_WITHOUT_RESPONSE) == 0 {
+                    return Err(WRITE_NOT_PERMITTED);
+                }
+                if value.len() > MAX_VALUE_LEN {
+                    return Err(INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                self.characteristics.map(|characteristics| {
+                    characteristics[index].value[..value.len()].copy_from_slice(value);
+                    characteristics[index].len = value.len();
+                });
+                Ok(Some(index))
+            }
+            Attribute::ClientConfiguration(index) => {
+                if value.len() != 2 {
+                    return Err(INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                self.characteristics.map(|characteristics| {
+                    characteristics[index].notifications = value[0] & 1 != 0;
+                });
+                Ok(None)
+            }
+            _ => Err(WRITE_NOT_PERMITTED),
+        }
+    }
+
+    fn reset_client_configurations(&self) {
+        self.pending_len.clear();
+        self.characteristics.map(|characteristics| {
+            for characteristic in characteristics.iter_mut() {
+                characteristic.notifications = false;
+            }
+        });
+    }
+
+    /// Check the handle range of a request.
+    fn handle_range(&self, request: &[u8]) -> Result<(u16, u16), (u16, u8)> {
+        let start = u16::from_le_bytes([request[1], request[2]]);
+        let end = u16::from_le_bytes([request[3], request[4]]);
+        if start == 0 || start > end {
+            Err((start, INVALID_HANDLE))
+        } else {
+            Ok((start, cmp::min(end, self.last_handle())))
+        }
+    }
+
+    /// Answer the ATT `request` into `response`. Returns the length of the
+    /// response, and the index of the characteristic written by the request,
+    /// or the handle and the ATT error code of an error response.
+    fn respond(
+        &self,
+        request: &[u8],
+        response: &mut [u8; ATT_MTU],
+    ) -> Result<(usize, Option<usize>), (u16, u8)> {
+        let u16_at = |i: usize| u16::from_le_bytes([request[i], request[i + 1]]);
+        let mut value = [0; MAX_VALUE_LEN];
+        match request[0] {
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.2
+            EXCHANGE_MTU_REQ if request.len() == 3 => {
+                response[0] = EXCHANGE_MTU_RSP;
+                response[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+                Ok((3, None))
+            }
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.3
+            FIND_INFORMATION_REQ if request.len() == 5 => {
+                let (start, end) = self.handle_range(request)?;
+                response[0] = FIND_INFORMATION_RSP;
+                // Handles and 16-bit UUIDs.
+                response[1] = 0x01;
+                let mut len = 2;
+                for handle in start..=end {
+                    if len + 4 > ATT_MTU {
+                        break;
+                    }
+                    if let Some(attribute) = self.attribute(handle) {
+                        response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                        response[len + 2..len + 4]
+                            .copy_from_slice(&self.attribute_type(attribute).to_le_bytes());
+                        len += 4;
+                    }
+                }
+                if len == 2 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                Ok((len, None))
+            }
+
+            FIND_BY_TYPE_VALUE_REQ if request.len() >= 7 => {
+                let (start, end) = self.handle_range(request)?;
+                let attribute_type = u16_at(5);
+                response[0] = FIND_BY_TYPE_VALUE_RSP;
+                let mut len = 1;
+                for handle in start..=end {
+                    if len + 4 > ATT_MTU {
+                        break;
+                    }
+                    let found = self.attribute(handle).map_or(false, |attribute| {
+                        self.attribute_type(attribute) == attribute_type
+                            && self
+                                .read(attribute, &mut value)
+                                .map_or(false, |value_len| value[..value_len] == request[7..])
+                    });
+                    if found {
+                        let group_end = match attribute_type {
+                            PRIMARY_SERVICE_UUID => self.group_end(handle),
+                            _ => handle,
+                        };
+                        response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                        response[len + 2..len + 4].copy_from_slice(&group_end.to_le_bytes());
+                        len += 4;
+                    }
+                }
+                if len == 1 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                Ok((len, None))
+            }
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.4
+            READ_BY_TYPE_REQ if request.len() == 7 || request.len() == 21 => {
+                let (start, end) = self.handle_range(request)?;
+                // Only 16-bit UUIDs are in the database.
+                if request.len() == 21 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                let attribute_type = u16_at(5);
+                response[0] = READ_BY_TYPE_RSP;
+                let mut len = 2;
+                let mut pair_len = 0;
+                for handle in start..=end {
+                    let attribute = match self.attribute(handle) {
+                        Some(attribute) if self.attribute_type(attribute) == attribute_type => {
+                            attribute
+                        }
+                        _ => continue,
+                    };
+                    let value_len = match self.read(attribute, &mut value) {
+                        Ok(value_len) => cmp::min(value_len, ATT_MTU - 4),
+                        Err(error) if pair_len == 0 => return Err((handle, error)),
+                        Err(_) => break,
+                    };
+                    if pair_len == 0 {
+                        pair_len = 2 + value_len;
+                    } else if pair_len != 2 + value_len || len + pair_len > ATT_MTU {
+                        break;
+                    }
+                    response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    response[len + 2..len + pair_len].copy_from_slice(&value[..value_len]);
+                    len += pair_len;
+                }
+                if pair_len == 0 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                response[1] = pair_len as u8;
+                Ok((len, None))
+            }
+
+            READ_REQ | READ_BLOB_REQ => {
+                let blob = request[0] == READ_BLOB_REQ;
+                if request.len() != if blob { 5 } else { 3 } {
+                    return Err((0, INVALID_PDU));
+                }
+                let handle = u16_at(1);
+                let offset = if blob { u16_at(3) as usize } else { 0 };
+                let attribute = self.attribute(handle).ok_or((handle, INVALID_HANDLE))?;
+                let value_len = self
+                    .read(attribute, &mut value)
+                    .map_err(|error| (handle, error))?;
+                if offset > value_len {
+                    return Err((handle, INVALID_OFFSET));
+                }
+                let len = cmp::min(value_len - offset, ATT_MTU - 1);
+                response[0] = if blob { READ_BLOB_RSP } else { READ_RSP };
+                response[1..1 + len].copy_from_slice(&value[offset..offset + len]);
+                Ok((1 + len, None))
+            }
+
+            READ_BY_GROUP_TYPE_REQ if request.len() == 7 || request.len() == 21 => {
+                let (start, end) = self.handle_range(request)?;
+                let group_type = if request.len() == 7 { u16_at(5) } else { 0 };
+                match group_type {
+                    PRIMARY_SERVICE_UUID => {}
+                    SECONDARY_SERVICE_UUID => return Err((start, ATTRIBUTE_NOT_FOUND)),
+                    _ => return Err((start, UNSUPPORTED_GROUP_TYPE)),
+                }
+                response[0] = READ_BY_GROUP_TYPE_RSP;
+                // Handle, end group handle and 16-bit UUID.
+                response[1] = 6;
+                let mut len = 2;
+                for handle in start..=end {
+                    if len + 6 > ATT_MTU {
+                        break;
+                    }
+                    if let Some(Attribute::Service(uuid)) = self.attribute(handle) {
+                        response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                        response[len + 2..len + 4]
+                            .copy_from_slice(&self.group_end(handle).to_le_bytes());
+                        response[len + 4..len + 6].copy_from_slice(&uuid.to_le_bytes());
+                        len += 6;
+                    }
+                }
+                if len == 2 {
+                    return Err((start, ATTRIBUTE_NOT_FOUND));
+                }
+                Ok((len, None))
+            }
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part F], section 3.4.5
+            WRITE_REQ | WRITE_CMD if request.len() >= 3 => {
+                let handle = u16_at(1);
+                let written = self
+                    .write(handle, &request[3..])
+                    .map_err(|error| (handle, error))?;
+                response[0] = WRITE_RSP;
+                Ok((1, written))
+            }
+
+            _ => Err((0, REQUEST_NOT_SUPPORTED)),
+        }
+    }
+
+    /// Prepend the L2CAP header of the ATT channel to `pdu`.
+    fn frame(&self, pdu: &[u8]) -> ([u8; MAX_FRAME_LEN], usize) {
+        let mut frame = [0; MAX_FRAME_LEN];
+        frame[..2].copy_from_slice(&(pdu.len() as u16).to_le_bytes());
+        frame[2..4].copy_from_slice(&ATT_CID.to_le_bytes());
+        frame[L2CAP_HEADER_LEN..L2CAP_HEADER_LEN + pdu.len()].copy_from_slice(pdu);
+        (frame, L2CAP_HEADER_LEN + pdu.len())
+    }
+
+    fn receive_att(&self, request: &[u8]) {
+        let opcode = request[0];
+        let mut response = [0; ATT_MTU];
+        let (len, written) = match self.respond(request, &mut response) {
+            Ok(result) => result,
+            Err((handle, error)) => {
+                response[0] = ERROR_RSP;
+                response[1] = opcode;
+                response[2..4].copy_from_slice(&handle.to_le_bytes());
+                response[4] = error;
+                (5, None)
+            }
+        };
+
+        // Commands have no response, even when they fail.
+        if opcode & COMMAND_FLAG == 0 {
+            let (frame, frame_len) = self.frame(&response[..len]);
+            if self.connection.send(&frame[..frame_len]) == Err(ErrorCode::BUSY) {
+                self.pending.set(frame);
+                self.pending_len.set(frame_len);
+            }
+        }
+        written.map(|index| {
+            self.client.map(|client| client.value_written(index));
+        });
+    }
+}
+
+impl<'a, C: Connection<'a>> ConnectionClient for GattServer<'a, C> {
+    fn connected(&self) {
+        self.reset_client_configurations();
+        self.client.map(|client| client.connected());
+    }
+
+    fn disconnected(&self, reason: u8) {
+        self.reset_client_configurations();
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    fn received(&self, frame: &[u8]) {
+        if frame.len() <= L2CAP_HEADER_LEN {
+            return;
+        }
+        let len = u16::from_le_bytes([frame[0], frame[1]]) as usize;
+        let cid = u16::from_le_bytes([frame[2], frame[3]]);
+        if cid == ATT_CID && len == frame.len() - L2CAP_HEADER_LEN {
+            self.receive_att(&frame[L2CAP_HEADER_LEN..]);
+        }
+    }
+
+    fn send_done(&self) {
+        self.pending_len.take().map(|len| {
+            let _ = self.connection.send(&self.pending.get()[..len]);
+        });
+    }
+}
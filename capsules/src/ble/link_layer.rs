@@ -0,0 +1,922 @@
+//! Bluetooth Low Energy link layer connections, in the peripheral role.
+//!
+//! The link layer advertises with connectable undirected advertisements
+//! (`ADV_IND`) on the three advertising channels, and listens for a
+//! connection request (`CONNECT_IND`) after each of them. Once a central
+//! connects, the link layer follows the connection events of the central:
+//!
+//! - At each connection event, it listens on the data channel picked with
+//!   channel selection algorithm #1, and answers the packet of the central.
+//!   The anchor point of the events is synchronized on the packets of the
+//!   central, and the receive window is widened by the sleep clock accuracy.
+//! - Packets are acknowledged with the SN and NESN bits of their header, and
+//!   packets that were not acknowledged are retransmitted.
+//! - The connection is lost if no packet is received for the supervision
+//!   timeout.
+//! - The control procedures for connection parameter updates, channel map
+//!   updates, termination, feature exchange, version exchange and ping are
+//!   supported. Other procedures are answered with `LL_UNKNOWN_RSP`.
+//!
+//! L2CAP frames are exchanged with the client of the connection, usually a
+//! `GattServer`. With the default ATT MTU of 23 bytes, frames fit in a single
+//! data channel PDU, so frames are not fragmented.
+//!
+//! The response to a packet of the central is transmitted from the receive
+//! callback, so the radio and the interrupt latency must allow to transmit
+//! it within the inter frame space of 150 µs.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let link_layer = static_init!(
+//!     capsules::ble::link_layer::LinkLayer<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ble::link_layer::LinkLayer::new(
+//!         radio,
+//!         alarm,
+//!         static_init!([u8; capsules::ble::link_layer::BUFFER_LEN], [0; capsules::ble::link_layer::BUFFER_LEN]),
+//!     )
+//! );
+//! radio.set_receive_client(link_layer);
+//! radio.set_transmit_client(link_layer);
+//! alarm.set_alarm_client(link_layer);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::ble_advertising::{
+    self, BleAdvertisementDriver, BleConfig, BleConnectionConfig, RadioChannel,
+};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks};
+use kernel::{ErrorCode, ReturnCode};
+
+/// The length of the buffer of the link layer, which holds advertising PDUs
+/// and data channel PDUs.
+pub const BUFFER_LEN: usize = 39;
+/// The largest L2CAP frame, i.e. the largest payload of data channel PDUs.
+pub const MAX_FRAME_LEN: usize = 27;
+/// The largest advertising data.
+pub const MAX_ADVERTISING_DATA_LEN: usize = 31;
+pub const ADDRESS_LEN: usize = 6;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 2, Part D], section 1.3 List of Error Codes
+pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+pub const LOCAL_HOST_TERMINATED: u8 = 0x16;
+pub const CONNECTION_TIMEOUT: u8 = 0x08;
+pub const INSTANT_PASSED: u8 = 0x28;
+pub const FAILED_TO_ESTABLISH: u8 = 0x3e;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+const ADV_IND: u8 = 0b0000;
+const CONNECT_IND: u8 = 0b0101;
+const ADV_HEADER_TXADD: u8 = 1 << 6;
+const ADV_HEADER_RXADD: u8 = 1 << 7;
+const CONNECT_IND_LEN: usize = 34;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4
+const LLID_CONTINUATION: u8 = 0b01;
+const LLID_START: u8 = 0b10;
+const LLID_CONTROL: u8 = 0b11;
+const LLID_MASK: u8 = 0b11;
+const HEADER_NESN: u8 = 1 << 2;
+const HEADER_SN: u8 = 1 << 3;
+
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.4.2
+const LL_CONNECTION_UPDATE_IND: u8 = 0x00;
+const LL_CHANNEL_MAP_IND: u8 = 0x01;
+const LL_TERMINATE_IND: u8 = 0x02;
+const LL_UNKNOWN_RSP: u8 = 0x07;
+const LL_FEATURE_REQ: u8 = 0x08;
+const LL_FEATURE_RSP: u8 = 0x09;
+const LL_VERSION_IND: u8 = 0x0c;
+const LL_PING_REQ: u8 = 0x12;
+const LL_PING_RSP: u8 = 0x13;
+
+// LE Ping is the only supported optional feature.
+const FEATURES: u8 = 1 << 4;
+// Version 4.2 of the specification, and no company identifier.
+const VERSION: u8 = 0x08;
+const COMPANY_ID: u16 = 0xffff;
+
+const DATA_CHANNELS: u8 = 37;
+// The time after the end of the CONNECT_IND at which the transmit window
+// starts, without the offset.
+const TRANSMIT_WINDOW_DELAY_US: u32 = 1250;
+// The time to listen for a connection request after advertising on a channel.
+const CONNECT_LISTEN_US: u32 = 1000;
+// The time a receive window stays open after the expected anchor point, long
+// enough to receive a packet of the longest length.
+const RECEIVE_MARGIN_US: u32 = 1250;
+// The sleep clock accuracy of both devices, in ppm, used to widen receive
+// windows.
+const CLOCK_ACCURACY_PPM: u32 = 500;
+const UNIT_US: u32 = 1250;
+
+/// Implement this trait and use `set_connection_client()` to be notified
+/// of connections and to receive L2CAP frames.
+pub trait ConnectionClient {
+    /// A central connected.
+    fn connected(&self);
+
+    /// The connection ended, with the HCI error code `reason`, e.g.
+    /// `REMOTE_USER_TERMINATED` or `CONNECTION_TIMEOUT`.
+    fn disconnected(&self, reason: u8);
+
+    /// An L2CAP frame was received.
+    fn received(&self, frame: &[u8]);
+
+    /// The frame passed to `send()` was acknowledged by the central, and
+    /// another frame can be sent.
+    fn send_done(&self);
+}
+
+/// A connection over which L2CAP frames are exchanged.
+pub trait Connection<'a> {
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient);
+
+    /// Send an L2CAP frame of up to `MAX_FRAME_LEN` bytes. Returns `OFF` if
+    /// there is no connection, and `BUSY` if the previous frame was not
+    /// acknowledged yet.
+    fn send(&self, frame: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Terminate the connection. `disconnected()` is called once the
+    /// central acknowledged the termination.
+    fn disconnect(&self) -> Result<(), ErrorCode>;
+
+    fn is_connected(&self) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting for the next advertising event.
+    AdvertisingIdle,
+    /// Transmitting an advertisement on the channel.
+    Advertising(RadioChannel),
+    /// Listening for a connection request after advertising on the channel.
+    AdvertisingListen(RadioChannel),
+    /// Connected, waiting for the next connection event.
+    ConnectionIdle,
+    /// Listening for the packet of the central in a connection event.
+    ConnectionListen,
+    /// Transmitting the response to the central.
+    ConnectionRespond,
+}
+
+/// The PDU transmitted last, until it is acknowledged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pdu {
+    Empty,
+    Frame,
+    Control(u8),
+}
+
+/// Control PDUs to transmit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ControlPdu {
+    Terminate(u8),
+    UnknownResponse(u8),
+    FeatureResponse,
+    Version,
+    PingResponse,
+}
+
+#[derive(Clone, Copy)]
+struct ConnectionParameters {
+    access_address: u32,
+    crc_init: u32,
+    /// In units of 1.25 ms.
+    window_size: u8,
+    /// In units of 1.25 ms.
+    window_offset: u16,
+    /// In units of 1.25 ms.
+    interval: u16,
+    /// In units of 10 ms.
+    timeout: u16,
+    channel_map: [u8; 5],
+    hop: u8,
+}
+
+impl ConnectionParameters {
+    const fn empty() -> ConnectionParameters {
+        ConnectionParameters {
+            access_address: 0,
+            crc_init: 0,
+            window_size: 0,
+            window_offset: 0,
+            interval: 0,
+            timeout: 0,
+            channel_map: [0; 5],
+            hop: 0,
+        }
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.3.1
+    fn from_connect_ind(lldata: &[u8]) -> Option<ConnectionParameters> {
+        let u16_at = |i: usize| u16::from_le_bytes([lldata[i], lldata[i + 1]]);
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&lldata[16..21]);
+        let parameters = ConnectionParameters {
+            access_address: u32::from_le_bytes([lldata[0], lldata[1], lldata[2], lldata[3]]),
+            crc_init: u32::from_le_bytes([lldata[4], lldata[5], lldata[6], 0]),
+            window_size: lldata[7],
+            window_offset: u16_at(8),
+            interval: u16_at(10),
+            timeout: u16_at(14),
+            channel_map,
+            hop: lldata[21] & 0x1f,
+        };
+        if parameters.valid() && (5..=16).contains(&parameters.hop) {
+            Some(parameters)
+        } else {
+            None
+        }
+    }
+
+    fn valid(&self) -> bool {
+        (6..=3200).contains(&self.interval)
+            && (10..=3200).contains(&self.timeout)
+            && self.window_size >= 1
+            && self.used_channels() >= 2
+    }
+
+    fn used_channels(&self) -> u8 {
+        (0..DATA_CHANNELS).filter(|&i| self.uses_channel(i)).count() as u8
+    }
+
+    fn uses_channel(&self, channel: u8) -> bool {
+        self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.8.2
+    fn remap(&self, unmapped_channel: u8) -> u8 {
+        if self.uses_channel(unmapped_channel) {
+            return unmapped_channel;
+        }
+        let index = unmapped_channel % self.used_channels();
+        (0..DATA_CHANNELS)
+            .filter(|&i| self.uses_channel(i))
+            .nth(index as usize)
+            .unwrap_or(0)
+    }
+}
+
+pub struct LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    radio: &'a B,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn ConnectionClient>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+
+    // Advertising
+    address: Cell<[u8; ADDRESS_LEN]>,
+    advertising_data: Cell<[u8; MAX_ADVERTISING_DATA_LEN]>,
+    advertising_data_len: Cell<usize>,
+    advertising: Cell<bool>,
+    advertising_interval_ms: Cell<u32>,
+    random_nonce: Cell<u32>,
+
+    // Connection
+    parameters: Cell<ConnectionParameters>,
+    parameters_update: Cell<Option<(ConnectionParameters, u16)>>,
+    channel_map_update: Cell<Option<([u8; 5], u16)>>,
+    channel: Cell<RadioChannel>,
+    unmapped_channel: Cell<u8>,
+    event_counter: Cell<u16>,
+    anchor: Cell<A::Ticks>,
+    window_start: Cell<A::Ticks>,
+    window_end: Cell<A::Ticks>,
+    last_received: Cell<A::Ticks>,
+    synchronized: Cell<bool>,
+    transmit_sequence: Cell<bool>,
+    next_expected_sequence: Cell<bool>,
+    unacknowledged: OptionalCell<Pdu>,
+    control: OptionalCell<ControlPdu>,
+    version_sent: Cell<bool>,
+    terminated: OptionalCell<u8>,
+    frame: Cell<[u8; MAX_FRAME_LEN]>,
+    frame_len: OptionalCell<usize>,
+}
+
+impl<'a, B, A> LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    pub fn new(radio: &'a B, alarm: &'a A, buffer: &'static mut [u8]) -> LinkLayer<'a, B, A> {
+        LinkLayer {
+            radio,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            address: Cell::new([0; ADDRESS_LEN]),
+            advertising_data: Cell::new([0; MAX_ADVERTISING_DATA_LEN]),
+            advertising_data_len: Cell::new(0),
+            advertising: Cell::new(false),
+            advertising_interval_ms: Cell::new(100),
+            // Just use any non-zero starting value by default
+            random_nonce: Cell::new(0xdeadbeef),
+            parameters: Cell::new(ConnectionParameters::empty()),
+            parameters_update: Cell::new(None),
+            channel_map_update: Cell::new(None),
+            channel: Cell::new(RadioChannel::DataChannel0),
+            unmapped_channel: Cell::new(0),
+            event_counter: Cell::new(0),
+            anchor: Cell::new(A::Ticks::from(0)),
+            window_start: Cell::new(A::Ticks::from(0)),
+            window_end: Cell::new(A::Ticks::from(0)),
+            last_received: Cell::new(A::Ticks::from(0)),
+            synchronized: Cell::new(false),
+            transmit_sequence: Cell::new(false),
+            next_expected_sequence: Cell::new(false),
+            unacknowledged: OptionalCell::empty(),
+            control: OptionalCell::empty(),
+            version_sent: Cell::new(false),
+            terminated: OptionalCell::empty(),
+            frame: Cell::new([0; MAX_FRAME_LEN]),
+            frame_len: OptionalCell::empty(),
+        }
+    }
+
+    /// Set the static random device address, with the least significant
+    /// byte first. The two most significant bits must be set.
+    pub fn set_address(&self, address: [u8; ADDRESS_LEN]) {
+        self.address.set(address);
+    }
+
+    /// Set the advertising data, made of AD structures.
+    pub fn set_advertising_data(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        if data.len() > MAX_ADVERTISING_DATA_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut advertising_data = [0; MAX_ADVERTISING_DATA_LEN];
+        advertising_data[..data.len()].copy_from_slice(data);
+        self.advertising_data.set(advertising_data);
+        self.advertising_data_len.set(data.len());
+        Ok(())
+    }
+
+    pub fn set_tx_power(&self, power: u8) -> ReturnCode {
+        self.radio.set_tx_power(power)
+    }
+
+    /// Start advertising every `interval_ms` milliseconds, plus a random
+    /// delay of up to 10 ms, until a central connects. Returns `BUSY` if
+    /// connected.
+    pub fn start_advertising(&self, interval_ms: u32) -> Result<(), ErrorCode> {
+        self.advertising_interval_ms.set(interval_ms);
+        match self.state.get() {
+            State::Idle => {
+                self.advertising.set(true);
+                self.random_nonce.set(self.alarm.now().into_u32() | 1);
+                self.state.set(State::AdvertisingIdle);
+                self.alarm.set_alarm(self.alarm.now(), A::Ticks::from(0));
+                Ok(())
+            }
+            State::AdvertisingIdle | State::Advertising(_) | State::AdvertisingListen(_) => {
+                self.advertising.set(true);
+                Ok(())
+            }
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    /// Stop advertising. An advertising event in progress completes first.
+    pub fn stop_advertising(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::AdvertisingIdle => {
+                self.advertising.set(false);
+                self.alarm.disarm();
+                self.state.set(State::Idle);
+                Ok(())
+            }
+            State::Advertising(_) | State::AdvertisingListen(_) => {
+                self.advertising.set(false);
+                Ok(())
+            }
+            State::Idle => Err(ErrorCode::ALREADY),
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    pub fn is_advertising(&self) -> bool {
+        self.advertising.get()
+    }
+
+    fn ticks(&self, us: u32) -> A::Ticks {
+        let frequency = A::Frequency::frequency() as u64;
+        A::Ticks::from((us as u64 * frequency / 1_000_000) as u32)
+    }
+
+    fn micros(&self, ticks: A::Ticks) -> u32 {
+        let frequency = A::Frequency::frequency() as u64;
+        (ticks.into_u32() as u64 * 1_000_000 / frequency) as u32
+    }
+
+    // Uses the [Xorshift](https://en.wikipedia.org/wiki/Xorshift) algorithm
+    // to produce the pseudo-random advertising delay.
+    fn random_nonce(&self) -> u32 {
+        let mut nonce = self.random_nonce.get();
+        nonce ^= nonce << 13;
+        nonce ^= nonce >> 17;
+        nonce ^= nonce << 5;
+        self.random_nonce.set(nonce);
+        nonce
+    }
+
+    fn advertise(&self, channel: RadioChannel) {
+        self.buffer.take().map(|buffer| {
+            let data_len = self.advertising_data_len.get();
+            buffer[0] = ADV_IND | ADV_HEADER_TXADD;
+            buffer[1] = (ADDRESS_LEN + data_len) as u8;
+            buffer[2..2 + ADDRESS_LEN].copy_from_slice(&self.address.get());
+            buffer[2 + ADDRESS_LEN..2 + ADDRESS_LEN + data_len]
+                .copy_from_slice(&self.advertising_data.get()[..data_len]);
+            self.state.set(State::Advertising(channel));
+            self.radio
+                .transmit_advertisement(buffer, 2 + ADDRESS_LEN + data_len, channel);
+        });
+    }
+
+    /// Advertise on the next channel, or wait for the next advertising event
+    /// after advertising on the last one.
+    fn next_advertising_channel(&self, channel: RadioChannel) {
+        if !self.advertising.get() {
+            self.state.set(State::Idle);
+            return;
+        }
+        match channel {
+            RadioChannel::AdvertisingChannel37 => {
+                self.advertise(RadioChannel::AdvertisingChannel38)
+            }
+            RadioChannel::AdvertisingChannel38 => {
+                self.advertise(RadioChannel::AdvertisingChannel39)
+            }
+            _ => {
+                // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+                let delay_ms = self.random_nonce() % 10;
+                self.state.set(State::AdvertisingIdle);
+                self.alarm.set_alarm(
+                    self.alarm.now(),
+                    self.ticks((self.advertising_interval_ms.get() + delay_ms) * 1000),
+                );
+            }
+        }
+    }
+
+    fn receive_connect_ind(&self, channel: RadioChannel, buf: &[u8], len: usize) {
+        let valid = len >= 2 + CONNECT_IND_LEN
+            && buf[0] & 0x0f == CONNECT_IND
+            && buf[0] & ADV_HEADER_RXADD != 0
+            && buf[1] as usize == CONNECT_IND_LEN
+            && buf[8..14] == self.address.get();
+        let parameters = if valid {
+            ConnectionParameters::from_connect_ind(&buf[14..2 + CONNECT_IND_LEN])
+        } else {
+            None
+        };
+        match parameters {
+            Some(parameters) => {
+                self.alarm.disarm();
+                self.connect(parameters);
+            }
+            // Keep listening until the end of the listening window.
+            None => self.radio.receive_advertisement(channel),
+        }
+    }
+
+    fn connect(&self, parameters: ConnectionParameters) {
+        self.advertising.set(false);
+        self.radio
+            .set_access_address(parameters.access_address, parameters.crc_init);
+        self.parameters.set(parameters);
+        self.unmapped_channel.set(0);
+        self.event_counter.set(0);
+        self.synchronized.set(false);
+        self.transmit_sequence.set(false);
+        self.next_expected_sequence.set(false);
+        self.unacknowledged.clear();
+        self.control.clear();
+        self.parameters_update.set(None);
+        self.channel_map_update.set(None);
+        self.version_sent.set(false);
+        self.terminated.clear();
+        self.frame_len.clear();
+
+        // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.3
+        let now = self.alarm.now();
+        let start = now.wrapping_add(
+            self.ticks(TRANSMIT_WINDOW_DELAY_US + parameters.window_offset as u32 * UNIT_US),
+        );
+        self.last_received.set(now);
+        self.open_transmit_window(start, parameters.window_size);
+        self.next_channel();
+        self.state.set(State::ConnectionIdle);
+        self.alarm.set_alarm(now, start.wrapping_sub(now));
+        self.client.map(|client| client.connected());
+    }
+
+    /// The first packet of the central is sent in the transmit window, which
+    /// starts at `start` and lasts `size` units of 1.25 ms.
+    fn open_transmit_window(&self, start: A::Ticks, size: u8) {
+        self.anchor.set(start);
+        self.window_start.set(start);
+        self.window_end
+            .set(start.wrapping_add(self.ticks(size as u32 * UNIT_US + RECEIVE_MARGIN_US)));
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.8.2
+    fn next_channel(&self) {
+        let parameters = self.parameters.get();
+        let unmapped_channel = (self.unmapped_channel.get() + parameters.hop) % DATA_CHANNELS;
+        self.unmapped_channel.set(unmapped_channel);
+        self.channel.set(
+            RadioChannel::from_channel_index(parameters.remap(unmapped_channel) as u32)
+                .unwrap_or(RadioChannel::DataChannel0),
+        );
+    }
+
+    /// Schedule the connection event after the current one.
+    fn next_connection_event(&self) {
+        let mut parameters = self.parameters.get();
+        let previous_anchor = self.anchor.get();
+        let anchor = previous_anchor.wrapping_add(self.ticks(parameters.interval as u32 * UNIT_US));
+        let counter = self.event_counter.get().wrapping_add(1);
+        self.event_counter.set(counter);
+
+        if let Some((channel_map, instant)) = self.channel_map_update.get() {
+            if instant == counter {
+                self.channel_map_update.set(None);
+                parameters.channel_map = channel_map;
+                self.parameters.set(parameters);
+            }
+        }
+
+        match self.parameters_update.get() {
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 5.1.1
+            Some((update, instant)) if instant == counter => {
+                self.parameters_update.set(None);
+                parameters.window_size = update.window_size;
+                parameters.window_offset = update.window_offset;
+                parameters.interval = update.interval;
+                parameters.timeout = update.timeout;
+                self.parameters.set(parameters);
+                let start = anchor.wrapping_add(self.ticks(update.window_offset as u32 * UNIT_US));
+                self.open_transmit_window(start, update.window_size);
+            }
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.7
+            _ => {
+                let since_sync = self.micros(anchor.wrapping_sub(self.last_received.get()));
+                let widening = self
+                    .ticks((since_sync as u64 * CLOCK_ACCURACY_PPM as u64 / 1_000_000) as u32 + 16);
+                self.anchor.set(anchor);
+                self.window_start.set(anchor.wrapping_sub(widening));
+                self.window_end.set(
+                    anchor
+                        .wrapping_add(widening)
+                        .wrapping_add(self.ticks(RECEIVE_MARGIN_US)),
+                );
+            }
+        }
+
+        self.next_channel();
+        self.state.set(State::ConnectionIdle);
+        self.alarm.set_alarm(
+            previous_anchor,
+            self.window_start.get().wrapping_sub(previous_anchor),
+        );
+    }
+
+    /// Whether no packet was received for longer than the supervision
+    /// timeout.
+    fn supervision_timeout(&self) -> Option<u8> {
+        let parameters = self.parameters.get();
+        let silence = self
+            .alarm
+            .now()
+            .wrapping_sub(self.last_received.get())
+            .into_u32();
+        if !self.synchronized.get() {
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.2
+            if self.event_counter.get() >= 5 {
+                return Some(FAILED_TO_ESTABLISH);
+            }
+        } else if silence > self.ticks(parameters.timeout as u32 * 10_000).into_u32() {
+            return Some(CONNECTION_TIMEOUT);
+        }
+        None
+    }
+
+    fn close(&self, reason: u8) {
+        self.alarm.disarm();
+        self.state.set(State::Idle);
+        self.frame_len.clear();
+        self.radio.set_access_address(
+            ble_advertising::ADVERTISING_ACCESS_ADDRESS,
+            ble_advertising::ADVERTISING_CRC_INIT,
+        );
+        self.client.map(|client| client.disconnected(reason));
+    }
+
+    /// Whether the control procedure with `instant` is too late to apply.
+    fn instant_passed(&self, instant: u16) -> bool {
+        instant.wrapping_sub(self.event_counter.get()) >= 32767
+    }
+
+    fn receive_control(&self, pdu: &[u8]) {
+        let u16_at = |i: usize| u16::from_le_bytes([pdu[i], pdu[i + 1]]);
+        let response = match pdu[0] {
+            LL_CONNECTION_UPDATE_IND if pdu.len() == 12 => {
+                let mut update = self.parameters.get();
+                update.window_size = pdu[1];
+                update.window_offset = u16_at(2);
+                update.interval = u16_at(4);
+                update.timeout = u16_at(8);
+                let instant = u16_at(10);
+                if self.instant_passed(instant) {
+                    self.terminated.set(INSTANT_PASSED);
+                } else if update.valid() {
+                    self.parameters_update.set(Some((update, instant)));
+                }
+                None
+            }
+            LL_CHANNEL_MAP_IND if pdu.len() == 8 => {
+                let mut channel_map = [0; 5];
+                channel_map.copy_from_slice(&pdu[1..6]);
+                let instant = u16_at(6);
+                if self.instant_passed(instant) {
+                    self.terminated.set(INSTANT_PASSED);
+                } else {
+                    self.channel_map_update.set(Some((channel_map, instant)));
+                }
+                None
+            }
+            LL_TERMINATE_IND if pdu.len() == 2 => {
+                // The termination is acknowledged by the response.
+                self.terminated.set(pdu[1]);
+                None
+            }
+            LL_FEATURE_REQ => Some(ControlPdu::FeatureResponse),
+            LL_VERSION_IND if self.version_sent.get() => None,
+            LL_VERSION_IND => Some(ControlPdu::Version),
+            LL_PING_REQ => Some(ControlPdu::PingResponse),
+            opcode => Some(ControlPdu::UnknownResponse(opcode)),
+        };
+        response.map(|response| self.control.set(response));
+    }
+
+    /// Write the next PDU to transmit into `buffer`, and return its length.
+    fn prepare_pdu(&self, buffer: &mut [u8]) -> usize {
+        let (llid, payload_len, pdu) = if let Some(control) = self.control.take() {
+            let payload = &mut buffer[2..];
+            let len = match control {
+                ControlPdu::Terminate(reason) => {
+                    payload[0] = LL_TERMINATE_IND;
+                    payload[1] = reason;
+                    2
+                }
+                ControlPdu::UnknownResponse(opcode) => {
+                    payload[0] = LL_UNKNOWN_RSP;
+                    payload[1] = opcode;
+                    2
+                }
+                ControlPdu::FeatureResponse => {
+                    payload[0] = LL_FEATURE_RSP;
+                    payload[1..9].copy_from_slice(&[FEATURES, 0, 0, 0, 0, 0, 0, 0]);
+                    9
+                }
+                ControlPdu::Version => {
+                    self.version_sent.set(true);
+                    payload[0] = LL_VERSION_IND;
+                    payload[1] = VERSION;
+                    payload[2..4].copy_from_slice(&COMPANY_ID.to_le_bytes());
+                    payload[4..6].copy_from_slice(&[0, 0]);
+                    6
+                }
+                ControlPdu::PingResponse => {
+                    payload[0] = LL_PING_RSP;
+                    1
+                }
+            };
+            (LLID_CONTROL, len, Pdu::Control(payload[0]))
+        } else if let Some(len) = self.frame_len.map(|len| *len) {
+            buffer[2..2 + len].copy_from_slice(&self.frame.get()[..len]);
+            (LLID_START, len, Pdu::Frame)
+        } else {
+            (LLID_CONTINUATION, 0, Pdu::Empty)
+        };
+
+        buffer[0] = llid;
+        if self.transmit_sequence.get() {
+            buffer[0] |= HEADER_SN;
+        }
+        buffer[1] = payload_len as u8;
+        self.unacknowledged.set(pdu);
+        2 + payload_len
+    }
+
+    fn receive_data(&self, buf: &[u8], len: usize, result: ReturnCode) {
+        self.alarm.disarm();
+        let mut acknowledged = None;
+
+        if result == ReturnCode::SUCCESS && len >= 2 && 2 + buf[1] as usize <= len {
+            let header = buf[0];
+            let payload = &buf[2..2 + buf[1] as usize];
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.5
+            let now = self.alarm.now();
+            let airtime_us = (1 + 4 + 2 + payload.len() as u32 + 3) * 8;
+            self.anchor.set(now.wrapping_sub(self.ticks(airtime_us)));
+            self.last_received.set(now);
+            self.synchronized.set(true);
+
+            // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.5.9
+            if (header & HEADER_NESN != 0) != self.transmit_sequence.get() {
+                self.transmit_sequence.set(!self.transmit_sequence.get());
+                acknowledged = self.unacknowledged.take();
+            }
+            let new_pdu = (header & HEADER_SN != 0) == self.next_expected_sequence.get();
+            if new_pdu {
+                self.next_expected_sequence
+                    .set(!self.next_expected_sequence.get());
+            }
+
+            match acknowledged {
+                Some(Pdu::Control(LL_TERMINATE_IND)) => {
+                    self.close(LOCAL_HOST_TERMINATED);
+                    return;
+                }
+                Some(Pdu::Frame) => self.frame_len.clear(),
+                _ => {}
+            }
+
+            // Fragments of frames longer than `MAX_FRAME_LEN` are not
+            // reassembled.
+            if new_pdu && !payload.is_empty() {
+                match header & LLID_MASK {
+                    LLID_START => {
+                        self.client.map(|client| client.received(payload));
+                    }
+                    LLID_CONTROL => self.receive_control(payload),
+                    _ => {}
+                }
+            }
+        }
+
+        if acknowledged == Some(Pdu::Frame) {
+            self.client.map(|client| client.send_done());
+        }
+
+        self.buffer.take().map(|buffer| {
+            let len = if self.unacknowledged.is_some() {
+                // Retransmit with the new acknowledgement.
+                2 + buffer[1] as usize
+            } else {
+                self.prepare_pdu(buffer)
+            };
+            buffer[0] &= !HEADER_NESN;
+            if self.next_expected_sequence.get() {
+                buffer[0] |= HEADER_NESN;
+            }
+            self.state.set(State::ConnectionRespond);
+            self.radio
+                .transmit_advertisement(buffer, len, self.channel.get());
+        });
+    }
+}
+
+impl<'a, B, A> Connection<'a> for LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient) {
+        self.client.set(client);
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.frame_len.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let mut buffer = [0; MAX_FRAME_LEN];
+        buffer[..frame.len()].copy_from_slice(frame);
+        self.frame.set(buffer);
+        self.frame_len.set(frame.len());
+        Ok(())
+    }
+
+    fn disconnect(&self) -> Result<(), ErrorCode> {
+        if !self.is_connected() {
+            return Err(ErrorCode::OFF);
+        }
+        self.control
+            .set(ControlPdu::Terminate(REMOTE_USER_TERMINATED));
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        match self.state.get() {
+            State::ConnectionIdle | State::ConnectionListen | State::ConnectionRespond => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'a, B, A> AlarmClient for LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::AdvertisingIdle => {
+                if self.advertising.get() {
+                    self.advertise(RadioChannel::AdvertisingChannel37);
+                } else {
+                    self.state.set(State::Idle);
+                }
+            }
+            State::AdvertisingListen(channel) => {
+                self.radio.cancel_receive();
+                self.next_advertising_channel(channel);
+            }
+            State::ConnectionIdle => {
+                // The receive window opens.
+                self.state.set(State::ConnectionListen);
+                self.radio.receive_advertisement(self.channel.get());
+                let start = self.window_start.get();
+                self.alarm
+                    .set_alarm(start, self.window_end.get().wrapping_sub(start));
+            }
+            State::ConnectionListen => {
+                // The receive window closes without a packet from the
+                // central.
+                self.radio.cancel_receive();
+                match self.supervision_timeout() {
+                    Some(reason) => self.close(reason),
+                    None => self.next_connection_event(),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, B, A> ble_advertising::RxClient for LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    fn receive_event(&self, buf: &'static mut [u8], len: u8, result: ReturnCode) {
+        let len = core::cmp::min(len as usize, buf.len());
+        match self.state.get() {
+            State::AdvertisingListen(channel) => {
+                if result == ReturnCode::SUCCESS {
+                    self.receive_connect_ind(channel, buf, len);
+                } else {
+                    self.radio.receive_advertisement(channel);
+                }
+            }
+            State::ConnectionListen => self.receive_data(buf, len, result),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, B, A> ble_advertising::TxClient for LinkLayer<'a, B, A>
+where
+    B: BleAdvertisementDriver<'a> + BleConfig + BleConnectionConfig,
+    A: Alarm<'a>,
+{
+    fn transmit_event(&self, buf: &'static mut [u8], _result: ReturnCode) {
+        self.buffer.replace(buf);
+        match self.state.get() {
+            State::Advertising(channel) => {
+                if self.advertising.get() {
+                    self.state.set(State::AdvertisingListen(channel));
+                    self.radio.receive_advertisement(channel);
+                    self.alarm
+                        .set_alarm(self.alarm.now(), self.ticks(CONNECT_LISTEN_US));
+                } else {
+                    self.state.set(State::Idle);
+                }
+            }
+            State::ConnectionRespond => match self.terminated.take() {
+                Some(reason) => self.close(reason),
+                None => self.next_connection_event(),
+            },
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Support for Bluetooth Low Energy connections.
+//!
+//! The `link_layer` accepts connections from a central on top of the
+//! `BleAdvertisementDriver` radio HIL, the `gatt_server` exposes attributes
+//! over the connection, and the `BleConnectionDriver` lets a process set up
+//! the attributes and advertise.
+
+pub mod gatt_server;
+pub mod link_layer;
+
+mod driver;
+
+pub use self::driver::BleConnectionDriver;
+pub use self::driver::DRIVER_NUM;
@@ -7,17 +7,34 @@
 //! driver but processes can request an advertising or scanning interval.
 //! Processes can also control the TX power used for their advertisements.
 //!
-//! Data payloads are limited to 31 bytes since the maximum advertising channel
-//! protocol data unit (PDU) is 37 bytes and includes a 6-byte header.
+//! Legacy advertisements carry up to 31 bytes of data since the maximum
+//! legacy advertising channel protocol data unit (PDU) is 37 bytes and
+//! includes a 6-byte address. Processes can also use BLE 5 extended
+//! advertising: the driver then sends an `ADV_EXT_IND` on each primary
+//! advertising channel, each followed by an `AUX_ADV_IND` on a data channel
+//! with up to 243 bytes of data. Extended advertisements are neither
+//! connectable nor scannable.
+//!
+//! Scanning is passive or active. An active scanner sends a scan request to
+//! the scannable advertisements it reports, and reports the scan response as
+//! well. A process that advertises scannable legacy advertisements and shares
+//! scan response data answers the scan requests of other devices. Each
+//! process can filter the packets it receives by the address of the
+//! advertiser, and by the type of an AD structure in the advertising data.
+//!
+//! Scan requests and scan responses are sent from the reception callback, so
+//! whether they meet the 150 µs inter frame space depends on the interrupt
+//! latency of the chip.
 //!
 //! ### Allow system calls
 //!
-//! There is one ReadWrite and one ReadOnly allow buffers, both at index `0`.
+//! There is one ReadWrite and two ReadOnly allow buffers.
 //!
-//! * ReadOnly: Advertising data, containing the full _payload_ (i.e. excluding the header) the
-//!             process wishes to advertise.
-//! * ReadWrite: Passive scanning buffer, which is populated during BLE scans with complete (i.e.
-//!              including headers) advertising packets received on channels 37, 38 and 39.
+//! * ReadOnly 0: Advertising data, containing the full _payload_ (i.e. excluding the header) the
+//!               process wishes to advertise.
+//! * ReadOnly 1: Scan response data, sent in answer to scan requests.
+//! * ReadWrite 0: Scanning buffer, which is populated during BLE scans with complete (i.e.
+//!                including headers) advertising packets received on channels 37, 38 and 39.
 //!
 //! The possible return codes from the 'allow' system call indicate the following:
 //!
@@ -47,9 +64,18 @@
 //! `command number` is used to specify the specific operation, currently
 //! the following commands are supported:
 //!
-//! * 0: start advertisement
+//! * 0: start advertisement, with the PDU type `ADV_IND`, `ADV_NONCONN_IND`,
+//!      `ADV_SCAN_IND` or `ADV_EXT_IND`
 //! * 1: stop advertisement or scanning
-//! * 5: start scanning
+//! * 2: configure the TX power
+//! * 5: start passive scanning
+//! * 6: start active scanning
+//! * 7: only receive packets from the advertiser with an address made of the
+//!      four bytes of the first argument and the two bytes of the second,
+//!      least significant byte first
+//! * 8: only receive packets with an AD structure of the type of the first
+//!      argument
+//! * 9: remove the filters
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
@@ -117,12 +143,23 @@ use kernel::{
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::BleAdvertising as usize;
 
+/// Length of the advertisement buffer, which fits extended advertising PDUs.
+pub const BUFFER_LENGTH: usize = 255;
+
 /// Advertisement Buffer
-pub static mut BUF: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
+pub static mut BUF: [u8; BUFFER_LENGTH] = [0; BUFFER_LENGTH];
 
+const PACKET_HDR_LEN: usize = 2;
 const PACKET_ADDR_LEN: usize = 6;
+// Length of legacy advertising channel PDUs, with the header
 const PACKET_LENGTH: usize = 39;
+const LEGACY_DATA_LEN: usize = PACKET_LENGTH - PACKET_HDR_LEN - PACKET_ADDR_LEN;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
+const ADV_HEADER_RXADD_OFFSET: usize = 7;
+
+// How long to listen for a scan request after an advertisement, or for a scan response after a
+// scan request, in microseconds
+const LISTEN_WINDOW_US: u32 = 1000;
 
 #[derive(PartialEq, Debug)]
 enum BLEState {
@@ -130,8 +167,18 @@ enum BLEState {
     Initialized,
     ScanningIdle,
     Scanning(RadioChannel),
+    // Sending a scan request to the advertiser of the last reported packet
+    ScanRequest(RadioChannel),
+    // Waiting for the scan response of the advertiser
+    ScanResponseListen(RadioChannel),
     AdvertisingIdle,
     Advertising(RadioChannel),
+    // Sending the auxiliary packet of an extended advertisement
+    AuxiliaryAdvertising(RadioChannel),
+    // Waiting for scan requests after an advertisement
+    ScanRequestListen(RadioChannel),
+    // Answering a scan request
+    ScanResponse(RadioChannel),
 }
 
 #[derive(Copy, Clone)]
@@ -157,16 +204,30 @@ type AdvPduType = u8;
 
 // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.3
 const ADV_IND: AdvPduType = 0b0000;
-#[allow(dead_code)]
 const ADV_DIRECTED_IND: AdvPduType = 0b0001;
 const ADV_NONCONN_IND: AdvPduType = 0b0010;
-#[allow(dead_code)]
 const SCAN_REQ: AdvPduType = 0b0011;
-#[allow(dead_code)]
 const SCAN_RESP: AdvPduType = 0b0100;
 #[allow(dead_code)]
 const CONNECT_IND: AdvPduType = 0b0101;
 const ADV_SCAN_IND: AdvPduType = 0b0110;
+// BLUETOOTH SPECIFICATION Version 5.2 [Vol 6, Part B], section 2.3
+//
+// `ADV_EXT_IND` on the primary advertising channels, `AUX_ADV_IND` on the secondary ones.
+const ADV_EXT_IND: AdvPduType = 0b0111;
+
+// BLUETOOTH SPECIFICATION Version 5.2 [Vol 6, Part B], section 2.3.4
+const EXT_HEADER_ADVA: u8 = 1 << 0;
+const EXT_HEADER_ADI: u8 = 1 << 3;
+const EXT_HEADER_AUX_PTR: u8 = 1 << 4;
+// Extended header flags, ADI and AuxPtr
+const EXT_HEADER_PRIMARY_LEN: usize = 1 + 2 + 3;
+// Extended header flags, AdvA and ADI
+const EXT_HEADER_AUXILIARY_LEN: usize = 1 + PACKET_ADDR_LEN + 2;
+// The auxiliary packet is sent as soon as the `ADV_EXT_IND` is sent, which takes 136 µs, and the
+// radio has ramped up again, so it starts about 300 µs after the start of the `ADV_EXT_IND`. In
+// units of 30 µs.
+const AUX_OFFSET: u16 = 10;
 
 /// Process specific memory
 pub struct App {
@@ -175,6 +236,7 @@ pub struct App {
 
     // Advertising meta-data
     adv_data: ReadOnlyAppSlice,
+    scan_response_data: ReadOnlyAppSlice,
     address: [u8; PACKET_ADDR_LEN],
     pdu_type: AdvPduType,
     advertisement_interval_ms: u32,
@@ -185,10 +247,20 @@ pub struct App {
     /// It should be read using the `random_number` method, which updates it as
     /// well.
     random_nonce: u32,
+    /// The Advertising Data ID of the current extended advertising event.
+    data_id: u16,
+    /// The data channel of the auxiliary packets of the current extended
+    /// advertising event.
+    aux_channel: RadioChannel,
 
     // Scanning meta-data
     scan_buffer: ReadWriteAppSlice,
     scan_callback: kernel::Upcall,
+    active_scanning: bool,
+    /// The advertiser address, with its TxAdd bit, of the last scan request.
+    scanned_address: ([u8; PACKET_ADDR_LEN], bool),
+    address_filter: Option<[u8; PACKET_ADDR_LEN]>,
+    ad_type_filter: Option<u8>,
 }
 
 impl Default for App {
@@ -196,6 +268,7 @@ impl Default for App {
         App {
             alarm_data: AlarmData::new(),
             adv_data: ReadOnlyAppSlice::default(),
+            scan_response_data: ReadOnlyAppSlice::default(),
             scan_buffer: ReadWriteAppSlice::default(),
             address: [0; PACKET_ADDR_LEN],
             pdu_type: ADV_NONCONN_IND,
@@ -205,6 +278,12 @@ impl Default for App {
             advertisement_interval_ms: 200,
             // Just use any non-zero starting value by default
             random_nonce: 0xdeadbeef,
+            data_id: 0,
+            aux_channel: RadioChannel::DataChannel0,
+            active_scanning: false,
+            scanned_address: ([0; PACKET_ADDR_LEN], false),
+            address_filter: None,
+            ad_type_filter: None,
         }
     }
 }
@@ -238,40 +317,130 @@ impl App {
         Ok(())
     }
 
-    fn send_advertisement<'a, B, A>(&self, ble: &BLE<'a, B, A>, channel: RadioChannel) -> ReturnCode
-    where
-        B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
-        A: kernel::hil::time::Alarm<'a>,
-    {
-        self.adv_data.map_or(ReturnCode::FAIL, |adv_data| {
-            ble.kernel_tx.take().map_or(ReturnCode::FAIL, |kernel_tx| {
-                let adv_data_len = cmp::min(kernel_tx.len() - PACKET_ADDR_LEN - 2, adv_data.len());
-                let adv_data_corrected = &adv_data.as_ref()[..adv_data_len];
-                let payload_len = adv_data_corrected.len() + PACKET_ADDR_LEN;
-                {
-                    let (header, payload) = kernel_tx.split_at_mut(2);
-                    header[0] = self.pdu_type;
-                    match self.pdu_type {
-                        ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
-                            // Set TxAdd because AdvA field is going to be a "random"
-                            // address
-                            header[0] |= 1 << ADV_HEADER_TXADD_OFFSET;
-                        }
-                        _ => {}
-                    }
-                    // The LENGTH field is 6-bits wide, so make sure to truncate it
-                    header[1] = (payload_len & 0x3f) as u8;
+    // Whether the process answers scan requests to its advertisements.
+    fn answers_scan_requests(&self) -> bool {
+        (self.pdu_type == ADV_IND || self.pdu_type == ADV_SCAN_IND)
+            && self.scan_response_data.len() > 0
+    }
 
-                    let (adva, data) = payload.split_at_mut(6);
-                    adva.copy_from_slice(&self.address);
-                    data[..adv_data_len].copy_from_slice(adv_data_corrected);
-                }
-                let total_len = cmp::min(PACKET_LENGTH, payload_len + 2);
-                ble.radio
-                    .transmit_advertisement(kernel_tx, total_len, channel);
-                ReturnCode::SUCCESS
-            })
-        })
+    // Serialize a legacy PDU sent by an advertiser, with `data` as its AdvData or ScanRspData.
+    //
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3
+    fn serialize_legacy(
+        &self,
+        pdu_type: AdvPduType,
+        data: &ReadOnlyAppSlice,
+        buf: &mut [u8],
+    ) -> usize {
+        let (header, payload) = buf.split_at_mut(PACKET_HDR_LEN);
+        let (adva, payload_data) = payload.split_at_mut(PACKET_ADDR_LEN);
+        let data_len = data.map_or(0, |data| {
+            let data_len = cmp::min(data.len(), LEGACY_DATA_LEN);
+            payload_data[..data_len].copy_from_slice(&data[..data_len]);
+            data_len
+        });
+        // Set TxAdd because AdvA field is going to be a "random" address
+        header[0] = pdu_type | 1 << ADV_HEADER_TXADD_OFFSET;
+        header[1] = (PACKET_ADDR_LEN + data_len) as u8;
+        adva.copy_from_slice(&self.address);
+        PACKET_HDR_LEN + PACKET_ADDR_LEN + data_len
+    }
+
+    // The Advertising Data Info field, with the advertising set 0.
+    //
+    // BLUETOOTH SPECIFICATION Version 5.2 [Vol 6, Part B], section 2.3.4.4
+    fn adi(&self) -> [u8; 2] {
+        (self.data_id & 0x0fff).to_le_bytes()
+    }
+
+    // Serialize the `ADV_EXT_IND` of an extended advertisement, which points to the `AUX_ADV_IND`
+    // with the advertising data.
+    //
+    // BLUETOOTH SPECIFICATION Version 5.2 [Vol 6, Part B], sections 2.3.4 and 2.3.4.5
+    fn serialize_extended(&self, buf: &mut [u8]) -> usize {
+        let adi = self.adi();
+        buf[0] = ADV_EXT_IND;
+        buf[1] = (1 + EXT_HEADER_PRIMARY_LEN) as u8;
+        // The AdvMode is 0, non-connectable and non-scannable
+        buf[2] = EXT_HEADER_PRIMARY_LEN as u8;
+        buf[3] = EXT_HEADER_ADI | EXT_HEADER_AUX_PTR;
+        buf[4..6].copy_from_slice(&adi);
+        // AuxPtr: the channel index, a clock accuracy of 51 to 500 ppm, an offset in units of
+        // 30 µs, and the LE 1M PHY
+        buf[6] = self.aux_channel.get_channel_index() as u8;
+        buf[7..9].copy_from_slice(&AUX_OFFSET.to_le_bytes());
+        PACKET_HDR_LEN + 1 + EXT_HEADER_PRIMARY_LEN
+    }
+
+    // Serialize the `AUX_ADV_IND` of an extended advertisement.
+    fn serialize_auxiliary(&self, buf: &mut [u8]) -> usize {
+        let adi = self.adi();
+        let (header, payload) = buf.split_at_mut(PACKET_HDR_LEN);
+        let (extended_header, payload_data) = payload.split_at_mut(1 + EXT_HEADER_AUXILIARY_LEN);
+        let data_len = self.adv_data.map_or(0, |data| {
+            let data_len = cmp::min(data.len(), payload_data.len());
+            payload_data[..data_len].copy_from_slice(&data[..data_len]);
+            data_len
+        });
+        header[0] = ADV_EXT_IND | 1 << ADV_HEADER_TXADD_OFFSET;
+        header[1] = (1 + EXT_HEADER_AUXILIARY_LEN + data_len) as u8;
+        extended_header[0] = EXT_HEADER_AUXILIARY_LEN as u8;
+        extended_header[1] = EXT_HEADER_ADVA | EXT_HEADER_ADI;
+        extended_header[2..8].copy_from_slice(&self.address);
+        extended_header[8..10].copy_from_slice(&adi);
+        PACKET_HDR_LEN + 1 + EXT_HEADER_AUXILIARY_LEN + data_len
+    }
+
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.2.1
+    fn serialize_scan_request(&self, buf: &mut [u8]) -> usize {
+        let (advertiser_address, advertiser_random) = self.scanned_address;
+        buf[0] = SCAN_REQ
+            | 1 << ADV_HEADER_TXADD_OFFSET
+            | (advertiser_random as u8) << ADV_HEADER_RXADD_OFFSET;
+        buf[1] = (2 * PACKET_ADDR_LEN) as u8;
+        buf[2..8].copy_from_slice(&self.address);
+        buf[8..14].copy_from_slice(&advertiser_address);
+        PACKET_HDR_LEN + 2 * PACKET_ADDR_LEN
+    }
+
+    // Whether `packet` is a scan request for the advertisements of the process.
+    fn is_scan_request(&self, packet: &[u8]) -> bool {
+        packet[0] & 0x0f == SCAN_REQ
+            && packet[0] & 1 << ADV_HEADER_RXADD_OFFSET != 0
+            && packet.len() == PACKET_HDR_LEN + 2 * PACKET_ADDR_LEN
+            && packet[8..14] == self.address
+    }
+
+    // Whether `packet` is the scan response to the last scan request of the process.
+    fn is_scan_response(&self, packet: &[u8]) -> bool {
+        let (scanned_address, _) = self.scanned_address;
+        packet[0] & 0x0f == SCAN_RESP && advertiser_address(packet) == Some(&scanned_address[..])
+    }
+
+    // Whether `packet` passes the filters of the process.
+    fn accepts(&self, packet: &[u8]) -> bool {
+        let address = self.address_filter.map_or(true, |filter| {
+            advertiser_address(packet) == Some(&filter[..])
+        });
+        let ad_type = self.ad_type_filter.map_or(true, |ad_type| {
+            advertising_data(packet).map_or(false, |data| has_ad_type(data, ad_type))
+        });
+        address && ad_type
+    }
+
+    // Copy `packet` to the scanning buffer and notify the process. Returns whether the process
+    // has a scanning buffer.
+    fn report(&mut self, packet: &[u8]) -> bool {
+        let len = self.scan_buffer.mut_map_or(0, |userland| {
+            let len = cmp::min(packet.len(), userland.len());
+            userland[..len].copy_from_slice(&packet[..len]);
+            len
+        });
+        if len > 0 {
+            self.scan_callback
+                .schedule(usize::from(ReturnCode::SUCCESS), len, 0);
+        }
+        len > 0
     }
 
     // Returns a new pseudo-random number and updates the randomness state.
@@ -295,6 +464,54 @@ impl App {
         let period_ms = (self.advertisement_interval_ms + nonce) * F::frequency() / 1000;
         self.alarm_data.expiration = Expiration::Enabled(now, period_ms);
     }
+
+    // Set the alarm for the end of a listening window starting at `now`.
+    fn set_window_alarm<F: Frequency>(&mut self, now: u32) {
+        let window = (LISTEN_WINDOW_US as u64 * F::frequency() as u64 / 1_000_000) as u32;
+        self.alarm_data.expiration = Expiration::Enabled(now, cmp::max(1, window));
+    }
+}
+
+// The AdvA field of the PDUs sent by advertisers.
+fn advertiser_address(packet: &[u8]) -> Option<&[u8]> {
+    match packet[0] & 0x0f {
+        ADV_IND | ADV_DIRECTED_IND | ADV_NONCONN_IND | SCAN_RESP | ADV_SCAN_IND => {
+            packet.get(PACKET_HDR_LEN..PACKET_HDR_LEN + PACKET_ADDR_LEN)
+        }
+        _ => None,
+    }
+}
+
+// The AdvData or ScanRspData field of the PDUs sent by advertisers.
+fn advertising_data(packet: &[u8]) -> Option<&[u8]> {
+    match packet[0] & 0x0f {
+        ADV_IND | ADV_NONCONN_IND | SCAN_RESP | ADV_SCAN_IND => {
+            packet.get(PACKET_HDR_LEN + PACKET_ADDR_LEN..)
+        }
+        _ => None,
+    }
+}
+
+// Whether advertising data has an AD structure of type `ad_type`.
+//
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 3, Part C], section 11
+fn has_ad_type(mut data: &[u8], ad_type: u8) -> bool {
+    // A zero length ends the significant part of the data
+    while data.len() >= 2 && data[0] != 0 {
+        if data[1] == ad_type {
+            return true;
+        }
+        data = data.get(1 + data[0] as usize..).unwrap_or(&[]);
+    }
+    false
+}
+
+fn next_advertising_channel(channel: RadioChannel) -> Option<RadioChannel> {
+    match channel {
+        RadioChannel::AdvertisingChannel37 => Some(RadioChannel::AdvertisingChannel38),
+        RadioChannel::AdvertisingChannel38 => Some(RadioChannel::AdvertisingChannel39),
+        _ => None,
+    }
 }
 
 pub struct BLE<'a, B, A>
@@ -364,6 +581,93 @@ where
                 .set_alarm(A::Ticks::from(next_ref), A::Ticks::from(next_dt));
         }
     }
+
+    // Serialize a packet with `serialize`, which returns its length, and send it on `channel`.
+    fn transmit<F: FnOnce(&mut [u8]) -> usize>(&self, channel: RadioChannel, serialize: F) {
+        self.kernel_tx.take().map(|kernel_tx| {
+            let len = serialize(kernel_tx);
+            self.radio.transmit_advertisement(kernel_tx, len, channel);
+        });
+    }
+
+    fn send_advertisement(&self, app: &App, channel: RadioChannel) {
+        if app.pdu_type == ADV_EXT_IND {
+            self.transmit(channel, |buf| app.serialize_extended(buf));
+        } else {
+            self.transmit(channel, |buf| {
+                app.serialize_legacy(app.pdu_type, &app.adv_data, buf)
+            });
+        }
+    }
+
+    // Advertise on the channel after `channel`, or end the advertising event.
+    fn next_advertisement(&self, app: &mut App, appid: kernel::AppId, channel: RadioChannel) {
+        match next_advertising_channel(channel) {
+            Some(next) => {
+                app.process_status = Some(BLEState::Advertising(next));
+                self.sending_app.set(appid);
+                self.send_advertisement(app, next);
+            }
+            None => {
+                self.busy.set(false);
+                app.process_status = Some(BLEState::AdvertisingIdle);
+                app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+            }
+        }
+    }
+
+    // Scan on the channel after `channel`, or end the scanning event.
+    fn next_scan(&self, app: &mut App, appid: kernel::AppId, channel: RadioChannel) {
+        match next_advertising_channel(channel) {
+            Some(next) => {
+                app.process_status = Some(BLEState::Scanning(next));
+                self.receiving_app.set(appid);
+                self.radio.receive_advertisement(next);
+            }
+            None => {
+                self.busy.set(false);
+                app.process_status = Some(BLEState::ScanningIdle);
+                app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+            }
+        }
+    }
+
+    // Listen on `channel` in `state` until a packet arrives or the window ends.
+    fn listen(&self, app: &mut App, appid: kernel::AppId, state: BLEState, channel: RadioChannel) {
+        app.process_status = Some(state);
+        app.set_window_alarm::<A::Frequency>(self.alarm.now().into_u32());
+        self.receiving_app.set(appid);
+        self.radio.receive_advertisement(channel);
+    }
+
+    fn start_scanning(&self, appid: kernel::AppId, active: bool) -> CommandReturn {
+        self.app
+            .enter(appid, |app, _| {
+                if let Some(BLEState::Initialized) = app.process_status {
+                    if active {
+                        // The address of the scanner is part of the scan requests
+                        app.generate_random_address(appid)?;
+                    }
+                    app.active_scanning = active;
+                    app.process_status = Some(BLEState::ScanningIdle);
+                    app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                    Ok(())
+                } else {
+                    Err(ErrorCode::BUSY)
+                }
+            })
+            .map_or_else(
+                |err| err.into(),
+                |res| match res {
+                    Ok(_) => {
+                        // must be called outside closure passed to grant region!
+                        self.reset_active_alarm();
+                        CommandReturn::success()
+                    }
+                    Err(e) => CommandReturn::failure(e.into()),
+                },
+            )
+    }
 }
 
 // Timer alarm
@@ -393,12 +697,31 @@ where
                 let t0 = A::Ticks::from(reference);
                 let expired = !now.within_range(t0, exp);
                 if expired {
+                    let appid = app.appid();
+
+                    // The listening window of the app that uses the radio ended
+                    match app.process_status {
+                        Some(BLEState::ScanRequestListen(channel)) => {
+                            app.alarm_data.expiration = Expiration::Disabled;
+                            self.radio.cancel_receive();
+                            self.next_advertisement(app, appid, channel);
+                            return;
+                        }
+                        Some(BLEState::ScanResponseListen(channel)) => {
+                            app.alarm_data.expiration = Expiration::Disabled;
+                            self.radio.cancel_receive();
+                            self.next_scan(app, appid, channel);
+                            return;
+                        }
+                        _ => {}
+                    }
+
                     if self.busy.get() {
                         // The radio is currently busy, so we won't be able to start the
                         // operation at the appropriate time. Instead, reschedule the
                         // operation for later. This is _kind_ of simulating actual
                         // on-air interference. 3 seems like a small number of ticks.
-                        debug!("BLE: operation delayed for app {:?}", app.appid());
+                        debug!("BLE: operation delayed for app {:?}", appid);
                         app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                         return;
                     }
@@ -410,24 +733,27 @@ where
                             self.busy.set(true);
                             app.process_status =
                                 Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
-                            self.sending_app.set(app.appid());
+                            if app.pdu_type == ADV_EXT_IND {
+                                // A new data ID tells scanners that the data may have changed
+                                app.data_id = app.random_nonce() as u16;
+                                app.aux_channel =
+                                    RadioChannel::from_channel_index(app.random_nonce() % 37)
+                                        .unwrap_or(RadioChannel::DataChannel0);
+                            }
+                            self.sending_app.set(appid);
                             self.radio.set_tx_power(app.tx_power);
-                            app.send_advertisement(&self, RadioChannel::AdvertisingChannel37);
+                            self.send_advertisement(app, RadioChannel::AdvertisingChannel37);
                         }
                         Some(BLEState::ScanningIdle) => {
                             self.busy.set(true);
                             app.process_status =
                                 Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
-                            self.receiving_app.set(app.appid());
+                            self.receiving_app.set(appid);
                             self.radio.set_tx_power(app.tx_power);
                             self.radio
                                 .receive_advertisement(RadioChannel::AdvertisingChannel37);
                         }
-                        _ => debug!(
-                            "app: {:?} \t invalid state {:?}",
-                            app.appid(),
-                            app.process_status
-                        ),
+                        _ => debug!("app: {:?} \t invalid state {:?}", appid, app.process_status),
                     }
                 }
             }
@@ -453,41 +779,68 @@ where
                 // channels 37, 38 and 39 should only be used for advertisements!
                 // Packets that are bigger than 39 bytes are likely `Channel PDUs` which should
                 // only be sent on the other 37 RadioChannel channels.
-
-                if len <= PACKET_LENGTH as u8 && result == ReturnCode::SUCCESS {
-                    // write to buffer in userland
-                    let success = app.scan_buffer.mut_map_or(false, |userland| {
-                        userland[0..len as usize].copy_from_slice(&buf[0..len as usize]);
-                        true
-                    });
-
-                    if success {
-                        app.scan_callback
-                            .schedule(usize::from(result), len as usize, 0);
-                    }
-                }
+                let len = len as usize;
+                let packet = if (PACKET_HDR_LEN..=cmp::min(PACKET_LENGTH, buf.len())).contains(&len)
+                    && result == ReturnCode::SUCCESS
+                {
+                    Some(&buf[..len])
+                } else {
+                    None
+                };
+                let appid = app.appid();
 
                 match app.process_status {
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38));
-                        self.receiving_app.set(app.appid());
-                        self.radio.set_tx_power(app.tx_power);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel38);
-                    }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39));
-                        self.receiving_app.set(app.appid());
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel39);
+                    Some(BLEState::Scanning(channel)) => {
+                        // The advertiser to send a scan request to
+                        let scanned = packet.and_then(|packet| {
+                            if !(app.accepts(packet) && app.report(packet) && app.active_scanning) {
+                                return None;
+                            }
+                            match packet[0] & 0x0f {
+                                ADV_IND | ADV_SCAN_IND => {
+                                    advertiser_address(packet).map(|address| {
+                                        let mut scanned_address = [0; PACKET_ADDR_LEN];
+                                        scanned_address.copy_from_slice(address);
+                                        let random = packet[0] & 1 << ADV_HEADER_TXADD_OFFSET != 0;
+                                        (scanned_address, random)
+                                    })
+                                }
+                                _ => None,
+                            }
+                        });
+                        match scanned {
+                            Some(scanned_address) => {
+                                app.scanned_address = scanned_address;
+                                app.process_status = Some(BLEState::ScanRequest(channel));
+                                self.sending_app.set(appid);
+                                self.transmit(channel, |buf| app.serialize_scan_request(buf));
+                            }
+                            None => self.next_scan(app, appid, channel),
+                        }
                     }
-                    Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
-                        self.busy.set(false);
-                        app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                    Some(BLEState::ScanResponseListen(channel)) => {
+                        match packet {
+                            Some(packet) if app.is_scan_response(packet) => {
+                                app.report(packet);
+                                app.alarm_data.expiration = Expiration::Disabled;
+                                self.next_scan(app, appid, channel);
+                            }
+                            // Keep listening until the end of the window
+                            _ => self.radio.receive_advertisement(channel),
+                        }
                     }
+                    Some(BLEState::ScanRequestListen(channel)) => match packet {
+                        Some(packet) if app.is_scan_request(packet) => {
+                            app.alarm_data.expiration = Expiration::Disabled;
+                            app.process_status = Some(BLEState::ScanResponse(channel));
+                            self.sending_app.set(appid);
+                            self.transmit(channel, |buf| {
+                                app.serialize_legacy(SCAN_RESP, &app.scan_response_data, buf)
+                            });
+                        }
+                        // Keep listening until the end of the window
+                        _ => self.radio.receive_advertisement(channel),
+                    },
                     // Invalid state => don't care
                     _ => (),
                 }
@@ -509,27 +862,28 @@ where
         self.kernel_tx.replace(buf);
         self.sending_app.map(|appid| {
             let _ = self.app.enter(*appid, |app, _| {
+                let appid = app.appid();
                 match app.process_status {
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38));
-                        self.sending_app.set(app.appid());
-                        self.radio.set_tx_power(app.tx_power);
-                        app.send_advertisement(&self, RadioChannel::AdvertisingChannel38);
+                    Some(BLEState::Advertising(channel)) => {
+                        if app.pdu_type == ADV_EXT_IND {
+                            app.process_status = Some(BLEState::AuxiliaryAdvertising(channel));
+                            self.transmit(app.aux_channel, |buf| app.serialize_auxiliary(buf));
+                        } else if app.answers_scan_requests() {
+                            self.listen(app, appid, BLEState::ScanRequestListen(channel), channel);
+                        } else {
+                            self.next_advertisement(app, appid, channel);
+                        }
                     }
 
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel38)) => {
-                        app.process_status =
-                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39));
-                        self.sending_app.set(app.appid());
-                        app.send_advertisement(&self, RadioChannel::AdvertisingChannel39);
+                    Some(BLEState::AuxiliaryAdvertising(channel))
+                    | Some(BLEState::ScanResponse(channel)) => {
+                        self.next_advertisement(app, appid, channel);
                     }
 
-                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39)) => {
-                        self.busy.set(false);
-                        app.process_status = Some(BLEState::AdvertisingIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                    Some(BLEState::ScanRequest(channel)) => {
+                        self.listen(app, appid, BLEState::ScanResponseListen(channel), channel);
                     }
+
                     // Invalid state => don't care
                     _ => (),
                 }
@@ -560,7 +914,7 @@ where
                         if let Some(BLEState::Initialized) = app.process_status {
                             let pdu_type = data as AdvPduType;
                             match pdu_type {
-                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
+                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND | ADV_EXT_IND => {
                                     app.pdu_type = pdu_type;
                                     app.process_status = Some(BLEState::AdvertisingIdle);
                                     app.random_nonce = self.alarm.now().into_u32();
@@ -631,29 +985,41 @@ where
             }
 
             // Passive scanning mode
-            5 => {
-                self.app
-                    .enter(appid, |app, _| {
-                        if let Some(BLEState::Initialized) = app.process_status {
-                            app.process_status = Some(BLEState::ScanningIdle);
-                            app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
-                            Ok(())
-                        } else {
-                            Err(ErrorCode::BUSY)
-                        }
-                    })
-                    .map_or_else(
-                        |err| err.into(),
-                        |res| match res {
-                            Ok(_) => {
-                                // must be called outside closure passed to grant region!
-                                self.reset_active_alarm();
-                                CommandReturn::success()
-                            }
-                            Err(e) => CommandReturn::failure(e.into()),
-                        },
-                    )
-            }
+            5 => self.start_scanning(appid, false),
+
+            // Active scanning mode
+            6 => self.start_scanning(appid, true),
+
+            // Filter by advertiser address, in the order of the packets
+            7 => self
+                .app
+                .enter(appid, |app, _| {
+                    let mut address = [0; PACKET_ADDR_LEN];
+                    address[..4].copy_from_slice(&(data as u32).to_le_bytes());
+                    address[4..].copy_from_slice(&(interval as u16).to_le_bytes());
+                    app.address_filter = Some(address);
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // Filter by AD type
+            8 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.ad_type_filter = Some(data as u8);
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // Remove the filters
+            9 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.address_filter = None;
+                    app.ad_type_filter = None;
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| err.into()),
 
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
@@ -678,6 +1044,14 @@ where
                 })
                 .unwrap_or_else(|err| Err(err.into())),
 
+            // Scan response buffer
+            1 => self
+                .app
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.scan_response_data, &mut slice);
+                })
+                .map_err(ErrorCode::from),
+
             // Operation not supported
             _ => Err(ErrorCode::NOSUPPORT),
         };
@@ -695,7 +1069,7 @@ where
         mut slice: ReadWriteAppSlice,
     ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
         let res = match allow_num {
-            // Passive scanning buffer
+            // Scanning buffer
             0 => self
                 .app
                 .enter(appid, |app, _| match app.process_status {
@@ -0,0 +1,416 @@
+//! Provides userspace access to a real-time clock.
+//!
+//! Applications can read and set the date and time of a `hil::date_time`
+//! clock, such as an RTC peripheral or `capsules::date_time_software`, and
+//! set an alarm for a date and time. The clock is shared by all
+//! applications, so setting it affects all of them.
+//!
+//! Each application has an alarm of its own. This capsule programs the
+//! earliest of them into the clock, and calls the alarm upcall of an
+//! application once the clock reaches or passes the time of its alarm, also
+//! if the alarm was set to a time that passed already or the clock was set
+//! past it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let date_time_driver = static_init!(
+//!     capsules::date_time::DateTimeDriver<'static>,
+//!     capsules::date_time::DateTimeDriver::new(
+//!         &peripherals.rtc,
+//!         board_kernel.create_grant(&memory_allocation_cap)
+//!     )
+//! );
+//! kernel::hil::date_time::DateTime::set_client(&peripherals.rtc, date_time_driver);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! Dates and times are passed packed in two arguments:
+//!
+//! - date: the year in bits 16 and up, the month (1 to 12) in bits 8 to 15
+//!   and the day of the month (1 to 31) in bits 0 to 7.
+//! - time: the day of the week (0 is Sunday, only when reading) in bits 24
+//!   to 31, the hour in bits 16 to 23, the minute in bits 8 to 15 and the
+//!   seconds in bits 0 to 7.
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//! - `1`: Read the date and time. The result is passed to upcall 0.
+//! - `2`: Set the date and time to date `data1` and time `data2`. The result
+//!        is passed to upcall 0.
+//! - `3`: Set the alarm of the process to date `data1` and time `data2`,
+//!        replacing any previous alarm.
+//! - `4`: Disable the alarm of the process.
+//!
+//! #### `subscribe_num`
+//!
+//! - `0`: The completion of reads and sets, with the result, and the date
+//!        and the time after a read.
+//! - `1`: The alarm, with 0, and the date and the time of the alarm.
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::DateTime as usize;
+
+use core::cell::Cell;
+use core::convert::TryFrom;
+use core::mem;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, Month};
+use kernel::{AppId, CommandReturn, Driver, ErrorCode, Grant, ReturnCode, Upcall};
+
+/// The commands an application can queue.
+#[derive(Clone, Copy, PartialEq)]
+enum UserCommand {
+    Get,
+    Set(DateTimeValues),
+}
+
+/// The operation in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Get(AppId),
+    Set(AppId),
+    /// Reading the clock to fire the alarms that are due and program the
+    /// next one. `verify` is set when reading it again after programming an
+    /// alarm, which may have passed in the meantime.
+    Refresh {
+        verify: bool,
+    },
+}
+
+/// Packs `date_time` into the date and time arguments of an upcall.
+fn pack(date_time: &DateTimeValues) -> (usize, usize) {
+    (
+        (date_time.year as usize) << 16 | (date_time.month as usize) << 8 | date_time.day as usize,
+        (date_time.day_of_week() as usize) << 24
+            | (date_time.hour as usize) << 16
+            | (date_time.minute as usize) << 8
+            | date_time.seconds as usize,
+    )
+}
+
+/// Unpacks the date and time arguments of a command.
+fn unpack(date: usize, time: usize) -> Result<DateTimeValues, ErrorCode> {
+    let date_time = DateTimeValues {
+        year: u16::try_from(date >> 16).map_err(|_| ErrorCode::INVAL)?,
+        month: Month::try_from((date >> 8) as u8)?,
+        day: date as u8,
+        hour: (time >> 16) as u8,
+        minute: (time >> 8) as u8,
+        seconds: time as u8,
+    };
+    if date_time.is_valid() {
+        Ok(date_time)
+    } else {
+        Err(ErrorCode::INVAL)
+    }
+}
+
+pub struct DateTimeDriver<'a> {
+    date_time: &'a dyn DateTime<'a>,
+    apps: Grant<App>,
+    operation: Cell<Operation>,
+    /// Whether the alarms must be refreshed once the clock is free.
+    refresh_pending: Cell<bool>,
+    /// The alarm programmed into the clock, in seconds since 1970-01-01.
+    armed: OptionalCell<u64>,
+}
+
+impl<'a> DateTimeDriver<'a> {
+    pub fn new(date_time: &'a dyn DateTime<'a>, grant: Grant<App>) -> DateTimeDriver<'a> {
+        DateTimeDriver {
+            date_time,
+            apps: grant,
+            operation: Cell::new(Operation::None),
+            refresh_pending: Cell::new(false),
+            armed: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts `operation` on the clock.
+    fn start(&self, operation: Operation, command: Option<UserCommand>) -> Result<(), ErrorCode> {
+        let ret = match command {
+            Some(UserCommand::Set(date_time)) => self.date_time.set_date_time(date_time),
+            _ => self.date_time.get_date_time(),
+        };
+        if ret.is_ok() {
+            self.operation.set(operation);
+        }
+        ret
+    }
+
+    /// Reads the clock to refresh the alarms, now or once the clock is free.
+    fn refresh(&self, verify: bool) {
+        if self.operation.get() != Operation::None {
+            self.refresh_pending.set(true);
+        } else if self.start(Operation::Refresh { verify }, None).is_err() {
+            self.refresh_pending.set(true);
+        }
+    }
+
+    /// Calls the alarm upcall of the processes whose alarm is at or before
+    /// `now`, and returns whether there were any.
+    fn fire_alarms(&self, now: u64) -> bool {
+        let fired = Cell::new(false);
+        self.apps.each(|app| {
+            if let Some(alarm) = app.alarm.filter(|alarm| *alarm <= now) {
+                app.alarm = None;
+                let (date, time) = DateTimeValues::from_seconds_since_epoch(alarm)
+                    .map_or((0, 0), |date_time| pack(&date_time));
+                app.alarm_callback.schedule(0, date, time);
+                fired.set(true);
+            }
+        });
+        fired.get()
+    }
+
+    /// Programs the earliest alarm of all processes into the clock, and
+    /// returns whether there is one.
+    fn program_alarm(&self) -> bool {
+        let earliest: Cell<Option<u64>> = Cell::new(None);
+        self.apps.each(|app| {
+            if let Some(alarm) = app.alarm {
+                if earliest.get().map_or(true, |earliest| alarm < earliest) {
+                    earliest.set(Some(alarm));
+                }
+            }
+        });
+        match earliest.get() {
+            Some(alarm) => {
+                if self.armed.map_or(true, |armed| *armed != alarm) {
+                    let _ = DateTimeValues::from_seconds_since_epoch(alarm)
+                        .and_then(|date_time| self.date_time.set_alarm(date_time));
+                    self.armed.set(alarm);
+                }
+                true
+            }
+            None => {
+                if self.armed.is_some() {
+                    let _ = self.date_time.disable_alarm();
+                    self.armed.clear();
+                }
+                false
+            }
+        }
+    }
+
+    /// Finishes the current operation, calls the upcall of the process that
+    /// started it and starts the next queued operation.
+    fn complete(&self, result: ReturnCode, date: usize, time: usize) {
+        match self.operation.replace(Operation::None) {
+            Operation::Get(appid) | Operation::Set(appid) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.schedule(usize::from(result), date, time);
+                });
+            }
+            _ => (),
+        }
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        if self.refresh_pending.get() {
+            self.refresh_pending.set(false);
+            self.refresh(false);
+        }
+
+        for appiter in self.apps.iter() {
+            // If an operation is in progress let it complete
+            if self.operation.get() != Operation::None {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                app.pending_command
+                    .take()
+                    .map(|command| (app.appid(), command))
+            });
+            if let Some((appid, command)) = pending {
+                if let Err(e) = self.run(appid, command) {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback
+                            .schedule(usize::from(ReturnCode::from(e)), 0, 0);
+                    });
+                }
+            }
+        }
+    }
+
+    fn run(&self, appid: AppId, command: UserCommand) -> Result<(), ErrorCode> {
+        let operation = match command {
+            UserCommand::Get => Operation::Get(appid),
+            UserCommand::Set(_) => Operation::Set(appid),
+        };
+        self.start(operation, Some(command))
+    }
+
+    /// Starts or queues `command` for `appid`.
+    fn enqueue(&self, appid: AppId, command: UserCommand) -> CommandReturn {
+        if self.operation.get() == Operation::None {
+            match self.run(appid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::failure(e),
+            }
+        } else {
+            // The clock is busy, we must wait.
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending_command.is_some() {
+                        // No more room in the queue
+                        CommandReturn::failure(ErrorCode::NOMEM)
+                    } else {
+                        app.pending_command = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+}
+
+impl DateTimeClient for DateTimeDriver<'_> {
+    fn get_date_time_done(&self, date_time: Result<DateTimeValues, ErrorCode>) {
+        match (self.operation.get(), date_time) {
+            (Operation::Get(_), Ok(date_time)) => {
+                let (date, time) = pack(&date_time);
+                self.complete(ReturnCode::SUCCESS, date, time);
+            }
+            (Operation::Refresh { verify }, Ok(date_time)) => {
+                let now = date_time.seconds_since_epoch();
+                let fired = self.fire_alarms(now);
+                if (fired || !verify) && self.program_alarm() {
+                    self.operation.set(Operation::None);
+                    self.refresh(true);
+                    if self.operation.get() != Operation::None {
+                        return;
+                    }
+                }
+                self.complete(ReturnCode::SUCCESS, 0, 0);
+            }
+            (Operation::None, _) | (Operation::Set(_), _) => (),
+            (_, Err(e)) => self.complete(ReturnCode::from(e), 0, 0),
+        }
+    }
+
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>) {
+        if let Operation::Set(_) = self.operation.get() {
+            // Alarms the clock skipped over are due now
+            self.refresh_pending.set(true);
+            match result {
+                Ok(()) => self.complete(ReturnCode::SUCCESS, 0, 0),
+                Err(e) => self.complete(ReturnCode::from(e), 0, 0),
+            }
+        }
+    }
+
+    fn alarm_fired(&self) {
+        self.armed.clear();
+        self.refresh(false);
+    }
+}
+
+impl Driver for DateTimeDriver<'_> {
+    /// Subscribe to date and time events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of reads and sets. The callback
+    ///        signature is `fn(result: u32, date: u32, time: u32)`.
+    /// - `1`: Subscribe to the alarm. The callback signature is
+    ///        `fn(0, date: u32, time: u32)`, with the date and time of the
+    ///        alarm.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app, _| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.alarm_callback, &mut callback);
+                    Ok(())
+                }
+
+                // default
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .unwrap_or(Err(ErrorCode::FAIL));
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Read and set the clock.
+    ///
+    /// Reads and sets are queued if the clock is busy. Each process can
+    /// queue one of them.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the date and time.
+    /// - `2`: Set the date and time to date `data1` and time `data2`.
+    /// - `3`: Set the alarm to date `data1` and time `data2`.
+    /// - `4`: Disable the alarm.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => self.enqueue(appid, UserCommand::Get),
+            2 => match unpack(data1, data2) {
+                Ok(date_time) => self.enqueue(appid, UserCommand::Set(date_time)),
+                Err(e) => CommandReturn::failure(e),
+            },
+            3 | 4 => {
+                let alarm = match command_num {
+                    3 => match unpack(data1, data2) {
+                        Ok(date_time) => Some(date_time.seconds_since_epoch()),
+                        Err(e) => return CommandReturn::failure(e),
+                    },
+                    _ => None,
+                };
+                let ret = self.apps.enter(appid, |app, _| app.alarm = alarm);
+                match ret {
+                    Ok(()) => {
+                        self.refresh(false);
+                        CommandReturn::success()
+                    }
+                    Err(err) => err.into(),
+                }
+            }
+
+            // default
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    alarm_callback: Upcall,
+    pending_command: Option<UserCommand>,
+    /// The alarm of the process, in seconds since 1970-01-01.
+    alarm: Option<u64>,
+}
@@ -0,0 +1,218 @@
+//! Software real-time clock on top of an `Alarm`.
+//!
+//! `DateTimeSoftware` implements the `DateTime` HIL for chips without an RTC
+//! peripheral, or whose RTC is used for something else, by counting the ticks
+//! of an alarm. It keeps the time as the number of seconds since 1970-01-01
+//! at some tick of the alarm, and moves that reference forward at least
+//! every half wrap of the counter, so that it never loses a wraparound. The
+//! clock starts at 1970-01-01 00:00:00 when `start()` is called and does not
+//! survive a reset.
+//!
+//! The clock is only as accurate as the alarm's oscillator, and the alarm
+//! must not be shared with anything that disarms it, so it usually is a
+//! `VirtualMuxAlarm`. Operations complete in a deferred call.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::date_time_software::DateTimeSoftware;
+//!
+//! let virtual_alarm = static_init!(
+//!     capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let date_time = static_init!(
+//!     DateTimeSoftware<'static, capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     DateTimeSoftware::new(virtual_alarm, dynamic_deferred_caller)
+//! );
+//! virtual_alarm.set_alarm_client(date_time);
+//! date_time.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(date_time)
+//!         .expect("no deferred call slot available for software date time"),
+//! );
+//! date_time.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::ErrorCode;
+
+pub struct DateTimeSoftware<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+    /// The alarm tick at which the clock was `reference_seconds` and
+    /// `reference_fraction` ticks.
+    reference_ticks: Cell<A::Ticks>,
+    reference_seconds: Cell<u64>,
+    reference_fraction: Cell<u32>,
+    /// The time of the RTC alarm, in seconds since 1970-01-01, if armed.
+    alarm_seconds: OptionalCell<u64>,
+    get_pending: Cell<bool>,
+    set_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> DateTimeSoftware<'a, A> {
+    pub fn new(alarm: &'a A, deferred_caller: &'a DynamicDeferredCall) -> DateTimeSoftware<'a, A> {
+        DateTimeSoftware {
+            alarm,
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            reference_ticks: Cell::new(A::Ticks::from(0)),
+            reference_seconds: Cell::new(0),
+            reference_fraction: Cell::new(0),
+            alarm_seconds: OptionalCell::empty(),
+            get_pending: Cell::new(false),
+            set_pending: Cell::new(false),
+        }
+    }
+
+    /// Must be called with the handle returned when registering this clock
+    /// with the deferred caller, otherwise no operation ever completes.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Starts the clock at 1970-01-01 00:00:00.
+    pub fn start(&self) {
+        self.reference_ticks.set(self.alarm.now());
+        self.reference_seconds.set(0);
+        self.reference_fraction.set(0);
+        self.schedule();
+    }
+
+    /// Moves the reference to the current tick, so that the clock only
+    /// depends on the ticks elapsed since then.
+    fn update_reference(&self) {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.reference_ticks.get()).into_u32() as u64;
+        let frequency = A::Frequency::frequency() as u64;
+        let ticks = self.reference_fraction.get() as u64 + elapsed;
+        self.reference_ticks.set(now);
+        self.reference_seconds
+            .set(self.reference_seconds.get() + ticks / frequency);
+        self.reference_fraction.set((ticks % frequency) as u32);
+    }
+
+    /// The current time in seconds since 1970-01-01.
+    fn now_seconds(&self) -> u64 {
+        self.update_reference();
+        self.reference_seconds.get()
+    }
+
+    /// Arms the alarm for the RTC alarm, or before the counter wraps
+    /// around, whichever comes first.
+    fn schedule(&self) {
+        self.update_reference();
+        let now = self.reference_ticks.get();
+        let half_wrap = A::Ticks::max_value().into_u32() / 2;
+        let dt = self.alarm_seconds.map_or(half_wrap, |alarm_seconds| {
+            let seconds = alarm_seconds.saturating_sub(self.reference_seconds.get());
+            let ticks = seconds
+                .saturating_mul(A::Frequency::frequency() as u64)
+                .saturating_sub(self.reference_fraction.get() as u64);
+            if ticks < half_wrap as u64 {
+                ticks as u32
+            } else {
+                half_wrap
+            }
+        });
+        let dt = A::Ticks::from(dt);
+        let minimum_dt = self.alarm.minimum_dt();
+        self.alarm
+            .set_alarm(now, if dt < minimum_dt { minimum_dt } else { dt });
+    }
+}
+
+impl<'a, A: Alarm<'a>> DateTime<'a> for DateTimeSoftware<'a, A> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        if self.get_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.get_pending.set(true);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        if self.set_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !date_time.is_valid() {
+            return Err(ErrorCode::INVAL);
+        }
+        let seconds = date_time.seconds_since_epoch();
+        self.reference_ticks.set(self.alarm.now());
+        self.reference_seconds.set(seconds);
+        self.reference_fraction.set(0);
+        // An alarm the new time skipped over will never be reached
+        if self.alarm_seconds.map_or(false, |alarm| *alarm <= seconds) {
+            self.alarm_seconds.clear();
+        }
+        self.schedule();
+        self.set_pending.set(true);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        if !date_time.is_valid() {
+            return Err(ErrorCode::INVAL);
+        }
+        let seconds = date_time.seconds_since_epoch();
+        if seconds > self.now_seconds() {
+            self.alarm_seconds.set(seconds);
+        } else {
+            self.alarm_seconds.clear();
+        }
+        self.schedule();
+        Ok(())
+    }
+
+    fn disable_alarm(&self) -> Result<(), ErrorCode> {
+        self.alarm_seconds.clear();
+        self.schedule();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for DateTimeSoftware<'a, A> {
+    fn alarm(&self) {
+        let now = self.now_seconds();
+        let fired = self.alarm_seconds.map_or(false, |alarm| *alarm <= now);
+        if fired {
+            self.alarm_seconds.clear();
+        }
+        self.schedule();
+        if fired {
+            self.client.map(|client| client.alarm_fired());
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> DynamicDeferredCallClient for DateTimeSoftware<'a, A> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.set_pending.take() {
+            self.client.map(|client| client.set_date_time_done(Ok(())));
+        }
+        if self.get_pending.take() {
+            let date_time = DateTimeValues::from_seconds_since_epoch(self.now_seconds());
+            self.client
+                .map(|client| client.get_date_time_done(date_time));
+        }
+    }
+}
@@ -16,9 +16,11 @@ pub enum NUM {
     Adc                   = 0x00005,
     Dac                   = 0x00006,
     AnalogComparator      = 0x00007,
+    Pwm                   = 0x00010,
 
     // Kernel
     Ipc                   = 0x10000,
+    AppWatchdog           = 0x10001,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -31,17 +33,25 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
+    BleConnection         = 0x30004,
+    Thread                = 0x30005,
 
     // Cryptography
+    Aes                   = 0x40000,
     Rng                   = 0x40001,
     Crc                   = 0x40002,
     Hmac                  = 0x40003,
     CtapHid               = 0x40004,
+    Signature             = 0x40005,
 
     // Storage
     AppFlash              = 0x50000,
     NvmStorage            = 0x50001,
     SdCard                = 0x50002,
+    KVStore               = 0x50003,
+    Log                   = 0x50004,
+    FileSystem            = 0x50005,
 
     // Sensors
     Temperature           = 0x60000,
@@ -71,5 +81,6 @@ pub enum NUM {
     Screen                = 0x90001,
     Touch                 = 0x90002,
     TextScreen            = 0x90003,
+    DateTime              = 0x90004,
 }
 }
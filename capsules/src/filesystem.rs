@@ -0,0 +1,834 @@
+//! A small power-loss safe filesystem on top of `hil::nonvolatile_storage`.
+//!
+//! The filesystem stores a flat table of files, each with a directory name,
+//! a file name and up to `MAX_FILE_BLOCKS` blocks of data. It is meant for
+//! internal flash, for example behind `NonvolatileToPages` or the kernel
+//! region of the nonvolatile storage driver.
+//!
+//! The file table is kept in memory and stored twice at the start of the
+//! storage region, each copy with a sequence number and a CRC-32. Every
+//! change writes the whole table to the older copy, so the previous table is
+//! left intact until the new one is complete. File data is copied on write:
+//! changed blocks are written to free blocks, which only the new table
+//! refers to, and blocks are only reused once a table that no longer refers
+//! to them is stored. When power is lost, mounting finds the newest valid
+//! copy of the table, so the filesystem is as it was after the last completed
+//! operation.
+//!
+//! ```text
+//! +---------+---------+---------+---------+-----+
+//! | table 0 | table 1 | block 0 | block 1 | ... |
+//! +---------+---------+---------+---------+-----+
+//! ```
+//!
+//! Each copy of the table takes the length of the table buffer, rounded up
+//! to whole blocks. Storage without a valid table is formatted when it is
+//! mounted.
+//!
+//! Files are identified by their index in the table, and are read and
+//! written at byte offsets. Writes may extend a file, but not leave a gap
+//! after its end.
+//!
+//! The `hil::nonvolatile_storage` interface does not return the buffer when
+//! an operation fails to start, so the filesystem stops working if that
+//! happens.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fs = static_init!(
+//!     capsules::filesystem::FileSystem<'static>,
+//!     capsules::filesystem::FileSystem::new(
+//!         nv_to_page,
+//!         0x60000, // Start of the region
+//!         0x20000, // Length of the region
+//!         512,     // Block size
+//!         static_init!([u8; 1420], [0; 1420]),
+//!         static_init!([u8; 512], [0; 512]),
+//!     )
+//! );
+//! nv_to_page.set_client(fs);
+//! fs.set_client(fs_client);
+//! fs.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::convert::TryInto;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::ErrorCode;
+
+/// The maximum length of a directory name.
+pub const MAX_DIR_LEN: usize = 32;
+/// The maximum length of a file name.
+pub const MAX_NAME_LEN: usize = 32;
+/// The maximum number of blocks in a file.
+pub const MAX_FILE_BLOCKS: usize = 8;
+
+/// The length of the header of the table: the magic number, the sequence
+/// number and the CRC-32 of the entries, in that order.
+pub const HEADER_LEN: usize = 12;
+/// The length of an entry in the table: the flags, the length of the
+/// directory name, the length of the file name, a reserved byte, the size
+/// of the file, its blocks, the directory name and the file name.
+pub const ENTRY_LEN: usize = ENTRY_NAME + MAX_NAME_LEN;
+
+const MAGIC: u32 = 0x3153_4654;
+
+const ENTRY_FLAGS: usize = 0;
+const ENTRY_DIR_LEN: usize = 1;
+const ENTRY_NAME_LEN: usize = 2;
+const ENTRY_SIZE: usize = 4;
+const ENTRY_BLOCKS: usize = 8;
+const ENTRY_DIR: usize = ENTRY_BLOCKS + 2 * MAX_FILE_BLOCKS;
+const ENTRY_NAME: usize = ENTRY_DIR + MAX_DIR_LEN;
+
+/// Set in the flags of entries that hold a file.
+const FLAG_USED: u8 = 0x01;
+
+/// The block number of blocks that are not stored.
+const NO_BLOCK: u16 = 0xffff;
+
+/// Identifies a file by its index in the table.
+pub type FileId = usize;
+
+pub trait FileSystemClient {
+    /// Called when the filesystem is mounted, or failed to mount.
+    fn mount_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when a file was created, deleted or truncated.
+    fn update_done(&self, result: Result<(), ErrorCode>);
+
+    /// Returns the buffer passed to `read` and the number of bytes read into
+    /// it.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>);
+
+    /// Returns the buffer passed to `write` and the number of bytes written
+    /// from it.
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>);
+}
+
+/// The operation requested by the client.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Mount,
+    Update,
+    Read,
+    Write,
+}
+
+/// The storage access in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Reading copy `copy` of the table. `seq` is the sequence number of
+    /// copy 0 if it is valid.
+    MountRead {
+        copy: usize,
+        seq: Option<u32>,
+    },
+    /// Reading copy 0 of the table again, as it is newer than copy 1.
+    MountReload {
+        seq: u32,
+    },
+    /// Storing the table.
+    Commit,
+    /// Reading part of a block of the file.
+    Read,
+    /// Reading the block that a write changes part of.
+    WriteRead,
+    /// Writing a block of the file.
+    WriteBlock,
+}
+
+/// CRC-32 (IEEE 802.3) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes(buf[..4].try_into().unwrap())
+}
+
+fn entry(table: &[u8], file: FileId) -> &[u8] {
+    &table[HEADER_LEN + file * ENTRY_LEN..HEADER_LEN + (file + 1) * ENTRY_LEN]
+}
+
+fn entry_mut(table: &mut [u8], file: FileId) -> &mut [u8] {
+    &mut table[HEADER_LEN + file * ENTRY_LEN..HEADER_LEN + (file + 1) * ENTRY_LEN]
+}
+
+fn is_used(entry: &[u8]) -> bool {
+    entry[ENTRY_FLAGS] & FLAG_USED != 0
+}
+
+fn file_size(entry: &[u8]) -> usize {
+    read_u32(&entry[ENTRY_SIZE..]) as usize
+}
+
+fn block(entry: &[u8], index: usize) -> u16 {
+    let offset = ENTRY_BLOCKS + 2 * index;
+    u16::from_le_bytes([entry[offset], entry[offset + 1]])
+}
+
+fn set_block(entry: &mut [u8], index: usize, block: u16) {
+    let offset = ENTRY_BLOCKS + 2 * index;
+    entry[offset..offset + 2].copy_from_slice(&block.to_le_bytes());
+}
+
+fn dir_name(entry: &[u8]) -> &[u8] {
+    &entry[ENTRY_DIR..ENTRY_DIR + entry[ENTRY_DIR_LEN] as usize]
+}
+
+fn file_name(entry: &[u8]) -> &[u8] {
+    &entry[ENTRY_NAME..ENTRY_NAME + entry[ENTRY_NAME_LEN] as usize]
+}
+
+/// Empties `entry`, or makes it an empty file named `dir`/`name`.
+fn init_entry(entry: &mut [u8], names: Option<(&[u8], &[u8])>) {
+    for byte in entry.iter_mut() {
+        *byte = 0;
+    }
+    for index in 0..MAX_FILE_BLOCKS {
+        set_block(entry, index, NO_BLOCK);
+    }
+    if let Some((dir, name)) = names {
+        entry[ENTRY_FLAGS] = FLAG_USED;
+        entry[ENTRY_DIR_LEN] = dir.len() as u8;
+        entry[ENTRY_NAME_LEN] = name.len() as u8;
+        entry[ENTRY_DIR..ENTRY_DIR + dir.len()].copy_from_slice(dir);
+        entry[ENTRY_NAME..ENTRY_NAME + name.len()].copy_from_slice(name);
+    }
+}
+
+pub struct FileSystem<'a> {
+    storage: &'a dyn NonvolatileStorage<'static>,
+    client: OptionalCell<&'a dyn FileSystemClient>,
+    /// Address of the start of the storage region.
+    start: usize,
+    block_size: usize,
+    /// The space taken by each copy of the table, in bytes.
+    table_space: usize,
+    num_files: usize,
+    num_blocks: usize,
+
+    operation: Cell<Operation>,
+    state: Cell<State>,
+    mounted: Cell<bool>,
+    /// The copy of the table that holds the current table.
+    active_copy: Cell<usize>,
+    /// The sequence number of the current table.
+    seq: Cell<u32>,
+    table: TakeCell<'static, [u8]>,
+    block_buffer: TakeCell<'static, [u8]>,
+
+    // Note: for saving state across stack ripping.
+    /// Client buffer of the current read or write.
+    buffer: TakeCell<'static, [u8]>,
+    file: Cell<FileId>,
+    offset: Cell<usize>,
+    length: Cell<usize>,
+    /// The number of bytes read or written so far.
+    done: Cell<usize>,
+    /// The block being written.
+    new_block: Cell<u16>,
+    /// Blocks replaced by the current write, which the stored table still
+    /// refers to.
+    replaced: Cell<[u16; MAX_FILE_BLOCKS]>,
+}
+
+impl<'a> FileSystem<'a> {
+    /// Creates a filesystem in the `length` bytes of `storage` from address
+    /// `start`, with blocks of `block_size` bytes.
+    ///
+    /// The number of files is set by the length of `table`: `HEADER_LEN`
+    /// bytes plus `ENTRY_LEN` bytes for each file. `block_buffer` must be at
+    /// least `block_size` bytes long.
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'static>,
+        start: usize,
+        length: usize,
+        block_size: usize,
+        table: &'static mut [u8],
+        block_buffer: &'static mut [u8],
+    ) -> FileSystem<'a> {
+        let table_space = (table.len() + block_size - 1) / block_size * block_size;
+        let num_blocks = length.saturating_sub(2 * table_space) / block_size;
+        FileSystem {
+            storage,
+            client: OptionalCell::empty(),
+            start,
+            block_size,
+            table_space,
+            num_files: table.len().saturating_sub(HEADER_LEN) / ENTRY_LEN,
+            num_blocks: cmp::min(num_blocks, NO_BLOCK as usize),
+            operation: Cell::new(Operation::None),
+            state: Cell::new(State::Idle),
+            mounted: Cell::new(false),
+            active_copy: Cell::new(0),
+            seq: Cell::new(0),
+            table: TakeCell::new(table),
+            block_buffer: TakeCell::new(block_buffer),
+            buffer: TakeCell::empty(),
+            file: Cell::new(0),
+            offset: Cell::new(0),
+            length: Cell::new(0),
+            done: Cell::new(0),
+            new_block: Cell::new(NO_BLOCK),
+            replaced: Cell::new([NO_BLOCK; MAX_FILE_BLOCKS]),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn FileSystemClient) {
+        self.client.set(client);
+    }
+
+    /// The number of files the table has room for.
+    pub fn num_files(&self) -> usize {
+        self.num_files
+    }
+
+    /// The number of data blocks.
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn table_address(&self, copy: usize) -> usize {
+        self.start + copy * self.table_space
+    }
+
+    fn block_address(&self, block: u16) -> usize {
+        self.start + 2 * self.table_space + block as usize * self.block_size
+    }
+
+    /// Checks that no operation is in progress and the filesystem is
+    /// mounted.
+    fn check_ready(&self) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::None {
+            Err(ErrorCode::BUSY)
+        } else if !self.mounted.get() {
+            Err(ErrorCode::OFF)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Calls `f` with the table, which is not available while it is being
+    /// read or stored.
+    fn with_table<R, F: FnOnce(&mut [u8]) -> Result<R, ErrorCode>>(
+        &self,
+        f: F,
+    ) -> Result<R, ErrorCode> {
+        self.table.map_or(Err(ErrorCode::BUSY), |table| f(table))
+    }
+
+    /// Calls `f` with the entry of `file`, if it holds a file.
+    fn with_file<R, F: FnOnce(&mut [u8]) -> Result<R, ErrorCode>>(
+        &self,
+        file: FileId,
+        f: F,
+    ) -> Result<R, ErrorCode> {
+        if file >= self.num_files {
+            return Err(ErrorCode::INVAL);
+        }
+        self.with_table(|table| {
+            let entry = entry_mut(table, file);
+            if is_used(entry) {
+                f(entry)
+            } else {
+                Err(ErrorCode::INVAL)
+            }
+        })
+    }
+
+    /// Reads the table and formats the storage if no copy of the table is
+    /// valid. `mount_done` is called when done.
+    pub fn mount(&self) -> Result<(), ErrorCode> {
+        if self.operation.get() != Operation::None {
+            return Err(ErrorCode::BUSY);
+        }
+        self.operation.set(Operation::Mount);
+        self.mounted.set(false);
+        self.read_table(State::MountRead { copy: 0, seq: None }, 0)
+    }
+
+    /// Finds the file `name` in directory `dir`.
+    pub fn find(&self, dir: &[u8], name: &[u8]) -> Result<Option<FileId>, ErrorCode> {
+        if !self.mounted.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.with_table(|table| {
+            Ok((0..self.num_files).find(|file| {
+                let entry = entry(table, *file);
+                is_used(entry) && dir_name(entry) == dir && file_name(entry) == name
+            }))
+        })
+    }
+
+    /// Copies the name of the `index`th file in directory `dir` to `name`
+    /// and returns its length. Returns `INVAL` if there are not that many
+    /// files, and `SIZE` if `name` is too short.
+    pub fn list(&self, dir: &[u8], index: usize, name: &mut [u8]) -> Result<usize, ErrorCode> {
+        if !self.mounted.get() {
+            return Err(ErrorCode::OFF);
+        }
+        self.with_table(|table| {
+            let entry = (0..self.num_files)
+                .map(|file| entry(table, file))
+                .filter(|entry| is_used(entry) && dir_name(entry) == dir)
+                .nth(index)
+                .ok_or(ErrorCode::INVAL)?;
+            let file_name = file_name(entry);
+            if file_name.len() > name.len() {
+                return Err(ErrorCode::SIZE);
+            }
+            name[..file_name.len()].copy_from_slice(file_name);
+            Ok(file_name.len())
+        })
+    }
+
+    /// The size of `file` in bytes.
+    pub fn size(&self, file: FileId) -> Result<usize, ErrorCode> {
+        self.with_file(file, |entry| Ok(file_size(entry)))
+    }
+
+    /// Creates the empty file `name` in directory `dir`. `update_done` is
+    /// called when done. Returns `ALREADY` if the file exists, and `NOMEM`
+    /// if the table is full.
+    pub fn create(&self, dir: &[u8], name: &[u8]) -> Result<(), ErrorCode> {
+        self.check_ready()?;
+        if dir.is_empty() || dir.len() > MAX_DIR_LEN || name.is_empty() || name.len() > MAX_NAME_LEN
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.find(dir, name)?.is_some() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.with_table(|table| {
+            let file = (0..self.num_files)
+                .find(|file| !is_used(entry(table, *file)))
+                .ok_or(ErrorCode::NOMEM)?;
+            init_entry(entry_mut(table, file), Some((dir, name)));
+            Ok(())
+        })?;
+        self.operation.set(Operation::Update);
+        self.commit()
+    }
+
+    /// Deletes `file`. `update_done` is called when done.
+    pub fn delete(&self, file: FileId) -> Result<(), ErrorCode> {
+        self.check_ready()?;
+        self.with_file(file, |entry| {
+            init_entry(entry, None);
+            Ok(())
+        })?;
+        self.operation.set(Operation::Update);
+        self.commit()
+    }
+
+    /// Removes the contents of `file`. `update_done` is called when done.
+    pub fn truncate(&self, file: FileId) -> Result<(), ErrorCode> {
+        self.check_ready()?;
+        self.with_file(file, |entry| {
+            entry[ENTRY_SIZE..ENTRY_SIZE + 4].copy_from_slice(&0u32.to_le_bytes());
+            for index in 0..MAX_FILE_BLOCKS {
+                set_block(entry, index, NO_BLOCK);
+            }
+            Ok(())
+        })?;
+        self.operation.set(Operation::Update);
+        self.commit()
+    }
+
+    /// Reads up to `length` bytes of `file` from `offset` into `buffer`.
+    /// `read_done` is called when done. Returns `SIZE` if `offset` is at or
+    /// after the end of the file.
+    pub fn read(
+        &self,
+        file: FileId,
+        offset: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let ret = self.check_ready().and_then(|()| {
+            self.with_file(file, |entry| {
+                let size = file_size(entry);
+                if offset >= size {
+                    Err(ErrorCode::SIZE)
+                } else {
+                    Ok(cmp::min(cmp::min(length, buffer.len()), size - offset))
+                }
+            })
+        });
+        match ret {
+            Ok(length) => {
+                self.start_transfer(Operation::Read, file, offset, buffer, length);
+                self.read_next();
+                Ok(())
+            }
+            Err(e) => Err((e, buffer)),
+        }
+    }
+
+    /// Writes `length` bytes of `buffer` to `file` from `offset`, which may
+    /// be at most the size of the file. `write_done` is called when done.
+    /// Returns `SIZE` if the file would grow beyond `MAX_FILE_BLOCKS`
+    /// blocks, and `NOMEM` if there are not enough free blocks.
+    pub fn write(
+        &self,
+        file: FileId,
+        offset: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let ret = self.check_ready().and_then(|()| {
+            let size = self.with_file(file, |entry| Ok(file_size(entry)))?;
+            if length == 0 || length > buffer.len() || offset > size {
+                Err(ErrorCode::INVAL)
+            } else if offset + length > MAX_FILE_BLOCKS * self.block_size {
+                Err(ErrorCode::SIZE)
+            } else {
+                // Every block the write touches is written to a new block
+                let blocks = (offset + length - 1) / self.block_size - offset / self.block_size + 1;
+                if self.with_table(|table| Ok(self.free_blocks(table)))? < blocks {
+                    Err(ErrorCode::NOMEM)
+                } else {
+                    Ok(())
+                }
+            }
+        });
+        match ret {
+            Ok(()) => {
+                self.replaced.set([NO_BLOCK; MAX_FILE_BLOCKS]);
+                self.start_transfer(Operation::Write, file, offset, buffer, length);
+                self.write_next();
+                Ok(())
+            }
+            Err(e) => Err((e, buffer)),
+        }
+    }
+
+    fn start_transfer(
+        &self,
+        operation: Operation,
+        file: FileId,
+        offset: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) {
+        self.operation.set(operation);
+        self.file.set(file);
+        self.offset.set(offset);
+        self.length.set(length);
+        self.done.set(0);
+        self.buffer.replace(buffer);
+    }
+
+    /// Whether any file refers to `block`, or the current write replaced
+    /// it.
+    fn is_allocated(&self, table: &[u8], block: u16) -> bool {
+        self.replaced.get().contains(&block)
+            || (0..self.num_files).any(|file| {
+                let entry = entry(table, file);
+                is_used(entry)
+                    && (0..MAX_FILE_BLOCKS).any(|index| self::block(entry, index) == block)
+            })
+    }
+
+    fn free_blocks(&self, table: &[u8]) -> usize {
+        (0..self.num_blocks as u16)
+            .filter(|block| !self.is_allocated(table, *block))
+            .count()
+    }
+
+    /// Reads copy `copy` of the table, continuing in `state`.
+    fn read_table(&self, state: State, copy: usize) -> Result<(), ErrorCode> {
+        let table = self.table.take().ok_or(ErrorCode::FAIL)?;
+        let len = table.len();
+        self.state.set(state);
+        self.storage
+            .read(table, self.table_address(copy), len)
+            .map_err(|e| {
+                self.state.set(State::Idle);
+                self.operation.set(Operation::None);
+                e
+            })
+    }
+
+    /// The sequence number of `table` if it is valid.
+    fn table_seq(&self, table: &[u8]) -> Option<u32> {
+        if read_u32(table) == MAGIC && read_u32(&table[8..]) == crc32(&table[HEADER_LEN..]) {
+            Some(read_u32(&table[4..]))
+        } else {
+            None
+        }
+    }
+
+    /// Stores the table in the older copy.
+    fn commit(&self) -> Result<(), ErrorCode> {
+        let result = match self.table.take() {
+            Some(table) => {
+                let seq = self.seq.get().wrapping_add(1);
+                let crc = crc32(&table[HEADER_LEN..]);
+                table[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+                table[4..8].copy_from_slice(&seq.to_le_bytes());
+                table[8..12].copy_from_slice(&crc.to_le_bytes());
+                let len = table.len();
+                self.state.set(State::Commit);
+                self.storage
+                    .write(table, self.table_address(1 - self.active_copy.get()), len)
+            }
+            None => Err(ErrorCode::FAIL),
+        };
+        if result.is_err() {
+            self.state.set(State::Idle);
+            self.operation.set(Operation::None);
+        }
+        result
+    }
+
+    /// Finishes the current operation and calls the client.
+    fn complete(&self, result: Result<(), ErrorCode>) {
+        let operation = self.operation.replace(Operation::None);
+        self.state.set(State::Idle);
+        match operation {
+            Operation::Mount => {
+                self.mounted.set(result.is_ok());
+                self.client.map(|client| client.mount_done(result));
+            }
+            Operation::Update => {
+                self.client.map(|client| client.update_done(result));
+            }
+            Operation::Read | Operation::Write => {
+                let length = if result.is_ok() { self.length.get() } else { 0 };
+                self.buffer.take().map(|buffer| {
+                    self.client.map(move |client| {
+                        if operation == Operation::Read {
+                            client.read_done(buffer, length, result)
+                        } else {
+                            client.write_done(buffer, length, result)
+                        }
+                    })
+                });
+            }
+            Operation::None => (),
+        }
+    }
+
+    /// The block of the current transfer that the next byte is in, the
+    /// offset of that byte in the block and the number of bytes of the
+    /// transfer in the block.
+    fn next_chunk(&self) -> (usize, usize, usize) {
+        let position = self.offset.get() + self.done.get();
+        let offset = position % self.block_size;
+        let chunk = cmp::min(
+            self.block_size - offset,
+            self.length.get() - self.done.get(),
+        );
+        (position / self.block_size, offset, chunk)
+    }
+
+    fn read_next(&self) {
+        if self.done.get() == self.length.get() {
+            self.complete(Ok(()));
+            return;
+        }
+        let (index, offset, chunk) = self.next_chunk();
+        let ret = self
+            .with_file(self.file.get(), |entry| Ok(block(entry, index)))
+            .and_then(|block| {
+                let buffer = self.block_buffer.take().ok_or(ErrorCode::FAIL)?;
+                self.state.set(State::Read);
+                self.storage
+                    .read(buffer, self.block_address(block) + offset, chunk)
+            });
+        if let Err(e) = ret {
+            self.complete(Err(e));
+        }
+    }
+
+    fn write_next(&self) {
+        if self.done.get() == self.length.get() {
+            let end = self.offset.get() + self.length.get();
+            let _ = self.with_file(self.file.get(), |entry| {
+                let size = cmp::max(file_size(entry), end) as u32;
+                entry[ENTRY_SIZE..ENTRY_SIZE + 4].copy_from_slice(&size.to_le_bytes());
+                Ok(())
+            });
+            if let Err(e) = self.commit() {
+                self.complete(Err(e));
+            }
+            return;
+        }
+
+        let (index, offset, chunk) = self.next_chunk();
+        let ret = self
+            .with_file(self.file.get(), |entry| {
+                Ok((block(entry, index), file_size(entry)))
+            })
+            .and_then(|(old, size)| {
+                let buffer = self.block_buffer.take().ok_or(ErrorCode::FAIL)?;
+                // Keep the data of the block that is not overwritten
+                let kept = size > index * self.block_size + offset + chunk;
+                if old != NO_BLOCK && (offset > 0 || kept) {
+                    self.state.set(State::WriteRead);
+                    let len = self.block_size;
+                    self.storage.read(buffer, self.block_address(old), len)
+                } else {
+                    for byte in buffer[..self.block_size].iter_mut() {
+                        *byte = 0xff;
+                    }
+                    self.write_block(buffer)
+                }
+            });
+        if let Err(e) = ret {
+            self.complete(Err(e));
+        }
+    }
+
+    /// Copies the next chunk of the client buffer into `buffer`, which holds
+    /// the rest of the block, and writes it to a free block.
+    fn write_block(&self, buffer: &'static mut [u8]) -> Result<(), ErrorCode> {
+        let (_, offset, chunk) = self.next_chunk();
+        let done = self.done.get();
+        self.buffer.map(|data| {
+            buffer[offset..offset + chunk].copy_from_slice(&data[done..done + chunk]);
+        });
+        let new_block = self.with_table(|table| {
+            (0..self.num_blocks as u16)
+                .find(|block| !self.is_allocated(table, *block))
+                .ok_or(ErrorCode::NOMEM)
+        });
+        match new_block {
+            Ok(new_block) => {
+                self.new_block.set(new_block);
+                self.state.set(State::WriteBlock);
+                let len = self.block_size;
+                self.storage
+                    .write(buffer, self.block_address(new_block), len)
+            }
+            Err(e) => {
+                self.block_buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'a> NonvolatileStorageClient<'static> for FileSystem<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        match self.state.get() {
+            State::MountRead { copy: 0, .. } => {
+                let seq = self.table_seq(buffer);
+                self.table.replace(buffer);
+                if let Err(e) = self.read_table(State::MountRead { copy: 1, seq }, 1) {
+                    self.complete(Err(e));
+                }
+            }
+            State::MountRead { seq: seq0, .. } => {
+                let seq1 = self.table_seq(buffer);
+                self.table.replace(buffer);
+                match (seq0, seq1) {
+                    (Some(seq0), seq1) if seq1.map_or(true, |seq1| seq0 >= seq1) => {
+                        if let Err(e) = self.read_table(State::MountReload { seq: seq0 }, 0) {
+                            self.complete(Err(e));
+                        }
+                    }
+                    (_, Some(seq1)) => {
+                        self.active_copy.set(1);
+                        self.seq.set(seq1);
+                        self.complete(Ok(()));
+                    }
+                    (_, None) => {
+                        // Format: store an empty table in copy 0
+                        self.table.map(|table| {
+                            for file in 0..self.num_files {
+                                init_entry(entry_mut(table, file), None);
+                            }
+                        });
+                        self.active_copy.set(1);
+                        self.seq.set(0);
+                        if let Err(e) = self.commit() {
+                            self.complete(Err(e));
+                        }
+                    }
+                }
+            }
+            State::MountReload { seq } => {
+                self.table.replace(buffer);
+                self.active_copy.set(0);
+                self.seq.set(seq);
+                self.complete(Ok(()));
+            }
+            State::Read => {
+                let done = self.done.get();
+                self.buffer.map(|data| {
+                    data[done..done + length].copy_from_slice(&buffer[..length]);
+                });
+                self.block_buffer.replace(buffer);
+                self.done.set(done + length);
+                self.read_next();
+            }
+            State::WriteRead => {
+                if let Err(e) = self.write_block(buffer) {
+                    self.complete(Err(e));
+                }
+            }
+            _ => {
+                self.block_buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        match self.state.get() {
+            State::Commit => {
+                self.table.replace(buffer);
+                self.active_copy.set(1 - self.active_copy.get());
+                self.seq.set(self.seq.get().wrapping_add(1));
+                self.replaced.set([NO_BLOCK; MAX_FILE_BLOCKS]);
+                self.complete(Ok(()));
+            }
+            State::WriteBlock => {
+                self.block_buffer.replace(buffer);
+                let (index, _, chunk) = self.next_chunk();
+                let new_block = self.new_block.get();
+                let _ = self.with_file(self.file.get(), |entry| {
+                    let old = block(entry, index);
+                    if old != NO_BLOCK {
+                        let mut replaced = self.replaced.get();
+                        if let Some(slot) = replaced.iter_mut().find(|block| **block == NO_BLOCK) {
+                            *slot = old;
+                        }
+                        self.replaced.set(replaced);
+                    }
+                    set_block(entry, index, new_block);
+                    Ok(())
+                });
+                self.done.set(self.done.get() + chunk);
+                self.write_next();
+            }
+            _ => {
+                self.block_buffer.replace(buffer);
+            }
+        }
+    }
+}
@@ -0,0 +1,668 @@
+//! Files for applications.
+//!
+//! This capsule exposes `capsules::filesystem::FileSystem` to applications.
+//! Applications open files by name, read and write them at the offset of the
+//! open file, list the names of their files and delete them.
+//!
+//! Each application has a directory of its own, named after the name the
+//! board's `AppIdentities` table gives the application, and can only open the
+//! files in it. Applications the table has no name for (see `app_identity`),
+//! or whose name is longer than a directory name, cannot use this driver.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Applications         |
+//! |                       |
+//! +-----------------------+
+//!
+//!    syscalls
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FS Driver (this file)|
+//! |                       |
+//! +-----------------------+
+//!
+//!    capsules::filesystem
+//!
+//! +-----------------------+
+//! |                       |
+//! |  FileSystem           |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::nonvolatile_storage
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Storage              |
+//! |                       |
+//! +-----------------------+
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let fs_driver = static_init!(
+//!     capsules::filesystem_driver::FileSystemDriver<'static>,
+//!     capsules::filesystem_driver::FileSystemDriver::new(
+//!         fs,
+//!         static_init!([u8; 256], [0; 256]),
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!         identities,
+//!     )
+//! );
+//! fs.set_client(fs_driver);
+//! fs.mount();
+//! ```
+
+use crate::app_identity::AppIdentities;
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::FileSystem as usize;
+
+use crate::filesystem::{FileId, FileSystem, FileSystemClient, MAX_DIR_LEN, MAX_NAME_LEN};
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, ReturnCode, Upcall,
+};
+
+/// The number of files each process can have open at once.
+pub const MAX_OPEN_FILES: usize = 4;
+
+/// Open flag: create the file if it does not exist.
+const OPEN_CREATE: usize = 0x01;
+/// Open flag: remove the contents of the file.
+const OPEN_TRUNCATE: usize = 0x02;
+
+/// The commands an application can queue.
+#[derive(Clone, Copy, PartialEq)]
+enum UserCommand {
+    /// Open the file named in the name buffer with these flags.
+    Open(usize),
+    /// Read up to this many bytes from the open file.
+    Read { handle: usize, length: usize },
+    /// Write this many bytes of the write buffer to the open file.
+    Write { handle: usize, length: usize },
+    /// Delete the file named in the name buffer.
+    Delete,
+}
+
+/// The operation in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    /// Creating or truncating a file before opening it.
+    Open,
+    Read(usize),
+    Write(usize),
+    Delete(FileId),
+}
+
+#[derive(Clone, Copy)]
+struct OpenFile {
+    file: FileId,
+    /// The offset the next read or write starts at.
+    offset: usize,
+}
+
+pub struct FileSystemDriver<'a> {
+    fs: &'a FileSystem<'a>,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    /// The directory of the process of the current operation.
+    dir: OptionalCell<&'static [u8]>,
+    /// The name of the file being opened.
+    name: Cell<[u8; MAX_NAME_LEN]>,
+    name_len: Cell<usize>,
+    operation: Cell<Operation>,
+    buffer: TakeCell<'static, [u8]>,
+    identities: &'static AppIdentities,
+}
+
+impl<'a> FileSystemDriver<'a> {
+    /// Creates the driver. Reads and writes are split into parts of at most
+    /// the length of `buffer`.
+    pub fn new(
+        fs: &'a FileSystem<'a>,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+        identities: &'static AppIdentities,
+    ) -> FileSystemDriver<'a> {
+        FileSystemDriver {
+            fs,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            dir: OptionalCell::empty(),
+            name: Cell::new([0; MAX_NAME_LEN]),
+            name_len: Cell::new(0),
+            operation: Cell::new(Operation::None),
+            buffer: TakeCell::new(buffer),
+            identities,
+        }
+    }
+
+    /// The directory of the files of `appid`.
+    fn directory(&self, appid: AppId) -> Result<&'static [u8], ReturnCode> {
+        Ok(self.identities.name(appid, MAX_DIR_LEN)?.as_bytes())
+    }
+
+    /// Starts `command` for `appid`.
+    fn start(&self, appid: AppId, command: UserCommand) -> Result<(), ReturnCode> {
+        let dir = self.directory(appid)?;
+        self.appid.set(appid);
+        self.dir.set(dir);
+        self.run(command).map_err(|e| {
+            self.operation.set(Operation::None);
+            self.dir.clear();
+            self.appid.clear();
+            e
+        })
+    }
+
+    fn run(&self, command: UserCommand) -> Result<(), ReturnCode> {
+        match command {
+            UserCommand::Open(flags) => {
+                let file = self.find_named()?;
+                let has_room = self.with_app(|app| app.files.iter().any(|file| file.is_none()))?;
+                if !has_room {
+                    return Err(ReturnCode::ENOMEM);
+                }
+                self.operation.set(Operation::Open);
+                match file {
+                    Some(file) if flags & OPEN_TRUNCATE != 0 => self.fs.truncate(file)?,
+                    Some(file) => self.open_done(file),
+                    None if flags & OPEN_CREATE != 0 => self.dir.map_or(Ok(()), |dir| {
+                        let name = self.name.get();
+                        self.fs.create(dir, &name[..self.name_len.get()])
+                    })?,
+                    None => return Err(ReturnCode::FAIL),
+                }
+                Ok(())
+            }
+            UserCommand::Delete => {
+                let file = self.find_named()?.ok_or(ReturnCode::FAIL)?;
+                self.operation.set(Operation::Delete(file));
+                self.fs.delete(file).map_err(ReturnCode::from)
+            }
+            UserCommand::Read { handle, length } => {
+                let open = self.open_file(handle)?;
+                self.operation.set(Operation::Read(handle));
+                if open.offset >= self.fs.size(open.file)? {
+                    // At the end of the file
+                    self.complete(ReturnCode::SUCCESS, 0);
+                    return Ok(());
+                }
+                let length = cmp::min(self.with_app(|app| app.read_buffer.len())?, length);
+                if length == 0 {
+                    return Err(ReturnCode::EINVAL);
+                }
+                let buffer = self.buffer.take().ok_or(ReturnCode::EBUSY)?;
+                self.fs
+                    .read(open.file, open.offset, buffer, length)
+                    .map_err(|(e, buffer)| {
+                        self.buffer.replace(buffer);
+                        ReturnCode::from(e)
+                    })
+            }
+            UserCommand::Write { handle, length } => {
+                let open = self.open_file(handle)?;
+                let buffer = self.buffer.take().ok_or(ReturnCode::EBUSY)?;
+                let ret = self
+                    .with_app(|app| {
+                        app.write_buffer.map_or(Err(ReturnCode::EINVAL), |data| {
+                            let data = data.as_ref();
+                            if length == 0 || length > data.len() {
+                                return Err(ReturnCode::EINVAL);
+                            }
+                            let length = cmp::min(length, buffer.len());
+                            buffer[..length].copy_from_slice(&data[..length]);
+                            Ok(length)
+                        })
+                    })
+                    .and_then(|ret| ret);
+                match ret {
+                    Ok(length) => {
+                        self.operation.set(Operation::Write(handle));
+                        self.fs
+                            .write(open.file, open.offset, buffer, length)
+                            .map_err(|(e, buffer)| {
+                                self.buffer.replace(buffer);
+                                ReturnCode::from(e)
+                            })
+                    }
+                    Err(e) => {
+                        self.buffer.replace(buffer);
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the grant region of the current process.
+    fn with_app<R, F: FnOnce(&mut App) -> R>(&self, f: F) -> Result<R, ReturnCode> {
+        self.appid.map_or(Err(ReturnCode::FAIL), |appid| {
+            self.apps
+                .enter(*appid, |app, _| f(app))
+                .map_err(ReturnCode::from)
+        })
+    }
+
+    /// The open file `handle` of the current process.
+    fn open_file(&self, handle: usize) -> Result<OpenFile, ReturnCode> {
+        self.with_app(|app| app.files.get(handle).copied().flatten())?
+            .ok_or(ReturnCode::EINVAL)
+    }
+
+    /// Copies the name in the name buffer of the current process and finds
+    /// the file with that name in its directory.
+    fn find_named(&self) -> Result<Option<FileId>, ReturnCode> {
+        let mut name = [0; MAX_NAME_LEN];
+        let len = self
+            .with_app(|app| {
+                app.name_buffer.map_or(Err(ReturnCode::EINVAL), |data| {
+                    let data = data.as_ref();
+                    if data.is_empty() || data.len() > MAX_NAME_LEN {
+                        return Err(ReturnCode::EINVAL);
+                    }
+                    name[..data.len()].copy_from_slice(data);
+                    Ok(data.len())
+                })
+            })
+            .and_then(|ret| ret)?;
+        self.name.set(name);
+        self.name_len.set(len);
+        self.dir.map_or(Err(ReturnCode::FAIL), |dir| {
+            self.fs.find(dir, &name[..len]).map_err(ReturnCode::from)
+        })
+    }
+
+    /// Gives the current process a handle for `file`.
+    fn open_done(&self, file: FileId) {
+        let ret = self.with_app(|app| {
+            app.files
+                .iter_mut()
+                .enumerate()
+                .find(|(_, open)| open.is_none())
+                .map(|(handle, open)| {
+                    *open = Some(OpenFile { file, offset: 0 });
+                    handle
+                })
+        });
+        match ret {
+            Ok(Some(handle)) => self.complete(ReturnCode::SUCCESS, handle),
+            Ok(None) => self.complete(ReturnCode::ENOMEM, 0),
+            Err(e) => self.complete(e, 0),
+        }
+    }
+
+    /// Moves the offset of the open file of the current operation past the
+    /// `length` bytes read or written.
+    fn advance(&self, handle: usize, length: usize) {
+        let _ = self.with_app(|app| {
+            app.files[handle].as_mut().map(|open| open.offset += length);
+        });
+    }
+
+    /// Schedules the upcall of `appid`.
+    fn upcall(&self, appid: AppId, result: ReturnCode, arg1: usize) {
+        let _ = self.apps.enter(appid, |app, _| {
+            app.callback.schedule(usize::from(result), arg1, 0);
+        });
+    }
+
+    /// Finishes the current operation, calls the upcall of the application
+    /// and starts the next queued command.
+    fn complete(&self, result: ReturnCode, arg1: usize) {
+        self.operation.set(Operation::None);
+        self.dir.clear();
+        self.appid
+            .take()
+            .map(|appid| self.upcall(appid, result, arg1));
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            // If an app is already running let it complete
+            if self.appid.is_some() {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                app.pending_command
+                    .take()
+                    .map(|command| (app.appid(), command))
+            });
+            if let Some((appid, command)) = pending {
+                if let Err(e) = self.start(appid, command) {
+                    self.upcall(appid, e, 0);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> FileSystemClient for FileSystemDriver<'a> {
+    fn mount_done(&self, _result: Result<(), ErrorCode>) {
+        self.check_queue();
+    }
+
+    fn update_done(&self, result: Result<(), ErrorCode>) {
+        match (self.operation.get(), result) {
+            (Operation::Open, Ok(())) => {
+                let name = self.name.get();
+                let file = self.dir.map_or(Err(ErrorCode::FAIL), |dir| {
+                    self.fs.find(dir, &name[..self.name_len.get()])
+                });
+                match file {
+                    Ok(Some(file)) => self.open_done(file),
+                    Ok(None) => self.complete(ReturnCode::FAIL, 0),
+                    Err(e) => self.complete(ReturnCode::from(e), 0),
+                }
+            }
+            (Operation::Delete(file), Ok(())) => {
+                // Close the file in every process sharing the directory, as
+                // its id can be reused by a new file
+                for appiter in self.apps.iter() {
+                    appiter.enter(|app, _| {
+                        for open in app.files.iter_mut() {
+                            if open.map_or(false, |open| open.file == file) {
+                                *open = None;
+                            }
+                        }
+                    });
+                }
+                self.complete(ReturnCode::SUCCESS, 0);
+            }
+            (Operation::None, _) | (_, Ok(())) => (),
+            (_, Err(e)) => self.complete(ReturnCode::from(e), 0),
+        }
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>) {
+        let ret = result.map_err(ReturnCode::from).and_then(|()| {
+            self.with_app(|app| {
+                app.read_buffer.mut_map_or((), |dest| {
+                    let dest = dest.as_mut();
+                    let length = cmp::min(length, dest.len());
+                    dest[..length].copy_from_slice(&buffer[..length]);
+                });
+            })
+        });
+        self.buffer.replace(buffer);
+        match (self.operation.get(), ret) {
+            (Operation::Read(handle), Ok(())) => {
+                self.advance(handle, length);
+                self.complete(ReturnCode::SUCCESS, length);
+            }
+            (Operation::None, _) => (),
+            (_, ret) => self.complete(ret.err().unwrap_or(ReturnCode::FAIL), 0),
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, result: Result<(), ErrorCode>) {
+        self.buffer.replace(buffer);
+        match (self.operation.get(), result) {
+            (Operation::Write(handle), Ok(())) => {
+                self.advance(handle, length);
+                self.complete(ReturnCode::SUCCESS, length);
+            }
+            (Operation::None, _) | (_, Ok(())) => (),
+            (_, Err(e)) => self.complete(ReturnCode::from(e), 0),
+        }
+    }
+}
+
+impl<'a> Driver for FileSystemDriver<'a> {
+    /// Specify the buffer files are read into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer for the data read by `read` and the names
+    ///        returned by `list`.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.read_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Specify file names and the data written to files.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer containing the name of the file to open or
+    ///        delete.
+    /// - `1`: Allow a buffer containing the data to write.
+    ///
+    /// The buffers should not be changed until the operation has completed.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.name_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.write_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to filesystem events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: u32, arg1: u32)`. After an open,
+    ///        `arg1` is the handle of the open file. After a read or write,
+    ///        `arg1` is the number of bytes read or written.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Run filesystem operations.
+    ///
+    /// Opening, reading, writing and deleting are queued if another
+    /// operation is in progress. Each process can queue one operation.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file named in the name buffer. `data1` holds flags:
+    ///        bit 0 creates the file if it does not exist, and bit 1
+    ///        removes its contents.
+    /// - `2`: Read up to `data2` bytes of open file `data1` into the read
+    ///        buffer.
+    /// - `3`: Write the first `data2` bytes of the write buffer to open file
+    ///        `data1`.
+    /// - `4`: Set the offset of open file `data1` to `data2`.
+    /// - `5`: Close open file `data1`.
+    /// - `6`: Copy the name of file number `data1` of this process into the
+    ///        read buffer and return its length.
+    /// - `7`: Delete the file named in the name buffer.
+    /// - `8`: Get the size of open file `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => UserCommand::Open(data1),
+            2 => UserCommand::Read {
+                handle: data1,
+                length: data2,
+            },
+            3 => UserCommand::Write {
+                handle: data1,
+                length: data2,
+            },
+            4 => {
+                let fs = self.fs;
+                return self
+                    .apps
+                    .enter(appid, |app, _| {
+                        match app.files.get_mut(data1).and_then(|open| open.as_mut()) {
+                            Some(open) => match fs.size(open.file) {
+                                Ok(size) if data2 <= size => {
+                                    open.offset = data2;
+                                    CommandReturn::success()
+                                }
+                                Ok(_) => CommandReturn::failure(ErrorCode::INVAL),
+                                Err(e) => CommandReturn::failure(e),
+                            },
+                            None => CommandReturn::failure(ErrorCode::INVAL),
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+            }
+            5 => {
+                return self
+                    .apps
+                    .enter(appid, |app, _| match app.files.get_mut(data1) {
+                        Some(open) if open.is_some() => {
+                            *open = None;
+                            CommandReturn::success()
+                        }
+                        _ => CommandReturn::failure(ErrorCode::INVAL),
+                    })
+                    .unwrap_or_else(|err| err.into());
+            }
+            6 => {
+                let dir = match self.directory(appid) {
+                    Ok(dir) => dir,
+                    Err(e) => return CommandReturn::from(e),
+                };
+                let fs = self.fs;
+                return self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.read_buffer.mut_map_or(Err(ErrorCode::INVAL), |dest| {
+                            fs.list(dir, data1, dest.as_mut())
+                        })
+                    })
+                    .unwrap_or_else(|err| Err(err.into()))
+                    .map_or_else(CommandReturn::failure, |len| {
+                        CommandReturn::success_u32(len as u32)
+                    });
+            }
+            7 => UserCommand::Delete,
+            8 => {
+                let fs = self.fs;
+                return self
+                    .apps
+                    .enter(appid, |app, _| {
+                        match app.files.get(data1).copied().flatten() {
+                            Some(open) => fs
+                                .size(open.file)
+                                .map_or_else(CommandReturn::failure, |size| {
+                                    CommandReturn::success_u32(size as u32)
+                                }),
+                            None => CommandReturn::failure(ErrorCode::INVAL),
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+            }
+
+            // default
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if self.operation.get() == Operation::None {
+            match self.start(appid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::from(e),
+            }
+        } else {
+            // Some app is using the filesystem, we must wait.
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending_command.is_some() {
+                        // No more room in the queue
+                        CommandReturn::failure(ErrorCode::NOMEM)
+                    } else {
+                        app.pending_command = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    pending_command: Option<UserCommand>,
+    read_buffer: ReadWriteAppSlice,
+    name_buffer: ReadOnlyAppSlice,
+    write_buffer: ReadOnlyAppSlice,
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+}
@@ -0,0 +1,557 @@
+//! IEEE 802.15.4 MAC layer with CSMA-CA, acknowledgements and
+//! retransmissions in software.
+//!
+//! `AwakeMac` leaves channel access and acknowledgements to the radio, and
+//! radio drivers differ in how much of it they implement. `CsmaMac` does it
+//! in software on top of any `kernel::hil::radio::Radio`, so that frames
+//! are sent the same way on every radio:
+//!
+//!   * Before each transmission, the MAC waits a random number of unit
+//!     backoff periods (20 symbols, 320 us) in `[0, 2^BE - 1]`, following the
+//!     unslotted CSMA-CA algorithm of IEEE 802.15.4-2015, 6.2.5.1. The radio
+//!     HIL has no clear channel assessment, so the channel is considered busy
+//!     when the radio reports that it is busy, or refuses the frame with
+//!     `EBUSY`. Each busy channel increases the backoff exponent `BE`, and
+//!     after `max_csma_backoffs` busy channels the frame fails with `EBUSY`.
+//!   * Frames that request an acknowledgement and are not broadcast wait for
+//!     an ACK frame with their sequence number. Without one within
+//!     `ack_wait_us`, the frame is sent again, up to `max_frame_retries`
+//!     times, and then fails with `ENOACK`. If the radio reports a frame as
+//!     acknowledged, the MAC trusts it.
+//!   * Received frames that request an acknowledgement are acknowledged, if
+//!     `software_acks` is set, and repeated frames, with the source address
+//!     and sequence number of the previous frame, are dropped. ACK frames are
+//!     not passed to the receive client.
+//!
+//! The optional `FrameStatsClient` is told, for each frame, how many
+//! transmissions and backoffs it needed, just before the transmit client.
+//!
+//! Usage
+//! -----
+//!
+//! `CsmaMac` is used in place of `AwakeMac`. The buffer passed to
+//! `initialize` holds the ACK frames that the MAC sends.
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! static mut ACK_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+//!
+//! let csma_mac = static_init!(
+//!     capsules::ieee802154::csma::CsmaMac<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::csma::CsmaMac::new(radio, alarm, rng)
+//! );
+//! alarm.set_alarm_client(csma_mac);
+//! rng.set_client(csma_mac);
+//! radio.set_transmit_client(csma_mac);
+//! radio.set_receive_client(csma_mac, &mut RADIO_RX_BUF);
+//! csma_mac.initialize(&mut ACK_BUF);
+//!
+//! let mac_device = static_init!(
+//!     capsules::ieee802154::framer::Framer<'static, CsmaMacDevice>,
+//!     capsules::ieee802154::framer::Framer::new(csma_mac)
+//! );
+//! csma_mac.set_transmit_client(mac_device);
+//! csma_mac.set_receive_client(mac_device);
+//! csma_mac.set_config_client(mac_device);
+//! ```
+
+use crate::ieee802154::mac::Mac;
+use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+/// Duration of a unit backoff period: 20 symbols of 16 us.
+const UNIT_BACKOFF_US: u32 = 320;
+
+/// Length of an ACK frame without its FCS.
+const ACK_FRAME_LEN: usize = 3;
+
+const BROADCAST_ADDRESS: u16 = 0xffff;
+
+/// Parameters of channel access and retransmissions. The defaults are those
+/// of IEEE 802.15.4-2015, 8.4.2, except for the ACK wait, which leaves time
+/// for a peer that acknowledges in software.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CsmaParameters {
+    /// Initial backoff exponent, `macMinBe`.
+    pub min_be: u8,
+    /// Largest backoff exponent, `macMaxBe`.
+    pub max_be: u8,
+    /// Busy channels allowed for one transmission, `macMaxCsmaBackoffs`.
+    pub max_csma_backoffs: u8,
+    /// Retransmissions of a frame that is not acknowledged,
+    /// `macMaxFrameRetries`.
+    pub max_frame_retries: u8,
+    /// Time to wait for an ACK after a transmission, in microseconds.
+    pub ack_wait_us: u32,
+    /// Whether to acknowledge received frames that request it.
+    pub software_acks: bool,
+}
+
+impl Default for CsmaParameters {
+    fn default() -> CsmaParameters {
+        CsmaParameters {
+            min_be: 3,
+            max_be: 5,
+            max_csma_backoffs: 4,
+            max_frame_retries: 3,
+            ack_wait_us: 2000,
+            software_acks: true,
+        }
+    }
+}
+
+/// How a transmitted frame went.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameStats {
+    /// Sequence number of the frame.
+    pub seq: Option<u8>,
+    /// Result reported to the transmit client.
+    pub result: ReturnCode,
+    pub acked: bool,
+    /// Number of times the frame went on air.
+    pub transmissions: u8,
+    /// Number of backoffs, one before each channel access.
+    pub backoffs: u8,
+    /// Number of channel accesses that found the channel busy.
+    pub busy_channels: u8,
+}
+
+pub trait FrameStatsClient {
+    fn frame_stats(&self, stats: &FrameStats);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    // Waiting for randomness, then for the backoff to end.
+    Backoff,
+    Transmitting,
+    WaitingForAck,
+}
+
+pub struct CsmaMac<'a, R: radio::Radio, A: Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    rng: &'a dyn Rng<'a>,
+    parameters: Cell<CsmaParameters>,
+
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    stats_client: OptionalCell<&'static dyn FrameStatsClient>,
+
+    state: Cell<State>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_seq: Cell<Option<u8>>,
+    tx_needs_ack: Cell<bool>,
+    // Busy channels and backoff exponent of the current transmission
+    nb: Cell<u8>,
+    be: Cell<u8>,
+    stats: Cell<FrameStats>,
+
+    ack_buf: TakeCell<'static, [u8]>,
+    ack_transmitting: Cell<bool>,
+    // Source address and sequence number of the last frame received
+    last_rx: Cell<Option<(Option<MacAddress>, u8)>>,
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> CsmaMac<'a, R, A> {
+    pub fn new(radio: &'a R, alarm: &'a A, rng: &'a dyn Rng<'a>) -> CsmaMac<'a, R, A> {
+        CsmaMac {
+            radio,
+            alarm,
+            rng,
+            parameters: Cell::new(CsmaParameters::default()),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            stats_client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_seq: Cell::new(None),
+            tx_needs_ack: Cell::new(false),
+            nb: Cell::new(0),
+            be: Cell::new(0),
+            stats: Cell::new(FrameStats {
+                seq: None,
+                result: ReturnCode::SUCCESS,
+                acked: false,
+                transmissions: 0,
+                backoffs: 0,
+                busy_channels: 0,
+            }),
+            ack_buf: TakeCell::empty(),
+            ack_transmitting: Cell::new(false),
+            last_rx: Cell::new(None),
+        }
+    }
+
+    pub fn parameters(&self) -> CsmaParameters {
+        self.parameters.get()
+    }
+
+    /// Change the parameters of channel access and retransmissions.
+    pub fn set_parameters(&self, parameters: CsmaParameters) {
+        self.parameters.set(parameters);
+    }
+
+    pub fn set_stats_client(&self, client: &'static dyn FrameStatsClient) {
+        self.stats_client.set(client);
+    }
+
+    fn update_stats<F: FnOnce(&mut FrameStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// Start the CSMA-CA algorithm for a transmission of the frame.
+    fn start_csma(&self) -> ReturnCode {
+        self.nb.set(0);
+        self.be.set(self.parameters.get().min_be);
+        self.backoff()
+    }
+
+    /// Ask for the randomness of the next backoff.
+    fn backoff(&self) -> ReturnCode {
+        self.state.set(State::Backoff);
+        self.update_stats(|stats| stats.backoffs += 1);
+        let result = self.rng.get();
+        if result != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+        }
+        result
+    }
+
+    /// Wait a random number of unit backoff periods, from `random`.
+    fn wait_backoff(&self, random: u32) {
+        let periods = random & ((1 << self.be.get()) - 1);
+        if periods == 0 {
+            self.transmit_frame();
+        } else {
+            let dt = A::ticks_from_us(periods * UNIT_BACKOFF_US);
+            self.alarm.set_alarm(self.alarm.now(), dt);
+        }
+    }
+
+    fn transmit_frame(&self) {
+        if self.radio.busy() {
+            self.channel_busy();
+            return;
+        }
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        self.state.set(State::Transmitting);
+        match self.radio.transmit(buf, self.tx_len.get()) {
+            (ReturnCode::SUCCESS, _) => {
+                self.update_stats(|stats| stats.transmissions += 1);
+            }
+            (result, buf) => {
+                if let Some(buf) = buf {
+                    self.tx_buf.replace(buf);
+                }
+                if result == ReturnCode::EBUSY {
+                    self.channel_busy();
+                } else {
+                    self.finish(result, false);
+                }
+            }
+        }
+    }
+
+    fn channel_busy(&self) {
+        let parameters = self.parameters.get();
+        self.update_stats(|stats| stats.busy_channels += 1);
+        self.nb.set(self.nb.get() + 1);
+        self.be
+            .set(core::cmp::min(self.be.get() + 1, parameters.max_be));
+        let result = if self.nb.get() > parameters.max_csma_backoffs {
+            ReturnCode::EBUSY
+        } else {
+            self.backoff()
+        };
+        if result != ReturnCode::SUCCESS {
+            self.finish(result, false);
+        }
+    }
+
+    fn no_ack(&self) {
+        let retries = self.stats.get().transmissions - 1;
+        let result = if retries < self.parameters.get().max_frame_retries {
+            self.start_csma()
+        } else {
+            ReturnCode::ENOACK
+        };
+        if result != ReturnCode::SUCCESS {
+            self.finish(result, false);
+        }
+    }
+
+    fn finish(&self, result: ReturnCode, acked: bool) {
+        self.state.set(State::Idle);
+        self.update_stats(|stats| {
+            stats.result = result;
+            stats.acked = acked;
+        });
+        let stats = self.stats.get();
+        self.stats_client.map(|client| client.frame_stats(&stats));
+        self.tx_buf.take().map(|buf| {
+            self.tx_client.map(move |client| {
+                client.send_done(buf, acked, result);
+            });
+        });
+    }
+
+    fn is_for_us(&self, dst_addr: Option<MacAddress>) -> bool {
+        match dst_addr {
+            Some(MacAddress::Short(addr)) => {
+                addr == BROADCAST_ADDRESS || addr == self.radio.get_address()
+            }
+            Some(MacAddress::Long(addr)) => addr == self.radio.get_address_long(),
+            None => false,
+        }
+    }
+
+    /// Acknowledge the frame with sequence number `seq`, if the ACK buffer
+    /// and the radio are free.
+    fn send_ack(&self, seq: u8) {
+        if self.state.get() == State::Transmitting {
+            return;
+        }
+        self.ack_buf.take().map(|buf| {
+            let header = Header {
+                frame_type: FrameType::Acknowledgement,
+                frame_pending: false,
+                ack_requested: false,
+                version: FrameVersion::V2006,
+                seq: Some(seq),
+                dst_pan: None,
+                dst_addr: None,
+                src_pan: None,
+                src_addr: None,
+                security: None,
+                header_ies: Default::default(),
+                header_ies_len: 0,
+                payload_ies: Default::default(),
+                payload_ies_len: 0,
+            };
+            if header
+                .encode(&mut buf[radio::PSDU_OFFSET..], false)
+                .done()
+                .is_none()
+            {
+                self.ack_buf.replace(buf);
+                return;
+            }
+            match self.radio.transmit(buf, ACK_FRAME_LEN) {
+                (ReturnCode::SUCCESS, _) => self.ack_transmitting.set(true),
+                (_, buf) => {
+                    if let Some(buf) = buf {
+                        self.ack_buf.replace(buf);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for CsmaMac<'a, R, A> {
+    fn initialize(&self, mac_buf: &'static mut [u8]) -> ReturnCode {
+        if mac_buf.len() < radio::PSDU_OFFSET + ACK_FRAME_LEN + radio::MFR_SIZE {
+            return ReturnCode::ESIZE;
+        }
+        self.ack_buf.replace(mac_buf);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.radio.is_on()
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.radio.set_config_client(client)
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.radio.set_pan(id)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.radio.get_pan()
+    }
+
+    fn config_commit(&self) {
+        self.radio.config_commit()
+    }
+
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(buffer);
+    }
+
+    fn transmit(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.state.get() != State::Idle {
+            return (ReturnCode::EBUSY, Some(full_mac_frame));
+        }
+        let header = match Header::decode(&full_mac_frame[radio::PSDU_OFFSET..], false).done() {
+            Some((_, (header, _))) => header,
+            None => return (ReturnCode::EINVAL, Some(full_mac_frame)),
+        };
+        let broadcast = header.dst_addr == Some(MacAddress::Short(BROADCAST_ADDRESS));
+        self.tx_needs_ack
+            .set(header.ack_requested && header.seq.is_some() && !broadcast);
+        self.tx_seq.set(header.seq);
+        self.stats.set(FrameStats {
+            seq: header.seq,
+            result: ReturnCode::SUCCESS,
+            acked: false,
+            transmissions: 0,
+            backoffs: 0,
+            busy_channels: 0,
+        });
+
+        self.tx_buf.replace(full_mac_frame);
+        self.tx_len.set(frame_len);
+        match self.start_csma() {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            result => (result, self.tx_buf.take()),
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> rng::Client for CsmaMac<'a, R, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        match randomness.next() {
+            Some(random) => {
+                if self.state.get() == State::Backoff {
+                    self.wait_backoff(random);
+                }
+                rng::Continue::Done
+            }
+            None => rng::Continue::More,
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> time::AlarmClient for CsmaMac<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Backoff => self.transmit_frame(),
+            State::WaitingForAck => self.no_ack(),
+            State::Idle | State::Transmitting => {}
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> radio::TxClient for CsmaMac<'a, R, A> {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+        if self.ack_transmitting.get() {
+            self.ack_transmitting.set(false);
+            self.ack_buf.replace(buf);
+            return;
+        }
+        self.tx_buf.replace(buf);
+        match result {
+            ReturnCode::SUCCESS => {
+                if acked || !self.tx_needs_ack.get() {
+                    self.finish(ReturnCode::SUCCESS, acked);
+                } else {
+                    self.state.set(State::WaitingForAck);
+                    let dt = A::ticks_from_us(self.parameters.get().ack_wait_us);
+                    self.alarm.set_alarm(self.alarm.now(), dt);
+                }
+            }
+            ReturnCode::ENOACK => self.no_ack(),
+            ReturnCode::EBUSY => self.channel_busy(),
+            _ => self.finish(result, false),
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: Alarm<'a>> radio::RxClient for CsmaMac<'a, R, A> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        crc_valid: bool,
+        result: ReturnCode,
+    ) {
+        let header = match Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
+            Some((_, (header, _))) if crc_valid => header,
+            _ => {
+                self.radio.set_receive_buffer(buf);
+                return;
+            }
+        };
+
+        if header.frame_type == FrameType::Acknowledgement {
+            if self.state.get() == State::WaitingForAck && header.seq == self.tx_seq.get() {
+                self.alarm.disarm();
+                self.finish(ReturnCode::SUCCESS, true);
+            }
+            self.radio.set_receive_buffer(buf);
+            return;
+        }
+
+        if !self.is_for_us(header.dst_addr) {
+            self.radio.set_receive_buffer(buf);
+            return;
+        }
+
+        if let Some(seq) = header.seq {
+            let broadcast = header.dst_addr == Some(MacAddress::Short(BROADCAST_ADDRESS));
+            if header.ack_requested && !broadcast && self.parameters.get().software_acks {
+                self.send_ack(seq);
+            }
+            // A repeated frame is one whose ACK was lost
+            let rx = (header.src_addr, seq);
+            if self.last_rx.replace(Some(rx)) == Some(rx) {
+                self.radio.set_receive_buffer(buf);
+                return;
+            }
+        }
+
+        self.rx_client.map(move |client| {
+            client.receive(buf, frame_len, crc_valid, result);
+        });
+    }
+}
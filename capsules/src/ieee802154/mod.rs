@@ -1,5 +1,6 @@
 //! Support for IEEE 802.15.4.
 
+pub mod csma;
 pub mod device;
 pub mod framer;
 pub mod mac;
@@ -0,0 +1,877 @@
+//! Key-value storage for applications.
+//!
+//! This capsule exposes a `hil::kv_system` implementation, such as
+//! `capsules::tickv::TicKVStore`, to applications as a key-value store with
+//! get, set and delete operations.
+//!
+//! Each application has its own namespace. Keys are hashed together with the
+//! name the board's `AppIdentities` table gives the application, so an
+//! application cannot read, change or delete the values of another
+//! application, and keeps its values when it is updated. Applications the
+//! table has no name for cannot use this driver (see `app_identity`).
+//!
+//! Each stored value is preceded by a header that contains the unhashed key
+//! and name it belongs to. This is checked when the value is read, so two
+//! keys whose hashes collide can not be used to access each other's values.
+//! Setting a key whose hash collides with the key of another application
+//! fails with `ENOSUPPORT`.
+//!
+//! The number of bytes each process has stored, including the headers, is
+//! limited to the quota given to `new()`. It is counted by finding every
+//! value stored under the name of the process the first time the process
+//! sets a value or asks for its usage, and kept up to date in its grant
+//! after that.
+//!
+//! Replacing a value uses `KVSystem::update_key()`, so the key keeps either
+//! its old or its new value if power is lost.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Applications         |
+//! |                       |
+//! +-----------------------+
+//!
+//!    syscalls
+//!
+//! +-----------------------+
+//! |                       |
+//! |  K-V (this file)      |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::kv_system
+//!
+//! +-----------------------+
+//! |                       |
+//! |  TicKV                |
+//! |                       |
+//! +-----------------------+
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kv_store = static_init!(
+//!     capsules::kv_driver::KVStoreDriver<
+//!         'static,
+//!         capsules::tickv::TicKVStore<'static, FlashUser<'static, FlashCtrl<'static>>>,
+//!         capsules::tickv::TicKVKeyType,
+//!     >,
+//!     capsules::kv_driver::KVStoreDriver::new(
+//!         tickv,
+//!         static_init!(capsules::tickv::TicKVKeyType, [0; 8]),
+//!         static_init!([u8; capsules::kv_driver::UNHASHED_KEY_LEN], [0; capsules::kv_driver::UNHASHED_KEY_LEN]),
+//!         static_init!([u8; 256], [0; 256]),
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!         identities,
+//!         1024,
+//!     )
+//! );
+//! tickv.set_client(kv_store);
+//! ```
+
+use crate::app_identity::AppIdentities;
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::KVStore as usize;
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::kv_system::{self, KVSystem, KeyType};
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, ReturnCode, Upcall,
+};
+
+/// The maximum length of a key passed by an application.
+pub const MAX_KEY_LEN: usize = 32;
+
+/// The maximum length of the application name that is used in a key. Apps
+/// with longer names cannot use this driver.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// The length of the buffer the unhashed keys are built in.
+pub const UNHASHED_KEY_LEN: usize = 2 + MAX_KEY_LEN + MAX_NAME_LEN;
+
+/// The version of the header stored before each value.
+const HEADER_VERSION: u8 = 0;
+
+/// The length of the fixed part of the header: the version, the length of
+/// the unhashed key and the length of the value, in that order. The unhashed
+/// key follows.
+const HEADER_LEN: usize = 4;
+
+/// The commands an application can queue.
+#[derive(Clone, Copy, PartialEq)]
+enum UserCommand {
+    Get,
+    Set,
+    Delete,
+    Usage,
+}
+
+/// The operation in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    /// Counting the bytes stored by the process before running `command`.
+    /// `used` bytes were found so far.
+    Scan {
+        command: UserCommand,
+        used: usize,
+    },
+    Get,
+    /// Finding the value that a set replaces.
+    SetLookup,
+    /// Storing a new value, `new` bytes long.
+    SetAppend {
+        new: usize,
+    },
+    /// Replacing the value, `old` bytes long, with a new value, `new` bytes
+    /// long.
+    SetUpdate {
+        old: usize,
+        new: usize,
+    },
+    /// Finding the value to delete.
+    DeleteLookup,
+    /// Removing the value to delete, `old` bytes long.
+    DeleteInvalidate {
+        old: usize,
+    },
+}
+
+pub struct KVStoreDriver<'a, K: KVSystem<'a, K = T>, T: 'static + KeyType> {
+    kv: &'a K,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    operation: Cell<Operation>,
+    /// The names the values of applications are stored under.
+    identities: &'static AppIdentities,
+    /// The number of bytes each process may store.
+    quota: usize,
+
+    key_buffer: TakeCell<'static, T>,
+    unhashed_key_buffer: TakeCell<'static, [u8]>,
+    data_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> KVStoreDriver<'a, K, T> {
+    /// Creates the driver.
+    ///
+    /// `unhashed_key_buffer` must be at least `UNHASHED_KEY_LEN` bytes long.
+    /// Values are limited by the length of `data_buffer`, less the length of
+    /// the header. Each process can store up to `quota` bytes.
+    pub fn new(
+        kv: &'a K,
+        key_buffer: &'static mut T,
+        unhashed_key_buffer: &'static mut [u8],
+        data_buffer: &'static mut [u8],
+        grant: Grant<App>,
+        identities: &'static AppIdentities,
+        quota: usize,
+    ) -> KVStoreDriver<'a, K, T> {
+        KVStoreDriver {
+            kv,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            operation: Cell::new(Operation::None),
+            identities,
+            quota,
+            key_buffer: TakeCell::new(key_buffer),
+            unhashed_key_buffer: TakeCell::new(unhashed_key_buffer),
+            data_buffer: TakeCell::new(data_buffer),
+        }
+    }
+
+    /// Starts `command` for `appid`, by counting the bytes the process has
+    /// stored if they are needed and not known yet, or else by generating
+    /// the hashed key.
+    fn start(&self, appid: AppId, command: UserCommand) -> Result<(), ReturnCode> {
+        let name = self.identities.name(appid, MAX_NAME_LEN)?.as_bytes();
+
+        let used = self
+            .apps
+            .enter(appid, |app, _| app.used)
+            .map_err(ReturnCode::from)?;
+        match (command, used) {
+            (UserCommand::Set, None) | (UserCommand::Usage, None) => {
+                return self.start_scan(appid, command)
+            }
+            (UserCommand::Usage, Some(used)) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback
+                        .schedule(usize::from(ReturnCode::SUCCESS), used, self.quota);
+                });
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let unhashed_key = self.unhashed_key_buffer.take().ok_or(ReturnCode::EBUSY)?;
+        let ret = self
+            .apps
+            .enter(appid, |app, _| {
+                app.key.map_or(Err(ReturnCode::EINVAL), |key| {
+                    let key = key.as_ref();
+                    if key.is_empty() || key.len() > MAX_KEY_LEN {
+                        return Err(ReturnCode::EINVAL);
+                    }
+
+                    // The unhashed key is the key and the name, each preceded
+                    // by its length. The rest of the buffer is zeroed, as the
+                    // whole buffer is hashed.
+                    for byte in unhashed_key.iter_mut() {
+                        *byte = 0;
+                    }
+                    unhashed_key[0] = key.len() as u8;
+                    unhashed_key[1..1 + key.len()].copy_from_slice(key);
+                    unhashed_key[1 + key.len()] = name.len() as u8;
+                    unhashed_key[2 + key.len()..2 + key.len() + name.len()].copy_from_slice(name);
+                    Ok(())
+                })
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+        if let Err(e) = ret {
+            self.unhashed_key_buffer.replace(unhashed_key);
+            return Err(e);
+        }
+
+        let key = match self.key_buffer.take() {
+            Some(key) => key,
+            None => {
+                self.unhashed_key_buffer.replace(unhashed_key);
+                return Err(ReturnCode::EBUSY);
+            }
+        };
+
+        self.appid.set(appid);
+        self.operation.set(match command {
+            UserCommand::Get => Operation::Get,
+            UserCommand::Set => Operation::SetLookup,
+            _ => Operation::DeleteLookup,
+        });
+
+        // The key may be generated, and the operation continued, before this
+        // returns.
+        if let Err((unhashed_key, key, e)) = self.kv.generate_key(unhashed_key, key) {
+            self.unhashed_key_buffer.replace(unhashed_key);
+            self.key_buffer.replace(key);
+            self.appid.clear();
+            self.operation.set(Operation::None);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Starts counting the bytes stored by `appid` by going through every
+    /// key in the store. `command` is started once this is done.
+    fn start_scan(&self, appid: AppId, command: UserCommand) -> Result<(), ReturnCode> {
+        let key = self.key_buffer.take().ok_or(ReturnCode::EBUSY)?;
+
+        self.appid.set(appid);
+        self.operation.set(Operation::Scan { command, used: 0 });
+        if let Err((key, e)) = self.kv.next_key(key, true) {
+            self.key_buffer.replace(key);
+            self.appid.clear();
+            self.operation.set(Operation::None);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Records the `used` bytes found by the scan and starts `command`.
+    fn finish_scan(&self, command: UserCommand, used: usize) {
+        self.operation.set(Operation::None);
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.used = Some(used);
+            });
+            if let Err(e) = self.start(appid, command) {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback.schedule(usize::from(e), 0, 0);
+                });
+            }
+        });
+        if self.appid.is_none() {
+            self.check_queue();
+        }
+    }
+
+    /// Returns the total length of the stored value in `data` and the
+    /// unhashed key it belongs to.
+    fn stored_owner<'b>(&self, data: &'b [u8]) -> Option<(usize, &'b [u8])> {
+        let owner_len = data.get(1).map(|len| *len as usize)?;
+        let owner = data.get(HEADER_LEN..HEADER_LEN + owner_len)?;
+        if data[0] != HEADER_VERSION {
+            return None;
+        }
+        let value_len = u16::from_le_bytes([data[2], data[3]]) as usize;
+        let total_len = HEADER_LEN + owner_len + value_len;
+        if total_len > data.len() {
+            return None;
+        }
+        Some((total_len, owner))
+    }
+
+    /// Returns the total length of the stored value in `data` if it was
+    /// stored under the name of the current process.
+    fn scanned_length(&self, data: &[u8]) -> Option<usize> {
+        let (total_len, owner) = self.stored_owner(data)?;
+        let key_len = *owner.get(0)? as usize;
+        let name_len = *owner.get(1 + key_len)? as usize;
+        let name = owner.get(2 + key_len..)?;
+        let expected = self.appid.map_or(None, |appid| {
+            self.identities.name(*appid, MAX_NAME_LEN).ok()
+        })?;
+
+        if name.len() != name_len || name != expected.as_bytes() {
+            return None;
+        }
+        Some(total_len)
+    }
+
+    /// Returns the total length of the stored value in `data` and the length
+    /// of the value the application set, if it belongs to the current unhashed
+    /// key.
+    fn stored_length(&self, data: &[u8]) -> Option<(usize, usize)> {
+        let (total_len, owner) = self.stored_owner(data)?;
+        self.unhashed_key_buffer.map_or(None, |unhashed_key| {
+            let key_len = unhashed_key[0] as usize;
+            let unhashed_len = 2 + key_len + unhashed_key[1 + key_len] as usize;
+
+            if owner != &unhashed_key[..unhashed_len] {
+                return None;
+            }
+            Some((total_len, total_len - HEADER_LEN - owner.len()))
+        })
+    }
+
+    /// Stores the value of the application, replacing `old` bytes of stored
+    /// value if the key exists.
+    fn set_value(&self, key: &'static mut T, old: Option<usize>) {
+        let data = match self.data_buffer.take() {
+            Some(data) => data,
+            None => {
+                self.key_buffer.replace(key);
+                self.complete(ReturnCode::FAIL, 0);
+                return;
+            }
+        };
+
+        let ret = self.appid.map_or(Err(ReturnCode::FAIL), |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    let unhashed_len = self.unhashed_key_buffer.map_or(0, |unhashed_key| {
+                        let key_len = unhashed_key[0] as usize;
+                        let unhashed_len = 2 + key_len + unhashed_key[1 + key_len] as usize;
+                        data[HEADER_LEN..HEADER_LEN + unhashed_len]
+                            .copy_from_slice(&unhashed_key[..unhashed_len]);
+                        unhashed_len
+                    });
+
+                    app.value.map_or(Err(ReturnCode::EINVAL), |value| {
+                        let value = value.as_ref();
+                        let offset = HEADER_LEN + unhashed_len;
+                        let new = offset + value.len();
+                        if new > data.len() || value.len() > u16::MAX as usize {
+                            return Err(ReturnCode::ESIZE);
+                        }
+                        let used = app.used.unwrap_or(0);
+                        if used.saturating_sub(old.unwrap_or(0)) + new > self.quota {
+                            return Err(ReturnCode::ENOMEM);
+                        }
+
+                        data[0] = HEADER_VERSION;
+                        data[1] = unhashed_len as u8;
+                        data[2..4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+                        data[offset..new].copy_from_slice(value);
+                        Ok(new)
+                    })
+                })
+                .unwrap_or_else(|err| Err(err.into()))
+        });
+
+        let new = match ret {
+            Ok(new) => new,
+            Err(e) => {
+                self.data_buffer.replace(data);
+                self.key_buffer.replace(key);
+                self.complete(e, 0);
+                return;
+            }
+        };
+
+        let ret = match old {
+            Some(old) => {
+                self.operation.set(Operation::SetUpdate { old, new });
+                self.kv.update_key(key, data, new)
+            }
+            None => {
+                self.operation.set(Operation::SetAppend { new });
+                self.kv.append_key(key, data, new)
+            }
+        };
+        if let Err((key, data, e)) = ret {
+            self.key_buffer.replace(key);
+            self.data_buffer.replace(data);
+            self.complete(e, 0);
+        }
+    }
+
+    /// Updates the number of bytes stored by the current process after
+    /// `removed` bytes were removed and `added` bytes were added.
+    fn update_used(&self, removed: usize, added: usize) {
+        self.appid.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.used = app.used.map(|used| used.saturating_sub(removed) + added);
+            });
+        });
+    }
+
+    /// Forgets the number of bytes stored by the current process, after a
+    /// write failed part way through. They are counted again when needed.
+    fn forget_used(&self) {
+        self.appid.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.used = None;
+            });
+        });
+    }
+
+    /// Finishes the current operation, calls the upcall of the application
+    /// and starts the next queued command.
+    fn complete(&self, result: ReturnCode, length: usize) {
+        self.operation.set(Operation::None);
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.schedule(usize::from(result), length, 0);
+            });
+        });
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            // If an app is already running let it complete
+            if self.appid.is_some() {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                app.pending_command
+                    .take()
+                    .map(|command| (app.appid(), command))
+            });
+            if let Some((appid, command)) = pending {
+                if let Err(e) = self.start(appid, command) {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback.schedule(usize::from(e), 0, 0);
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> kv_system::Client<T> for KVStoreDriver<'a, K, T> {
+    fn generate_key_complete(
+        &self,
+        result: Result<(), ReturnCode>,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut T,
+    ) {
+        self.unhashed_key_buffer.replace(unhashed_key);
+
+        if let Err(e) = result {
+            self.key_buffer.replace(key_buf);
+            self.complete(e, 0);
+            return;
+        }
+
+        // Every operation starts by reading the current value of the key.
+        match self.data_buffer.take() {
+            Some(data) => {
+                if let Err((key, data, e)) = self.kv.get_value(key_buf, data) {
+                    self.key_buffer.replace(key);
+                    self.data_buffer.replace(data);
+                    self.complete(e, 0);
+                }
+            }
+            None => {
+                self.key_buffer.replace(key_buf);
+                self.complete(ReturnCode::FAIL, 0);
+            }
+        }
+    }
+
+    fn append_key_complete(
+        &self,
+        result: Result<(), ReturnCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.key_buffer.replace(key);
+        self.data_buffer.replace(value);
+
+        if let Operation::SetAppend { new } = self.operation.get() {
+            match result {
+                Ok(()) => {
+                    self.update_used(0, new);
+                    self.complete(ReturnCode::SUCCESS, 0);
+                }
+                Err(e) => {
+                    self.forget_used();
+                    self.complete(e, 0);
+                }
+            }
+        }
+    }
+
+    fn get_value_complete(
+        &self,
+        result: Result<(), ReturnCode>,
+        key: &'static mut T,
+        ret_buf: &'static mut [u8],
+    ) {
+        if let Operation::Scan { command, used } = self.operation.get() {
+            // Values that can not be read into the data buffer were not
+            // stored by this driver, so errors only skip the key.
+            let found = match result {
+                Ok(()) => self.scanned_length(ret_buf).unwrap_or(0),
+                Err(_) => 0,
+            };
+            self.data_buffer.replace(ret_buf);
+            self.operation.set(Operation::Scan {
+                command,
+                used: used + found,
+            });
+            if let Err((key, e)) = self.kv.next_key(key, false) {
+                self.key_buffer.replace(key);
+                self.complete(e, 0);
+            }
+            return;
+        }
+
+        // A value stored for a different unhashed key with the same hash is
+        // treated as a collision.
+        let found = match result {
+            Ok(()) => self.stored_length(ret_buf).ok_or(ReturnCode::ENOSUPPORT),
+            Err(e) => Err(e),
+        };
+
+        match self.operation.get() {
+            Operation::Get => {
+                self.key_buffer.replace(key);
+                let ret = found.and_then(|(total_len, value_len)| {
+                    self.appid.map_or(Err(ReturnCode::FAIL), |appid| {
+                        self.apps
+                            .enter(*appid, |app, _| {
+                                app.dest.mut_map_or((), |dest| {
+                                    let dest = dest.as_mut();
+                                    let len = cmp::min(value_len, dest.len());
+                                    let start = total_len - value_len;
+                                    dest[..len].copy_from_slice(&ret_buf[start..start + len]);
+                                });
+                                Ok(value_len)
+                            })
+                            .unwrap_or_else(|err| Err(err.into()))
+                    })
+                });
+                self.data_buffer.replace(ret_buf);
+                match ret {
+                    Ok(value_len) => self.complete(ReturnCode::SUCCESS, value_len),
+                    Err(e) => self.complete(e, 0),
+                }
+            }
+            Operation::SetLookup => {
+                // Don't store the value if the key could not be read, unless
+                // it doesn't exist yet.
+                let exists = match result {
+                    Ok(()) => found.map(|(total_len, _)| Some(total_len)),
+                    Err(ReturnCode::ENOSUPPORT) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                self.data_buffer.replace(ret_buf);
+                match exists {
+                    Ok(old) => self.set_value(key, old),
+                    Err(e) => {
+                        self.key_buffer.replace(key);
+                        self.complete(e, 0);
+                    }
+                }
+            }
+            Operation::DeleteLookup => {
+                self.data_buffer.replace(ret_buf);
+                match found {
+                    Ok((total_len, _)) => {
+                        self.operation
+                            .set(Operation::DeleteInvalidate { old: total_len });
+                        if let Err((key, e)) = self.kv.invalidate_key(key) {
+                            self.key_buffer.replace(key);
+                            self.complete(e, 0);
+                        }
+                    }
+                    Err(e) => {
+                        self.key_buffer.replace(key);
+                        self.complete(e, 0);
+                    }
+                }
+            }
+            _ => {
+                self.key_buffer.replace(key);
+                self.data_buffer.replace(ret_buf);
+            }
+        }
+    }
+
+    fn update_key_complete(
+        &self,
+        result: Result<(), ReturnCode>,
+        key: &'static mut T,
+        value: &'static mut [u8],
+    ) {
+        self.key_buffer.replace(key);
+        self.data_buffer.replace(value);
+
+        if let Operation::SetUpdate { old, new } = self.operation.get() {
+            match result {
+                Ok(()) => {
+                    self.update_used(old, new);
+                    self.complete(ReturnCode::SUCCESS, 0);
+                }
+                Err(e) => {
+                    self.forget_used();
+                    self.complete(e, 0);
+                }
+            }
+        }
+    }
+
+    fn invalidate_key_complete(&self, result: Result<(), ReturnCode>, key: &'static mut T) {
+        self.key_buffer.replace(key);
+
+        if let Operation::DeleteInvalidate { old } = self.operation.get() {
+            match result {
+                Ok(()) => {
+                    self.update_used(old, 0);
+                    self.complete(ReturnCode::SUCCESS, 0);
+                }
+                Err(e) => {
+                    self.forget_used();
+                    self.complete(e, 0);
+                }
+            }
+        }
+    }
+
+    fn next_key_complete(&self, result: Result<usize, ReturnCode>, key_buf: &'static mut T) {
+        let (command, used) = match self.operation.get() {
+            Operation::Scan { command, used } => (command, used),
+            _ => {
+                self.key_buffer.replace(key_buf);
+                return;
+            }
+        };
+
+        match result {
+            Ok(_) => match self.data_buffer.take() {
+                Some(data) => {
+                    if let Err((key, data, e)) = self.kv.get_value(key_buf, data) {
+                        self.key_buffer.replace(key);
+                        self.data_buffer.replace(data);
+                        self.complete(e, 0);
+                    }
+                }
+                None => {
+                    self.key_buffer.replace(key_buf);
+                    self.complete(ReturnCode::FAIL, 0);
+                }
+            },
+            // Every key has been found.
+            Err(ReturnCode::ENOSUPPORT) => {
+                self.key_buffer.replace(key_buf);
+                self.finish_scan(command, used);
+            }
+            Err(e) => {
+                self.key_buffer.replace(key_buf);
+                self.complete(e, 0);
+            }
+        }
+    }
+
+    fn garbage_collect_complete(&self, _result: Result<(), ReturnCode>) {}
+}
+
+impl<'a, K: KVSystem<'a, K = T>, T: KeyType> Driver for KVStoreDriver<'a, K, T> {
+    /// Specify the buffer the value of a key is read into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer for the value read by `get`. If the value is
+    ///        longer than the buffer, only the start of the value is copied.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.dest);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Specify the key and the value.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer containing the key. Keys are 1 to `MAX_KEY_LEN`
+    ///        bytes long.
+    /// - `1`: Allow a buffer containing the value stored by `set`.
+    ///
+    /// The buffers should not be changed until the operation has completed.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.key);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.value);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to key-value events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: u32, length: u32, quota: u32)`,
+    ///        where `length` is the length of the value read by `get`, or
+    ///        the number of bytes stored by the process for command 4, and
+    ///        `quota` is the quota of the process for command 4.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Run key-value operations.
+    ///
+    /// Operations on the key are queued if another operation is in progress.
+    /// Each process can queue one operation.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the value of the key.
+    /// - `2`: Set the key to the value, replacing any previous value.
+    /// - `3`: Delete the key.
+    /// - `4`: Get the number of bytes stored by this process and its quota.
+    ///        Both are passed to the upcall.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => UserCommand::Get,
+            2 => UserCommand::Set,
+            3 => UserCommand::Delete,
+            4 => UserCommand::Usage,
+
+            // default
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if self.operation.get() == Operation::None {
+            match self.start(appid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::from(e),
+            }
+        } else {
+            // Some app is using the store, we must wait.
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending_command.is_some() {
+                        // No more room in the queue
+                        CommandReturn::failure(ErrorCode::NOMEM)
+                    } else {
+                        app.pending_command = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    pending_command: Option<UserCommand>,
+    key: ReadOnlyAppSlice,
+    value: ReadOnlyAppSlice,
+    dest: ReadWriteAppSlice,
+    /// The number of bytes stored by this process, if they were counted.
+    used: Option<usize>,
+}
@@ -9,12 +9,18 @@ pub mod net;
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod aes;
+pub mod aes_modes;
+pub mod aes_software;
 pub mod alarm;
 pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
+pub mod app_identity;
+pub mod app_watchdog;
+pub mod ble;
 pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
@@ -23,8 +29,12 @@ pub mod console;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
+pub mod date_time;
+pub mod date_time_software;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod filesystem;
+pub mod filesystem_driver;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -37,10 +47,12 @@ pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod kv_driver;
 pub mod l3gd20;
 pub mod led;
 pub mod led_matrix;
 pub mod log;
+pub mod log_driver;
 pub mod low_level_debug;
 pub mod lps25hb;
 pub mod lsm303agr;
@@ -55,18 +67,22 @@ pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
+pub mod p256;
 pub mod panic_button;
 pub mod pca9544a;
 pub mod process_console;
 pub mod proximity;
+pub mod pwm;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha256;
 pub mod sht3x;
 pub mod si7021;
+pub mod signature_driver;
 pub mod sound_pressure;
 pub mod spi_controller;
 pub mod spi_peripheral;
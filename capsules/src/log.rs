@@ -758,7 +758,8 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
 
     /// Sync log to storage.
     /// ReturnCodes used:
-    ///     * SUCCESS: flush started successfully.
+    ///     * SUCCESS: flush started successfully, or nothing to flush. `sync_done` is called
+    ///       in both cases.
     ///     * FAIL: flash driver not configured.
     ///     * EBUSY: log or flash driver busy, try again later.
     ///     * ERESERVE: no log client set.
@@ -766,12 +767,15 @@ impl<'a, F: Flash + 'static> LogWrite<'a> for Log<'a, F> {
     ///     * SUCCESS: append succeeded.
     ///     * FAIL: write failed due to flash error.
     fn sync(&self) -> ReturnCode {
-        if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
-            // Pagebuffer empty, don't need to flush.
-            return ReturnCode::SUCCESS;
-        } else if self.state.get() != State::Idle {
+        if self.state.get() != State::Idle {
             // Log busy, try appending again later.
             return ReturnCode::EBUSY;
+        } else if self.append_entry_id.get() % self.page_size == PAGE_HEADER_SIZE {
+            // Pagebuffer empty, don't need to flush.
+            self.state.set(State::Sync);
+            self.error.set(ReturnCode::SUCCESS);
+            self.deferred_client_callback();
+            return ReturnCode::SUCCESS;
         }
 
         self.pagebuffer
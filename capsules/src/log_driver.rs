@@ -0,0 +1,669 @@
+//! Persistent logs for applications.
+//!
+//! This capsule exposes a `hil::log` implementation, such as
+//! `capsules::log::Log`, to applications as an append-only log. Applications
+//! append entries, read them back in order, seek to positions they saved
+//! earlier, sync the log to storage and erase it.
+//!
+//! All applications share the underlying log. Each entry is preceded by a
+//! header with the name the board's `AppIdentities` table gives the
+//! application that appended it, and entries of other applications are
+//! skipped when reading, so each application sees a log of its own.
+//! Applications the table has no name for cannot use this driver (see
+//! `app_identity`).
+//!
+//! Erasing only affects the entries of one application, so it does not erase
+//! the underlying log. Instead, an erase marker is appended, and entries that
+//! precede the latest marker of an application are skipped. To find it, the
+//! log is scanned once for each process, before its first read or seek.
+//!
+//! Entries become persistent once the log is synced, or once the page they
+//! are in is full. In a circular log, the oldest entries of all applications
+//! are overwritten when the log is full.
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Applications         |
+//! |                       |
+//! +-----------------------+
+//!
+//!    syscalls
+//!
+//! +-----------------------+
+//! |                       |
+//! |  Log (this file)      |
+//! |                       |
+//! +-----------------------+
+//!
+//!    hil::log
+//!
+//! +-----------------------+
+//! |                       |
+//! |  capsules::log::Log   |
+//! |                       |
+//! +-----------------------+
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let log_driver = static_init!(
+//!     capsules::log_driver::LogDriver<'static, capsules::log::Log<'static, FLASHCALW>>,
+//!     capsules::log_driver::LogDriver::new(
+//!         log,
+//!         static_init!([u8; 256], [0; 256]),
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!         identities,
+//!     )
+//! );
+//! log.set_read_client(log_driver);
+//! log.set_append_client(log_driver);
+//! ```
+
+use crate::app_identity::AppIdentities;
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Log as usize;
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, ReturnCode, Upcall,
+};
+
+/// The maximum length of the application name stored in each entry. Apps
+/// with longer names cannot use this driver.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// The version of the header stored before each entry.
+const HEADER_VERSION: u8 = 0;
+
+/// The length of the fixed part of the header: the version, the flags and
+/// the length of the application name, in that order. The name follows.
+const HEADER_LEN: usize = 3;
+
+/// Set in the flags of an erase marker, which has no data.
+const FLAG_ERASED: u8 = 0x01;
+
+/// The commands an application can queue.
+#[derive(Clone, Copy, PartialEq)]
+enum UserCommand {
+    /// Append an entry with this many bytes of the write buffer.
+    Append(usize),
+    Read,
+    /// Seek to this read position.
+    Seek(usize),
+    Sync,
+    Erase,
+}
+
+/// The operation in progress.
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    /// Finding the latest erase marker of the process before running the
+    /// command. `start` is the read position after the latest marker found
+    /// so far.
+    Scan {
+        command: UserCommand,
+        start: usize,
+    },
+    /// Reading the next entry of the process.
+    Read,
+    Append,
+    Sync,
+    /// Appending an erase marker.
+    Erase,
+}
+
+pub struct LogDriver<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> {
+    log: &'a L,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    /// The name of the process of the current operation.
+    name: OptionalCell<&'static [u8]>,
+    operation: Cell<Operation>,
+    buffer: TakeCell<'static, [u8]>,
+    identities: &'static AppIdentities,
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogDriver<'a, L> {
+    /// Creates the driver.
+    ///
+    /// Entries are limited by the length of `buffer`, less the length of the
+    /// header, which is 3 bytes plus the length of the application name.
+    pub fn new(
+        log: &'a L,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+        identities: &'static AppIdentities,
+    ) -> LogDriver<'a, L> {
+        LogDriver {
+            log,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            name: OptionalCell::empty(),
+            operation: Cell::new(Operation::None),
+            buffer: TakeCell::new(buffer),
+            identities,
+        }
+    }
+
+    /// Starts `command` for `appid`, after finding where its log starts if
+    /// the command needs it and that is not known yet.
+    fn start(&self, appid: AppId, command: UserCommand) -> Result<(), ReturnCode> {
+        let name = self.identities.name(appid, MAX_NAME_LEN)?.as_bytes();
+
+        let needs_start = match command {
+            UserCommand::Read | UserCommand::Seek(_) => self
+                .apps
+                .enter(appid, |app, _| app.start.is_none())
+                .map_err(ReturnCode::from)?,
+            _ => false,
+        };
+
+        self.appid.set(appid);
+        self.name.set(name);
+        if needs_start {
+            let start = self.log.log_start();
+            self.operation.set(Operation::Scan { command, start });
+            self.seek(start);
+            Ok(())
+        } else {
+            self.run(command).map_err(|e| {
+                self.operation.set(Operation::None);
+                self.name.clear();
+                self.appid.clear();
+                e
+            })
+        }
+    }
+
+    /// Runs `command` for the current process, whose log start is known if
+    /// the command needs it.
+    fn run(&self, command: UserCommand) -> Result<(), ReturnCode> {
+        match command {
+            UserCommand::Append(length) => {
+                self.operation.set(Operation::Append);
+                self.append(Some(length))
+            }
+            UserCommand::Erase => {
+                self.operation.set(Operation::Erase);
+                self.append(None)
+            }
+            UserCommand::Read => {
+                self.operation.set(Operation::Read);
+                self.with_app(|app| app.position.or(app.start).unwrap_or(0))
+                    .map(|position| self.seek(cmp::max(position, self.log.log_start())))
+            }
+            UserCommand::Seek(position) => {
+                let (log_start, log_end) = (self.log.log_start(), self.log.log_end());
+                let ret = self.with_app(|app| {
+                    let start = cmp::max(app.start.unwrap_or(0), log_start);
+                    if position == 0 {
+                        app.position = None;
+                        Ok(())
+                    } else if position >= start && position <= log_end {
+                        app.position = Some(position);
+                        Ok(())
+                    } else {
+                        Err(ReturnCode::EINVAL)
+                    }
+                });
+                ret.and_then(|ret| ret)
+                    .map(|()| self.complete(ReturnCode::SUCCESS, 0, 0))
+            }
+            UserCommand::Sync => {
+                self.operation.set(Operation::Sync);
+                match self.log.sync() {
+                    ReturnCode::SUCCESS => Ok(()),
+                    e => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the grant region of the current process.
+    fn with_app<R, F: FnOnce(&mut App) -> R>(&self, f: F) -> Result<R, ReturnCode> {
+        self.appid.map_or(Err(ReturnCode::FAIL), |appid| {
+            self.apps
+                .enter(*appid, |app, _| f(app))
+                .map_err(ReturnCode::from)
+        })
+    }
+
+    /// Appends an entry with `length` bytes of the write buffer of the
+    /// current process, or an erase marker if `length` is `None`.
+    fn append(&self, length: Option<usize>) -> Result<(), ReturnCode> {
+        let buffer = self.buffer.take().ok_or(ReturnCode::EBUSY)?;
+        let header_len = self.write_header(buffer, length.is_none());
+
+        let ret = match length {
+            Some(length) => self
+                .with_app(|app| {
+                    app.write_buffer.map_or(Err(ReturnCode::EINVAL), |data| {
+                        let data = data.as_ref();
+                        if length == 0 || length > data.len() {
+                            Err(ReturnCode::EINVAL)
+                        } else if header_len + length > buffer.len() {
+                            Err(ReturnCode::ESIZE)
+                        } else {
+                            buffer[header_len..header_len + length]
+                                .copy_from_slice(&data[..length]);
+                            Ok(header_len + length)
+                        }
+                    })
+                })
+                .and_then(|ret| ret),
+            None => Ok(header_len),
+        };
+
+        match ret {
+            Ok(entry_len) => self.log.append(buffer, entry_len).map_err(|(e, buffer)| {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                e
+            }),
+            Err(e) => {
+                self.buffer.replace(buffer);
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the header of an entry of the current process to `buffer` and
+    /// returns its length.
+    fn write_header(&self, buffer: &mut [u8], erased: bool) -> usize {
+        self.name.map_or(0, |name| {
+            buffer[0] = HEADER_VERSION;
+            buffer[1] = if erased { FLAG_ERASED } else { 0 };
+            buffer[2] = name.len() as u8;
+            buffer[HEADER_LEN..HEADER_LEN + name.len()].copy_from_slice(name);
+            HEADER_LEN + name.len()
+        })
+    }
+
+    /// If `entry` belongs to the current process, returns whether it is an
+    /// erase marker and the offset of its data.
+    fn parse_entry(&self, entry: &[u8]) -> Option<(bool, usize)> {
+        self.name.map_or(None, |name| {
+            let name_len = *entry.get(2)? as usize;
+            let owner = entry.get(HEADER_LEN..HEADER_LEN + name_len)?;
+            if entry[0] != HEADER_VERSION || owner != *name {
+                return None;
+            }
+            Some((entry[1] & FLAG_ERASED != 0, HEADER_LEN + name_len))
+        })
+    }
+
+    /// Moves the read position of the log to `position`, continuing in
+    /// `seek_done`.
+    fn seek(&self, position: usize) {
+        let ret = self.log.seek(position);
+        if ret != ReturnCode::SUCCESS {
+            self.complete(ret, 0, 0);
+        }
+    }
+
+    /// Reads the next entry of the log, continuing in `read_done`.
+    fn read_next(&self) {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => {
+                self.complete(ReturnCode::EBUSY, 0, 0);
+                return;
+            }
+        };
+        let len = buffer.len();
+        match self.log.read(buffer, len) {
+            Ok(()) => (),
+            Err((e, buffer)) => {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.end_of_log(e);
+            }
+        }
+    }
+
+    /// Handles the end of reading through the log, at the end of the log if
+    /// `result` is `FAIL`.
+    fn end_of_log(&self, result: ReturnCode) {
+        match (self.operation.get(), result) {
+            (Operation::Scan { command, start }, ReturnCode::FAIL) => {
+                let ret = self.with_app(|app| app.start = Some(start));
+                if let Err(e) = ret.and_then(|()| self.run(command)) {
+                    self.complete(e, 0, 0);
+                }
+            }
+            _ => self.complete(result, 0, 0),
+        }
+    }
+
+    /// Schedules the upcall of `appid`.
+    fn upcall(&self, appid: AppId, result: ReturnCode, arg1: usize, arg2: usize) {
+        let _ = self.apps.enter(appid, |app, _| {
+            app.callback.schedule(usize::from(result), arg1, arg2);
+        });
+    }
+
+    /// Finishes the current operation, calls the upcall of the application
+    /// and starts the next queued command.
+    fn complete(&self, result: ReturnCode, arg1: usize, arg2: usize) {
+        self.operation.set(Operation::None);
+        self.name.clear();
+        self.appid
+            .take()
+            .map(|appid| self.upcall(appid, result, arg1, arg2));
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            // If an app is already running let it complete
+            if self.appid.is_some() {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                app.pending_command
+                    .take()
+                    .map(|command| (app.appid(), command))
+            });
+            if let Some((appid, command)) = pending {
+                if let Err(e) = self.start(appid, command) {
+                    self.upcall(appid, e, 0, 0);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogReadClient for LogDriver<'a, L> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, error: ReturnCode) {
+        if error != ReturnCode::SUCCESS {
+            self.buffer.replace(buffer);
+            self.complete(error, 0, 0);
+            return;
+        }
+
+        let entry = self.parse_entry(&buffer[..length]);
+        match self.operation.get() {
+            Operation::Scan { command, .. } => {
+                if let Some((true, _)) = entry {
+                    self.operation.set(Operation::Scan {
+                        command,
+                        start: self.log.next_read_entry_id(),
+                    });
+                }
+                self.buffer.replace(buffer);
+                self.read_next();
+            }
+            Operation::Read => match entry {
+                Some((false, offset)) => {
+                    let data = &buffer[offset..length];
+                    let position = self.log.next_read_entry_id();
+                    let ret = self
+                        .with_app(|app| {
+                            let ret = app.read_buffer.mut_map_or(Err(ReturnCode::ESIZE), |dest| {
+                                let dest = dest.as_mut();
+                                if data.len() > dest.len() {
+                                    return Err(ReturnCode::ESIZE);
+                                }
+                                dest[..data.len()].copy_from_slice(data);
+                                Ok(data.len())
+                            });
+                            // Not advancing the read position on failure lets
+                            // the process retry with a larger buffer
+                            if ret.is_ok() {
+                                app.position = Some(position);
+                            }
+                            ret
+                        })
+                        .and_then(|ret| ret);
+                    self.buffer.replace(buffer);
+                    match ret {
+                        Ok(len) => self.complete(ReturnCode::SUCCESS, len, position),
+                        Err(e) => self.complete(e, 0, 0),
+                    }
+                }
+                _ => {
+                    // An entry of another process, or an erase marker
+                    self.buffer.replace(buffer);
+                    self.read_next();
+                }
+            },
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn seek_done(&self, error: ReturnCode) {
+        match (self.operation.get(), error) {
+            (Operation::Scan { .. }, ReturnCode::SUCCESS)
+            | (Operation::Read, ReturnCode::SUCCESS) => self.read_next(),
+            (Operation::None, _) => (),
+            (_, error) => self.complete(error, 0, 0),
+        }
+    }
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> LogWriteClient for LogDriver<'a, L> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        records_lost: bool,
+        error: ReturnCode,
+    ) {
+        self.buffer.replace(buffer);
+        match self.operation.get() {
+            Operation::Append => {
+                let header_len = self.name.map_or(0, |name| HEADER_LEN + name.len());
+                self.complete(
+                    error,
+                    length.saturating_sub(header_len),
+                    records_lost as usize,
+                );
+            }
+            Operation::Erase => {
+                if error == ReturnCode::SUCCESS {
+                    let end = self.log.log_end();
+                    let _ = self.with_app(|app| {
+                        app.start = Some(end);
+                        app.position = None;
+                    });
+                }
+                self.complete(error, 0, 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn sync_done(&self, error: ReturnCode) {
+        if self.operation.get() == Operation::Sync {
+            self.complete(error, 0, 0);
+        }
+    }
+
+    fn erase_done(&self, _error: ReturnCode) {}
+}
+
+impl<'a, L: LogRead<'a, EntryID = usize> + LogWrite<'a>> Driver for LogDriver<'a, L> {
+    /// Specify the buffer entries are read into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer for the entries read by `read`.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.read_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Specify the buffer entries are appended from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer containing the entry to append.
+    ///
+    /// The buffer should not be changed until the append has completed.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.write_buffer);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to log events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: u32, arg1: u32, arg2: u32)`. After an
+    ///        append, `arg1` is the length of the entry and `arg2` whether
+    ///        entries were overwritten. After a read, `arg1` is the length
+    ///        of the entry and `arg2` the read position after it.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Run log operations.
+    ///
+    /// Operations are queued if another operation is in progress. Each
+    /// process can queue one operation.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Append an entry with the first `data1` bytes of the write
+    ///        buffer.
+    /// - `2`: Read the next entry into the read buffer.
+    /// - `3`: Seek to read position `data1`, or to the first entry if it is
+    ///        0.
+    /// - `4`: Sync the log to storage.
+    /// - `5`: Erase all entries of this process.
+    /// - `6`: Get the read position, or 0 if it is at the first entry.
+    /// - `7`: Get the capacity of the log in bytes.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        let command = match command_num {
+            0 => return CommandReturn::success(),
+            1 => UserCommand::Append(data1),
+            2 => UserCommand::Read,
+            3 => UserCommand::Seek(data1),
+            4 => UserCommand::Sync,
+            5 => UserCommand::Erase,
+            6 => {
+                return self
+                    .apps
+                    .enter(appid, |app, _| {
+                        CommandReturn::success_u32(app.position.unwrap_or(0) as u32)
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            7 => return CommandReturn::success_u32(self.log.get_size() as u32),
+
+            // default
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        if self.operation.get() == Operation::None {
+            match self.start(appid, command) {
+                Ok(()) => CommandReturn::success(),
+                Err(e) => CommandReturn::from(e),
+            }
+        } else {
+            // Some app is using the log, we must wait.
+            self.apps
+                .enter(appid, |app, _| {
+                    if app.pending_command.is_some() {
+                        // No more room in the queue
+                        CommandReturn::failure(ErrorCode::NOMEM)
+                    } else {
+                        app.pending_command = Some(command);
+                        CommandReturn::success()
+                    }
+                })
+                .unwrap_or_else(|err| err.into())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    pending_command: Option<UserCommand>,
+    read_buffer: ReadWriteAppSlice,
+    write_buffer: ReadOnlyAppSlice,
+    /// The read position after the latest erase marker of the process, once
+    /// the log has been scanned for it.
+    start: Option<usize>,
+    /// The read position of the process, or `None` at its first entry.
+    position: Option<usize>,
+}
@@ -19,18 +19,46 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    Type133 {
+        reserved: u32,
+    },
+    Type134 {
+        cur_hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+    },
+    Type135 {
+        reserved: u32,
+    },
+    Type136 {
+        flags: u32,
+    },
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ICMP6Type {
     Type1,   // Destination Unreachable
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
 }
 
 impl ICMP6Header {
@@ -40,6 +68,14 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                cur_hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { reserved: 0 },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: 0 },
         };
 
         ICMP6Header {
@@ -51,12 +87,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(ICMP6Header::new(icmp_type).options);
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -81,6 +112,10 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
         }
     }
 
@@ -90,6 +125,10 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
         }
     }
 
@@ -132,7 +171,11 @@ impl ICMP6Header {
         off = enc_consume!(buf, off; encode_u16, self.cksum);
 
         match self.options {
-            ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+            ICMP6HeaderOptions::Type1 { unused }
+            | ICMP6HeaderOptions::Type3 { unused }
+            | ICMP6HeaderOptions::Type133 { reserved: unused }
+            | ICMP6HeaderOptions::Type135 { reserved: unused }
+            | ICMP6HeaderOptions::Type136 { flags: unused } => {
                 off = enc_consume!(buf, off; encode_u32, unused);
             }
             ICMP6HeaderOptions::Type128 { id, seqno }
@@ -140,6 +183,15 @@ impl ICMP6Header {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type134 {
+                cur_hop_limit,
+                flags,
+                router_lifetime,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, cur_hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, router_lifetime);
+            }
         }
 
         stream_done!(off, off);
@@ -163,6 +215,10 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
             _ => return SResult::Error(()),
         };
 
@@ -171,34 +227,52 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
+        let (off, options) = match icmp_type {
             ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type1 { unused })
             }
             ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+                let (off, unused) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type3 { unused })
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type128 { id, seqno })
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
+                (off, ICMP6HeaderOptions::Type129 { id, seqno })
             }
-        }
+            ICMP6Type::Type133 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type133 { reserved })
+            }
+            ICMP6Type::Type134 => {
+                let (off, cur_hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, router_lifetime) = dec_try!(buf, off; decode_u16);
+                let options = ICMP6HeaderOptions::Type134 {
+                    cur_hop_limit,
+                    flags,
+                    router_lifetime,
+                };
+                (off, options)
+            }
+            ICMP6Type::Type135 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type135 { reserved })
+            }
+            ICMP6Type::Type136 => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                (off, ICMP6HeaderOptions::Type136 { flags })
+            }
+        };
+        icmp_header.set_options(options);
 
         stream_done!(off, icmp_header);
     }
@@ -0,0 +1,79 @@
+//! This file contains the definition and implementation of the ICMPv6
+//! receiving interface. The [ICMP6RecvStruct](struct.ICMP6RecvStruct.html) is
+//! the ICMPv6 protocol client of the IPv6 layer: it decodes the ICMPv6 header
+//! of each received message, and passes the message to all of its
+//! [ICMP6RecvClient](trait.ICMP6RecvClient.html)s, such as the
+//! `ICMP6Responder`, which answers echo requests and Neighbor Discovery.
+//!
+//! The IPv6 layer verifies the checksum of messages before they get here.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_recv = static_init!(ICMP6RecvStruct<'static>, ICMP6RecvStruct::new());
+//! ip_receive.set_protocol_client(ip6_nh::ICMP, icmp_recv);
+//! icmp_recv.add_client(icmp_responder);
+//! ```
+
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::IP6Header;
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+
+/// A trait for a client of an `ICMP6RecvStruct`.
+pub trait ICMP6RecvClient {
+    /// Called for every ICMPv6 message received.
+    ///
+    /// # Arguments
+    ///
+    /// `ip_header` - The IPv6 header of the packet
+    /// `icmp_header` - The decoded ICMPv6 header
+    /// `payload` - The message after the 8-byte ICMPv6 header
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+/// Maximum number of clients of an `ICMP6RecvStruct`.
+pub const MAX_ICMP6_CLIENTS: usize = 4;
+
+pub struct ICMP6RecvStruct<'a> {
+    clients: [OptionalCell<&'a dyn ICMP6RecvClient>; MAX_ICMP6_CLIENTS],
+}
+
+impl<'a> ICMP6RecvStruct<'a> {
+    pub fn new() -> ICMP6RecvStruct<'a> {
+        ICMP6RecvStruct {
+            clients: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+        }
+    }
+
+    /// Adds a client that receives all ICMPv6 messages. Returns ENOMEM if
+    /// there are `MAX_ICMP6_CLIENTS` clients already.
+    pub fn add_client(&self, client: &'a dyn ICMP6RecvClient) -> ReturnCode {
+        match self.clients.iter().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                slot.set(client);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+}
+
+impl<'a> IP6RecvClient for ICMP6RecvStruct<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        // Messages of unknown types do not decode, and are dropped
+        if let Some((offset, mut icmp_header)) = ICMP6Header::decode(payload).done() {
+            icmp_header.set_len(payload.len() as u16);
+            for slot in self.clients.iter() {
+                slot.map(|client| client.receive(ip_header, icmp_header, &payload[offset..]));
+            }
+        }
+    }
+}
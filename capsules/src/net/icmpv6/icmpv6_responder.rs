@@ -0,0 +1,457 @@
+//! This file contains the `ICMP6Responder`, which makes a node answer pings
+//! and take part in Neighbor Discovery (RFC 4861, with the 6LoWPAN
+//! link-layer address options of RFC 4944):
+//!
+//! - Echo Requests to one of the local addresses, or to a multicast address,
+//!   are answered with an Echo Reply carrying the same identifier, sequence
+//!   number and data.
+//! - Neighbor Solicitations for a local address are answered with a
+//!   Neighbor Advertisement carrying the 802.15.4 address of the node, and
+//!   the address of the soliciting node goes into the neighbor cache.
+//! - Neighbor Advertisements update the neighbor cache.
+//! - Router Advertisements update the default router, the neighbor cache
+//!   and the prefix to autoconfigure addresses from. The node is a host, so
+//!   Router Solicitations from other nodes are ignored, but it can send one
+//!   with `send_router_solicitation` to ask routers to advertise.
+//!
+//! Neighbor Discovery messages that are not valid (e.g. with a hop limit
+//! other than 255, or malformed options) are dropped, as RFC 4861 requires.
+//!
+//! The responder sends one message at a time, through its own `IP6Sender`.
+//! Before each message, it sets the gateway of the sender to the 802.15.4
+//! address of the destination: the broadcast address for multicast
+//! destinations, the address in the neighbor cache, or else the address
+//! that the interface identifier of the destination is derived from. A
+//! message to answer while another one is being sent is dropped; the peer
+//! will retransmit its request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_responder = static_init!(
+//!     ICMP6Responder<'static>,
+//!     ICMP6Responder::new(ip_send, &LOCAL_ADDRS, src_mac_addr, &mut ICMP_TX_BUF, net_cap)
+//! );
+//! ip_send.set_client(icmp_responder);
+//! icmp_recv.add_client(icmp_responder);
+//! ```
+
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::neighbor_cache::{Neighbor, NeighborCache};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader, ICMP_HDR_LEN};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::ReturnCode;
+
+/// Types of Neighbor Discovery options.
+pub mod nd_option {
+    pub const SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+    pub const TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+    pub const PREFIX_INFORMATION: u8 = 3;
+}
+
+/// Flags of Neighbor Advertisements.
+pub const NA_FLAG_ROUTER: u32 = 1 << 31;
+pub const NA_FLAG_SOLICITED: u32 = 1 << 30;
+pub const NA_FLAG_OVERRIDE: u32 = 1 << 29;
+
+/// Autonomous address-configuration flag of Prefix Information options.
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Hop limit of all Neighbor Discovery messages, which shows that they come
+/// from the link.
+const ND_HOP_LIMIT: u8 = 255;
+
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// Length of the fields of Router Advertisements after the ICMPv6 header:
+/// the reachable time and the retransmission timer.
+const RA_FIELDS_LEN: usize = 8;
+const PREFIX_OPTION_LEN: usize = 32;
+
+/// Iterates over the options of a Neighbor Discovery message, as their type
+/// and their bytes, including the type and length.
+struct NdOptions<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> NdOptions<'b> {
+    /// Whether all options have a valid length.
+    fn valid(buf: &[u8]) -> bool {
+        let mut options = NdOptions { buf };
+        while options.next().is_some() {}
+        options.buf.is_empty()
+    }
+}
+
+impl<'b> Iterator for NdOptions<'b> {
+    type Item = (u8, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'b [u8])> {
+        if self.buf.len() < 2 {
+            return None;
+        }
+        // The length is in units of 8 bytes
+        let len = self.buf[1] as usize * 8;
+        if len == 0 || len > self.buf.len() {
+            return None;
+        }
+        let (option, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some((option[0], option))
+    }
+}
+
+/// The 802.15.4 address of a link-layer address option.
+fn link_layer_address(option: &[u8]) -> Option<MacAddress> {
+    match option[1] {
+        1 => Some(MacAddress::Short(u16::from_be_bytes([
+            option[2], option[3],
+        ]))),
+        2 => {
+            let mut addr = [0; 8];
+            addr.copy_from_slice(&option[2..10]);
+            Some(MacAddress::Long(addr))
+        }
+        _ => None,
+    }
+}
+
+/// The first link-layer address option of type `option_type` in `options`.
+fn find_link_layer_address(options: &[u8], option_type: u8) -> Option<MacAddress> {
+    NdOptions { buf: options }
+        .find(|(ty, _)| *ty == option_type)
+        .and_then(|(_, option)| link_layer_address(option))
+}
+
+/// The 802.15.4 address that the interface identifier of `ip_addr` is
+/// derived from, as by `IPAddr::generate_from_mac`.
+fn mac_from_iid(ip_addr: &IPAddr) -> MacAddress {
+    let iid = &ip_addr.0[8..];
+    if iid[..6] == [0, 0, 0, 0xff, 0xfe, 0] {
+        MacAddress::Short(u16::from_be_bytes([iid[6], iid[7]]))
+    } else {
+        let mut addr = [0; 8];
+        addr.copy_from_slice(iid);
+        addr[0] ^= 0x02;
+        MacAddress::Long(addr)
+    }
+}
+
+/// The solicited-node multicast address of `ip_addr`.
+fn solicited_node_addr(ip_addr: &IPAddr) -> IPAddr {
+    let mut addr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0]);
+    addr.0[13..].copy_from_slice(&ip_addr.0[13..]);
+    addr
+}
+
+pub struct ICMP6Responder<'a> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    local_addrs: &'a [IPAddr],
+    link_addr: MacAddress,
+    neighbors: NeighborCache,
+    router: OptionalCell<IPAddr>,
+    prefix: OptionalCell<(IPAddr, u8)>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    sending: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a> ICMP6Responder<'a> {
+    /// Creates a responder for a node with addresses `local_addrs` and
+    /// 802.15.4 address `link_addr`. `tx_buffer` holds the messages after
+    /// their ICMPv6 header, so it limits the size of the echo requests that
+    /// are answered.
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        local_addrs: &'a [IPAddr],
+        link_addr: MacAddress,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6Responder<'a> {
+        ICMP6Responder {
+            ip_sender,
+            local_addrs,
+            link_addr,
+            neighbors: NeighborCache::new(),
+            router: OptionalCell::empty(),
+            prefix: OptionalCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            sending: Cell::new(false),
+            net_cap,
+        }
+    }
+
+    pub fn neighbors(&self) -> &NeighborCache {
+        &self.neighbors
+    }
+
+    /// The address of the default router, learned from Router
+    /// Advertisements.
+    pub fn default_router(&self) -> Option<IPAddr> {
+        self.router.map(|router| *router)
+    }
+
+    /// The prefix, and its length in bits, that Router Advertisements
+    /// offer for address autoconfiguration.
+    pub fn prefix(&self) -> Option<(IPAddr, u8)> {
+        self.prefix.map(|prefix| *prefix)
+    }
+
+    /// Asks the routers on the link to send a Router Advertisement.
+    pub fn send_router_solicitation(&self) -> ReturnCode {
+        let header = ICMP6Header::new(ICMP6Type::Type133);
+        self.send(ALL_ROUTERS, header, |buf| {
+            self.encode_link_layer_address(buf, nd_option::SOURCE_LINK_LAYER_ADDRESS)
+        })
+    }
+
+    /// Asks the node with address `target` for its 802.15.4 address. Its
+    /// Neighbor Advertisement adds it to the neighbor cache.
+    pub fn send_neighbor_solicitation(&self, target: IPAddr) -> ReturnCode {
+        let header = ICMP6Header::new(ICMP6Type::Type135);
+        self.send(solicited_node_addr(&target), header, |buf| {
+            buf[..16].copy_from_slice(&target.0);
+            16 + self
+                .encode_link_layer_address(&mut buf[16..], nd_option::SOURCE_LINK_LAYER_ADDRESS)
+        })
+    }
+
+    fn is_local(&self, ip_addr: &IPAddr) -> bool {
+        self.local_addrs.iter().any(|addr| addr == ip_addr)
+    }
+
+    /// The 802.15.4 address to send packets to `dst` to.
+    fn next_hop(&self, dst: &IPAddr) -> MacAddress {
+        if dst.is_multicast() {
+            BROADCAST_MAC_ADDR
+        } else {
+            self.neighbors
+                .lookup(dst)
+                .map_or_else(|| mac_from_iid(dst), |neighbor| neighbor.mac_addr)
+        }
+    }
+
+    /// Writes a link-layer address option with the address of the node to
+    /// `buf`, and returns its length.
+    fn encode_link_layer_address(&self, buf: &mut [u8], option_type: u8) -> usize {
+        let len = match self.link_addr {
+            MacAddress::Short(addr) => {
+                buf[2..4].copy_from_slice(&addr.to_be_bytes());
+                8
+            }
+            MacAddress::Long(addr) => {
+                buf[2..10].copy_from_slice(&addr);
+                16
+            }
+        };
+        buf[0] = option_type;
+        buf[1] = (len / 8) as u8;
+        len
+    }
+
+    /// Sends an ICMPv6 message to `dst`, with the body that `encode` writes
+    /// to the buffer it is given, returning its length.
+    fn send<F: FnOnce(&mut [u8]) -> usize>(
+        &self,
+        dst: IPAddr,
+        mut header: ICMP6Header,
+        encode: F,
+    ) -> ReturnCode {
+        if self.sending.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.tx_buffer.take().map_or(ReturnCode::ENOMEM, |buf| {
+            for byte in buf.iter_mut() {
+                *byte = 0;
+            }
+            let len = encode(buf);
+            let mut buf = LeasableBuffer::new(buf);
+            buf.slice(0..len);
+            header.set_len((ICMP_HDR_LEN + len) as u16);
+
+            self.ip_sender.set_gateway(self.next_hop(&dst));
+            let result =
+                self.ip_sender
+                    .send_to(dst, TransportHeader::ICMP(header), &buf, self.net_cap);
+            if result == ReturnCode::SUCCESS {
+                self.sending.set(true);
+            }
+            self.tx_buffer.replace(buf.take());
+            result
+        })
+    }
+
+    fn echo_request(&self, ip_header: &IP6Header, id: u16, seqno: u16, data: &[u8]) {
+        let dst = ip_header.get_dst_addr();
+        if !(self.is_local(&dst) || dst.is_multicast()) {
+            return;
+        }
+        if self.tx_buffer.map_or(true, |buf| buf.len() < data.len()) {
+            return;
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type129);
+        header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+        let _ = self.send(ip_header.get_src_addr(), header, |buf| {
+            buf[..data.len()].copy_from_slice(data);
+            data.len()
+        });
+    }
+
+    fn neighbor_solicitation(&self, ip_header: &IP6Header, body: &[u8]) {
+        if body.len() < 16 || !NdOptions::valid(&body[16..]) {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..16]);
+        if !self.is_local(&target) {
+            return;
+        }
+        let src = ip_header.get_src_addr();
+        let src_link_addr =
+            find_link_layer_address(&body[16..], nd_option::SOURCE_LINK_LAYER_ADDRESS);
+
+        let (dst, flags) = if src.is_unspecified() {
+            // Duplicate address detection by another node: it must not
+            // include its link-layer address, and the answer goes to all
+            // nodes
+            if src_link_addr.is_some() || !ip_header.get_dst_addr().is_multicast() {
+                return;
+            }
+            (ALL_NODES, NA_FLAG_OVERRIDE)
+        } else {
+            if let Some(mac_addr) = src_link_addr {
+                let is_router = self
+                    .neighbors
+                    .lookup(&src)
+                    .map_or(false, |neighbor| neighbor.is_router);
+                self.neighbors.insert(Neighbor {
+                    ip_addr: src,
+                    mac_addr,
+                    is_router,
+                });
+            }
+            (src, NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE)
+        };
+
+        let mut header = ICMP6Header::new(ICMP6Type::Type136);
+        header.set_options(ICMP6HeaderOptions::Type136 { flags });
+        let _ = self.send(dst, header, |buf| {
+            buf[..16].copy_from_slice(&target.0);
+            16 + self
+                .encode_link_layer_address(&mut buf[16..], nd_option::TARGET_LINK_LAYER_ADDRESS)
+        });
+    }
+
+    fn neighbor_advertisement(&self, ip_header: &IP6Header, flags: u32, body: &[u8]) {
+        if body.len() < 16 || !NdOptions::valid(&body[16..]) {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..16]);
+        let solicited = flags & NA_FLAG_SOLICITED != 0;
+        if target.is_multicast() || (solicited && ip_header.get_dst_addr().is_multicast()) {
+            return;
+        }
+        let is_router = flags & NA_FLAG_ROUTER != 0;
+        match find_link_layer_address(&body[16..], nd_option::TARGET_LINK_LAYER_ADDRESS) {
+            Some(mac_addr) => self.neighbors.insert(Neighbor {
+                ip_addr: target,
+                mac_addr,
+                is_router,
+            }),
+            None => {
+                if let Some(neighbor) = self.neighbors.lookup(&target) {
+                    self.neighbors.insert(Neighbor {
+                        is_router,
+                        ..neighbor
+                    });
+                }
+            }
+        }
+    }
+
+    fn router_advertisement(&self, ip_header: &IP6Header, router_lifetime: u16, body: &[u8]) {
+        let src = ip_header.get_src_addr();
+        if !src.is_unicast_link_local()
+            || body.len() < RA_FIELDS_LEN
+            || !NdOptions::valid(&body[RA_FIELDS_LEN..])
+        {
+            return;
+        }
+        let options = &body[RA_FIELDS_LEN..];
+
+        if let Some(mac_addr) =
+            find_link_layer_address(options, nd_option::SOURCE_LINK_LAYER_ADDRESS)
+        {
+            self.neighbors.insert(Neighbor {
+                ip_addr: src,
+                mac_addr,
+                is_router: true,
+            });
+        }
+
+        if router_lifetime != 0 {
+            self.router.set(src);
+        } else if self.router.contains(&src) {
+            self.router.clear();
+        }
+
+        let prefixes = NdOptions { buf: options }.filter(|(ty, option)| {
+            *ty == nd_option::PREFIX_INFORMATION && option.len() == PREFIX_OPTION_LEN
+        });
+        for (_, option) in prefixes {
+            let prefix_len = option[2];
+            let flags = option[3];
+            let valid_lifetime = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+            if flags & PREFIX_FLAG_AUTONOMOUS == 0 || prefix_len > 128 {
+                continue;
+            }
+            let mut prefix = IPAddr::new();
+            prefix.set_prefix(&option[16..32], prefix_len);
+            if valid_lifetime != 0 {
+                self.prefix.set((prefix, prefix_len));
+            } else if self.prefix.contains(&(prefix, prefix_len)) {
+                self.prefix.clear();
+            }
+        }
+    }
+}
+
+impl<'a> ICMP6RecvClient for ICMP6Responder<'a> {
+    fn receive(&self, ip_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        // Neighbor Discovery messages must come from the link
+        let nd_valid = ip_header.get_hop_limit() == ND_HOP_LIMIT && icmp_header.get_code() == 0;
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.echo_request(&ip_header, id, seqno, payload)
+            }
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } if nd_valid => self.router_advertisement(&ip_header, router_lifetime, payload),
+            ICMP6HeaderOptions::Type135 { .. } if nd_valid => {
+                self.neighbor_solicitation(&ip_header, payload)
+            }
+            ICMP6HeaderOptions::Type136 { flags } if nd_valid => {
+                self.neighbor_advertisement(&ip_header, flags, payload)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> IP6SendClient for ICMP6Responder<'a> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+    }
+}
@@ -1,4 +1,7 @@
+pub mod icmpv6_recv;
+pub mod icmpv6_responder;
 pub mod icmpv6_send;
+pub mod neighbor_cache;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::icmpv6::icmpv6::ICMP6Header`)
@@ -0,0 +1,91 @@
+//! This file contains the neighbor cache of Neighbor Discovery (RFC 4861),
+//! which maps the IPv6 addresses of neighbors to their 802.15.4 addresses.
+//!
+//! The cache holds `NEIGHBOR_CACHE_SIZE` entries, and is filled from the
+//! link-layer address options of received Neighbor Discovery messages. When
+//! it is full, a new neighbor replaces the least recently used one. Entries
+//! do not track reachability: an entry stays until it is replaced or
+//! removed.
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+
+/// Number of neighbors in the cache.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Neighbor {
+    pub ip_addr: IPAddr,
+    pub mac_addr: MacAddress,
+    pub is_router: bool,
+}
+
+pub struct NeighborCache {
+    // Each entry with the value of `clock` when it was last used
+    entries: [Cell<Option<(Neighbor, u32)>>; NEIGHBOR_CACHE_SIZE],
+    clock: Cell<u32>,
+}
+
+impl NeighborCache {
+    pub fn new() -> NeighborCache {
+        NeighborCache {
+            entries: Default::default(),
+            clock: Cell::new(0),
+        }
+    }
+
+    fn tick(&self) -> u32 {
+        let now = self.clock.get().wrapping_add(1);
+        self.clock.set(now);
+        now
+    }
+
+    fn find(&self, ip_addr: &IPAddr) -> Option<&Cell<Option<(Neighbor, u32)>>> {
+        self.entries.iter().find(|entry| {
+            entry
+                .get()
+                .map_or(false, |(neighbor, _)| neighbor.ip_addr == *ip_addr)
+        })
+    }
+
+    /// Adds a neighbor, or updates it if it is in the cache.
+    pub fn insert(&self, neighbor: Neighbor) {
+        let now = self.tick();
+        let entry = self
+            .find(&neighbor.ip_addr)
+            .or_else(|| self.entries.iter().find(|entry| entry.get().is_none()))
+            .unwrap_or_else(|| {
+                // Replace the entry used longest ago
+                self.entries
+                    .iter()
+                    .max_by_key(|entry| entry.get().map_or(0, |(_, used)| now.wrapping_sub(used)))
+                    .unwrap()
+            });
+        entry.set(Some((neighbor, now)));
+    }
+
+    /// The neighbor with address `ip_addr`, if it is in the cache.
+    pub fn lookup(&self, ip_addr: &IPAddr) -> Option<Neighbor> {
+        self.find(ip_addr).and_then(|entry| {
+            entry.get().map(|(neighbor, _)| {
+                entry.set(Some((neighbor, self.tick())));
+                neighbor
+            })
+        })
+    }
+
+    /// Removes the neighbor with address `ip_addr` from the cache.
+    pub fn remove(&self, ip_addr: &IPAddr) {
+        if let Some(entry) = self.find(ip_addr) {
+            entry.set(None);
+        }
+    }
+
+    /// Iterates over the neighbors in the cache.
+    pub fn iter(&self) -> impl Iterator<Item = Neighbor> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.get().map(|(neighbor, _)| neighbor))
+    }
+}
@@ -2,10 +2,12 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::IP6Header;
+use crate::net::ipv6::{IP6Header, ICMP_HDR_LEN};
+use crate::net::tcp::{TCPHeader, TCP_HDR_LEN};
 use crate::net::udp::UDPHeader;
+use crate::net::util::{checksum_add, checksum_finish};
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum MacAddr {
@@ -185,43 +187,54 @@ pub fn compute_icmp_checksum(
     icmp_header: &ICMP6Header,
     payload: &[u8],
 ) -> u16 {
-    let mut sum: u32 = 0;
-
-    // add ipv6 pseudo-header
-    sum += compute_ipv6_ph_sum(ipv6_header);
-
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
-    }
+    // The checksum field counts as zero
+    let mut icmp_header = *icmp_header;
+    icmp_header.set_cksum(0);
+    let mut header = [0; ICMP_HDR_LEN];
+    let _ = icmp_header.encode(&mut header, 0);
+
+    let icmp_len = icmp_header.get_len() as usize;
+    let payload_len = icmp_len - icmp_header.get_hdr_size();
+    let sum = compute_ph_sum(ipv6_header, icmp_len, ip6_nh::ICMP);
+    let sum = checksum_add(sum, &header);
+    checksum_finish(checksum_add(sum, &payload[..payload_len]))
+}
 
-    // add icmp payload
-    let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
-    sum += compute_sum(payload, payload_len);
+/// Computes the ICMPv6 checksum over a whole message, including its checksum
+/// field, which is zero for a received message with a valid checksum.
+pub fn compute_icmp_message_checksum(ip6_header: &IP6Header, message: &[u8]) -> u16 {
+    let sum = compute_ph_sum(ip6_header, message.len(), ip6_nh::ICMP);
+    checksum_finish(checksum_add(sum, message))
+}
 
-    // carry overflow
-    while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
-    }
+/// Computes the TCP checksum of a segment with the given header and payload.
+/// The checksum covers the current value of the header's checksum field, so
+/// it must be 0 when computing the checksum of an outgoing segment. Only
+/// headers without options are supported.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut header = [0; TCP_HDR_LEN];
+    let _ = tcp_header.encode(&mut header, 0);
+
+    let mut sum = compute_ph_sum(ip6_header, TCP_HDR_LEN + payload.len(), ip6_nh::TCP);
+    sum = checksum_add(sum, &header);
+    sum = checksum_add(sum, payload);
+    checksum_finish(sum)
+}
 
-    sum = !sum;
-    sum = sum & 0xffff;
+/// Computes the TCP checksum of a serialized segment (header, including any
+/// options, and payload). This is 0 for a segment with a correct checksum.
+pub fn compute_tcp_segment_checksum(ip6_header: &IP6Header, segment: &[u8]) -> u16 {
+    let sum = compute_ph_sum(ip6_header, segment.len(), ip6_nh::TCP);
+    checksum_finish(checksum_add(sum, segment))
+}
 
-    sum as u16
+// Sum over the IPv6 pseudo-header of an upper-layer packet (RFC 2460, section
+// 8.1)
+fn compute_ph_sum(ip6_header: &IP6Header, length: usize, next_header: u8) -> u32 {
+    let mut sum = checksum_add(0, &ip6_header.src_addr.0);
+    sum = checksum_add(sum, &ip6_header.dst_addr.0);
+    sum = checksum_add(sum, &(length as u32).to_be_bytes());
+    checksum_add(sum, &[0, 0, 0, next_header])
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
@@ -241,7 +254,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
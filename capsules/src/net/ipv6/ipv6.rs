@@ -67,7 +67,10 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_icmp_message_checksum, compute_tcp_checksum,
+    compute_tcp_segment_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
@@ -281,17 +284,13 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
-                    }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
-                };
-                if checksum != 0 {
+                if compute_icmp_message_checksum(&self, buf) != 0 {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
+            ip6_nh::TCP => {
+                if compute_tcp_segment_checksum(&self, buf) != 0 {
                     return ReturnCode::FAIL; //Incorrect cksum
                 }
                 ReturnCode::SUCCESS
@@ -369,7 +368,12 @@ impl<'a> IPPayload<'a> {
                 self.header = transport_header;
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_payload_len(payload.len() as u16);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
         }
     }
 
@@ -388,9 +392,7 @@ impl<'a> IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -405,9 +407,7 @@ impl<'a> IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.get_payload_len() as usize,
         }
     }
 }
@@ -454,7 +454,7 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -480,8 +480,15 @@ impl<'a> IP6Packet<'a> {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                let payload_len = tcp_header.get_payload_len() as usize;
+                tcp_header.set_cksum(0);
+                let cksum = compute_tcp_checksum(
+                    &self.header,
+                    &tcp_header,
+                    &self.payload.payload[..payload_len],
+                );
+                tcp_header.set_cksum(cksum);
             }
         }
     }
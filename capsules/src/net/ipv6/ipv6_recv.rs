@@ -1,3 +1,4 @@
+use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 use kernel::common::cells::OptionalCell;
@@ -21,41 +22,125 @@ use kernel::ReturnCode;
 - The udp_mac MacUser has a single receive client, which is the `sixlowpan_state` struct
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
-- the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
+- the `ip_receive` implementing struct (`IP6RecvStruct`) has a default client, which is
+  udp_recv, a `UDPReceive` struct. Other transport protocols (e.g. TCP) can register
+  their own client for packets with their next header value.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
+- Once `IP6RecvStruct` knows the local addresses, it passes unicast packets for
+  other addresses to its forwarder (typically an `IP6SendStruct`), which sends
+  them on to the next hop, instead of to its clients.
 */
 
 pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// A trait for forwarding the received packets that are addressed to other
+/// nodes (route-over routing).
+pub trait IP6Forwarder {
+    /// Called for every received unicast packet whose destination is not a
+    /// local address, with its header and the rest of the packet.
+    fn forward(&self, header: IP6Header, payload: &[u8]);
+}
+
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
+    /// Sets the client that receives all packets for which no protocol client
+    /// is set.
     fn set_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Sets the client that receives packets whose next header is
+    /// `next_header` (one of the `ip6_nh` values). Returns ENOMEM if no more
+    /// protocol clients can be set.
+    fn set_protocol_client(&self, next_header: u8, client: &'a dyn IP6RecvClient) -> ReturnCode;
 }
 
+/// Maximum number of protocol-specific clients of an `IP6RecvStruct`.
+pub const MAX_PROTOCOL_CLIENTS: usize = 4;
+
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    protocol_clients: [OptionalCell<(u8, &'a dyn IP6RecvClient)>; MAX_PROTOCOL_CLIENTS],
+    local_addrs: OptionalCell<&'a [IPAddr]>,
+    forwarder: OptionalCell<&'a dyn IP6Forwarder>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_protocol_client(&self, next_header: u8, client: &'a dyn IP6RecvClient) -> ReturnCode {
+        // Replace the client for this protocol if there is one, otherwise
+        // use the first free slot.
+        let slot = self
+            .protocol_clients
+            .iter()
+            .find(|slot| slot.map_or(false, |(nh, _)| *nh == next_header))
+            .or_else(|| self.protocol_clients.iter().find(|slot| slot.is_none()));
+        match slot {
+            Some(slot) => {
+                slot.set((next_header, client));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            protocol_clients: [
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+                OptionalCell::empty(),
+            ],
+            local_addrs: OptionalCell::empty(),
+            forwarder: OptionalCell::empty(),
         }
     }
+
+    /// Sets the addresses of this node. Once they are set, received unicast
+    /// packets for other addresses go to the forwarder, or are dropped if
+    /// there is none.
+    pub fn set_local_addrs(&self, local_addrs: &'a [IPAddr]) {
+        self.local_addrs.set(local_addrs);
+    }
+
+    /// Sets the forwarder of the packets that are addressed to other nodes.
+    pub fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder) {
+        self.forwarder.set(forwarder);
+    }
+
+    fn is_for_other_node(&self, dst: &IPAddr) -> bool {
+        !dst.is_multicast()
+            && self
+                .local_addrs
+                .map_or(false, |local_addrs| !local_addrs.contains(dst))
+    }
+
+    fn get_client(&self, next_header: u8) -> Option<&'a dyn IP6RecvClient> {
+        self.protocol_clients
+            .iter()
+            .find_map(|slot| {
+                slot.and_then(|(nh, client)| {
+                    if nh == next_header {
+                        Some(client)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .or_else(|| self.client.map(|client| *client))
+    }
 }
 
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
@@ -66,15 +151,22 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
+                // Routers do not verify the transport checksum of the packets
+                // they forward
+                if self.is_for_other_node(&ip6_header.get_dst_addr()) {
+                    self.forwarder
+                        .map(|forwarder| forwarder.forward(ip6_header, &buf[offset..len]));
+                    return;
+                }
                 let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
                 if checksum_result == ReturnCode::FAIL {
                     debug!("cksum fail!: {:?}", checksum_result);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
+                self.get_client(ip6_header.get_next_header())
                     .map(|client| client.receive(ip6_header, &buf[offset..len]));
             }
             None => {
@@ -0,0 +1,101 @@
+//! This file contains the IPv6 routing table, which the
+//! [IP6SendStruct](../ipv6_send/struct.IP6SendStruct.html) consults to find
+//! the next hop of the packets it sends and forwards (route-over routing).
+//!
+//! Each route maps an IPv6 prefix to the 802.15.4 address of the neighbor
+//! that packets for it are sent to. The route with the longest prefix that
+//! matches the destination of a packet wins, so a route with a prefix length
+//! of 0 is the default route.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let routes = static_init!(RoutingTable, RoutingTable::new());
+//! routes.add_route(Route {
+//!     prefix: IPAddr::new(),
+//!     prefix_len: 0,
+//!     next_hop: MacAddress::Short(0x0001),
+//! });
+//! ip_send.set_routing_table(routes);
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::util;
+use core::cell::Cell;
+use kernel::ReturnCode;
+
+/// Number of routes in a `RoutingTable`.
+pub const ROUTING_TABLE_SIZE: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: IPAddr,
+    /// Length of the prefix in bits
+    pub prefix_len: u8,
+    pub next_hop: MacAddress,
+}
+
+pub struct RoutingTable {
+    routes: [Cell<Option<Route>>; ROUTING_TABLE_SIZE],
+}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable {
+            routes: Default::default(),
+        }
+    }
+
+    fn find(&self, prefix: &IPAddr, prefix_len: u8) -> Option<&Cell<Option<Route>>> {
+        self.routes.iter().find(|slot| {
+            slot.get().map_or(false, |route| {
+                route.prefix_len == prefix_len
+                    && util::matches_prefix(&route.prefix.0, &prefix.0, prefix_len)
+            })
+        })
+    }
+
+    /// Adds a route, replacing the route for the same prefix if there is one.
+    /// Returns EINVAL if the prefix is longer than 128 bits, and ENOMEM if the
+    /// table is full.
+    pub fn add_route(&self, route: Route) -> ReturnCode {
+        if route.prefix_len > 128 {
+            return ReturnCode::EINVAL;
+        }
+        match self
+            .find(&route.prefix, route.prefix_len)
+            .or_else(|| self.routes.iter().find(|slot| slot.get().is_none()))
+        {
+            Some(slot) => {
+                slot.set(Some(route));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Removes the route for `prefix`/`prefix_len`. Returns EINVAL if there
+    /// is none.
+    pub fn remove_route(&self, prefix: &IPAddr, prefix_len: u8) -> ReturnCode {
+        match self.find(prefix, prefix_len) {
+            Some(slot) => {
+                slot.set(None);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    /// The next hop of packets to `dst`, from the matching route with the
+    /// longest prefix.
+    pub fn lookup(&self, dst: &IPAddr) -> Option<MacAddress> {
+        self.routes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|route| util::matches_prefix(&dst.0, &route.prefix.0, route.prefix_len))
+            .max_by_key(|route| route.prefix_len)
+            .map(|route| route.next_hop)
+    }
+}
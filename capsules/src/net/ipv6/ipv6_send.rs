@@ -5,7 +5,10 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. It finds the next hop of each packet in
+//! its routing table, if it has one, and can send packets over several
+//! link-layer hops with mesh-under routing. It also forwards received packets
+//! for other nodes, as an `IP6Forwarder`.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -17,11 +20,17 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6Forwarder;
+use crate::net::ipv6::ipv6_routing::RoutingTable;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::sixlowpan::sixlowpan_mesh::{MeshRoutingTable, MESH_HOPS_LEFT};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
+use crate::net::tcp::TCPHeader;
+use crate::net::udp::UDPHeader;
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::leasable_buffer::LeasableBuffer;
@@ -101,10 +110,16 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
+    routes: OptionalCell<&'a RoutingTable>,
+    mesh_routes: OptionalCell<&'a MeshRoutingTable>,
+    // Whether a packet, sent locally or forwarded, is being sent
+    busy: Cell<bool>,
+    // Whether the packet being sent is forwarded for another node, so its
+    // completion is not reported to the client
+    forwarding: Cell<bool>,
 }
 
 impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
@@ -135,14 +150,16 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
-        self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.busy.set(true);
+        self.init_sixlowpan(&dst);
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
+        if ret != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
         ret
     }
 }
@@ -166,10 +183,54 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
+            routes: OptionalCell::empty(),
+            mesh_routes: OptionalCell::empty(),
+            busy: Cell::new(false),
+            forwarding: Cell::new(false),
+        }
+    }
+
+    /// Sets the routing table that gives the next hop of packets. Packets to
+    /// link-local or multicast addresses, and packets that no route matches,
+    /// go to the gateway.
+    pub fn set_routing_table(&self, routes: &'a RoutingTable) {
+        self.routes.set(routes);
+    }
+
+    /// Sets the table of the nodes that are several link-layer hops away.
+    /// Packets whose next hop is in it are sent over several hops, through the
+    /// neighbor of its route, with a mesh header.
+    pub fn set_mesh_routing_table(&self, mesh_routes: &'a MeshRoutingTable) {
+        self.mesh_routes.set(mesh_routes);
+    }
+
+    /// The 802.15.4 address that packets to `dst` are sent to.
+    fn next_hop(&self, dst: &IPAddr) -> MacAddress {
+        if dst.is_multicast() || dst.is_unicast_link_local() {
+            return self.gateway.get();
+        }
+        self.routes
+            .and_then(|routes| routes.lookup(dst))
+            .unwrap_or(self.gateway.get())
+    }
+
+    fn init_sixlowpan(&self, dst: &IPAddr) -> ReturnCode {
+        let next_hop = self.next_hop(dst);
+        let result = self
+            .sixlowpan
+            .init(self.src_mac_addr, next_hop, self.radio.get_pan(), None);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        match self
+            .mesh_routes
+            .and_then(|mesh_routes| mesh_routes.next_hop(&next_hop))
+        {
+            Some(mesh_next_hop) => self.sixlowpan.set_mesh_route(mesh_next_hop, MESH_HOPS_LEFT),
+            None => ReturnCode::SUCCESS,
         }
     }
 
@@ -239,6 +300,10 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        self.busy.set(false);
+        if self.forwarding.replace(false) {
+            return;
+        }
         self.client.map(move |client| {
             client.send_done(result);
         });
@@ -259,9 +324,7 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         self.tx_buf.replace(tx_buf);
         if result != ReturnCode::SUCCESS {
             debug!("Send Failed: {:?}, acked: {}", result, acked);
-            self.client.map(move |client| {
-                client.send_done(result);
-            });
+            self.send_completed(result);
         } else {
             // Below code adds delay between fragments. Despite some efforts
             // to fix this bug, I find that without it the receiving imix cannot
@@ -276,3 +339,59 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         }
     }
 }
+
+impl<'a, A: time::Alarm<'a>> IP6Forwarder for IP6SendStruct<'a, A> {
+    /// Sends the packet on to its next hop, with its hop limit decremented.
+    /// Packets whose hop limit runs out, packets with a transport header
+    /// that cannot be sent, and packets that arrive while another packet is
+    /// being sent are dropped.
+    fn forward(&self, header: IP6Header, payload: &[u8]) {
+        if self.busy.get() || header.get_hop_limit() <= 1 {
+            return;
+        }
+        let transport_header = match header.get_next_header() {
+            ip6_nh::UDP => UDPHeader::decode(payload)
+                .done()
+                .map(|(offset, udp_header)| (offset, TransportHeader::UDP(udp_header))),
+            ip6_nh::TCP => TCPHeader::decode(payload)
+                .done()
+                .map(|(offset, mut tcp_header)| {
+                    tcp_header.set_payload_len((payload.len() - offset) as u16);
+                    (offset, TransportHeader::TCP(tcp_header))
+                }),
+            ip6_nh::ICMP => ICMP6Header::decode(payload)
+                .done()
+                .map(|(offset, mut icmp_header)| {
+                    icmp_header.set_len(payload.len() as u16);
+                    (offset, TransportHeader::ICMP(icmp_header))
+                }),
+            _ => None,
+        };
+        let (offset, transport_header) = match transport_header {
+            Some(transport_header) => transport_header,
+            None => return,
+        };
+        let fits = self.ip6_packet.map_or(false, |ip6_packet| {
+            ip6_packet.payload.payload.len() >= payload.len() - offset
+        });
+        if !fits || self.init_sixlowpan(&header.get_dst_addr()) != ReturnCode::SUCCESS {
+            return;
+        }
+
+        self.ip6_packet.map(|ip6_packet| {
+            // The transport checksum covers neither the hop limit nor the
+            // link-layer addresses, so it stays valid
+            ip6_packet.header = header;
+            ip6_packet.header.set_hop_limit(header.get_hop_limit() - 1);
+            ip6_packet.payload.header = transport_header;
+            ip6_packet.payload.payload[..payload.len() - offset]
+                .copy_from_slice(&payload[offset..]);
+        });
+        self.busy.set(true);
+        self.forwarding.set(true);
+        if self.send_next_fragment() != ReturnCode::SUCCESS {
+            self.busy.set(false);
+            self.forwarding.set(false);
+        }
+    }
+}
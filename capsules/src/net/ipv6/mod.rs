@@ -1,5 +1,6 @@
 pub mod ip_utils;
 pub mod ipv6_recv;
+pub mod ipv6_routing;
 pub mod ipv6_send;
 
 // Reexport the exports of the [`ipv6`] module, to avoid redundant
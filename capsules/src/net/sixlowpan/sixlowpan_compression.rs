@@ -156,6 +156,137 @@ pub fn is_lowpan(packet: &[u8]) -> bool {
     (packet[0] & iphc::DISPATCH[0]) == iphc::DISPATCH[0]
 }
 
+/// Contains bit masks and constants related to the mesh addressing header of
+/// RFC 4944, which precedes the other 6LoWPAN headers of frames that are
+/// forwarded over several link-layer hops.
+pub mod lowpan_mesh {
+    pub const DISPATCH: u8 = 0b1000_0000;
+    pub const DISPATCH_MASK: u8 = 0b1100_0000;
+    /// Set if the originator address is a short address
+    pub const V: u8 = 0b0010_0000;
+    /// Set if the final destination address is a short address
+    pub const F: u8 = 0b0001_0000;
+    pub const HOPS_LEFT_MASK: u8 = 0x0f;
+    /// Hops left value meaning that the number of hops left is in the
+    /// following byte
+    pub const DEEP_HOPS_LEFT: u8 = 0x0f;
+    /// Size of a header with a deep hops left byte and two long addresses
+    pub const MAX_HDR_SIZE: usize = 18;
+}
+
+/// The mesh addressing header of a frame, with the link-layer addresses of
+/// the node that originated it and of its final destination.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MeshHeader {
+    pub hops_left: u8,
+    pub originator: MacAddress,
+    pub final_dst: MacAddress,
+}
+
+fn mesh_addr_len(addr: &MacAddress) -> usize {
+    match addr {
+        MacAddress::Short(_) => 2,
+        MacAddress::Long(_) => 8,
+    }
+}
+
+fn encode_mesh_addr(addr: &MacAddress, buf: &mut [u8]) -> usize {
+    match addr {
+        MacAddress::Short(short_addr) => {
+            u16_to_network_slice(*short_addr, &mut buf[0..2]);
+            2
+        }
+        MacAddress::Long(long_addr) => {
+            buf[0..8].copy_from_slice(long_addr);
+            8
+        }
+    }
+}
+
+fn decode_mesh_addr(buf: &[u8], is_short: bool) -> Result<(MacAddress, usize), ()> {
+    if is_short {
+        if buf.len() < 2 {
+            return Err(());
+        }
+        Ok((MacAddress::Short(network_slice_to_u16(&buf[0..2])), 2))
+    } else {
+        if buf.len() < 8 {
+            return Err(());
+        }
+        let mut long_addr = [0; 8];
+        long_addr.copy_from_slice(&buf[0..8]);
+        Ok((MacAddress::Long(long_addr), 8))
+    }
+}
+
+impl MeshHeader {
+    /// The length of the encoded header in bytes.
+    pub fn get_hdr_size(&self) -> usize {
+        let hops_left_len = if self.hops_left < lowpan_mesh::DEEP_HOPS_LEFT {
+            0
+        } else {
+            1
+        };
+        1 + hops_left_len + mesh_addr_len(&self.originator) + mesh_addr_len(&self.final_dst)
+    }
+
+    /// Writes the header to `buf`, which must hold at least `get_hdr_size()`
+    /// bytes, and returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut dispatch = lowpan_mesh::DISPATCH;
+        if let MacAddress::Short(_) = self.originator {
+            dispatch |= lowpan_mesh::V;
+        }
+        if let MacAddress::Short(_) = self.final_dst {
+            dispatch |= lowpan_mesh::F;
+        }
+        let mut written = 1;
+        if self.hops_left < lowpan_mesh::DEEP_HOPS_LEFT {
+            dispatch |= self.hops_left;
+        } else {
+            dispatch |= lowpan_mesh::DEEP_HOPS_LEFT;
+            buf[written] = self.hops_left;
+            written += 1;
+        }
+        buf[0] = dispatch;
+        written += encode_mesh_addr(&self.originator, &mut buf[written..]);
+        written += encode_mesh_addr(&self.final_dst, &mut buf[written..]);
+        written
+    }
+
+    /// Decodes the header at the start of `buf`. Returns the header and its
+    /// length, or an error if `buf` does not start with a mesh header.
+    pub fn decode(buf: &[u8]) -> Result<(MeshHeader, usize), ()> {
+        if !is_mesh(buf) {
+            return Err(());
+        }
+        let dispatch = buf[0];
+        let mut consumed = 1;
+        let mut hops_left = dispatch & lowpan_mesh::HOPS_LEFT_MASK;
+        if hops_left == lowpan_mesh::DEEP_HOPS_LEFT {
+            hops_left = *buf.get(consumed).ok_or(())?;
+            consumed += 1;
+        }
+        let (originator, len) = decode_mesh_addr(&buf[consumed..], dispatch & lowpan_mesh::V != 0)?;
+        consumed += len;
+        let (final_dst, len) = decode_mesh_addr(&buf[consumed..], dispatch & lowpan_mesh::F != 0)?;
+        consumed += len;
+        Ok((
+            MeshHeader {
+                hops_left,
+                originator,
+                final_dst,
+            },
+            consumed,
+        ))
+    }
+}
+
+/// Whether `packet` starts with a mesh addressing header.
+pub fn is_mesh(packet: &[u8]) -> bool {
+    !packet.is_empty() && (packet[0] & lowpan_mesh::DISPATCH_MASK) == lowpan_mesh::DISPATCH
+}
+
 /// Maps a LoWPAN_NHC header the corresponding IPv6 next header type,
 /// or an error if the NHC header is invalid
 fn nhc_to_ip6_nh(nhc: u8) -> Result<u8, ()> {
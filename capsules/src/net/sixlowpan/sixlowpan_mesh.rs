@@ -0,0 +1,208 @@
+//! Mesh-under forwarding of 6LoWPAN frames (RFC 4944, section 11).
+//!
+//! A frame that has to cross several radio hops carries a mesh header with
+//! the link-layer addresses of its originator and final destination (see
+//! `TxState::set_mesh_route`). Every node on the way forwards it to the next
+//! hop towards the final destination, without reassembling or decompressing
+//! the packet it is part of.
+//!
+//! The [MeshForwarder](struct.MeshForwarder.html) sits between a MAC device
+//! and the `Sixlowpan` layer. Frames without a mesh header, or whose final
+//! destination is this node, go up to its receive client unchanged. Other
+//! frames with a mesh header are sent on with one hop less to the next hop
+//! that the [MeshRoutingTable](struct.MeshRoutingTable.html) gives for their
+//! final destination, or directly to it if there is no route. Frames with no
+//! hops left are dropped, as are frames that arrive while the forwarder is
+//! still sending the previous one. Mesh frames to the broadcast address are
+//! received but not forwarded.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mesh_routes = static_init!(MeshRoutingTable, MeshRoutingTable::new());
+//! let mesh_forwarder = static_init!(
+//!     MeshForwarder<'static>,
+//!     MeshForwarder::new(mac_user, mesh_routes, src_mac_addr, &mut FORWARD_BUF)
+//! );
+//! mac_user.set_transmit_client(mesh_forwarder);
+//! mac_user.set_receive_client(mesh_forwarder);
+//! mesh_forwarder.set_receive_client(sixlowpan);
+//! ```
+
+use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
+use crate::net::ieee802154::{Header, MacAddress};
+use crate::net::sixlowpan::sixlowpan_compression::{is_mesh, lowpan_mesh, MeshHeader};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+
+/// Number of routes in a `MeshRoutingTable`.
+pub const MESH_ROUTES: usize = 8;
+
+/// Hops left of the frames that this node originates over several hops.
+pub const MESH_HOPS_LEFT: u8 = 8;
+
+const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MeshRoute {
+    pub final_dst: MacAddress,
+    pub next_hop: MacAddress,
+}
+
+/// Maps the link-layer addresses of nodes that are several hops away to the
+/// neighbor that frames for them go through.
+pub struct MeshRoutingTable {
+    routes: [Cell<Option<MeshRoute>>; MESH_ROUTES],
+}
+
+impl MeshRoutingTable {
+    pub fn new() -> MeshRoutingTable {
+        MeshRoutingTable {
+            routes: Default::default(),
+        }
+    }
+
+    fn find(&self, final_dst: &MacAddress) -> Option<&Cell<Option<MeshRoute>>> {
+        self.routes.iter().find(|route| {
+            route
+                .get()
+                .map_or(false, |route| route.final_dst == *final_dst)
+        })
+    }
+
+    /// Adds a route, replacing the route to the same final destination if
+    /// there is one. Returns ENOMEM if the table is full.
+    pub fn add_route(&self, route: MeshRoute) -> ReturnCode {
+        match self
+            .find(&route.final_dst)
+            .or_else(|| self.routes.iter().find(|slot| slot.get().is_none()))
+        {
+            Some(slot) => {
+                slot.set(Some(route));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Removes the route to `final_dst`. Returns EINVAL if there is none.
+    pub fn remove_route(&self, final_dst: &MacAddress) -> ReturnCode {
+        match self.find(final_dst) {
+            Some(slot) => {
+                slot.set(None);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    /// The neighbor that frames for `final_dst` go through, if there is a
+    /// route to it.
+    pub fn next_hop(&self, final_dst: &MacAddress) -> Option<MacAddress> {
+        self.find(final_dst)
+            .and_then(|slot| slot.get())
+            .map(|route| route.next_hop)
+    }
+}
+
+pub struct MeshForwarder<'a> {
+    mac: &'a dyn MacDevice<'a>,
+    routes: &'a MeshRoutingTable,
+    src_mac_addr: MacAddress,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_client: OptionalCell<&'a dyn RxClient>,
+}
+
+impl<'a> MeshForwarder<'a> {
+    /// Creates a forwarder that sends frames from `src_mac_addr` through
+    /// `mac`. `tx_buf` must be large enough for an 802.15.4 frame.
+    pub fn new(
+        mac: &'a dyn MacDevice<'a>,
+        routes: &'a MeshRoutingTable,
+        src_mac_addr: MacAddress,
+        tx_buf: &'static mut [u8],
+    ) -> MeshForwarder<'a> {
+        MeshForwarder {
+            mac,
+            routes,
+            src_mac_addr,
+            tx_buf: TakeCell::new(tx_buf),
+            rx_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives the frames that are not forwarded.
+    pub fn set_receive_client(&self, client: &'a dyn RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn is_local(&self, addr: &MacAddress) -> bool {
+        *addr == BROADCAST_MAC_ADDR
+            || *addr == MacAddress::Short(self.mac.get_address())
+            || *addr == MacAddress::Long(self.mac.get_address_long())
+    }
+
+    /// Sends `payload`, the part of a frame after its mesh header, on towards
+    /// its final destination.
+    fn forward(&self, mut mesh_header: MeshHeader, payload: &[u8]) {
+        if mesh_header.hops_left <= 1 {
+            return;
+        }
+        mesh_header.hops_left -= 1;
+        let next_hop = self
+            .routes
+            .next_hop(&mesh_header.final_dst)
+            .unwrap_or(mesh_header.final_dst);
+
+        self.tx_buf.take().map(|tx_buf| {
+            let pan = self.mac.get_pan();
+            match self
+                .mac
+                .prepare_data_frame(tx_buf, pan, next_hop, pan, self.src_mac_addr, None)
+            {
+                Ok(mut frame) => {
+                    let mut hdr = [0 as u8; lowpan_mesh::MAX_HDR_SIZE];
+                    let len = mesh_header.encode(&mut hdr);
+                    if frame.append_payload(&hdr[0..len]) != ReturnCode::SUCCESS
+                        || frame.append_payload(payload) != ReturnCode::SUCCESS
+                    {
+                        self.tx_buf.replace(frame.into_buf());
+                        return;
+                    }
+                    let (_, buf) = self.mac.transmit(frame);
+                    buf.map(|buf| self.tx_buf.replace(buf));
+                }
+                Err(buf) => {
+                    self.tx_buf.replace(buf);
+                }
+            }
+        });
+    }
+}
+
+impl<'a> RxClient for MeshForwarder<'a> {
+    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+        let payload = &buf[data_offset..data_offset + data_len];
+        if is_mesh(payload) {
+            match MeshHeader::decode(payload) {
+                Ok((mesh_header, consumed)) => {
+                    if !self.is_local(&mesh_header.final_dst) {
+                        self.forward(mesh_header, &payload[consumed..]);
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+        self.rx_client
+            .map(|client| client.receive(buf, header, data_offset, data_len));
+    }
+}
+
+impl<'a> TxClient for MeshForwarder<'a> {
+    fn send_done(&self, spi_buf: &'static mut [u8], _acked: bool, _result: ReturnCode) {
+        self.tx_buf.replace(spi_buf);
+    }
+}
@@ -230,7 +230,9 @@ use crate::net::frag_utils::Bitmap;
 use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
 use crate::net::ipv6::IP6Packet;
 use crate::net::sixlowpan::sixlowpan_compression;
-use crate::net::sixlowpan::sixlowpan_compression::{is_lowpan, ContextStore};
+use crate::net::sixlowpan::sixlowpan_compression::{
+    is_lowpan, is_mesh, lowpan_mesh, ContextStore, MeshHeader,
+};
 use crate::net::util::{network_slice_to_u16, u16_to_network_slice};
 use core::cell::Cell;
 use core::cmp::min;
@@ -317,6 +319,8 @@ pub struct TxState<'a> {
     src_mac_addr: Cell<MacAddress>,
     dst_mac_addr: Cell<MacAddress>,
     security: Cell<Option<(SecurityLevel, KeyId)>>,
+    // The next hop and hops left of a packet sent over several hops
+    mesh_route: Cell<Option<(MacAddress, u8)>>,
     dgram_tag: Cell<u16>, // Used to identify particular fragment streams
     dgram_size: Cell<u16>,
     dgram_offset: Cell<usize>,
@@ -342,6 +346,7 @@ impl<'a> TxState<'a> {
             src_mac_addr: Cell::new(MacAddress::Short(0)),
             dst_mac_addr: Cell::new(MacAddress::Short(0)),
             security: Cell::new(None),
+            mesh_route: Cell::new(None),
 
             // Internal fields
             dgram_tag: Cell::new(0),
@@ -382,6 +387,7 @@ impl<'a> TxState<'a> {
             self.src_mac_addr.set(src_mac_addr);
             self.dst_mac_addr.set(dst_mac_addr);
             self.security.set(security);
+            self.mesh_route.set(None);
             self.busy.set(false);
             self.src_pan.set(radio_pan);
             self.dst_pan.set(radio_pan);
@@ -389,6 +395,21 @@ impl<'a> TxState<'a> {
         }
     }
 
+    /// Sends the packet initialized with `init` over several link-layer hops
+    /// (mesh-under): its frames go to `next_hop`, and carry a mesh header
+    /// with the source and destination MAC addresses given to `init` as the
+    /// originator and final destination. Nodes on the way forward the frames
+    /// until `hops_left` reaches zero. Must be called after `init` and before
+    /// the first call to `next_fragment`.
+    pub fn set_mesh_route(&self, next_hop: MacAddress, hops_left: u8) -> ReturnCode {
+        if self.busy.get() {
+            ReturnCode::EBUSY
+        } else {
+            self.mesh_route.set(Some((next_hop, hops_left)));
+            ReturnCode::SUCCESS
+        }
+    }
+
     /// Gets the next 6LoWPAN Fragment (as a MAC frame) to be sent. Note that
     /// this layer **does not** send the frame, and assumes that `init` has
     /// already been called.
@@ -414,12 +435,16 @@ impl<'a> TxState<'a> {
         frag_buf: &'static mut [u8],
         radio: &dyn MacDevice,
     ) -> Result<(bool, Frame), (ReturnCode, &'static mut [u8])> {
+        let frame_dst_addr = self
+            .mesh_route
+            .get()
+            .map_or(self.dst_mac_addr.get(), |(next_hop, _)| next_hop);
         // This consumes frag_buf
         let frame = radio
             .prepare_data_frame(
                 frag_buf,
                 self.dst_pan.get(),
-                self.dst_mac_addr.get(),
+                frame_dst_addr,
                 self.src_pan.get(),
                 self.src_mac_addr.get(),
                 self.security.get(),
@@ -492,6 +517,7 @@ impl<'a> TxState<'a> {
         // TODO: This -2 is added to account for the FCS; this should be changed
         // in the MAC code
         let mut remaining_capacity = frame.remaining_data_capacity() - 2;
+        remaining_capacity -= self.write_mesh_hdr(&mut frame);
 
         // Need to fragment
         if lowpan_len > remaining_capacity {
@@ -530,6 +556,7 @@ impl<'a> TxState<'a> {
     ) -> Result<Frame, (ReturnCode, &'static mut [u8])> {
         let dgram_offset = self.dgram_offset.get();
         let mut remaining_capacity = frame.remaining_data_capacity();
+        remaining_capacity -= self.write_mesh_hdr(&mut frame);
         remaining_capacity -= self.write_frag_hdr(&mut frame, false);
 
         // This rounds payload_len down to the nearest multiple of 8 if it
@@ -583,6 +610,22 @@ impl<'a> TxState<'a> {
         (payload_len, dgram_offset)
     }
 
+    // The mesh header goes before the fragmentation header of every frame
+    fn write_mesh_hdr(&self, frame: &mut Frame) -> usize {
+        self.mesh_route.get().map_or(0, |(_, hops_left)| {
+            let mesh_header = MeshHeader {
+                hops_left,
+                originator: self.src_mac_addr.get(),
+                final_dst: self.dst_mac_addr.get(),
+            };
+            let mut hdr = [0 as u8; lowpan_mesh::MAX_HDR_SIZE];
+            let len = mesh_header.encode(&mut hdr);
+            // TODO: Check success
+            frame.append_payload(&hdr[0..len]);
+            len
+        })
+    }
+
     fn write_frag_hdr(&self, frame: &mut Frame, first_frag: bool) -> usize {
         if first_frag {
             let mut frag_header = [0 as u8; lowpan_frag::FRAG1_HDR_SIZE];
@@ -800,15 +843,27 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> RxClient for Sixlowpan<'a, A, C> {
         // a callback for an invalid frame reception
         // TODO: Handle the case where the addresses are None/elided - they
         // should not default to the zero address
-        let src_mac_addr = header.src_addr.unwrap_or(MacAddress::Short(0));
-        let dst_mac_addr = header.dst_addr.unwrap_or(MacAddress::Short(0));
+        let mut src_mac_addr = header.src_addr.unwrap_or(MacAddress::Short(0));
+        let mut dst_mac_addr = header.dst_addr.unwrap_or(MacAddress::Short(0));
+        let mut payload = &buf[data_offset..data_offset + data_len];
+
+        // The headers of a frame forwarded over several hops are relative to
+        // the originator and final destination in its mesh header. Frames
+        // that need forwarding are taken care of by a `MeshForwarder` below
+        // this layer.
+        if is_mesh(payload) {
+            match MeshHeader::decode(payload) {
+                Ok((mesh_header, consumed)) if consumed < payload.len() => {
+                    src_mac_addr = mesh_header.originator;
+                    dst_mac_addr = mesh_header.final_dst;
+                    payload = &payload[consumed..];
+                }
+                _ => return,
+            }
+        }
 
-        let (rx_state, returncode) = self.receive_frame(
-            &buf[data_offset..data_offset + data_len],
-            data_len,
-            src_mac_addr,
-            dst_mac_addr,
-        );
+        let (rx_state, returncode) =
+            self.receive_frame(payload, payload.len(), src_mac_addr, dst_mac_addr);
         // Reception completed if rx_state is not None. Note that this can
         // also occur for some fail states (e.g. dropping an invalid packet)
         rx_state.map(|state| state.end_receive(self.rx_client.get(), returncode));
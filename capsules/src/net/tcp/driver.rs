@@ -0,0 +1,422 @@
+//! TCP userspace interface.
+//!
+//! Implements a userspace interface for TCP connections. The driver owns a
+//! fixed set of kernel `TCPSocket`s, and each process can use one of them at
+//! a time, either to listen for a connection on a port or to connect to a
+//! remote endpoint. The socket is assigned to the process by the listen or
+//! connect command, and stays assigned until the process aborts the
+//! connection (or the socket is reused by the next listen or connect of the
+//! same process once it is closed).
+//!
+//! Data is copied between the process buffers and the send and receive
+//! buffers of the socket with the send and receive commands, which return
+//! how many bytes were copied. Callbacks tell the process when data arrived,
+//! when sent data was acknowledged, and when the state of the connection
+//! changed.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_driver = static_init!(
+//!     capsules::net::tcp::TCPDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::net::tcp::TCPDriver::new(
+//!         tcp_sockets,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! for (id, socket) in tcp_sockets.iter().enumerate() {
+//!     socket.set_client(tcp_driver, id);
+//! }
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_socket::{TCPClient, TCPSocket, TCPState};
+use crate::net::util::host_slice_to_u16;
+use core::convert::TryFrom;
+use core::mem;
+use kernel::hil::time::Alarm;
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReadWrite,
+    ReadWriteAppSlice, ReturnCode, Upcall,
+};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Size of an endpoint (IPv6 address and port) in the config buffer.
+const ENDPOINT_LEN: usize = 18;
+
+/// Events passed as the first argument of the connection callback.
+mod event {
+    pub const CONNECTED: usize = 0;
+    pub const REMOTE_CLOSED: usize = 1;
+    pub const CLOSED: usize = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Upcall,
+    tx_callback: Upcall,
+    conn_callback: Upcall,
+    app_read: ReadWriteAppSlice,
+    app_write: ReadOnlyAppSlice,
+    app_cfg: ReadWriteAppSlice,
+    socket: Option<usize>,
+}
+
+pub struct TCPDriver<'a, A: Alarm<'a>> {
+    /// Sockets available to processes
+    sockets: &'a [TCPSocket<'a, A>],
+
+    /// Grant of apps that use this driver
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm<'a>> TCPDriver<'a, A> {
+    /// Creates the driver. Each of `sockets` must have the driver as its
+    /// client, with its index in `sockets` as id.
+    pub fn new(sockets: &'a [TCPSocket<'a, A>], grant: Grant<App>) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sockets: sockets,
+            apps: grant,
+        }
+    }
+
+    /// Returns the socket of `appid`, assigning it a free socket if it has
+    /// none. Fails with BUSY if the socket of the process is still in use and
+    /// NOMEM if there is no free socket.
+    fn assign_socket(&self, appid: AppId) -> Result<usize, ErrorCode> {
+        let current = self
+            .apps
+            .enter(appid, |app, _| app.socket)
+            .map_err(ErrorCode::from)?;
+        if let Some(id) = current {
+            if self.sockets[id].get_state() == TCPState::Closed {
+                return Ok(id);
+            } else {
+                return Err(ErrorCode::BUSY);
+            }
+        }
+
+        let free = (0..self.sockets.len()).find(|id| {
+            self.sockets[*id].get_state() == TCPState::Closed
+                && !self
+                    .apps
+                    .iter()
+                    .any(|app| app.enter(|app, _| app.socket == Some(*id)))
+        });
+        match free {
+            Some(id) => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.socket = Some(id);
+                    id
+                })
+                .map_err(ErrorCode::from),
+            None => Err(ErrorCode::NOMEM),
+        }
+    }
+
+    /// Returns the socket assigned to `appid`, or RESERVE if it has none.
+    fn get_socket(&self, appid: AppId) -> Result<&TCPSocket<'a, A>, ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| app.socket)
+            .map_err(ErrorCode::from)?
+            .map(|id| &self.sockets[id])
+            .ok_or(ErrorCode::RESERVE)
+    }
+
+    /// Reads the remote endpoint for a connect command from the config
+    /// buffer of `appid`.
+    fn get_remote_endpoint(&self, appid: AppId) -> Result<(IPAddr, u16), ErrorCode> {
+        self.apps
+            .enter(appid, |app, _| {
+                app.app_cfg.map_or(Err(ErrorCode::INVAL), |cfg| {
+                    if cfg.len() != ENDPOINT_LEN {
+                        return Err(ErrorCode::INVAL);
+                    }
+                    let mut addr = IPAddr::new();
+                    addr.0.copy_from_slice(&cfg.as_ref()[..16]);
+                    Ok((addr, host_slice_to_u16(&cfg.as_ref()[16..])))
+                })
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Calls `fun` on the app that owns socket `id`.
+    fn with_socket_owner<F>(&self, id: usize, fun: F)
+    where
+        F: Fn(&mut App),
+    {
+        self.apps.each(|app| {
+            if app.socket == Some(id) {
+                fun(app);
+            }
+        });
+    }
+}
+
+fn result_to_command_return(result: ReturnCode) -> CommandReturn {
+    match ErrorCode::try_from(result) {
+        Ok(err) => CommandReturn::failure(err),
+        Err(_) => CommandReturn::success(),
+    }
+}
+
+impl<'a, A: Alarm<'a>> Driver for TCPDriver<'a, A> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Received data is copied into it by the receive
+    ///        command.
+    /// - `1`: Config buffer. Contains the remote endpoint (16 byte IPv6
+    ///        address followed by the port in host byte order) to connect
+    ///        to. Once a connection to a listening socket is established,
+    ///        the endpoint of the remote end is written to it.
+    fn allow_readwrite(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadWriteAppSlice,
+    ) -> Result<ReadWriteAppSlice, (ReadWriteAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app, _| match allow_num {
+                0 => {
+                    mem::swap(&mut app.app_read, &mut slice);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.app_cfg, &mut slice);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .map_err(ErrorCode::from);
+
+        match res {
+            Ok(Ok(())) => Ok(slice),
+            Ok(Err(e)) | Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Write buffer. Data is copied from it by the send command.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.app_write, &mut slice);
+                })
+                .map_err(ErrorCode::from),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        if let Err(e) = res {
+            Err((slice, e))
+        } else {
+            Ok(slice)
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Data received. The argument is the number of bytes that can be
+    ///        read with the receive command.
+    /// - `1`: Data acknowledged. The argument is the number of bytes that
+    ///        were acknowledged, and that can be sent again.
+    /// - `2`: Connection state changed. The first argument is the event: `0`
+    ///        if the connection is established, `1` if the remote end closed
+    ///        its side of the connection and `2` if the connection is
+    ///        closed. For `2`, the second argument is the result: SUCCESS if
+    ///        the connection was closed gracefully, FAIL if it was reset and
+    ///        ENOACK if the remote end stopped responding.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        app_id: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => {
+                    mem::swap(&mut app.rx_callback, &mut callback);
+                    Ok(())
+                }
+                1 => {
+                    mem::swap(&mut app.tx_callback, &mut callback);
+                    Ok(())
+                }
+                2 => {
+                    mem::swap(&mut app.conn_callback, &mut callback);
+                    Ok(())
+                }
+                _ => Err(ErrorCode::NOSUPPORT),
+            })
+            .map_err(ErrorCode::from);
+
+        match res {
+            Ok(Ok(())) => Ok(callback),
+            Ok(Err(e)) | Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Listen for a connection on the local port `arg1`. Returns
+    ///        BUSY if the process already has an open connection, NOMEM if
+    ///        no socket is free and RESERVE if the port is in use.
+    /// - `2`: Connect from the local port `arg1` to the endpoint in the
+    ///        config buffer. Returns INVAL if the config buffer does not hold
+    ///        an endpoint, and the same errors as `1` otherwise.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. Returns the
+    ///        number of bytes queued, which can be less than `arg1` if the
+    ///        send buffer of the socket is full. Returns OFF if the
+    ///        connection is not established.
+    /// - `4`: Receive data into the read buffer. Returns the number of bytes
+    ///        copied.
+    /// - `5`: Close the connection once all queued data has been sent.
+    /// - `6`: Abort the connection, and release the socket.
+    /// - `7`: Get the state of the connection, as a `TCPState` value.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.assign_socket(appid) {
+                Ok(id) => result_to_command_return(self.sockets[id].listen(arg1 as u16)),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            2 => {
+                let (addr, port) = match self.get_remote_endpoint(appid) {
+                    Ok(endpoint) => endpoint,
+                    Err(e) => return CommandReturn::failure(e),
+                };
+                match self.assign_socket(appid) {
+                    Ok(id) => {
+                        result_to_command_return(self.sockets[id].connect(arg1 as u16, addr, port))
+                    }
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            3 => {
+                let socket = match self.get_socket(appid) {
+                    Ok(socket) => socket,
+                    Err(e) => return CommandReturn::failure(e),
+                };
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.app_write
+                            .map_or(CommandReturn::failure(ErrorCode::RESERVE), |data| {
+                                if arg1 > data.len() {
+                                    return CommandReturn::failure(ErrorCode::SIZE);
+                                }
+                                match socket.send(&data.as_ref()[..arg1]) {
+                                    Ok(queued) => CommandReturn::success_u32(queued as u32),
+                                    Err(e) => result_to_command_return(e),
+                                }
+                            })
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            4 => {
+                let socket = match self.get_socket(appid) {
+                    Ok(socket) => socket,
+                    Err(e) => return CommandReturn::failure(e),
+                };
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.app_read
+                            .mut_map_or(CommandReturn::failure(ErrorCode::RESERVE), |buf| {
+                                CommandReturn::success_u32(socket.recv(buf.as_mut()) as u32)
+                            })
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            5 => match self.get_socket(appid) {
+                Ok(socket) => result_to_command_return(socket.close()),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            6 => match self.get_socket(appid) {
+                Ok(socket) => {
+                    socket.abort();
+                    self.apps
+                        .enter(appid, |app, _| {
+                            app.socket = None;
+                            CommandReturn::success()
+                        })
+                        .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+                }
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            7 => match self.get_socket(appid) {
+                Ok(socket) => CommandReturn::success_u32(socket.get_state() as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> TCPClient for TCPDriver<'a, A> {
+    fn connected(&self, id: usize) {
+        let (addr, port) = self.sockets[id].get_remote_endpoint();
+        self.with_socket_owner(id, |app| {
+            app.app_cfg.mut_map_or((), |cfg| {
+                if cfg.len() == ENDPOINT_LEN {
+                    cfg.as_mut()[..16].copy_from_slice(&addr.0);
+                    cfg.as_mut()[16..].copy_from_slice(&port.to_le_bytes());
+                }
+            });
+            app.conn_callback.schedule(event::CONNECTED, 0, 0);
+        });
+    }
+
+    fn received(&self, id: usize, available: usize) {
+        self.with_socket_owner(id, |app| {
+            app.rx_callback.schedule(available, 0, 0);
+        });
+    }
+
+    fn sent(&self, id: usize, acked: usize) {
+        self.with_socket_owner(id, |app| {
+            app.tx_callback.schedule(acked, 0, 0);
+        });
+    }
+
+    fn remote_closed(&self, id: usize) {
+        self.with_socket_owner(id, |app| {
+            app.conn_callback.schedule(event::REMOTE_CLOSED, 0, 0);
+        });
+    }
+
+    fn closed(&self, id: usize, result: ReturnCode) {
+        self.with_socket_owner(id, |app| {
+            app.conn_callback
+                .schedule(event::CLOSED, usize::from(result), 0);
+        });
+    }
+}
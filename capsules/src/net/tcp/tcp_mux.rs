@@ -0,0 +1,195 @@
+//! This file contains the multiplexer of TCP sockets over the IPv6 layer.
+//!
+//! `MuxTcp` is the `IP6SendClient` and the TCP `IP6RecvClient` for a list of
+//! `TCPSocket`s. Received segments are dispatched to the socket of their
+//! connection, or to a socket listening on their destination port. Segments
+//! for which there is no socket are dropped.
+//!
+//! The IPv6 sender sends one packet at a time, so sockets do not send
+//! segments themselves. Instead, whenever the sender is idle the mux asks its
+//! sockets, in order, for the next segment they need to send. A socket that
+//! has more to send is asked again when the sender becomes idle again.
+//!
+//! The mux also drives the retransmission and TIME-WAIT timers of its
+//! sockets from a single alarm (usually a `VirtualMuxAlarm`), which fires
+//! every `TCP_TIMER_INTERVAL_MS` while any socket timer is running.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_mux = static_init!(
+//!     MuxTcp<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     MuxTcp::new(ip_send, tcp_alarm, &mut TCP_TX_BUF, net_cap)
+//! );
+//! ip_send.set_client(tcp_mux);
+//! ip_receive.set_protocol_client(ip6_nh::TCP, tcp_mux);
+//! tcp_alarm.set_alarm_client(tcp_mux);
+//!
+//! let socket = static_init!(
+//!     TCPSocket<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     TCPSocket::new(tcp_mux, &mut SEND_BUF, &mut RECV_BUF)
+//! );
+//! tcp_mux.add_socket(socket);
+//! ```
+
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::tcp::tcp_socket::TCPSocket;
+use crate::net::tcp::TCPHeader;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::common::List;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ReturnCode;
+
+/// Period of the timer of the mux, i.e. the granularity of the timers of
+/// the sockets.
+pub const TCP_TIMER_INTERVAL_MS: u32 = 100;
+
+pub struct MuxTcp<'a, A: Alarm<'a>> {
+    sockets: List<'a, TCPSocket<'a, A>>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    // Buffer for the payload of segments. Its length is the maximum segment
+    // size.
+    tx_buffer: TakeCell<'static, [u8]>,
+    sending: Cell<bool>,
+    timer_running: Cell<bool>,
+    iss_count: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: Alarm<'a>> MuxTcp<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> MuxTcp<'a, A> {
+        MuxTcp {
+            sockets: List::new(),
+            ip_sender: ip_sender,
+            alarm: alarm,
+            tx_buffer: TakeCell::new(tx_buffer),
+            sending: Cell::new(false),
+            timer_running: Cell::new(false),
+            iss_count: Cell::new(0),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn add_socket(&self, socket: &'a TCPSocket<'a, A>) {
+        self.sockets.push_tail(socket);
+    }
+
+    /// Whether any socket is bound to `port`.
+    pub(crate) fn port_in_use(&self, port: u16) -> bool {
+        self.sockets.iter().any(|socket| socket.uses_port(port))
+    }
+
+    /// Returns an initial sequence number for a new connection. RFC 793
+    /// derives it from a clock; we add a counter so that connections opened
+    /// at the same time get different numbers.
+    pub(crate) fn next_iss(&self) -> u32 {
+        let count = self.iss_count.get().wrapping_add(1);
+        self.iss_count.set(count);
+        self.alarm
+            .now()
+            .into_u32()
+            .wrapping_add(count.wrapping_mul(64000))
+    }
+
+    /// Starts the timer of the mux if it is not running already. Sockets call
+    /// this after starting their own timer.
+    pub(crate) fn start_timer(&self) {
+        if !self.timer_running.get() {
+            self.timer_running.set(true);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(TCP_TIMER_INTERVAL_MS));
+        }
+    }
+
+    /// Sends the next segment any socket needs to send, if the IPv6 sender is
+    /// idle.
+    pub(crate) fn output(&self) {
+        if self.sending.get() {
+            return;
+        }
+        self.tx_buffer.take().map(|buf| {
+            let mut buf = LeasableBuffer::new(buf);
+            for socket in self.sockets.iter() {
+                buf.reset();
+                let segment = socket.build_segment(&mut buf[..]);
+                if let Some((dst, header, len)) = segment {
+                    buf.slice(0..len);
+                    let result = self.ip_sender.send_to(
+                        dst,
+                        TransportHeader::TCP(header),
+                        &buf,
+                        self.net_cap,
+                    );
+                    // If the segment could not be sent, it is lost: the
+                    // retransmission timer of the socket recovers from this
+                    // like from any other loss.
+                    if result == ReturnCode::SUCCESS {
+                        self.sending.set(true);
+                        break;
+                    }
+                }
+            }
+            self.tx_buffer.replace(buf.take());
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6SendClient for MuxTcp<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+        self.output();
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6RecvClient for MuxTcp<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let len = cmp::min(payload.len(), ip_header.get_payload_len() as usize);
+        let (offset, tcp_header) = match TCPHeader::decode(&payload[..len]).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let src_addr = ip_header.get_src_addr();
+        let src_port = tcp_header.get_src_port();
+        let dst_port = tcp_header.get_dst_port();
+
+        let socket = self
+            .sockets
+            .iter()
+            .find(|socket| socket.is_connection(dst_port, src_addr, src_port))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find(|socket| socket.is_listening(dst_port))
+            });
+        socket.map(|socket| socket.receive_segment(src_addr, &tcp_header, &payload[offset..len]));
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for MuxTcp<'a, A> {
+    fn alarm(&self) {
+        for socket in self.sockets.iter() {
+            socket.tick(TCP_TIMER_INTERVAL_MS);
+        }
+        self.output();
+
+        if self.sockets.iter().any(|socket| socket.timer_running()) {
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(TCP_TIMER_INTERVAL_MS));
+        } else {
+            self.timer_running.set(false);
+        }
+    }
+}
@@ -0,0 +1,772 @@
+//! This file contains the implementation of a TCP connection (RFC 793).
+//!
+//! A `TCPSocket` holds the state of a single connection: its state in the
+//! TCP state machine, the send and receive sequence variables, and a send
+//! and a receive buffer. Data written with `send()` stays in the send buffer
+//! until it is acknowledged, and is sent in segments that fit the window
+//! advertised by the remote end. Received data is kept in the receive buffer
+//! until it is read with `recv()`; the free space of the receive buffer is
+//! advertised as the receive window.
+//!
+//! Sockets do not send or receive segments themselves. They are multiplexed
+//! over the IPv6 layer by a `MuxTcp` (see `tcp_mux.rs`), which asks sockets
+//! for segments to send whenever the IPv6 sender is idle, hands them
+//! received segments, and drives their retransmission timers.
+//!
+//! Limitations
+//! -----------
+//! - Only segments that arrive in order are accepted. Out of order segments
+//!   are dropped and acknowledged, so that the remote end retransmits them.
+//! - Unacknowledged data is retransmitted go-back-N style when the
+//!   retransmission timer expires. The retransmission timeout starts at
+//!   `INITIAL_RTO_MS` and doubles on each retransmission; it is not derived
+//!   from round trip time measurements.
+//! - No TCP options are sent, so the remote end uses the default MSS.
+//! - A listening socket becomes the connection when it receives a SYN (as in
+//!   RFC 793); there is no queue of pending connections.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_mux::MuxTcp;
+use crate::net::tcp::{tcp_flags, TCPHeader};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{ListLink, ListNode};
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+
+/// Initial retransmission timeout.
+pub const INITIAL_RTO_MS: u32 = 1000;
+/// Upper bound of the retransmission timeout after exponential backoff.
+pub const MAX_RTO_MS: u32 = 16000;
+/// Number of retransmissions of a segment after which the connection is
+/// closed with ENOACK.
+pub const MAX_RETRANSMISSIONS: u8 = 5;
+/// Time spent in the TIME-WAIT state. This is much shorter than the 2 MSL
+/// of RFC 793 to free sockets quickly.
+pub const TIME_WAIT_MS: u32 = 4000;
+
+/// The states of the TCP state machine (RFC 793, section 3.2).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TCPState {
+    Closed = 0,
+    Listen = 1,
+    SynSent = 2,
+    SynReceived = 3,
+    Established = 4,
+    FinWait1 = 5,
+    FinWait2 = 6,
+    CloseWait = 7,
+    Closing = 8,
+    LastAck = 9,
+    TimeWait = 10,
+}
+
+/// Callbacks for the user of a `TCPSocket`. `id` is the value passed to
+/// `TCPSocket::set_client()`, so that one client can use several sockets.
+pub trait TCPClient {
+    /// The connection has been established, either after a call to
+    /// `connect()` or, for a listening socket, after a remote end connected.
+    fn connected(&self, id: usize);
+
+    /// New data has been received. `available` is the number of bytes that
+    /// can now be read with `recv()`.
+    fn received(&self, id: usize, available: usize);
+
+    /// `acked` bytes of data passed to `send()` have been acknowledged by the
+    /// remote end, and the space they used in the send buffer is free again.
+    fn sent(&self, id: usize, acked: usize);
+
+    /// The remote end closed its side of the connection: no more data will
+    /// be received. Data can still be sent until `close()` is called.
+    fn remote_closed(&self, id: usize);
+
+    /// The connection is closed and the socket can be used again. `result`
+    /// is SUCCESS if it was closed gracefully, FAIL if it was reset by the
+    /// remote end and ENOACK if the remote end stopped acknowledging data.
+    fn closed(&self, id: usize, result: ReturnCode);
+}
+
+// Comparisons of sequence numbers, modulo 2^32
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+pub struct TCPSocket<'a, A: Alarm<'a>> {
+    mux: &'a MuxTcp<'a, A>,
+    client: OptionalCell<&'a dyn TCPClient>,
+    client_id: Cell<usize>,
+    next: ListLink<'a, TCPSocket<'a, A>>,
+
+    state: Cell<TCPState>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+
+    // Send sequence variables. Once the connection is synchronized, the
+    // first byte of `send_buf` is the byte with sequence number `snd_una`.
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    // Highest sequence number sent, which is ahead of `snd_nxt` while
+    // unacknowledged data is being retransmitted.
+    snd_max: Cell<u32>,
+    snd_wnd: Cell<u16>,
+    send_buf: TakeCell<'static, [u8]>,
+    send_len: Cell<usize>,
+    fin_queued: Cell<bool>,
+    fin_sent: Cell<bool>,
+
+    // Receive sequence variables
+    rcv_nxt: Cell<u32>,
+    recv_buf: TakeCell<'static, [u8]>,
+    recv_len: Cell<usize>,
+
+    // Pending control segments
+    ack_needed: Cell<bool>,
+    rst_needed: Cell<bool>,
+
+    // Retransmission (or TIME-WAIT) timer, in ms. 0 if stopped.
+    timer: Cell<u32>,
+    rto: Cell<u32>,
+    retransmissions: Cell<u8>,
+    // Whether to send one byte past a zero send window to probe for a
+    // window update.
+    probe: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> ListNode<'a, TCPSocket<'a, A>> for TCPSocket<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, TCPSocket<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: Alarm<'a>> TCPSocket<'a, A> {
+    /// Creates a closed socket. The socket must be added to `mux` with
+    /// `MuxTcp::add_socket()` before it is used.
+    pub fn new(
+        mux: &'a MuxTcp<'a, A>,
+        send_buf: &'static mut [u8],
+        recv_buf: &'static mut [u8],
+    ) -> TCPSocket<'a, A> {
+        TCPSocket {
+            mux: mux,
+            client: OptionalCell::empty(),
+            client_id: Cell::new(0),
+            next: ListLink::empty(),
+            state: Cell::new(TCPState::Closed),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_max: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            send_buf: TakeCell::new(send_buf),
+            send_len: Cell::new(0),
+            fin_queued: Cell::new(false),
+            fin_sent: Cell::new(false),
+            rcv_nxt: Cell::new(0),
+            recv_buf: TakeCell::new(recv_buf),
+            recv_len: Cell::new(0),
+            ack_needed: Cell::new(false),
+            rst_needed: Cell::new(false),
+            timer: Cell::new(0),
+            rto: Cell::new(INITIAL_RTO_MS),
+            retransmissions: Cell::new(0),
+            probe: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TCPClient, id: usize) {
+        self.client.set(client);
+        self.client_id.set(id);
+    }
+
+    pub fn get_state(&self) -> TCPState {
+        self.state.get()
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port.get()
+    }
+
+    /// Returns the address and port of the remote end of the connection.
+    pub fn get_remote_endpoint(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+
+    /// Waits for a connection on `port` (passive open).
+    ///
+    /// Returns EBUSY if the socket is not closed, EINVAL if `port` is 0 and
+    /// ERESERVE if another socket already uses `port`.
+    pub fn listen(&self, port: u16) -> ReturnCode {
+        if self.state.get() != TCPState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self.mux.port_in_use(port) {
+            return ReturnCode::ERESERVE;
+        }
+        self.reset();
+        self.local_port.set(port);
+        self.state.set(TCPState::Listen);
+        ReturnCode::SUCCESS
+    }
+
+    /// Opens a connection from `local_port` to `remote_port` at
+    /// `remote_addr` (active open). `TCPClient::connected()` is called once
+    /// the connection is established.
+    ///
+    /// Returns EBUSY if the socket is not closed, EINVAL if a port is 0 and
+    /// ERESERVE if another socket already uses `local_port`.
+    pub fn connect(&self, local_port: u16, remote_addr: IPAddr, remote_port: u16) -> ReturnCode {
+        if self.state.get() != TCPState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if local_port == 0 || remote_port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self.mux.port_in_use(local_port) {
+            return ReturnCode::ERESERVE;
+        }
+        self.reset();
+        self.local_port.set(local_port);
+        self.remote_addr.set(remote_addr);
+        self.remote_port.set(remote_port);
+        let iss = self.mux.next_iss();
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_max.set(iss);
+        self.state.set(TCPState::SynSent);
+        self.mux.output();
+        ReturnCode::SUCCESS
+    }
+
+    /// Queues as much of `data` as fits in the send buffer for transmission.
+    /// Returns the number of bytes queued, which is 0 if the send buffer is
+    /// full. `TCPClient::sent()` is called as queued data is acknowledged.
+    ///
+    /// Returns EOFF if the connection is not established or has been closed
+    /// with `close()`.
+    pub fn send(&self, data: &[u8]) -> Result<usize, ReturnCode> {
+        match self.state.get() {
+            TCPState::Established | TCPState::CloseWait => {}
+            _ => return Err(ReturnCode::EOFF),
+        }
+        if self.fin_queued.get() {
+            return Err(ReturnCode::EOFF);
+        }
+        let send_len = self.send_len.get();
+        let queued = self.send_buf.map_or(0, |send_buf| {
+            let len = cmp::min(data.len(), send_buf.len() - send_len);
+            send_buf[send_len..send_len + len].copy_from_slice(&data[..len]);
+            len
+        });
+        self.send_len.set(send_len + queued);
+        if queued > 0 {
+            self.mux.output();
+        }
+        Ok(queued)
+    }
+
+    /// Returns the number of received bytes that can be read with `recv()`.
+    pub fn available(&self) -> usize {
+        self.recv_len.get()
+    }
+
+    /// Reads received data into `buf` and returns the number of bytes read.
+    pub fn recv(&self, buf: &mut [u8]) -> usize {
+        let recv_len = self.recv_len.get();
+        let read = self.recv_buf.map_or(0, |recv_buf| {
+            let len = cmp::min(buf.len(), recv_len);
+            buf[..len].copy_from_slice(&recv_buf[..len]);
+            recv_buf.copy_within(len..recv_len, 0);
+            len
+        });
+        self.recv_len.set(recv_len - read);
+
+        // If the window we last advertised was small, tell the remote end
+        // that it opened again.
+        let capacity = self.recv_buf.map_or(0, |recv_buf| recv_buf.len());
+        if read > 0 && capacity - recv_len < capacity / 2 && self.is_synchronized() {
+            self.ack_needed.set(true);
+            self.mux.output();
+        }
+        read
+    }
+
+    /// Closes the sending side of the connection once all queued data has
+    /// been sent. `TCPClient::closed()` is called once both ends have closed
+    /// the connection. A listening socket, or a socket still waiting for a
+    /// reply to its SYN, is closed immediately without a callback.
+    ///
+    /// Returns EALREADY if the connection is already closing.
+    pub fn close(&self) -> ReturnCode {
+        match self.state.get() {
+            TCPState::Listen | TCPState::SynSent => {
+                self.state.set(TCPState::Closed);
+                self.timer.set(0);
+                ReturnCode::SUCCESS
+            }
+            TCPState::SynReceived | TCPState::Established => {
+                self.fin_queued.set(true);
+                self.state.set(TCPState::FinWait1);
+                self.mux.output();
+                ReturnCode::SUCCESS
+            }
+            TCPState::CloseWait => {
+                self.fin_queued.set(true);
+                self.state.set(TCPState::LastAck);
+                self.mux.output();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EALREADY,
+        }
+    }
+
+    /// Closes the connection immediately, discarding any queued or received
+    /// data, and resets it if it was synchronized. No callback is called.
+    pub fn abort(&self) {
+        if self.is_synchronized() || self.state.get() == TCPState::SynReceived {
+            self.rst_needed.set(true);
+        }
+        self.state.set(TCPState::Closed);
+        self.timer.set(0);
+        self.send_len.set(0);
+        self.recv_len.set(0);
+        self.mux.output();
+    }
+
+    fn reset(&self) {
+        self.send_len.set(0);
+        self.recv_len.set(0);
+        self.fin_queued.set(false);
+        self.fin_sent.set(false);
+        self.ack_needed.set(false);
+        self.rst_needed.set(false);
+        self.timer.set(0);
+        self.rto.set(INITIAL_RTO_MS);
+        self.retransmissions.set(0);
+        self.probe.set(false);
+    }
+
+    // Whether the SYNs of both ends have been acknowledged
+    fn is_synchronized(&self) -> bool {
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen | TCPState::SynSent | TCPState::SynReceived => {
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn recv_window(&self) -> u16 {
+        let capacity = self.recv_buf.map_or(0, |recv_buf| recv_buf.len());
+        cmp::min(capacity - self.recv_len.get(), u16::MAX as usize) as u16
+    }
+
+    fn set_snd_nxt(&self, snd_nxt: u32) {
+        self.snd_nxt.set(snd_nxt);
+        if seq_lt(self.snd_max.get(), snd_nxt) {
+            self.snd_max.set(snd_nxt);
+        }
+    }
+
+    fn start_timer(&self, ms: u32) {
+        self.timer.set(ms);
+        self.mux.start_timer();
+    }
+
+    fn enter_closed(&self, result: ReturnCode) {
+        self.state.set(TCPState::Closed);
+        self.timer.set(0);
+        self.client
+            .map(|client| client.closed(self.client_id.get(), result));
+    }
+
+    /// Whether the socket is bound to `port`.
+    pub(crate) fn uses_port(&self, port: u16) -> bool {
+        self.state.get() != TCPState::Closed && self.local_port.get() == port
+    }
+
+    /// Whether a segment from `remote_port` at `remote_addr` to `local_port`
+    /// belongs to this socket's connection.
+    pub(crate) fn is_connection(
+        &self,
+        local_port: u16,
+        remote_addr: IPAddr,
+        remote_port: u16,
+    ) -> bool {
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen => false,
+            _ => {
+                self.local_port.get() == local_port
+                    && self.remote_addr.get() == remote_addr
+                    && self.remote_port.get() == remote_port
+            }
+        }
+    }
+
+    /// Whether the socket listens for connections on `port`.
+    pub(crate) fn is_listening(&self, port: u16) -> bool {
+        self.state.get() == TCPState::Listen && self.local_port.get() == port
+    }
+
+    /// Whether the retransmission (or TIME-WAIT) timer is running.
+    pub(crate) fn timer_running(&self) -> bool {
+        self.timer.get() != 0
+    }
+
+    /// Advances the timer of the socket by `elapsed` ms, and handles its
+    /// expiry.
+    pub(crate) fn tick(&self, elapsed: u32) {
+        let timer = self.timer.get();
+        if timer == 0 {
+            return;
+        }
+        if timer > elapsed {
+            self.timer.set(timer - elapsed);
+            return;
+        }
+        self.timer.set(0);
+
+        if self.state.get() == TCPState::TimeWait {
+            self.enter_closed(ReturnCode::SUCCESS);
+        } else if self.snd_una.get() != self.snd_nxt.get() {
+            // Retransmit everything that was not acknowledged. Window
+            // probes that are not acknowledged because the window is still
+            // closed do not count as retransmissions.
+            let mut retransmissions = self.retransmissions.get();
+            if self.snd_wnd.get() != 0 {
+                retransmissions += 1;
+            }
+            if retransmissions > MAX_RETRANSMISSIONS {
+                self.rst_needed.set(self.is_synchronized());
+                self.enter_closed(ReturnCode::ENOACK);
+                return;
+            }
+            self.retransmissions.set(retransmissions);
+            self.rto.set(cmp::min(self.rto.get() * 2, MAX_RTO_MS));
+            self.snd_nxt.set(self.snd_una.get());
+            self.fin_sent.set(false);
+        } else if self.snd_wnd.get() == 0 && self.send_len.get() > 0 {
+            self.probe.set(true);
+        }
+    }
+
+    /// Builds the next segment this socket needs to send, writing its
+    /// payload to `buf`. Returns the destination address, the header and the
+    /// payload length of the segment, or None if there is nothing to send.
+    pub(crate) fn build_segment(&self, buf: &mut [u8]) -> Option<(IPAddr, TCPHeader, usize)> {
+        let mut header = TCPHeader::new();
+        header.set_src_port(self.local_port.get());
+        header.set_dst_port(self.remote_port.get());
+        header.set_window(self.recv_window());
+
+        if self.rst_needed.get() {
+            self.rst_needed.set(false);
+            header.set_seq_num(self.snd_nxt.get());
+            header.set_flags(tcp_flags::RST);
+            return Some((self.remote_addr.get(), header, 0));
+        }
+
+        let iss = self.iss.get();
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen => return None,
+            TCPState::SynSent | TCPState::SynReceived => {
+                // The only segment to send before the connection is
+                // synchronized is the SYN, or its retransmission.
+                if self.snd_nxt.get() != iss {
+                    return None;
+                }
+                header.set_seq_num(iss);
+                if self.state.get() == TCPState::SynReceived {
+                    header.set_ack_num(self.rcv_nxt.get());
+                    header.set_flags(tcp_flags::SYN | tcp_flags::ACK);
+                } else {
+                    header.set_flags(tcp_flags::SYN);
+                }
+                self.set_snd_nxt(iss.wrapping_add(1));
+                self.ack_needed.set(false);
+                self.start_timer(self.rto.get());
+                return Some((self.remote_addr.get(), header, 0));
+            }
+            _ => {}
+        }
+
+        let snd_una = self.snd_una.get();
+        let snd_nxt = self.snd_nxt.get();
+        let in_flight = snd_nxt.wrapping_sub(snd_una) as usize;
+        // Once our FIN is acknowledged nothing is in flight
+        let data_sent = in_flight.saturating_sub(if self.fin_sent.get() { 1 } else { 0 });
+        let send_len = self.send_len.get();
+
+        let mut len = 0;
+        let mut fin = false;
+        if !self.fin_sent.get() {
+            let mut window = self.snd_wnd.get() as usize;
+            if window == 0 && self.probe.get() {
+                window = 1;
+            }
+            len = cmp::min(
+                send_len - data_sent,
+                cmp::min(window.saturating_sub(data_sent), buf.len()),
+            );
+            fin = self.fin_queued.get() && data_sent + len == send_len;
+        }
+        if len == 0 && !fin && !self.ack_needed.get() {
+            return None;
+        }
+
+        self.send_buf.map(|send_buf| {
+            buf[..len].copy_from_slice(&send_buf[data_sent..data_sent + len]);
+        });
+        let mut flags = tcp_flags::ACK;
+        if len > 0 {
+            flags |= tcp_flags::PSH;
+        }
+        if fin {
+            flags |= tcp_flags::FIN;
+            self.fin_sent.set(true);
+        }
+        header.set_seq_num(snd_nxt);
+        header.set_ack_num(self.rcv_nxt.get());
+        header.set_flags(flags);
+
+        self.set_snd_nxt(snd_nxt.wrapping_add(len as u32 + if fin { 1 } else { 0 }));
+        self.ack_needed.set(false);
+        self.probe.set(false);
+        if (len > 0 || fin) && !self.timer_running() {
+            self.start_timer(self.rto.get());
+        }
+        Some((self.remote_addr.get(), header, len))
+    }
+
+    /// Processes a segment received for this socket (RFC 793, section 3.9).
+    pub(crate) fn receive_segment(&self, src_addr: IPAddr, header: &TCPHeader, payload: &[u8]) {
+        let seq = header.get_seq_num();
+        let ack = header.get_ack_num();
+        let has_ack = header.has_flags(tcp_flags::ACK);
+        let has_syn = header.has_flags(tcp_flags::SYN);
+        let has_rst = header.has_flags(tcp_flags::RST);
+        let iss = self.iss.get();
+
+        match self.state.get() {
+            TCPState::Closed => return,
+            TCPState::Listen => {
+                if has_rst || has_ack || !has_syn {
+                    return;
+                }
+                self.remote_addr.set(src_addr);
+                self.remote_port.set(header.get_src_port());
+                self.rcv_nxt.set(seq.wrapping_add(1));
+                let iss = self.mux.next_iss();
+                self.iss.set(iss);
+                self.snd_una.set(iss);
+                self.snd_nxt.set(iss);
+                self.snd_max.set(iss);
+                self.snd_wnd.set(header.get_window());
+                self.state.set(TCPState::SynReceived);
+                self.mux.output();
+                return;
+            }
+            TCPState::SynSent => {
+                if has_ack && ack != iss.wrapping_add(1) {
+                    return;
+                }
+                if has_rst {
+                    if has_ack {
+                        self.enter_closed(ReturnCode::FAIL);
+                    }
+                    return;
+                }
+                if !has_syn {
+                    return;
+                }
+                self.rcv_nxt.set(seq.wrapping_add(1));
+                self.snd_wnd.set(header.get_window());
+                if has_ack {
+                    // Only the SYN was sent, but it may be waiting to be
+                    // retransmitted
+                    self.snd_una.set(ack);
+                    self.set_snd_nxt(ack);
+                    self.state.set(TCPState::Established);
+                    self.timer.set(0);
+                    self.retransmissions.set(0);
+                    self.rto.set(INITIAL_RTO_MS);
+                    self.ack_needed.set(true);
+                    self.client
+                        .map(|client| client.connected(self.client_id.get()));
+                } else {
+                    // Simultaneous open: send a SYN,ACK instead
+                    self.state.set(TCPState::SynReceived);
+                    self.snd_nxt.set(iss);
+                }
+                self.mux.output();
+                return;
+            }
+            _ => {}
+        }
+
+        if seq != self.rcv_nxt.get() {
+            // Not the next segment we expect (e.g. a retransmission of a
+            // segment we already acknowledged, or out of order data): make
+            // sure the remote end knows what we expect.
+            if !has_rst {
+                if self.state.get() == TCPState::SynReceived {
+                    self.snd_nxt.set(iss);
+                } else {
+                    self.ack_needed.set(true);
+                }
+                self.mux.output();
+            }
+            return;
+        }
+
+        if has_rst {
+            if self.state.get() == TCPState::SynReceived {
+                // Passive open: go back to listening
+                self.reset();
+                self.state.set(TCPState::Listen);
+            } else {
+                self.enter_closed(ReturnCode::FAIL);
+            }
+            return;
+        }
+        if has_syn || !has_ack {
+            return;
+        }
+
+        if self.state.get() == TCPState::SynReceived {
+            if ack != iss.wrapping_add(1) {
+                return;
+            }
+            self.snd_una.set(ack);
+            self.set_snd_nxt(ack);
+            self.state.set(TCPState::Established);
+            self.timer.set(0);
+            self.retransmissions.set(0);
+            self.rto.set(INITIAL_RTO_MS);
+            self.client
+                .map(|client| client.connected(self.client_id.get()));
+        }
+
+        // Process the acknowledgement
+        // After a retransmission timeout, the remote end may acknowledge
+        // data past `snd_nxt` that it received before the timeout.
+        let snd_una = self.snd_una.get();
+        let snd_max = self.snd_max.get();
+        if seq_lt(snd_una, ack) && seq_le(ack, snd_max) {
+            let send_len = self.send_len.get();
+            let fin_acked =
+                self.fin_queued.get() && ack.wrapping_sub(snd_una) as usize == send_len + 1;
+            let acked = ack.wrapping_sub(snd_una) as usize - if fin_acked { 1 } else { 0 };
+            self.send_buf.map(|send_buf| {
+                send_buf.copy_within(acked..send_len, 0);
+            });
+            self.send_len.set(send_len - acked);
+            self.snd_una.set(ack);
+            if seq_lt(self.snd_nxt.get(), ack) {
+                self.snd_nxt.set(ack);
+                self.fin_sent.set(fin_acked);
+            }
+            self.retransmissions.set(0);
+            self.rto.set(INITIAL_RTO_MS);
+            if ack == self.snd_nxt.get() {
+                self.timer.set(0);
+            } else {
+                self.start_timer(INITIAL_RTO_MS);
+            }
+            if acked > 0 {
+                self.client
+                    .map(|client| client.sent(self.client_id.get(), acked));
+            }
+            if fin_acked {
+                match self.state.get() {
+                    TCPState::FinWait1 => self.state.set(TCPState::FinWait2),
+                    TCPState::Closing => {
+                        self.state.set(TCPState::TimeWait);
+                        self.start_timer(TIME_WAIT_MS);
+                    }
+                    TCPState::LastAck => {
+                        self.enter_closed(ReturnCode::SUCCESS);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        } else if seq_lt(snd_max, ack) {
+            // Acknowledges data we did not send
+            self.ack_needed.set(true);
+            self.mux.output();
+            return;
+        }
+        self.snd_wnd.set(header.get_window());
+        if header.get_window() == 0
+            && self.send_len.get() > 0
+            && self.snd_una.get() == self.snd_nxt.get()
+            && !self.timer_running()
+        {
+            // Probe the zero window when the timer expires, in case the
+            // window update is lost
+            self.start_timer(self.rto.get());
+        }
+
+        // Process the segment data
+        let mut accepted = 0;
+        match self.state.get() {
+            TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => {
+                let recv_len = self.recv_len.get();
+                accepted = self.recv_buf.map_or(0, |recv_buf| {
+                    let len = cmp::min(payload.len(), recv_buf.len() - recv_len);
+                    recv_buf[recv_len..recv_len + len].copy_from_slice(&payload[..len]);
+                    len
+                });
+                self.recv_len.set(recv_len + accepted);
+                self.rcv_nxt
+                    .set(self.rcv_nxt.get().wrapping_add(accepted as u32));
+                if payload.len() > 0 {
+                    self.ack_needed.set(true);
+                }
+                if accepted > 0 {
+                    self.client
+                        .map(|client| client.received(self.client_id.get(), self.recv_len.get()));
+                }
+            }
+            _ => {}
+        }
+
+        // Process the FIN, unless some of the data before it was dropped
+        if header.has_flags(tcp_flags::FIN) && accepted == payload.len() {
+            self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+            self.ack_needed.set(true);
+            match self.state.get() {
+                TCPState::Established => {
+                    self.state.set(TCPState::CloseWait);
+                    self.client
+                        .map(|client| client.remote_closed(self.client_id.get()));
+                }
+                TCPState::FinWait1 => {
+                    // Our FIN was not acknowledged yet
+                    self.state.set(TCPState::Closing);
+                }
+                TCPState::FinWait2 => {
+                    self.state.set(TCPState::TimeWait);
+                    self.start_timer(TIME_WAIT_MS);
+                }
+                _ => {}
+            }
+        }
+
+        self.mux.output();
+    }
+}
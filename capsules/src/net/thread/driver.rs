@@ -0,0 +1,221 @@
+//! Thread network attach for applications.
+//!
+//! This capsule lets a process attach the device to a Thread network as a
+//! Sleepy End Device, given the master key of the network, with the MLE
+//! handshake of `MleSed`.
+//!
+//! The driver is used by one process at a time: the first process to
+//! attach or detach owns the driver until it exits.
+//!
+//! ### Allow system call
+//!
+//! * ReadOnly 0: Master key of the network, 16 bytes.
+//!
+//! ### Subscribe system call
+//!
+//! * 0: Attach events. The callback signature is `fn(event: u32, data: u32)`,
+//!   where `event` is 0 when the device attached, with its RLOC16 as `data`,
+//!   and 1 when no parent accepted it.
+//!
+//! ### Command system call
+//!
+//! * 0: Driver check.
+//! * 1: Attach to the network with the master key, using the keys of key
+//!      sequence `data1`.
+//! * 2: Stop attaching, or detach from the parent.
+//! * 3: Return the RLOC16 of the device, if it is attached.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let thread = static_init!(
+//!     capsules::net::thread::ThreadDriver<'static, VirtualMuxAlarm<'static, Ast>, CCM>,
+//!     capsules::net::thread::ThreadDriver::new(
+//!         mle,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! mle.set_client(thread);
+//! ```
+
+use core::convert::TryInto;
+use core::mem;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time::Alarm;
+use kernel::{AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, Upcall};
+
+use super::mle::{MleClient, MleSed, State, MASTER_KEY_LEN};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Thread as usize;
+
+const EVENT_ATTACHED: usize = 0;
+const EVENT_ATTACH_FAILED: usize = 1;
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    master_key: ReadOnlyAppSlice,
+}
+
+pub struct ThreadDriver<'a, A: Alarm<'a>, C: AES128CCM<'a>> {
+    mle: &'a MleSed<'a, A, C>,
+    apps: Grant<App>,
+    owner: OptionalCell<AppId>,
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> ThreadDriver<'a, A, C> {
+    pub fn new(mle: &'a MleSed<'a, A, C>, grant: Grant<App>) -> ThreadDriver<'a, A, C> {
+        ThreadDriver {
+            mle,
+            apps: grant,
+            owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Make `appid` the owner of the driver, unless another process that is
+    /// still alive owns it.
+    fn claim(&self, appid: AppId) -> Result<(), ErrorCode> {
+        let owned_by_other = self.owner.map_or(false, |owner| {
+            *owner != appid && self.apps.enter(*owner, |_, _| ()).is_ok()
+        });
+        if owned_by_other {
+            Err(ErrorCode::BUSY)
+        } else {
+            self.owner.set(appid);
+            Ok(())
+        }
+    }
+
+    fn attach(&self, appid: AppId, key_sequence: u32) -> Result<(), ErrorCode> {
+        let master_key = self
+            .apps
+            .enter(appid, |app, _| {
+                app.master_key.map_or(Err(ErrorCode::RESERVE), |key| {
+                    let key: &[u8; MASTER_KEY_LEN] = key.try_into().or(Err(ErrorCode::SIZE))?;
+                    Ok(*key)
+                })
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+        self.mle.attach(&master_key, key_sequence)
+    }
+
+    fn upcall(&self, event: usize, data: usize) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |app, _| {
+                app.callback.schedule(event, data, 0);
+            });
+        });
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> MleClient for ThreadDriver<'a, A, C> {
+    fn attach_done(&self, result: Result<u16, ErrorCode>) {
+        match result {
+            Ok(rloc16) => self.upcall(EVENT_ATTACHED, rloc16 as usize),
+            Err(_) => self.upcall(EVENT_ATTACH_FAILED, 0),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> Driver for ThreadDriver<'a, A, C> {
+    /// Share the master key.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Master key of the network.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut slice, &mut app.master_key);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to attach events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Attaches and failed attaches.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Attach to and detach from the network.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Attach with the keys of key sequence `data1`.
+    /// - `2`: Stop attaching, or detach.
+    /// - `3`: Get the RLOC16 of the device.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        _data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => return CommandReturn::success(),
+            3 => {
+                return match self.mle.state() {
+                    State::Attached(rloc16) => CommandReturn::success_u32(rloc16 as u32),
+                    _ => CommandReturn::failure(ErrorCode::OFF),
+                }
+            }
+            _ => {}
+        }
+        if let Err(e) = self.claim(appid) {
+            return CommandReturn::failure(e);
+        }
+
+        let res = match command_num {
+            1 => self.attach(appid, data1 as u32),
+            2 => self.mle.detach(),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => CommandReturn::success(),
+            Err(e) => CommandReturn::failure(e),
+        }
+    }
+}
@@ -0,0 +1,758 @@
+//! Implements Mesh Link Establishment (MLE) for attaching a Sleepy End
+//! Device (SED) to a Thread network, as outlined in Chapter 4 of the Thread
+//! 1.1.1 Specification.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! The Parent Request is first sent to the routers only. If no router
+//! responds within 750 ms, it is sent again to routers and router-eligible
+//! end devices, which have 1250 ms to respond. The child selects the parent
+//! with the best link margin among the responses. The Child ID Request is
+//! sent up to three times, and the attach fails if the parent never
+//! responds.
+//!
+//! MLE messages are exchanged over UDP on port 19788, between link-local
+//! addresses. They are secured with AES-CCM at security level 5 (encryption
+//! and a 32-bit MIC), with the MLE key of the key sequence in the auxiliary
+//! security header. The authenticated data is the source and destination
+//! IPv6 addresses followed by the auxiliary security header, and the nonce is
+//! the extended address of the sender, the frame counter and the security
+//! level. The extended address of the sender of a received message is taken
+//! from the interface identifier of its link-local source address.
+//!
+//! The MLE key and the MAC key are derived from the master key as the two
+//! halves of HMAC-SHA256(master key, key sequence || "Thread").
+//!
+//! Only one message is secured or checked at a time: a message received
+//! while another one is being checked is dropped. Once attached, the child
+//! does not send Child Update Requests, so the parent eventually drops it if
+//! it does not communicate within the timeout it requested.
+//!
+//! Usage
+//! -----
+//!
+//! The IPv6 layer must send from `local_addr`, and the UDP sender and
+//! receiver must be bound to `MLE_PORT`.
+//!
+//! ```rust
+//! let ccm = static_init!(
+//!     VirtualAES128CCM<'static, AES>,
+//!     VirtualAES128CCM::new(ccm_mux, &mut CCM_BUF)
+//! );
+//! ccm.setup();
+//! let mle = static_init!(
+//!     MleSed<'static, VirtualMuxAlarm<'static, Ast>, VirtualAES128CCM<'static, AES>>,
+//!     MleSed::new(
+//!         udp_send,
+//!         ccm,
+//!         rng,
+//!         mle_alarm,
+//!         net_cap,
+//!         ext_addr,
+//!         IPAddr::generate_from_mac(MacAddress::Long(ext_addr)),
+//!         &mut CRYPT_BUF,
+//!         &mut SEND_BUF,
+//!     )
+//! );
+//! udp_send.set_client(mle);
+//! udp_recv.set_client(mle);
+//! ccm.set_client(mle);
+//! rng.set_client(mle);
+//! mle_alarm.set_alarm_client(mle);
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::sha256::HmacSha256;
+use core::cell::Cell;
+use core::convert::TryFrom;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{
+    CCMClient, AES128CCM, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+use kernel::{ErrorCode, ReturnCode};
+
+/// UDP port of MLE.
+pub const MLE_PORT: u16 = 19788;
+
+/// Length of the master key of a Thread network.
+pub const MASTER_KEY_LEN: usize = 16;
+
+/// Maximum length of the command of an MLE message: its type and its TLVs.
+pub const MAX_COMMAND_LEN: usize = 180;
+
+const SECURITY_SUITE_154: u8 = 0;
+/// Encryption with a 32-bit MIC.
+const SECURITY_LEVEL: u8 = 5;
+/// Key identifier mode 2: a 4-byte key source and a key index.
+const KEY_ID_MODE_2: u8 = 0b10 << 3;
+const SECURITY_CONTROL: u8 = KEY_ID_MODE_2 | SECURITY_LEVEL;
+/// Security control, frame counter, key source and key index.
+const AUX_HEADER_LEN: usize = 10;
+/// The security suite and the auxiliary security header.
+const SECURITY_HEADER_LEN: usize = 1 + AUX_HEADER_LEN;
+const MIC_LEN: usize = 4;
+/// Source and destination IPv6 addresses, and the auxiliary security header.
+const AUTH_DATA_LEN: usize = 16 + 16 + AUX_HEADER_LEN;
+
+/// Length of the buffer used to send MLE messages.
+pub const SEND_BUF_LEN: usize = SECURITY_HEADER_LEN + MAX_COMMAND_LEN + MIC_LEN;
+
+/// Length of the buffer used to secure and check MLE messages.
+pub const CRYPT_BUF_LEN: usize = AUTH_DATA_LEN + MAX_COMMAND_LEN + MIC_LEN;
+
+/// Length of the buffer the `VirtualAES128CCM` used by MLE needs: the first
+/// block, then the authenticated data and the command, each padded to
+/// blocks.
+pub const CCM_BUF_LEN: usize =
+    AES128_BLOCK_SIZE + round_up_to_block(2 + AUTH_DATA_LEN) + round_up_to_block(MAX_COMMAND_LEN);
+
+const fn round_up_to_block(len: usize) -> usize {
+    (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE
+}
+
+// MLE command types.
+const PARENT_REQUEST: u8 = 9;
+const PARENT_RESPONSE: u8 = 10;
+const CHILD_ID_REQUEST: u8 = 11;
+const CHILD_ID_RESPONSE: u8 = 12;
+
+/// Thread 1.1.
+const THREAD_VERSION: u16 = 2;
+
+/// A Sleepy End Device does not keep its receiver on, is not a full Thread
+/// device and only needs the stable network data.
+const MODE: u8 = LinkMode::SecureDataRequests as u8;
+
+/// Timeout requested from the parent, in seconds.
+const CHILD_TIMEOUT_S: u32 = 240;
+
+/// Time to wait for Parent Responses after each Parent Request: the first
+/// one is for routers only, the second one for routers and router-eligible
+/// end devices.
+const PARENT_RESPONSE_WINDOW_MS: [u32; 2] = [750, 1250];
+
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1250;
+const CHILD_ID_REQUEST_ATTEMPTS: u8 = 3;
+
+/// The link-local all-routers multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([
+    0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+]);
+
+/// Derive the MLE key and the MAC key of `key_sequence` from the master
+/// key of a Thread network.
+pub fn derive_keys(
+    master_key: &[u8; MASTER_KEY_LEN],
+    key_sequence: u32,
+) -> ([u8; AES128_KEY_SIZE], [u8; AES128_KEY_SIZE]) {
+    let mut message = [0; 10];
+    message[..4].copy_from_slice(&key_sequence.to_be_bytes());
+    message[4..].copy_from_slice(b"Thread");
+    let hash = HmacSha256::mac(master_key, &[&message]);
+
+    let mut mle_key = [0; AES128_KEY_SIZE];
+    let mut mac_key = [0; AES128_KEY_SIZE];
+    mle_key.copy_from_slice(&hash[..AES128_KEY_SIZE]);
+    mac_key.copy_from_slice(&hash[AES128_KEY_SIZE..]);
+    (mle_key, mac_key)
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum State {
+    Detached,
+    /// Waiting for random numbers for the challenge.
+    Starting,
+    /// Waiting for Parent Responses to a Parent Request. The value is the
+    /// index of the request.
+    ParentRequest(u8),
+    /// Waiting for the Child ID Response of the parent. The value is the
+    /// index of the request.
+    ChildIdRequest(u8),
+    /// Attached to a parent, with the given RLOC16.
+    Attached(u16),
+}
+
+/// The best parent that responded to a Parent Request.
+#[derive(Copy, Clone)]
+struct Parent {
+    address: IPAddr,
+    rloc16: u16,
+    /// Challenge of the parent, to return in the Child ID Request.
+    challenge: [u8; 8],
+    link_margin: u8,
+    /// Frame counter of the last message of the parent.
+    frame_counter: u32,
+}
+
+/// The message being secured or checked.
+#[derive(Copy, Clone)]
+enum Crypt {
+    Idle,
+    /// Securing a command of `len` bytes to send to `destination`.
+    Securing {
+        destination: IPAddr,
+        len: usize,
+    },
+    /// Checking a command of `len` bytes received from `source`.
+    Checking {
+        source: IPAddr,
+        frame_counter: u32,
+        len: usize,
+    },
+}
+
+pub trait MleClient {
+    /// Called when an attach started with `attach()` completes, with the
+    /// RLOC16 the parent assigned to the device, or `FAIL` if no parent
+    /// accepted it.
+    fn attach_done(&self, result: Result<u16, ErrorCode>);
+}
+
+/// Iterates over the TLVs of an MLE command, skipping the TLVs that cannot
+/// be decoded.
+struct Tlvs<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> Iterator for Tlvs<'b> {
+    type Item = Tlv<'b>;
+
+    fn next(&mut self) -> Option<Tlv<'b>> {
+        while self.buf.len() >= 2 {
+            let end = 2 + self.buf[1] as usize;
+            if end > self.buf.len() {
+                break;
+            }
+            let (tlv, rest) = self.buf.split_at(end);
+            self.buf = rest;
+            if let SResult::Done(_, tlv) = Tlv::decode(tlv) {
+                return Some(tlv);
+            }
+        }
+        None
+    }
+}
+
+/// Write an MLE command, returning its length.
+fn encode_command(buf: &mut [u8], command: u8, tlvs: &[Tlv]) -> Option<usize> {
+    if buf.is_empty() {
+        return None;
+    }
+    buf[0] = command;
+    let mut offset = 1;
+    for tlv in tlvs {
+        match tlv.encode(&mut buf[offset..]) {
+            SResult::Done(len, ()) => offset += len,
+            _ => return None,
+        }
+    }
+    Some(offset)
+}
+
+fn nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SECURITY_LEVEL;
+    nonce
+}
+
+/// The extended address of the sender of a message from `addr`, if it is a
+/// link-local address.
+fn ext_addr_of(addr: &IPAddr) -> Option<[u8; 8]> {
+    if !addr.is_unicast_link_local() {
+        return None;
+    }
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&addr.0[8..]);
+    ext_addr[0] ^= 0b0000_0010;
+    Some(ext_addr)
+}
+
+pub struct MleSed<'a, A: Alarm<'a>, C: AES128CCM<'a>> {
+    udp_sender: &'a dyn UDPSender<'a>,
+    ccm: &'a C,
+    rng: &'a dyn Rng<'a>,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    client: OptionalCell<&'a dyn MleClient>,
+
+    /// Extended address of the device, used in the nonces.
+    ext_addr: [u8; 8],
+    /// Address the IPv6 layer sends from.
+    local_addr: IPAddr,
+
+    state: Cell<State>,
+    key_sequence: Cell<u32>,
+    mle_key: Cell<[u8; AES128_KEY_SIZE]>,
+    mac_key: Cell<[u8; AES128_KEY_SIZE]>,
+    frame_counter: Cell<u32>,
+    challenge: Cell<[u8; 8]>,
+    parent: Cell<Option<Parent>>,
+
+    crypt: Cell<Crypt>,
+    crypt_buf: TakeCell<'static, [u8]>,
+    send_buf: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> MleSed<'a, A, C> {
+    /// `crypt_buf` must be at least `CRYPT_BUF_LEN` bytes long, and
+    /// `send_buf` at least `SEND_BUF_LEN` bytes long.
+    pub fn new(
+        udp_sender: &'a dyn UDPSender<'a>,
+        ccm: &'a C,
+        rng: &'a dyn Rng<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        ext_addr: [u8; 8],
+        local_addr: IPAddr,
+        crypt_buf: &'static mut [u8],
+        send_buf: &'static mut [u8],
+    ) -> MleSed<'a, A, C> {
+        MleSed {
+            udp_sender,
+            ccm,
+            rng,
+            alarm,
+            net_cap,
+            client: OptionalCell::empty(),
+            ext_addr,
+            local_addr,
+            state: Cell::new(State::Detached),
+            key_sequence: Cell::new(0),
+            mle_key: Cell::new([0; AES128_KEY_SIZE]),
+            mac_key: Cell::new([0; AES128_KEY_SIZE]),
+            frame_counter: Cell::new(0),
+            challenge: Cell::new([0; 8]),
+            parent: Cell::new(None),
+            crypt: Cell::new(Crypt::Idle),
+            crypt_buf: TakeCell::new(crypt_buf),
+            send_buf: TakeCell::new(send_buf),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MleClient) {
+        self.client.set(client);
+    }
+
+    pub fn state(&self) -> State {
+        self.state.get()
+    }
+
+    /// The MAC key of the network, for the frame security of the MAC layer,
+    /// if the device is attached.
+    pub fn mac_key(&self) -> Option<[u8; AES128_KEY_SIZE]> {
+        match self.state.get() {
+            State::Attached(_) => Some(self.mac_key.get()),
+            _ => None,
+        }
+    }
+
+    /// Attach to the Thread network with `master_key`, using the keys of
+    /// `key_sequence`. `attach_done()` is called when the attach completes.
+    pub fn attach(
+        &self,
+        master_key: &[u8; MASTER_KEY_LEN],
+        key_sequence: u32,
+    ) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Detached => {}
+            State::Attached(_) => return Err(ErrorCode::ALREADY),
+            _ => return Err(ErrorCode::BUSY),
+        }
+
+        let (mle_key, mac_key) = derive_keys(master_key, key_sequence);
+        self.key_sequence.set(key_sequence);
+        self.mle_key.set(mle_key);
+        self.mac_key.set(mac_key);
+        self.parent.set(None);
+
+        match self.rng.get() {
+            ReturnCode::SUCCESS => {
+                self.state.set(State::Starting);
+                Ok(())
+            }
+            rc => Err(ErrorCode::try_from(rc).unwrap_or(ErrorCode::FAIL)),
+        }
+    }
+
+    /// Stop attaching, or forget the parent.
+    pub fn detach(&self) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Detached => return Err(ErrorCode::ALREADY),
+            State::Starting => {
+                self.rng.cancel();
+            }
+            _ => {}
+        }
+        self.alarm.disarm();
+        self.state.set(State::Detached);
+        self.parent.set(None);
+        Ok(())
+    }
+
+    fn fail(&self) {
+        self.state.set(State::Detached);
+        self.parent.set(None);
+        self.client
+            .map(|client| client.attach_done(Err(ErrorCode::FAIL)));
+    }
+
+    fn set_timer(&self, ms: u32) {
+        let frequency = A::Frequency::frequency() as u64;
+        let ticks = A::Ticks::from((ms as u64 * frequency / 1000) as u32);
+        self.alarm.set_alarm(self.alarm.now(), ticks);
+    }
+
+    fn send_parent_request(&self, index: u8) {
+        let scan_mask = if index == 0 {
+            MulticastResponder::Router as u8
+        } else {
+            MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+        };
+        self.state.set(State::ParentRequest(index));
+        // A request that fails to be sent is sent again when the timer
+        // fires, as if no parent responded.
+        let _ = self.send(
+            ALL_ROUTERS,
+            PARENT_REQUEST,
+            &[
+                Tlv::Mode(MODE),
+                Tlv::Challenge(self.challenge.get()),
+                Tlv::ScanMask(scan_mask),
+                Tlv::Version(THREAD_VERSION),
+            ],
+        );
+        self.set_timer(PARENT_RESPONSE_WINDOW_MS[index as usize]);
+    }
+
+    fn send_child_id_request(&self, index: u8) {
+        self.parent.get().map(|parent| {
+            self.state.set(State::ChildIdRequest(index));
+            let _ = self.send(
+                parent.address,
+                CHILD_ID_REQUEST,
+                &[
+                    Tlv::Response(parent.challenge),
+                    Tlv::LinkLayerFrameCounter(0),
+                    Tlv::MleFrameCounter(self.frame_counter.get()),
+                    Tlv::Mode(MODE),
+                    Tlv::Timeout(CHILD_TIMEOUT_S),
+                    Tlv::Version(THREAD_VERSION),
+                    Tlv::TlvRequest(&[TlvType::Address16 as u8, TlvType::NetworkData as u8]),
+                ],
+            );
+            self.set_timer(CHILD_ID_RESPONSE_TIMEOUT_MS);
+        });
+    }
+
+    /// Secure an MLE command and send it to `destination`.
+    fn send(&self, destination: IPAddr, command: u8, tlvs: &[Tlv]) -> Result<(), ErrorCode> {
+        let buf = self.crypt_buf.take().ok_or(ErrorCode::BUSY)?;
+        let len = match encode_command(
+            &mut buf[AUTH_DATA_LEN..CRYPT_BUF_LEN - MIC_LEN],
+            command,
+            tlvs,
+        ) {
+            Some(len) => len,
+            None => {
+                self.crypt_buf.replace(buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+
+        let frame_counter = self.frame_counter.get();
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+        let key_sequence = self.key_sequence.get();
+
+        buf[..16].copy_from_slice(&self.local_addr.0);
+        buf[16..32].copy_from_slice(&destination.0);
+        let aux_header = &mut buf[32..AUTH_DATA_LEN];
+        aux_header[0] = SECURITY_CONTROL;
+        aux_header[1..5].copy_from_slice(&frame_counter.to_le_bytes());
+        aux_header[5..9].copy_from_slice(&key_sequence.to_be_bytes());
+        aux_header[9] = (key_sequence & 0x7f) as u8 + 1;
+
+        self.crypt.set(Crypt::Securing { destination, len });
+        self.start_crypt(buf, len, &nonce(&self.ext_addr, frame_counter), true)
+    }
+
+    fn start_crypt(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        nonce: &[u8; CCM_NONCE_LENGTH],
+        encrypting: bool,
+    ) -> Result<(), ErrorCode> {
+        self.ccm.set_key(&self.mle_key.get());
+        self.ccm.set_nonce(nonce);
+        match self
+            .ccm
+            .crypt(buf, 0, AUTH_DATA_LEN, len, MIC_LEN, true, encrypting)
+        {
+            (ReturnCode::SUCCESS, _) => Ok(()),
+            (rc, buf) => {
+                buf.map(|buf| self.crypt_buf.replace(buf));
+                self.crypt.set(Crypt::Idle);
+                Err(ErrorCode::try_from(rc).unwrap_or(ErrorCode::FAIL))
+            }
+        }
+    }
+
+    /// Send a secured message: the auxiliary security header, the encrypted
+    /// command and the MIC.
+    fn transmit(&self, destination: IPAddr, secured: &[u8]) {
+        self.send_buf.take().map(|buf| {
+            buf[0] = SECURITY_SUITE_154;
+            buf[1..1 + secured.len()].copy_from_slice(secured);
+            let mut dgram = LeasableBuffer::new(buf);
+            dgram.slice(..1 + secured.len());
+            if let Err(dgram) = self
+                .udp_sender
+                .send_to(destination, MLE_PORT, dgram, self.net_cap)
+            {
+                self.send_buf.replace(dgram.take());
+            }
+        });
+    }
+
+    fn received(&self, source: IPAddr, frame_counter: u32, command: &[u8]) {
+        if command.is_empty() {
+            return;
+        }
+        let tlvs = Tlvs { buf: &command[1..] };
+        match (command[0], self.state.get()) {
+            (PARENT_RESPONSE, State::ParentRequest(_)) => {
+                self.parent_response(source, frame_counter, tlvs)
+            }
+            (CHILD_ID_RESPONSE, State::ChildIdRequest(_)) => {
+                self.child_id_response(source, frame_counter, tlvs)
+            }
+            _ => {}
+        }
+    }
+
+    fn parent_response(&self, source: IPAddr, frame_counter: u32, tlvs: Tlvs) {
+        let mut rloc16 = None;
+        let mut response = None;
+        let mut challenge = None;
+        let mut link_margin = 0;
+        for tlv in tlvs {
+            match tlv {
+                Tlv::SourceAddress(address) => rloc16 = Some(address),
+                Tlv::Response(value) => response = Some(value),
+                Tlv::Challenge(value) => challenge = Some(value),
+                Tlv::LinkMargin(margin) => link_margin = margin,
+                _ => {}
+            }
+        }
+
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        if let (Some(rloc16), Some(challenge)) = (rloc16, challenge) {
+            let better = self
+                .parent
+                .get()
+                .map_or(true, |parent| link_margin > parent.link_margin);
+            if better {
+                self.parent.set(Some(Parent {
+                    address: source,
+                    rloc16,
+                    challenge,
+                    link_margin,
+                    frame_counter,
+                }));
+            }
+        }
+    }
+
+    fn child_id_response(&self, source: IPAddr, frame_counter: u32, tlvs: Tlvs) {
+        let parent = match self.parent.get() {
+            Some(parent) if parent.address == source => parent,
+            _ => return,
+        };
+        // Replayed messages are ignored.
+        if frame_counter <= parent.frame_counter {
+            return;
+        }
+
+        let mut source_address = None;
+        let mut address16 = None;
+        for tlv in tlvs {
+            match tlv {
+                Tlv::SourceAddress(address) => source_address = Some(address),
+                Tlv::Address16(address) => address16 = Some(address),
+                _ => {}
+            }
+        }
+
+        if source_address != Some(parent.rloc16) {
+            return;
+        }
+        if let Some(rloc16) = address16 {
+            self.alarm.disarm();
+            self.parent.set(Some(Parent {
+                frame_counter,
+                ..parent
+            }));
+            self.state.set(State::Attached(rloc16));
+            self.client.map(|client| client.attach_done(Ok(rloc16)));
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> rng::Client for MleSed<'a, A, C> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if self.state.get() != State::Starting {
+            return rng::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            self.fail();
+            return rng::Continue::Done;
+        }
+
+        match (randomness.next(), randomness.next()) {
+            (Some(first), Some(second)) => {
+                let mut challenge = [0; 8];
+                challenge[..4].copy_from_slice(&first.to_be_bytes());
+                challenge[4..].copy_from_slice(&second.to_be_bytes());
+                self.challenge.set(challenge);
+                self.send_parent_request(0);
+                rng::Continue::Done
+            }
+            _ => rng::Continue::More,
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> AlarmClient for MleSed<'a, A, C> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::ParentRequest(index) => {
+                if self.parent.get().is_some() {
+                    self.send_child_id_request(0);
+                } else if (index as usize) + 1 < PARENT_RESPONSE_WINDOW_MS.len() {
+                    self.send_parent_request(index + 1);
+                } else {
+                    self.fail();
+                }
+            }
+            State::ChildIdRequest(index) => {
+                if index + 1 < CHILD_ID_REQUEST_ATTEMPTS {
+                    self.send_child_id_request(index + 1);
+                } else {
+                    self.fail();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> CCMClient for MleSed<'a, A, C> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        match self.crypt.replace(Crypt::Idle) {
+            Crypt::Idle => {
+                self.crypt_buf.replace(buf);
+            }
+            Crypt::Securing { destination, len } => {
+                if res == ReturnCode::SUCCESS {
+                    self.transmit(
+                        destination,
+                        &buf[AUTH_DATA_LEN - AUX_HEADER_LEN..AUTH_DATA_LEN + len + MIC_LEN],
+                    );
+                }
+                self.crypt_buf.replace(buf);
+            }
+            Crypt::Checking {
+                source,
+                frame_counter,
+                len,
+            } => {
+                // Copy the command, so that the buffer is available to
+                // respond to it.
+                let mut command = [0; MAX_COMMAND_LEN];
+                command[..len].copy_from_slice(&buf[AUTH_DATA_LEN..AUTH_DATA_LEN + len]);
+                self.crypt_buf.replace(buf);
+                if res == ReturnCode::SUCCESS && tag_is_valid {
+                    self.received(source, frame_counter, &command[..len]);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> UDPSendClient for MleSed<'a, A, C> {
+    fn send_done(&self, _result: ReturnCode, dgram: LeasableBuffer<'static, u8>) {
+        self.send_buf.replace(dgram.take());
+    }
+}
+
+impl<'a, A: Alarm<'a>, C: AES128CCM<'a>> UDPRecvClient for MleSed<'a, A, C> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        _src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        match self.state.get() {
+            State::ParentRequest(_) | State::ChildIdRequest(_) => {}
+            _ => return,
+        }
+        if payload.len() < SECURITY_HEADER_LEN + MIC_LEN
+            || payload.len() > SECURITY_HEADER_LEN + MAX_COMMAND_LEN + MIC_LEN
+            || payload[0] != SECURITY_SUITE_154
+        {
+            return;
+        }
+        let aux_header = &payload[1..SECURITY_HEADER_LEN];
+        if aux_header[0] != SECURITY_CONTROL
+            || aux_header[5..9] != self.key_sequence.get().to_be_bytes()
+        {
+            return;
+        }
+        let sender = match ext_addr_of(&src_addr) {
+            Some(ext_addr) => ext_addr,
+            None => return,
+        };
+
+        let frame_counter =
+            u32::from_le_bytes([aux_header[1], aux_header[2], aux_header[3], aux_header[4]]);
+        let len = payload.len() - SECURITY_HEADER_LEN - MIC_LEN;
+        self.crypt_buf.take().map(|buf| {
+            buf[..16].copy_from_slice(&src_addr.0);
+            buf[16..32].copy_from_slice(&dst_addr.0);
+            buf[32..AUTH_DATA_LEN + len + MIC_LEN].copy_from_slice(&payload[1..]);
+            self.crypt.set(Crypt::Checking {
+                source: src_addr,
+                frame_counter,
+                len,
+            });
+            let _ = self.start_crypt(buf, len, &nonce(&sender, frame_counter), false);
+        });
+    }
+}
@@ -1 +1,7 @@
+pub mod mle;
 pub mod tlv;
+
+mod driver;
+
+pub use self::driver::ThreadDriver;
+pub use self::driver::DRIVER_NUM;
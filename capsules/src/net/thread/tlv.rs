@@ -9,16 +9,7 @@
 //! required to support MLE for attaching a Sleepy End Device (SED) to a
 //! Thread network.
 //!
-//! MLE for network attaching comprises a four-step handshake that works
-//! as follows:
-//!
-//! 1. A child device multicasts a Parent Request MLE command.
-//! 2. Each potential parent device on the network unicasts a Parent
-//!    Response MLE command.
-//! 3. The child device selects a parent based on a hierarchy of
-//!    connectivity metrics and unicasts a Child ID Request MLE
-//!    command.
-//! 4. The selected parent unicasts a Child ID Response MLE command.
+//! See the `mle` module for the MLE handshake that uses these TLVs.
 //!
 //! A TLV is comprised of three parts:
 //!
@@ -47,10 +38,7 @@
 //!
 //! Author: Mateo Garcia <mateog@stanford.edu>
 
-// TODO: Move the MLE explanation above to the MLE module, when it is created.
-
 // NOTES FOR DEBUGGING:
-// - .to_be() may not have been called on values wider than one byte
 // - encode_bytes_be may have been used instead of encode_bytes
 // - decode_bytes_be may have been used instead of decode_bytes
 // - See 4.5.25 Active Operational Dataset TLV and 4.5.26 Pending Operational Dataset TLV
@@ -58,7 +46,7 @@
 //      if either of the dataset tlvs are sent?
 
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_bytes_be, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{decode_bytes, decode_bytes_be, decode_u16, decode_u32, decode_u8};
 use crate::net::stream::{encode_bytes, encode_bytes_be, encode_u16, encode_u32, encode_u8};
 use core::mem;
 
@@ -127,7 +115,7 @@ impl Tlv<'_> {
             Tlv::SourceAddress(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::Mode(ref mode) => {
@@ -139,37 +127,37 @@ impl Tlv<'_> {
             Tlv::Timeout(ref max_transmit_interval) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, max_transmit_interval.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *max_transmit_interval);
                 stream_done!(offset)
             }
             Tlv::Challenge(ref byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
             Tlv::Response(ref byte_str) => {
                 let value_width = byte_str.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_bytes_be, byte_str);
+                offset = enc_consume!(buf, offset; encode_bytes, byte_str);
                 stream_done!(offset)
             }
             Tlv::LinkLayerFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::MleFrameCounter(ref frame_counter) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, frame_counter.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *frame_counter);
                 stream_done!(offset)
             }
             Tlv::Address16(ref mac_address) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, mac_address.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *mac_address);
                 stream_done!(offset)
             }
             Tlv::LeaderData {
@@ -185,7 +173,7 @@ impl Tlv<'_> {
                     + mem::size_of::<u8>()
                     + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, partition_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, partition_id);
                 offset = enc_consume!(buf, offset; encode_u8, weighting);
                 offset = enc_consume!(buf, offset; encode_u8, data_version);
                 offset = enc_consume!(buf, offset; encode_u8, stable_data_version);
@@ -246,7 +234,7 @@ impl Tlv<'_> {
                 offset = enc_consume!(buf, offset; encode_u8, id_sequence);
                 offset = enc_consume!(buf, offset; encode_u8, active_routers);
                 if let Some(ref buf_size) = sed_buffer_size {
-                    offset = enc_consume!(buf, offset; encode_u16, buf_size.to_be());
+                    offset = enc_consume!(buf, offset; encode_u16, *buf_size);
                 }
                 if let Some(ref datagram_cnt) = sed_datagram_count {
                     offset = enc_consume!(buf, offset; encode_u8, *datagram_cnt);
@@ -316,12 +304,12 @@ impl Tlv<'_> {
             }
             TlvType::Challenge => {
                 let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut byte_str);
                 stream_done!(offset, Tlv::Challenge(byte_str))
             }
             TlvType::Response => {
                 let mut byte_str = [0u8; 8];
-                let offset = dec_consume!(buf, offset; decode_bytes_be, &mut byte_str);
+                let offset = dec_consume!(buf, offset; decode_bytes, &mut byte_str);
                 stream_done!(offset, Tlv::Response(byte_str))
             }
             TlvType::LinkLayerFrameCounter => {
@@ -618,7 +606,7 @@ impl NetworkDataTlv<'_> {
                 };
                 let first_byte: u8 = t_bit | (0b1111 & s_id);
                 offset = enc_consume!(buf, offset; encode_u8, first_byte);
-                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, s_enterprise_number);
                 offset = enc_consume!(buf, offset; encode_u8, s_service_data_length);
                 offset = enc_consume!(buf, offset; encode_bytes_be, &s_service_data);
                 offset = enc_consume!(buf, offset; encode_bytes, sub_tlvs);
@@ -883,7 +871,7 @@ impl HasRouteTlvValue {
     /// Serializes this Has Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 3);
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.r_border_router_16);
         let last_byte = ((self.r_preference & 0b11) as u8) << 6;
         offset = enc_consume!(buf, offset; encode_u8, last_byte);
         stream_done!(offset)
@@ -930,8 +918,8 @@ impl BorderRouterTlvValue {
     /// Serializes this Border Route TLV value into `buf`.
     pub fn encode(&self, buf: &mut [u8]) -> SResult {
         stream_len_cond!(buf, 4); // Each Border Router TLV value is 32 bits wide.
-        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16.to_be());
-        offset = enc_consume!(buf, offset; encode_u16, self.p_bits.to_be());
+        let mut offset = enc_consume!(buf, 0; encode_u16, self.p_border_router_16);
+        offset = enc_consume!(buf, offset; encode_u16, self.p_bits);
         stream_done!(offset)
     }
 
@@ -969,7 +957,7 @@ impl ServiceSubTlv {
             } => {
                 let value_width = mem::size_of::<u16>() + s_server_data.len();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width, stable);
-                offset = enc_consume!(buf, offset; encode_u16, s_server_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, s_server_16);
                 offset = enc_consume!(buf, offset; encode_bytes_be, &s_server_data);
                 stream_done!(offset)
             }
@@ -1094,13 +1082,13 @@ impl NetworkManagementTlv<'_> {
                 let value_width = mem::size_of::<u8>() + mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
                 offset = enc_consume!(buf, offset; encode_u8, channel_page);
-                offset = enc_consume!(buf, offset; encode_u16, channel.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, channel);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PanId(ref pan_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, pan_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *pan_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ExtendedPanId(ref extended_pan_id) => {
@@ -1151,7 +1139,7 @@ impl NetworkManagementTlv<'_> {
             NetworkManagementTlv::BorderAgentLocator(ref rloc_16) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rloc_16.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *rloc_16);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerId(ref commissioner_id) => {
@@ -1164,7 +1152,7 @@ impl NetworkManagementTlv<'_> {
             NetworkManagementTlv::CommissionerSessionId(ref session_id) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, session_id.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *session_id);
                 stream_done!(offset)
             }
             NetworkManagementTlv::SecurityPolicy {
@@ -1173,7 +1161,7 @@ impl NetworkManagementTlv<'_> {
             } => {
                 let value_width = mem::size_of::<u16>() + mem::size_of::<u8>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, rotation_time.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, rotation_time);
                 offset = enc_consume!(buf, offset; encode_u8, policy_bits);
                 stream_done!(offset)
             }
@@ -1187,13 +1175,13 @@ impl NetworkManagementTlv<'_> {
                 offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::CommissionerUdpPort(ref udp_port) => {
                 let value_width = mem::size_of::<u16>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u16, udp_port.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, *udp_port);
                 stream_done!(offset)
             }
             NetworkManagementTlv::PendingTimestamp {
@@ -1206,13 +1194,13 @@ impl NetworkManagementTlv<'_> {
                 offset = enc_consume!(buf, offset; encode_bytes_be, &timestamp_seconds);
                 let u_bit_val = if u_bit { 1u16 } else { 0u16 };
                 let end_bytes = (timestamp_ticks << 1) | u_bit_val;
-                offset = enc_consume!(buf, offset; encode_u16, end_bytes.to_be());
+                offset = enc_consume!(buf, offset; encode_u16, end_bytes);
                 stream_done!(offset)
             }
             NetworkManagementTlv::DelayTimer(ref time_remaining) => {
                 let value_width = mem::size_of::<u32>();
                 let mut offset = enc_consume!(buf; self; encode_tl, value_width);
-                offset = enc_consume!(buf, offset; encode_u32, time_remaining.to_be());
+                offset = enc_consume!(buf, offset; encode_u32, *time_remaining);
                 stream_done!(offset)
             }
             NetworkManagementTlv::ChannelMask(ref entries) => {
@@ -64,3 +64,31 @@ pub fn u16_to_network_slice(short: u16, slice: &mut [u8]) {
     slice[0] = (short >> 8) as u8;
     slice[1] = (short & 0xff) as u8;
 }
+
+/// Adds the contents of `buf` to the checksum accumulator `sum` as a sequence
+/// of 16-bit words in network byte order, as required by the Internet
+/// checksum (RFC 1071). An odd trailing byte is padded with zero, so only the
+/// last buffer added to an accumulator may have an odd length.
+pub fn checksum_add(sum: u32, buf: &[u8]) -> u32 {
+    let mut sum = sum;
+    for word in buf.chunks(2) {
+        let msb = (word[0] as u32) << 8;
+        let lsb = if word.len() > 1 { word[1] as u32 } else { 0 };
+        sum += msb + lsb;
+        // Fold early so that the accumulator can not overflow
+        if sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+    }
+    sum
+}
+
+/// Folds the checksum accumulator `sum` into 16 bits and returns its ones'
+/// complement, i.e. the value of the checksum field (in host byte order).
+pub fn checksum_finish(sum: u32) -> u16 {
+    let mut sum = sum;
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,804 @@
+//! Software implementation of ECDSA and ECDH over the NIST P-256 curve.
+//!
+//! `P256Software` implements the `public_key_crypto` HILs without any
+//! hardware support:
+//!
+//! - `SignatureVerify` and `SignatureSign` with ECDSA over SHA-256 hashes.
+//!   Signatures are the 64 byte `r || s`, and signing derives the nonce from
+//!   the private key and the hash as in RFC 6979, so it needs no randomness.
+//! - `KeyAgreement` with ECDH. The shared secret is the x coordinate of the
+//!   shared point.
+//!
+//! Public keys are in the 65 byte uncompressed SEC1 encoding
+//! `0x04 || x || y`, and private keys are 32 byte big-endian scalars. Public
+//! keys are checked to be on the curve before they are used.
+//!
+//! Each operation completes in a single deferred call, which takes a few
+//! scalar multiplications. Where a signature has to be checked synchronously,
+//! `verify_signature()` does the same work in place, and
+//! `P256CredentialsChecker` uses it to check the ECDSA credentials of
+//! processes as they are loaded. The arithmetic is not hardened against timing
+//! side channels, so the private key operations should only be used where
+//! the timing of the kernel cannot be observed by an attacker.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::p256::P256Software;
+//!
+//! let p256 = static_init!(
+//!     P256Software<'static>,
+//!     P256Software::new(dynamic_deferred_caller)
+//! );
+//! p256.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(p256)
+//!         .expect("no deferred call slot available for p256"),
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::convert::TryInto;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::public_key_crypto::{
+    ClientKeyAgreement, ClientSign, ClientVerify, KeyAgreement, PrivateKey, PublicKey,
+    SignatureSign, SignatureVerify,
+};
+use kernel::procs::{
+    AppCredentialsChecker, CheckResult, TbfHeaderV2Credentials, TbfHeaderV2CredentialsType,
+};
+use kernel::ErrorCode;
+
+use crate::sha256::{HmacSha256, Sha256};
+
+/// Length of the hashes that are signed, in bytes.
+pub const HASH_LEN: usize = 32;
+/// Length of a signature, `r || s`, in bytes.
+pub const SIGNATURE_LEN: usize = 64;
+/// Length of an uncompressed public key, `0x04 || x || y`, in bytes.
+pub const PUBLIC_KEY_LEN: usize = 65;
+/// Length of a private key, in bytes.
+pub const PRIVATE_KEY_LEN: usize = 32;
+/// Length of a shared secret, in bytes.
+pub const SECRET_LEN: usize = 32;
+
+/// A 256-bit integer, as little-endian 32-bit limbs.
+type U256 = [u32; 8];
+
+/// A modulus, with the constants for Montgomery multiplication with
+/// R = 2^256.
+struct Modulus {
+    m: U256,
+    /// -m^-1 mod 2^32.
+    m_inv: u32,
+    /// R^2 mod m.
+    r2: U256,
+}
+
+/// The prime of the field of the curve.
+const P: Modulus = Modulus::new([
+    0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001, 0xffffffff,
+]);
+
+/// The order of the base point.
+const N: Modulus = Modulus::new([
+    0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000, 0xffffffff,
+]);
+
+/// The `b` coefficient of the curve `y^2 = x^3 - 3x + b`.
+const B: U256 = [
+    0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0, 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8,
+];
+
+/// The coordinates of the base point.
+const GX: U256 = [
+    0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81, 0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2,
+];
+const GY: U256 = [
+    0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357, 0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2,
+];
+
+const ZERO: U256 = [0; 8];
+const ONE: U256 = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// Returns `a + b` and the carry.
+const fn add(a: U256, b: U256) -> (U256, bool) {
+    let mut sum = [0; 8];
+    let mut carry = 0;
+    let mut i = 0;
+    while i < 8 {
+        let s = a[i] as u64 + b[i] as u64 + carry;
+        sum[i] = s as u32;
+        carry = s >> 32;
+        i += 1;
+    }
+    (sum, carry != 0)
+}
+
+/// Returns `a - b` and the borrow.
+const fn sub(a: U256, b: U256) -> (U256, bool) {
+    let mut difference = [0; 8];
+    let mut borrow = 0;
+    let mut i = 0;
+    while i < 8 {
+        let d = (a[i] as u64).wrapping_sub(b[i] as u64 + borrow);
+        difference[i] = d as u32;
+        borrow = d >> 63;
+        i += 1;
+    }
+    (difference, borrow != 0)
+}
+
+fn is_zero(a: &U256) -> bool {
+    a.iter().all(|limb| *limb == 0)
+}
+
+fn bit(a: &U256, index: usize) -> bool {
+    (a[index / 32] >> (index % 32)) & 1 == 1
+}
+
+fn from_be_bytes(bytes: &[u8]) -> U256 {
+    let mut a = ZERO;
+    for (limb, chunk) in a.iter_mut().zip(bytes.rchunks(4)) {
+        *limb = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    a
+}
+
+fn to_be_bytes(a: &U256, bytes: &mut [u8]) {
+    for (limb, chunk) in a.iter().zip(bytes.rchunks_mut(4)) {
+        chunk.copy_from_slice(&limb.to_be_bytes());
+    }
+}
+
+impl Modulus {
+    const fn new(m: U256) -> Modulus {
+        // Newton's iteration doubles the number of correct low bits of the
+        // inverse of the odd m[0] every step.
+        let mut inv: u32 = 1;
+        let mut i = 0;
+        while i < 5 {
+            inv = inv.wrapping_mul(2u32.wrapping_sub(m[0].wrapping_mul(inv)));
+            i += 1;
+        }
+
+        // R mod m is 2^256 - m, as m > 2^255. Doubling it 256 more times
+        // gives R^2 mod m.
+        let mut r2 = sub(ZERO, m).0;
+        let mut i = 0;
+        while i < 256 {
+            let (double, carry) = add(r2, r2);
+            let (reduced, borrow) = sub(double, m);
+            r2 = if carry || !borrow { reduced } else { double };
+            i += 1;
+        }
+
+        Modulus {
+            m,
+            m_inv: inv.wrapping_neg(),
+            r2,
+        }
+    }
+
+    /// Reduce `a`, which must be less than 2m.
+    fn reduce(&self, a: U256, carry: bool) -> U256 {
+        let (reduced, borrow) = sub(a, self.m);
+        if carry || !borrow {
+            reduced
+        } else {
+            a
+        }
+    }
+
+    fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add(*a, *b);
+        self.reduce(sum, carry)
+    }
+
+    fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (difference, borrow) = sub(*a, *b);
+        if borrow {
+            add(difference, self.m).0
+        } else {
+            difference
+        }
+    }
+
+    /// Montgomery multiplication: returns `a * b / R mod m`.
+    fn mul(&self, a: &U256, b: &U256) -> U256 {
+        let mut t = [0u32; 10];
+        for i in 0..8 {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let s = t[j] as u64 + a[j] as u64 * b[i] as u64 + carry;
+                t[j] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[8] = s as u32;
+            t[9] = (s >> 32) as u32;
+
+            let factor = t[0].wrapping_mul(self.m_inv) as u64;
+            let mut carry = (t[0] as u64 + factor * self.m[0] as u64) >> 32;
+            for j in 1..8 {
+                let s = t[j] as u64 + factor * self.m[j] as u64 + carry;
+                t[j - 1] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[7] = s as u32;
+            t[8] = t[9] + (s >> 32) as u32;
+        }
+
+        let mut result = ZERO;
+        result.copy_from_slice(&t[..8]);
+        self.reduce(result, t[8] != 0)
+    }
+
+    fn square(&self, a: &U256) -> U256 {
+        self.mul(a, a)
+    }
+
+    fn to_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &self.r2)
+    }
+
+    fn from_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &ONE)
+    }
+
+    /// Inverse of `a`, in the Montgomery domain, by Fermat's little theorem.
+    fn invert(&self, a: &U256) -> U256 {
+        let exponent = sub(self.m, [2, 0, 0, 0, 0, 0, 0, 0]).0;
+        let mut result = self.to_montgomery(&ONE);
+        for index in (0..256).rev() {
+            result = self.square(&result);
+            if bit(&exponent, index) {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+
+    /// Whether `a` is less than the modulus.
+    fn contains(&self, a: &U256) -> bool {
+        sub(*a, self.m).1
+    }
+}
+
+/// A point of the curve in Jacobian coordinates, in the Montgomery domain
+/// of the field. `z` is zero for the point at infinity.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl Point {
+    const INFINITY: Point = Point {
+        x: ZERO,
+        y: ZERO,
+        z: ZERO,
+    };
+
+    /// The point with the affine coordinates `x` and `y`, or `None` if it is
+    /// not on the curve.
+    fn from_affine(x: &U256, y: &U256) -> Option<Point> {
+        if !P.contains(x) || !P.contains(y) {
+            return None;
+        }
+        let x = P.to_montgomery(x);
+        let y = P.to_montgomery(y);
+        let x3 = P.mul(&P.square(&x), &x);
+        let three_x = P.add(&P.add(&x, &x), &x);
+        let rhs = P.add(&P.sub(&x3, &three_x), &P.to_montgomery(&B));
+        if P.square(&y) != rhs {
+            return None;
+        }
+        Some(Point {
+            x,
+            y,
+            z: P.to_montgomery(&ONE),
+        })
+    }
+
+    fn base() -> Point {
+        Point {
+            x: P.to_montgomery(&GX),
+            y: P.to_montgomery(&GY),
+            z: P.to_montgomery(&ONE),
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        is_zero(&self.z)
+    }
+
+    /// The affine coordinates of the point, or `None` at infinity.
+    fn to_affine(&self) -> Option<(U256, U256)> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = P.invert(&self.z);
+        let z_inv2 = P.square(&z_inv);
+        let x = P.mul(&self.x, &z_inv2);
+        let y = P.mul(&self.y, &P.mul(&z_inv2, &z_inv));
+        Some((P.from_montgomery(&x), P.from_montgomery(&y)))
+    }
+
+    fn double(&self) -> Point {
+        if self.is_infinity() {
+            return *self;
+        }
+        let delta = P.square(&self.z);
+        let gamma = P.square(&self.y);
+        let beta = P.mul(&self.x, &gamma);
+        let product = P.mul(&P.sub(&self.x, &delta), &P.add(&self.x, &delta));
+        let alpha = P.add(&P.add(&product, &product), &product);
+
+        let beta4 = P.add(&P.add(&beta, &beta), &P.add(&beta, &beta));
+        let x = P.sub(&P.square(&alpha), &P.add(&beta4, &beta4));
+        let z = P.sub(&P.sub(&P.square(&P.add(&self.y, &self.z)), &gamma), &delta);
+        let gamma2 = P.square(&gamma);
+        let gamma2_4 = P.add(&P.add(&gamma2, &gamma2), &P.add(&gamma2, &gamma2));
+        let y = P.sub(
+            &P.mul(&alpha, &P.sub(&beta4, &x)),
+            &P.add(&gamma2_4, &gamma2_4),
+        );
+        Point { x, y, z }
+    }
+
+    fn add(&self, other: &Point) -> Point {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = P.square(&self.z);
+        let z2z2 = P.square(&other.z);
+        let u1 = P.mul(&self.x, &z2z2);
+        let u2 = P.mul(&other.x, &z1z1);
+        let s1 = P.mul(&P.mul(&self.y, &other.z), &z2z2);
+        let s2 = P.mul(&P.mul(&other.y, &self.z), &z1z1);
+
+        let h = P.sub(&u2, &u1);
+        let s = P.sub(&s2, &s1);
+        if is_zero(&h) {
+            return if is_zero(&s) {
+                self.double()
+            } else {
+                Point::INFINITY
+            };
+        }
+
+        let i = P.square(&P.add(&h, &h));
+        let j = P.mul(&h, &i);
+        let r = P.add(&s, &s);
+        let v = P.mul(&u1, &i);
+        let x = P.sub(&P.sub(&P.square(&r), &j), &P.add(&v, &v));
+        let s1j = P.mul(&s1, &j);
+        let y = P.sub(&P.mul(&r, &P.sub(&v, &x)), &P.add(&s1j, &s1j));
+        let z = P.mul(
+            &P.sub(&P.sub(&P.square(&P.add(&self.z, &other.z)), &z1z1), &z2z2),
+            &h,
+        );
+        Point { x, y, z }
+    }
+
+    fn mul(&self, scalar: &U256) -> Point {
+        let mut result = Point::INFINITY;
+        for index in (0..256).rev() {
+            result = result.double();
+            if bit(scalar, index) {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+/// Parse an uncompressed public key.
+fn parse_public_key(key: &[u8]) -> Option<Point> {
+    if key.len() != PUBLIC_KEY_LEN || key[0] != 0x04 {
+        return None;
+    }
+    Point::from_affine(&from_be_bytes(&key[1..33]), &from_be_bytes(&key[33..]))
+}
+
+/// Whether `a` is a valid scalar, between 1 and n - 1.
+fn is_scalar(a: &U256) -> bool {
+    !is_zero(a) && N.contains(a)
+}
+
+/// Reduce a hash, or the x coordinate of a point, modulo n.
+fn reduce_n(a: &U256) -> U256 {
+    N.reduce(*a, false)
+}
+
+/// Sign `hash` with the private key `d`, with the deterministic nonce of
+/// RFC 6979. Returns `r` and `s`.
+fn sign(d: &U256, hash: &[u8; HASH_LEN]) -> (U256, U256) {
+    let e = reduce_n(&from_be_bytes(hash));
+    let mut private = [0; 32];
+    to_be_bytes(d, &mut private);
+    let mut message = [0; 32];
+    to_be_bytes(&e, &mut message);
+
+    let mut k_mac = [0; 32];
+    let mut v = [0x01; 32];
+    k_mac = HmacSha256::mac(&k_mac, &[&v, &[0x00], &private, &message]);
+    v = HmacSha256::mac(&k_mac, &[&v]);
+    k_mac = HmacSha256::mac(&k_mac, &[&v, &[0x01], &private, &message]);
+    v = HmacSha256::mac(&k_mac, &[&v]);
+
+    let d = N.to_montgomery(d);
+    let e = N.to_montgomery(&e);
+    loop {
+        v = HmacSha256::mac(&k_mac, &[&v]);
+        let k = from_be_bytes(&v);
+        if is_scalar(&k) {
+            if let Some((x, _)) = Point::base().mul(&k).to_affine() {
+                let r = reduce_n(&x);
+                let k_inv = N.invert(&N.to_montgomery(&k));
+                let r_d = N.mul(&N.to_montgomery(&r), &d);
+                let s = N.from_montgomery(&N.mul(&k_inv, &N.add(&e, &r_d)));
+                if !is_zero(&r) && !is_zero(&s) {
+                    return (r, s);
+                }
+            }
+        }
+        k_mac = HmacSha256::mac(&k_mac, &[&v, &[0x00]]);
+        v = HmacSha256::mac(&k_mac, &[&v]);
+    }
+}
+
+/// Check that `r` and `s` are a signature of `hash` by `public_key`.
+fn verify(public_key: &Point, hash: &[u8; HASH_LEN], r: &U256, s: &U256) -> bool {
+    if !is_scalar(r) || !is_scalar(s) {
+        return false;
+    }
+    let e = N.to_montgomery(&reduce_n(&from_be_bytes(hash)));
+    let w = N.invert(&N.to_montgomery(s));
+    let u1 = N.from_montgomery(&N.mul(&e, &w));
+    let u2 = N.from_montgomery(&N.mul(&N.to_montgomery(r), &w));
+    match Point::base().mul(&u1).add(&public_key.mul(&u2)).to_affine() {
+        Some((x, _)) => reduce_n(&x) == *r,
+        None => false,
+    }
+}
+
+/// Check that `signature`, `r || s`, is a signature of `hash` by the
+/// uncompressed `public_key`.
+///
+/// Returns `INVAL` if the public key is malformed or not on the curve.
+pub fn verify_signature(
+    public_key: &[u8],
+    hash: &[u8; HASH_LEN],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<bool, ErrorCode> {
+    let key = parse_public_key(public_key).ok_or(ErrorCode::INVAL)?;
+    let r = from_be_bytes(&signature[..32]);
+    let s = from_be_bytes(&signature[32..]);
+    Ok(verify(&key, hash, &r, &s))
+}
+
+/// Checks ECDSA P-256 credentials of processes against a list of trusted
+/// public keys.
+///
+/// A credential is accepted if it is a signature of the SHA-256 hash of the
+/// integrity regions by one of the keys. Other credentials are passed, as a
+/// signature that does not verify may have been made by a key the board does
+/// not trust rather than be corrupt.
+pub struct P256CredentialsChecker {
+    public_keys: &'static [[u8; PUBLIC_KEY_LEN]],
+    require: bool,
+}
+
+impl P256CredentialsChecker {
+    /// `require` is whether processes without an accepted credential are
+    /// kept from running.
+    pub const fn new(
+        public_keys: &'static [[u8; PUBLIC_KEY_LEN]],
+        require: bool,
+    ) -> P256CredentialsChecker {
+        P256CredentialsChecker {
+            public_keys,
+            require,
+        }
+    }
+}
+
+impl AppCredentialsChecker for P256CredentialsChecker {
+    fn check_credentials(
+        &self,
+        credentials: &TbfHeaderV2Credentials,
+        integrity_regions: &[&[u8]],
+    ) -> CheckResult {
+        if credentials.format() != TbfHeaderV2CredentialsType::EcdsaNistP256 {
+            return CheckResult::Pass;
+        }
+        let signature = match credentials.data().try_into() {
+            Ok(signature) => signature,
+            Err(_) => return CheckResult::Pass,
+        };
+
+        let mut hash = Sha256::new();
+        for region in integrity_regions {
+            hash.update(region);
+        }
+        let hash = hash.finish();
+
+        let signed = self
+            .public_keys
+            .iter()
+            .any(|key| verify_signature(key, &hash, signature) == Ok(true));
+        if signed {
+            CheckResult::Accept
+        } else {
+            CheckResult::Pass
+        }
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Verify,
+    Sign,
+    Agree,
+}
+
+pub struct P256Software<'a> {
+    verify_client: OptionalCell<&'a dyn ClientVerify<HASH_LEN, SIGNATURE_LEN>>,
+    sign_client: OptionalCell<&'a dyn ClientSign<HASH_LEN, SIGNATURE_LEN>>,
+    agreement_client: OptionalCell<&'a dyn ClientKeyAgreement<PUBLIC_KEY_LEN, SECRET_LEN>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+
+    /// Affine coordinates of the public key.
+    public_key: OptionalCell<(U256, U256)>,
+    private_key: OptionalCell<U256>,
+
+    operation: OptionalCell<Operation>,
+    hash: TakeCell<'static, [u8; HASH_LEN]>,
+    signature: TakeCell<'static, [u8; SIGNATURE_LEN]>,
+    peer_key: TakeCell<'static, [u8; PUBLIC_KEY_LEN]>,
+    secret: TakeCell<'static, [u8; SECRET_LEN]>,
+    busy: Cell<bool>,
+}
+
+impl<'a> P256Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> P256Software<'a> {
+        P256Software {
+            verify_client: OptionalCell::empty(),
+            sign_client: OptionalCell::empty(),
+            agreement_client: OptionalCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+            public_key: OptionalCell::empty(),
+            private_key: OptionalCell::empty(),
+            operation: OptionalCell::empty(),
+            hash: TakeCell::empty(),
+            signature: TakeCell::empty(),
+            peer_key: TakeCell::empty(),
+            secret: TakeCell::empty(),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Must be called with the handle returned when registering this engine
+    /// with the deferred caller, otherwise no operation ever completes.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn start(&self, operation: Operation) {
+        self.busy.set(true);
+        self.operation.set(operation);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn verify_done(&self) {
+        let hash = self.hash.take().unwrap();
+        let signature = self.signature.take().unwrap();
+        let result = self.public_key.map_or(Err(ErrorCode::RESERVE), |(x, y)| {
+            // The key was checked when it was set.
+            let key = Point::from_affine(x, y).ok_or(ErrorCode::FAIL)?;
+            let r = from_be_bytes(&signature[..32]);
+            let s = from_be_bytes(&signature[32..]);
+            Ok(verify(&key, hash, &r, &s))
+        });
+        self.busy.set(false);
+        self.verify_client
+            .map(move |client| client.verification_done(result, hash, signature));
+    }
+
+    fn sign_done(&self) {
+        let hash = self.hash.take().unwrap();
+        let signature = self.signature.take().unwrap();
+        let result = self.private_key.map_or(Err(ErrorCode::RESERVE), |d| {
+            let (r, s) = sign(d, hash);
+            to_be_bytes(&r, &mut signature[..32]);
+            to_be_bytes(&s, &mut signature[32..]);
+            Ok(())
+        });
+        self.busy.set(false);
+        self.sign_client
+            .map(move |client| client.signing_done(result, hash, signature));
+    }
+
+    fn agree_done(&self) {
+        let peer_key = self.peer_key.take().unwrap();
+        let secret = self.secret.take().unwrap();
+        let result = self.private_key.map_or(Err(ErrorCode::RESERVE), |d| {
+            let peer = parse_public_key(&peer_key[..]).ok_or(ErrorCode::INVAL)?;
+            let (x, _) = peer.mul(d).to_affine().ok_or(ErrorCode::INVAL)?;
+            to_be_bytes(&x, &mut secret[..]);
+            Ok(())
+        });
+        self.busy.set(false);
+        self.agreement_client
+            .map(move |client| client.agreement_done(result, peer_key, secret));
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for P256Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        match self.operation.take() {
+            Some(Operation::Verify) => self.verify_done(),
+            Some(Operation::Sign) => self.sign_done(),
+            Some(Operation::Agree) => self.agree_done(),
+            None => {}
+        }
+    }
+}
+
+impl PublicKey for P256Software<'_> {
+    fn set_public_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        parse_public_key(key).ok_or(ErrorCode::INVAL)?;
+        self.public_key
+            .set((from_be_bytes(&key[1..33]), from_be_bytes(&key[33..])));
+        Ok(())
+    }
+
+    fn public_key(&self, key: &mut [u8]) -> Result<(), ErrorCode> {
+        if key.len() != PUBLIC_KEY_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        self.public_key.map_or(Err(ErrorCode::RESERVE), |(x, y)| {
+            key[0] = 0x04;
+            to_be_bytes(x, &mut key[1..33]);
+            to_be_bytes(y, &mut key[33..]);
+            Ok(())
+        })
+    }
+}
+
+impl PrivateKey for P256Software<'_> {
+    fn set_private_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if key.len() != PRIVATE_KEY_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+        let d = from_be_bytes(key);
+        if !is_scalar(&d) {
+            return Err(ErrorCode::INVAL);
+        }
+        let public_key = Point::base().mul(&d).to_affine().ok_or(ErrorCode::FAIL)?;
+        self.private_key.set(d);
+        self.public_key.set(public_key);
+        Ok(())
+    }
+
+    fn clear_private_key(&self) {
+        self.private_key.clear();
+    }
+}
+
+impl<'a> SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> for P256Software<'a> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<HASH_LEN, SIGNATURE_LEN>) {
+        self.verify_client.set(client);
+    }
+
+    fn verify(
+        &self,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; HASH_LEN],
+            &'static mut [u8; SIGNATURE_LEN],
+        ),
+    > {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, hash, signature));
+        }
+        if self.public_key.is_none() {
+            return Err((ErrorCode::RESERVE, hash, signature));
+        }
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.start(Operation::Verify);
+        Ok(())
+    }
+}
+
+impl<'a> SignatureSign<'a, HASH_LEN, SIGNATURE_LEN> for P256Software<'a> {
+    fn set_sign_client(&'a self, client: &'a dyn ClientSign<HASH_LEN, SIGNATURE_LEN>) {
+        self.sign_client.set(client);
+    }
+
+    fn sign(
+        &self,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; HASH_LEN],
+            &'static mut [u8; SIGNATURE_LEN],
+        ),
+    > {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, hash, signature));
+        }
+        if self.private_key.is_none() {
+            return Err((ErrorCode::RESERVE, hash, signature));
+        }
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.start(Operation::Sign);
+        Ok(())
+    }
+}
+
+impl<'a> KeyAgreement<'a, PUBLIC_KEY_LEN, SECRET_LEN> for P256Software<'a> {
+    fn set_key_agreement_client(
+        &'a self,
+        client: &'a dyn ClientKeyAgreement<PUBLIC_KEY_LEN, SECRET_LEN>,
+    ) {
+        self.agreement_client.set(client);
+    }
+
+    fn agree(
+        &self,
+        peer_key: &'static mut [u8; PUBLIC_KEY_LEN],
+        secret: &'static mut [u8; SECRET_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; PUBLIC_KEY_LEN],
+            &'static mut [u8; SECRET_LEN],
+        ),
+    > {
+        if self.busy.get() {
+            return Err((ErrorCode::BUSY, peer_key, secret));
+        }
+        if self.private_key.is_none() {
+            return Err((ErrorCode::RESERVE, peer_key, secret));
+        }
+        self.peer_key.replace(peer_key);
+        self.secret.replace(secret);
+        self.start(Operation::Agree);
+        Ok(())
+    }
+}
@@ -5,13 +5,19 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has the following commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'terminate n' terminates the process with name n
+//!  - 'restart n' terminates and restarts the process with name n
+//!  - 'boot n' restarts the terminated process with name n
+//!  - 'process n' prints the memory map and state of the process with name n
+//!  - 'kernel' prints kernel memory, grant, deferred call and timeslice
+//!    statistics
 //!
 //! ### `list` Command Fields:
 //!
@@ -25,10 +31,18 @@
 //!   because the queue was full.
 //! - `Restarts`: How many times this process has crashed and been restarted by
 //!   the kernel.
+//! - `Watchdog`: How many times this process missed the deadline of its app
+//!   watchdog (see `capsules::app_watchdog`).
 //! - `State`: The state the process is in.
 //! - `Grants`: The number of grants that have been initialized for the process
 //!   out of the total number of grants defined by the kernel.
 //!
+//! ### Line editing
+//!
+//! The command line can be edited with the left and right arrow keys, Home
+//! and End (or Ctrl-A and Ctrl-E), Backspace and Delete. The up and down arrow
+//! keys go through the last `COMMAND_HISTORY_LEN` commands.
+//!
 //! Setup
 //! -----
 //!
@@ -42,11 +56,17 @@
 //! pub struct Capability;
 //! unsafe impl capabilities::ProcessManagementCapability for Capability {}
 //!
+//! static mut WRITE_BUF: [u8; console::DEFAULT_WRITE_BUF_LEN] =
+//!     [0; console::DEFAULT_WRITE_BUF_LEN];
+//! static mut QUEUE_BUF: [u8; console::DEFAULT_QUEUE_BUF_LEN] =
+//!     [0; console::DEFAULT_QUEUE_BUF_LEN];
+//!
 //! let pconsole = static_init!(
 //!     ProcessConsole<usart::USART>,
 //!     ProcessConsole::new(&usart::USART0,
 //!                  115200,
-//!                  &mut console::WRITE_BUF,
+//!                  &mut WRITE_BUF,
+//!                  &mut QUEUE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
 //!                  kernel,
@@ -59,10 +79,13 @@
 //!
 //! Buffer use and output
 //! ---------------------
-//! `ProcessConsole` does not use its own write buffer for output:
+//! `ProcessConsole` does not use its own write buffer for most output:
 //! it uses the debug!() buffer, so as not to repeat all of its buffering and
-//! to maintain a correct ordering with debug!() calls. The write buffer of
-//! `ProcessConsole` is used solely for echoing what someone types.
+//! to maintain a correct ordering with debug!() calls. `ProcessConsole` queues
+//! the echoes of what someone types, and the output of the `process` command,
+//! which is too large for the debug!() buffer of most boards, in its queue
+//! buffer and sends the queue one write buffer at a time. The board chooses
+//! the size of both buffers (see `DEFAULT_QUEUE_BUF_LEN`).
 //!
 //! Using ProcessConsole
 //! --------------------
@@ -90,9 +113,9 @@
 //! Initialization complete. Entering main loop
 //! Hello World!
 //! list
-//! PID    Name    Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants
-//! 00     blink        0       113                0         0  Yielded    1/12
-//! 01     c_hello      0         8                0         0  Yielded    3/12
+//! PID    Name    Quanta  Syscalls  Dropped Upcalls  Restarts  Watchdog    State  Grants
+//! 00     blink        0       113                0         0         0  Yielded    1/12
+//! 01     c_hello      0         8                0         0         0  Yielded    3/12
 //! ```
 //!
 //! To get a general view of the system, use the status command:
@@ -110,36 +133,190 @@
 //! stop blink
 //! Process blink stopped
 //! ```
+//!
+//! The `kernel` command prints statistics about the kernel:
+//!
+//! ```text
+//! kernel
+//! Processes: 2 loaded, 2 active, 0 inactive
+//! Kernel memory in processes: 2312 bytes
+//! Grants: 4 allocated, 12 per process
+//! Deferred calls: 0 pending, 3/8 dynamic clients
+//! Timeslice expirations: 0
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{MapCell, TakeCell};
 use kernel::debug;
 use kernel::hil::uart;
 use kernel::introspection::KernelInfo;
+use kernel::procs::{ProcessType, State};
 use kernel::Kernel;
 use kernel::ReturnCode;
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
+/// Length of the command buffer, and of the commands kept in the history.
+pub const COMMAND_BUF_LEN: usize = 32;
+/// Number of commands kept in the history.
+pub const COMMAND_HISTORY_LEN: usize = 10;
+
+/// Default length of the write buffer, which sends the queue one chunk at a
+/// time.
+pub const DEFAULT_WRITE_BUF_LEN: usize = 32;
+/// Default length of the queue. Echoes of what is typed are queued while a
+/// write is in progress, and the output of the `process` command is rendered
+/// into the queue, which needs about 2kB. Output that does not fit is cut
+/// short and ends with `TRUNCATED_MARKER`.
+pub const DEFAULT_QUEUE_BUF_LEN: usize = 2048;
+
+/// Ends the output of the `process` command if it did not fit in the queue.
+const TRUNCATED_MARKER: &[u8] = b"\r\n[output truncated]\r\n";
+
 // Since reads are byte-by-byte, to properly echo what's typed,
 // we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-// Commands can be up to 32 bytes long: since commands themselves are 4-5
-// characters, limiting arguments to 25 bytes or so seems fine for now.
-pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Commands can be up to 32 bytes long: since commands themselves are 4-9
+// characters, limiting arguments to 20 bytes or so seems fine for now.
+pub static mut COMMAND_BUF: [u8; COMMAND_BUF_LEN] = [0; COMMAND_BUF_LEN];
+
+const VALID_COMMANDS: &str =
+    "help status list stop start fault terminate restart boot process kernel";
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const ESCAPE: u8 = 0x1b;
+const CTRL_A: u8 = 0x01;
+const CTRL_E: u8 = 0x05;
+
+/// Progress through a terminal escape sequence, such as `ESC [ A` for the
+/// up arrow key or `ESC [ 3 ~` for the Delete key.
+#[derive(Copy, Clone, PartialEq)]
+enum EscapeState {
+    None,
+    Escape,
+    Bracket,
+    Digit(u8),
+}
+
+/// The last commands that were executed, most recent first.
+struct CommandHistory {
+    commands: [[u8; COMMAND_BUF_LEN]; COMMAND_HISTORY_LEN],
+    lens: [usize; COMMAND_HISTORY_LEN],
+    // Number of commands in the history
+    count: usize,
+    // Index of the most recent command
+    head: usize,
+    // The command shown on the command line while going through the history,
+    // 0 being the most recent one. None when editing a new command.
+    position: Option<usize>,
+}
+
+impl CommandHistory {
+    fn new() -> CommandHistory {
+        CommandHistory {
+            commands: [[0; COMMAND_BUF_LEN]; COMMAND_HISTORY_LEN],
+            lens: [0; COMMAND_HISTORY_LEN],
+            count: 0,
+            head: 0,
+            position: None,
+        }
+    }
+
+    fn get(&self, position: usize) -> &[u8] {
+        let index = (self.head + COMMAND_HISTORY_LEN - position) % COMMAND_HISTORY_LEN;
+        &self.commands[index][..self.lens[index]]
+    }
+
+    // Adds `command` to the history, unless it is the same as the most
+    // recent command.
+    fn push(&mut self, command: &[u8]) {
+        self.position = None;
+        if command.is_empty() || (self.count > 0 && self.get(0) == command) {
+            return;
+        }
+        let len = cmp::min(command.len(), COMMAND_BUF_LEN);
+        self.head = (self.head + 1) % COMMAND_HISTORY_LEN;
+        self.commands[self.head][..len].copy_from_slice(&command[..len]);
+        self.lens[self.head] = len;
+        self.count = cmp::min(self.count + 1, COMMAND_HISTORY_LEN);
+    }
+
+    // Moves to the previous (older) command. Returns None at the oldest
+    // command.
+    fn previous(&mut self) -> Option<&[u8]> {
+        let position = self.position.map_or(0, |position| position + 1);
+        if position >= self.count {
+            return None;
+        }
+        self.position = Some(position);
+        Some(self.get(position))
+    }
+
+    // Moves to the next (newer) command. Returns an empty command after the
+    // most recent one, and None if not going through the history.
+    fn next(&mut self) -> Option<&[u8]> {
+        match self.position {
+            None => None,
+            Some(0) => {
+                self.position = None;
+                Some(&[])
+            }
+            Some(position) => {
+                self.position = Some(position - 1);
+                Some(self.get(position - 1))
+            }
+        }
+    }
+}
+
+/// `fmt::Write` implementation that appends the text written to it to
+/// `buffer` after its first `len` bytes. Text that does not fit is dropped.
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+    truncated: bool,
+}
+
+impl BufferWriter<'_> {
+    /// Returns the length of the text in the buffer, after replacing its end
+    /// with `TRUNCATED_MARKER` if any text was dropped.
+    fn finish(self) -> usize {
+        if self.truncated {
+            let start = self.buffer.len().saturating_sub(TRUNCATED_MARKER.len());
+            let marker = &TRUNCATED_MARKER[TRUNCATED_MARKER.len() - (self.len - start)..];
+            self.buffer[start..self.len].copy_from_slice(marker);
+        }
+        self.len
+    }
+}
+
+impl fmt::Write for BufferWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = cmp::min(s.len(), self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        self.truncated |= len < s.len();
+        Ok(())
+    }
+}
 
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
     tx_in_progress: Cell<bool>,
     tx_buffer: TakeCell<'static, [u8]>,
+    queue_buffer: TakeCell<'static, [u8]>,
+    queue_len: Cell<usize>,
     rx_in_progress: Cell<bool>,
     rx_buffer: TakeCell<'static, [u8]>,
     command_buffer: TakeCell<'static, [u8]>,
     command_index: Cell<usize>,
+    /// Position of the cursor in the command being edited.
+    cursor: Cell<usize>,
+    escape_state: Cell<EscapeState>,
+    history: MapCell<CommandHistory>,
 
     /// Flag to mark that the process console is active and has called receive
     /// from the underlying UART.
@@ -156,6 +333,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
     pub fn new(
         uart: &'a dyn uart::UartData<'a>,
         tx_buffer: &'static mut [u8],
+        queue_buffer: &'static mut [u8],
         rx_buffer: &'static mut [u8],
         cmd_buffer: &'static mut [u8],
         kernel: &'static Kernel,
@@ -165,10 +343,15 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             uart: uart,
             tx_in_progress: Cell::new(false),
             tx_buffer: TakeCell::new(tx_buffer),
+            queue_buffer: TakeCell::new(queue_buffer),
+            queue_len: Cell::new(0),
             rx_in_progress: Cell::new(false),
             rx_buffer: TakeCell::new(rx_buffer),
             command_buffer: TakeCell::new(cmd_buffer),
             command_index: Cell::new(0),
+            cursor: Cell::new(0),
+            escape_state: Cell::new(EscapeState::None),
+            history: MapCell::new(CommandHistory::new()),
             running: Cell::new(false),
             execute: Cell::new(false),
             kernel: kernel,
@@ -188,6 +371,26 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         ReturnCode::SUCCESS
     }
 
+    // Calls `fun` on the process named `name`. Returns whether there is such
+    // a process.
+    fn with_process<F>(&self, name: &str, fun: F) -> bool
+    where
+        F: Fn(&dyn ProcessType),
+    {
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                if proc.get_process_name() == name {
+                    found.set(true);
+                    fun(proc);
+                }
+            });
+        if !found.get() {
+            debug!("No process named {}", name);
+        }
+        found.get()
+    }
+
     // Process the command in the command buffer and clear the buffer.
     fn read_command(&self) {
         self.command_buffer.map(|command| {
@@ -208,90 +411,74 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
                 match cmd_str {
                     Ok(s) => {
                         let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
-                            debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start fault");
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            debug!("Process {} resumed.", name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            debug!("Process {} stopped", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            debug!("Process {} now faulted", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Upcalls  Restarts    State  Grants");
-                            self.kernel
-                                .process_each_capability(&self.capability, |proc| {
-                                    let info: KernelInfo = KernelInfo::new(self.kernel);
-
-                                    let pname = proc.get_process_name();
-                                    let appid = proc.appid();
-                                    let (grants_used, grants_total) = info.number_app_grant_uses(appid, &self.capability);
-
-                                    debug!(
-                                        "  {:?}\t{:<20}{:6}{:10}{:17}{:10}  {:?}{:5}/{}",
-                                        appid,
-                                        pname,
-                                        proc.debug_timeslice_expiration_count(),
-                                        proc.debug_syscall_count(),
-                                        proc.debug_dropped_upcall_count(),
-                                        proc.get_restart_count(),
-                                        proc.get_state(),
-                                        grants_used,
-                                        grants_total
-                                    );
+                        let mut words = clean_str.split_whitespace();
+                        let name = words.next().unwrap_or("");
+                        let argument = words.next();
+                        match (name, argument) {
+                            ("help", _) => {
+                                debug!("Welcome to the process console.");
+                                debug!("Valid commands are: {}", VALID_COMMANDS);
+                            }
+                            ("start", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    proc.resume();
+                                    debug!("Process {} resumed.", name);
+                                });
+                            }
+                            ("stop", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    proc.stop();
+                                    debug!("Process {} stopped", name);
+                                });
+                            }
+                            ("fault", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    proc.set_fault_state();
+                                    debug!("Process {} now faulted", name);
+                                });
+                            }
+                            ("terminate", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    proc.terminate(0);
+                                    debug!("Process {} terminated", name);
                                 });
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            debug!(
-                                "Total processes: {}",
-                                info.number_loaded_processes(&self.capability)
-                            );
-                            debug!(
-                                "Active processes: {}",
-                                info.number_active_processes(&self.capability)
-                            );
-                            debug!(
-                                "Timeslice expirations: {}",
-                                info.timeslice_expirations(&self.capability)
-                            );
-                        } else {
-                            debug!("Valid commands are: help status list stop start fault");
+                            }
+                            ("restart", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    proc.try_restart(0);
+                                    debug!("Process {} restarted", name);
+                                });
+                            }
+                            ("boot", Some(name)) => {
+                                self.with_process(name, |proc| {
+                                    if proc.get_state() == State::Terminated {
+                                        proc.try_restart(0);
+                                        debug!("Process {} booted", name);
+                                    } else {
+                                        debug!("Process {} is not terminated", name);
+                                    }
+                                });
+                            }
+                            ("process", Some(name)) => {
+                                // The output is rendered once into the
+                                // queue, which sends it one write buffer at
+                                // a time.
+                                self.with_process(name, |proc| {
+                                    self.queue_buffer.map(|queue| {
+                                        let mut writer = BufferWriter {
+                                            buffer: queue,
+                                            len: self.queue_len.get(),
+                                            truncated: false,
+                                        };
+                                        proc.print_full_process(&mut writer);
+                                        self.queue_len.set(writer.finish());
+                                    });
+                                });
+                            }
+                            ("list", _) => self.print_list(),
+                            ("status", _) => self.print_status(),
+                            ("kernel", _) => self.print_kernel(),
+                            _ => debug!("Valid commands are: {}", VALID_COMMANDS),
                         }
                     }
                     Err(_e) => debug!("Invalid command: {:?}", command),
@@ -302,34 +489,305 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             command[0] = 0;
         });
         self.command_index.set(0);
+        self.cursor.set(0);
     }
 
-    fn write_byte(&self, byte: u8) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
-        } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                buffer[0] = byte;
-                self.uart.transmit_buffer(buffer, 1);
+    fn print_list(&self) {
+        debug!(" PID    Name                Quanta  Syscalls  Dropped Upcalls  Restarts  Watchdog    State  Grants");
+        self.kernel
+            .process_each_capability(&self.capability, |proc| {
+                let info: KernelInfo = KernelInfo::new(self.kernel);
+
+                let pname = proc.get_process_name();
+                let appid = proc.appid();
+                let (grants_used, grants_total) =
+                    info.number_app_grant_uses(appid, &self.capability);
+
+                debug!(
+                    "  {:?}\t{:<20}{:6}{:10}{:17}{:10}{:10}  {:?}{:5}/{}",
+                    appid,
+                    pname,
+                    proc.debug_timeslice_expiration_count(),
+                    proc.debug_syscall_count(),
+                    proc.debug_dropped_upcall_count(),
+                    proc.get_restart_count(),
+                    proc.debug_watchdog_expiration_count(),
+                    proc.get_state(),
+                    grants_used,
+                    grants_total
+                );
             });
-            ReturnCode::SUCCESS
+    }
+
+    fn print_status(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        debug!(
+            "Total processes: {}",
+            info.number_loaded_processes(&self.capability)
+        );
+        debug!(
+            "Active processes: {}",
+            info.number_active_processes(&self.capability)
+        );
+        debug!(
+            "Timeslice expirations: {}",
+            info.timeslice_expirations(&self.capability)
+        );
+    }
+
+    fn print_kernel(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        debug!(
+            "Processes: {} loaded, {} active, {} inactive",
+            info.number_loaded_processes(&self.capability),
+            info.number_active_processes(&self.capability),
+            info.number_inactive_processes(&self.capability)
+        );
+        debug!(
+            "Kernel memory in processes: {} bytes",
+            info.kernel_memory_in_processes(&self.capability)
+        );
+        let (grants_used, grants_total) = info.number_grant_uses(&self.capability);
+        debug!(
+            "Grants: {} allocated, {} per process",
+            grants_used, grants_total
+        );
+        let (clients_registered, clients_total) =
+            info.number_dynamic_deferred_call_clients(&self.capability);
+        debug!(
+            "Deferred calls: {} pending, {}/{} dynamic clients",
+            info.number_pending_deferred_calls(&self.capability),
+            clients_registered,
+            clients_total
+        );
+        debug!(
+            "Timeslice expirations: {}",
+            info.timeslice_expirations(&self.capability)
+        );
+        debug!(
+            "Denied syscalls: {}",
+            info.denied_syscalls(&self.capability)
+        );
+        debug!(
+            "Deadline misses: {}",
+            info.deadline_misses(&self.capability)
+        );
+        debug!(
+            "Watchdog expirations: {}",
+            info.watchdog_expirations(&self.capability)
+        );
+    }
+
+    // Queues `bytes` to be written after any queued bytes. Bytes that do not
+    // fit in the queue are dropped.
+    fn queue_bytes(&self, bytes: &[u8]) {
+        self.queue_buffer.map(|queue| {
+            let queue_len = self.queue_len.get();
+            let len = cmp::min(bytes.len(), queue.len() - queue_len);
+            queue[queue_len..queue_len + len].copy_from_slice(&bytes[..len]);
+            self.queue_len.set(queue_len + len);
+        });
+    }
+
+    fn queue_byte_repeated(&self, byte: u8, count: usize) {
+        for _ in 0..count {
+            self.queue_bytes(&[byte]);
         }
     }
 
-    fn write_bytes(&self, bytes: &[u8]) -> ReturnCode {
+    // Writes the next queued bytes, if no write is in progress.
+    fn send_queued(&self) {
         if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
-        } else {
-            self.tx_in_progress.set(true);
+            return;
+        }
+        let queue_len = self.queue_len.get();
+        if queue_len == 0 {
+            return;
+        }
+        self.queue_buffer.map(|queue| {
             self.tx_buffer.take().map(|buffer| {
-                let len = cmp::min(bytes.len(), buffer.len());
-                // Copy elements of `bytes` into `buffer`
-                (&mut buffer[..len]).copy_from_slice(&bytes[..len]);
+                let len = cmp::min(queue_len, buffer.len());
+                buffer[..len].copy_from_slice(&queue[..len]);
+                queue.copy_within(len..queue_len, 0);
+                self.queue_len.set(queue_len - len);
+                self.tx_in_progress.set(true);
                 self.uart.transmit_buffer(buffer, len);
             });
-            ReturnCode::SUCCESS
+        });
+    }
+
+    // Handles a byte typed at the console, editing the command line and
+    // queueing its echo.
+    fn handle_byte(&self, byte: u8) {
+        match self.escape_state.get() {
+            EscapeState::None => {}
+            EscapeState::Escape => {
+                self.escape_state.set(if byte == b'[' || byte == b'O' {
+                    EscapeState::Bracket
+                } else {
+                    EscapeState::None
+                });
+                return;
+            }
+            EscapeState::Bracket => {
+                self.escape_state.set(EscapeState::None);
+                match byte {
+                    b'A' => self.history_previous(),
+                    b'B' => self.history_next(),
+                    b'C' => self.cursor_right(),
+                    b'D' => self.cursor_left(),
+                    b'H' => self.cursor_home(),
+                    b'F' => self.cursor_end(),
+                    b'0'..=b'9' => self.escape_state.set(EscapeState::Digit(byte)),
+                    _ => {}
+                }
+                return;
+            }
+            EscapeState::Digit(digit) => {
+                self.escape_state.set(EscapeState::None);
+                if byte == b'~' {
+                    match digit {
+                        b'1' | b'7' => self.cursor_home(),
+                        b'3' => self.delete(),
+                        b'4' | b'8' => self.cursor_end(),
+                        _ => {}
+                    }
+                }
+                return;
+            }
         }
+
+        match byte {
+            b'\n' | b'\r' => {
+                self.command_buffer.map(|command| {
+                    self.history
+                        .map(|history| history.push(&command[..self.command_index.get()]));
+                });
+                self.execute.set(true);
+                self.queue_bytes(b"\r\n");
+            }
+            BACKSPACE | DELETE => self.backspace(),
+            ESCAPE => self.escape_state.set(EscapeState::Escape),
+            CTRL_A => self.cursor_home(),
+            CTRL_E => self.cursor_end(),
+            // For some reason, sometimes reads return > 127 but no error,
+            // which causes utf-8 decoding failure, so only accept printable
+            // ASCII characters. -pal
+            0x20..=0x7e => self.insert(byte),
+            _ => {}
+        }
+    }
+
+    // Inserts `byte` at the cursor, and redraws the rest of the line.
+    fn insert(&self, byte: u8) {
+        self.command_buffer.map(|command| {
+            let len = self.command_index.get();
+            let cursor = self.cursor.get();
+            if len >= command.len() - 1 {
+                return;
+            }
+            command.copy_within(cursor..len, cursor + 1);
+            command[cursor] = byte;
+            command[len + 1] = 0;
+            self.queue_bytes(&command[cursor..len + 1]);
+            self.queue_byte_repeated(BACKSPACE, len - cursor);
+            self.command_index.set(len + 1);
+            self.cursor.set(cursor + 1);
+        });
+    }
+
+    // Removes the byte before the cursor. Note that erasing the last
+    // character of the line is echoed as '\b \b'.
+    fn backspace(&self) {
+        if self.cursor.get() == 0 {
+            return;
+        }
+        self.queue_bytes(&[BACKSPACE]);
+        self.cursor.set(self.cursor.get() - 1);
+        self.delete();
+    }
+
+    // Removes the byte at the cursor, and redraws the rest of the line.
+    fn delete(&self) {
+        self.command_buffer.map(|command| {
+            let len = self.command_index.get();
+            let cursor = self.cursor.get();
+            if cursor >= len {
+                return;
+            }
+            command.copy_within(cursor + 1..len, cursor);
+            command[len - 1] = 0;
+            self.queue_bytes(&command[cursor..len - 1]);
+            self.queue_bytes(b" ");
+            self.queue_byte_repeated(BACKSPACE, len - cursor);
+            self.command_index.set(len - 1);
+        });
+    }
+
+    fn cursor_left(&self) {
+        let cursor = self.cursor.get();
+        if cursor > 0 {
+            self.queue_bytes(&[BACKSPACE]);
+            self.cursor.set(cursor - 1);
+        }
+    }
+
+    fn cursor_right(&self) {
+        self.command_buffer.map(|command| {
+            let cursor = self.cursor.get();
+            if cursor < self.command_index.get() {
+                self.queue_bytes(&command[cursor..cursor + 1]);
+                self.cursor.set(cursor + 1);
+            }
+        });
+    }
+
+    fn cursor_home(&self) {
+        self.queue_byte_repeated(BACKSPACE, self.cursor.get());
+        self.cursor.set(0);
+    }
+
+    fn cursor_end(&self) {
+        self.command_buffer.map(|command| {
+            let len = self.command_index.get();
+            self.queue_bytes(&command[self.cursor.get()..len]);
+            self.cursor.set(len);
+        });
+    }
+
+    fn history_previous(&self) {
+        self.history.map(|history| {
+            if let Some(previous) = history.previous() {
+                self.replace_line(previous);
+            }
+        });
+    }
+
+    fn history_next(&self) {
+        self.history.map(|history| {
+            if let Some(next) = history.next() {
+                self.replace_line(next);
+            }
+        });
+    }
+
+    // Replaces the command being edited with `line`, and redraws it.
+    fn replace_line(&self, line: &[u8]) {
+        self.command_buffer.map(|command| {
+            let old_len = self.command_index.get();
+            let len = cmp::min(line.len(), command.len() - 1);
+            command[..len].copy_from_slice(&line[..len]);
+            command[len] = 0;
+
+            self.queue_byte_repeated(BACKSPACE, self.cursor.get());
+            self.queue_bytes(&command[..len]);
+            // Erase the end of the old command
+            let erase = old_len.saturating_sub(len);
+            self.queue_byte_repeated(b' ', erase);
+            self.queue_byte_repeated(BACKSPACE, erase);
+            self.command_index.set(len);
+            self.cursor.set(len);
+        });
     }
 }
 
@@ -340,10 +798,11 @@ impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole
 
         // Check if we just received and echoed a newline character, and
         // therefore need to process the received message.
-        if self.execute.get() {
+        if self.execute.get() && self.queue_len.get() == 0 {
             self.execute.set(false);
             self.read_command();
         }
+        self.send_queued();
     }
 }
 impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<'a, C> {
@@ -358,28 +817,12 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
                 1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            self.execute.set(true);
-                            self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
-                        }
-                    });
+                    // Ignore what is typed until the last command has been
+                    // executed.
+                    if !self.execute.get() {
+                        self.handle_byte(read_buf[0]);
+                        self.send_queued();
+                    }
                 }
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
@@ -0,0 +1,198 @@
+//! Provides userspace access to PWM outputs on a board.
+//!
+//! The board gives this capsule an array of PWM pins, usually
+//! `capsules::virtual_pwm::PwmPinUser`s on a `MuxPwm`, and processes refer to
+//! them by their index in the array. A pin belongs to the process that
+//! started it until that process stops it. Other processes cannot start or
+//! stop the pin in the meantime. The kernel does not tell capsules when a
+//! process exits, so the pins of exited processes are stopped and released
+//! the next time any process uses this driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let pwm_pin = static_init!(
+//!     capsules::virtual_pwm::PwmPinUser<'static, nrf52840::pwm::Pwm>,
+//!     capsules::virtual_pwm::PwmPinUser::new(mux_pwm, nrf52840::pinmux::Pinmux::new(13))
+//! );
+//! pwm_pin.add_to_mux();
+//! let pwm_pins = static_init!(
+//!     [&'static dyn kernel::hil::pwm::PwmPin; 1],
+//!     [pwm_pin]
+//! );
+//! let pwm = static_init!(
+//!     capsules::pwm::Pwm<'static, 1>,
+//!     capsules::pwm::Pwm::new(pwm_pins, board_kernel.create_grant(&grant_cap))
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! All PWM operations are synchronous, so this capsule only uses the
+//! `command` syscall.
+//!
+//! #### `command_num`
+//!
+//! - `0`: Driver check.
+//! - `1`: Start a PWM output, or change the frequency and duty cycle of an
+//!        output the process already started.
+//!   - `data1`: The index of the pin in the low 16 bits, and the duty cycle in
+//!     hundredths of a percent (0 to 10000) in the high 16 bits.
+//!   - `data2`: The frequency in Hz.
+//!   - Return: `SUCCESS`, `EINVAL` if an argument is out of range, or
+//!     `ERESERVE` if another process owns the pin.
+//! - `2`: Stop a PWM output.
+//!   - `data1`: The index of the pin.
+//!   - Return: `SUCCESS`, `EINVAL` if the index is out of range, or
+//!     `ERESERVE` if another process owns the pin.
+//! - `3`: Get the maximum frequency of a pin in Hz.
+//!   - `data1`: The index of the pin.
+//! - `4`: Get the duty cycle resolution of a pin, the number of steps between
+//!        0 and 100%.
+//!   - `data1`: The index of the pin.
+//! - `5`: Get the number of PWM pins.
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+use kernel::{AppId, CommandReturn, Driver, ErrorCode, Grant, ReturnCode};
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Pwm as usize;
+
+/// The duty cycle passed by processes for 100%.
+pub const MAX_DUTY_CYCLE: usize = 10000;
+
+pub struct Pwm<'a, const NUM_PINS: usize> {
+    pwm_pins: &'a [&'a dyn hil::pwm::PwmPin; NUM_PINS],
+    apps: Grant<App>,
+    /// The process that owns each pin.
+    owners: [OptionalCell<AppId>; NUM_PINS],
+}
+
+impl<'a, const NUM_PINS: usize> Pwm<'a, NUM_PINS> {
+    pub fn new(
+        pwm_pins: &'a [&'a dyn hil::pwm::PwmPin; NUM_PINS],
+        grant: Grant<App>,
+    ) -> Pwm<'a, NUM_PINS> {
+        const EMPTY: OptionalCell<AppId> = OptionalCell::empty();
+        Pwm {
+            pwm_pins,
+            apps: grant,
+            owners: [EMPTY; NUM_PINS],
+        }
+    }
+
+    /// Stops and releases the pins whose owner has exited, so that their
+    /// output does not outlive the process.
+    fn release_exited_owners(&self) {
+        for (owner, pwm_pin) in self.owners.iter().zip(self.pwm_pins.iter()) {
+            let exited = owner.map_or(false, |owner| self.apps.enter(*owner, |_, _| ()).is_err());
+            if exited {
+                owner.clear();
+                pwm_pin.stop();
+            }
+        }
+    }
+
+    /// Makes `appid` the owner of `pin`, unless another process owns it.
+    /// Returns whether `appid` did not own the pin before.
+    fn claim_pin(&self, appid: AppId, pin: usize) -> Result<bool, ErrorCode> {
+        let owner = &self.owners[pin];
+        match owner.map(|owner| *owner == appid) {
+            Some(true) => Ok(false),
+            Some(false) => Err(ErrorCode::RESERVE),
+            None => {
+                owner.set(appid);
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl<'a, const NUM_PINS: usize> Driver for Pwm<'a, NUM_PINS> {
+    /// Control the PWM pins.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start pin `data1 & 0xFFFF` at frequency `data2` in Hz with
+    ///        duty cycle `data1 >> 16` in hundredths of a percent.
+    /// - `2`: Stop pin `data1`.
+    /// - `3`: Get the maximum frequency of pin `data1` in Hz.
+    /// - `4`: Get the duty cycle resolution of pin `data1`.
+    /// - `5`: Get the number of pins.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        self.release_exited_owners();
+
+        let pin = match command_num {
+            1 => data1 & 0xFFFF,
+            _ => data1,
+        };
+        let pwm_pin = match command_num {
+            1..=4 => match self.pwm_pins.get(pin) {
+                Some(pwm_pin) => *pwm_pin,
+                None => return CommandReturn::failure(ErrorCode::INVAL),
+            },
+            0 => return CommandReturn::success(),
+            5 => return CommandReturn::success_u32(NUM_PINS as u32),
+            _ => return CommandReturn::failure(ErrorCode::NOSUPPORT),
+        };
+
+        match command_num {
+            // start
+            1 => {
+                let duty_cycle = data1 >> 16;
+                let frequency_hz = data2;
+                if duty_cycle > MAX_DUTY_CYCLE
+                    || frequency_hz == 0
+                    || frequency_hz > pwm_pin.get_maximum_frequency_hz()
+                {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let claimed = match self.claim_pin(appid, pin) {
+                    Ok(claimed) => claimed,
+                    Err(e) => return CommandReturn::failure(e),
+                };
+                // Scale to the resolution of the chip, avoiding overflow on
+                // 32-bit platforms
+                let duty_cycle = (pwm_pin.get_maximum_duty_cycle() as u64 * duty_cycle as u64
+                    / MAX_DUTY_CYCLE as u64) as usize;
+                let result = pwm_pin.start(frequency_hz, duty_cycle);
+                // A pin that did not start stays free for other processes
+                if claimed && result != ReturnCode::SUCCESS {
+                    self.owners[pin].clear();
+                }
+                CommandReturn::from(result)
+            }
+
+            // stop
+            2 => {
+                if let Err(e) = self.claim_pin(appid, pin) {
+                    return CommandReturn::failure(e);
+                }
+                self.owners[pin].clear();
+                CommandReturn::from(pwm_pin.stop())
+            }
+
+            // maximum frequency
+            3 => CommandReturn::success_u32(pwm_pin.get_maximum_frequency_hz() as u32),
+
+            // duty cycle resolution
+            _ => CommandReturn::success_u32(pwm_pin.get_maximum_duty_cycle() as u32),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {}
@@ -0,0 +1,412 @@
+//! Software implementation of SHA-256 and HMAC-SHA256.
+//!
+//! This implements the `Digest` and `HMACSha256` HILs without any hardware
+//! support, so that `capsules::hmac` and `virtual_digest` can be used on
+//! chips without a hash accelerator. Data is compressed in deferred calls, a
+//! few blocks at a time, so that hashing long messages does not stall the
+//! kernel.
+//!
+//! Unless `set_mode_hmacsha256()` is called, the digest computed is a plain
+//! SHA-256. After `run()` completes, the engine starts a new message with the
+//! same mode and key. `clear_data()` forgets the key and returns to SHA-256.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//! # use capsules::sha256::Sha256Software;
+//!
+//! let sha256 = static_init!(
+//!     Sha256Software<'static>,
+//!     Sha256Software::new(dynamic_deferred_caller)
+//! );
+//! sha256.initialize_callback_handle(
+//!     dynamic_deferred_caller
+//!         .register(sha256)
+//!         .expect("no deferred call slot available for sha256"),
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::ErrorCode;
+
+/// Size of a SHA-256 block, in bytes.
+const BLOCK_SIZE: usize = 64;
+
+/// Number of blocks compressed in a single deferred call.
+const BLOCKS_PER_CALL: usize = 4;
+
+const INITIAL_HASH: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// HMAC inner and outer padding bytes.
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// The SHA-256 state of a message being hashed.
+///
+/// This hashes synchronously, for capsules that need a hash as part of a
+/// larger computation. Use `Sha256Software` to hash data through the
+/// `Digest` HIL.
+#[derive(Clone, Copy)]
+pub struct Sha256 {
+    /// Intermediate hash of the message.
+    hash: [u32; 8],
+    /// Bytes of the message not yet compressed.
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    /// Number of bytes of the message so far.
+    message_len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            hash: INITIAL_HASH,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            message_len: 0,
+        }
+    }
+
+    /// Add bytes to the message, compressing every block completed.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.message_len += bytes.len() as u64;
+
+        while !bytes.is_empty() {
+            let len = cmp::min(BLOCK_SIZE - self.block_len, bytes.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&bytes[..len]);
+            self.block_len += len;
+            bytes = &bytes[len..];
+
+            if self.block_len == BLOCK_SIZE {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pad the message and return its hash.
+    pub fn finish(mut self) -> [u8; 32] {
+        let message_bits = self.message_len.wrapping_mul(8);
+        let mut block = self.block;
+        let block_len = self.block_len;
+
+        block[block_len] = 0x80;
+        for byte in block[block_len + 1..].iter_mut() {
+            *byte = 0;
+        }
+        // The length does not fit after the padding byte, so it goes in an
+        // extra block.
+        if block_len + 1 > BLOCK_SIZE - 8 {
+            self.compress(&block);
+            block = [0; BLOCK_SIZE];
+        }
+        block[BLOCK_SIZE - 8..].copy_from_slice(&message_bits.to_be_bytes());
+        self.compress(&block);
+
+        let mut output = [0; 32];
+        for (bytes, word) in output.chunks_mut(4).zip(self.hash.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        output
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_SIZE]) {
+        let mut w = [0u32; 64];
+        for (i, bytes) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.hash;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in self.hash.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+}
+
+/// The state of an HMAC-SHA256 being computed.
+///
+/// Like `Sha256`, this computes the MAC synchronously, for capsules that
+/// need one as part of a larger computation. `Sha256Software` computes the
+/// same MAC through the `HMACSha256` HIL.
+#[derive(Clone, Copy)]
+pub struct HmacSha256 {
+    /// The hash of the message, after the inner padding of the key.
+    inner: Sha256,
+    /// The hash of the outer padding of the key.
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    /// Start a MAC with `key`. Keys longer than a block are hashed first,
+    /// as in RFC 2104.
+    pub fn new(key: &[u8]) -> HmacSha256 {
+        let mut padded = [0; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            let mut hash = Sha256::new();
+            hash.update(key);
+            padded[..32].copy_from_slice(&hash.finish());
+        } else {
+            padded[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha256::new();
+        inner.update(&xor_pad(&padded, IPAD));
+        let mut outer = Sha256::new();
+        outer.update(&xor_pad(&padded, OPAD));
+        HmacSha256 { inner, outer }
+    }
+
+    /// Compute the MAC with `key` of the message made of `parts`, in order.
+    pub fn mac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+        let mut hmac = HmacSha256::new(key);
+        for part in parts {
+            hmac.update(part);
+        }
+        hmac.finish()
+    }
+
+    /// Add bytes to the message.
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.inner.update(bytes);
+    }
+
+    /// Return the MAC of the message.
+    pub fn finish(self) -> [u8; 32] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finish());
+        outer.finish()
+    }
+}
+
+/// The HMAC key padded to a block, XORed with the inner or outer padding.
+fn xor_pad(key: &[u8; BLOCK_SIZE], pad: u8) -> [u8; BLOCK_SIZE] {
+    let mut padded = *key;
+    for byte in padded.iter_mut() {
+        *byte ^= pad;
+    }
+    padded
+}
+
+/// A message hashed by `Sha256Software`.
+#[derive(Clone, Copy)]
+enum Message {
+    Sha256(Sha256),
+    Hmac(HmacSha256),
+}
+
+pub struct Sha256Software<'a> {
+    client: OptionalCell<&'a dyn digest::Client<'a, [u8; 32]>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+
+    /// The message being hashed.
+    state: Cell<Message>,
+    /// The MAC of an empty message with the HMAC key, which each message
+    /// starts from. Empty when computing a plain SHA-256.
+    key: OptionalCell<HmacSha256>,
+
+    data: Cell<Option<LeasableBuffer<'static, u8>>>,
+    data_index: Cell<usize>,
+    digest: TakeCell<'static, [u8; 32]>,
+}
+
+impl<'a> Sha256Software<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> Sha256Software<'a> {
+        Sha256Software {
+            client: OptionalCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+            state: Cell::new(Message::Sha256(Sha256::new())),
+            key: OptionalCell::empty(),
+            data: Cell::new(None),
+            data_index: Cell::new(0),
+            digest: TakeCell::empty(),
+        }
+    }
+
+    /// Must be called with the handle returned when registering this engine
+    /// with the deferred caller, otherwise no operation ever completes.
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn data_pending(&self) -> bool {
+        let data = self.data.take();
+        let pending = data.is_some();
+        self.data.set(data);
+        pending
+    }
+
+    fn is_busy(&self) -> bool {
+        self.data_pending() || self.digest.is_some()
+    }
+
+    fn schedule(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Start a new message, with the key in HMAC mode.
+    fn reset(&self) {
+        self.state.set(
+            self.key
+                .map_or(Message::Sha256(Sha256::new()), |key| Message::Hmac(*key)),
+        );
+    }
+
+    fn update(&self, bytes: &[u8]) {
+        let mut state = self.state.get();
+        match state {
+            Message::Sha256(ref mut sha256) => sha256.update(bytes),
+            Message::Hmac(ref mut hmac) => hmac.update(bytes),
+        }
+        self.state.set(state);
+    }
+
+    /// Compress the next chunk of the pending data. Returns the data once all
+    /// of it has been added to the message.
+    fn data_progress(&self) -> Option<&'static mut [u8]> {
+        let data = self.data.take()?;
+        let index = self.data_index.get();
+        let end = cmp::min(data.len(), index + BLOCKS_PER_CALL * BLOCK_SIZE);
+
+        self.update(&data[index..end]);
+
+        if end == data.len() {
+            Some(data.take())
+        } else {
+            self.data_index.set(end);
+            self.data.set(Some(data));
+            None
+        }
+    }
+
+    /// Compute the digest of the message, and start a new one.
+    fn hash_progress(&self, digest: &mut [u8; 32]) {
+        *digest = match self.state.get() {
+            Message::Sha256(sha256) => sha256.finish(),
+            Message::Hmac(hmac) => hmac.finish(),
+        };
+        self.reset();
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for Sha256Software<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.data_pending() {
+            match self.data_progress() {
+                Some(data) => {
+                    self.client
+                        .map(move |client| client.add_data_done(Ok(()), data));
+                }
+                None => self.schedule(),
+            }
+        } else if let Some(digest) = self.digest.take() {
+            self.hash_progress(digest);
+            self.client
+                .map(move |client| client.hash_done(Ok(()), digest));
+        }
+    }
+}
+
+impl<'a> digest::Digest<'a, [u8; 32]> for Sha256Software<'a> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, [u8; 32]>) {
+        self.client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ErrorCode, &'static mut [u8])> {
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, data.take()));
+        }
+
+        let len = data.len();
+        self.data_index.set(0);
+        self.data.set(Some(data));
+        self.schedule();
+        Ok(len)
+    }
+
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        if self.is_busy() {
+            return Err((ErrorCode::BUSY, digest));
+        }
+
+        self.digest.replace(digest);
+        self.schedule();
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.key.clear();
+        self.reset();
+    }
+}
+
+impl digest::HMACSha256 for Sha256Software<'_> {
+    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ErrorCode> {
+        if self.is_busy() {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.key.set(HmacSha256::new(key));
+        self.reset();
+        Ok(())
+    }
+}
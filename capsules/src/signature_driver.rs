@@ -0,0 +1,301 @@
+//! Signature verification for applications.
+//!
+//! This capsule lets applications check signatures, for example of data
+//! received over the network, with a `SignatureVerify` engine such as
+//! `capsules::p256::P256Software`. Applications share the public key, the
+//! hash and the signature with read-only allow buffers.
+//!
+//! The engine is shared between processes: one verification runs at a time,
+//! and the verifications of other processes are queued. Every verification
+//! sets the public key of the process that requested it.
+//!
+//! The engine must be dedicated to this driver, as the driver sets its
+//! public key.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let signature = static_init!(
+//!     capsules::signature_driver::SignatureDriver<'static, P256Software<'static>, 32, 64>,
+//!     capsules::signature_driver::SignatureDriver::new(
+//!         p256,
+//!         static_init!([u8; 32], [0; 32]),
+//!         static_init!([u8; 64], [0; 64]),
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! p256.set_verify_client(signature);
+//! ```
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Signature as usize;
+
+use core::mem;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::public_key_crypto::{ClientVerify, PublicKey, SignatureVerify};
+use kernel::{
+    AppId, CommandReturn, Driver, ErrorCode, Grant, Read, ReadOnlyAppSlice, ReturnCode, Upcall,
+};
+
+pub struct SignatureDriver<
+    'a,
+    V: SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> + PublicKey,
+    const HASH_LEN: usize,
+    const SIGNATURE_LEN: usize,
+> {
+    verifier: &'a V,
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+
+    hash: TakeCell<'static, [u8; HASH_LEN]>,
+    signature: TakeCell<'static, [u8; SIGNATURE_LEN]>,
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> + PublicKey,
+        const HASH_LEN: usize,
+        const SIGNATURE_LEN: usize,
+    > SignatureDriver<'a, V, HASH_LEN, SIGNATURE_LEN>
+{
+    pub fn new(
+        verifier: &'a V,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+        grant: Grant<App>,
+    ) -> SignatureDriver<'a, V, HASH_LEN, SIGNATURE_LEN> {
+        SignatureDriver {
+            verifier,
+            apps: grant,
+            appid: OptionalCell::empty(),
+            hash: TakeCell::new(hash),
+            signature: TakeCell::new(signature),
+        }
+    }
+
+    /// Starts the verification requested by `appid`.
+    fn start(&self, appid: AppId) -> Result<(), ReturnCode> {
+        let ret = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.hash.len() != HASH_LEN || app.signature.len() != SIGNATURE_LEN {
+                    return Err(ReturnCode::EINVAL);
+                }
+                app.public_key
+                    .map_or(Err(ErrorCode::INVAL), |key| {
+                        self.verifier.set_public_key(key)
+                    })
+                    .map_err(ReturnCode::from)?;
+
+                let hash = self.hash.take().ok_or(ReturnCode::EBUSY)?;
+                let signature = match self.signature.take() {
+                    Some(signature) => signature,
+                    None => {
+                        self.hash.replace(hash);
+                        return Err(ReturnCode::EBUSY);
+                    }
+                };
+                app.hash.map_or((), |data| hash.copy_from_slice(data));
+                app.signature
+                    .map_or((), |data| signature.copy_from_slice(data));
+
+                self.verifier
+                    .verify(hash, signature)
+                    .map_err(|(e, hash, signature)| {
+                        self.hash.replace(hash);
+                        self.signature.replace(signature);
+                        ReturnCode::from(e)
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()));
+
+        if ret.is_ok() {
+            self.appid.set(appid);
+        }
+        ret
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            // If an app is already running let it complete
+            if self.appid.is_some() {
+                break;
+            }
+
+            let pending = appiter.enter(|app, _| {
+                if app.pending {
+                    app.pending = false;
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            });
+            if let Some(appid) = pending {
+                if let Err(e) = self.start(appid) {
+                    let _ = self.apps.enter(appid, |app, _| {
+                        app.callback.schedule(usize::from(e), 0, 0);
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> + PublicKey,
+        const HASH_LEN: usize,
+        const SIGNATURE_LEN: usize,
+    > ClientVerify<HASH_LEN, SIGNATURE_LEN> for SignatureDriver<'a, V, HASH_LEN, SIGNATURE_LEN>
+{
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let (rcode, valid) = match result {
+                    Ok(valid) => (ReturnCode::SUCCESS, valid),
+                    Err(e) => (e.into(), false),
+                };
+                app.callback.schedule(usize::from(rcode), valid as usize, 0);
+            });
+        });
+        self.check_queue();
+    }
+}
+
+impl<
+        'a,
+        V: SignatureVerify<'a, HASH_LEN, SIGNATURE_LEN> + PublicKey,
+        const HASH_LEN: usize,
+        const SIGNATURE_LEN: usize,
+    > Driver for SignatureDriver<'a, V, HASH_LEN, SIGNATURE_LEN>
+{
+    /// Specify the public key, the hash and the signature.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Allow a buffer containing the public key, in the encoding of
+    ///        the engine. For P-256, this is the 65 byte uncompressed key.
+    /// - `1`: Allow a buffer containing the hash of the signed data.
+    /// - `2`: Allow a buffer containing the signature.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        mut slice: ReadOnlyAppSlice,
+    ) -> Result<ReadOnlyAppSlice, (ReadOnlyAppSlice, ErrorCode)> {
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => mem::swap(&mut slice, &mut app.public_key),
+                    1 => mem::swap(&mut slice, &mut app.hash),
+                    2 => mem::swap(&mut slice, &mut app.signature),
+                    _ => return Err(ErrorCode::NOSUPPORT),
+                }
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::FAIL));
+
+        match res {
+            Ok(()) => Ok(slice),
+            Err(e) => Err((slice, e)),
+        }
+    }
+
+    /// Subscribe to verification events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of verifications. The callback
+    ///        signature is `fn(result: u32, valid: u32)`, where `valid` is 1
+    ///        if the signature is valid.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        mut callback: Upcall,
+        appid: AppId,
+    ) -> Result<Upcall, (Upcall, ErrorCode)> {
+        let res = match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    mem::swap(&mut app.callback, &mut callback);
+                    Ok(())
+                })
+                .unwrap_or(Err(ErrorCode::FAIL)),
+
+            // default
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+
+        match res {
+            Ok(()) => Ok(callback),
+            Err(e) => Err((callback, e)),
+        }
+    }
+
+    /// Verify signatures.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Check that the signature buffer holds a signature of the hash
+    ///        in the hash buffer, by the public key in the key buffer. The
+    ///        verification is queued if another one is in progress. Each
+    ///        process can queue one verification.
+    fn command(
+        &self,
+        command_num: usize,
+        _data1: usize,
+        _data2: usize,
+        appid: AppId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                if self.appid.is_none() {
+                    match self.start(appid) {
+                        Ok(()) => CommandReturn::success(),
+                        Err(e) => CommandReturn::from(e),
+                    }
+                } else {
+                    // Some app is using the engine, we must wait.
+                    self.apps
+                        .enter(appid, |app, _| {
+                            if app.pending {
+                                // No more room in the queue
+                                CommandReturn::failure(ErrorCode::NOMEM)
+                            } else {
+                                app.pending = true;
+                                CommandReturn::success()
+                            }
+                        })
+                        .unwrap_or_else(|err| err.into())
+                }
+            }
+
+            // default
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Upcall,
+    pending: bool,
+    public_key: ReadOnlyAppSlice,
+    hash: ReadOnlyAppSlice,
+    signature: ReadOnlyAppSlice,
+}
@@ -86,8 +86,8 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
     fn generate_key_complete(
         &self,
         _result: Result<(), ReturnCode>,
-        _unhashed_key: &'static [u8],
-        _key_buf: &'static T,
+        _unhashed_key: &'static mut [u8],
+        _key_buf: &'static mut T,
     ) {
         unimplemented!()
     }
@@ -96,7 +96,7 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         &self,
         result: Result<(), ReturnCode>,
         key: &'static mut T,
-        value: &'static [u8],
+        value: &'static mut [u8],
     ) {
         match result {
             Ok(()) => {
@@ -139,6 +139,15 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         }
     }
 
+    fn update_key_complete(
+        &self,
+        _result: Result<(), ReturnCode>,
+        _key: &'static mut T,
+        _value: &'static mut [u8],
+    ) {
+        unimplemented!()
+    }
+
     fn invalidate_key_complete(&self, result: Result<(), ReturnCode>, key: &'static mut T) {
         match result {
             Ok(()) => {
@@ -156,6 +165,10 @@ impl<'a, S: KVSystem<'static, K = T>, T: KeyType + core::fmt::Debug> kv_system::
         }
     }
 
+    fn next_key_complete(&self, _result: Result<usize, ReturnCode>, _key_buf: &'static mut T) {
+        unimplemented!()
+    }
+
     fn garbage_collect_complete(&self, result: Result<(), ReturnCode>) {
         match result {
             Ok(()) => {
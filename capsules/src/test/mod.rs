@@ -6,6 +6,7 @@ pub mod kv_system;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
+pub mod tcp;
 pub mod udp;
 pub mod virtual_rng;
 pub mod virtual_uart;
@@ -0,0 +1,311 @@
+//! Loopback test harness for the TCP stack.
+//!
+//! `LoopbackIP6` is an `IP6Sender` that, instead of sending packets, passes
+//! them back up the receive path of the IPv6 layer (an `IP6RecvStruct`) a
+//! moment later. Packets go through the same serialization and checksum
+//! code as packets sent over 6LoWPAN, so TCP can be tested on a single board
+//! without a radio. It can also drop packets to exercise retransmissions.
+//!
+//! `TestTcpLoopback` opens a connection between two sockets of the same
+//! `MuxTcp` over a `LoopbackIP6`, sends a message larger than the window and
+//! maximum segment size from the client to the server and back, and closes
+//! the connection from the client. Its expected output is:
+//!
+//! ```
+//! TCP loopback: connected
+//! TCP loopback: server received message
+//! TCP loopback: client received echo
+//! TCP loopback test passed
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let loopback = static_init!(
+//!     LoopbackIP6<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     LoopbackIP6::new(loopback_alarm, ip6_dg, &mut LOOPBACK_BUF, LOCAL_ADDR)
+//! );
+//! loopback_alarm.set_alarm_client(loopback);
+//! loopback.set_rx_client(ip_receive);
+//! // Drop the fourth packet to test retransmission
+//! loopback.drop_packet(4);
+//!
+//! // Create `tcp_mux` over `loopback` and add two sockets to it.
+//!
+//! let tcp_test = static_init!(
+//!     TestTcpLoopback<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     TestTcpLoopback::new(server_socket, client_socket, LOCAL_ADDR)
+//! );
+//! server_socket.set_client(tcp_test, SERVER_ID);
+//! client_socket.set_client(tcp_test, CLIENT_ID);
+//! tcp_test.run();
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use crate::net::tcp::tcp_socket::{TCPClient, TCPSocket};
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+pub const SERVER_ID: usize = 0;
+pub const CLIENT_ID: usize = 1;
+pub const SERVER_PORT: u16 = 80;
+pub const CLIENT_PORT: u16 = 49152;
+pub const MESSAGE_LEN: usize = 600;
+
+pub struct LoopbackIP6<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    buf: TakeCell<'static, [u8]>,
+    pending_len: Cell<usize>,
+    addr: Cell<IPAddr>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    rx_client: OptionalCell<&'a dyn SixlowpanRxClient>,
+    drop_countdown: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> LoopbackIP6<'a, A> {
+    /// `buf` must be large enough for any packet sent, including its IPv6
+    /// header.
+    pub fn new(
+        alarm: &'a A,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        buf: &'static mut [u8],
+        addr: IPAddr,
+    ) -> LoopbackIP6<'a, A> {
+        LoopbackIP6 {
+            alarm: alarm,
+            ip6_packet: TakeCell::new(ip6_packet),
+            buf: TakeCell::new(buf),
+            pending_len: Cell::new(0),
+            addr: Cell::new(addr),
+            client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            drop_countdown: Cell::new(0),
+        }
+    }
+
+    pub fn set_rx_client(&self, rx_client: &'a dyn SixlowpanRxClient) {
+        self.rx_client.set(rx_client);
+    }
+
+    /// Drops the `n`th packet sent from now on (counting from 1).
+    pub fn drop_packet(&self, n: usize) {
+        self.drop_countdown.set(n);
+    }
+}
+
+impl<'a, A: Alarm<'a>> IP6Sender<'a> for LoopbackIP6<'a, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if self.pending_len.get() != 0 {
+            return ReturnCode::EBUSY;
+        }
+        let len = self.ip6_packet.map_or(0, |ip6_packet| {
+            ip6_packet.reset();
+            ip6_packet.header.src_addr = self.addr.get();
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+            self.buf
+                .map_or(0, |buf| match ip6_packet.encode(buf).done() {
+                    Some((len, _)) => len,
+                    None => 0,
+                })
+        });
+        if len == 0 {
+            return ReturnCode::ESIZE;
+        }
+        self.pending_len.set(len);
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(1));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for LoopbackIP6<'a, A> {
+    fn alarm(&self) {
+        let len = self.pending_len.get();
+        let countdown = self.drop_countdown.get();
+        self.drop_countdown.set(countdown.saturating_sub(1));
+        if countdown != 1 {
+            // The receive path may reply immediately. The sender is still
+            // busy at this point, so the reply is sent after `send_done`.
+            self.buf.map(|buf| {
+                self.rx_client
+                    .map(|rx_client| rx_client.receive(buf, len, ReturnCode::SUCCESS));
+            });
+        }
+        self.pending_len.set(0);
+        self.client
+            .map(|client| client.send_done(ReturnCode::SUCCESS));
+    }
+}
+
+// Byte `index` of the test message
+fn message_byte(index: usize) -> u8 {
+    (index % 251) as u8
+}
+
+pub struct TestTcpLoopback<'a, A: Alarm<'a>> {
+    server: &'a TCPSocket<'a, A>,
+    client: &'a TCPSocket<'a, A>,
+    addr: IPAddr,
+    // Number of bytes of the message sent and received by each socket
+    sent: [Cell<usize>; 2],
+    received: [Cell<usize>; 2],
+    closed: Cell<usize>,
+    failed: Cell<bool>,
+}
+
+impl<'a, A: Alarm<'a>> TestTcpLoopback<'a, A> {
+    /// `server` and `client` must have the test as client, with ids
+    /// `SERVER_ID` and `CLIENT_ID`. `addr` is the address of the loopback
+    /// interface.
+    pub fn new(
+        server: &'a TCPSocket<'a, A>,
+        client: &'a TCPSocket<'a, A>,
+        addr: IPAddr,
+    ) -> TestTcpLoopback<'a, A> {
+        TestTcpLoopback {
+            server: server,
+            client: client,
+            addr: addr,
+            sent: [Cell::new(0), Cell::new(0)],
+            received: [Cell::new(0), Cell::new(0)],
+            closed: Cell::new(0),
+            failed: Cell::new(false),
+        }
+    }
+
+    pub fn run(&self) {
+        let result = self.server.listen(SERVER_PORT);
+        if result != ReturnCode::SUCCESS {
+            self.fail("listen", result);
+            return;
+        }
+        let result = self.client.connect(CLIENT_PORT, self.addr, SERVER_PORT);
+        if result != ReturnCode::SUCCESS {
+            self.fail("connect", result);
+        }
+    }
+
+    fn socket(&self, id: usize) -> &'a TCPSocket<'a, A> {
+        if id == SERVER_ID {
+            self.server
+        } else {
+            self.client
+        }
+    }
+
+    fn fail(&self, what: &str, result: ReturnCode) {
+        self.failed.set(true);
+        debug!("TCP loopback test failed: {} ({:?})", what, result);
+    }
+
+    // Queue as much of the rest of the message as the socket accepts
+    fn send_more(&self, id: usize) {
+        let mut chunk = [0; 64];
+        while self.sent[id].get() < MESSAGE_LEN {
+            let sent = self.sent[id].get();
+            let len = cmp::min(chunk.len(), MESSAGE_LEN - sent);
+            for (i, byte) in chunk[..len].iter_mut().enumerate() {
+                *byte = message_byte(sent + i);
+            }
+            match self.socket(id).send(&chunk[..len]) {
+                Ok(0) => break,
+                Ok(queued) => self.sent[id].set(sent + queued),
+                Err(e) => {
+                    self.fail("send", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> TCPClient for TestTcpLoopback<'a, A> {
+    fn connected(&self, id: usize) {
+        if id == CLIENT_ID {
+            debug!("TCP loopback: connected");
+            self.send_more(CLIENT_ID);
+        }
+    }
+
+    fn received(&self, id: usize, _available: usize) {
+        let mut chunk = [0; 64];
+        loop {
+            let len = self.socket(id).recv(&mut chunk);
+            if len == 0 {
+                break;
+            }
+            let received = self.received[id].get();
+            let expected = (received..received + len).map(message_byte);
+            if received + len > MESSAGE_LEN || !chunk[..len].iter().copied().eq(expected) {
+                self.fail("received data", ReturnCode::FAIL);
+                return;
+            }
+            self.received[id].set(received + len);
+        }
+
+        if self.received[id].get() == MESSAGE_LEN {
+            if id == SERVER_ID {
+                debug!("TCP loopback: server received message");
+                self.send_more(SERVER_ID);
+            } else {
+                debug!("TCP loopback: client received echo");
+                self.client.close();
+            }
+        }
+    }
+
+    fn sent(&self, id: usize, _acked: usize) {
+        self.send_more(id);
+    }
+
+    fn remote_closed(&self, id: usize) {
+        if id == SERVER_ID {
+            self.server.close();
+        }
+    }
+
+    fn closed(&self, _id: usize, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            self.fail("close", result);
+            return;
+        }
+        self.closed.set(self.closed.get() + 1);
+        if self.closed.get() == 2 && !self.failed.get() {
+            debug!("TCP loopback test passed");
+        }
+    }
+}
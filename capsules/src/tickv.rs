@@ -35,7 +35,7 @@ use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::flash::{self, Flash};
 use kernel::hil::kv_system::{self, KVSystem};
 use kernel::ReturnCode;
-use tickv::{self, AsyncTicKV, ErrorCode};
+use tickv::{self, AsyncTicKV, ErrorCode, KeyCursor};
 
 #[derive(Clone, Copy, PartialEq)]
 enum Operation {
@@ -43,7 +43,9 @@ enum Operation {
     Init,
     GetKey,
     AppendKey,
+    UpdateKey,
     InvalidateKey,
+    NextKey,
     GarbageCollect,
 }
 
@@ -115,14 +117,38 @@ impl<'a, F: Flash> tickv::flash_controller::FlashController<512> for TickFSFlast
 
 pub type TicKVKeyType = [u8; 8];
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hashes `unhashed_key` with the 64-bit FNV-1a hash.
+fn hash_key(unhashed_key: &[u8]) -> u64 {
+    unhashed_key.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Converts a TicKV error to the `ReturnCode` described by
+/// `hil::kv_system`.
+fn return_code(error: ErrorCode) -> ReturnCode {
+    match error {
+        ErrorCode::KeyNotFound | ErrorCode::KeyAlreadyExists => ReturnCode::ENOSUPPORT,
+        ErrorCode::RegionFull | ErrorCode::FlashFull => ReturnCode::ENOMEM,
+        ErrorCode::ObjectTooLarge | ErrorCode::BufferTooSmall(_) => ReturnCode::ESIZE,
+        _ => ReturnCode::FAIL,
+    }
+}
+
 pub struct TicKVStore<'a, F: Flash + 'static> {
     tickv: AsyncTicKV<'a, TickFSFlastCtrl<'a, F>, 512>,
     operation: Cell<Operation>,
     next_operation: Cell<Operation>,
 
-    value_buffer: Cell<Option<&'static [u8]>>,
+    value_buffer: TakeCell<'static, [u8]>,
+    value_length: Cell<usize>,
     key_buffer: TakeCell<'static, [u8; 8]>,
     ret_buffer: TakeCell<'static, [u8]>,
+    /// The position of `next_key()`.
+    cursor: Cell<KeyCursor>,
 
     client: OptionalCell<&'a dyn kv_system::Client<TicKVKeyType>>,
 }
@@ -145,9 +171,11 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
             tickv,
             operation: Cell::new(Operation::None),
             next_operation: Cell::new(Operation::None),
-            value_buffer: Cell::new(None),
+            value_buffer: TakeCell::empty(),
+            value_length: Cell::new(0),
             key_buffer: TakeCell::empty(),
             ret_buffer: TakeCell::empty(),
+            cursor: Cell::new(KeyCursor::new()),
             client: OptionalCell::empty(),
         }
     }
@@ -157,6 +185,19 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
         self.operation.set(Operation::Init);
     }
 
+    /// Finishes `next_key()` with the result of the search from the cursor.
+    fn complete_next_key(&self, ret: Result<(u64, usize), ErrorCode>) {
+        self.operation.set(Operation::None);
+        let key = self.key_buffer.take().unwrap();
+        let result = ret.map(|(hash, len)| {
+            *key = hash.to_le_bytes();
+            len
+        });
+        self.client.map(move |cb| {
+            cb.next_key_complete(result.map_err(return_code), key);
+        });
+    }
+
     fn complete_init(&self) {
         self.operation.set(Operation::None);
         match self.next_operation.get() {
@@ -165,6 +206,7 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
                 match self.append_key(
                     self.key_buffer.take().unwrap(),
                     self.value_buffer.take().unwrap(),
+                    self.value_length.get(),
                 ) {
                     Err((key, value, error)) => {
                         self.client.map(move |cb| {
@@ -187,6 +229,20 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
                     _ => {}
                 }
             }
+            Operation::UpdateKey => {
+                match self.update_key(
+                    self.key_buffer.take().unwrap(),
+                    self.value_buffer.take().unwrap(),
+                    self.value_length.get(),
+                ) {
+                    Err((key, value, error)) => {
+                        self.client.map(move |cb| {
+                            cb.update_key_complete(Err(error), key, value);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::InvalidateKey => {
                 match self.invalidate_key(self.key_buffer.take().unwrap()) {
                     Err((key, error)) => {
@@ -197,6 +253,17 @@ impl<'a, F: Flash> TicKVStore<'a, F> {
                     _ => {}
                 }
             }
+            Operation::NextKey => {
+                // The cursor was already reset if the search restarts
+                match self.next_key(self.key_buffer.take().unwrap(), false) {
+                    Err((key, error)) => {
+                        self.client.map(move |cb| {
+                            cb.next_key_complete(Err(error), key);
+                        });
+                    }
+                    _ => {}
+                }
+            }
             Operation::GarbageCollect => match self.garbage_collect() {
                 Err(error) => {
                     self.client.map(move |cb| {
@@ -244,12 +311,12 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
                         );
                     });
                 }
-                Err(tickv::error_codes::ErrorCode::EraseNotReady(_)) | Ok(_) => {}
-                _ => {
+                Err(ErrorCode::ReadNotReady(_)) | Err(ErrorCode::EraseNotReady(_)) | Ok(_) => {}
+                Err(e) => {
                     self.operation.set(Operation::None);
                     self.client.map(|cb| {
                         cb.get_value_complete(
-                            Err(ReturnCode::FAIL),
+                            Err(return_code(e)),
                             self.key_buffer.take().unwrap(),
                             self.ret_buffer.take().unwrap(),
                         );
@@ -261,14 +328,77 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
                 }
-                _ => {}
+                Err(ErrorCode::ReadNotReady(_))
+                | Err(ErrorCode::WriteNotReady(_))
+                | Err(ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                Err(e) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.append_key_complete(
+                            Err(return_code(e)),
+                            self.key_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
+                        );
+                    });
+                }
+            },
+            Operation::UpdateKey => match ret {
+                Ok(tickv::success_codes::SuccessCode::Complete)
+                | Ok(tickv::success_codes::SuccessCode::Written) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.update_key_complete(
+                            Ok(()),
+                            self.key_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
+                        );
+                    });
+                }
+                Err(ErrorCode::ReadNotReady(_))
+                | Err(ErrorCode::WriteNotReady(_))
+                | Err(ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                Err(e) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.update_key_complete(
+                            Err(return_code(e)),
+                            self.key_buffer.take().unwrap(),
+                            self.tickv.get_stored_value_buffer().unwrap(),
+                        );
+                    });
+                }
+            },
+            Operation::NextKey => match ret {
+                Err(ErrorCode::ReadNotReady(_)) => {}
+                _ => {
+                    let cursor = self.tickv.get_stored_cursor().unwrap();
+                    self.cursor.set(cursor);
+                    self.complete_next_key(match ret {
+                        Ok(_) => Ok(cursor.key().unwrap()),
+                        Err(e) => Err(e),
+                    });
+                }
             },
             Operation::InvalidateKey => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
                 | Ok(tickv::success_codes::SuccessCode::Written) => {
                     self.operation.set(Operation::None);
                 }
-                _ => {}
+                Err(ErrorCode::ReadNotReady(_))
+                | Err(ErrorCode::WriteNotReady(_))
+                | Err(ErrorCode::EraseNotReady(_))
+                | Ok(_) => {}
+                Err(e) => {
+                    self.operation.set(Operation::None);
+                    self.client.map(|cb| {
+                        cb.invalidate_key_complete(
+                            Err(return_code(e)),
+                            self.key_buffer.take().unwrap(),
+                        );
+                    });
+                }
             },
             Operation::GarbageCollect => match ret {
                 Ok(tickv::success_codes::SuccessCode::Complete)
@@ -305,6 +435,24 @@ impl<'a, F: Flash> flash::Client<F> for TicKVStore<'a, F> {
                     );
                 });
             }
+            Operation::UpdateKey => {
+                // The new value is written before the old one is
+                // invalidated, so continue until both writes are done.
+                let (ret, _) = self.tickv.continue_operation();
+                match ret {
+                    Err(ErrorCode::ReadNotReady(_)) | Err(ErrorCode::WriteNotReady(_)) => {}
+                    _ => {
+                        self.operation.set(Operation::None);
+                        self.client.map(|cb| {
+                            cb.update_key_complete(
+                                ret.map(|_| ()).map_err(return_code),
+                                self.key_buffer.take().unwrap(),
+                                self.tickv.get_stored_value_buffer().unwrap(),
+                            );
+                        });
+                    }
+                }
+            }
             Operation::InvalidateKey => {
                 self.operation.set(Operation::None);
                 self.client.map(|cb| {
@@ -352,24 +500,40 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
         self.client.set(client);
     }
 
+    /// The key is hashed in software, so `generate_key_complete()` is called
+    /// before this returns.
     fn generate_key(
         &self,
-        _unhashed_key: &'static mut [u8],
-        _key_buf: &'static mut Self::K,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut Self::K,
     ) -> Result<(), (&'static mut [u8], &'static mut Self::K, ReturnCode)> {
-        unimplemented!()
+        let mut hash = hash_key(unhashed_key);
+        // TicKV uses these values to mark unused and erased flash
+        if hash == 0 || hash == u64::MAX {
+            hash = hash.wrapping_add(1) ^ FNV_PRIME;
+        }
+        *key_buf = hash.to_le_bytes();
+
+        self.client.map(move |cb| {
+            cb.generate_key_complete(Ok(()), unhashed_key, key_buf);
+        });
+        Ok(())
     }
 
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], ReturnCode)> {
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (&'static mut Self::K, &'static mut [u8], ReturnCode)> {
         match self.operation.get() {
             Operation::None => {
                 self.operation.set(Operation::AppendKey);
 
-                match self.tickv.append_key(u64::from_le_bytes(*key), value) {
+                match self
+                    .tickv
+                    .append_key(u64::from_le_bytes(*key), value, length)
+                {
                     Ok(_ret) => {
                         self.key_buffer.replace(key);
                         Ok(())
@@ -379,7 +543,14 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, value, ReturnCode::FAIL)),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((
+                                key,
+                                self.tickv.get_stored_value_buffer().unwrap(),
+                                return_code(e),
+                            ))
+                        }
                     },
                 }
             }
@@ -388,7 +559,8 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                 // We can save this request and start it after init
                 self.next_operation.set(Operation::AppendKey);
                 self.key_buffer.replace(key);
-                self.value_buffer.replace(Some(value));
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
                 Ok(())
             }
             _ => {
@@ -417,7 +589,10 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, buf.unwrap(), ReturnCode::FAIL)),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, buf.unwrap(), return_code(e)))
+                        }
                     },
                 }
             }
@@ -436,6 +611,107 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
         }
     }
 
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (&'static mut Self::K, &'static mut [u8], ReturnCode)> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::UpdateKey);
+
+                match self
+                    .tickv
+                    .update_key(u64::from_le_bytes(*key), value, length)
+                {
+                    Ok(_ret) => {
+                        self.operation.set(Operation::None);
+                        let value = self.tickv.get_stored_value_buffer().unwrap();
+                        self.client.map(move |cb| {
+                            cb.update_key_complete(Ok(()), key, value);
+                        });
+                        Ok(())
+                    }
+                    Err(e) => match e {
+                        ErrorCode::ReadNotReady(_) | ErrorCode::WriteNotReady(_) => {
+                            self.key_buffer.replace(key);
+                            Ok(())
+                        }
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((
+                                key,
+                                self.tickv.get_stored_value_buffer().unwrap(),
+                                return_code(e),
+                            ))
+                        }
+                    },
+                }
+            }
+            Operation::Init => {
+                // The init process is still occuring.
+                // We can save this request and start it after init
+                self.next_operation.set(Operation::UpdateKey);
+                self.key_buffer.replace(key);
+                self.value_buffer.replace(value);
+                self.value_length.set(length);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key, value, ReturnCode::EBUSY))
+            }
+        }
+    }
+
+    fn next_key(
+        &self,
+        key_buf: &'static mut Self::K,
+        restart: bool,
+    ) -> Result<(), (&'static mut Self::K, ReturnCode)> {
+        match self.operation.get() {
+            Operation::None => {
+                self.operation.set(Operation::NextKey);
+                if restart {
+                    self.cursor.set(KeyCursor::new());
+                }
+
+                let mut cursor = self.cursor.get();
+                match self.tickv.next_key(&mut cursor) {
+                    Ok(key) => {
+                        self.cursor.set(cursor);
+                        self.key_buffer.replace(key_buf);
+                        self.complete_next_key(Ok(key));
+                        Ok(())
+                    }
+                    Err(ErrorCode::ReadNotReady(_)) => {
+                        self.key_buffer.replace(key_buf);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        self.operation.set(Operation::None);
+                        Err((key_buf, return_code(e)))
+                    }
+                }
+            }
+            Operation::Init => {
+                // The init process is still occuring.
+                // We can save this request and start it after init
+                if restart {
+                    self.cursor.set(KeyCursor::new());
+                }
+                self.next_operation.set(Operation::NextKey);
+                self.key_buffer.replace(key_buf);
+                Ok(())
+            }
+            _ => {
+                // An operation is already in process.
+                Err((key_buf, ReturnCode::EBUSY))
+            }
+        }
+    }
+
     fn invalidate_key(
         &self,
         key: &'static mut Self::K,
@@ -454,7 +730,10 @@ impl<'a, F: Flash> KVSystem<'a> for TicKVStore<'a, F> {
                             self.key_buffer.replace(key);
                             Ok(())
                         }
-                        _ => Err((key, ReturnCode::FAIL)),
+                        _ => {
+                            self.operation.set(Operation::None);
+                            Err((key, return_code(e)))
+                        }
                     },
                 }
             }
@@ -78,6 +78,10 @@ impl<'a> Client for MuxRngMaster<'a> {
 
             if cont_code == Continue::Done {
                 self.do_next_op();
+            } else {
+                // The device waits for more randomness, so it stays in
+                // flight.
+                self.inflight.set(device);
             }
 
             cont_code
@@ -429,20 +429,36 @@ impl<'a> Ble<'a> {
         self.registers.inten.set(0x00);
     }
 
-    fn replace_radio_buffer(&self, buf: &'static mut [u8]) -> &'static mut [u8] {
+    /// Copy the first `len` bytes of `buf` to the payload, or return
+    /// `ESIZE` if they do not fit.
+    fn replace_radio_buffer(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> Result<&'static mut [u8], (kernel::ReturnCode, &'static mut [u8])> {
+        if len > buf.len() || len > unsafe { PAYLOAD.len() } {
+            return Err((kernel::ReturnCode::ESIZE, buf));
+        }
         // set payload
-        for (i, c) in buf.as_ref().iter().enumerate() {
+        for (i, c) in buf[..len].iter().enumerate() {
             unsafe {
                 PAYLOAD[i] = *c;
             }
         }
-        buf
+        Ok(buf)
     }
 }
 
 impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Ble<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, _channel: RadioChannel) {
-        let res = self.replace_radio_buffer(buf);
+        let res = match self.replace_radio_buffer(buf, len) {
+            Ok(res) => res,
+            Err((result, buf)) => {
+                self.tx_client
+                    .map(move |client| client.transmit_event(buf, result));
+                return;
+            }
+        };
 
         // Setup all of the buffers
         self.buffer.replace(res);
@@ -466,6 +482,10 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Ble<'a> {
         unimplemented!();
     }
 
+    fn cancel_receive(&self) -> kernel::ReturnCode {
+        kernel::ReturnCode::ENOSUPPORT
+    }
+
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
         self.rx_client.set(client);
     }
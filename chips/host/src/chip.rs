@@ -0,0 +1,133 @@
+//! High-level setup and interrupt handling for the simulated chip.
+
+use core::fmt::Write;
+use std::time::Duration;
+
+use crate::flash::Flash;
+use crate::syscall::SysCall;
+use crate::time::{Alarm, Clock, SchedulerTimer};
+use crate::uart::Uart;
+
+/// How long the chip sleeps, in real time, when it waits for input and no
+/// alarm is armed.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Host<'a> {
+    userspace_kernel_boundary: SysCall,
+    scheduler_timer: SchedulerTimer<'a>,
+    peripherals: &'a HostDefaultPeripherals<'a>,
+}
+
+pub struct HostDefaultPeripherals<'a> {
+    pub clock: &'a Clock,
+    pub alarm: Alarm<'a>,
+    pub uart: Uart<'a>,
+    pub flash: Option<Flash<'a>>,
+}
+
+impl<'a> HostDefaultPeripherals<'a> {
+    pub fn new(clock: &'a Clock, uart: Uart<'a>, flash: Option<Flash<'a>>) -> Self {
+        Self {
+            clock,
+            alarm: Alarm::new(clock),
+            uart,
+            flash,
+        }
+    }
+}
+
+impl<'a> Host<'a> {
+    pub fn new(peripherals: &'a HostDefaultPeripherals<'a>) -> Self {
+        Self {
+            userspace_kernel_boundary: SysCall::new(),
+            scheduler_timer: SchedulerTimer::new(peripherals.clock),
+            peripherals,
+        }
+    }
+
+    pub fn peripherals(&self) -> &'a HostDefaultPeripherals<'a> {
+        self.peripherals
+    }
+}
+
+impl<'a> kernel::Chip for Host<'a> {
+    type MPU = ();
+    type UserspaceKernelBoundary = SysCall;
+    type SchedulerTimer = SchedulerTimer<'a>;
+    type WatchDog = ();
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        &self.scheduler_timer
+    }
+
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        while self.has_pending_interrupts() {
+            self.peripherals.alarm.handle_interrupt();
+            self.peripherals.uart.handle_interrupt();
+            self.peripherals
+                .flash
+                .as_ref()
+                .map(|flash| flash.handle_interrupt());
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.peripherals.alarm.has_pending_interrupt()
+            || self.peripherals.uart.has_pending_interrupt()
+            || self
+                .peripherals
+                .flash
+                .as_ref()
+                .map_or(false, |flash| flash.has_pending_interrupt())
+    }
+
+    /// Move time forward until the next interrupt.
+    ///
+    /// Without stdin the clock jumps straight to the next alarm. With stdin
+    /// attached the chip waits in real time, so that input can arrive, and
+    /// the clock advances by the time waited.
+    fn sleep(&self) {
+        let uart = &self.peripherals.uart;
+        let remaining_us = self.peripherals.alarm.remaining_us();
+        if uart.is_stdin_attached() {
+            let timeout = remaining_us.map_or(IDLE_POLL_INTERVAL, Duration::from_micros);
+            let start = std::time::Instant::now();
+            uart.wait_for_input(timeout);
+            let waited_us = start.elapsed().as_micros() as u64;
+            self.peripherals
+                .clock
+                .advance(remaining_us.map_or(waited_us, |us| core::cmp::min(us, waited_us)));
+        } else if let Some(us) = remaining_us {
+            self.peripherals.clock.advance(us);
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Interrupts are only handled from the kernel loop, so nothing can
+        // interrupt `f`.
+        f()
+    }
+
+    unsafe fn print_state(&self, write: &mut dyn Write) {
+        let _ = write.write_fmt(format_args!(
+            "\r\n---| Host Simulation |---\
+             \r\n Time: {} us\r\n",
+            self.peripherals.clock.now_us()
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! Flash backed by a file.
+//!
+//! Each page is stored at `page_number * PAGE_SIZE` in the file, so the
+//! contents survive restarts of the simulation. Operations are carried out
+//! right away, but their callbacks run on the next interrupt, like on
+//! hardware.
+
+use core::cell::{Cell, RefCell};
+use core::ops::{Index, IndexMut};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+pub const PAGE_SIZE: usize = 512;
+
+pub struct HostPage(pub [u8; PAGE_SIZE]);
+
+impl Default for HostPage {
+    fn default() -> Self {
+        Self { 0: [0; PAGE_SIZE] }
+    }
+}
+
+impl Index<usize> for HostPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for HostPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for HostPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    None,
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct Flash<'a> {
+    file: RefCell<File>,
+    num_pages: usize,
+    client: OptionalCell<&'a dyn hil::flash::Client<Flash<'a>>>,
+    buffer: TakeCell<'static, HostPage>,
+    operation: Cell<Operation>,
+    result: Cell<hil::flash::Error>,
+}
+
+impl<'a> Flash<'a> {
+    /// Open the flash stored in the file at `path`, creating it if needed.
+    /// Pages that are not in the file yet start out erased.
+    pub fn new(path: &Path, num_pages: usize) -> io::Result<Flash<'a>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let size = (num_pages * PAGE_SIZE) as u64;
+        let len = file.metadata()?.len();
+        if len < size {
+            file.seek(SeekFrom::Start(len))?;
+            file.write_all(&vec![0xFF; (size - len) as usize])?;
+        }
+        Ok(Flash {
+            file: RefCell::new(file),
+            num_pages,
+            client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            operation: Cell::new(Operation::None),
+            result: Cell::new(hil::flash::Error::CommandComplete),
+        })
+    }
+
+    fn access<F>(&self, page_number: usize, access: F) -> hil::flash::Error
+    where
+        F: FnOnce(&mut File) -> io::Result<()>,
+    {
+        let mut file = self.file.borrow_mut();
+        match file
+            .seek(SeekFrom::Start((page_number * PAGE_SIZE) as u64))
+            .and_then(|_| access(&mut file))
+        {
+            Ok(()) => hil::flash::Error::CommandComplete,
+            Err(_) => hil::flash::Error::FlashError,
+        }
+    }
+
+    fn check_request(&self, page_number: usize) -> Result<(), ReturnCode> {
+        if self.operation.get() != Operation::None {
+            Err(ReturnCode::EBUSY)
+        } else if page_number >= self.num_pages {
+            Err(ReturnCode::EINVAL)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn has_pending_interrupt(&self) -> bool {
+        self.operation.get() != Operation::None
+    }
+
+    pub fn handle_interrupt(&self) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::None);
+        let result = self.result.get();
+        match operation {
+            Operation::None => {}
+            Operation::Read => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_complete(buffer, result))
+                });
+            }
+            Operation::Write => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_complete(buffer, result))
+                });
+            }
+            Operation::Erase => {
+                self.client.map(|client| client.erase_complete(result));
+            }
+        }
+    }
+}
+
+impl<'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C> for Flash<'a> {
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl hil::flash::Flash for Flash<'_> {
+    type Page = HostPage;
+
+    fn read_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        if let Err(error) = self.check_request(page_number) {
+            return Err((error, buf));
+        }
+        self.result
+            .set(self.access(page_number, |file| file.read_exact(&mut buf.0)));
+        self.buffer.replace(buf);
+        self.operation.set(Operation::Read);
+        Ok(())
+    }
+
+    fn write_page(
+        &self,
+        page_number: usize,
+        buf: &'static mut Self::Page,
+    ) -> Result<(), (ReturnCode, &'static mut Self::Page)> {
+        if let Err(error) = self.check_request(page_number) {
+            return Err((error, buf));
+        }
+        self.result
+            .set(self.access(page_number, |file| file.write_all(&buf.0)));
+        self.buffer.replace(buf);
+        self.operation.set(Operation::Write);
+        Ok(())
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        if let Err(error) = self.check_request(page_number) {
+            return error;
+        }
+        self.result
+            .set(self.access(page_number, |file| file.write_all(&[0xFF; PAGE_SIZE])));
+        self.operation.set(Operation::Erase);
+        ReturnCode::SUCCESS
+    }
+}
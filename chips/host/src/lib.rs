@@ -0,0 +1,26 @@
+//! Simulated chip that runs the Tock kernel as a program on the host.
+//!
+//! This chip replaces the hardware with simulated peripherals so that the
+//! kernel loop, capsules and schedulers can run, and be tested with
+//! `cargo test`, on a development machine:
+//!
+//! - `time`: a simulated clock, an alarm and the scheduler timer.
+//! - `uart`: a UART connected to stdin and stdout, or to buffers in tests.
+//! - `flash`: flash backed by a file.
+//! - `syscall`: processes that do not execute any code, but immediately
+//!   yield whenever they are run.
+//!
+//! The chip has no MPU.
+
+#![feature(const_fn)]
+#![crate_name = "host"]
+#![crate_type = "rlib"]
+
+pub mod chip;
+pub mod flash;
+pub mod syscall;
+pub mod time;
+pub mod uart;
+
+#[cfg(test)]
+mod tests;
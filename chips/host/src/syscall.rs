@@ -0,0 +1,103 @@
+//! Userspace on the host.
+//!
+//! The host cannot run process binaries built for a microcontroller, so
+//! processes do not execute any code. Whenever the kernel switches to a
+//! process it immediately calls `yield`. Upcalls are therefore handled
+//! instantly: the kernel sets up the function call, and the process yields
+//! again the next time it runs.
+//!
+//! This is enough to load processes, give them grants and upcalls, and to
+//! exercise the schedulers.
+
+use core::fmt::Write;
+
+use kernel::procs::FunctionCall;
+use kernel::syscall::{ContextSwitchReason, Syscall, SyscallReturn, YieldCall};
+
+/// Per-process state.
+#[derive(Default)]
+pub struct HostStoredState {
+    /// The function the process was last asked to run.
+    function: Option<FunctionCall>,
+    /// How many times the process was switched to.
+    switch_count: usize,
+}
+
+pub struct SysCall(());
+
+impl SysCall {
+    pub const fn new() -> SysCall {
+        SysCall(())
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        *state = HostStoredState::default();
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut Self::StoredState,
+        _return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        upcall: FunctionCall,
+    ) -> Result<(), ()> {
+        state.function = Some(upcall);
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        state.switch_count += 1;
+        (
+            ContextSwitchReason::SyscallFired {
+                syscall: Syscall::Yield {
+                    which: YieldCall::Wait as usize,
+                    address: core::ptr::null_mut(),
+                },
+            },
+            None,
+        )
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n Simulated process, switched to {} times\
+             \r\n Last function: {:?}\r\n",
+            state.switch_count, state.function,
+        ));
+    }
+}
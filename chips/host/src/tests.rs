@@ -0,0 +1,568 @@
+use core::cell::Cell;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::boxed::Box;
+use std::vec::Vec;
+
+use capsules::app_watchdog::AppWatchdog;
+use capsules::filesystem::FileSystem;
+use capsules::nonvolatile_to_pages::NonvolatileToPages;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities::{
+    MainLoopCapability, MemoryAllocationCapability, ProcessManagementCapability,
+};
+use kernel::hil::flash::{Flash as _, HasClient};
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::time::{Alarm as _, AlarmClient, Time};
+use kernel::hil::uart::{self, Receive, Transmit};
+use kernel::procs::{FaultResponse, ProcessSlot, ProcessType, State, ThresholdRestart};
+use kernel::{create_capability, Chip, Driver, Kernel, Platform, ReturnCode};
+use kernel::{RoundRobinProcessNode, RoundRobinSched};
+
+use crate::chip::{Host, HostDefaultPeripherals};
+use crate::flash::{Flash, HostPage, PAGE_SIZE};
+use crate::time::Clock;
+use crate::uart::Uart;
+
+/// Leak a value so it can be used where the kernel needs `'static`
+/// references.
+fn leak<T>(value: T) -> &'static mut T {
+    Box::leak(Box::new(value))
+}
+
+fn host(uart: Uart<'static>, flash: Option<Flash<'static>>) -> &'static Host<'static> {
+    let clock = leak(Clock::new());
+    let peripherals = leak(HostDefaultPeripherals::new(clock, uart, flash));
+    leak(Host::new(peripherals))
+}
+
+#[derive(Default)]
+struct AlarmCounter {
+    fired: Cell<usize>,
+}
+
+impl AlarmClient for AlarmCounter {
+    fn alarm(&self) {
+        self.fired.set(self.fired.get() + 1);
+    }
+}
+
+#[test]
+fn alarm_fires_after_sleep() {
+    let clock = leak(Clock::new());
+    let peripherals = leak(HostDefaultPeripherals::new(
+        clock,
+        Uart::new_captured(),
+        None,
+    ));
+    let chip = Host::new(peripherals);
+    let counter = leak(AlarmCounter::default());
+    let alarm = &peripherals.alarm;
+    alarm.set_alarm_client(counter);
+
+    alarm.set_alarm(alarm.now(), 1500.into());
+    assert!(!chip.has_pending_interrupts());
+
+    // Sleeping jumps straight to the alarm.
+    chip.sleep();
+    assert_eq!(clock.now_us(), 1500);
+    assert!(chip.has_pending_interrupts());
+    chip.service_pending_interrupts();
+    assert_eq!(counter.fired.get(), 1);
+    assert!(!alarm.is_armed());
+}
+
+#[test]
+fn virtual_alarms() {
+    let clock = leak(Clock::new());
+    let peripherals = leak(HostDefaultPeripherals::new(
+        clock,
+        Uart::new_captured(),
+        None,
+    ));
+    let chip = Host::new(peripherals);
+    let mux = leak(MuxAlarm::new(&peripherals.alarm));
+    peripherals.alarm.set_alarm_client(mux);
+
+    let first = leak(VirtualMuxAlarm::new(mux));
+    let second = leak(VirtualMuxAlarm::new(mux));
+    let first_counter = leak(AlarmCounter::default());
+    let second_counter = leak(AlarmCounter::default());
+    first.set_alarm_client(first_counter);
+    second.set_alarm_client(second_counter);
+
+    second.set_alarm(second.now(), 3000.into());
+    first.set_alarm(first.now(), 1000.into());
+
+    chip.sleep();
+    chip.service_pending_interrupts();
+    assert_eq!(clock.now_us(), 1000);
+    assert_eq!(
+        (first_counter.fired.get(), second_counter.fired.get()),
+        (1, 0)
+    );
+
+    chip.sleep();
+    chip.service_pending_interrupts();
+    assert_eq!(clock.now_us(), 3000);
+    assert_eq!(
+        (first_counter.fired.get(), second_counter.fired.get()),
+        (1, 1)
+    );
+}
+
+#[derive(Default)]
+struct UartClient {
+    transmitted: Cell<Option<usize>>,
+    received: Cell<Option<(usize, ReturnCode)>>,
+    buffer: Cell<Option<&'static mut [u8]>>,
+}
+
+impl uart::TransmitClient for UartClient {
+    fn transmitted_buffer(&self, tx_buffer: &'static mut [u8], tx_len: usize, rval: ReturnCode) {
+        assert_eq!(rval, ReturnCode::SUCCESS);
+        self.transmitted.set(Some(tx_len));
+        self.buffer.set(Some(tx_buffer));
+    }
+}
+
+impl uart::ReceiveClient for UartClient {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: ReturnCode,
+        _error: uart::Error,
+    ) {
+        self.received.set(Some((rx_len, rval)));
+        self.buffer.set(Some(rx_buffer));
+    }
+}
+
+#[test]
+fn uart_transmit_and_receive() {
+    let chip = host(Uart::new_captured(), None);
+    let uart = &chip.peripherals().uart;
+    let client = leak(UartClient::default());
+    uart.set_transmit_client(client);
+    uart.set_receive_client(client);
+
+    let (rval, _) = uart.transmit_buffer(leak(*b"hello world"), 5);
+    assert_eq!(rval, ReturnCode::SUCCESS);
+    assert!(chip.has_pending_interrupts());
+    chip.service_pending_interrupts();
+    assert_eq!(client.transmitted.get(), Some(5));
+    assert_eq!(uart.take_output(), b"hello");
+
+    // Reception completes once enough bytes arrived.
+    let (rval, _) = uart.receive_buffer(leak([0; 4]), 3);
+    assert_eq!(rval, ReturnCode::SUCCESS);
+    uart.push_input(b"ab");
+    chip.service_pending_interrupts();
+    assert_eq!(client.received.get(), None);
+    uart.push_input(b"cd");
+    chip.service_pending_interrupts();
+    assert_eq!(client.received.get(), Some((3, ReturnCode::SUCCESS)));
+    assert_eq!(&client.buffer.take().unwrap()[..3], b"abc");
+
+    // Aborting returns the bytes received so far.
+    let (rval, _) = uart.receive_buffer(leak([0; 4]), 4);
+    assert_eq!(rval, ReturnCode::SUCCESS);
+    assert_eq!(uart.receive_abort(), ReturnCode::EBUSY);
+    chip.service_pending_interrupts();
+    assert_eq!(client.received.get(), Some((1, ReturnCode::ECANCEL)));
+    assert_eq!(client.buffer.take().unwrap()[0], b'd');
+}
+
+#[derive(Default)]
+struct FlashClient {
+    completed: Cell<usize>,
+    page: Cell<Option<&'static mut HostPage>>,
+}
+
+impl kernel::hil::flash::Client<Flash<'static>> for FlashClient {
+    fn read_complete(&self, read_buffer: &'static mut HostPage, error: kernel::hil::flash::Error) {
+        assert_eq!(error, kernel::hil::flash::Error::CommandComplete);
+        self.completed.set(self.completed.get() + 1);
+        self.page.set(Some(read_buffer));
+    }
+
+    fn write_complete(
+        &self,
+        write_buffer: &'static mut HostPage,
+        error: kernel::hil::flash::Error,
+    ) {
+        assert_eq!(error, kernel::hil::flash::Error::CommandComplete);
+        self.completed.set(self.completed.get() + 1);
+        self.page.set(Some(write_buffer));
+    }
+
+    fn erase_complete(&self, error: kernel::hil::flash::Error) {
+        assert_eq!(error, kernel::hil::flash::Error::CommandComplete);
+        self.completed.set(self.completed.get() + 1);
+    }
+}
+
+#[test]
+fn flash_persists_in_file() {
+    let path = std::env::temp_dir().join(format!("tock-host-flash-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let flash = leak(Flash::new(&path, 4).unwrap());
+    let client = leak(FlashClient::default());
+    flash.set_client(client);
+
+    let mut page = HostPage::default();
+    page[0] = 0x12;
+    page[PAGE_SIZE - 1] = 0x34;
+    assert!(flash.write_page(2, leak(page)).is_ok());
+    assert!(flash.has_pending_interrupt());
+    flash.handle_interrupt();
+    assert_eq!(client.completed.get(), 1);
+
+    // Out of range pages are rejected right away.
+    assert!(flash.read_page(4, client.page.take().unwrap()).is_err());
+    assert!(!flash.has_pending_interrupt());
+
+    // The page can be read back after opening the file again.
+    let reopened = leak(Flash::new(&path, 4).unwrap());
+    reopened.set_client(client);
+    assert!(reopened.read_page(2, leak(HostPage::default())).is_ok());
+    reopened.handle_interrupt();
+    let page = client.page.take().unwrap();
+    assert_eq!((page[0], page[PAGE_SIZE - 1]), (0x12, 0x34));
+
+    // Pages that were never written start out erased, as do erased pages.
+    assert!(reopened.read_page(1, page).is_ok());
+    reopened.handle_interrupt();
+    let page = client.page.take().unwrap();
+    assert!(page.0.iter().all(|&byte| byte == 0xFF));
+    assert_eq!(reopened.erase_page(2), ReturnCode::SUCCESS);
+    reopened.handle_interrupt();
+    assert!(reopened.read_page(2, page).is_ok());
+    reopened.handle_interrupt();
+    assert!(client
+        .page
+        .take()
+        .unwrap()
+        .0
+        .iter()
+        .all(|&byte| byte == 0xFF));
+    assert_eq!(client.completed.get(), 5);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[derive(Default)]
+struct FileSystemClient {
+    completed: Cell<Option<Result<(), kernel::ErrorCode>>>,
+    data: Cell<Option<Vec<u8>>>,
+}
+
+impl capsules::filesystem::FileSystemClient for FileSystemClient {
+    fn mount_done(&self, result: Result<(), kernel::ErrorCode>) {
+        self.completed.set(Some(result));
+    }
+
+    fn update_done(&self, result: Result<(), kernel::ErrorCode>) {
+        self.completed.set(Some(result));
+    }
+
+    fn read_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        result: Result<(), kernel::ErrorCode>,
+    ) {
+        self.data.set(Some(buffer[..length].to_vec()));
+        self.completed.set(Some(result));
+    }
+
+    fn write_done(
+        &self,
+        _buffer: &'static mut [u8],
+        _length: usize,
+        result: Result<(), kernel::ErrorCode>,
+    ) {
+        self.completed.set(Some(result));
+    }
+}
+
+/// A filesystem in a flash file, after mounting it.
+fn filesystem(
+    path: &std::path::Path,
+) -> (
+    &'static Flash<'static>,
+    &'static FileSystem<'static>,
+    &'static FileSystemClient,
+) {
+    let flash = leak(Flash::new(path, 16).unwrap());
+    let storage = leak(NonvolatileToPages::new(flash, leak(HostPage::default())));
+    flash.set_client(storage);
+    let fs = leak(FileSystem::new(
+        storage,
+        0,
+        16 * PAGE_SIZE,
+        PAGE_SIZE,
+        leak([0; capsules::filesystem::HEADER_LEN + 4 * capsules::filesystem::ENTRY_LEN]),
+        leak([0; PAGE_SIZE]),
+    ));
+    storage.set_client(fs);
+    let client = leak(FileSystemClient::default());
+    fs.set_client(client);
+
+    assert_eq!(fs.mount(), Ok(()));
+    run_flash(flash, client);
+    (flash, fs, client)
+}
+
+/// Handles flash interrupts until the filesystem operation completes.
+fn run_flash(flash: &Flash, client: &FileSystemClient) {
+    while flash.has_pending_interrupt() {
+        flash.handle_interrupt();
+    }
+    assert_eq!(client.completed.take(), Some(Ok(())));
+}
+
+#[test]
+fn filesystem_persists_in_flash_file() {
+    let path = std::env::temp_dir().join(format!("tock-host-fs-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let data: Vec<u8> = (0..1200).map(|i| i as u8).collect();
+
+    // A new flash file is formatted when mounted.
+    let (flash, fs, client) = filesystem(&path);
+    assert_eq!(fs.create(b"app", b"log.txt"), Ok(()));
+    run_flash(flash, client);
+    let file = fs.find(b"app", b"log.txt").unwrap().unwrap();
+    let buffer = leak(data.clone()).as_mut_slice();
+    assert!(fs.write(file, 0, buffer, data.len()).is_ok());
+    run_flash(flash, client);
+    assert!(fs.write(file, 1000, leak([0xAA; 300]), 300).is_ok());
+    run_flash(flash, client);
+
+    // The file is there after opening the flash file again.
+    let (flash, fs, client) = filesystem(&path);
+    let file = fs.find(b"app", b"log.txt").unwrap().unwrap();
+    assert_eq!(fs.size(file), Ok(1300));
+    assert!(fs.read(file, 0, leak([0; 1300]), 1300).is_ok());
+    run_flash(flash, client);
+    let read = client.data.take().unwrap();
+    assert_eq!(read[..1000], data[..1000]);
+    assert!(read[1000..].iter().all(|&byte| byte == 0xAA));
+
+    let mut name = [0; 32];
+    assert_eq!(fs.list(b"app", 0, &mut name), Ok(7));
+    assert_eq!(&name[..7], b"log.txt");
+    assert_eq!(
+        fs.list(b"other", 0, &mut name),
+        Err(kernel::ErrorCode::INVAL)
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+struct NoDrivers;
+
+impl Platform for NoDrivers {
+    fn with_driver<F, R>(&self, _driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn Driver>) -> R,
+    {
+        f(None)
+    }
+}
+
+/// Build a TBF image for an app with the given name.
+fn app(name: &[u8]) -> Vec<u8> {
+    app_with_tlvs(name, &[])
+}
+
+/// Build a TBF image for an app with the given name, followed by the given
+/// TLV entries in its header.
+fn app_with_tlvs(name: &[u8], tlvs: &[u8]) -> Vec<u8> {
+    let mut header: Vec<u8> = vec![0; 16];
+    // Main: init_fn_offset, protected_size and minimum_ram_size.
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&12u16.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&0x1000u32.to_le_bytes());
+    // Package name.
+    header.extend_from_slice(&3u16.to_le_bytes());
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    header.extend_from_slice(name);
+    while header.len() % 4 != 0 {
+        header.push(0);
+    }
+    header.extend_from_slice(tlvs);
+
+    let header_size = header.len() as u16;
+    let total_size = 256u32;
+    header[0..2].copy_from_slice(&2u16.to_le_bytes());
+    header[2..4].copy_from_slice(&header_size.to_le_bytes());
+    header[4..8].copy_from_slice(&total_size.to_le_bytes());
+    // Enabled.
+    header[8..12].copy_from_slice(&1u32.to_le_bytes());
+    let checksum = header
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |acc, (_, chunk)| {
+            acc ^ u32::from_le_bytes(chunk.try_into().unwrap())
+        });
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+    header.resize(total_size as usize, 0);
+    header
+}
+
+/// Calls `f` with the `n`th loaded process of `kernel`, as the kernel only
+/// lends out its processes.
+fn with_process<R>(kernel: &'static Kernel, n: usize, f: impl Fn(&dyn ProcessType) -> R) -> R {
+    let cap = create_capability!(ProcessManagementCapability);
+    let index = Cell::new(0);
+    let result = Cell::new(None);
+    kernel.process_each_capability(&cap, |process| {
+        if index.get() == n {
+            result.set(Some(f(process)));
+        }
+        index.set(index.get() + 1);
+    });
+    result.into_inner().expect("no such process")
+}
+
+#[test]
+fn kernel_runs_processes() {
+    let chip = host(Uart::new_captured(), None);
+    let processes: &'static [ProcessSlot] = leak([ProcessSlot::EMPTY; 2]);
+    let kernel: &'static Kernel = leak(Kernel::new(processes));
+    let process_mgmt_cap = create_capability!(ProcessManagementCapability);
+    let main_loop_cap = create_capability!(MainLoopCapability);
+
+    let mut flash = app(b"first");
+    flash.extend(app(b"second"));
+    // Process memory must be word aligned.
+    let memory: &mut [u32] = leak([0u32; 0x1000]);
+    let memory = unsafe {
+        core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 4)
+    };
+    kernel::procs::load_processes(
+        kernel,
+        chip,
+        Box::leak(flash.into_boxed_slice()),
+        memory,
+        FaultResponse::Panic,
+        &process_mgmt_cap,
+    )
+    .unwrap();
+
+    let scheduler = leak(RoundRobinSched::new());
+    for process in processes.iter() {
+        scheduler
+            .processes
+            .push_tail(leak(RoundRobinProcessNode::new(process)));
+    }
+
+    for n in 0..2 {
+        assert_eq!(with_process(kernel, n, |p| p.get_state()), State::Unstarted);
+    }
+    for _ in 0..4 {
+        kernel.kernel_loop_operation::<_, _, _, 0>(
+            &NoDrivers,
+            chip,
+            None,
+            scheduler,
+            true,
+            &main_loop_cap,
+        );
+    }
+    let names: Vec<_> = (0..2)
+        .map(|n| with_process(kernel, n, |p| p.get_process_name()))
+        .collect();
+    assert_eq!(names, ["first", "second"]);
+    for n in 0..2 {
+        assert_eq!(with_process(kernel, n, |p| p.get_state()), State::Yielded);
+    }
+}
+
+/// The app watchdog holds its capability, so it needs a named type.
+struct WatchdogCap;
+unsafe impl ProcessManagementCapability for WatchdogCap {}
+
+static WATCHDOG_RESETS: AtomicUsize = AtomicUsize::new(0);
+
+#[test]
+fn app_watchdog_restarts_then_resets() {
+    let clock = leak(Clock::new());
+    let peripherals = leak(HostDefaultPeripherals::new(
+        clock,
+        Uart::new_captured(),
+        None,
+    ));
+    let chip = leak(Host::new(peripherals));
+    let mux = leak(MuxAlarm::new(&peripherals.alarm));
+    peripherals.alarm.set_alarm_client(mux);
+    let alarm = leak(VirtualMuxAlarm::new(mux));
+
+    let processes: &'static [ProcessSlot] = leak([ProcessSlot::EMPTY; 1]);
+    let kernel: &'static Kernel = leak(Kernel::new(processes));
+    let process_mgmt_cap = create_capability!(ProcessManagementCapability);
+    let memory_allocation_cap = create_capability!(MemoryAllocationCapability);
+    let watchdog = leak(AppWatchdog::new(
+        alarm,
+        kernel,
+        WatchdogCap,
+        kernel.create_grant(&memory_allocation_cap),
+        Some(|| {
+            WATCHDOG_RESETS.fetch_add(1, Ordering::Relaxed);
+        }),
+    ));
+    alarm.set_alarm_client(watchdog);
+
+    // Process memory must be word aligned.
+    let memory: &mut [u32] = leak([0u32; 0x800]);
+    let memory = unsafe {
+        core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 4)
+    };
+    // Restarted once, then left faulted.
+    kernel::procs::load_processes(
+        kernel,
+        chip,
+        Box::leak(app(b"hung").into_boxed_slice()),
+        memory,
+        FaultResponse::Restart(leak(ThresholdRestart::new(0))),
+        &process_mgmt_cap,
+    )
+    .unwrap();
+    let appid = || with_process(kernel, 0, |p| p.appid());
+    let expirations = || with_process(kernel, 0, |p| p.debug_watchdog_expiration_count());
+    let restarts = || with_process(kernel, 0, |p| p.get_restart_count());
+    let state = || with_process(kernel, 0, |p| p.get_state());
+
+    // Heartbeats keep the process alive.
+    watchdog.command(1, 10, 0, appid());
+    clock.advance(6000);
+    watchdog.command(2, 0, 0, appid());
+    clock.advance(6000);
+    chip.service_pending_interrupts();
+    assert_eq!(expirations(), 0);
+
+    // A missed deadline restarts it, without its watchdog.
+    chip.sleep();
+    assert_eq!(clock.now_us(), 16000);
+    chip.service_pending_interrupts();
+    assert_eq!(expirations(), 1);
+    assert_eq!(restarts(), 1);
+    assert_ne!(state(), State::Faulted);
+    assert_eq!(WATCHDOG_RESETS.load(Ordering::Relaxed), 0);
+
+    // Missing it again exceeds the restart policy, so the system resets.
+    watchdog.command(1, 10, 0, appid());
+    chip.sleep();
+    chip.service_pending_interrupts();
+    assert_eq!(expirations(), 2);
+    assert_eq!(restarts(), 1);
+    assert_eq!(state(), State::Faulted);
+    assert_eq!(WATCHDOG_RESETS.load(Ordering::Relaxed), 1);
+    assert!(!alarm.is_armed());
+}
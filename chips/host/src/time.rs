@@ -0,0 +1,162 @@
+//! Simulated time.
+//!
+//! Time on the host does not pass on its own. The `Clock` only moves forward
+//! when the chip sleeps, to the next time an alarm fires, or when it is
+//! advanced explicitly. This keeps simulations deterministic and lets tests
+//! cover long periods of time instantly.
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Ticks};
+use kernel::ReturnCode;
+
+/// The simulated time since boot, in microseconds.
+pub struct Clock {
+    now_us: Cell<u64>,
+}
+
+impl Clock {
+    pub const fn new() -> Clock {
+        Clock {
+            now_us: Cell::new(0),
+        }
+    }
+
+    /// The current time, in microseconds since boot.
+    pub fn now_us(&self) -> u64 {
+        self.now_us.get()
+    }
+
+    /// Move the clock forward by `us` microseconds.
+    pub fn advance(&self, us: u64) {
+        self.now_us.set(self.now_us.get() + us);
+    }
+}
+
+/// An alarm on the simulated clock, ticking at 1 MHz.
+pub struct Alarm<'a> {
+    clock: &'a Clock,
+    reference: Cell<u32>,
+    dt: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> Alarm<'a> {
+    pub const fn new(clock: &'a Clock) -> Alarm<'a> {
+        Alarm {
+            clock,
+            reference: Cell::new(0),
+            dt: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// The time until the alarm fires, in microseconds, or `None` if the
+    /// alarm is not armed.
+    pub fn remaining_us(&self) -> Option<u64> {
+        if self.armed.get() {
+            let elapsed = time::Time::now(self)
+                .wrapping_sub(time::Ticks32::from(self.reference.get()))
+                .into_u32();
+            Some(self.dt.get().saturating_sub(elapsed) as u64)
+        } else {
+            None
+        }
+    }
+
+    pub fn has_pending_interrupt(&self) -> bool {
+        self.remaining_us() == Some(0)
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.has_pending_interrupt() {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+    }
+}
+
+impl time::Time for Alarm<'_> {
+    type Frequency = time::Freq1MHz;
+    type Ticks = time::Ticks32;
+
+    fn now(&self) -> Self::Ticks {
+        time::Ticks32::from(self.clock.now_us() as u32)
+    }
+}
+
+impl<'a> time::Alarm<'a> for Alarm<'a> {
+    fn set_alarm_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.reference.set(reference.into_u32());
+        self.dt.set(dt.into_u32());
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        time::Ticks32::from(self.reference.get()).wrapping_add(time::Ticks32::from(self.dt.get()))
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.armed.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        time::Ticks32::from(1)
+    }
+}
+
+/// The scheduler timer on the simulated clock.
+///
+/// Processes on the host do not use any time, so the timer only expires if
+/// the clock is advanced while a process runs.
+pub struct SchedulerTimer<'a> {
+    clock: &'a Clock,
+    expiration_us: Cell<Option<u64>>,
+}
+
+impl<'a> SchedulerTimer<'a> {
+    pub const fn new(clock: &'a Clock) -> SchedulerTimer<'a> {
+        SchedulerTimer {
+            clock,
+            expiration_us: Cell::new(None),
+        }
+    }
+}
+
+impl kernel::SchedulerTimer for SchedulerTimer<'_> {
+    fn start(&self, us: u32) {
+        self.expiration_us
+            .set(Some(self.clock.now_us() + us as u64));
+    }
+
+    fn reset(&self) {
+        self.expiration_us.set(None);
+    }
+
+    fn arm(&self) {}
+
+    fn disarm(&self) {}
+
+    fn get_remaining_us(&self) -> Option<u32> {
+        self.expiration_us.get().and_then(|expiration| {
+            let now = self.clock.now_us();
+            if now < expiration {
+                Some((expiration - now) as u32)
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -0,0 +1,261 @@
+//! UART connected to stdin and stdout.
+//!
+//! Transmitted bytes are written to stdout, or kept in a buffer for tests
+//! (see `Uart::new_captured()`). Received bytes come from stdin once
+//! `Uart::attach_stdin()` is called, or are passed in with
+//! `Uart::push_input()`.
+//!
+//! Transfers complete on the next interrupt, i.e. the next time the chip
+//! services pending interrupts.
+
+use core::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+
+pub struct Uart<'a> {
+    /// Transmitted bytes, or `None` if they are written to stdout.
+    output: RefCell<Option<Vec<u8>>>,
+    /// Bytes that were received but not read yet.
+    input: RefCell<VecDeque<u8>>,
+    stdin: RefCell<Option<mpsc::Receiver<Vec<u8>>>>,
+    tx_client: OptionalCell<&'a dyn uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_position: Cell<usize>,
+    rx_aborted: Cell<bool>,
+}
+
+impl<'a> Uart<'a> {
+    /// A UART that writes to stdout.
+    pub fn new() -> Uart<'a> {
+        Uart::with_output(None)
+    }
+
+    /// A UART that keeps transmitted bytes, to be read with
+    /// `take_output()`.
+    pub fn new_captured() -> Uart<'a> {
+        Uart::with_output(Some(Vec::new()))
+    }
+
+    fn with_output(output: Option<Vec<u8>>) -> Uart<'a> {
+        Uart {
+            output: RefCell::new(output),
+            input: RefCell::new(VecDeque::new()),
+            stdin: RefCell::new(None),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_position: Cell::new(0),
+            rx_aborted: Cell::new(false),
+        }
+    }
+
+    /// Receive the bytes read from stdin. Reading happens on a separate
+    /// thread so that the kernel does not block.
+    pub fn attach_stdin(&self) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 64];
+            loop {
+                match std::io::stdin().read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => {
+                        if sender.send(buffer[..len].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        self.stdin.replace(Some(receiver));
+    }
+
+    /// Whether the UART reads from stdin.
+    pub fn is_stdin_attached(&self) -> bool {
+        self.stdin.borrow().is_some()
+    }
+
+    /// Make bytes available to receive, as if they arrived on the wire.
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes);
+    }
+
+    /// Take the bytes transmitted so far. Always empty if the UART writes to
+    /// stdout.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.output
+            .borrow_mut()
+            .as_mut()
+            .map_or(Vec::new(), |output| output.split_off(0))
+    }
+
+    /// Wait up to `timeout` for bytes from stdin. Returns immediately if
+    /// stdin is not attached.
+    pub fn wait_for_input(&self, timeout: Duration) {
+        let received = self
+            .stdin
+            .borrow()
+            .as_ref()
+            .and_then(|stdin| stdin.recv_timeout(timeout).ok());
+        received.map(|bytes| self.push_input(&bytes));
+    }
+
+    fn poll_stdin(&self) {
+        let stdin = self.stdin.borrow();
+        if let Some(stdin) = stdin.as_ref() {
+            while let Ok(bytes) = stdin.try_recv() {
+                self.push_input(&bytes);
+            }
+        }
+    }
+
+    pub fn has_pending_interrupt(&self) -> bool {
+        self.poll_stdin();
+        self.tx_buffer.is_some()
+            || (self.rx_buffer.is_some()
+                && (self.rx_aborted.get() || !self.input.borrow().is_empty()))
+    }
+
+    pub fn handle_interrupt(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            let len = self.tx_len.get();
+            match self.output.borrow_mut().as_mut() {
+                Some(output) => output.extend_from_slice(&buffer[..len]),
+                None => {
+                    let mut stdout = std::io::stdout();
+                    let _ = stdout.write_all(&buffer[..len]);
+                    let _ = stdout.flush();
+                }
+            }
+            self.tx_client
+                .map(move |client| client.transmitted_buffer(buffer, len, ReturnCode::SUCCESS));
+        });
+
+        self.rx_buffer.take().map(|buffer| {
+            let mut position = self.rx_position.get();
+            {
+                let mut input = self.input.borrow_mut();
+                while position < self.rx_len.get() {
+                    match input.pop_front() {
+                        Some(byte) => buffer[position] = byte,
+                        None => break,
+                    }
+                    position += 1;
+                }
+            }
+            self.rx_position.set(position);
+
+            if position == self.rx_len.get() {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(buffer, position, ReturnCode::SUCCESS, uart::Error::None)
+                });
+            } else if self.rx_aborted.get() {
+                self.rx_aborted.set(false);
+                self.rx_client.map(move |client| {
+                    client.received_buffer(
+                        buffer,
+                        position,
+                        ReturnCode::ECANCEL,
+                        uart::Error::Aborted,
+                    )
+                });
+            } else {
+                self.rx_buffer.replace(buffer);
+            }
+        });
+    }
+}
+
+impl uart::Configure for Uart<'_> {
+    fn configure(&self, _params: uart::Parameters) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a> uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.tx_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(tx_buffer))
+        } else if tx_len == 0 || tx_len > tx_buffer.len() {
+            (ReturnCode::ESIZE, Some(tx_buffer))
+        } else {
+            self.tx_len.set(tx_len);
+            self.tx_buffer.replace(tx_buffer);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        if self.tx_buffer.is_some() {
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.rx_buffer.is_some() {
+            (ReturnCode::EBUSY, Some(rx_buffer))
+        } else if rx_len == 0 || rx_len > rx_buffer.len() {
+            (ReturnCode::ESIZE, Some(rx_buffer))
+        } else {
+            self.rx_len.set(rx_len);
+            self.rx_position.set(0);
+            self.rx_aborted.set(false);
+            self.rx_buffer.replace(rx_buffer);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        if self.rx_buffer.is_some() {
+            // The buffer is returned on the next interrupt.
+            self.rx_aborted.set(true);
+            ReturnCode::EBUSY
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl<'a> uart::UartData<'a> for Uart<'a> {}
+impl<'a> uart::Uart<'a> for Uart<'a> {}
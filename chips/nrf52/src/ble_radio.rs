@@ -535,6 +535,8 @@ pub struct Radio<'a> {
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
     buffer: TakeCell<'static, [u8]>,
+    access_address: Cell<u32>,
+    crc_init: Cell<u32>,
 }
 
 impl<'a> Radio<'a> {
@@ -545,6 +547,8 @@ impl<'a> Radio<'a> {
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            access_address: Cell::new(ble_advertising::ADVERTISING_ACCESS_ADDRESS),
+            crc_init: Cell::new(ble_advertising::ADVERTISING_CRC_INIT),
         }
     }
 
@@ -673,9 +677,9 @@ impl<'a> Radio<'a> {
         self.registers.intenclr.set(0xffffffff);
     }
 
-    fn replace_radio_buffer(&self, buf: &'static mut [u8]) -> &'static mut [u8] {
+    fn replace_radio_buffer(&self, buf: &'static mut [u8], len: usize) -> &'static mut [u8] {
         // set payload
-        for (i, c) in buf.as_ref().iter().enumerate() {
+        for (i, c) in buf[..len].iter().enumerate() {
             unsafe {
                 PAYLOAD[i] = *c;
             }
@@ -697,7 +701,7 @@ impl<'a> Radio<'a> {
         self.set_rx_address();
 
         self.ble_set_packet_config();
-        self.ble_set_advertising_access_address();
+        self.ble_set_access_address();
 
         self.ble_set_crc_config();
 
@@ -709,19 +713,19 @@ impl<'a> Radio<'a> {
         self.registers
             .crccnf
             .write(CrcConfiguration::LEN::THREE + CrcConfiguration::SKIPADDR::EXCLUDE);
-        self.registers
-            .crcinit
-            .set(nrf5x::constants::RADIO_CRCINIT_BLE);
+        self.registers.crcinit.set(self.crc_init.get());
         self.registers
             .crcpoly
             .set(nrf5x::constants::RADIO_CRCPOLY_BLE);
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
-    // Set access address to 0x8E89BED6
-    fn ble_set_advertising_access_address(&self) {
-        self.registers.prefix0.set(0x0000008e);
-        self.registers.base0.set(0x89bed600);
+    // The most significant byte of the access address is the prefix, and the
+    // three others are the base, e.g. 0x8E and 0x89BED6 for advertising
+    fn ble_set_access_address(&self) {
+        let access_address = self.access_address.get();
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
     }
 
     // Packet configuration
@@ -787,8 +791,8 @@ impl<'a> Radio<'a> {
 }
 
 impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
-    fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
-        let res = self.replace_radio_buffer(buf);
+    fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel) {
+        let res = self.replace_radio_buffer(buf, len);
         self.buffer.replace(res);
         self.ble_initialize(channel);
         self.tx();
@@ -801,6 +805,13 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
         self.enable_interrupts();
     }
 
+    fn cancel_receive(&self) -> ReturnCode {
+        self.disable_all_interrupts();
+        self.registers.task_disable.write(Task::ENABLE::SET);
+        self.radio_off();
+        ReturnCode::SUCCESS
+    }
+
     fn set_receive_client(&self, client: &'a dyn ble_advertising::RxClient) {
         self.rx_client.set(client);
     }
@@ -826,3 +837,11 @@ impl ble_advertising::BleConfig for Radio<'_> {
         }
     }
 }
+
+impl ble_advertising::BleConnectionConfig for Radio<'_> {
+    fn set_access_address(&self, access_address: u32, crc_init: u32) -> ReturnCode {
+        self.access_address.set(access_address);
+        self.crc_init.set(crc_init & 0xffffff);
+        ReturnCode::SUCCESS
+    }
+}
@@ -29,6 +29,7 @@ pub struct Stm32f4xxDefaultPeripherals<'a> {
     pub usart3: crate::usart::Usart<'a>,
     pub gpio_ports: crate::gpio::GpioPorts<'a>,
     pub fsmc: crate::fsmc::Fsmc<'a>,
+    pub rtc: crate::rtc::Rtc<'a>,
 }
 
 impl<'a> Stm32f4xxDefaultPeripherals<'a> {
@@ -64,6 +65,7 @@ impl<'a> Stm32f4xxDefaultPeripherals<'a> {
                 ],
                 rcc,
             ),
+            rtc: crate::rtc::Rtc::new(rcc, exti),
         }
     }
 
@@ -114,6 +116,8 @@ impl<'a> InterruptService<DeferredCallTask> for Stm32f4xxDefaultPeripherals<'a>
 
             nvic::TIM2 => self.tim2.handle_interrupt(),
 
+            nvic::RTC_Alarm => self.rtc.handle_alarm_interrupt(),
+
             _ => return false,
         }
         true
@@ -122,6 +126,7 @@ impl<'a> InterruptService<DeferredCallTask> for Stm32f4xxDefaultPeripherals<'a>
     unsafe fn service_deferred_call(&self, task: DeferredCallTask) -> bool {
         match task {
             DeferredCallTask::Fsmc => self.fsmc.handle_interrupt(),
+            DeferredCallTask::Rtc => self.rtc.handle_deferred_call(),
         }
         true
     }
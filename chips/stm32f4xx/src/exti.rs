@@ -608,6 +608,17 @@ impl<'a> Exti<'a> {
         }
     }
 
+    /// Routes the RTC alarm, which is connected to line 17 rather than to a
+    /// GPIO pin, to the `RTC_Alarm` interrupt.
+    pub fn enable_rtc_alarm_line(&self) {
+        self.registers.rtsr.modify(RTSR::TR17::SET);
+        self.registers.imr.modify(IMR::MR17::SET);
+    }
+
+    pub fn clear_rtc_alarm_pending(&self) {
+        self.registers.pr.write(PR::PR17::SET);
+    }
+
     pub fn handle_interrupt(&self) {
         let mut exti_pr: u32 = 0;
 
@@ -21,6 +21,7 @@ pub mod fsmc;
 pub mod gpio;
 pub mod i2c;
 pub mod rcc;
+pub mod rtc;
 pub mod spi;
 pub mod syscfg;
 pub mod tim2;
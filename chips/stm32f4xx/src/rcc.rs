@@ -609,7 +609,12 @@ register_bitfields![u32,
         /// RTC clock enable
         RTCEN OFFSET(15) NUMBITS(1) [],
         /// RTC clock source selection
-        RTCSEL OFFSET(8) NUMBITS(2) [],
+        RTCSEL OFFSET(8) NUMBITS(2) [
+            NoClock = 0,
+            LSE = 1,
+            LSI = 2,
+            HSE = 3
+        ],
         /// External low-speed oscillator mode
         LSEMOD OFFSET(3) NUMBITS(1) [],
         /// External low-speed oscillator bypass
@@ -936,6 +941,45 @@ impl Rcc {
         self.registers.ahb3enr.modify(AHB3ENR::FMCEN::CLEAR)
     }
 
+    // PWR clock
+
+    fn is_enabled_pwr_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::PWREN)
+    }
+
+    fn enable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::SET)
+    }
+
+    fn disable_pwr_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::PWREN::CLEAR)
+    }
+
+    // RTC clock
+    //
+    // The RTC clock is in the backup domain, so writes only take effect once
+    // the backup domain write protection is disabled in the PWR peripheral.
+
+    fn is_enabled_rtc_clock(&self) -> bool {
+        self.registers.bdcr.is_set(BDCR::RTCEN)
+    }
+
+    fn enable_rtc_clock(&self) {
+        // The RTC runs from the LSI oscillator, which stops on reset
+        self.registers.csr.modify(CSR::LSION::SET);
+        while !self.registers.csr.is_set(CSR::LSIRDY) {}
+        // The clock source can only be changed by resetting the backup
+        // domain, which would reset the calendar
+        if self.registers.bdcr.read(BDCR::RTCSEL) == 0 {
+            self.registers.bdcr.modify(BDCR::RTCSEL::LSI);
+        }
+        self.registers.bdcr.modify(BDCR::RTCEN::SET)
+    }
+
+    fn disable_rtc_clock(&self) {
+        self.registers.bdcr.modify(BDCR::RTCEN::CLEAR)
+    }
+
     // USART2 clock
 
     fn is_enabled_usart2_clock(&self) -> bool {
@@ -1027,6 +1071,7 @@ pub enum PeripheralClockType {
     AHB3(HCLK3),
     APB1(PCLK1),
     APB2(PCLK2),
+    RTC,
 }
 
 /// Peripherals clocked by HCLK1
@@ -1060,6 +1105,7 @@ pub enum PCLK1 {
     USART3,
     SPI3,
     I2C1,
+    PWR,
 }
 
 /// Peripherals clocked by PCLK2
@@ -1105,11 +1151,13 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::USART3 => self.rcc.is_enabled_usart3_clock(),
                 PCLK1::I2C1 => self.rcc.is_enabled_i2c1_clock(),
                 PCLK1::SPI3 => self.rcc.is_enabled_spi3_clock(),
+                PCLK1::PWR => self.rcc.is_enabled_pwr_clock(),
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => self.rcc.is_enabled_adc1_clock(),
                 PCLK2::SYSCFG => self.rcc.is_enabled_syscfg_clock(),
             },
+            PeripheralClockType::RTC => self.rcc.is_enabled_rtc_clock(),
         }
     }
 
@@ -1171,6 +1219,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::SPI3 => {
                     self.rcc.enable_spi3_clock();
                 }
+                PCLK1::PWR => {
+                    self.rcc.enable_pwr_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => {
@@ -1180,6 +1231,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                     self.rcc.enable_syscfg_clock();
                 }
             },
+            PeripheralClockType::RTC => {
+                self.rcc.enable_rtc_clock();
+            }
         }
     }
 
@@ -1241,6 +1295,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::SPI3 => {
                     self.rcc.disable_spi3_clock();
                 }
+                PCLK1::PWR => {
+                    self.rcc.disable_pwr_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => {
@@ -1250,6 +1307,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                     self.rcc.disable_syscfg_clock();
                 }
             },
+            PeripheralClockType::RTC => {
+                self.rcc.disable_rtc_clock();
+            }
         }
     }
 }
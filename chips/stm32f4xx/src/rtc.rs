@@ -0,0 +1,428 @@
+//! Real-time clock (RTC) peripheral.
+//!
+//! The RTC keeps the calendar in the backup domain, so it keeps running
+//! across resets. It is clocked from the LSI oscillator, which is not very
+//! accurate, but is present on all boards. The calendar only covers the
+//! years 2000 to 2099.
+//!
+//! Alarm A implements the alarm of the `DateTime` HIL. As it only matches
+//! the day of the month and the time, the year and month of the alarm are
+//! checked when it fires, and it stays armed until they match.
+
+use core::cell::Cell;
+use core::convert::TryFrom;
+use kernel::common::cells::OptionalCell;
+use kernel::common::deferred_call::DeferredCall;
+use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues, DayOfWeek, Month};
+use kernel::ClockInterface;
+use kernel::ErrorCode;
+
+use crate::deferred_calls::DeferredCallTask;
+use crate::exti;
+use crate::rcc;
+
+/// Real-time clock
+#[repr(C)]
+struct RtcRegisters {
+    /// time register
+    tr: ReadWrite<u32, TR::Register>,
+    /// date register
+    dr: ReadWrite<u32, DR::Register>,
+    /// control register
+    cr: ReadWrite<u32, CR::Register>,
+    /// initialization and status register
+    isr: ReadWrite<u32, ISR::Register>,
+    /// prescaler register
+    prer: ReadWrite<u32, PRER::Register>,
+    /// wakeup timer register
+    wutr: ReadWrite<u32>,
+    /// calibration register
+    calibr: ReadWrite<u32>,
+    /// alarm A register
+    alrmar: ReadWrite<u32, ALRMAR::Register>,
+    /// alarm B register
+    alrmbr: ReadWrite<u32, ALRMAR::Register>,
+    /// write protection register
+    wpr: WriteOnly<u32, WPR::Register>,
+}
+
+/// Power controller, which holds the write protection of the backup domain
+#[repr(C)]
+struct PwrRegisters {
+    /// power control register
+    cr: ReadWrite<u32, PWR_CR::Register>,
+    /// power control/status register
+    csr: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    TR [
+        /// AM/PM notation
+        PM OFFSET(22) NUMBITS(1) [],
+        /// Hour tens in BCD format
+        HT OFFSET(20) NUMBITS(2) [],
+        /// Hour units in BCD format
+        HU OFFSET(16) NUMBITS(4) [],
+        /// Minute tens in BCD format
+        MNT OFFSET(12) NUMBITS(3) [],
+        /// Minute units in BCD format
+        MNU OFFSET(8) NUMBITS(4) [],
+        /// Second tens in BCD format
+        ST OFFSET(4) NUMBITS(3) [],
+        /// Second units in BCD format
+        SU OFFSET(0) NUMBITS(4) []
+    ],
+    DR [
+        /// Year tens in BCD format
+        YT OFFSET(20) NUMBITS(4) [],
+        /// Year units in BCD format
+        YU OFFSET(16) NUMBITS(4) [],
+        /// Week day units, 1 is Monday and 7 is Sunday
+        WDU OFFSET(13) NUMBITS(3) [],
+        /// Month tens in BCD format
+        MT OFFSET(12) NUMBITS(1) [],
+        /// Month units in BCD format
+        MU OFFSET(8) NUMBITS(4) [],
+        /// Date tens in BCD format
+        DT OFFSET(4) NUMBITS(2) [],
+        /// Date units in BCD format
+        DU OFFSET(0) NUMBITS(4) []
+    ],
+    CR [
+        /// Alarm A interrupt enable
+        ALRAIE OFFSET(12) NUMBITS(1) [],
+        /// Alarm A enable
+        ALRAE OFFSET(8) NUMBITS(1) [],
+        /// Hour format, 24 hours when cleared
+        FMT OFFSET(6) NUMBITS(1) []
+    ],
+    ISR [
+        /// Alarm A flag
+        ALRAF OFFSET(8) NUMBITS(1) [],
+        /// Initialization mode
+        INIT OFFSET(7) NUMBITS(1) [],
+        /// Initialization flag
+        INITF OFFSET(6) NUMBITS(1) [],
+        /// Registers synchronization flag
+        RSF OFFSET(5) NUMBITS(1) [],
+        /// Initialization status flag
+        INITS OFFSET(4) NUMBITS(1) [],
+        /// Alarm A write flag
+        ALRAWF OFFSET(0) NUMBITS(1) []
+    ],
+    PRER [
+        /// Asynchronous prescaler factor
+        PREDIV_A OFFSET(16) NUMBITS(7) [],
+        /// Synchronous prescaler factor
+        PREDIV_S OFFSET(0) NUMBITS(15) []
+    ],
+    ALRMAR [
+        /// Alarm date mask
+        MSK4 OFFSET(31) NUMBITS(1) [],
+        /// Week day selection
+        WDSEL OFFSET(30) NUMBITS(1) [],
+        /// Date tens in BCD format
+        DT OFFSET(28) NUMBITS(2) [],
+        /// Date units or day in BCD format
+        DU OFFSET(24) NUMBITS(4) [],
+        /// Alarm hours mask
+        MSK3 OFFSET(23) NUMBITS(1) [],
+        /// AM/PM notation
+        PM OFFSET(22) NUMBITS(1) [],
+        /// Hour tens in BCD format
+        HT OFFSET(20) NUMBITS(2) [],
+        /// Hour units in BCD format
+        HU OFFSET(16) NUMBITS(4) [],
+        /// Alarm minutes mask
+        MSK2 OFFSET(15) NUMBITS(1) [],
+        /// Minute tens in BCD format
+        MNT OFFSET(12) NUMBITS(3) [],
+        /// Minute units in BCD format
+        MNU OFFSET(8) NUMBITS(4) [],
+        /// Alarm seconds mask
+        MSK1 OFFSET(7) NUMBITS(1) [],
+        /// Second tens in BCD format
+        ST OFFSET(4) NUMBITS(3) [],
+        /// Second units in BCD format
+        SU OFFSET(0) NUMBITS(4) []
+    ],
+    WPR [
+        /// Write protection key
+        KEY OFFSET(0) NUMBITS(8) []
+    ],
+    PWR_CR [
+        /// Disable backup domain write protection
+        DBP OFFSET(8) NUMBITS(1) []
+    ]
+];
+
+const RTC_BASE: StaticRef<RtcRegisters> =
+    unsafe { StaticRef::new(0x4000_2800 as *const RtcRegisters) };
+
+const PWR_BASE: StaticRef<PwrRegisters> =
+    unsafe { StaticRef::new(0x4000_7000 as *const PwrRegisters) };
+
+/// This mechanism allows us to schedule "interrupts" even if the hardware
+/// does not support them.
+static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
+    unsafe { DeferredCall::new(DeferredCallTask::Rtc) };
+
+/// Divide the 32kHz LSI clock by 128 and then by 250 to get 1Hz.
+const PREDIV_A: u32 = 127;
+const PREDIV_S: u32 = 249;
+
+const FIRST_YEAR: u16 = 2000;
+const LAST_YEAR: u16 = 2099;
+
+/// Splits `value` into its tens and units.
+fn to_bcd(value: u8) -> (u32, u32) {
+    ((value / 10) as u32, (value % 10) as u32)
+}
+
+fn from_bcd(tens: u32, units: u32) -> u8 {
+    (tens * 10 + units) as u8
+}
+
+pub struct Rtc<'a> {
+    registers: StaticRef<RtcRegisters>,
+    pwr_registers: StaticRef<PwrRegisters>,
+    clock: rcc::PeripheralClock<'a>,
+    pwr_clock: rcc::PeripheralClock<'a>,
+    exti: &'a exti::Exti<'a>,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+    get_pending: Cell<bool>,
+    set_pending: Cell<bool>,
+    /// The year and month of the alarm, if it is armed.
+    alarm: OptionalCell<(u16, Month)>,
+}
+
+impl<'a> Rtc<'a> {
+    pub const fn new(rcc: &'a rcc::Rcc, exti: &'a exti::Exti<'a>) -> Self {
+        Self {
+            registers: RTC_BASE,
+            pwr_registers: PWR_BASE,
+            clock: rcc::PeripheralClock::new(rcc::PeripheralClockType::RTC, rcc),
+            pwr_clock: rcc::PeripheralClock::new(
+                rcc::PeripheralClockType::APB1(rcc::PCLK1::PWR),
+                rcc,
+            ),
+            exti,
+            client: OptionalCell::empty(),
+            get_pending: Cell::new(false),
+            set_pending: Cell::new(false),
+            alarm: OptionalCell::empty(),
+        }
+    }
+
+    pub fn is_enabled_clock(&self) -> bool {
+        self.clock.is_enabled()
+    }
+
+    /// Enables the RTC clock, and starts the calendar at 2000-01-01 if it is
+    /// not running already, e.g. after a power loss without a backup
+    /// battery.
+    pub fn enable_clock(&self) {
+        self.pwr_clock.enable();
+        self.pwr_registers.cr.modify(PWR_CR::DBP::SET);
+        self.clock.enable();
+
+        if !self.registers.isr.is_set(ISR::INITS) {
+            self.unlock();
+            self.enter_init_mode();
+            self.registers
+                .prer
+                .write(PRER::PREDIV_S.val(PREDIV_S) + PRER::PREDIV_A.val(PREDIV_A));
+            self.registers.cr.modify(CR::FMT::CLEAR);
+            self.write_calendar(&DateTimeValues {
+                year: FIRST_YEAR,
+                month: Month::January,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                seconds: 0,
+            });
+            self.exit_init_mode();
+            self.lock();
+        }
+    }
+
+    pub fn disable_clock(&self) {
+        self.clock.disable();
+    }
+
+    /// Disables the write protection of the RTC registers.
+    fn unlock(&self) {
+        self.registers.wpr.write(WPR::KEY.val(0xCA));
+        self.registers.wpr.write(WPR::KEY.val(0x53));
+    }
+
+    fn lock(&self) {
+        self.registers.wpr.write(WPR::KEY.val(0xFF));
+    }
+
+    /// Stops the calendar so that it can be written. This takes up to two
+    /// RTC clock cycles.
+    fn enter_init_mode(&self) {
+        self.registers.isr.modify(ISR::INIT::SET);
+        while !self.registers.isr.is_set(ISR::INITF) {}
+    }
+
+    fn exit_init_mode(&self) {
+        self.registers.isr.modify(ISR::INIT::CLEAR);
+        // The shadow registers are stale until the next synchronization
+        self.registers.isr.modify(ISR::RSF::CLEAR);
+    }
+
+    fn write_calendar(&self, date_time: &DateTimeValues) {
+        let (hour_tens, hour_units) = to_bcd(date_time.hour);
+        let (minute_tens, minute_units) = to_bcd(date_time.minute);
+        let (seconds_tens, seconds_units) = to_bcd(date_time.seconds);
+        self.registers.tr.write(
+            TR::HT.val(hour_tens)
+                + TR::HU.val(hour_units)
+                + TR::MNT.val(minute_tens)
+                + TR::MNU.val(minute_units)
+                + TR::ST.val(seconds_tens)
+                + TR::SU.val(seconds_units),
+        );
+
+        let (year_tens, year_units) = to_bcd((date_time.year - FIRST_YEAR) as u8);
+        let (month_tens, month_units) = to_bcd(date_time.month as u8);
+        let (day_tens, day_units) = to_bcd(date_time.day);
+        let day_of_week = match date_time.day_of_week() {
+            DayOfWeek::Sunday => 7,
+            day_of_week => day_of_week as u32,
+        };
+        self.registers.dr.write(
+            DR::YT.val(year_tens)
+                + DR::YU.val(year_units)
+                + DR::WDU.val(day_of_week)
+                + DR::MT.val(month_tens)
+                + DR::MU.val(month_units)
+                + DR::DT.val(day_tens)
+                + DR::DU.val(day_units),
+        );
+    }
+
+    fn read_calendar(&self) -> Result<DateTimeValues, ErrorCode> {
+        // Wait for the shadow registers to be synchronized with the
+        // calendar, which takes up to two RTC clock cycles after it was set
+        while !self.registers.isr.is_set(ISR::RSF) {}
+        // Reading the time locks the date until it is read
+        let tr = self.registers.tr.extract();
+        let dr = self.registers.dr.extract();
+        Ok(DateTimeValues {
+            year: FIRST_YEAR + from_bcd(dr.read(DR::YT), dr.read(DR::YU)) as u16,
+            month: Month::try_from(from_bcd(dr.read(DR::MT), dr.read(DR::MU)))
+                .map_err(|_| ErrorCode::FAIL)?,
+            day: from_bcd(dr.read(DR::DT), dr.read(DR::DU)),
+            hour: from_bcd(tr.read(TR::HT), tr.read(TR::HU)),
+            minute: from_bcd(tr.read(TR::MNT), tr.read(TR::MNU)),
+            seconds: from_bcd(tr.read(TR::ST), tr.read(TR::SU)),
+        })
+    }
+
+    fn is_in_range(date_time: &DateTimeValues) -> bool {
+        date_time.is_valid() && date_time.year >= FIRST_YEAR && date_time.year <= LAST_YEAR
+    }
+
+    pub fn handle_alarm_interrupt(&self) {
+        self.registers.isr.modify(ISR::ALRAF::CLEAR);
+        self.exti.clear_rtc_alarm_pending();
+
+        let fired = self.alarm.map_or(false, |&mut (year, month)| {
+            self.read_calendar()
+                .map_or(false, |now| now.year == year && now.month == month)
+        });
+        if fired {
+            let _ = self.disable_alarm();
+            self.client.map(|client| client.alarm_fired());
+        }
+    }
+
+    pub fn handle_deferred_call(&self) {
+        if self.set_pending.take() {
+            self.client.map(|client| client.set_date_time_done(Ok(())));
+        }
+        if self.get_pending.take() {
+            let date_time = self.read_calendar();
+            self.client
+                .map(|client| client.get_date_time_done(date_time));
+        }
+    }
+}
+
+impl<'a> DateTime<'a> for Rtc<'a> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        if self.get_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.get_pending.set(true);
+        DEFERRED_CALL.set();
+        Ok(())
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        if self.set_pending.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !Self::is_in_range(&date_time) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.unlock();
+        self.enter_init_mode();
+        self.write_calendar(&date_time);
+        self.exit_init_mode();
+        self.lock();
+        self.set_pending.set(true);
+        DEFERRED_CALL.set();
+        Ok(())
+    }
+
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        if !Self::is_in_range(&date_time) {
+            return Err(ErrorCode::INVAL);
+        }
+        let (day_tens, day_units) = to_bcd(date_time.day);
+        let (hour_tens, hour_units) = to_bcd(date_time.hour);
+        let (minute_tens, minute_units) = to_bcd(date_time.minute);
+        let (seconds_tens, seconds_units) = to_bcd(date_time.seconds);
+
+        self.unlock();
+        self.registers.cr.modify(CR::ALRAE::CLEAR);
+        while !self.registers.isr.is_set(ISR::ALRAWF) {}
+        self.registers.alrmar.write(
+            ALRMAR::DT.val(day_tens)
+                + ALRMAR::DU.val(day_units)
+                + ALRMAR::HT.val(hour_tens)
+                + ALRMAR::HU.val(hour_units)
+                + ALRMAR::MNT.val(minute_tens)
+                + ALRMAR::MNU.val(minute_units)
+                + ALRMAR::ST.val(seconds_tens)
+                + ALRMAR::SU.val(seconds_units),
+        );
+        self.registers.isr.modify(ISR::ALRAF::CLEAR);
+        self.registers.cr.modify(CR::ALRAIE::SET + CR::ALRAE::SET);
+        self.lock();
+
+        self.alarm.set((date_time.year, date_time.month));
+        self.exti.enable_rtc_alarm_line();
+        Ok(())
+    }
+
+    fn disable_alarm(&self) -> Result<(), ErrorCode> {
+        self.unlock();
+        self.registers
+            .cr
+            .modify(CR::ALRAIE::CLEAR + CR::ALRAE::CLEAR);
+        self.lock();
+        self.alarm.clear();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
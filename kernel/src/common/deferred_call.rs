@@ -51,6 +51,11 @@ pub fn has_tasks() -> bool {
     DEFERRED_CALL.load_relaxed() != 0
 }
 
+/// How many `DeferredCall`s are pending?
+pub(crate) fn number_pending() -> usize {
+    DEFERRED_CALL.load_relaxed().count_ones() as usize
+}
+
 /// Represents a way to generate an asynchronous call without a hardware
 /// interrupt. Supports up to 32 possible deferrable tasks.
 pub struct DeferredCall<T>(T);
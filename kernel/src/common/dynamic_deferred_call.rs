@@ -156,6 +156,24 @@ impl DynamicDeferredCall {
         DYNAMIC_DEFERRED_CALL.map(|ddc| ddc.has_pending())
     }
 
+    /// Get the number of clients registered with the globally registered
+    /// instance, the number of clients it has room for, and the number of
+    /// its clients with a pending deferred call
+    ///
+    /// Returns `None` if no global instance has been registered.
+    pub(crate) unsafe fn global_instance_usage() -> Option<(usize, usize, usize)> {
+        DYNAMIC_DEFERRED_CALL.map(|ddc| {
+            (
+                ddc.handle_counter.get(),
+                ddc.client_states.len(),
+                ddc.client_states
+                    .iter()
+                    .filter(|client_state| client_state.scheduled.get())
+                    .count(),
+            )
+        })
+    }
+
     /// Schedule a deferred call to be called
     ///
     /// The handle addresses the client that will be called.
@@ -214,8 +232,9 @@ impl DynamicDeferredCall {
     /// Call all registered and to-be-scheduled deferred calls
     ///
     /// It may be called without holding the `DynamicDeferredCall` reference through
-    /// `call_global_instance`.
-    pub(self) fn call(&self) {
+    /// `call_global_instance`. Calling an instance directly is useful for
+    /// instances that are not registered globally, e.g. in tests.
+    pub fn call(&self) {
         self.call_while(|| true)
     }
 
@@ -59,7 +59,7 @@ use crate::common::cells::{MapCell, TakeCell};
 use crate::common::queue::Queue;
 use crate::common::ring_buffer::RingBuffer;
 use crate::hil;
-use crate::process::ProcessType;
+use crate::process::ProcessSlot;
 use crate::Chip;
 use crate::ReturnCode;
 
@@ -96,7 +96,7 @@ pub unsafe fn panic<L: hil::led::Led, W: Write + IoWrite, C: Chip>(
     writer: &mut W,
     panic_info: &PanicInfo,
     nop: &dyn Fn(),
-    processes: &'static [Option<&'static dyn ProcessType>],
+    processes: &'static [ProcessSlot],
     chip: &'static Option<&'static C>,
 ) -> ! {
     panic_begin(nop);
@@ -148,14 +148,11 @@ pub unsafe fn panic_cpu_state<W: Write, C: Chip>(
 /// More detailed prints about all processes.
 ///
 /// **NOTE:** The supplied `writer` must be synchronous.
-pub unsafe fn panic_process_info<W: Write>(
-    procs: &'static [Option<&'static dyn ProcessType>],
-    writer: &mut W,
-) {
+pub unsafe fn panic_process_info<W: Write>(procs: &'static [ProcessSlot], writer: &mut W) {
     // print data about each process
     let _ = writer.write_fmt(format_args!("\r\n---| App Status |---\r\n"));
     for idx in 0..procs.len() {
-        procs[idx].as_ref().map(|process| {
+        procs[idx].get().map(|process| {
             process.print_full_process(writer);
         });
     }
@@ -5,7 +5,7 @@ use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
 use core::ptr::{slice_from_raw_parts_mut, write, NonNull};
 
-use crate::process::{Error, ProcessType};
+use crate::process::{Error, ProcessRef, ProcessSlot};
 use crate::sched::Kernel;
 use crate::upcall::AppId;
 
@@ -43,14 +43,14 @@ impl<'a, T: 'a + ?Sized> DerefMut for Borrowed<'a, T> {
 }
 
 pub struct AppliedGrant<'a, T: 'a> {
-    process: &'a dyn ProcessType,
+    process: ProcessRef<'a>,
     grant_num: usize,
     grant: &'a mut T,
     _phantom: PhantomData<T>,
 }
 
 impl<'a, T: Default> AppliedGrant<'a, T> {
-    fn get_or_allocate(grant: &Grant<T>, process: &'a dyn ProcessType) -> Result<Self, Error> {
+    fn get_or_allocate(grant: &Grant<T>, process: ProcessRef<'a>) -> Result<Self, Error> {
         // Here is an example of how the grants are laid out in a
         // process's memory:
         //
@@ -160,10 +160,7 @@ impl<'a, T: Default> AppliedGrant<'a, T> {
     /// On `Err()`, returns `Err(None)` if the grant has not been allocated
     /// for this process. Returns `Err(Some(Error))` with an appropriate
     /// error otherwise.
-    fn get_if_allocated(
-        grant: &Grant<T>,
-        process: &'a dyn ProcessType,
-    ) -> Result<Self, Option<Error>> {
+    fn get_if_allocated(grant: &Grant<T>, process: ProcessRef<'a>) -> Result<Self, Option<Error>> {
         process
             .get_grant_ptr(grant.grant_num)
             .map_or(Err(None), |grant_ptr| {
@@ -371,12 +368,9 @@ impl<T: Default> Grant<T> {
     where
         F: FnOnce(&mut Borrowed<T>, &mut Allocator) -> R,
     {
-        appid
-            .kernel
-            .process_map_or(Err(Error::NoSuchApp), appid, |process| {
-                let ag = AppliedGrant::get_or_allocate(self, process)?;
-                Ok(ag.enter(fun))
-            })
+        let process = appid.kernel.get_process(appid).ok_or(Error::NoSuchApp)?;
+        let ag = AppliedGrant::get_or_allocate(self, process)?;
+        Ok(ag.enter(fun))
     }
 
     /// Call a function on every active grant region.
@@ -418,8 +412,8 @@ impl<T: Default> Grant<T> {
 pub struct Iter<'a, T: 'a + Default> {
     grant: &'a Grant<T>,
     subiter: core::iter::FilterMap<
-        core::slice::Iter<'a, Option<&'static dyn ProcessType>>,
-        fn(&Option<&'static dyn ProcessType>) -> Option<&'static dyn ProcessType>,
+        core::slice::Iter<'a, ProcessSlot>,
+        fn(&ProcessSlot) -> Option<ProcessRef>,
     >,
     /// Whether this iterator must visit every grant region, or if
     /// it should skip grant regions which are already entered.
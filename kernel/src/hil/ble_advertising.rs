@@ -52,6 +52,11 @@ use crate::returncode::ReturnCode;
 pub trait BleAdvertisementDriver<'a> {
     fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel);
     fn receive_advertisement(&self, channel: RadioChannel);
+
+    /// Stop a pending reception, e.g. at the end of a receive window. No
+    /// `receive_event` is issued for the stopped reception.
+    fn cancel_receive(&self) -> ReturnCode;
+
     fn set_receive_client(&self, client: &'a dyn RxClient);
     fn set_transmit_client(&self, client: &'a dyn TxClient);
 }
@@ -60,6 +65,24 @@ pub trait BleConfig {
     fn set_tx_power(&self, power: u8) -> ReturnCode;
 }
 
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
+pub const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89BED6;
+// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3.1.1 CRC Generation
+pub const ADVERTISING_CRC_INIT: u32 = 0x555555;
+
+/// Radio configuration for link layer connections.
+///
+/// Packets on the advertising channels use `ADVERTISING_ACCESS_ADDRESS` and
+/// `ADVERTISING_CRC_INIT`. The packets of a connection use the access address
+/// and CRC initialization value chosen by the central in its connection
+/// request, and are sent and received with `transmit_advertisement()` and
+/// `receive_advertisement()` on the data channels.
+pub trait BleConnectionConfig {
+    /// Use `access_address` and the 24-bit `crc_init` for the following
+    /// transmissions and receptions.
+    fn set_access_address(&self, access_address: u32, crc_init: u32) -> ReturnCode;
+}
+
 pub trait RxClient {
     fn receive_event(&self, buf: &'static mut [u8], len: u8, result: ReturnCode);
 }
@@ -158,4 +181,52 @@ impl RadioChannel {
             RadioChannel::AdvertisingChannel39 => 39,
         }
     }
+
+    /// The channel with the channel index `index`, i.e. the inverse of
+    /// `get_channel_index()`.
+    pub fn from_channel_index(index: u32) -> Option<RadioChannel> {
+        match index {
+            0 => Some(RadioChannel::DataChannel0),
+            1 => Some(RadioChannel::DataChannel1),
+            2 => Some(RadioChannel::DataChannel2),
+            3 => Some(RadioChannel::DataChannel3),
+            4 => Some(RadioChannel::DataChannel4),
+            5 => Some(RadioChannel::DataChannel5),
+            6 => Some(RadioChannel::DataChannel6),
+            7 => Some(RadioChannel::DataChannel7),
+            8 => Some(RadioChannel::DataChannel8),
+            9 => Some(RadioChannel::DataChannel9),
+            10 => Some(RadioChannel::DataChannel10),
+            11 => Some(RadioChannel::DataChannel11),
+            12 => Some(RadioChannel::DataChannel12),
+            13 => Some(RadioChannel::DataChannel13),
+            14 => Some(RadioChannel::DataChannel14),
+            15 => Some(RadioChannel::DataChannel15),
+            16 => Some(RadioChannel::DataChannel16),
+            17 => Some(RadioChannel::DataChannel17),
+            18 => Some(RadioChannel::DataChannel18),
+            19 => Some(RadioChannel::DataChannel19),
+            20 => Some(RadioChannel::DataChannel20),
+            21 => Some(RadioChannel::DataChannel21),
+            22 => Some(RadioChannel::DataChannel22),
+            23 => Some(RadioChannel::DataChannel23),
+            24 => Some(RadioChannel::DataChannel24),
+            25 => Some(RadioChannel::DataChannel25),
+            26 => Some(RadioChannel::DataChannel26),
+            27 => Some(RadioChannel::DataChannel27),
+            28 => Some(RadioChannel::DataChannel28),
+            29 => Some(RadioChannel::DataChannel29),
+            30 => Some(RadioChannel::DataChannel30),
+            31 => Some(RadioChannel::DataChannel31),
+            32 => Some(RadioChannel::DataChannel32),
+            33 => Some(RadioChannel::DataChannel33),
+            34 => Some(RadioChannel::DataChannel34),
+            35 => Some(RadioChannel::DataChannel35),
+            36 => Some(RadioChannel::DataChannel36),
+            37 => Some(RadioChannel::AdvertisingChannel37),
+            38 => Some(RadioChannel::AdvertisingChannel38),
+            39 => Some(RadioChannel::AdvertisingChannel39),
+            _ => None,
+        }
+    }
 }
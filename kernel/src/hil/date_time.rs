@@ -0,0 +1,222 @@
+//! Interface for real-time clocks, which keep the calendar date and the
+//! time of day.
+//!
+//! Unlike the counters of `hil::time`, a real-time clock counts wall-clock
+//! time: it can be set to a date and time, and it can raise an alarm at a
+//! given date and time. The implementation decides where the time comes
+//! from, e.g. an RTC peripheral running from a 32kHz oscillator or a
+//! software clock on top of an `Alarm`.
+//!
+//! Dates are in the proleptic Gregorian calendar and times have a resolution
+//! of one second. The clock has no notion of time zones.
+
+use crate::ErrorCode;
+use core::convert::TryFrom;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Month {
+    January = 1,
+    February = 2,
+    March = 3,
+    April = 4,
+    May = 5,
+    June = 6,
+    July = 7,
+    August = 8,
+    September = 9,
+    October = 10,
+    November = 11,
+    December = 12,
+}
+
+impl TryFrom<u8> for Month {
+    type Error = ErrorCode;
+
+    fn try_from(month: u8) -> Result<Month, ErrorCode> {
+        match month {
+            1 => Ok(Month::January),
+            2 => Ok(Month::February),
+            3 => Ok(Month::March),
+            4 => Ok(Month::April),
+            5 => Ok(Month::May),
+            6 => Ok(Month::June),
+            7 => Ok(Month::July),
+            8 => Ok(Month::August),
+            9 => Ok(Month::September),
+            10 => Ok(Month::October),
+            11 => Ok(Month::November),
+            12 => Ok(Month::December),
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DayOfWeek {
+    Sunday = 0,
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+}
+
+/// The year of the Unix epoch, the earliest year a `DateTimeValues` can
+/// represent.
+pub const EPOCH_YEAR: u16 = 1970;
+
+/// A calendar date and time of day.
+///
+/// The day of the week is not stored, as it follows from the date: see
+/// `day_of_week()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTimeValues {
+    pub year: u16,
+    pub month: Month,
+    /// The day of the month, starting at 1.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub seconds: u8,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: Month) -> u8 {
+    match month {
+        Month::February if is_leap_year(year) => 29,
+        Month::February => 28,
+        Month::April | Month::June | Month::September | Month::November => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days between 1970-01-01 and the given date. The month
+/// is shifted to start in March so that the leap day ends the year.
+fn days_from_civil(year: u16, month: Month, day: u8) -> u32 {
+    let month = month as u32;
+    let year = year as u32 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day as u32 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+impl DateTimeValues {
+    /// Whether all fields are in range, including the day for the month and
+    /// year.
+    pub fn is_valid(&self) -> bool {
+        self.year >= EPOCH_YEAR
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.seconds < 60
+    }
+
+    /// The day of the week of the date. Only meaningful if `is_valid()`.
+    pub fn day_of_week(&self) -> DayOfWeek {
+        // 1970-01-01 was a Thursday
+        match (days_from_civil(self.year, self.month, self.day) + 4) % 7 {
+            0 => DayOfWeek::Sunday,
+            1 => DayOfWeek::Monday,
+            2 => DayOfWeek::Tuesday,
+            3 => DayOfWeek::Wednesday,
+            4 => DayOfWeek::Thursday,
+            5 => DayOfWeek::Friday,
+            _ => DayOfWeek::Saturday,
+        }
+    }
+
+    /// The number of seconds since 1970-01-01 00:00:00, ignoring leap
+    /// seconds. Only meaningful if `is_valid()`.
+    pub fn seconds_since_epoch(&self) -> u64 {
+        days_from_civil(self.year, self.month, self.day) as u64 * 86400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.seconds as u64
+    }
+
+    /// The date and time `seconds` seconds after 1970-01-01 00:00:00,
+    /// ignoring leap seconds. Returns `INVAL` if the year does not fit in
+    /// a `u16`.
+    pub fn from_seconds_since_epoch(seconds: u64) -> Result<DateTimeValues, ErrorCode> {
+        let days = seconds / 86400;
+        let time = (seconds % 86400) as u32;
+        // The inverse of `days_from_civil()`
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        Ok(DateTimeValues {
+            year: u16::try_from(year).map_err(|_| ErrorCode::INVAL)?,
+            month: Month::try_from(month as u8)?,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            seconds: (time % 60) as u8,
+        })
+    }
+}
+
+/// A real-time clock.
+pub trait DateTime<'a> {
+    /// Reads the current date and time. The result is passed to
+    /// `get_date_time_done()`.
+    ///
+    /// Return values:
+    /// - `Ok(())`: The callback will be called.
+    /// - `BUSY`: Another operation is in progress.
+    fn get_date_time(&self) -> Result<(), ErrorCode>;
+
+    /// Sets the current date and time. When the clock is set,
+    /// `set_date_time_done()` is called.
+    ///
+    /// Return values:
+    /// - `Ok(())`: The callback will be called.
+    /// - `BUSY`: Another operation is in progress.
+    /// - `INVAL`: `date_time` is not valid, or the clock cannot represent it.
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode>;
+
+    /// Arms the alarm of the clock, which calls `alarm_fired()` once the
+    /// clock reaches `date_time`, replacing any previous alarm. An alarm
+    /// set to a time that already passed does not fire. Setting the clock
+    /// does not disarm the alarm, but the alarm does not fire if the clock
+    /// is set past it.
+    ///
+    /// Return values:
+    /// - `Ok(())`: The alarm is armed.
+    /// - `INVAL`: `date_time` is not valid, or the clock cannot represent it.
+    /// - `NOSUPPORT`: The clock has no alarm.
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode>;
+
+    /// Disarms the alarm. Does nothing if the alarm is not armed.
+    fn disable_alarm(&self) -> Result<(), ErrorCode>;
+
+    fn set_client(&self, client: &'a dyn DateTimeClient);
+}
+
+pub trait DateTimeClient {
+    /// Called when a `get_date_time()` operation completes.
+    fn get_date_time_done(&self, date_time: Result<DateTimeValues, ErrorCode>);
+
+    /// Called when a `set_date_time()` operation completes.
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when the clock reaches the date and time of the alarm.
+    fn alarm_fired(&self);
+}
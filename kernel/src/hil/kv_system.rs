@@ -30,7 +30,7 @@
 //! This level is also in charge of generating the key hash by calling into
 //! level 2.
 //!
-//! There is currently no implementation of this HIL in Tock.
+//! `capsules::kv_driver` implements this level for applications.
 //!
 //! The expected setup inside Tock will look like this:
 //! +-----------------------+
@@ -39,7 +39,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_store (not written yet)
+//!    syscalls (capsules::kv_driver)
 //!
 //! +-----------------------+
 //! |                       |
@@ -47,7 +47,7 @@
 //! |                       |
 //! +-----------------------+
 //!
-//!    hil::kv_system (this PR)
+//!    hil::kv_system (this file)
 //!
 //! +-----------------------+
 //! |                       |
@@ -67,7 +67,7 @@ impl KeyType for [u8; 8] {}
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
 pub trait Client<K: KeyType> {
-    /// This callback is called when the append_key operation completes
+    /// This callback is called when the generate_key operation completes
     ///
     /// `result`: Nothing on success, 'ReturnCode' on error
     /// `unhashed_key`: The unhashed_key buffer
@@ -75,8 +75,8 @@ pub trait Client<K: KeyType> {
     fn generate_key_complete(
         &self,
         result: Result<(), ReturnCode>,
-        unhashed_key: &'static [u8],
-        key_buf: &'static K,
+        unhashed_key: &'static mut [u8],
+        key_buf: &'static mut K,
     );
 
     /// This callback is called when the append_key operation completes
@@ -88,7 +88,7 @@ pub trait Client<K: KeyType> {
         &self,
         result: Result<(), ReturnCode>,
         key: &'static mut K,
-        value: &'static [u8],
+        value: &'static mut [u8],
     );
 
     /// This callback is called when the get_value operation completes
@@ -103,12 +103,31 @@ pub trait Client<K: KeyType> {
         ret_buf: &'static mut [u8],
     );
 
+    /// This callback is called when the update_key operation completes
+    ///
+    /// `result`: Nothing on success, 'ReturnCode' on error
+    /// `key`: The key buffer
+    /// `value`: The value buffer
+    fn update_key_complete(
+        &self,
+        result: Result<(), ReturnCode>,
+        key: &'static mut K,
+        value: &'static mut [u8],
+    );
+
     /// This callback is called when the invalidate_key operation completes
     ///
     /// `result`: Nothing on success, 'ReturnCode' on error
     /// `key`: The key buffer
     fn invalidate_key_complete(&self, result: Result<(), ReturnCode>, key: &'static mut K);
 
+    /// This callback is called when the next_key operation completes
+    ///
+    /// `result`: The length of the value of the key that was found on
+    ///           success, 'ReturnCode' on error
+    /// `key_buf`: The key_buf buffer, holding the key that was found
+    fn next_key_complete(&self, result: Result<usize, ReturnCode>, key_buf: &'static mut K);
+
     /// This callback is called when the garbage_collect operation completes
     ///
     /// `result`: Nothing on success, 'ReturnCode' on error
@@ -127,7 +146,9 @@ pub trait KVSystem<'a> {
     /// `unhashed_key`: A unhashed key that should be hashed.
     /// `key_buf`: A buffer to store the hashed key output.
     ///
-    /// On success returns nothing.
+    /// On success returns nothing and `generate_key_complete()` will be
+    /// called. Implementations that hash in software may call it before
+    /// returning.
     /// On error the unhashed_key, key_buf and `ReturnCode` will be returned.
     fn generate_key(
         &self,
@@ -140,6 +161,7 @@ pub trait KVSystem<'a> {
     /// `key`: A hashed key. This key will be used in future to retrieve
     ///        or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The number of bytes of `value` to store.
     ///
     /// On success nothing will be returned.
     /// On error the key, value and a `ReturnCode` will be returned.
@@ -153,8 +175,9 @@ pub trait KVSystem<'a> {
     fn append_key(
         &self,
         key: &'static mut Self::K,
-        value: &'static [u8],
-    ) -> Result<(), (&'static mut Self::K, &'static [u8], ReturnCode)>;
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (&'static mut Self::K, &'static mut [u8], ReturnCode)>;
 
     /// Retrieves the value from a specified key.
     ///
@@ -175,6 +198,31 @@ pub trait KVSystem<'a> {
         ret_buf: &'static mut [u8],
     ) -> Result<(), (&'static mut Self::K, &'static mut [u8], ReturnCode)>;
 
+    /// Replaces the value of an existing key.
+    ///
+    /// `key`: A hashed key. The key must already exist.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    /// `length`: The number of bytes of `value` to store.
+    ///
+    /// Unlike invalidating the key and appending it again, the key keeps
+    /// either its old or its new value if power is lost during the update.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key, value and a `ReturnCode` will be returned.
+    ///
+    /// The possible `ReturnCode`s are:
+    ///    `EBUSY`: An operation is already in progress
+    ///    `EINVAL`: An invalid parameter was passed
+    ///    `ENODEVICE`: No KV store was setup
+    ///    `ENOSUPPORT`: The key could not be found.
+    ///    `ENOMEM`: There is no space left for the new value.
+    fn update_key(
+        &self,
+        key: &'static mut Self::K,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<(), (&'static mut Self::K, &'static mut [u8], ReturnCode)>;
+
     /// Invalidates the key in flash storage
     ///
     /// `key`: A hashed key. This key will be used to remove the `value`.
@@ -192,6 +240,28 @@ pub trait KVSystem<'a> {
         key: &'static mut Self::K,
     ) -> Result<(), (&'static mut Self::K, ReturnCode)>;
 
+    /// Finds the next stored key.
+    ///
+    /// `key_buf`: A buffer to store the key that was found.
+    /// `restart`: Start from the first key rather than after the key that was
+    ///            last found.
+    ///
+    /// Keys are found in the order they are stored, and each key is found
+    /// once unless keys are added, updated or removed in between.
+    ///
+    /// On success nothing will be returned.
+    /// On error the key_buf and a `ReturnCode` will be returned.
+    ///
+    /// The possible `ReturnCode`s are:
+    ///    `EBUSY`: An operation is already in progress
+    ///    `ENODEVICE`: No KV store was setup
+    ///    `ENOSUPPORT`: Every key has been found.
+    fn next_key(
+        &self,
+        key_buf: &'static mut Self::K,
+        restart: bool,
+    ) -> Result<(), (&'static mut Self::K, ReturnCode)>;
+
     /// Perform a garbage collection on the KV Store
     ///
     /// For implementations that don't require garbage collecting
@@ -6,6 +6,7 @@ pub mod ble_advertising;
 pub mod bus8080;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
@@ -17,6 +18,7 @@ pub mod kv_system;
 pub mod led;
 pub mod log;
 pub mod nonvolatile_storage;
+pub mod public_key_crypto;
 pub mod pwm;
 pub mod radio;
 pub mod rng;
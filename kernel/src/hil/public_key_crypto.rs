@@ -0,0 +1,146 @@
+//! Interfaces for public key cryptography.
+//!
+//! These traits cover digital signatures and key agreement over elliptic
+//! curves, such as ECDSA and ECDH with the NIST P-256 curve:
+//!
+//! - `SignatureVerify` checks the signature of a hash with a public key, for
+//!   example to verify a firmware update or the signature of an application.
+//! - `SignatureSign` signs a hash with a private key, for example for the
+//!   attestation of a security key.
+//! - `KeyAgreement` computes a secret shared with a peer from a private key
+//!   and the public key of the peer.
+//!
+//! The keys are set with `PublicKey` and `PrivateKey`. Operations complete
+//! asynchronously, and return the buffers they were given in the callback.
+//! The lengths of the hash, signature, public key and shared secret are set
+//! by the implementation: for P-256 with SHA-256, hashes are 32 bytes,
+//! signatures are the 64 byte `r || s`, public keys are the 65 byte
+//! uncompressed SEC1 encoding `0x04 || x || y`, and shared secrets are the 32
+//! byte x coordinate of the shared point.
+
+use crate::ErrorCode;
+
+/// Set the public key used to verify signatures.
+pub trait PublicKey {
+    /// Set the public key. Returns `INVAL` if the key has the wrong length or
+    /// is not a valid key, and `BUSY` if an operation is in progress.
+    fn set_public_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Copy the public key into `key`. Returns `RESERVE` if no key is set,
+    /// and `SIZE` if `key` has the wrong length.
+    fn public_key(&self, key: &mut [u8]) -> Result<(), ErrorCode>;
+}
+
+/// Set the private key used to sign and to agree on keys.
+pub trait PrivateKey {
+    /// Set the private key. The public key of the pair is set as well, so
+    /// that it can be read with `PublicKey::public_key()`. Returns `INVAL` if
+    /// the key has the wrong length or is not a valid key, and `BUSY` if an
+    /// operation is in progress.
+    fn set_private_key(&self, key: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Forget the private key.
+    fn clear_private_key(&self);
+}
+
+/// Implement this trait and use `set_verify_client()` to receive the result
+/// of signature verifications.
+pub trait ClientVerify<const HASH_LEN: usize, const SIGNATURE_LEN: usize> {
+    /// Called when a verification completes. `result` is `Ok(true)` if the
+    /// signature is valid and `Ok(false)` if it is not.
+    fn verification_done(
+        &self,
+        result: Result<bool, ErrorCode>,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    );
+}
+
+/// Verify signatures with the public key set with `PublicKey`.
+pub trait SignatureVerify<'a, const HASH_LEN: usize, const SIGNATURE_LEN: usize> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<HASH_LEN, SIGNATURE_LEN>);
+
+    /// Check that `signature` is a signature of `hash`. Returns `RESERVE` if
+    /// no public key is set, and `BUSY` if an operation is in progress.
+    fn verify(
+        &self,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; HASH_LEN],
+            &'static mut [u8; SIGNATURE_LEN],
+        ),
+    >;
+}
+
+/// Implement this trait and use `set_sign_client()` to receive signatures.
+pub trait ClientSign<const HASH_LEN: usize, const SIGNATURE_LEN: usize> {
+    /// Called when a signature is computed into `signature`.
+    fn signing_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    );
+}
+
+/// Sign hashes with the private key set with `PrivateKey`.
+pub trait SignatureSign<'a, const HASH_LEN: usize, const SIGNATURE_LEN: usize> {
+    fn set_sign_client(&'a self, client: &'a dyn ClientSign<HASH_LEN, SIGNATURE_LEN>);
+
+    /// Sign `hash`, writing the signature into `signature`. Returns `RESERVE`
+    /// if no private key is set, and `BUSY` if an operation is in progress.
+    fn sign(
+        &self,
+        hash: &'static mut [u8; HASH_LEN],
+        signature: &'static mut [u8; SIGNATURE_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; HASH_LEN],
+            &'static mut [u8; SIGNATURE_LEN],
+        ),
+    >;
+}
+
+/// Implement this trait and use `set_key_agreement_client()` to receive
+/// shared secrets.
+pub trait ClientKeyAgreement<const PUBLIC_KEY_LEN: usize, const SECRET_LEN: usize> {
+    /// Called when the secret shared with the owner of `peer_key` is computed
+    /// into `secret`. Fails with `INVAL` if `peer_key` is not a valid key.
+    fn agreement_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        peer_key: &'static mut [u8; PUBLIC_KEY_LEN],
+        secret: &'static mut [u8; SECRET_LEN],
+    );
+}
+
+/// Compute secrets shared with peers with the private key set with
+/// `PrivateKey`.
+pub trait KeyAgreement<'a, const PUBLIC_KEY_LEN: usize, const SECRET_LEN: usize> {
+    fn set_key_agreement_client(
+        &'a self,
+        client: &'a dyn ClientKeyAgreement<PUBLIC_KEY_LEN, SECRET_LEN>,
+    );
+
+    /// Compute the secret shared with the owner of `peer_key` into `secret`.
+    /// Returns `RESERVE` if no private key is set, and `BUSY` if an operation
+    /// is in progress.
+    fn agree(
+        &self,
+        peer_key: &'static mut [u8; PUBLIC_KEY_LEN],
+        secret: &'static mut [u8; SECRET_LEN],
+    ) -> Result<
+        (),
+        (
+            ErrorCode,
+            &'static mut [u8; PUBLIC_KEY_LEN],
+            &'static mut [u8; SECRET_LEN],
+        ),
+    >;
+}
@@ -14,6 +14,8 @@ use core::cell::Cell;
 
 use crate::capabilities::ProcessManagementCapability;
 use crate::common::cells::NumericCellExt;
+use crate::common::deferred_call;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::process;
 use crate::sched::Kernel;
 use crate::upcall::AppId;
@@ -103,6 +105,39 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_dropped_upcall_count())
     }
 
+    /// Returns the number of syscalls of the app that the system call filter
+    /// of the platform denied.
+    pub fn number_app_denied_syscalls(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_syscall_denied_count())
+    }
+
+    /// Returns the number of jobs of the app that missed their deadline. This
+    /// is only counted for real-time apps run by a real-time scheduler.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_deadline_miss_count())
+    }
+
+    /// Returns the number of times the app missed the deadline of its app
+    /// watchdog, including before it was restarted.
+    pub fn number_app_watchdog_expirations(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_watchdog_expiration_count())
+    }
+
     /// Returns the number of time this app has been restarted.
     pub fn number_app_restarts(
         &self,
@@ -150,6 +185,85 @@ impl KernelInfo {
         (used, number_of_grants)
     }
 
+    /// Returns a tuple of (the number of grant regions allocated by all
+    /// processes, the number of grants that exist in the system).
+    pub fn number_grant_uses(
+        &self,
+        capability: &dyn ProcessManagementCapability,
+    ) -> (usize, usize) {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|process| {
+            count.add(self.number_app_grant_uses(process.appid(), capability).0);
+        });
+        (count.get(), self.kernel.get_grant_count_and_finalize())
+    }
+
+    /// Returns how many bytes of process memory the kernel uses, summed over
+    /// all processes. This is the memory at the end of each process's memory
+    /// region that holds the process's grants, upcall queue and process
+    /// state.
+    pub fn kernel_memory_in_processes(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|process| {
+            count.add(process.mem_end() as usize - process.kernel_memory_break() as usize);
+        });
+        count.get()
+    }
+
+    /// Returns a tuple of (the number of clients registered with the
+    /// dynamic deferred call instance, the number of clients it has room
+    /// for). Both are 0 if the board has no dynamic deferred call instance.
+    pub fn number_dynamic_deferred_call_clients(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> (usize, usize) {
+        unsafe { DynamicDeferredCall::global_instance_usage() }
+            .map_or((0, 0), |(registered, capacity, _)| (registered, capacity))
+    }
+
+    /// Returns how many deferred calls, static and dynamic, are pending.
+    pub fn number_pending_deferred_calls(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        let dynamic_pending = unsafe { DynamicDeferredCall::global_instance_usage() }
+            .map_or(0, |(_, _, pending)| pending);
+        deferred_call::number_pending() + dynamic_pending
+    }
+
+    /// Returns the total number of syscalls of all processes that the system
+    /// call filter denied.
+    pub fn denied_syscalls(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_syscall_denied_count());
+        });
+        count.get()
+    }
+
+    /// Returns the total number of jobs of all processes that missed their
+    /// deadline.
+    pub fn deadline_misses(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_deadline_miss_count());
+        });
+        count.get()
+    }
+
+    /// Returns the total number of times processes missed the deadline of
+    /// their app watchdog.
+    pub fn watchdog_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        let count: Cell<usize> = Cell::new(0);
+        self.kernel.process_each(|proc| {
+            count.add(proc.debug_watchdog_expiration_count());
+        });
+        count.get()
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -106,8 +106,12 @@ mod mem;
 mod memop;
 mod platform;
 mod process;
+mod process_checker;
+mod process_loader;
 mod returncode;
 mod sched;
+#[cfg(test)]
+mod test_util;
 mod upcall;
 
 pub use crate::driver::{CommandReturn, Driver};
@@ -115,6 +119,7 @@ pub use crate::errorcode::ErrorCode;
 pub use crate::grant::{DynamicGrant, Grant};
 pub use crate::mem::{Read, ReadOnlyAppSlice, ReadWrite, ReadWriteAppSlice};
 pub use crate::platform::scheduler_timer::{SchedulerTimer, VirtualSchedulerTimer};
+pub use crate::platform::syscall_filter;
 pub use crate::platform::watchdog;
 pub use crate::platform::{mpu, Chip, InterruptService, Platform};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
@@ -122,8 +127,12 @@ pub use crate::returncode::ReturnCode;
 pub use crate::sched::cooperative::{CoopProcessNode, CooperativeSched};
 pub use crate::sched::mlfq::{MLFQProcessNode, MLFQSched};
 pub use crate::sched::priority::PrioritySched;
+pub use crate::sched::real_time::{
+    EDFSched, EarliestDeadlineFirst, PriorityPolicy, RateMonotonic, RateMonotonicSched,
+    RealTimeProcessNode, RealTimeSched,
+};
 pub use crate::sched::round_robin::{RoundRobinProcessNode, RoundRobinSched};
-pub use crate::sched::{Kernel, Scheduler};
+pub use crate::sched::{Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason};
 pub use crate::upcall::{AppId, Upcall};
 
 // Export only select items from the process module. To remove the name conflict
@@ -133,8 +142,13 @@ pub use crate::upcall::{AppId, Upcall};
 /// Publicly available process-related objects.
 pub mod procs {
     pub use crate::process::{
-        load_processes, AlwaysRestart, Error, FaultResponse, FunctionCall, FunctionCallSource,
-        Process, ProcessLoadError, ProcessRestartPolicy, ProcessType, State, Task,
-        ThresholdRestart, ThresholdRestartThenPanic,
+        load_and_check_processes, load_processes, AlwaysRestart, Error, FaultResponse,
+        FunctionCall, FunctionCallSource, Process, ProcessLoadError, ProcessRestartPolicy,
+        ProcessSlot, ProcessType, State, Task, ThresholdRestart, ThresholdRestartThenPanic,
+    };
+    pub use crate::process_checker::{AppCredentialsChecker, CheckResult, NullCredentialsChecker};
+    pub use crate::process_loader::ProcessLoader;
+    pub use tock_tbf::types::{
+        CommandPermissions, TbfHeaderV2Credentials, TbfHeaderV2CredentialsType, TbfHeaderV2RealTime,
     };
 }
@@ -8,6 +8,7 @@ use core::fmt::Write;
 
 pub mod mpu;
 pub(crate) mod scheduler_timer;
+pub mod syscall_filter;
 pub mod watchdog;
 
 /// Interface for individual boards.
@@ -55,6 +56,9 @@ pub trait Platform {
     ///
     /// This API should be considered unstable, and is likely to change in the
     /// future.
+    ///
+    /// Boards can use a `SyscallFilter`, such as `TbfHeaderFilterDefaultAllow`,
+    /// to implement this.
     fn filter_syscall(
         &self,
         _process: &dyn process::ProcessType,
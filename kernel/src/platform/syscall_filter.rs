@@ -0,0 +1,229 @@
+//! System call filters that boards can use in `Platform::filter_syscall()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! struct Board {
+//!     syscall_filter: kernel::syscall_filter::TbfHeaderFilterDefaultAllow,
+//!     ...
+//! }
+//!
+//! impl Platform for Board {
+//!     ...
+//!
+//!     fn filter_syscall(
+//!         &self,
+//!         process: &dyn kernel::procs::ProcessType,
+//!         syscall: &kernel::syscall::Syscall,
+//!     ) -> Result<(), kernel::ErrorCode> {
+//!         self.syscall_filter.filter_syscall(process, syscall)
+//!     }
+//! }
+//! ```
+
+use crate::errorcode::ErrorCode;
+use crate::process::ProcessType;
+use crate::syscall::Syscall;
+use tock_tbf::types::CommandPermissions;
+
+/// Decides whether a process may make a system call.
+///
+/// The kernel does not pass yield, memop or exit system calls to the
+/// filter.
+pub trait SyscallFilter {
+    /// Returns `Ok(())` if `process` may make `syscall`, otherwise the
+    /// `ErrorCode` returned to the process.
+    fn filter_syscall(&self, process: &dyn ProcessType, syscall: &Syscall)
+        -> Result<(), ErrorCode>;
+}
+
+/// Filters system calls with the permissions TLV in the TBF header of each
+/// process.
+///
+/// A process with a permissions TLV may only use the drivers listed in it,
+/// and only call the command numbers of those drivers that are set in it.
+/// Subscribe and allow calls are permitted to every listed driver. A system
+/// call to a driver that is not listed fails with `NODEVICE`, as if the
+/// driver did not exist, and a command that is not set fails with
+/// `NOSUPPORT`.
+///
+/// Processes without a permissions TLV may make any system call.
+pub struct TbfHeaderFilterDefaultAllow {}
+
+impl SyscallFilter for TbfHeaderFilterDefaultAllow {
+    fn filter_syscall(
+        &self,
+        process: &dyn ProcessType,
+        syscall: &Syscall,
+    ) -> Result<(), ErrorCode> {
+        let (driver_number, command_number) = match *syscall {
+            Syscall::Command {
+                driver_number,
+                subdriver_number,
+                ..
+            } => (driver_number, Some(subdriver_number)),
+            Syscall::Subscribe { driver_number, .. }
+            | Syscall::ReadWriteAllow { driver_number, .. }
+            | Syscall::ReadOnlyAllow { driver_number, .. } => (driver_number, None),
+            _ => return Ok(()),
+        };
+
+        // Each permission covers 64 command numbers
+        let offset = command_number.map_or(0, |command_number| command_number / 64);
+        match process.get_command_permissions(driver_number, offset) {
+            CommandPermissions::NoPermsAtAll => Ok(()),
+            CommandPermissions::NoPermsThisDriver => Err(ErrorCode::NODEVICE),
+            CommandPermissions::Mask(allowed_commands) => match command_number {
+                Some(command_number) if allowed_commands & (1 << (command_number % 64)) == 0 => {
+                    Err(ErrorCode::NOSUPPORT)
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{SyscallFilter, TbfHeaderFilterDefaultAllow};
+    use crate::errorcode::ErrorCode;
+    use crate::sched::Kernel;
+    use crate::syscall::Syscall;
+    use crate::test_util::{app, app_with_tlvs, loader, Capability};
+    use crate::upcall::AppId;
+    use std::vec::Vec;
+
+    const DRIVER: usize = 0x40000;
+
+    /// Build a permissions TLV with the given driver numbers, offsets and
+    /// masks of allowed commands.
+    fn permissions(permissions: &[(u32, u32, u64)]) -> Vec<u8> {
+        let mut tlv: Vec<u8> = Vec::new();
+        tlv.extend_from_slice(&6u16.to_le_bytes());
+        tlv.extend_from_slice(&(16 * permissions.len() as u16).to_le_bytes());
+        for (driver_number, offset, allowed_commands) in permissions {
+            tlv.extend_from_slice(&driver_number.to_le_bytes());
+            tlv.extend_from_slice(&offset.to_le_bytes());
+            tlv.extend_from_slice(&allowed_commands.to_le_bytes());
+        }
+        tlv
+    }
+
+    /// Loads `app_flash` and returns its kernel and `AppId`.
+    fn process(app_flash: &'static [u8]) -> (&'static Kernel, AppId) {
+        let (kernel, loader) = loader();
+        let appid = loader
+            .load_process(app_flash, &Capability)
+            .unwrap()
+            .unwrap();
+        (kernel, appid)
+    }
+
+    fn filter(kernel: &Kernel, appid: AppId, syscall: Syscall) -> Result<(), ErrorCode> {
+        kernel.process_map_or(Err(ErrorCode::FAIL), appid, |process| {
+            TbfHeaderFilterDefaultAllow {}.filter_syscall(process, &syscall)
+        })
+    }
+
+    fn command(driver_number: usize, subdriver_number: usize) -> Syscall {
+        Syscall::Command {
+            driver_number,
+            subdriver_number,
+            arg0: 0,
+            arg1: 0,
+        }
+    }
+
+    fn subscribe(driver_number: usize) -> Syscall {
+        Syscall::Subscribe {
+            driver_number,
+            subdriver_number: 0,
+            upcall_ptr: core::ptr::null_mut(),
+            appdata: 0,
+        }
+    }
+
+    fn allow(driver_number: usize) -> Syscall {
+        Syscall::ReadOnlyAllow {
+            driver_number,
+            subdriver_number: 0,
+            allow_address: core::ptr::null(),
+            allow_size: 0,
+        }
+    }
+
+    #[test]
+    fn app_without_permissions_may_make_any_syscall() {
+        let (kernel, appid) = process(app(b"a"));
+
+        assert_eq!(filter(kernel, appid, command(DRIVER, 0)), Ok(()));
+        assert_eq!(filter(kernel, appid, command(DRIVER, 200)), Ok(()));
+        assert_eq!(filter(kernel, appid, subscribe(DRIVER)), Ok(()));
+        assert_eq!(filter(kernel, appid, allow(DRIVER)), Ok(()));
+    }
+
+    #[test]
+    fn unlisted_driver_does_not_exist() {
+        let (kernel, appid) = process(app_with_tlvs(b"a", &permissions(&[(1, 0, !0)])));
+
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 0)),
+            Err(ErrorCode::NODEVICE)
+        );
+        assert_eq!(
+            filter(kernel, appid, subscribe(DRIVER)),
+            Err(ErrorCode::NODEVICE)
+        );
+        assert_eq!(
+            filter(kernel, appid, allow(DRIVER)),
+            Err(ErrorCode::NODEVICE)
+        );
+    }
+
+    #[test]
+    fn unset_command_is_not_supported() {
+        let tlv = permissions(&[(DRIVER as u32, 0, 0b101)]);
+        let (kernel, appid) = process(app_with_tlvs(b"a", &tlv));
+
+        assert_eq!(filter(kernel, appid, command(DRIVER, 0)), Ok(()));
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 1)),
+            Err(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(filter(kernel, appid, command(DRIVER, 2)), Ok(()));
+        // Subscribe and allow calls are permitted to listed drivers.
+        assert_eq!(filter(kernel, appid, subscribe(DRIVER)), Ok(()));
+        assert_eq!(filter(kernel, appid, allow(DRIVER)), Ok(()));
+    }
+
+    #[test]
+    fn commands_map_to_64_per_offset() {
+        // Command 63 is the last bit of offset 0, and command 64 the first
+        // bit of offset 1. Offset 2 is not listed.
+        let tlv = permissions(&[(DRIVER as u32, 0, 1 << 63), (DRIVER as u32, 1, 1 | 1 << 5)]);
+        let (kernel, appid) = process(app_with_tlvs(b"a", &tlv));
+
+        assert_eq!(filter(kernel, appid, command(DRIVER, 63)), Ok(()));
+        assert_eq!(filter(kernel, appid, command(DRIVER, 64)), Ok(()));
+        assert_eq!(filter(kernel, appid, command(DRIVER, 69)), Ok(()));
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 0)),
+            Err(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 5)),
+            Err(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 127)),
+            Err(ErrorCode::NOSUPPORT)
+        );
+        assert_eq!(
+            filter(kernel, appid, command(DRIVER, 128)),
+            Err(ErrorCode::NOSUPPORT)
+        );
+    }
+}
@@ -18,11 +18,14 @@ use crate::ipc;
 use crate::mem::{ReadOnlyAppSlice, ReadWriteAppSlice};
 use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
+use crate::process_checker::{self, AppCredentialsChecker, NullCredentialsChecker};
 use crate::returncode::ReturnCode;
 use crate::sched::Kernel;
 use crate::syscall::{self, Syscall, SyscallReturn, UserspaceKernelBoundary};
 use crate::upcall::{AppId, UpcallId};
 
+use tock_tbf::types::{CommandPermissions, TbfHeaderV2Credentials, TbfHeaderV2RealTime};
+
 // The completion code for a process if it faulted.
 const COMPLETION_FAULT: u32 = 0xffffffff;
 
@@ -59,6 +62,13 @@ pub enum ProcessLoadError {
         expected_address: u32,
     },
 
+    /// All entries of the processes array are in use, so there is no entry
+    /// to store a new process in.
+    NoProcessSlot,
+
+    /// A process is already running from the TBF that was to be loaded.
+    AlreadyLoaded,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -112,11 +122,93 @@ impl fmt::Debug for ProcessLoadError {
                 actual_address, expected_address
             ),
 
+            ProcessLoadError::NoProcessSlot => write!(f, "No free slot in the processes array"),
+
+            ProcessLoadError::AlreadyLoaded => write!(f, "App is already loaded"),
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
 }
 
+/// An entry of the processes array of the kernel, which holds a process or is
+/// free.
+///
+/// Boards declare the array as a `static mut` of empty slots, and only give
+/// out shared references to it:
+///
+/// ```ignore
+/// static mut PROCESSES: [ProcessSlot; NUM_PROCS] = [ProcessSlot::EMPTY; NUM_PROCS];
+/// ```
+///
+/// Only the kernel changes the slots, when it loads and unloads processes.
+/// Unloading a process frees its memory, which also holds its `Process`
+/// struct, for new processes. So the process in a slot is only reachable
+/// through a `ProcessRef`, which counts as a borrow of the slot until it is
+/// dropped, and a slot cannot be freed while it is borrowed. Outside the
+/// kernel crate, processes are only visible for the duration of a closure
+/// (e.g. `Kernel::process_each_capability()`).
+pub struct ProcessSlot {
+    proc: Cell<Option<&'static dyn ProcessType>>,
+    /// The number of `ProcessRef`s to the process.
+    borrows: Cell<usize>,
+}
+
+impl ProcessSlot {
+    /// An empty slot, to initialize the processes array with.
+    pub const EMPTY: ProcessSlot = ProcessSlot {
+        proc: Cell::new(None),
+        borrows: Cell::new(0),
+    };
+
+    /// Borrows the process in the slot, if there is one.
+    pub(crate) fn get(&self) -> Option<ProcessRef> {
+        self.proc.get().map(|process| {
+            self.borrows.increment();
+            ProcessRef {
+                slot: self,
+                process,
+            }
+        })
+    }
+
+    /// Stores a newly created process in the slot.
+    pub(crate) fn set(&self, proc: &'static dyn ProcessType) {
+        self.proc.set(Some(proc));
+    }
+
+    /// Frees the slot. Returns BUSY if the process is borrowed, as its memory
+    /// may be reused once the slot is free.
+    pub(crate) fn clear(&self) -> Result<(), ErrorCode> {
+        if self.borrows.get() > 0 {
+            return Err(ErrorCode::BUSY);
+        }
+        self.proc.set(None);
+        Ok(())
+    }
+}
+
+/// A borrow of the process in a `ProcessSlot`, which keeps the slot from
+/// being freed until it is dropped.
+pub(crate) struct ProcessRef<'a> {
+    slot: &'a ProcessSlot,
+    process: &'a dyn ProcessType,
+}
+
+impl<'a> core::ops::Deref for ProcessRef<'a> {
+    type Target = dyn ProcessType + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        self.process
+    }
+}
+
+impl Drop for ProcessRef<'_> {
+    fn drop(&mut self) {
+        self.slot.borrows.decrement();
+    }
+}
+
 /// Helper function to load processes from flash into an array of active
 /// processes. This is the default template for loading processes, but a board
 /// is able to create its own `load_processes()` function and use that instead.
@@ -128,7 +220,10 @@ impl fmt::Debug for ProcessLoadError {
 /// ensuring that this code cannot hold onto the slice past the end of this function
 /// (instead, processes store a pointer and length), which necessary for later
 /// creation of `AppSlice`'s in this memory region to be sound.
-/// A reference to each process is stored in the provided `procs` array.
+/// A reference to each process is stored in the processes array of the
+/// kernel. The kernel keeps `app_memory` as the region later processes are
+/// allocated from (see `ProcessLoader`), so it must not be used for anything
+/// else.
 /// How process faults are handled by the
 /// kernel must be provided and is assigned to every created process.
 ///
@@ -139,13 +234,44 @@ impl fmt::Debug for ProcessLoadError {
 /// Returns `Ok(())` if process discovery went as expected. Returns a
 /// `ProcessLoadError` if something goes wrong during TBF parsing or process
 /// creation.
+///
+/// This does not check the credentials of processes. Use
+/// `load_and_check_processes()` to only run processes with valid credentials.
 pub fn load_processes<C: Chip>(
     kernel: &'static Kernel,
     chip: &'static C,
     app_flash: &'static [u8],
     app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
-    procs: &'static mut [Option<&'static dyn ProcessType>],
     fault_response: FaultResponse,
+    capability: &dyn ProcessManagementCapability,
+) -> Result<(), ProcessLoadError> {
+    load_and_check_processes(
+        kernel,
+        chip,
+        app_flash,
+        app_memory,
+        fault_response,
+        &NullCredentialsChecker::new(),
+        capability,
+    )
+}
+
+/// Helper function to load processes from flash into an array of active
+/// processes, only creating processes whose credentials are permitted by
+/// `checker`.
+///
+/// This behaves like `load_processes()`, except that the credentials (hashes,
+/// MACs or signatures) in each enabled app's TBF header are checked by the
+/// `AppCredentialsChecker` before a `Process` is created. Apps that the
+/// checker does not permit to run are skipped, just like disabled apps, and
+/// loading continues with the next app in flash.
+pub fn load_and_check_processes<C: Chip>(
+    kernel: &'static Kernel,
+    chip: &'static C,
+    app_flash: &'static [u8],
+    app_memory: &mut [u8], // not static, so that process.rs cannot hold on to slice w/o unsafe
+    fault_response: FaultResponse,
+    checker: &dyn AppCredentialsChecker,
     _capability: &dyn ProcessManagementCapability,
 ) -> Result<(), ProcessLoadError> {
     if config::CONFIG.debug_load_processes {
@@ -158,6 +284,9 @@ pub fn load_processes<C: Chip>(
         );
     }
 
+    kernel.set_app_memory(app_memory.as_mut_ptr(), app_memory.len());
+    let procs = kernel.process_slots();
+
     let mut remaining_flash = app_flash;
     let mut remaining_memory = app_memory;
 
@@ -232,6 +361,7 @@ pub fn load_processes<C: Chip>(
                     version,
                     remaining_memory,
                     fault_response,
+                    checker,
                     i,
                 )?
             };
@@ -249,7 +379,7 @@ pub fn load_processes<C: Chip>(
                 }
 
                 // Save the reference to this process in the processes array.
-                procs[i] = Some(process);
+                procs[i].set(process);
             });
             unused_memory
         } else {
@@ -329,6 +459,21 @@ pub trait ProcessType {
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the permissions of the process, from its TBF header, for the
+    /// command numbers `offset * 64` to `offset * 64 + 63` of the driver
+    /// `driver_num`. Used by system call filters.
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions;
+
+    /// Get the timing requirements of the process from its TBF header, or
+    /// `None` if it is not a real-time process. Used by real-time schedulers.
+    fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime>;
+
+    /// Get the credential of the process that the `AppCredentialsChecker`
+    /// accepted when the process was loaded, or `None` if the process runs
+    /// without an accepted credential. Unlike the package name, this can not
+    /// be chosen by another app, so capsules can use it to identify the app.
+    fn get_credentials(&self) -> Option<TbfHeaderV2Credentials>;
+
     /// Stop and clear a process's state, putting it into the `Terminated`
     /// state.
     ///
@@ -582,6 +727,31 @@ pub trait ProcessType {
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
+
+    /// Returns how many syscalls of this process the system call filter of
+    /// the platform denied.
+    fn debug_syscall_denied_count(&self) -> usize;
+
+    /// Increment the number of syscalls of this process that the system call
+    /// filter denied.
+    fn debug_syscall_denied(&self);
+
+    /// Returns how many jobs of this process finished after their deadline
+    /// (or did not finish at all).
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Increment the number of jobs of this process that missed their
+    /// deadline.
+    fn debug_deadline_missed(&self);
+
+    /// Returns how many times this process missed the deadline of its app
+    /// watchdog. Unlike the other counts, this is not reset when the process
+    /// restarts, as a missed deadline is what restarts it.
+    fn debug_watchdog_expiration_count(&self) -> usize;
+
+    /// Increment the number of times this process missed the deadline of its
+    /// app watchdog.
+    fn debug_watchdog_expired(&self);
 }
 
 /// Generic trait for implementing process restart policies.
@@ -875,6 +1045,16 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many syscalls the system call filter denied.
+    syscall_denied_count: usize,
+
+    /// How many jobs of this process missed their deadline.
+    deadline_miss_count: usize,
+
+    /// How many times this process missed the deadline of its app watchdog.
+    /// Kept across restarts.
+    watchdog_expiration_count: usize,
 }
 
 /// A type for userspace processes in Tock.
@@ -945,6 +1125,10 @@ pub struct Process<'a, C: 'static + Chip> {
     /// Collection of pointers to the TBF header in flash.
     header: tock_tbf::types::TbfHeader,
 
+    /// The credential in the TBF header that was accepted when the process
+    /// was loaded.
+    credentials: Option<TbfHeaderV2Credentials>,
+
     /// State saved on behalf of the process each time the app switches to the
     /// kernel.
     stored_state:
@@ -1528,6 +1712,18 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         self.process_name
     }
 
+    fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        self.header.get_command_permissions(driver_num, offset)
+    }
+
+    fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime> {
+        self.header.get_real_time_parameters()
+    }
+
+    fn get_credentials(&self) -> Option<TbfHeaderV2Credentials> {
+        self.credentials
+    }
+
     fn set_syscall_return_value(&self, return_value: SyscallReturn) {
         match self.stored_state.map(|stored_state| unsafe {
             // Actually set the return value for a particular process.
@@ -1675,6 +1871,31 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         });
     }
 
+    fn debug_syscall_denied_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.syscall_denied_count)
+    }
+
+    fn debug_syscall_denied(&self) {
+        self.debug.map(|debug| debug.syscall_denied_count += 1);
+    }
+
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| debug.deadline_miss_count += 1);
+    }
+
+    fn debug_watchdog_expiration_count(&self) -> usize {
+        self.debug
+            .map_or(0, |debug| debug.watchdog_expiration_count)
+    }
+
+    fn debug_watchdog_expired(&self) {
+        self.debug.map(|debug| debug.watchdog_expiration_count += 1);
+    }
+
     fn print_memory_map(&self, writer: &mut dyn Write) {
         // Flash
         let flash_end = self.flash.as_ptr().wrapping_add(self.flash.len()) as usize;
@@ -1707,19 +1928,28 @@ impl<C: Chip> ProcessType for Process<'_, C> {
         let syscall_count = self.debug.map_or(0, |debug| debug.syscall_count);
         let last_syscall = self.debug.map(|debug| debug.last_syscall);
         let dropped_upcall_count = self.debug.map_or(0, |debug| debug.dropped_upcall_count);
+        let syscall_denied_count = self.debug.map_or(0, |debug| debug.syscall_denied_count);
+        let deadline_miss_count = self.debug.map_or(0, |debug| debug.deadline_miss_count);
+        let watchdog_expiration_count = self
+            .debug
+            .map_or(0, |debug| debug.watchdog_expiration_count);
         let restart_count = self.restart_count.get();
 
         let _ = writer.write_fmt(format_args!(
             "\
              𝐀𝐩𝐩: {}   -   [{:?}]\
              \r\n Events Queued: {}   Syscall Count: {}   Dropped Upcall Count: {}\
-             \r\n Restart Count: {}\r\n",
+             \r\n Restart Count: {}   Denied Syscall Count: {}   Deadline Miss Count: {}\
+             \r\n Watchdog Expiration Count: {}\r\n",
             self.process_name,
             self.state.get(),
             events_queued,
             syscall_count,
             dropped_upcall_count,
             restart_count,
+            syscall_denied_count,
+            deadline_miss_count,
+            watchdog_expiration_count,
         ));
 
         let _ = match last_syscall {
@@ -1946,6 +2176,7 @@ impl<C: 'static + Chip> Process<'_, C> {
         app_version: u16,
         remaining_memory: &'a mut [u8],
         fault_response: FaultResponse,
+        checker: &dyn AppCredentialsChecker,
         index: usize,
     ) -> Result<(Option<&'static dyn ProcessType>, &'a mut [u8]), ProcessLoadError> {
         // Get a slice for just the app header.
@@ -2000,6 +2231,25 @@ impl<C: 'static + Chip> Process<'_, C> {
             return Ok((None, remaining_memory));
         }
 
+        // Check the credentials of the app. Apps that are not permitted to run
+        // are skipped like disabled apps.
+        let credentials = match process_checker::check_credentials(&tbf_header, app_flash, checker)
+        {
+            Ok(credentials) => credentials,
+            Err(()) => {
+                if config::CONFIG.debug_load_processes {
+                    debug!(
+                        "Process credentials rejected flash={:#010X}-{:#010X} process={:?}",
+                        app_flash.as_ptr() as usize,
+                        app_flash.as_ptr() as usize + app_flash.len() - 1,
+                        process_name
+                    );
+                }
+                // Return no process and the full memory slice we were given.
+                return Ok((None, remaining_memory));
+            }
+        };
+
         // Otherwise, actually load the app.
         let process_ram_requested_size = tbf_header.get_minimum_app_ram_size() as usize;
         let init_fn = app_flash
@@ -2252,6 +2502,7 @@ impl<C: 'static + Chip> Process<'_, C> {
         process.memory_start = app_memory.as_ptr();
         process.memory_len = app_memory.len();
         process.header = tbf_header;
+        process.credentials = credentials;
         process.kernel_memory_break = Cell::new(kernel_memory_break);
         process.app_break = Cell::new(initial_app_brk);
 
@@ -2285,6 +2536,9 @@ impl<C: 'static + Chip> Process<'_, C> {
             last_syscall: None,
             dropped_upcall_count: 0,
             timeslice_expiration_count: 0,
+            syscall_denied_count: 0,
+            deadline_miss_count: 0,
+            watchdog_expiration_count: 0,
         });
 
         let flash_protected_size = process.header.get_protected_size() as usize;
@@ -2357,6 +2611,8 @@ impl<C: 'static + Chip> Process<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.syscall_denied_count = 0;
+            debug.deadline_miss_count = 0;
         });
 
         // FLASH
@@ -0,0 +1,309 @@
+//! Policies for deciding whether a process may run based on the credentials
+//! (hashes, MACs or signatures) attached to its TBF.
+//!
+//! When processes are loaded, the kernel asks an `AppCredentialsChecker` to
+//! check each credential in the TBF header against the integrity regions of
+//! the TBF (see `TbfHeader::get_integrity_regions()`). A credential can be
+//! accepted, rejected, or passed on if the checker does not handle that
+//! credentials format. The first credential that is accepted or rejected
+//! decides whether the process runs. If no credential is accepted or rejected,
+//! the checker's `require_credentials()` decides.
+
+use tock_tbf::types::{TbfHeader, TbfHeaderV2Credentials};
+
+/// The outcome of checking a single credential.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CheckResult {
+    /// The credential is valid and the process may run.
+    Accept,
+    /// The checker does not handle this credential (for example it does not
+    /// support its format or does not hold the required key), so the next
+    /// credential should be checked.
+    Pass,
+    /// The credential is invalid and the process must not run.
+    Reject,
+}
+
+/// Generic trait for implementing process credentials checking policies.
+///
+/// This policy allows a board to specify which credentials it understands and
+/// whether processes without valid credentials may run.
+pub trait AppCredentialsChecker {
+    /// Check `credentials` over the integrity regions of a TBF.
+    ///
+    /// `integrity_regions` are the regions of the TBF covered by the
+    /// credential, in order. They must be processed as if they were one
+    /// contiguous buffer.
+    fn check_credentials(
+        &self,
+        credentials: &TbfHeaderV2Credentials,
+        integrity_regions: &[&[u8]],
+    ) -> CheckResult;
+
+    /// Whether a process must have an accepted credential to run.
+    ///
+    /// Returns `true` if processes without any accepted credential should not
+    /// run, `false` if they should run anyway.
+    fn require_credentials(&self) -> bool;
+}
+
+/// Implementation of `AppCredentialsChecker` that does not check any
+/// credentials and allows all processes to run. This is the behavior of
+/// `load_processes()`.
+pub struct NullCredentialsChecker {}
+
+impl NullCredentialsChecker {
+    pub const fn new() -> NullCredentialsChecker {
+        NullCredentialsChecker {}
+    }
+}
+
+impl AppCredentialsChecker for NullCredentialsChecker {
+    fn check_credentials(
+        &self,
+        _credentials: &TbfHeaderV2Credentials,
+        _integrity_regions: &[&[u8]],
+    ) -> CheckResult {
+        CheckResult::Pass
+    }
+
+    fn require_credentials(&self) -> bool {
+        false
+    }
+}
+
+/// Decide whether the process in `app_flash` with TBF header `header` may run
+/// according to `checker`.
+///
+/// Returns `Err(())` if the process may not run. Otherwise returns the
+/// credential that `checker` accepted, or `None` if the process runs without
+/// an accepted credential.
+///
+/// `app_flash` must be the entire TBF of the process.
+pub(crate) fn check_credentials(
+    header: &TbfHeader,
+    app_flash: &[u8],
+    checker: &dyn AppCredentialsChecker,
+) -> Result<Option<TbfHeaderV2Credentials>, ()> {
+    let integrity_regions = header.get_integrity_regions(app_flash).ok_or(())?;
+
+    for index in 0..header.number_credentials() {
+        if let Some(credentials) = header.get_credentials(index) {
+            match checker.check_credentials(&credentials, &integrity_regions) {
+                CheckResult::Accept => return Ok(Some(credentials)),
+                CheckResult::Reject => return Err(()),
+                CheckResult::Pass => {}
+            }
+        }
+    }
+
+    if checker.require_credentials() {
+        Err(())
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::NullCredentialsChecker;
+    use super::{check_credentials, AppCredentialsChecker, CheckResult};
+    use core::convert::TryInto;
+    use std::boxed::Box;
+    use std::vec::Vec;
+    use tock_tbf::types::{TbfHeader, TbfHeaderV2Credentials, TbfHeaderV2CredentialsType};
+
+    /// Checker that treats the HMAC format as a simple XOR "MAC" of every
+    /// integrity byte, so that tests can compute valid and invalid
+    /// credentials without real cryptography.
+    struct XorChecker {
+        required: bool,
+    }
+
+    fn xor_mac(regions: &[&[u8]]) -> u8 {
+        regions
+            .iter()
+            .flat_map(|region| region.iter())
+            .fold(0, |acc, b| acc ^ b)
+    }
+
+    impl AppCredentialsChecker for XorChecker {
+        fn check_credentials(
+            &self,
+            credentials: &TbfHeaderV2Credentials,
+            integrity_regions: &[&[u8]],
+        ) -> CheckResult {
+            match credentials.format() {
+                TbfHeaderV2CredentialsType::HmacSha256 => {
+                    let mac = xor_mac(integrity_regions);
+                    if credentials.data().iter().all(|b| *b == mac) {
+                        CheckResult::Accept
+                    } else {
+                        CheckResult::Reject
+                    }
+                }
+                _ => CheckResult::Pass,
+            }
+        }
+
+        fn require_credentials(&self) -> bool {
+            self.required
+        }
+    }
+
+    /// Build a TBF with a Main TLV, the given credentials TLVs (format and
+    /// data) and an app binary, and parse its header.
+    fn build_tbf(credentials: &[(u32, Vec<u8>)], binary: &[u8]) -> (&'static [u8], TbfHeader) {
+        let mut header: Vec<u8> = std::vec![0; 16];
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&12u16.to_le_bytes());
+        header.extend_from_slice(&[0; 12]);
+        for (format, data) in credentials {
+            header.extend_from_slice(&128u16.to_le_bytes());
+            header.extend_from_slice(&(4 + data.len() as u16).to_le_bytes());
+            header.extend_from_slice(&format.to_le_bytes());
+            header.extend_from_slice(data);
+        }
+
+        let header_size = header.len();
+        header[0..2].copy_from_slice(&2u16.to_le_bytes());
+        header[2..4].copy_from_slice(&(header_size as u16).to_le_bytes());
+        header[4..8].copy_from_slice(&((header_size + binary.len()) as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let checksum = header
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 3)
+            .fold(0, |acc, (_, chunk)| {
+                acc ^ u32::from_le_bytes(chunk.try_into().unwrap())
+            });
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+        header.extend_from_slice(binary);
+
+        let app: &'static [u8] = Box::leak(header.into_boxed_slice());
+        let tbf_header = match tock_tbf::parse::parse_tbf_header(&app[0..header_size], 2) {
+            Ok(tbf_header) => tbf_header,
+            Err(e) => panic!("Could not parse TBF header: {:?}", e),
+        };
+        (app, tbf_header)
+    }
+
+    /// Build a TBF like `build_tbf()` whose last credential is a valid XOR
+    /// "MAC", computed over the integrity regions of the TBF.
+    fn build_signed_tbf(
+        credentials: &[(u32, Vec<u8>)],
+        binary: &[u8],
+    ) -> (&'static [u8], TbfHeader) {
+        // The integrity regions do not include the credentials data, so a
+        // placeholder MAC yields the same regions as the final TBF.
+        let mut all_credentials = credentials.to_vec();
+        all_credentials.push((2, std::vec![0; 32]));
+        let (app, header) = build_tbf(&all_credentials, binary);
+        let mac = xor_mac(&header.get_integrity_regions(app).unwrap());
+
+        all_credentials.pop();
+        all_credentials.push((2, std::vec![mac; 32]));
+        build_tbf(&all_credentials, binary)
+    }
+
+    /// Whether the process may run.
+    fn credentials_permit_running(
+        header: &TbfHeader,
+        app: &[u8],
+        checker: &dyn AppCredentialsChecker,
+    ) -> bool {
+        check_credentials(header, app, checker).is_ok()
+    }
+
+    #[test]
+    fn null_checker_allows_everything() {
+        let checker = NullCredentialsChecker::new();
+
+        let (app, header) = build_tbf(&[], &[1, 2, 3, 4]);
+        assert!(credentials_permit_running(&header, app, &checker));
+
+        let (app, header) = build_tbf(&[(2, std::vec![0xFF; 32])], &[1, 2, 3, 4]);
+        assert!(credentials_permit_running(&header, app, &checker));
+    }
+
+    #[test]
+    fn unsigned_apps() {
+        let (app, header) = build_tbf(&[], &[1, 2, 3, 4]);
+        assert!(credentials_permit_running(
+            &header,
+            app,
+            &XorChecker { required: false }
+        ));
+        assert!(!credentials_permit_running(
+            &header,
+            app,
+            &XorChecker { required: true }
+        ));
+    }
+
+    #[test]
+    fn valid_credentials() {
+        let (app, header) = build_signed_tbf(&[], &[0x10, 0x20, 0x30, 0x47]);
+        assert!(credentials_permit_running(
+            &header,
+            app,
+            &XorChecker { required: true }
+        ));
+    }
+
+    #[test]
+    fn accepted_credential_is_returned() {
+        let binary = [0x10, 0x20, 0x30, 0x47];
+        let checker = XorChecker { required: false };
+
+        let (app, header) = build_signed_tbf(&[(1, std::vec![0; 32])], &binary);
+        let accepted = check_credentials(&header, app, &checker).unwrap().unwrap();
+        assert_eq!(accepted.format(), TbfHeaderV2CredentialsType::HmacSha256);
+        assert_eq!(accepted.data(), header.get_credentials(1).unwrap().data());
+
+        // An unsigned app runs without an accepted credential
+        let (app, header) = build_tbf(&[], &binary);
+        assert!(check_credentials(&header, app, &checker).unwrap().is_none());
+    }
+
+    #[test]
+    fn tampered_binary() {
+        let (app, header) = build_signed_tbf(&[], &[0x10, 0x20, 0x30, 0x47]);
+
+        // Flip a bit in the binary after the credentials were computed.
+        let mut tampered = app.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        let tampered: &'static [u8] = Box::leak(tampered.into_boxed_slice());
+
+        assert!(!credentials_permit_running(
+            &header,
+            tampered,
+            &XorChecker { required: false }
+        ));
+    }
+
+    #[test]
+    fn unsupported_credentials_are_passed() {
+        let binary = [0x10, 0x20, 0x30, 0x47];
+
+        // A SHA-256 credential the checker does not handle is skipped, and the
+        // HMAC after it decides.
+        let (app, header) = build_signed_tbf(&[(1, std::vec![0; 32])], &binary);
+        assert!(credentials_permit_running(
+            &header,
+            app,
+            &XorChecker { required: true }
+        ));
+
+        // Only unsupported credentials behaves like an unsigned app.
+        let (app, header) = build_tbf(&[(1, std::vec![0; 32])], &binary);
+        assert!(!credentials_permit_running(
+            &header,
+            app,
+            &XorChecker { required: true }
+        ));
+    }
+}
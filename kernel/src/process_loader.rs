@@ -0,0 +1,451 @@
+//! Loading and unloading processes while the kernel is running.
+//!
+//! `load_processes()` creates processes once, at boot, from all apps in flash.
+//! A `ProcessLoader` can load apps that were written to flash after boot (for
+//! example by an over-the-air update) into free entries of the processes
+//! array, and can free the entry and memory of a process that stopped so that
+//! they can be used by a new process. Loaded processes are started as they
+//! would be at boot.
+//!
+//! The processes array and the process memory are owned by the kernel: the
+//! loader changes the array through the kernel, and allocates process memory
+//! from the region that `load_processes()` was given at boot. Memory is not
+//! tracked separately: the free memory is whatever part of the region is not
+//! used by a process in the processes array. Unloading a process therefore
+//! frees its memory, including the memory of its `Process` struct. This is
+//! sound because processes are only reachable through borrows of their slot
+//! in the processes array, and a process cannot be unloaded while its slot is
+//! borrowed (see `ProcessSlot`).
+//!
+//! Usage
+//! -----
+//!
+//! ```rust,ignore
+//! let process_loader = static_init!(
+//!     kernel::procs::ProcessLoader<sam4l::chip::Sam4l<Sam4lDefaultPeripherals>>,
+//!     kernel::procs::ProcessLoader::new(
+//!         board_kernel,
+//!         chip,
+//!         core::slice::from_raw_parts(
+//!             &_sapps as *const u8,
+//!             &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+//!         ),
+//!         FAULT_RESPONSE,
+//!         &CREDENTIALS_CHECKER,
+//!         &process_management_capability,
+//!     )
+//! );
+//!
+//! // Later, once a new app has been written to flash:
+//! process_loader.discover_processes(&process_management_capability);
+//! ```
+
+use core::convert::TryInto;
+use core::slice;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::config;
+use crate::debug;
+use crate::errorcode::ErrorCode;
+use crate::platform::Chip;
+use crate::process::{FaultResponse, Process, ProcessLoadError, ProcessSlot, ProcessType, State};
+use crate::process_checker::AppCredentialsChecker;
+use crate::sched::Kernel;
+use crate::upcall::AppId;
+
+/// Loads processes into, and removes processes from, the processes array of
+/// the kernel at runtime.
+pub struct ProcessLoader<C: 'static + Chip> {
+    kernel: &'static Kernel,
+    chip: &'static C,
+    /// Flash region that `discover_processes()` searches for apps.
+    app_flash: &'static [u8],
+    fault_response: FaultResponse,
+    checker: &'static dyn AppCredentialsChecker,
+}
+
+impl<C: 'static + Chip> ProcessLoader<C> {
+    /// Creates a loader for the processes array of `kernel`.
+    ///
+    /// New processes are allocated from the memory region given to
+    /// `load_processes()` at boot, in the parts of it that no process uses.
+    /// Processes loaded by the loader use `fault_response` and only run if
+    /// `checker` permits them to.
+    ///
+    /// Like `load_processes()`, this requires the
+    /// `ProcessManagementCapability`.
+    pub fn new(
+        kernel: &'static Kernel,
+        chip: &'static C,
+        app_flash: &'static [u8],
+        fault_response: FaultResponse,
+        checker: &'static dyn AppCredentialsChecker,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> ProcessLoader<C> {
+        ProcessLoader {
+            kernel,
+            chip,
+            app_flash,
+            fault_response,
+            checker,
+        }
+    }
+
+    /// Searches the app flash region for apps that are not loaded, and loads
+    /// them. This stops at the end of the apps in flash, like
+    /// `load_processes()`.
+    ///
+    /// Returns the number of processes that were loaded, or the first error
+    /// that occurred. Apps that are disabled, or whose credentials are not
+    /// permitted, are skipped.
+    pub fn discover_processes(
+        &self,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<usize, ProcessLoadError> {
+        let mut remaining_flash = self.app_flash;
+        let mut loaded = 0;
+
+        while let Some((version, header_length, entry_length)) = parse_lengths(remaining_flash)? {
+            let entry_flash = remaining_flash
+                .get(0..entry_length as usize)
+                .ok_or(ProcessLoadError::NotEnoughFlash)?;
+            remaining_flash = remaining_flash
+                .get(entry_flash.len()..)
+                .ok_or(ProcessLoadError::NotEnoughFlash)?;
+
+            if header_length > 0 && !self.is_loaded(entry_flash) {
+                if self
+                    .load_entry(entry_flash, header_length as usize, version)?
+                    .is_some()
+                {
+                    loaded += 1;
+                }
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    /// Loads the app in `app_flash`, which must contain one entire TBF, and
+    /// starts it.
+    ///
+    /// Returns the `AppId` of the new process, or `None` if the TBF is padding,
+    /// a disabled app, or an app whose credentials are not permitted.
+    pub fn load_process(
+        &self,
+        app_flash: &'static [u8],
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<Option<AppId>, ProcessLoadError> {
+        let (version, header_length, entry_length) =
+            parse_lengths(app_flash)?.ok_or(ProcessLoadError::NotEnoughFlash)?;
+        let entry_flash = app_flash
+            .get(0..entry_length as usize)
+            .ok_or(ProcessLoadError::NotEnoughFlash)?;
+        if header_length == 0 {
+            return Ok(None);
+        }
+        if self.is_loaded(entry_flash) {
+            return Err(ProcessLoadError::AlreadyLoaded);
+        }
+        self.load_entry(entry_flash, header_length as usize, version)
+            .map(|process| process.map(|process| process.appid()))
+    }
+
+    /// Removes the process `appid` from the processes array, freeing its
+    /// entry and its memory for new processes.
+    ///
+    /// Only processes that are no longer running, i.e. that terminated or
+    /// faulted, can be unloaded. Returns BUSY if the process is in any other
+    /// state, and INVAL if `appid` is not a loaded process.
+    ///
+    /// Any `AppId` of the process becomes invalid, as for a process that was
+    /// restarted.
+    pub fn unload_process(
+        &self,
+        appid: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Result<(), ErrorCode> {
+        let entry = self
+            .kernel
+            .process_slots()
+            .get(appid.index)
+            .ok_or(ErrorCode::INVAL)?;
+        let (state, name) = entry
+            .get()
+            .filter(|process| process.appid() == appid)
+            .map(|process| (process.get_state(), process.get_process_name()))
+            .ok_or(ErrorCode::INVAL)?;
+        match state {
+            State::Terminated | State::Faulted => {
+                entry.clear()?;
+                if config::CONFIG.debug_load_processes {
+                    debug!("Unloaded process[{}] {:?}", appid.index, name);
+                }
+                Ok(())
+            }
+            _ => Err(ErrorCode::BUSY),
+        }
+    }
+
+    // Whether a process was loaded from the TBF at the start of `entry_flash`
+    fn is_loaded(&self, entry_flash: &[u8]) -> bool {
+        self.kernel
+            .get_process_iter()
+            .any(|process| process.flash_start() == entry_flash.as_ptr())
+    }
+
+    // Creates a process for the TBF in `entry_flash` in a free entry of the
+    // processes array and free memory.
+    fn load_entry(
+        &self,
+        entry_flash: &'static [u8],
+        header_length: usize,
+        version: u16,
+    ) -> Result<Option<&'static dyn ProcessType>, ProcessLoadError> {
+        let procs = self.kernel.process_slots();
+        let index = procs
+            .iter()
+            .position(|entry| entry.get().is_none())
+            .ok_or(ProcessLoadError::NoProcessSlot)?;
+
+        // Try each free range of memory in turn, as the MPU may not be able
+        // to place the process in the first one that is large enough.
+        let (memory_start, memory_len) = self.kernel.app_memory();
+        let used = procs
+            .iter()
+            .filter_map(ProcessSlot::get)
+            .map(|process| (process.mem_start() as usize, process.mem_end() as usize));
+        let mut result = Err(ProcessLoadError::NotEnoughMemory);
+        for (start, end) in free_ranges(used, memory_start as usize, memory_len) {
+            // This is safe because no process uses memory in this
+            // range, and the slice does not outlive `create()`.
+            // Processes only keep pointers to the part of it they are
+            // given.
+            let memory = unsafe { slice::from_raw_parts_mut(start as *mut u8, end - start) };
+            result = unsafe {
+                Process::create(
+                    self.kernel,
+                    self.chip,
+                    entry_flash,
+                    header_length,
+                    version,
+                    memory,
+                    self.fault_response,
+                    self.checker,
+                    index,
+                )
+            }
+            .map(|(process, _unused_memory)| process);
+
+            match result {
+                Err(ProcessLoadError::NotEnoughMemory)
+                | Err(ProcessLoadError::MemoryAddressMismatch { .. }) => continue,
+                _ => break,
+            }
+        }
+
+        let process = result?;
+        process.map(|process| {
+            if config::CONFIG.debug_load_processes {
+                debug!(
+                    "Loaded process[{}] from flash={:#010X}-{:#010X} into sram={:#010X}-{:#010X} = {:?}",
+                    index,
+                    entry_flash.as_ptr() as usize,
+                    entry_flash.as_ptr() as usize + entry_flash.len() - 1,
+                    process.mem_start() as usize,
+                    process.mem_end() as usize - 1,
+                    process.get_process_name()
+                );
+            }
+            procs[index].set(process);
+        });
+        Ok(process)
+    }
+}
+
+/// Parses the lengths of the TBF at the start of `flash`. Returns `None` at
+/// the end of the apps in flash. As in `load_processes()`, the header length
+/// is 0 if the header is invalid and the entry should be skipped.
+fn parse_lengths(flash: &'static [u8]) -> Result<Option<(u16, u16, u32)>, ProcessLoadError> {
+    let test_header_slice = match flash.get(0..8) {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    match tock_tbf::parse::parse_tbf_header_lengths(
+        test_header_slice
+            .try_into()
+            .or(Err(ProcessLoadError::InternalError))?,
+    ) {
+        Ok((version, header_length, entry_length)) => {
+            Ok(Some((version, header_length, entry_length)))
+        }
+        Err(tock_tbf::types::InitialTbfParseError::InvalidHeader(entry_length)) => {
+            Ok(Some((0, 0, entry_length)))
+        }
+        Err(tock_tbf::types::InitialTbfParseError::UnableToParse) => Ok(None),
+    }
+}
+
+/// Returns the ranges `(start, end)` of the memory region starting at
+/// `region_start` that are not in any of the `used` ranges. Each range
+/// extends from the start of the region or the end of a used range to the start
+/// of the next used range or the end of the region.
+fn free_ranges<I: Iterator<Item = (usize, usize)> + Clone>(
+    used: I,
+    region_start: usize,
+    region_len: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let region_end = region_start + region_len;
+    // The memory ranges of the processes in the region
+    let used = used.filter(move |(start, _)| *start >= region_start && *start < region_end);
+
+    core::iter::once(region_start)
+        .chain(used.clone().map(|(_, end)| end))
+        // Skip ranges that start inside another process
+        .filter({
+            let used = used.clone();
+            move |start| {
+                !used
+                    .clone()
+                    .any(|(used_start, used_end)| used_start <= *start && *start < used_end)
+            }
+        })
+        .map(move |start| {
+            let end = used
+                .clone()
+                .map(|(used_start, _)| used_start)
+                .filter(|used_start| *used_start >= start)
+                .min()
+                .unwrap_or(region_end);
+            (start, end)
+        })
+        .filter(|(start, end)| start < end)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{free_ranges, ProcessLoader};
+    use crate::errorcode::ErrorCode;
+    use crate::process::ProcessLoadError;
+    use crate::test_util::{app, loader, Capability, TestChip};
+    use crate::upcall::AppId;
+    use std::vec::Vec;
+
+    fn ranges(used: &[(usize, usize)], start: usize, len: usize) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<_> = free_ranges(used.iter().copied(), start, len).collect();
+        ranges.sort();
+        ranges
+    }
+
+    #[test]
+    fn empty_region_is_free() {
+        assert_eq!(ranges(&[], 0x1000, 0x1000), [(0x1000, 0x2000)]);
+        assert_eq!(ranges(&[], 0x1000, 0), []);
+    }
+
+    #[test]
+    fn ranges_between_processes() {
+        let used = [(0x1400, 0x1800), (0x1000, 0x1200)];
+        assert_eq!(
+            ranges(&used, 0x1000, 0x1000),
+            [(0x1200, 0x1400), (0x1800, 0x2000)]
+        );
+    }
+
+    #[test]
+    fn adjacent_processes_leave_no_range() {
+        let used = [(0x1000, 0x1800), (0x1800, 0x2000)];
+        assert_eq!(ranges(&used, 0x1000, 0x1000), []);
+    }
+
+    #[test]
+    fn processes_outside_region_are_ignored() {
+        let used = [(0x0800, 0x0c00), (0x2000, 0x2400), (0x1800, 0x1c00)];
+        assert_eq!(
+            ranges(&used, 0x1000, 0x1000),
+            [(0x1000, 0x1800), (0x1c00, 0x2000)]
+        );
+    }
+
+    fn load(loader: &ProcessLoader<TestChip>, app_flash: &'static [u8]) -> AppId {
+        loader
+            .load_process(app_flash, &Capability)
+            .unwrap()
+            .expect("app was not loaded")
+    }
+
+    #[test]
+    fn running_process_is_not_unloaded() {
+        let (kernel, loader) = loader();
+        let appid = load(&loader, app(b"a"));
+
+        assert_eq!(
+            loader.unload_process(appid, &Capability),
+            Err(ErrorCode::BUSY)
+        );
+        assert!(kernel.get_process(appid).is_some());
+    }
+
+    #[test]
+    fn stopped_processes_are_unloaded() {
+        let (kernel, loader) = loader();
+        let terminated = load(&loader, app(b"a"));
+        let faulted = load(&loader, app(b"b"));
+
+        kernel.process_map_or((), terminated, |process| process.terminate(0));
+        kernel.process_map_or((), faulted, |process| process.set_fault_state());
+
+        assert_eq!(loader.unload_process(terminated, &Capability), Ok(()));
+        assert_eq!(loader.unload_process(faulted, &Capability), Ok(()));
+        assert!(kernel.get_process(terminated).is_none());
+        assert!(kernel.get_process(faulted).is_none());
+        // The process is gone, so its `AppId` is no longer valid.
+        assert_eq!(
+            loader.unload_process(terminated, &Capability),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn borrowed_process_is_not_unloaded() {
+        let (kernel, loader) = loader();
+        let appid = load(&loader, app(b"a"));
+        kernel.process_map_or((), appid, |process| process.terminate(0));
+
+        let process = kernel.get_process(appid);
+        assert_eq!(
+            loader.unload_process(appid, &Capability),
+            Err(ErrorCode::BUSY)
+        );
+        drop(process);
+        assert_eq!(loader.unload_process(appid, &Capability), Ok(()));
+    }
+
+    #[test]
+    fn slot_and_memory_are_reused_after_unload() {
+        let (kernel, loader) = loader();
+        let first = load(&loader, app(b"a"));
+        let second = load(&loader, app(b"b"));
+        let memory = kernel.process_map_or(None, first, |process| Some(process.mem_start()));
+
+        // Both slots are in use.
+        assert!(matches!(
+            loader.load_process(app(b"c"), &Capability),
+            Err(ProcessLoadError::NoProcessSlot)
+        ));
+
+        kernel.process_map_or((), first, |process| process.terminate(0));
+        loader.unload_process(first, &Capability).unwrap();
+
+        let third = load(&loader, app(b"c"));
+        assert_eq!(third.index, first.index);
+        assert_ne!(third, first);
+        assert_eq!(
+            kernel.process_map_or(None, third, |process| Some(process.mem_start())),
+            memory
+        );
+        assert!(kernel.get_process(second).is_some());
+    }
+}
@@ -8,6 +8,7 @@
 pub(crate) mod cooperative;
 pub(crate) mod mlfq;
 pub(crate) mod priority;
+pub(crate) mod real_time;
 pub(crate) mod round_robin;
 
 use core::cell::Cell;
@@ -129,7 +130,12 @@ pub struct Kernel {
     work: Cell<usize>,
 
     /// This holds a pointer to the static array of Process pointers.
-    processes: &'static [Option<&'static dyn process::ProcessType>],
+    processes: &'static [process::ProcessSlot],
+
+    /// The memory region processes are allocated from, as given to
+    /// `load_processes()`: its start and length. Processes own parts of it, so
+    /// we only keep a pointer.
+    app_memory: Cell<(*mut u8, usize)>,
 
     /// A counter which keeps track of how many process identifiers have been
     /// created. This is used to create new unique identifiers for processes.
@@ -173,10 +179,11 @@ pub enum StoppedExecutingReason {
 }
 
 impl Kernel {
-    pub fn new(processes: &'static [Option<&'static dyn process::ProcessType>]) -> Kernel {
+    pub fn new(processes: &'static [process::ProcessSlot]) -> Kernel {
         Kernel {
             work: Cell::new(0),
             processes,
+            app_memory: Cell::new((core::ptr::null_mut(), 0)),
             process_identifier_max: Cell::new(0),
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
@@ -243,30 +250,27 @@ impl Kernel {
     where
         F: FnOnce(&dyn process::ProcessType) -> R,
     {
+        self.get_process(appid)
+            .map_or(default, |process| closure(&*process))
+    }
+
+    /// Borrow the process identified by `appid`, if it still exists.
+    ///
+    /// The same matching rules as `process_map_or()` apply. The process
+    /// cannot be unloaded until the returned reference is dropped.
+    pub(crate) fn get_process(&self, appid: AppId) -> Option<process::ProcessRef> {
         // We use the index in the `appid` so we can do a direct lookup.
         // However, we are not guaranteed that the app still exists at that
         // index in the processes array. To avoid additional overhead, we do the
         // lookup and check here, rather than calling `.index()`.
         let tentative_index = appid.index;
 
-        // Get the process at that index, and if it matches, run the closure
-        // on it.
+        // Get the process at that index, and check that the process stored
+        // there matches the identifier in the `appid`.
         self.processes
             .get(tentative_index)
-            .map_or(None, |process_entry| {
-                // Check if there is any process state here, or if the entry is
-                // `None`.
-                process_entry.map_or(None, |process| {
-                    // Check that the process stored here matches the identifier
-                    // in the `appid`.
-                    if process.appid() == appid {
-                        Some(closure(process))
-                    } else {
-                        None
-                    }
-                })
-            })
-            .unwrap_or(default)
+            .and_then(process::ProcessSlot::get)
+            .filter(|process| process.appid() == appid)
     }
 
     /// Run a closure on every valid process. This will iterate the array of
@@ -276,28 +280,40 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(&*p);
                 }
                 None => {}
             }
         }
     }
 
-    /// Returns an iterator over all processes loaded by the kernel
+    /// Returns an iterator over all processes loaded by the kernel. Each
+    /// process is borrowed until the item is dropped.
     pub(crate) fn get_process_iter(
         &self,
     ) -> core::iter::FilterMap<
-        core::slice::Iter<Option<&dyn process::ProcessType>>,
-        fn(&Option<&'static dyn process::ProcessType>) -> Option<&'static dyn process::ProcessType>,
+        core::slice::Iter<process::ProcessSlot>,
+        fn(&process::ProcessSlot) -> Option<process::ProcessRef>,
     > {
-        fn keep_some(
-            &x: &Option<&'static dyn process::ProcessType>,
-        ) -> Option<&'static dyn process::ProcessType> {
-            x
-        }
-        self.processes.iter().filter_map(keep_some)
+        self.processes.iter().filter_map(process::ProcessSlot::get)
+    }
+
+    /// Returns the processes array.
+    pub(crate) fn process_slots(&self) -> &'static [process::ProcessSlot] {
+        self.processes
+    }
+
+    /// Records the memory region processes are allocated from.
+    pub(crate) fn set_app_memory(&self, start: *mut u8, len: usize) {
+        self.app_memory.set((start, len));
+    }
+
+    /// Returns the memory region processes are allocated from, or an empty
+    /// region if no processes were loaded.
+    pub(crate) fn app_memory(&self) -> (*mut u8, usize) {
+        self.app_memory.get()
     }
 
     /// Run a closure on every valid process. This will iterate the array of
@@ -314,9 +330,9 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType),
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(*p);
+                    closure(&*p);
                 }
                 None => {}
             }
@@ -331,9 +347,9 @@ impl Kernel {
         F: Fn(&dyn process::ProcessType) -> Option<T>,
     {
         for process in self.processes.iter() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    let ret = closure(*p);
+                    let ret = closure(&*p);
                     if ret.is_some() {
                         return ret;
                     }
@@ -349,8 +365,8 @@ impl Kernel {
     /// as from userspace) and needs to be expanded to a full `AppId` for use
     /// with other APIs.
     pub(crate) fn lookup_app_by_identifier(&self, identifier: usize) -> Option<AppId> {
-        self.processes.iter().find_map(|&p| {
-            p.map_or(None, |p2| {
+        self.processes.iter().find_map(|p| {
+            p.get().map_or(None, |p2| {
                 if p2.appid().id() == identifier {
                     Some(p2.appid())
                 } else {
@@ -368,7 +384,8 @@ impl Kernel {
     /// verify that the referenced app is still at the correct index.
     pub(crate) fn appid_is_valid(&self, appid: &AppId) -> bool {
         self.processes.get(appid.index).map_or(false, |p| {
-            p.map_or(false, |process| process.appid().id() == appid.id())
+            p.get()
+                .map_or(false, |process| process.appid().id() == appid.id())
         })
     }
 
@@ -447,55 +464,73 @@ impl Kernel {
     /// apps.
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
-            p.map(|process| {
+            p.get().map(|process| {
                 process.set_fault_state();
             });
         }
     }
 
-    /// Main loop of the OS.
+    /// Perform one iteration of the core Tock kernel loop.
     ///
-    /// Most of the behavior of this loop is controlled by the `Scheduler`
-    /// implementation in use.
-    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
+    /// This function is responsible for three main operations:
+    ///
+    /// 1. Check if the kernel itself has any work to be done and if the
+    ///    scheduler wants to complete that work now. If so, it allows the
+    ///    kernel to run.
+    /// 2. Check if any processes have any work to be done, and if so if the
+    ///    scheduler wants to allow any processes to run now, and if so which
+    ///    one.
+    /// 3. After ensuring the scheduler does not want to complete any kernel or
+    ///    process work (or there is no work to be done), are there are no
+    ///    outstanding interrupts to handle, put the chip to sleep.
+    ///
+    /// This function has one configuration option: `no_sleep`. If that
+    /// argument is set to true, the kernel will never attempt to put the chip
+    /// to sleep, and this function can be called again immediately. This is
+    /// useful for running the kernel loop step by step, e.g. in a simulation.
+    pub fn kernel_loop_operation<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
         &self,
         platform: &P,
         chip: &C,
         ipc: Option<&ipc::IPC<NUM_PROCS>>,
         scheduler: &SC,
+        no_sleep: bool,
         _capability: &dyn capabilities::MainLoopCapability,
-    ) -> ! {
-        chip.watchdog().setup();
-        loop {
-            chip.watchdog().tickle();
-            unsafe {
-                // Ask the scheduler if we should do tasks inside of the kernel,
-                // such as handle interrupts. A scheduler may want to prioritize
-                // processes instead, or there may be no kernel work to do.
-                match scheduler.do_kernel_work_now(chip) {
-                    true => {
-                        // Execute kernel work. This includes handling
-                        // interrupts and is how code in the chips/ and capsules
-                        // crates is able to execute.
-                        scheduler.execute_kernel_work(chip);
-                    }
-                    false => {
-                        // No kernel work ready, so ask scheduler for a process.
-                        match scheduler.next(self) {
-                            SchedulingDecision::RunProcess((appid, timeslice_us)) => {
-                                self.process_map_or((), appid, |process| {
-                                    let (reason, time_executed) = self.do_process(
-                                        platform,
-                                        chip,
-                                        scheduler,
-                                        process,
-                                        ipc,
-                                        timeslice_us,
-                                    );
-                                    scheduler.result(reason, time_executed);
-                                });
-                            }
-                            SchedulingDecision::TrySleep => {
+    ) {
+        chip.watchdog().tickle();
+        unsafe {
+            // Ask the scheduler if we should do tasks inside of the kernel,
+            // such as handle interrupts. A scheduler may want to prioritize
+            // processes instead, or there may be no kernel work to do.
+            match scheduler.do_kernel_work_now(chip) {
+                true => {
+                    // Execute kernel work. This includes handling
+                    // interrupts and is how code in the chips/ and capsules
+                    // crates is able to execute.
+                    scheduler.execute_kernel_work(chip);
+                }
+                false => {
+                    // No kernel work ready, so ask scheduler for a process.
+                    match scheduler.next(self) {
+                        SchedulingDecision::RunProcess((appid, timeslice_us)) => {
+                            self.process_map_or((), appid, |process| {
+                                let (reason, time_executed) = self.do_process(
+                                    platform,
+                                    chip,
+                                    scheduler,
+                                    process,
+                                    ipc,
+                                    timeslice_us,
+                                );
+                                scheduler.result(reason, time_executed);
+                            });
+                        }
+                        SchedulingDecision::TrySleep => {
+                            // For testing, it may be helpful to
+                            // disable sleeping the chip in case
+                            // the running test does not generate
+                            // any interrupts.
+                            if !no_sleep {
                                 chip.atomic(|| {
                                     // Cannot sleep if interrupts are pending,
                                     // as on most platforms unhandled interrupts
@@ -523,6 +558,24 @@ impl Kernel {
         }
     }
 
+    /// Main loop of the OS.
+    ///
+    /// Most of the behavior of this loop is controlled by the `Scheduler`
+    /// implementation in use.
+    pub fn kernel_loop<P: Platform, C: Chip, SC: Scheduler<C>, const NUM_PROCS: usize>(
+        &self,
+        platform: &P,
+        chip: &C,
+        ipc: Option<&ipc::IPC<NUM_PROCS>>,
+        scheduler: &SC,
+        capability: &dyn capabilities::MainLoopCapability,
+    ) -> ! {
+        chip.watchdog().setup();
+        loop {
+            self.kernel_loop_operation(platform, chip, ipc, scheduler, false, capability);
+        }
+    }
+
     /// Transfer control from the kernel to a userspace process.
     ///
     /// This function is called by the main kernel loop to run userspace code.
@@ -788,6 +841,7 @@ impl Kernel {
             _ => {
                 // Check all other syscalls for filtering
                 if let Err(response) = platform.filter_syscall(process, &syscall) {
+                    process.debug_syscall_denied();
                     process.set_syscall_return_value(SyscallReturn::Failure(response));
 
                     return;
@@ -43,7 +43,7 @@ impl<C: Chip> Scheduler<C> for PrioritySched {
             let next = self
                 .kernel
                 .get_process_iter()
-                .find(|&proc| proc.ready())
+                .find(|proc| proc.ready())
                 .map_or(None, |proc| Some(proc.appid()));
             self.running.insert(next);
 
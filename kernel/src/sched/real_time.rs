@@ -0,0 +1,678 @@
+//! Real-time schedulers for Tock: earliest deadline first and rate monotonic.
+//!
+//! These schedulers run real-time processes, i.e. processes with real-time
+//! parameters in their TBF header, by the priority of their current job. The
+//! two schedulers only differ in how they assign priorities, which is
+//! selected by the `PriorityPolicy` parameter of `RealTimeSched`:
+//!
+//! - `EDFSched` (`EarliestDeadlineFirst`): the job whose absolute deadline is
+//!   closest runs first. EDF can meet all deadlines as long as the CPU time
+//!   the real-time processes need, the sum of `budget / deadline` over all
+//!   processes, is at most 100% (minus the time the kernel needs to handle
+//!   interrupts).
+//! - `RateMonotonicSched` (`RateMonotonic`): fixed priorities assigned by
+//!   period, so the process with the shortest period has the highest
+//!   priority. Unlike with EDF, the priority of a process does not change
+//!   from job to job, which makes it easy to reason about which processes
+//!   meet their deadlines when the CPU is overloaded: the processes with the
+//!   shortest periods do.
+//!
+//! The schedulers do not check whether all deadlines can be met; jobs that
+//! finish late are counted as deadline misses, see
+//! `KernelInfo::deadline_misses()`. A process that becomes ready with a
+//! higher priority than the running process pre-empts it. Jobs with the same
+//! priority run in the order of the process list.
+//!
+//! Real-time processes declare a period, a relative deadline and a budget in
+//! their TBF header (see `TbfHeaderV2RealTime`). The schedulers treat them as
+//! sporadic tasks:
+//!
+//! - Whenever a real-time process becomes ready to run it releases a job. The
+//!   job finishes once the process has no work left, i.e. it is no longer
+//!   ready.
+//! - A job is released no earlier than one period after the previous job of
+//!   the same process. Until then the process is held back.
+//! - A job must finish by its release time plus the deadline. A job that
+//!   finishes late is counted as a deadline miss in the debug information of
+//!   the process, as is a job that is still unfinished at its deadline (once).
+//! - A job may use up to its budget of CPU time. Once the budget is used up
+//!   the job is no longer considered real-time.
+//!
+//! Released jobs with budget left run in the order chosen by the scheduler.
+//! Held back jobs, jobs without budget and processes without real-time
+//! parameters run in the background, in round-robin order, whenever no
+//! released job is ready.
+
+use core::cell::Cell;
+use core::cmp;
+use core::marker::PhantomData;
+
+use crate::common::cells::OptionalCell;
+use crate::common::dynamic_deferred_call::DynamicDeferredCall;
+use crate::common::list::{List, ListLink, ListNode};
+use crate::hil::time::{self, Frequency, Ticks};
+use crate::platform::Chip;
+use crate::process::ProcessSlot;
+use crate::sched::MIN_QUANTA_THRESHOLD_US;
+use crate::sched::{Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason};
+use crate::upcall::AppId;
+use tock_tbf::types::TbfHeaderV2RealTime;
+
+/// How long a process running in the background can run before being
+/// pre-empted.
+const BACKGROUND_TIMESLICE_US: u32 = 10000;
+
+/// Shortest timeslice the schedulers hand out when they shorten a timeslice
+/// to notice a held back job being released.
+const MIN_TIMESLICE_US: u32 = 2 * MIN_QUANTA_THRESHOLD_US;
+
+/// How a real-time scheduler orders released jobs.
+pub trait PriorityPolicy {
+    /// The priority of a job with the absolute `deadline`, in ticks, of a
+    /// process with the real-time parameters `params`. Jobs with lower
+    /// values run first.
+    fn priority(deadline: u64, params: &TbfHeaderV2RealTime) -> u64;
+}
+
+/// Earliest deadline first: the job whose deadline is closest runs first.
+pub struct EarliestDeadlineFirst;
+
+impl PriorityPolicy for EarliestDeadlineFirst {
+    fn priority(deadline: u64, _params: &TbfHeaderV2RealTime) -> u64 {
+        deadline
+    }
+}
+
+/// Rate monotonic: the process with the shortest period runs first.
+pub struct RateMonotonic;
+
+impl PriorityPolicy for RateMonotonic {
+    fn priority(_deadline: u64, params: &TbfHeaderV2RealTime) -> u64 {
+        params.period_us() as u64
+    }
+}
+
+/// Earliest deadline first scheduler.
+pub type EDFSched<'a, A> = RealTimeSched<'a, A, EarliestDeadlineFirst>;
+
+/// Fixed priority scheduler with rate monotonic priorities.
+pub type RateMonotonicSched<'a, A> = RealTimeSched<'a, A, RateMonotonic>;
+
+/// A job of a real-time process.
+#[derive(Clone, Copy)]
+struct Job {
+    /// The process the job belongs to. Jobs of a process do not survive a
+    /// restart.
+    appid: AppId,
+    /// When the job is released, in ticks.
+    release: u64,
+    /// When the job must be finished, in ticks.
+    deadline: u64,
+    /// CPU time the job has used so far.
+    used_us: u32,
+    /// Whether the job was already counted as a deadline miss.
+    missed: bool,
+}
+
+/// Nodes store per-process state
+pub struct RealTimeProcessNode<'a> {
+    proc: &'static ProcessSlot,
+    job: Cell<Option<Job>>,
+    /// Process and release time of the most recent job, to hold back the next
+    /// job until a period has passed.
+    last_release: Cell<Option<(AppId, u64)>>,
+    next: ListLink<'a, RealTimeProcessNode<'a>>,
+}
+
+impl<'a> RealTimeProcessNode<'a> {
+    pub fn new(proc: &'static ProcessSlot) -> RealTimeProcessNode<'a> {
+        RealTimeProcessNode {
+            proc,
+            job: Cell::new(None),
+            last_release: Cell::new(None),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Release, finish and check the deadline of the job of this process.
+    fn update(&self, now: u64, frequency: u32) {
+        let (proc, params) = match self
+            .proc
+            .get()
+            .and_then(|proc| proc.get_real_time_parameters().map(|params| (proc, params)))
+        {
+            Some(proc_params) => proc_params,
+            None => {
+                self.job.set(None);
+                self.last_release.set(None);
+                return;
+            }
+        };
+        let appid = proc.appid();
+
+        match self.job.get().filter(|job| job.appid == appid) {
+            Some(mut job) => {
+                let late = now > job.deadline && !job.missed;
+                if late {
+                    proc.debug_deadline_missed();
+                    job.missed = true;
+                }
+                if proc.ready() {
+                    self.job.set(Some(job));
+                } else {
+                    self.job.set(None);
+                }
+            }
+            None if proc.ready() => {
+                let release = match self.last_release.get() {
+                    Some((last_appid, last)) if last_appid == appid => {
+                        cmp::max(now, last + ticks_from_us(params.period_us(), frequency))
+                    }
+                    _ => now,
+                };
+                self.job.set(Some(Job {
+                    appid,
+                    release,
+                    deadline: release + ticks_from_us(params.deadline_us(), frequency),
+                    used_us: 0,
+                    missed: false,
+                }));
+                self.last_release.set(Some((appid, release)));
+            }
+            None => self.job.set(None),
+        }
+    }
+
+    /// The released job of this process and its parameters, if the process
+    /// is ready and the job has enough budget left to run.
+    fn released_job(&self, now: u64) -> Option<(Job, TbfHeaderV2RealTime)> {
+        let proc = self.proc.get()?;
+        let params = proc.get_real_time_parameters()?;
+        self.job
+            .get()
+            .filter(|job| {
+                job.release <= now
+                    && remaining_budget_us(job, &params) > MIN_QUANTA_THRESHOLD_US
+                    && proc.ready()
+            })
+            .map(|job| (job, params))
+    }
+
+    /// Charge CPU time to the job of this process.
+    fn charge(&self, us: u32) {
+        self.job.set(self.job.get().map(|mut job| {
+            job.used_us = job.used_us.saturating_add(us);
+            job
+        }));
+    }
+}
+
+impl<'a> ListNode<'a, RealTimeProcessNode<'a>> for RealTimeProcessNode<'a> {
+    fn next(&'a self) -> &'static ListLink<'a, RealTimeProcessNode<'a>> {
+        &self.next
+    }
+}
+
+fn remaining_budget_us(job: &Job, params: &TbfHeaderV2RealTime) -> u32 {
+    params.budget_us().saturating_sub(job.used_us)
+}
+
+fn ticks_from_us(us: u32, frequency: u32) -> u64 {
+    us as u64 * frequency as u64 / 1_000_000
+}
+
+fn us_from_ticks(ticks: u64, frequency: u32) -> u64 {
+    ticks * 1_000_000 / frequency as u64
+}
+
+/// A monotonic 64 bit tick count built from an alarm, so that release times
+/// and deadlines do not wrap.
+///
+/// The count is only correct as long as it is read at least once per
+/// overflow of the alarm's counter. The schedulers read it every time they
+/// pick a process.
+struct RealTimeClock<A: 'static + time::Alarm<'static>> {
+    alarm: &'static A,
+    last: Cell<A::Ticks>,
+    ticks: Cell<u64>,
+}
+
+impl<A: 'static + time::Alarm<'static>> RealTimeClock<A> {
+    fn new(alarm: &'static A) -> Self {
+        Self {
+            alarm,
+            last: Cell::new(A::Ticks::from(0)),
+            ticks: Cell::new(0),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last.get()).into_u32();
+        self.last.set(now);
+        self.ticks.set(self.ticks.get() + elapsed as u64);
+        self.ticks.get()
+    }
+
+    fn frequency(&self) -> u32 {
+        A::Frequency::frequency()
+    }
+}
+
+/// The process a real-time scheduler chose to run.
+#[derive(Clone, Copy)]
+struct Running<'a> {
+    node: &'a RealTimeProcessNode<'a>,
+    /// The priority of the job being run, or `None` if the process runs in
+    /// the background.
+    priority: Option<u64>,
+}
+
+/// Real-time scheduler, ordering released jobs by the policy `P`.
+pub struct RealTimeSched<'a, A: 'static + time::Alarm<'static>, P: PriorityPolicy> {
+    clock: RealTimeClock<A>,
+    pub processes: List<'a, RealTimeProcessNode<'a>>,
+    running: OptionalCell<Running<'a>>,
+    /// The index of the process that last ran in the background, so that
+    /// background processes take turns.
+    last_background: Cell<usize>,
+    _policy: PhantomData<P>,
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, P: PriorityPolicy> RealTimeSched<'a, A, P> {
+    pub fn new(alarm: &'static A) -> Self {
+        Self {
+            clock: RealTimeClock::new(alarm),
+            processes: List::new(),
+            running: OptionalCell::empty(),
+            last_background: Cell::new(0),
+            _policy: PhantomData,
+        }
+    }
+
+    /// Update the jobs of all processes.
+    fn update_jobs(&self, now: u64) {
+        let frequency = self.clock.frequency();
+        for node in self.processes.iter() {
+            node.update(now, frequency);
+        }
+    }
+
+    /// Find the released job with the highest priority, i.e. the lowest
+    /// value of `P::priority()`. Ties go to the process that comes first in
+    /// the list.
+    fn highest_priority_job(
+        &self,
+        now: u64,
+    ) -> Option<(&'a RealTimeProcessNode<'a>, Job, TbfHeaderV2RealTime, u64)> {
+        let mut highest: Option<(&'a RealTimeProcessNode<'a>, Job, TbfHeaderV2RealTime, u64)> =
+            None;
+        for node in self.processes.iter() {
+            if let Some((job, params)) = node.released_job(now) {
+                let job_priority = P::priority(job.deadline, &params);
+                if highest.map_or(true, |(_, _, _, p)| job_priority < p) {
+                    highest = Some((node, job, params, job_priority));
+                }
+            }
+        }
+        highest
+    }
+
+    /// Choose the process to run next and its timeslice.
+    ///
+    /// Returns `None` if no process is ready.
+    fn choose(&self, now: u64) -> Option<(Running<'a>, AppId, u32)> {
+        let frequency = self.clock.frequency();
+        // Shorten the timeslice to notice when a held back job is released.
+        let until_release_us = self
+            .processes
+            .iter()
+            .filter_map(|node| node.job.get())
+            .filter(|job| job.release > now)
+            .map(|job| us_from_ticks(job.release - now, frequency))
+            .min()
+            .map(|us| cmp::max(cmp::min(us, u32::MAX as u64) as u32, MIN_TIMESLICE_US));
+
+        if let Some((node, job, params, job_priority)) = self.highest_priority_job(now) {
+            let budget_us = remaining_budget_us(&job, &params);
+            let timeslice = until_release_us.map_or(budget_us, |us| cmp::min(us, budget_us));
+            let appid = node.proc.get()?.appid();
+            return Some((
+                Running {
+                    node,
+                    priority: Some(job_priority),
+                },
+                appid,
+                timeslice,
+            ));
+        }
+
+        // No released job is ready, so run the next ready process in the
+        // background.
+        let start = self.last_background.get() + 1;
+        let (index, node) = self
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.proc.get().map_or(false, |proc| proc.ready()))
+            .min_by_key(|(index, _)| (*index < start, *index))?;
+        self.last_background.set(index);
+        let timeslice = until_release_us.map_or(BACKGROUND_TIMESLICE_US, |us| {
+            cmp::min(us, BACKGROUND_TIMESLICE_US)
+        });
+        let appid = node.proc.get()?.appid();
+        Some((
+            Running {
+                node,
+                priority: None,
+            },
+            appid,
+            timeslice,
+        ))
+    }
+
+    /// Whether a released job has a higher priority than the running process.
+    fn preempts(&self, running: Running<'a>) -> bool {
+        let now = self.clock.now();
+        self.update_jobs(now);
+        self.highest_priority_job(now)
+            .map_or(false, |(node, _, _, job_priority)| {
+                !core::ptr::eq(node, running.node)
+                    && running.priority.map_or(true, |p| job_priority < p)
+            })
+    }
+}
+
+impl<'a, A: 'static + time::Alarm<'static>, P: PriorityPolicy, C: Chip> Scheduler<C>
+    for RealTimeSched<'a, A, P>
+{
+    fn next(&self, kernel: &Kernel) -> SchedulingDecision {
+        // Jobs must be updated even if no process is ready, so that the time a
+        // job finished is recorded correctly.
+        let now = self.clock.now();
+        self.update_jobs(now);
+
+        if kernel.processes_blocked() {
+            // No processes ready
+            SchedulingDecision::TrySleep
+        } else {
+            let (running, next, timeslice) = self.choose(now).unwrap(); // Panic if fail bc processes_blocked()!
+            self.running.set(running);
+
+            SchedulingDecision::RunProcess((next, Some(timeslice)))
+        }
+    }
+
+    fn result(&self, _: StoppedExecutingReason, execution_time_us: Option<u32>) {
+        // Only time spent running as a real-time job counts towards its
+        // budget.
+        self.running.take().map(|running| {
+            if running.priority.is_some() {
+                execution_time_us.map(|us| running.node.charge(us));
+            }
+        });
+    }
+
+    unsafe fn continue_process(&self, _: AppId, chip: &C) -> bool {
+        // In addition to checking for kernel work, also checks if a higher
+        // priority job has become ready, e.g. because the running process
+        // communicates with it via IPC.
+        !(chip.has_pending_interrupts()
+            || DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
+            || self
+                .running
+                .map_or(false, |running| self.preempts(*running)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::{EDFSched, RateMonotonicSched, RealTimeProcessNode, RealTimeSched};
+    use super::{EarliestDeadlineFirst, PriorityPolicy, RateMonotonic};
+    use crate::hil::time::{self, AlarmClient, Freq1MHz, Ticks, Ticks32};
+    use crate::process::{FunctionCall, FunctionCallSource, Task};
+    use crate::sched::{Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason};
+    use crate::test_util::{app_with_tlvs, loader, Capability, TestChip};
+    use crate::ReturnCode;
+
+    /// An alarm that counts microseconds, whose clock only moves when the
+    /// test advances it. The schedulers only read the clock.
+    struct TestAlarm {
+        now: Cell<Ticks32>,
+    }
+
+    impl TestAlarm {
+        fn advance(&self, us: u32) {
+            self.now.set(self.now.get().wrapping_add(us.into()));
+        }
+    }
+
+    impl time::Time for TestAlarm {
+        type Frequency = Freq1MHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get()
+        }
+    }
+
+    impl<'a> time::Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {}
+
+        fn get_alarm(&self) -> Ticks32 {
+            0.into()
+        }
+
+        fn disarm(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+
+        fn is_armed(&self) -> bool {
+            false
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// Build a TBF image for an app with the given name and real-time
+    /// parameters.
+    fn real_time_app(
+        name: &[u8],
+        period_us: u32,
+        deadline_us: u32,
+        budget_us: u32,
+    ) -> &'static [u8] {
+        let mut tlv: Vec<u8> = Vec::new();
+        tlv.extend_from_slice(&7u16.to_le_bytes());
+        tlv.extend_from_slice(&12u16.to_le_bytes());
+        tlv.extend_from_slice(&period_us.to_le_bytes());
+        tlv.extend_from_slice(&deadline_us.to_le_bytes());
+        tlv.extend_from_slice(&budget_us.to_le_bytes());
+        app_with_tlvs(name, &tlv)
+    }
+
+    /// Loads `apps` and creates a real-time scheduler for them.
+    fn real_time<P: 'static + PriorityPolicy>(
+        apps: &[&'static [u8]],
+    ) -> (
+        &'static Kernel,
+        &'static TestAlarm,
+        &'static RealTimeSched<'static, TestAlarm, P>,
+    ) {
+        let (kernel, loader) = loader();
+        for app in apps {
+            loader.load_process(app, &Capability).unwrap().unwrap();
+        }
+
+        let alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm {
+            now: Cell::new(0.into()),
+        }));
+        let scheduler: &'static RealTimeSched<'static, TestAlarm, P> =
+            Box::leak(Box::new(RealTimeSched::new(alarm)));
+        for slot in kernel.process_slots().iter() {
+            scheduler
+                .processes
+                .push_tail(Box::leak(Box::new(RealTimeProcessNode::new(slot))));
+        }
+        (kernel, alarm, scheduler)
+    }
+
+    /// Asks `scheduler` for the next process and returns the index of the
+    /// process and its timeslice, or `None` if it asks the kernel to sleep.
+    fn next<S: Scheduler<TestChip>>(kernel: &Kernel, scheduler: &S) -> Option<(usize, u32)> {
+        match scheduler.next(kernel) {
+            SchedulingDecision::RunProcess((appid, timeslice)) => {
+                Some((appid.index, timeslice.unwrap()))
+            }
+            SchedulingDecision::TrySleep => None,
+        }
+    }
+
+    /// Tells `scheduler` that the process it chose ran for `us` microseconds.
+    fn ran<S: Scheduler<TestChip>>(scheduler: &S, us: u32) {
+        scheduler.result(StoppedExecutingReason::TimesliceExpired, Some(us));
+    }
+
+    /// Makes the `n`th process ready, as if an upcall was scheduled for it.
+    fn make_ready(kernel: &Kernel, n: usize) {
+        let process = kernel.process_slots()[n].get().unwrap();
+        assert!(process.enqueue_task(Task::FunctionCall(FunctionCall {
+            source: FunctionCallSource::Kernel,
+            pc: 0,
+            argument0: 0,
+            argument1: 0,
+            argument2: 0,
+            argument3: 0,
+        })));
+    }
+
+    /// Makes the `n`th process wait, as if it handled all its upcalls.
+    fn finish(kernel: &Kernel, n: usize) {
+        let process = kernel.process_slots()[n].get().unwrap();
+        while process.dequeue_task().is_some() {}
+    }
+
+    fn deadline_misses(kernel: &Kernel, n: usize) -> usize {
+        let process = kernel.process_slots()[n].get().unwrap();
+        process.debug_deadline_miss_count()
+    }
+
+    #[test]
+    fn jobs_are_released_once_per_period() {
+        let (kernel, alarm, scheduler) =
+            real_time::<EarliestDeadlineFirst>(&[real_time_app(b"rt", 10000, 5000, 2000)]);
+
+        // The first job is released as soon as the process is ready, and may
+        // run for its budget.
+        assert_eq!(next(kernel, scheduler), Some((0, 2000)));
+        ran(scheduler, 1000);
+        alarm.advance(1000);
+        finish(kernel, 0);
+        assert_eq!(next(kernel, scheduler), None);
+
+        // The next job is held back until a period after the first. Until
+        // then the process only runs in the background, until the job is
+        // released.
+        alarm.advance(2000);
+        make_ready(kernel, 0);
+        assert_eq!(next(kernel, scheduler), Some((0, 7000)));
+        ran(scheduler, 3000);
+        alarm.advance(7000);
+
+        // Time in the background did not count towards the budget of the job.
+        assert_eq!(next(kernel, scheduler), Some((0, 2000)));
+        ran(scheduler, 1200);
+        alarm.advance(1200);
+        assert_eq!(next(kernel, scheduler), Some((0, 800)));
+        assert_eq!(deadline_misses(kernel, 0), 0);
+    }
+
+    /// Starts a process with a long period, then makes a process with a
+    /// shorter period, but a later deadline, ready while it runs. Returns
+    /// whether the scheduler lets the first process continue.
+    fn preempt<P: 'static + PriorityPolicy>() -> (
+        bool,
+        &'static Kernel,
+        &'static RealTimeSched<'static, TestAlarm, P>,
+    ) {
+        let (kernel, alarm, scheduler) = real_time::<P>(&[
+            real_time_app(b"long", 20000, 20000, 15000),
+            real_time_app(b"short", 10000, 10000, 2000),
+        ]);
+        finish(kernel, 1);
+
+        assert_eq!(next(kernel, scheduler), Some((0, 15000)));
+        let long = kernel.process_slots()[0].get().unwrap().appid();
+        alarm.advance(12000);
+        assert!(unsafe { Scheduler::<TestChip>::continue_process(scheduler, long, &TestChip) });
+
+        // The job of the second process is due at 22 ms, after the job of
+        // the first process.
+        make_ready(kernel, 1);
+        let continues =
+            unsafe { Scheduler::<TestChip>::continue_process(scheduler, long, &TestChip) };
+        ran(scheduler, 12000);
+        (continues, kernel, scheduler)
+    }
+
+    #[test]
+    fn edf_runs_the_earliest_deadline() {
+        let (continues, kernel, scheduler) = preempt::<EarliestDeadlineFirst>();
+        let _: &EDFSched<TestAlarm> = scheduler;
+        assert!(continues);
+        assert_eq!(next(kernel, scheduler), Some((0, 3000)));
+    }
+
+    #[test]
+    fn rate_monotonic_runs_the_shortest_period() {
+        let (continues, kernel, scheduler) = preempt::<RateMonotonic>();
+        let _: &RateMonotonicSched<TestAlarm> = scheduler;
+        assert!(!continues);
+        assert_eq!(next(kernel, scheduler), Some((1, 2000)));
+    }
+
+    #[test]
+    fn deadline_misses_are_counted_once() {
+        let (kernel, alarm, scheduler) =
+            real_time::<EarliestDeadlineFirst>(&[real_time_app(b"rt", 10000, 5000, 2000)]);
+
+        assert_eq!(next(kernel, scheduler), Some((0, 2000)));
+        ran(scheduler, 1000);
+
+        // The job is still running at its deadline.
+        alarm.advance(6000);
+        assert_eq!(next(kernel, scheduler), Some((0, 1000)));
+        assert_eq!(deadline_misses(kernel, 0), 1);
+        ran(scheduler, 1000);
+        alarm.advance(1000);
+
+        // Once its budget is used up the job continues in the background,
+        // and is not counted again when it finishes.
+        assert_eq!(next(kernel, scheduler), Some((0, 10000)));
+        ran(scheduler, 1000);
+        alarm.advance(1000);
+        finish(kernel, 0);
+        assert_eq!(next(kernel, scheduler), None);
+        assert_eq!(deadline_misses(kernel, 0), 1);
+
+        // The next job finishes in time.
+        alarm.advance(2000);
+        make_ready(kernel, 0);
+        assert_eq!(next(kernel, scheduler), Some((0, 2000)));
+        ran(scheduler, 500);
+        alarm.advance(500);
+        finish(kernel, 0);
+        assert_eq!(next(kernel, scheduler), None);
+        assert_eq!(deadline_misses(kernel, 0), 1);
+    }
+}
@@ -16,19 +16,19 @@
 
 use crate::common::list::{List, ListLink, ListNode};
 use crate::platform::Chip;
-use crate::procs::ProcessType;
+use crate::procs::ProcessSlot;
 use crate::sched::{Kernel, Scheduler, SchedulingDecision, StoppedExecutingReason};
 use core::cell::Cell;
 
 /// A node in the linked list the scheduler uses to track processes
 /// Each node holds a pointer to a slot in the processes array
 pub struct RoundRobinProcessNode<'a> {
-    proc: &'static Option<&'static dyn ProcessType>,
+    proc: &'static ProcessSlot,
     next: ListLink<'a, RoundRobinProcessNode<'a>>,
 }
 
 impl<'a> RoundRobinProcessNode<'a> {
-    pub fn new(proc: &'static Option<&'static dyn ProcessType>) -> RoundRobinProcessNode<'a> {
+    pub fn new(proc: &'static ProcessSlot) -> RoundRobinProcessNode<'a> {
         RoundRobinProcessNode {
             proc,
             next: ListLink::empty(),
@@ -73,7 +73,7 @@ impl<'a, C: Chip> Scheduler<C> for RoundRobinSched<'a> {
             // Find next ready process. Place any *empty* process slots, or not-ready
             // processes, at the back of the queue.
             for node in self.processes.iter() {
-                match node.proc {
+                match node.proc.get() {
                     Some(proc) => {
                         if proc.ready() {
                             next = Some(proc.appid());
@@ -0,0 +1,182 @@
+//! Helpers for the unit tests of the kernel, which create processes without
+//! running them.
+
+extern crate std;
+
+use core::convert::TryInto;
+use core::fmt::Write;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::capabilities::ProcessManagementCapability;
+use crate::platform::Chip;
+use crate::process::{self, FaultResponse, ProcessSlot};
+use crate::process_checker::NullCredentialsChecker;
+use crate::process_loader::ProcessLoader;
+use crate::sched::Kernel;
+use crate::syscall::{ContextSwitchReason, SyscallReturn, UserspaceKernelBoundary};
+
+/// Chip without an MPU whose processes never run, which is enough to
+/// create processes.
+pub(crate) struct TestChip;
+
+impl UserspaceKernelBoundary for TestChip {
+    type StoredState = ();
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+        _return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+        _upcall: process::FunctionCall,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &mut (),
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        (ContextSwitchReason::Interrupted, None)
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        _state: &(),
+        _writer: &mut dyn Write,
+    ) {
+    }
+}
+
+impl Chip for TestChip {
+    type MPU = ();
+    type UserspaceKernelBoundary = TestChip;
+    type SchedulerTimer = ();
+    type WatchDog = ();
+
+    fn service_pending_interrupts(&self) {}
+
+    fn has_pending_interrupts(&self) -> bool {
+        false
+    }
+
+    fn mpu(&self) -> &() {
+        &()
+    }
+
+    fn scheduler_timer(&self) -> &() {
+        &()
+    }
+
+    fn watchdog(&self) -> &() {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &TestChip {
+        self
+    }
+
+    fn sleep(&self) {}
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+
+    unsafe fn print_state(&self, _writer: &mut dyn Write) {}
+}
+
+pub(crate) struct Capability;
+unsafe impl ProcessManagementCapability for Capability {}
+
+/// A kernel with two process slots and room for two apps, and a loader
+/// for it.
+pub(crate) fn loader() -> (&'static Kernel, ProcessLoader<TestChip>) {
+    let slots: &'static [ProcessSlot] = Box::leak(Box::new([ProcessSlot::EMPTY; 2]));
+    let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(slots)));
+    // Use `u64`s so that the memory is aligned for the process structs.
+    let memory: &'static mut [u64] = Box::leak(std::vec![0u64; 0x4000 / 8].into_boxed_slice());
+    kernel.set_app_memory(memory.as_mut_ptr() as *mut u8, 0x4000);
+    let checker: &'static NullCredentialsChecker =
+        Box::leak(Box::new(NullCredentialsChecker::new()));
+    let loader = ProcessLoader::new(
+        kernel,
+        &TestChip,
+        &[],
+        FaultResponse::Stop,
+        checker,
+        &Capability,
+    );
+    (kernel, loader)
+}
+
+/// Build an enabled TBF image for an app that needs 4 kB of memory.
+pub(crate) fn app(name: &[u8]) -> &'static [u8] {
+    app_with_tlvs(name, &[])
+}
+
+/// Build a TBF image like `app()`, followed by the given TLV entries in its
+/// header.
+pub(crate) fn app_with_tlvs(name: &[u8], tlvs: &[u8]) -> &'static [u8] {
+    let mut header: Vec<u8> = std::vec![0; 16];
+    // Main: init_fn_offset, protected_size and minimum_ram_size.
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&12u16.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&0x1000u32.to_le_bytes());
+    // Package name.
+    header.extend_from_slice(&3u16.to_le_bytes());
+    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    header.extend_from_slice(name);
+    while header.len() % 4 != 0 {
+        header.push(0);
+    }
+    header.extend_from_slice(tlvs);
+
+    let header_size = header.len() as u16;
+    let total_size = 256u32;
+    header[0..2].copy_from_slice(&2u16.to_le_bytes());
+    header[2..4].copy_from_slice(&header_size.to_le_bytes());
+    header[4..8].copy_from_slice(&total_size.to_le_bytes());
+    header[8..12].copy_from_slice(&1u32.to_le_bytes());
+    let checksum = header
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |acc, (_, chunk)| {
+            acc ^ u32::from_le_bytes(chunk.try_into().unwrap())
+        });
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+    header.resize(total_size as usize, 0);
+    Box::leak(header.into_boxed_slice())
+}
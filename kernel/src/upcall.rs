@@ -11,6 +11,8 @@ use crate::sched::Kernel;
 use crate::syscall::SyscallReturn;
 use crate::ErrorCode;
 
+use tock_tbf::types::TbfHeaderV2Credentials;
+
 /// Userspace app identifier.
 ///
 /// This should be treated as an opaque type that can be used to represent an
@@ -153,6 +155,26 @@ impl AppId {
             (start, end)
         })
     }
+
+    /// Returns the package name of the app from its TBF header.
+    ///
+    /// Returns `None` if the app no longer exists.
+    pub fn get_process_name(&self) -> Option<&'static str> {
+        self.kernel
+            .process_map_or(None, *self, |process| Some(process.get_process_name()))
+    }
+
+    /// Returns the credential of the app that was accepted when it was
+    /// loaded. Unlike `id()` this stays the same when the app restarts or the
+    /// board reboots, and unlike the package name another app can not claim
+    /// it, so capsules can use it to identify the app's persistent data.
+    ///
+    /// Returns `None` if the app no longer exists or runs without an accepted
+    /// credential.
+    pub fn get_credentials(&self) -> Option<TbfHeaderV2Credentials> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_credentials())
+    }
 }
 
 /// Type to uniquely identify an upcall subscription across all drivers.
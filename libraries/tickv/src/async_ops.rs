@@ -100,8 +100,8 @@
 //! // when appending a key:
 //!
 //! // Add a key
-//! static VALUE: [u8; 32] = [0x23; 32];
-//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &VALUE) };
+//! static mut VALUE: [u8; 32] = [0x23; 32];
+//! let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
 //!
 //! match ret {
 //!     Err(ErrorCode::ReadNotReady(reg)) => {
@@ -126,7 +126,7 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
-use crate::tickv::{State, TicKV};
+use crate::tickv::{KeyCursor, State, TicKV};
 use core::cell::Cell;
 
 /// The return type from the continue operation
@@ -142,8 +142,11 @@ pub struct AsyncTicKV<'a, C: FlashController<S>, const S: usize> {
     /// The main TicKV struct
     pub tickv: TicKV<'a, C, S>,
     key: Cell<Option<u64>>,
-    value: Cell<Option<&'static [u8]>>,
+    value: Cell<Option<&'static mut [u8]>>,
+    value_len: Cell<usize>,
     buf: Cell<Option<&'static mut [u8]>>,
+    len: Cell<Option<usize>>,
+    cursor: Cell<Option<KeyCursor>>,
 }
 
 impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
@@ -158,7 +161,10 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             tickv: TicKV::<C, S>::new(controller, read_buffer, flash_size),
             key: Cell::new(None),
             value: Cell::new(None),
+            value_len: Cell::new(0),
             buf: Cell::new(None),
+            len: Cell::new(None),
+            cursor: Cell::new(None),
         }
     }
 
@@ -182,18 +188,27 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     /// `hash`: A hashed key. This key will be used in future to retrieve
     ///         or remove the `value`.
     /// `value`: A buffer containing the data to be stored to flash.
+    /// `length`: The number of bytes of `value` to store.
     ///
     /// On success nothing will be returned.
     /// On error a `ErrorCode` will be returned.
-    pub fn append_key(&self, hash: u64, value: &'static [u8]) -> Result<SuccessCode, ErrorCode> {
-        match self.tickv.append_key(hash, value) {
-            Ok(code) => Ok(code),
-            Err(e) => {
-                self.key.replace(Some(hash));
-                self.value.replace(Some(value));
-                Err(e)
-            }
-        }
+    ///
+    /// The `value` buffer is kept until it is retrieved with
+    /// `get_stored_value_buffer()`.
+    pub fn append_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let ret = match value.get(..length) {
+            Some(data) => self.tickv.append_key(hash, data),
+            None => Err(ErrorCode::BufferTooSmall(value.len())),
+        };
+        self.key.replace(Some(hash));
+        self.value.replace(Some(value));
+        self.value_len.set(length);
+        ret
     }
 
     /// Retrieves the value from flash storage.
@@ -226,6 +241,74 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         }
     }
 
+    /// Retrieves the length of the value of a key from flash storage.
+    ///
+    /// `hash`: A hashed key.
+    ///
+    /// On success the length of the value will be returned.
+    /// On error a `ErrorCode` will be returned. If the operation was
+    /// continued with `continue_operation()` the length can be retrieved
+    /// with `get_stored_len()`.
+    pub fn get_key_len(&self, hash: u64) -> Result<usize, ErrorCode> {
+        match self.tickv.get_key_len(hash) {
+            Ok(len) => Ok(len),
+            Err(e) => {
+                self.key.replace(Some(hash));
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the value of a key in flash storage.
+    ///
+    /// `hash`: A hashed key. The key must already exist.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    /// `length`: The number of bytes of `value` to store.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    ///
+    /// This performs more than one write, so `continue_operation()` must
+    /// also be called from a write complete callback. As for `append_key()`
+    /// the `value` buffer is kept until it is retrieved with
+    /// `get_stored_value_buffer()`.
+    pub fn update_key(
+        &self,
+        hash: u64,
+        value: &'static mut [u8],
+        length: usize,
+    ) -> Result<SuccessCode, ErrorCode> {
+        let ret = match value.get(..length) {
+            Some(data) => self.tickv.update_key(hash, data),
+            None => Err(ErrorCode::BufferTooSmall(value.len())),
+        };
+        self.key.replace(Some(hash));
+        self.value.replace(Some(value));
+        self.value_len.set(length);
+        ret
+    }
+
+    /// Finds the next valid key in flash storage.
+    ///
+    /// `cursor`: The position to continue from. A new `KeyCursor` starts at
+    ///           the first key.
+    ///
+    /// On success the hashed key and the length of its value will be
+    /// returned, and the cursor is moved past that key.
+    /// On error a `ErrorCode` will be returned. `KeyNotFound` is returned
+    /// once every key has been found. If the operation was continued with
+    /// `continue_operation()` the moved cursor, which holds the key that was
+    /// found, can be retrieved with `get_stored_cursor()`.
+    pub fn next_key(&self, cursor: &mut KeyCursor) -> Result<(u64, usize), ErrorCode> {
+        match self.tickv.next_key(cursor) {
+            Ok(key) => Ok(key),
+            Err(e) => {
+                self.cursor.replace(Some(*cursor));
+                Err(e)
+            }
+        }
+    }
+
     /// Invalidates the key in flash storage
     ///
     /// `hash`: A hashed key.
@@ -266,7 +349,7 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
 
     /// Get the `value` buffer that was passed in by previous
     /// commands.
-    pub fn get_stored_value_buffer(&self) -> Option<&'static [u8]> {
+    pub fn get_stored_value_buffer(&self) -> Option<&'static mut [u8]> {
         self.value.take()
     }
 
@@ -276,8 +359,20 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
         self.buf.take()
     }
 
+    /// Get the length of the value found by a previous `get_key_len()`
+    /// command.
+    pub fn get_stored_len(&self) -> Option<usize> {
+        self.len.take()
+    }
+
+    /// Get the cursor moved by a previous `next_key()` command.
+    pub fn get_stored_cursor(&self) -> Option<KeyCursor> {
+        self.cursor.take()
+    }
+
     /// Continue the last operation after the async operation has completed.
-    /// This should be called from a read/erase complete callback.
+    /// This should be called from a read/erase complete callback, and from
+    /// a write complete callback when running `update_key()`.
     /// NOTE: If called from a read callback, `set_read_buffer` should be
     /// called first to update the data.
     ///
@@ -294,15 +389,41 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
     pub fn continue_operation(&self) -> ContinueReturn {
         let ret = match self.tickv.state.get() {
             State::Init(_) => self.tickv.initalise(self.key.get().unwrap()),
-            State::AppendKey(_) => self
-                .tickv
-                .append_key(self.key.get().unwrap(), self.value.get().unwrap()),
+            State::AppendKey(_) => {
+                let value = self.value.take().unwrap();
+                let ret = self
+                    .tickv
+                    .append_key(self.key.get().unwrap(), &value[..self.value_len.get()]);
+                self.value.replace(Some(value));
+                ret
+            }
             State::GetKey(_) => {
                 let buf = self.buf.take().unwrap();
                 let ret = self.tickv.get_key(self.key.get().unwrap(), buf);
                 self.buf.replace(Some(buf));
                 ret
             }
+            State::GetKeyLen(_) => match self.tickv.get_key_len(self.key.get().unwrap()) {
+                Ok(len) => {
+                    self.len.replace(Some(len));
+                    Ok(SuccessCode::Complete)
+                }
+                Err(e) => Err(e),
+            },
+            State::UpdateKey(_) => {
+                let value = self.value.take().unwrap();
+                let ret = self
+                    .tickv
+                    .update_key(self.key.get().unwrap(), &value[..self.value_len.get()]);
+                self.value.replace(Some(value));
+                ret
+            }
+            State::NextKey(_) => {
+                let mut cursor = self.cursor.get().unwrap();
+                let ret = self.tickv.next_key(&mut cursor);
+                self.cursor.replace(Some(cursor));
+                ret.map(|_| SuccessCode::Complete)
+            }
             State::InvalidateKey(_) => self.tickv.invalidate_key(self.key.get().unwrap()),
             State::GarbageCollect(_) => match self.tickv.garbage_collect() {
                 Ok(_) => Ok(SuccessCode::Complete),
@@ -319,7 +440,12 @@ impl<'a, C: FlashController<S>, const S: usize> AsyncTicKV<'a, C, S> {
             Err(e) => match e {
                 ErrorCode::ReadNotReady(_) | ErrorCode::EraseNotReady(_) => (ret, None),
                 ErrorCode::WriteNotReady(_) => {
-                    self.tickv.state.set(State::None);
+                    // Operations with more than one write continue once the
+                    // write has completed
+                    match self.tickv.state.get() {
+                        State::UpdateKey(_) | State::InvalidateKey(_) => {}
+                        _ => self.tickv.state.set(State::None),
+                    }
                     (ret, None)
                 }
                 _ => {
@@ -337,7 +463,7 @@ mod store_flast_ctrl {
     use crate::async_ops::AsyncTicKV;
     use crate::error_codes::ErrorCode;
     use crate::flash_controller::FlashController;
-    use crate::tickv::{HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
+    use crate::tickv::{KeyCursor, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
     use core::hash::{Hash, Hasher};
     use std::cell::Cell;
     use std::cell::RefCell;
@@ -542,9 +668,10 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
 
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -555,7 +682,8 @@ mod store_flast_ctrl {
             _ => unreachable!(),
         }
 
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"TWO"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -582,11 +710,12 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -617,7 +746,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE again");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -632,7 +762,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key TWO");
-        let ret = tickv.append_key(get_hashed_key(b"TWO"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"TWO"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -707,11 +838,12 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -764,7 +896,7 @@ mod store_flast_ctrl {
             ret = r;
         }
 
-        static VALUE: [u8; 32] = [0x23; 32];
+        static mut VALUE: [u8; 32] = [0x23; 32];
         static mut BUF: [u8; 32] = [0; 32];
 
         println!("Garbage collect empty flash");
@@ -778,7 +910,8 @@ mod store_flast_ctrl {
         }
 
         println!("Add key ONE");
-        let ret = tickv.append_key(get_hashed_key(b"ONE"), &VALUE);
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
         match ret {
             Err(ErrorCode::ReadNotReady(reg)) => {
                 // There is no actual delay in the test, just continue now
@@ -860,6 +993,115 @@ mod store_flast_ctrl {
         }
 
         println!("Add Key ONE");
-        tickv.append_key(get_hashed_key(b"ONE"), &VALUE).unwrap();
+        #[allow(unsafe_code)]
+        unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) }.unwrap();
+    }
+
+    #[test]
+    fn test_update_key() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+
+        let tickv = AsyncTicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+
+        let mut ret = tickv.initalise(hash_function.finish());
+        while ret.is_err() {
+            // There is no actual delay in the test, just continue now
+            let (r, _buf) = tickv.continue_operation();
+            ret = r;
+        }
+
+        static mut VALUE: [u8; 32] = [0x23; 32];
+        static mut NEW_VALUE: [u8; 16] = [0x42; 16];
+        static mut BUF: [u8; 32] = [0; 32];
+
+        println!("Add key ONE");
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"ONE"), &mut VALUE, 32) };
+        match ret {
+            Err(ErrorCode::ReadNotReady(reg)) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+            }
+            Ok(_) => {}
+            _ => unreachable!(),
+        }
+
+        println!("Add key TWO");
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.append_key(get_hashed_key(b"TWO"), &mut VALUE, 32) };
+        match ret {
+            Err(ErrorCode::ReadNotReady(reg)) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+            }
+            Ok(_) => {}
+            _ => unreachable!(),
+        }
+
+        println!("Update key ONE");
+        #[allow(unsafe_code)]
+        let ret = unsafe { tickv.update_key(get_hashed_key(b"ONE"), &mut NEW_VALUE, 16) };
+        match ret {
+            Err(ErrorCode::ReadNotReady(reg)) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+            }
+            Ok(_) => {}
+            _ => unreachable!("ret: {:?}", ret),
+        }
+
+        println!("Get length of key ONE");
+        let ret = tickv.get_key_len(get_hashed_key(b"ONE"));
+        match ret {
+            Err(ErrorCode::ReadNotReady(reg)) => {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                tickv.continue_operation().0.unwrap();
+                assert_eq!(tickv.get_stored_len(), Some(16));
+            }
+            Ok(len) => assert_eq!(len, 16),
+            _ => unreachable!("ret: {:?}", ret),
+        }
+
+        println!("Get key ONE");
+        #[allow(unsafe_code)]
+        unsafe {
+            tickv.get_key(get_hashed_key(b"ONE"), &mut BUF).unwrap();
+            assert_eq!(&BUF[..16], &NEW_VALUE);
+        }
+
+        println!("Find all keys");
+        let mut cursor = KeyCursor::new();
+        let mut keys = vec![];
+        loop {
+            let mut ret = tickv.next_key(&mut cursor);
+            while let Err(ErrorCode::ReadNotReady(reg)) = ret {
+                // There is no actual delay in the test, just continue now
+                tickv.set_read_buffer(&tickv.tickv.controller.buf.borrow()[reg]);
+                ret = tickv.continue_operation().0.map(|_| {
+                    cursor = tickv.get_stored_cursor().unwrap();
+                    cursor.key().unwrap()
+                });
+            }
+
+            match ret {
+                Ok(key) => keys.push(key),
+                Err(ErrorCode::KeyNotFound) => break,
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+        keys.sort();
+        let mut expected = vec![
+            (hash_function.finish(), 0),
+            (get_hashed_key(b"ONE"), 16),
+            (get_hashed_key(b"TWO"), 32),
+        ];
+        expected.sort();
+        assert_eq!(keys, expected);
     }
 }
@@ -161,6 +161,8 @@
 //! ```
 //!
 //! You can then use the `get_key()` function to get the key back from flash.
+//! `get_key_len()` returns the length of a value, `update_key()` replaces a
+//! value and `next_key()` iterates over the stored keys with a `KeyCursor`.
 //!
 //! # Collisions
 //!
@@ -178,6 +180,10 @@
 //! before it has completed then the operation probably did not complete and
 //! that data is lost.
 //!
+//! `update_key()` replaces the value of a key without this risk: if a power
+//! loss occurs before it has completed the key has either its previous or its
+//! new value.
+//!
 //! To help reduce this time to be as short as possible the `FlashController`
 //! is synchronous. Although flash writes can take a considerable amount of time
 //! and this will stall the application, this still seems like a good idea
@@ -221,6 +227,7 @@ pub use crate::async_ops::AsyncTicKV;
 pub use crate::error_codes::ErrorCode;
 #[doc(inline)]
 pub use crate::flash_controller::FlashController;
+pub use crate::tickv::KeyCursor;
 #[doc(inline)]
 pub use crate::tickv::TicKV;
 pub use crate::tickv::MAIN_KEY;
@@ -1,5 +1,6 @@
 use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
+use crate::success_codes::SuccessCode;
 use crate::tickv::{TicKV, HASH_OFFSET, LEN_OFFSET, MAIN_KEY, VERSION, VERSION_OFFSET};
 use core::hash::{Hash, Hasher};
 use std::cell::Cell;
@@ -557,4 +558,441 @@ mod no_check_store_flast_ctrl {
             Err(ErrorCode::KeyNotFound)
         );
     }
+
+    #[test]
+    fn test_append_region_boundary() {
+        let mut read_buf: [u8; 256] = [0; 256];
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        let hash = hash_function.finish();
+
+        let tickv = TicKV::<FlashCtrl, 256>::new(FlashCtrl::new(), &mut read_buf, 0x200);
+        tickv.initalise(hash).unwrap();
+
+        // The header and value fit in a region, but the check sum does not
+        println!("Add Key ONE");
+        assert_eq!(
+            tickv.append_key(get_hashed_key(b"ONE"), &[0x23; 256 - 15 + 1]),
+            Err(ErrorCode::FlashFull)
+        );
+
+        // The object fills the empty region exactly
+        println!("Add Key TWO");
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &[0x23; 256 - 15])
+            .unwrap();
+        let mut buf: [u8; 256] = [0; 256];
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf),
+            Ok(SuccessCode::Complete)
+        );
+        assert_eq!(&buf[..256 - 15], &[0x23; 256 - 15][..]);
+    }
+}
+
+/// Tests using a flash controller that can lose power
+mod power_loss_flash_ctrl {
+    use super::*;
+    use crate::tickv::KeyCursor;
+    use std::vec::Vec;
+
+    // An example FlashCtrl implementation, that stops writing to flash after
+    // a number of writes to simulate a power loss.
+    struct FlashCtrl {
+        buf: RefCell<[[u8; 1024]; 64]>,
+        writes_left: Cell<Option<usize>>,
+    }
+
+    impl FlashCtrl {
+        fn new() -> Self {
+            Self {
+                buf: RefCell::new([[0xFF; 1024]; 64]),
+                writes_left: Cell::new(None),
+            }
+        }
+
+        /// Create a controller for the same flash, as if the system had
+        /// restarted.
+        fn restart(&self) -> Self {
+            Self {
+                buf: RefCell::new(*self.buf.borrow()),
+                writes_left: Cell::new(None),
+            }
+        }
+
+        /// Lose power after `writes` more writes
+        fn lose_power_after(&self, writes: usize) {
+            self.writes_left.set(Some(writes));
+        }
+    }
+
+    impl FlashController<1024> for FlashCtrl {
+        fn read_region(
+            &self,
+            region_number: usize,
+            offset: usize,
+            buf: &mut [u8; 1024],
+        ) -> Result<(), ErrorCode> {
+            println!("Read from region: {}", region_number);
+
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.buf.borrow()[region_number][offset + i]
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+            match self.writes_left.get() {
+                Some(0) => {
+                    println!("Lost power before write to address: {:#x}", address);
+                    return Err(ErrorCode::WriteFail);
+                }
+                Some(writes) => self.writes_left.set(Some(writes - 1)),
+                None => {}
+            }
+
+            println!(
+                "Write to address: {:#x}, region: {}",
+                address,
+                address / 1024
+            );
+
+            for (i, d) in buf.iter().enumerate() {
+                self.buf.borrow_mut()[address / 1024][(address % 1024) + i] = *d;
+            }
+
+            Ok(())
+        }
+
+        fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+            println!("Erase region: {}", region_number);
+
+            for d in self.buf.borrow_mut()[region_number].iter_mut() {
+                *d = 0xFF;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn get_main_key() -> u64 {
+        let mut hash_function = DefaultHasher::new();
+        MAIN_KEY.hash(&mut hash_function);
+        hash_function.finish()
+    }
+
+    /// Returns all of the keys found by `next_key()`, with their lengths
+    fn get_all_keys(tickv: &TicKV<FlashCtrl, 1024>) -> Vec<(u64, usize)> {
+        let mut cursor = KeyCursor::new();
+        let mut keys = Vec::new();
+
+        loop {
+            match tickv.next_key(&mut cursor) {
+                Ok(key) => {
+                    assert_eq!(cursor.key(), Some(key));
+                    keys.push(key);
+                }
+                Err(ErrorCode::KeyNotFound) => break,
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_get_key_len() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        println!("Add Key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &[0x23; 32])
+            .unwrap();
+
+        println!("Add Key TWO");
+        tickv
+            .append_key(get_hashed_key(b"TWO"), &[0x42; 7])
+            .unwrap();
+
+        assert_eq!(tickv.get_key_len(get_main_key()), Ok(0));
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(32));
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"TWO")), Ok(7));
+        assert_eq!(
+            tickv.get_key_len(get_hashed_key(b"THREE")),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Delete Key TWO");
+        tickv.invalidate_key(get_hashed_key(b"TWO")).unwrap();
+        assert_eq!(
+            tickv.get_key_len(get_hashed_key(b"TWO")),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    #[test]
+    fn test_next_key() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        assert_eq!(get_all_keys(&tickv), vec![(get_main_key(), 0)]);
+
+        let mut expected = vec![(get_main_key(), 0)];
+        for (i, key) in [&b"ONE"[..], b"TWO", b"THREE", b"FOUR", b"FIVE"]
+            .iter()
+            .enumerate()
+        {
+            println!("Add Key {:?}", key);
+            tickv
+                .append_key(get_hashed_key(key), &[0x23; 16][..i])
+                .unwrap();
+            expected.push((get_hashed_key(key), i));
+        }
+        expected.sort();
+        assert_eq!(get_all_keys(&tickv), expected);
+
+        println!("Delete Key THREE");
+        tickv.invalidate_key(get_hashed_key(b"THREE")).unwrap();
+        expected.retain(|(key, _)| *key != get_hashed_key(b"THREE"));
+        assert_eq!(get_all_keys(&tickv), expected);
+
+        // The cursor stays at the end once all keys have been found
+        let mut cursor = KeyCursor::new();
+        while tickv.next_key(&mut cursor).is_ok() {}
+        assert_eq!(tickv.next_key(&mut cursor), Err(ErrorCode::KeyNotFound));
+    }
+
+    #[test]
+    fn test_update_key() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        let mut buf: [u8; 64] = [0; 64];
+
+        println!("Update non-existant Key ONE");
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &[0x42; 8]),
+            Err(ErrorCode::KeyNotFound)
+        );
+
+        println!("Add Key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &[0x23; 32])
+            .unwrap();
+
+        println!("Update Key ONE");
+        tickv
+            .update_key(get_hashed_key(b"ONE"), &[0x42; 48])
+            .unwrap();
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(48));
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(&buf[..48], &[0x42; 48][..]);
+
+        println!("Update Key ONE again");
+        tickv
+            .update_key(get_hashed_key(b"ONE"), &[0x17; 4])
+            .unwrap();
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(4));
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(&buf[..4], &[0x17; 4]);
+
+        assert_eq!(get_all_keys(&tickv), {
+            let mut keys = vec![(get_main_key(), 0), (get_hashed_key(b"ONE"), 4)];
+            keys.sort();
+            keys
+        });
+
+        println!("Fill the region of Key ONE");
+        let mut ret = Ok(SuccessCode::Written);
+        for _ in 0..32 {
+            ret = tickv.update_key(get_hashed_key(b"ONE"), &[0x17; 64]);
+            if ret.is_err() {
+                break;
+            }
+        }
+        assert_eq!(ret, Err(ErrorCode::RegionFull));
+
+        // The key keeps the last value
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(64));
+
+        println!("Delete Key ONE");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+    }
+
+    /// Returns the offset of the end of the objects in the region that holds
+    /// the object with `hash`.
+    fn region_end(tickv: &TicKV<FlashCtrl, 1024>, hash: u64) -> usize {
+        let flash = tickv.controller.buf.borrow();
+        for region in flash.iter() {
+            let mut offset = 0;
+            let mut found = false;
+            while offset < 1024 && region[offset + VERSION_OFFSET] != 0xFF {
+                let hashed_key = &region[offset + HASH_OFFSET..offset + HASH_OFFSET + 8];
+                found |= hashed_key == hash.to_be_bytes();
+                offset += ((region[offset + LEN_OFFSET] as usize & 0x0F) << 8)
+                    | region[offset + LEN_OFFSET + 1] as usize;
+            }
+            if found {
+                return offset;
+            }
+        }
+        panic!("Key not found");
+    }
+
+    #[test]
+    fn test_update_key_region_boundary() {
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        println!("Add Key ONE");
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &[0x23; 32])
+            .unwrap();
+        let free = 1024 - region_end(&tickv, get_hashed_key(b"ONE"));
+
+        // The header and value fit in the region, but the check sum does not
+        println!("Update Key ONE past the end of the region");
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &vec![0x42; free - 15 + 1]),
+            Err(ErrorCode::RegionFull)
+        );
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(32));
+
+        println!("Update Key ONE to the end of the region");
+        tickv
+            .update_key(get_hashed_key(b"ONE"), &vec![0x42; free - 15])
+            .unwrap();
+        assert_eq!(tickv.get_key_len(get_hashed_key(b"ONE")), Ok(free - 15));
+        assert_eq!(region_end(&tickv, get_hashed_key(b"ONE")), 1024);
+    }
+
+    #[test]
+    fn test_update_key_power_loss() {
+        let old_value: [u8; 32] = [0x23; 32];
+        let new_value: [u8; 16] = [0x42; 16];
+
+        // Updating a key performs two writes: the new value, then the
+        // invalidation of the old value. Lose power before each of them, and
+        // after both.
+        for writes in 0..=2 {
+            println!("Lose power after {} writes", writes);
+
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+            tickv.initalise(get_main_key()).unwrap();
+            tickv
+                .append_key(get_hashed_key(b"ONE"), &old_value)
+                .unwrap();
+            tickv
+                .append_key(get_hashed_key(b"TWO"), &old_value)
+                .unwrap();
+
+            tickv.controller.lose_power_after(writes);
+            let ret = tickv.update_key(get_hashed_key(b"ONE"), &new_value);
+            if writes < 2 {
+                assert_eq!(ret, Err(ErrorCode::WriteFail));
+            } else {
+                assert_eq!(ret, Ok(SuccessCode::Written));
+            }
+
+            // Restart and check that the key has either value
+            let mut read_buf: [u8; 1024] = [0; 1024];
+            let tickv =
+                TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+            tickv.initalise(get_main_key()).unwrap();
+
+            let mut buf: [u8; 32] = [0; 32];
+            let expected: &[u8] = if writes == 0 { &old_value } else { &new_value };
+            assert_eq!(
+                tickv.get_key_len(get_hashed_key(b"ONE")),
+                Ok(expected.len())
+            );
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+            assert_eq!(&buf[..expected.len()], expected);
+
+            // Other keys are not affected
+            tickv.get_key(get_hashed_key(b"TWO"), &mut buf).unwrap();
+            assert_eq!(buf, old_value);
+
+            // The key is only found once
+            let mut keys = vec![
+                (get_main_key(), 0),
+                (get_hashed_key(b"ONE"), expected.len()),
+                (get_hashed_key(b"TWO"), old_value.len()),
+            ];
+            keys.sort();
+            assert_eq!(get_all_keys(&tickv), keys);
+
+            // The key can be updated again
+            tickv
+                .update_key(get_hashed_key(b"ONE"), &[0x17; 8])
+                .unwrap();
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+            assert_eq!(&buf[..8], &[0x17; 8]);
+            assert_eq!(get_all_keys(&tickv).len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_invalidate_key_power_loss_after_update() {
+        let old_value: [u8; 32] = [0x23; 32];
+        let new_value: [u8; 16] = [0x42; 16];
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv = TicKV::<FlashCtrl, 1024>::new(FlashCtrl::new(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+        tickv
+            .append_key(get_hashed_key(b"ONE"), &old_value)
+            .unwrap();
+
+        println!("Lose power after writing the new value");
+        tickv.controller.lose_power_after(1);
+        assert_eq!(
+            tickv.update_key(get_hashed_key(b"ONE"), &new_value),
+            Err(ErrorCode::WriteFail)
+        );
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        // Both values are still valid in flash. Invalidating the key
+        // invalidates the old value first, so losing power after that
+        // leaves the new value.
+        println!("Lose power after invalidating the old value");
+        tickv.controller.lose_power_after(1);
+        assert_eq!(
+            tickv.invalidate_key(get_hashed_key(b"ONE")),
+            Err(ErrorCode::WriteFail)
+        );
+
+        let mut read_buf: [u8; 1024] = [0; 1024];
+        let tickv =
+            TicKV::<FlashCtrl, 1024>::new(tickv.controller.restart(), &mut read_buf, 0x10000);
+        tickv.initalise(get_main_key()).unwrap();
+
+        let mut buf: [u8; 16] = [0; 16];
+        tickv.get_key(get_hashed_key(b"ONE"), &mut buf).unwrap();
+        assert_eq!(buf, new_value);
+
+        println!("Delete Key ONE");
+        tickv.invalidate_key(get_hashed_key(b"ONE")).unwrap();
+        assert_eq!(
+            tickv.get_key(get_hashed_key(b"ONE"), &mut buf),
+            Err(ErrorCode::KeyNotFound)
+        );
+        assert_eq!(get_all_keys(&tickv), vec![(get_main_key(), 0)]);
+    }
 }
@@ -5,6 +5,7 @@ use crate::error_codes::ErrorCode;
 use crate::flash_controller::FlashController;
 use crate::success_codes::SuccessCode;
 use core::cell::Cell;
+use core::convert::TryInto;
 
 /// The current version of TicKV
 pub const VERSION: u8 = 0;
@@ -27,6 +28,15 @@ pub(crate) enum KeyState {
     ReadRegion(usize),
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum UpdateState {
+    /// Trying to read the key from a region
+    ReadRegion(usize),
+    /// The new object has been written to the region at the offset, and
+    /// the older objects for the key are being invalidated
+    InvalidateOld(usize, usize),
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub(crate) enum RubbishState {
     ReadRegion(usize),
@@ -45,6 +55,12 @@ pub(crate) enum State {
     AppendKey(KeyState),
     /// Getting a key
     GetKey(KeyState),
+    /// Getting the length of a key
+    GetKeyLen(KeyState),
+    /// Updating a key
+    UpdateKey(UpdateState),
+    /// Finding the next key with a cursor
+    NextKey(KeyState),
     /// Invalidating a key
     InvalidateKey(KeyState),
     /// Running garbage collection
@@ -60,6 +76,31 @@ pub struct TicKV<'a, C: FlashController<S>, const S: usize> {
     pub(crate) state: Cell<State>,
 }
 
+/// A position in the flash storage, used to iterate over the stored keys
+/// with `next_key()`.
+///
+/// A new cursor starts at the first key. After each successful call to
+/// `next_key()` the cursor also holds the key that was found.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyCursor {
+    region: usize,
+    offset: usize,
+    key: Option<(u64, usize)>,
+}
+
+impl KeyCursor {
+    /// Create a cursor at the first key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The hashed key and the length of its value found by the last call
+    /// to `next_key()`, or `None` if no key has been found yet.
+    pub fn key(&self) -> Option<(u64, usize)> {
+        self.key
+    }
+}
+
 /// This is the current object header used for TicKV objects
 struct ObjectHeader {
     version: u8,
@@ -91,6 +132,21 @@ pub(crate) const HASH_OFFSET: usize = 3;
 pub(crate) const HEADER_LENGTH: usize = HASH_OFFSET + 8;
 pub(crate) const CHECK_SUM_LEN: usize = 4;
 
+/// The total length of the object at `offset` in some loaded region data
+fn object_total_length(region_data: &[u8], offset: usize) -> usize {
+    (((region_data[offset + LEN_OFFSET] as u16) & !0xF0) << 8
+        | region_data[offset + LEN_OFFSET + 1] as u16) as usize
+}
+
+/// The hashed key of the object at `offset` in some loaded region data
+fn object_hashed_key(region_data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(
+        region_data[offset + HASH_OFFSET..offset + HASH_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+    )
+}
+
 /// The main key. A hashed version of this should be passed to
 /// `initalise()`.
 pub const MAIN_KEY: &[u8; 15] = b"tickv-super-key";
@@ -243,6 +299,10 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
     /// Find a key in some loaded region data.
     ///
+    /// If there is more than one valid object for the key in the region,
+    /// because an update was interrupted by a power loss, the most recent one
+    /// is used. Objects are appended to a region, so this is the last one.
+    ///
     /// On success return the offset in the region_data where the key is and the
     /// total length of the key.
     /// On failure return a bool indicating if the caller should keep looking in
@@ -259,11 +319,12 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
 
         let mut offset: usize = 0;
         let mut empty: bool = true;
+        let mut found: Option<(usize, u16)> = None;
 
         loop {
             if offset + HEADER_LENGTH >= S {
                 // We have reached the end of the region
-                return Err((false, ErrorCode::KeyNotFound));
+                return found.ok_or((false, ErrorCode::KeyNotFound));
             }
 
             // Check to see if we have data
@@ -283,7 +344,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 // Check to see if all fields are just 0
                 if total_length == 0 {
                     // We found something invalid here
-                    return Err((false, ErrorCode::KeyNotFound));
+                    return found.ok_or((false, ErrorCode::KeyNotFound));
                 }
 
                 // Check to see if the entry has been deleted
@@ -308,15 +369,106 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     continue;
                 }
 
-                // If we get here we have found out value (assuming no collisions)
-                return Ok((offset, total_length));
+                // We have found our value (assuming no collisions), keep
+                // looking in case there is a more recent one.
+                found = Some((offset, total_length));
+                offset += total_length as usize;
             } else {
                 // We hit the end.
-                return Err((!empty, ErrorCode::KeyNotFound));
+                return found.ok_or((!empty, ErrorCode::KeyNotFound));
             }
         }
     }
 
+    /// Find the offset of the oldest valid object for a key that starts
+    /// before `end` in some loaded region data.
+    ///
+    /// This is used to invalidate the objects for a key that an update has
+    /// replaced.
+    fn find_oldest_key_offset(&self, hash: u64, region_data: &[u8], end: usize) -> Option<usize> {
+        let mut offset: usize = 0;
+
+        while offset < end
+            && offset + HEADER_LENGTH < S
+            && region_data[offset + VERSION_OFFSET] == VERSION
+        {
+            let total_length = object_total_length(region_data, offset);
+            if total_length == 0 {
+                return None;
+            }
+
+            if region_data[offset + LEN_OFFSET] & 0x80 == 0x80
+                && object_hashed_key(region_data, offset) == hash
+            {
+                return Some(offset);
+            }
+
+            offset += total_length;
+        }
+
+        None
+    }
+
+    /// Writes a new object for the key/value pair to `region` at `offset`.
+    ///
+    /// `region_data` must contain the data of the region. The object is
+    /// also copied to it.
+    fn write_object(
+        &self,
+        region: usize,
+        offset: usize,
+        region_data: &mut [u8],
+        hash: u64,
+        value: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let crc = crc32::Crc::new();
+        let mut check_sum = crc.digest();
+
+        // Length not including check sum
+        let package_length = HEADER_LENGTH + value.len();
+        let object_length = HEADER_LENGTH + value.len() + CHECK_SUM_LEN;
+
+        // Create the header:
+        let header = ObjectHeader::new(hash, object_length as u16);
+
+        // Copy in new header
+        // This is a little painful, but avoids any unsafe Rust
+        region_data[offset + VERSION_OFFSET] = header.version;
+        region_data[offset + LEN_OFFSET] =
+            (header.len >> 8) as u8 & 0x0F | (header.flags << 4) & 0xF0;
+        region_data[offset + LEN_OFFSET + 1] = (header.len & 0xFF) as u8;
+        region_data[offset + HASH_OFFSET] = (header.hashed_key >> 56) as u8;
+        region_data[offset + HASH_OFFSET + 1] = (header.hashed_key >> 48) as u8;
+        region_data[offset + HASH_OFFSET + 2] = (header.hashed_key >> 40) as u8;
+        region_data[offset + HASH_OFFSET + 3] = (header.hashed_key >> 32) as u8;
+        region_data[offset + HASH_OFFSET + 4] = (header.hashed_key >> 24) as u8;
+        region_data[offset + HASH_OFFSET + 5] = (header.hashed_key >> 16) as u8;
+        region_data[offset + HASH_OFFSET + 6] = (header.hashed_key >> 8) as u8;
+        region_data[offset + HASH_OFFSET + 7] = (header.hashed_key) as u8;
+
+        // Hash the new header data
+        check_sum.update(&region_data[offset + VERSION_OFFSET..=offset + HASH_OFFSET + 7]);
+
+        // Copy the value
+        let slice = &mut region_data[(offset + HEADER_LENGTH)..(offset + package_length)];
+        slice.copy_from_slice(value);
+
+        // Include the value in the hash
+        check_sum.update(value);
+
+        // Append a Check Hash
+        let check_sum = check_sum.finalise();
+        let slice =
+            &mut region_data[(offset + package_length)..(offset + package_length + CHECK_SUM_LEN)];
+        slice.copy_from_slice(&check_sum.to_ne_bytes());
+
+        // Write the data back to the region
+        self.controller.write(
+            S * region + offset,
+            &region_data[offset..(offset + package_length + CHECK_SUM_LEN)],
+        )
+    }
+
     /// Appends the key/value pair to flash storage.
     ///
     /// `hash`: A hashed key. This key will be used in future to retrieve
@@ -327,20 +479,13 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
     /// On error a `ErrorCode` will be returned.
     pub fn append_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
         let region = self.get_region(hash);
-        let crc = crc32::Crc::new();
-        let mut check_sum = crc.digest();
 
-        // Length not including check sum
-        let package_length = HEADER_LENGTH + value.len();
         let object_length = HEADER_LENGTH + value.len() + CHECK_SUM_LEN;
 
         if object_length > 0xFFF {
             return Err(ErrorCode::ObjectTooLarge);
         }
 
-        // Create the header:
-        let header = ObjectHeader::new(hash, object_length as u16);
-
         let mut region_offset: isize = 0;
 
         loop {
@@ -391,7 +536,7 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             let mut offset: usize = 0;
 
             loop {
-                if offset + package_length >= S {
+                if offset + object_length > S {
                     // We have reached the end of the region
                     // We will need to try the next region
 
@@ -465,43 +610,9 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                 }
 
                 // If we get here we have found an empty spot
-
-                // Copy in new header
-                // This is a little painful, but avoids any unsafe Rust
-                region_data[offset + VERSION_OFFSET] = header.version;
-                region_data[offset + LEN_OFFSET] =
-                    (header.len >> 8) as u8 & 0x0F | (header.flags << 4) & 0xF0;
-                region_data[offset + LEN_OFFSET + 1] = (header.len & 0xFF) as u8;
-                region_data[offset + HASH_OFFSET] = (header.hashed_key >> 56) as u8;
-                region_data[offset + HASH_OFFSET + 1] = (header.hashed_key >> 48) as u8;
-                region_data[offset + HASH_OFFSET + 2] = (header.hashed_key >> 40) as u8;
-                region_data[offset + HASH_OFFSET + 3] = (header.hashed_key >> 32) as u8;
-                region_data[offset + HASH_OFFSET + 4] = (header.hashed_key >> 24) as u8;
-                region_data[offset + HASH_OFFSET + 5] = (header.hashed_key >> 16) as u8;
-                region_data[offset + HASH_OFFSET + 6] = (header.hashed_key >> 8) as u8;
-                region_data[offset + HASH_OFFSET + 7] = (header.hashed_key) as u8;
-
-                // Hash the new header data
-                check_sum.update(&region_data[offset + VERSION_OFFSET..=offset + HASH_OFFSET + 7]);
-
-                // Copy the value
-                let slice = &mut region_data[(offset + HEADER_LENGTH)..(offset + package_length)];
-                slice.copy_from_slice(value);
-
-                // Include the value in the hash
-                check_sum.update(value);
-
-                // Append a Check Hash
-                let check_sum = check_sum.finalise();
-                let slice = &mut region_data
-                    [(offset + package_length)..(offset + package_length + CHECK_SUM_LEN)];
-                slice.copy_from_slice(&check_sum.to_ne_bytes());
-
-                // Write the data back to the region
-                if let Err(e) = self.controller.write(
-                    S * new_region as usize + offset,
-                    &region_data[offset..(offset + package_length + CHECK_SUM_LEN)],
-                ) {
+                if let Err(e) =
+                    self.write_object(new_region as usize, offset, region_data, hash, value)
+                {
                     self.read_buffer.replace(Some(region_data));
                     match e {
                         ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
@@ -625,6 +736,286 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
         }
     }
 
+    /// Retrieves the length of the value of a key from flash storage.
+    ///
+    /// `hash`: A hashed key.
+    ///
+    /// On success the length of the value will be returned.
+    /// On error a `ErrorCode` will be returned.
+    ///
+    /// This can be used to allocate a large enough buffer before calling
+    /// `get_key()`.
+    pub fn get_key_len(&self, hash: u64) -> Result<usize, ErrorCode> {
+        let region = self.get_region(hash);
+
+        let mut new_region = match self.state.get() {
+            State::GetKeyLen(KeyState::ReadRegion(reg)) => reg as isize,
+            _ => region as isize,
+        };
+
+        loop {
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::GetKeyLen(KeyState::ReadRegion(new_region as usize)) {
+                match self
+                    .controller
+                    .read_region(new_region as usize, 0, region_data)
+                {
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state.set(State::GetKeyLen(KeyState::ReadRegion(reg)));
+                        }
+                        return Err(e);
+                    }
+                };
+            }
+
+            let ret = self.find_key_offset(hash, region_data);
+            self.read_buffer.replace(Some(region_data));
+
+            match ret {
+                Ok((_offset, total_length)) => {
+                    return Ok(total_length as usize - HEADER_LENGTH - CHECK_SUM_LEN);
+                }
+                Err((cont, e)) => {
+                    if !cont {
+                        return Err(e);
+                    }
+                    match self.increment_region_offset(new_region) {
+                        Some(o) => {
+                            new_region = region as isize + o;
+                        }
+                        None => {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the value of a key in flash storage.
+    ///
+    /// `hash`: A hashed key. The key must already exist.
+    /// `value`: A buffer containing the new value to be stored to flash.
+    ///
+    /// On success a `SuccessCode` will be returned.
+    /// On error a `ErrorCode` will be returned.
+    ///
+    /// The new value is appended to the region that holds the current value
+    /// before the current value is invalidated. If a power loss occurs before
+    /// success is returned the key has either the current or the new value.
+    ///
+    /// `RegionFull` is returned if there is no space left for the new value
+    /// in that region. As the region still holds the current value, garbage
+    /// collection won't free any space in it. The key can instead be
+    /// invalidated and appended again, without the power loss protection.
+    ///
+    /// Unlike the other operations, this performs more than one write. If
+    /// `write()` returns `WriteNotReady` then `continue_operation()` of
+    /// `AsyncTicKV` must be called once the write has completed.
+    pub fn update_key(&self, hash: u64, value: &[u8]) -> Result<SuccessCode, ErrorCode> {
+        let region = self.get_region(hash);
+
+        let object_length = HEADER_LENGTH + value.len() + CHECK_SUM_LEN;
+
+        if object_length > 0xFFF {
+            return Err(ErrorCode::ObjectTooLarge);
+        }
+
+        let (new_region, new_offset) = match self.state.get() {
+            // The new value has already been written
+            State::UpdateKey(UpdateState::InvalidateOld(reg, offset)) => (reg, offset),
+            _ => {
+                let mut new_region = match self.state.get() {
+                    State::UpdateKey(UpdateState::ReadRegion(reg)) => reg as isize,
+                    _ => region as isize,
+                };
+
+                loop {
+                    // Get the data from that region
+                    let region_data = self.read_buffer.take().unwrap();
+                    if self.state.get()
+                        != State::UpdateKey(UpdateState::ReadRegion(new_region as usize))
+                    {
+                        match self
+                            .controller
+                            .read_region(new_region as usize, 0, region_data)
+                        {
+                            Ok(()) => {}
+                            Err(e) => {
+                                self.read_buffer.replace(Some(region_data));
+                                if let ErrorCode::ReadNotReady(reg) = e {
+                                    self.state
+                                        .set(State::UpdateKey(UpdateState::ReadRegion(reg)));
+                                }
+                                return Err(e);
+                            }
+                        };
+                    }
+
+                    match self.find_key_offset(hash, region_data) {
+                        Ok(_) => {
+                            // Find the end of the objects in the region
+                            let mut offset: usize = 0;
+                            while offset + object_length <= S
+                                && region_data[offset + VERSION_OFFSET] != 0xFF
+                            {
+                                let total_length = object_total_length(region_data, offset);
+                                if total_length == 0 {
+                                    self.read_buffer.replace(Some(region_data));
+                                    return Err(ErrorCode::CorruptData);
+                                }
+                                offset += total_length;
+                            }
+
+                            if offset + object_length > S {
+                                self.read_buffer.replace(Some(region_data));
+                                return Err(ErrorCode::RegionFull);
+                            }
+
+                            let ret = self.write_object(
+                                new_region as usize,
+                                offset,
+                                region_data,
+                                hash,
+                                value,
+                            );
+                            self.read_buffer.replace(Some(region_data));
+
+                            match ret {
+                                Ok(()) => break (new_region as usize, offset),
+                                Err(e) => {
+                                    if let ErrorCode::WriteNotReady(_) = e {
+                                        self.state.set(State::UpdateKey(
+                                            UpdateState::InvalidateOld(new_region as usize, offset),
+                                        ));
+                                    }
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        Err((cont, e)) => {
+                            self.read_buffer.replace(Some(region_data));
+
+                            if !cont {
+                                return Err(e);
+                            }
+                            match self.increment_region_offset(new_region) {
+                                Some(o) => {
+                                    new_region = region as isize + o;
+                                }
+                                None => {
+                                    return Err(e);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        // Invalidate the older values, the oldest first, so that the key
+        // never goes back to an older value if we lose power.
+        let region_data = self.read_buffer.take().unwrap();
+        while let Some(offset) = self.find_oldest_key_offset(hash, region_data, new_offset) {
+            region_data[offset + LEN_OFFSET] &= !0x80;
+
+            if let Err(e) = self.controller.write(
+                S * new_region + offset + LEN_OFFSET,
+                &region_data[offset + LEN_OFFSET..offset + LEN_OFFSET + 1],
+            ) {
+                self.read_buffer.replace(Some(region_data));
+                if let ErrorCode::WriteNotReady(_) = e {
+                    self.state.set(State::UpdateKey(UpdateState::InvalidateOld(
+                        new_region, new_offset,
+                    )));
+                }
+                return Err(e);
+            }
+        }
+
+        self.read_buffer.replace(Some(region_data));
+        Ok(SuccessCode::Written)
+    }
+
+    /// Finds the next valid key in flash storage.
+    ///
+    /// `cursor`: The position to continue from. A new `KeyCursor` starts at
+    ///           the first key. On success the cursor is moved past the key
+    ///           that was found, and holds that key.
+    ///
+    /// On success the hashed key and the length of its value will be
+    /// returned.
+    /// On error a `ErrorCode` will be returned. `KeyNotFound` is returned
+    /// once every key has been found.
+    ///
+    /// Keys are found in the order they are stored in flash, which is not
+    /// the order they were added in. This includes the main key added by
+    /// `initalise()`. Keys that are appended, updated or invalidated while
+    /// iterating might be missed or found twice.
+    pub fn next_key(&self, cursor: &mut KeyCursor) -> Result<(u64, usize), ErrorCode> {
+        let num_region = self.flash_size / S;
+
+        while cursor.region < num_region {
+            // Get the data from that region
+            let region_data = self.read_buffer.take().unwrap();
+            if self.state.get() != State::NextKey(KeyState::ReadRegion(cursor.region)) {
+                match self.controller.read_region(cursor.region, 0, region_data) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        self.read_buffer.replace(Some(region_data));
+                        if let ErrorCode::ReadNotReady(reg) = e {
+                            self.state.set(State::NextKey(KeyState::ReadRegion(reg)));
+                        }
+                        return Err(e);
+                    }
+                };
+            }
+
+            let mut offset = cursor.offset;
+            while offset + HEADER_LENGTH < S && region_data[offset + VERSION_OFFSET] != 0xFF {
+                // We found a version, check that we support it
+                if region_data[offset + VERSION_OFFSET] != VERSION {
+                    self.read_buffer.replace(Some(region_data));
+                    return Err(ErrorCode::UnsupportedVersion);
+                }
+
+                let total_length = object_total_length(region_data, offset);
+                if total_length == 0 {
+                    // We found something invalid here
+                    break;
+                }
+
+                // Skip objects that have been invalidated, or replaced by an
+                // interrupted update.
+                let hash = object_hashed_key(region_data, offset);
+                if region_data[offset + LEN_OFFSET] & 0x80 == 0x80
+                    && matches!(self.find_key_offset(hash, region_data),
+                                Ok((key_offset, _)) if key_offset == offset)
+                {
+                    let len = total_length - HEADER_LENGTH - CHECK_SUM_LEN;
+                    cursor.offset = offset + total_length;
+                    cursor.key = Some((hash, len));
+                    self.read_buffer.replace(Some(region_data));
+                    return Ok((hash, len));
+                }
+
+                offset += total_length;
+            }
+
+            self.read_buffer.replace(Some(region_data));
+
+            // Move on to the next region
+            cursor.region += 1;
+            cursor.offset = 0;
+        }
+
+        Err(ErrorCode::KeyNotFound)
+    }
+
     /// Invalidates the key in flash storage
     ///
     /// `hash`: A hashed key.
@@ -669,8 +1060,14 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
             }
 
             match self.find_key_offset(hash, region_data) {
-                Ok((offset, _data_len)) => {
-                    // We found a key, let's delete it
+                Ok((last_offset, _data_len)) => loop {
+                    // We found a key, let's delete it. If an update of the
+                    // key was interrupted there are older objects for the
+                    // key as well. These are deleted first, so that an older
+                    // value is never used again if we lose power.
+                    let offset = self
+                        .find_oldest_key_offset(hash, region_data, last_offset)
+                        .unwrap_or(last_offset);
                     region_data[offset + LEN_OFFSET] &= !0x80;
 
                     if let Err(e) = self.controller.write(
@@ -679,14 +1076,26 @@ impl<'a, C: FlashController<S>, const S: usize> TicKV<'a, C, S> {
                     ) {
                         self.read_buffer.replace(Some(region_data));
                         match e {
-                            ErrorCode::WriteNotReady(_) => return Ok(SuccessCode::Queued),
+                            ErrorCode::WriteNotReady(_) if offset == last_offset => {
+                                return Ok(SuccessCode::Queued)
+                            }
+                            ErrorCode::WriteNotReady(_) => {
+                                // Continue with the next object once the
+                                // write has completed
+                                self.state.set(State::InvalidateKey(KeyState::ReadRegion(
+                                    new_region as usize,
+                                )));
+                                return Err(e);
+                            }
                             _ => return Err(e),
                         }
                     }
 
-                    self.read_buffer.replace(Some(region_data));
-                    return Ok(SuccessCode::Written);
-                }
+                    if offset == last_offset {
+                        self.read_buffer.replace(Some(region_data));
+                        return Ok(SuccessCode::Written);
+                    }
+                },
                 Err((cont, e)) => {
                     self.read_buffer.replace(Some(region_data));
 
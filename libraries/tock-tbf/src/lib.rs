@@ -6,3 +6,11 @@
 
 pub mod parse;
 pub mod types;
+
+// This is used to run the tests on a host
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+#[cfg(test)]
+mod tests;
@@ -130,9 +130,19 @@ pub fn parse_tbf_header(
                     Default::default();
                 let mut app_name_str = "";
                 let mut fixed_address_pointer: Option<types::TbfHeaderV2FixedAddresses> = None;
+                let mut permissions_pointer: Option<[Option<types::TbfHeaderDriverPermission>; 8]> =
+                    None;
+                let mut real_time_pointer: Option<types::TbfHeaderV2RealTime> = None;
+                let mut credentials_pointer: [Option<types::TbfHeaderV2Credentials>; 4] =
+                    Default::default();
+                let mut number_credentials = 0;
+                let mut credentials_offset: Option<u16> = None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
+                    // Offset of this TLV entry from the start of the header.
+                    let tlv_offset = header.len() - remaining.len();
+
                     // Get the T and L portions of the next header (if it is
                     // there).
                     let tlv_header: types::TbfHeaderTlv = remaining.try_into()?;
@@ -140,6 +150,25 @@ pub fn parse_tbf_header(
                         .get(4..)
                         .ok_or(types::TbfParseError::NotEnoughFlash)?;
 
+                    // Credentials only cover the TLV entries in front of them,
+                    // so nothing but more credentials may follow them.
+                    if credentials_offset.is_some() {
+                        match tlv_header.tipe {
+                            types::TbfHeaderTypes::TbfHeaderCredentials => {}
+                            _ => {
+                                let tipe = u16::from_le_bytes(
+                                    header
+                                        .get(tlv_offset..tlv_offset + 2)
+                                        .ok_or(types::TbfParseError::InternalError)?
+                                        .try_into()?,
+                                );
+                                return Err(types::TbfParseError::TlvAfterCredentials(
+                                    tipe as usize,
+                                ));
+                            }
+                        }
+                    }
+
                     match tlv_header.tipe {
                         types::TbfHeaderTypes::TbfHeaderMain => {
                             let entry_len = mem::size_of::<types::TbfHeaderV2Main>();
@@ -219,6 +248,80 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderPermissions => {
+                            let permission_len = 16;
+                            let number_permissions = tlv_header.length as usize / permission_len;
+
+                            // The length must be a multiple of the size of a
+                            // permission. To enable a static buffer, we only
+                            // support up to eight permissions. Permissions
+                            // can't be dropped like writeable flash regions,
+                            // as that would change which system calls the app
+                            // may use.
+                            let mut permissions: [Option<types::TbfHeaderDriverPermission>; 8] =
+                                Default::default();
+                            if tlv_header.length as usize % permission_len != 0
+                                || number_permissions > permissions.len()
+                                || permissions_pointer.is_some()
+                            {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+
+                            let permissions_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            for (i, permission) in
+                                permissions_slice.chunks_exact(permission_len).enumerate()
+                            {
+                                permissions[i] = Some(permission.try_into()?);
+                            }
+                            permissions_pointer = Some(permissions);
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderRealTime => {
+                            let entry_len = 12;
+                            if tlv_header.length as usize == entry_len
+                                && real_time_pointer.is_none()
+                            {
+                                real_time_pointer = Some(remaining.try_into()?);
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderCredentials => {
+                            // Credentials hold at least the 32 bit format
+                            // identifier.
+                            if (tlv_header.length as usize) < 4 {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+
+                            let credentials_slice = remaining
+                                .get(0..tlv_header.length as usize)
+                                .ok_or(types::TbfParseError::NotEnoughFlash)?;
+                            let credentials: types::TbfHeaderV2Credentials =
+                                credentials_slice.try_into()?;
+
+                            if credentials_offset.is_none() {
+                                credentials_offset = Some(tlv_offset as u16);
+                            }
+
+                            // To enable a static buffer, we only keep up to
+                            // four credentials. Any further credentials are
+                            // still checked for validity but otherwise
+                            // ignored.
+                            if number_credentials < credentials_pointer.len() {
+                                credentials_pointer[number_credentials] = Some(credentials);
+                                number_credentials += 1;
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -236,6 +339,10 @@ pub fn parse_tbf_header(
                     package_name: Some(app_name_str),
                     writeable_regions: Some(wfr_pointer),
                     fixed_addresses: fixed_address_pointer,
+                    permissions: permissions_pointer,
+                    real_time: real_time_pointer,
+                    credentials: credentials_pointer,
+                    credentials_offset: credentials_offset.unwrap_or(header.len() as u16),
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -0,0 +1,305 @@
+//! Tests for parsing hand-built TBF images.
+
+use crate::parse::{parse_tbf_header, parse_tbf_header_lengths};
+use crate::types::{CommandPermissions, TbfHeader, TbfHeaderV2CredentialsType, TbfParseError};
+use core::convert::TryInto;
+use std::boxed::Box;
+use std::vec::Vec;
+
+const TLV_MAIN: u16 = 1;
+const TLV_PACKAGE_NAME: u16 = 3;
+const TLV_PERMISSIONS: u16 = 6;
+const TLV_REAL_TIME: u16 = 7;
+const TLV_CREDENTIALS: u16 = 128;
+
+/// Build a complete TBF image from a list of TLV entries and an app binary.
+///
+/// The header size, total size and checksum are filled in so that the image
+/// parses. The image is leaked to satisfy the `'static` bound of the parser.
+fn build_tbf(tlvs: &[(u16, Vec<u8>)], binary: &[u8]) -> &'static [u8] {
+    let mut header: Vec<u8> = vec![0; 16];
+    for (tipe, data) in tlvs {
+        header.extend_from_slice(&tipe.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        header.extend_from_slice(data);
+        while header.len() % 4 != 0 {
+            header.push(0);
+        }
+    }
+
+    let header_size = header.len() as u16;
+    let total_size = (header.len() + binary.len()) as u32;
+    header[0..2].copy_from_slice(&2u16.to_le_bytes());
+    header[2..4].copy_from_slice(&header_size.to_le_bytes());
+    header[4..8].copy_from_slice(&total_size.to_le_bytes());
+    // Enabled.
+    header[8..12].copy_from_slice(&1u32.to_le_bytes());
+
+    let checksum = header
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |acc, (_, chunk)| {
+            acc ^ u32::from_le_bytes(chunk.try_into().unwrap())
+        });
+    header[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+    header.extend_from_slice(binary);
+    Box::leak(header.into_boxed_slice())
+}
+
+fn main_tlv() -> (u16, Vec<u8>) {
+    let mut data = Vec::new();
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0x1000u32.to_le_bytes());
+    (TLV_MAIN, data)
+}
+
+fn credentials_tlv(format: u32, data: &[u8]) -> (u16, Vec<u8>) {
+    let mut tlv = Vec::new();
+    tlv.extend_from_slice(&format.to_le_bytes());
+    tlv.extend_from_slice(data);
+    (TLV_CREDENTIALS, tlv)
+}
+
+fn permissions_tlv(permissions: &[(u32, u32, u64)]) -> (u16, Vec<u8>) {
+    let mut tlv = Vec::new();
+    for (driver_number, offset, allowed_commands) in permissions {
+        tlv.extend_from_slice(&driver_number.to_le_bytes());
+        tlv.extend_from_slice(&offset.to_le_bytes());
+        tlv.extend_from_slice(&allowed_commands.to_le_bytes());
+    }
+    (TLV_PERMISSIONS, tlv)
+}
+
+fn real_time_tlv(period_us: u32, deadline_us: u32, budget_us: u32) -> (u16, Vec<u8>) {
+    let mut tlv = Vec::new();
+    tlv.extend_from_slice(&period_us.to_le_bytes());
+    tlv.extend_from_slice(&deadline_us.to_le_bytes());
+    tlv.extend_from_slice(&budget_us.to_le_bytes());
+    (TLV_REAL_TIME, tlv)
+}
+
+fn parse(app: &'static [u8]) -> Result<TbfHeader, TbfParseError> {
+    let (version, header_length, total_length) =
+        match parse_tbf_header_lengths(app[0..8].try_into().unwrap()) {
+            Ok(lengths) => lengths,
+            Err(_) => panic!("Could not parse TBF lengths"),
+        };
+    assert_eq!(total_length as usize, app.len());
+    parse_tbf_header(&app[0..header_length as usize], version)
+}
+
+#[test]
+fn no_credentials() {
+    let app = build_tbf(
+        &[main_tlv(), (TLV_PACKAGE_NAME, b"blink".to_vec())],
+        &[0xAA; 64],
+    );
+    let header = parse(app).unwrap();
+
+    assert!(header.is_app());
+    assert_eq!(header.get_package_name(), Some("blink"));
+    assert_eq!(header.number_credentials(), 0);
+    assert!(header.get_credentials(0).is_none());
+
+    // Without credentials the regions cover the whole header (except the
+    // checksum) and the whole binary.
+    let regions = header.get_integrity_regions(app).unwrap();
+    assert_eq!(regions[0], &app[0..12]);
+    assert_eq!(regions[1], &app[16..44]);
+    assert_eq!(regions[2], &app[44..]);
+}
+
+#[test]
+fn sha256_credentials() {
+    let hash = [0x5A; 32];
+    let app = build_tbf(
+        &[
+            main_tlv(),
+            (TLV_PACKAGE_NAME, b"app".to_vec()),
+            credentials_tlv(1, &hash),
+        ],
+        &[0xAA; 64],
+    );
+    let header = parse(app).unwrap();
+
+    assert_eq!(header.number_credentials(), 1);
+    let credentials = header.get_credentials(0).unwrap();
+    assert_eq!(credentials.format(), TbfHeaderV2CredentialsType::Sha256);
+    assert_eq!(credentials.data(), &hash[..]);
+
+    // The credentials start after the base header (16), main (16) and
+    // package name (8) entries.
+    let regions = header.get_integrity_regions(app).unwrap();
+    assert_eq!(regions[0], &app[0..12]);
+    assert_eq!(regions[1], &app[16..40]);
+    assert_eq!(regions[2], &app[80..]);
+    assert_eq!(regions[2].len(), 64);
+}
+
+#[test]
+fn multiple_credentials() {
+    let app = build_tbf(
+        &[
+            main_tlv(),
+            credentials_tlv(2, &[0x11; 32]),
+            credentials_tlv(3, &[0x22; 64]),
+            credentials_tlv(0x1234, &[0x33; 5]),
+        ],
+        &[0xAA; 16],
+    );
+    let header = parse(app).unwrap();
+
+    assert_eq!(header.number_credentials(), 3);
+    assert_eq!(
+        header.get_credentials(0).unwrap().format(),
+        TbfHeaderV2CredentialsType::HmacSha256
+    );
+    let ecdsa = header.get_credentials(1).unwrap();
+    assert_eq!(ecdsa.format(), TbfHeaderV2CredentialsType::EcdsaNistP256);
+    assert_eq!(ecdsa.data(), &[0x22; 64][..]);
+    let unknown = header.get_credentials(2).unwrap();
+    assert_eq!(unknown.format(), TbfHeaderV2CredentialsType::Unknown);
+    assert_eq!(unknown.data(), &[0x33; 5][..]);
+
+    let regions = header.get_integrity_regions(app).unwrap();
+    assert_eq!(regions[1], &app[16..32]);
+}
+
+#[test]
+fn credentials_bad_length() {
+    let app = build_tbf(&[main_tlv(), credentials_tlv(1, &[0x5A; 31])], &[0; 8]);
+    match parse(app) {
+        Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_CREDENTIALS as usize),
+        _ => panic!("Expected bad TLV entry"),
+    }
+
+    let app = build_tbf(&[main_tlv(), (TLV_CREDENTIALS, vec![1, 0])], &[0; 8]);
+    match parse(app) {
+        Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_CREDENTIALS as usize),
+        _ => panic!("Expected bad TLV entry"),
+    }
+}
+
+#[test]
+fn tlv_after_credentials() {
+    let app = build_tbf(
+        &[
+            main_tlv(),
+            credentials_tlv(1, &[0x5A; 32]),
+            (TLV_PACKAGE_NAME, b"evil".to_vec()),
+        ],
+        &[0; 8],
+    );
+    match parse(app) {
+        Err(TbfParseError::TlvAfterCredentials(tipe)) => {
+            assert_eq!(tipe, TLV_PACKAGE_NAME as usize)
+        }
+        _ => panic!("Expected TLV after credentials error"),
+    }
+}
+
+#[test]
+fn permissions() {
+    let app = build_tbf(&[main_tlv()], &[0; 8]);
+    let header = parse(app).unwrap();
+    assert_eq!(
+        header.get_command_permissions(1, 0),
+        CommandPermissions::NoPermsAtAll
+    );
+
+    let app = build_tbf(
+        &[
+            main_tlv(),
+            permissions_tlv(&[(1, 0, 0b11), (0x50003, 0, 0b11111), (1, 1, 1 << 5)]),
+        ],
+        &[0; 8],
+    );
+    let header = parse(app).unwrap();
+    assert_eq!(
+        header.get_command_permissions(1, 0),
+        CommandPermissions::Mask(0b11)
+    );
+    assert_eq!(
+        header.get_command_permissions(1, 1),
+        CommandPermissions::Mask(1 << 5)
+    );
+    assert_eq!(
+        header.get_command_permissions(1, 2),
+        CommandPermissions::Mask(0)
+    );
+    assert_eq!(
+        header.get_command_permissions(0x50003, 0),
+        CommandPermissions::Mask(0b11111)
+    );
+    assert_eq!(
+        header.get_command_permissions(2, 0),
+        CommandPermissions::NoPermsThisDriver
+    );
+}
+
+#[test]
+fn permissions_bad_length() {
+    let mut tlv = permissions_tlv(&[(1, 0, 1)]);
+    tlv.1.pop();
+    let app = build_tbf(&[main_tlv(), tlv], &[0; 8]);
+    match parse(app) {
+        Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_PERMISSIONS as usize),
+        _ => panic!("Expected bad TLV entry"),
+    }
+
+    // More permissions than the header can hold
+    let app = build_tbf(&[main_tlv(), permissions_tlv(&[(1, 0, 1); 9])], &[0; 8]);
+    match parse(app) {
+        Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_PERMISSIONS as usize),
+        _ => panic!("Expected bad TLV entry"),
+    }
+}
+
+#[test]
+fn real_time() {
+    let app = build_tbf(&[main_tlv()], &[0; 8]);
+    let header = parse(app).unwrap();
+    assert!(header.get_real_time_parameters().is_none());
+
+    let app = build_tbf(&[main_tlv(), real_time_tlv(10000, 8000, 2000)], &[0; 8]);
+    let header = parse(app).unwrap();
+    let real_time = header.get_real_time_parameters().unwrap();
+    assert_eq!(real_time.period_us(), 10000);
+    assert_eq!(real_time.deadline_us(), 8000);
+    assert_eq!(real_time.budget_us(), 2000);
+}
+
+#[test]
+fn real_time_bad_parameters() {
+    // Budget longer than the deadline, deadline longer than the period, and
+    // no budget at all.
+    for &(period_us, deadline_us, budget_us) in
+        &[(1000, 500, 600), (1000, 1200, 100), (1000, 1000, 0)]
+    {
+        let app = build_tbf(
+            &[main_tlv(), real_time_tlv(period_us, deadline_us, budget_us)],
+            &[0; 8],
+        );
+        match parse(app) {
+            Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_REAL_TIME as usize),
+            _ => panic!("Expected bad TLV entry"),
+        }
+    }
+
+    // Only one set of parameters is allowed
+    let app = build_tbf(
+        &[
+            main_tlv(),
+            real_time_tlv(1000, 1000, 100),
+            real_time_tlv(2000, 2000, 100),
+        ],
+        &[0; 8],
+    );
+    match parse(app) {
+        Err(TbfParseError::BadTlvEntry(tipe)) => assert_eq!(tipe, TLV_REAL_TIME as usize),
+        _ => panic!("Expected bad TLV entry"),
+    }
+}
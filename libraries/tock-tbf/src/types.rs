@@ -50,6 +50,12 @@ pub enum TbfParseError {
     /// UTF-8 string.
     BadProcessName,
 
+    /// A TLV entry other than a credentials entry was found after the first
+    /// credentials entry. Credentials must be the last entries in the header
+    /// so that everything before them is covered by the credentials.
+    /// The `usize` is the value of the "tipe" field of the offending entry.
+    TlvAfterCredentials(usize),
+
     /// Internal kernel error. This is a bug inside of this library. Likely this
     /// means that for some reason a slice was not sized properly for parsing a
     /// certain type, which is something completely controlled by this library.
@@ -81,6 +87,9 @@ impl fmt::Debug for TbfParseError {
             ),
             TbfParseError::BadTlvEntry(tipe) => write!(f, "TLV entry type {} is invalid", tipe),
             TbfParseError::BadProcessName => write!(f, "Process name not UTF-8"),
+            TbfParseError::TlvAfterCredentials(tipe) => {
+                write!(f, "TLV entry type {} follows credentials", tipe)
+            }
             TbfParseError::InternalError => write!(f, "Internal kernel error. This is a bug."),
         }
     }
@@ -105,6 +114,9 @@ pub enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderFixedAddresses = 5,
+    TbfHeaderPermissions = 6,
+    TbfHeaderRealTime = 7,
+    TbfHeaderCredentials = 128,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -164,6 +176,122 @@ pub struct TbfHeaderV2FixedAddresses {
     start_process_flash: u32,
 }
 
+/// The commands of one driver a process may call.
+///
+/// Each bit of `allowed_commands` stands for one command number: bit `n` is
+/// command number `offset * 64 + n`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TbfHeaderDriverPermission {
+    driver_number: u32,
+    offset: u32,
+    allowed_commands: u64,
+}
+
+/// The permissions of a process for the command numbers `offset * 64` to
+/// `offset * 64 + 63` of a driver.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandPermissions {
+    /// The process has no permissions TLV, so its system calls are not
+    /// restricted.
+    NoPermsAtAll,
+    /// The process has a permissions TLV, but may not use the driver.
+    NoPermsThisDriver,
+    /// The process may use the driver, and may call the commands set in the
+    /// mask.
+    Mask(u64),
+}
+
+/// Timing requirements of a real-time process.
+///
+/// The process is treated as a sporadic task: each time it becomes ready to
+/// run it releases a job that must finish (i.e. the process must yield with
+/// nothing left to do) within `deadline_us` microseconds, and that may use up
+/// to `budget_us` microseconds of CPU time. Jobs are expected to be released
+/// at most once every `period_us` microseconds.
+///
+/// Real-time schedulers use these values to order processes, and processes
+/// without them run in the background.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TbfHeaderV2RealTime {
+    period_us: u32,
+    deadline_us: u32,
+    budget_us: u32,
+}
+
+impl TbfHeaderV2RealTime {
+    /// The minimum time between two job releases.
+    pub fn period_us(&self) -> u32 {
+        self.period_us
+    }
+
+    /// The time after its release by which a job must be finished.
+    pub fn deadline_us(&self) -> u32 {
+        self.deadline_us
+    }
+
+    /// The maximum CPU time a single job may use.
+    pub fn budget_us(&self) -> u32 {
+        self.budget_us
+    }
+}
+
+/// Formats of credentials that can be attached to a TBF.
+///
+/// Each format defines how long its credential data is and how that data is
+/// checked against the integrity regions of the TBF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TbfHeaderV2CredentialsType {
+    /// Reserved value, carries no data and is never accepted.
+    Reserved = 0,
+    /// SHA-256 hash of the integrity regions (32 bytes).
+    Sha256 = 1,
+    /// HMAC-SHA256 of the integrity regions under a device key (32 bytes).
+    HmacSha256 = 2,
+    /// ECDSA NIST P-256 signature (`r` followed by `s`, 64 bytes) over the
+    /// SHA-256 hash of the integrity regions.
+    EcdsaNistP256 = 3,
+
+    /// A credentials format that this library does not understand. The data
+    /// is kept so that out-of-tree checkers can still inspect it.
+    Unknown,
+}
+
+impl TbfHeaderV2CredentialsType {
+    /// Length in bytes of the credential data for this format, or `None` if
+    /// the format does not have a fixed length.
+    pub fn data_length(&self) -> Option<usize> {
+        match *self {
+            TbfHeaderV2CredentialsType::Reserved => Some(0),
+            TbfHeaderV2CredentialsType::Sha256 => Some(32),
+            TbfHeaderV2CredentialsType::HmacSha256 => Some(32),
+            TbfHeaderV2CredentialsType::EcdsaNistP256 => Some(64),
+            TbfHeaderV2CredentialsType::Unknown => None,
+        }
+    }
+}
+
+/// A credential (hash, MAC or signature) attached to a TBF.
+///
+/// Credentials cover the integrity regions of the TBF, see
+/// `TbfHeader::get_integrity_regions()`.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2Credentials {
+    format: TbfHeaderV2CredentialsType,
+    data: &'static [u8],
+}
+
+impl TbfHeaderV2Credentials {
+    /// The format of this credential.
+    pub fn format(&self) -> TbfHeaderV2CredentialsType {
+        self.format
+    }
+
+    /// The raw credential data (hash, MAC or signature) stored in flash.
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -209,11 +337,51 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             2 => Ok(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
             3 => Ok(TbfHeaderTypes::TbfHeaderPackageName),
             5 => Ok(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
+            7 => Ok(TbfHeaderTypes::TbfHeaderRealTime),
+            128 => Ok(TbfHeaderTypes::TbfHeaderCredentials),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
 }
 
+impl core::convert::TryFrom<u32> for TbfHeaderV2CredentialsType {
+    type Error = TbfParseError;
+
+    fn try_from(format: u32) -> Result<TbfHeaderV2CredentialsType, Self::Error> {
+        match format {
+            0 => Ok(TbfHeaderV2CredentialsType::Reserved),
+            1 => Ok(TbfHeaderV2CredentialsType::Sha256),
+            2 => Ok(TbfHeaderV2CredentialsType::HmacSha256),
+            3 => Ok(TbfHeaderV2CredentialsType::EcdsaNistP256),
+            _ => Ok(TbfHeaderV2CredentialsType::Unknown),
+        }
+    }
+}
+
+impl core::convert::TryFrom<&'static [u8]> for TbfHeaderV2Credentials {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfHeaderV2Credentials, Self::Error> {
+        let format: TbfHeaderV2CredentialsType = u32::from_le_bytes(
+            b.get(0..4)
+                .ok_or(TbfParseError::InternalError)?
+                .try_into()?,
+        )
+        .try_into()?;
+        let data = b.get(4..).ok_or(TbfParseError::InternalError)?;
+
+        // Credentials with a known format must carry exactly the amount of
+        // data that format requires.
+        match format.data_length() {
+            Some(length) if length != data.len() => Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderCredentials as usize,
+            )),
+            _ => Ok(TbfHeaderV2Credentials { format, data }),
+        }
+    }
+}
+
 impl core::convert::TryFrom<&[u8]> for TbfHeaderTlv {
     type Error = TbfParseError;
 
@@ -296,11 +464,72 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2FixedAddresses {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderDriverPermission {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderDriverPermission, Self::Error> {
+        Ok(TbfHeaderDriverPermission {
+            driver_number: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            offset: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            allowed_commands: u64::from_le_bytes(
+                b.get(8..16)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2RealTime {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2RealTime, Self::Error> {
+        let real_time = TbfHeaderV2RealTime {
+            period_us: u32::from_le_bytes(
+                b.get(0..4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            deadline_us: u32::from_le_bytes(
+                b.get(4..8)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+            budget_us: u32::from_le_bytes(
+                b.get(8..12)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ),
+        };
+
+        // A job can neither use more time than it has until its deadline nor
+        // have a deadline after the next job may be released.
+        if real_time.budget_us == 0
+            || real_time.budget_us > real_time.deadline_us
+            || real_time.deadline_us > real_time.period_us
+        {
+            Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderRealTime as usize,
+            ))
+        } else {
+            Ok(real_time)
+        }
+    }
+}
+
 /// Single header that can contain all parts of a v2 header.
 ///
 /// Note, this struct limits the number of writeable regions an app can have to
-/// four since we need to statically know the length of the array to store in
-/// this type.
+/// four, and the number of driver permissions to eight, since we need to
+/// statically know the length of the array to store in this type.
 #[derive(Clone, Copy, Debug)]
 pub struct TbfHeaderV2 {
     pub(crate) base: TbfHeaderV2Base,
@@ -308,6 +537,12 @@ pub struct TbfHeaderV2 {
     pub(crate) package_name: Option<&'static str>,
     pub(crate) writeable_regions: Option<[Option<TbfHeaderV2WriteableFlashRegion>; 4]>,
     pub(crate) fixed_addresses: Option<TbfHeaderV2FixedAddresses>,
+    pub(crate) permissions: Option<[Option<TbfHeaderDriverPermission>; 8]>,
+    pub(crate) real_time: Option<TbfHeaderV2RealTime>,
+    pub(crate) credentials: [Option<TbfHeaderV2Credentials>; 4],
+    /// Offset in the header of the first credentials TLV, or the header size
+    /// if there are no credentials.
+    pub(crate) credentials_offset: u16,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -434,4 +669,86 @@ impl TbfHeader {
             start => Some(start),
         }
     }
+
+    /// Get the permissions of this app for the command numbers
+    /// `offset * 64` to `offset * 64 + 63` of the driver `driver_num`.
+    pub fn get_command_permissions(&self, driver_num: usize, offset: usize) -> CommandPermissions {
+        let permissions = match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.permissions {
+                Some(permissions) => permissions,
+                None => return CommandPermissions::NoPermsAtAll,
+            },
+            _ => return CommandPermissions::NoPermsAtAll,
+        };
+
+        let mut found_driver = false;
+        for permission in permissions.iter().flatten() {
+            if permission.driver_number as usize == driver_num {
+                found_driver = true;
+                if permission.offset as usize == offset {
+                    return CommandPermissions::Mask(permission.allowed_commands);
+                }
+            }
+        }
+
+        if found_driver {
+            // The app may use the driver, but none of these commands.
+            CommandPermissions::Mask(0)
+        } else {
+            CommandPermissions::NoPermsThisDriver
+        }
+    }
+
+    /// Get the timing requirements of this app, if it is a real-time app.
+    pub fn get_real_time_parameters(&self) -> Option<TbfHeaderV2RealTime> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.real_time,
+            _ => None,
+        }
+    }
+
+    /// Get the number of credentials this app has in its header.
+    pub fn number_credentials(&self) -> usize {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.credentials.iter().filter(|c| c.is_some()).count(),
+            _ => 0,
+        }
+    }
+
+    /// Get the credentials at the given index, if they exist.
+    pub fn get_credentials(&self, index: usize) -> Option<TbfHeaderV2Credentials> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.credentials.get(index).copied().flatten(),
+            _ => None,
+        }
+    }
+
+    /// Get the regions of the TBF that credentials are computed over.
+    ///
+    /// `app` must be the entire TBF (header, binary and padding) as described
+    /// by the total size in the base header. The integrity regions are, in
+    /// order:
+    ///
+    /// 1. The base header up to (but excluding) the checksum.
+    /// 2. All TLV entries before the first credentials entry.
+    /// 3. Everything after the header until the end of the TBF.
+    ///
+    /// The checksum and the credentials themselves are excluded since both
+    /// depend on the value of the credentials.
+    ///
+    /// Returns `None` if `app` is too short to contain the regions.
+    pub fn get_integrity_regions<'a>(&self, app: &'a [u8]) -> Option<[&'a [u8]; 3]> {
+        let hd = match self {
+            TbfHeader::TbfHeaderV2(hd) => hd,
+            _ => return None,
+        };
+        let header_size = hd.base.header_size as usize;
+        let total_size = hd.base.total_size as usize;
+        let credentials_offset = hd.credentials_offset as usize;
+        Some([
+            app.get(0..12)?,
+            app.get(16..credentials_offset)?,
+            app.get(header_size..total_size)?,
+        ])
+    }
 }